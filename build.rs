@@ -0,0 +1,120 @@
+//! Convert our built-in census data into Rust code at compile time, so that
+//! we never need to parse CSV data at runtime.
+
+extern crate sha2;
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::path::Path;
+
+/// The census data we compile into our library.
+const ZIP_POPULATION_CSV: &str = "src/zip2010.csv";
+
+/// The location of each zip code in `ZIP_POPULATION_CSV`.
+const ZIP_CENTROID_CSV: &str = "src/zip_centroids.csv";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", ZIP_POPULATION_CSV);
+    println!("cargo:rerun-if-changed={}", ZIP_CENTROID_CSV);
+    if env::var_os("CARGO_FEATURE_DATA_ZIP2010").is_none() {
+        return;
+    }
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR should be set by cargo");
+    write_census_rows(Path::new(&out_dir));
+    write_centroid_rows(Path::new(&out_dir));
+}
+
+/// Convert `ZIP_POPULATION_CSV` into `census2010.rs` in `out_dir`.
+fn write_census_rows(out_dir: &Path) {
+    let data = fs::read_to_string(ZIP_POPULATION_CSV)
+        .unwrap_or_else(|e| panic!("could not read {}: {}", ZIP_POPULATION_CSV, e));
+
+    let mut rows = String::new();
+    let mut line_for_zip = HashMap::new();
+    for (i, line) in data.lines().enumerate().skip(1) {
+        let invalid = || -> ! {
+            panic!("invalid data in {} at line {}: {:?}",
+                   ZIP_POPULATION_CSV,
+                   i + 1,
+                   line)
+        };
+        let mut fields = line.split(',');
+        let (zip, pop) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(zip), Some(pop), None) => (zip, pop),
+            _ => invalid(),
+        };
+        if zip.len() != 5 || !zip.bytes().all(|b| b.is_ascii_digit()) {
+            invalid();
+        }
+        let pop = pop.parse::<u64>().unwrap_or_else(|_| invalid());
+        // Zip codes which cross state lines have a row for each state, on
+        // consecutive lines, and we add them together. Any other repeat is a
+        // mistake, which would count a zip code twice and skew our chunks.
+        match line_for_zip.insert(zip, i + 1) {
+            Some(prev) if prev != i => {
+                panic!("zip code {} appears in {} at both line {} and line {}",
+                       zip,
+                       ZIP_POPULATION_CSV,
+                       prev,
+                       i + 1)
+            }
+            _ => {}
+        }
+        writeln!(rows, "    ({:?}, {}),", zip, pop).expect("could not format row");
+    }
+    let sha256: String = Sha256::digest(data.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    let code = format!("/// The zip codes and populations from `{}`.\n\
+                        const CENSUS2010_ROWS: &[(&str, u64)] = &[\n{}];\n\n\
+                        /// The SHA-256 checksum of `{}`, in hex.\n\
+                        const CENSUS2010_SHA256: &str = {:?};\n",
+                       ZIP_POPULATION_CSV,
+                       rows,
+                       ZIP_POPULATION_CSV,
+                       sha256);
+    fs::write(out_dir.join("census2010.rs"), code)
+        .expect("could not write generated census data");
+}
+
+/// Convert `ZIP_CENTROID_CSV` into `zip_centroids.rs` in `out_dir`.
+fn write_centroid_rows(out_dir: &Path) {
+    let data = fs::read_to_string(ZIP_CENTROID_CSV)
+        .unwrap_or_else(|e| panic!("could not read {}: {}", ZIP_CENTROID_CSV, e));
+
+    let mut rows = String::new();
+    for (i, line) in data.lines().enumerate().skip(1) {
+        let invalid = || -> ! {
+            panic!("invalid data in {} at line {}: {:?}",
+                   ZIP_CENTROID_CSV,
+                   i + 1,
+                   line)
+        };
+        let mut fields = line.split(',');
+        let (zip, lat, lng) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(zip), Some(lat), Some(lng), None) => (zip, lat, lng),
+            _ => invalid(),
+        };
+        if zip.len() != 5 || !zip.bytes().all(|b| b.is_ascii_digit()) {
+            invalid();
+        }
+        let lat = lat.parse::<f64>().unwrap_or_else(|_| invalid());
+        let lng = lng.parse::<f64>().unwrap_or_else(|_| invalid());
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+            invalid();
+        }
+        writeln!(rows, "    ({:?}, {:?}, {:?}),", zip, lat, lng).expect("could not format row");
+    }
+
+    let code = format!("/// The zip codes and locations from `{}`.\n\
+                        const ZIP_CENTROID_ROWS: &[(&str, f64, f64)] = &[\n{}];\n",
+                       ZIP_CENTROID_CSV,
+                       rows);
+    fs::write(out_dir.join("zip_centroids.rs"), code)
+        .expect("could not write generated centroid data");
+}
@@ -0,0 +1,16 @@
+// Copy the addon Cargo built to `geochunk.node`, where `index.js` loads it
+// from. Run with `release` or `debug` to choose which build to copy.
+
+"use strict";
+
+const fs = require("fs");
+const path = require("path");
+
+const profile = process.argv[2] || "release";
+const names = {
+  darwin: "libgeochunk_node.dylib",
+  win32: "geochunk_node.dll",
+};
+const lib = names[process.platform] || "libgeochunk_node.so";
+const target = process.env.CARGO_TARGET_DIR || path.join(__dirname, "target");
+fs.copyFileSync(path.join(target, profile, lib), path.join(__dirname, "geochunk.node"));
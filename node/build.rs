@@ -0,0 +1,5 @@
+//! Set up the linker flags Node.js addons need.
+
+fn main() {
+    napi_build::setup();
+}
@@ -0,0 +1,16 @@
+/** Assigns zip codes to chunks of similar 2010 census population. */
+export class Classifier {
+  /**
+   * Classifiers for recently used target populations are shared by every
+   * instance, so creating one per request is cheap.
+   */
+  constructor(targetPopulation: number);
+  /** The chunk containing `zip`, or `null` if it can't be classified. */
+  chunkFor(zip: string): string | null;
+  /** Classify an array of zip codes at once. */
+  chunksFor(zips: Array<string | null>): Array<string | null>;
+  /** Classify an array of zip codes on Node's thread pool. */
+  chunksForAsync(zips: Array<string | null>): Promise<Array<string | null>>;
+  /** Our chunk column's name, such as `geochunk_zip2010_250000`. */
+  get columnName(): string;
+}
@@ -0,0 +1,11 @@
+// Split data sets into chunks of similar population, based on zip codes.
+//
+//   const { Classifier } = require("geochunk");
+//   const classifier = new Classifier(250000);
+//   classifier.chunkFor("60614");                  // "6061_0"
+//   classifier.chunksFor(["60614", "N/A"]);        // ["6061_0", null]
+//   await classifier.chunksForAsync(["60614"]);    // ["6061_0"]
+
+"use strict";
+
+module.exports = require("./geochunk.node");
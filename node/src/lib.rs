@@ -0,0 +1,87 @@
+//! Node.js bindings for geochunk's classifiers, built as an N-API addon.
+//! These are much faster than calling WebAssembly, because we don't need
+//! to copy strings in and out of linear memory for every zip code.
+
+use geochunk::zip2010;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+/// Assigns zip codes to chunks of similar 2010 census population.
+#[napi]
+pub struct Classifier {
+    inner: zip2010::Classifier,
+}
+
+#[napi]
+impl Classifier {
+    /// `new Classifier(targetPopulation)`. Classifiers for recently used
+    /// target populations are shared by every instance, so creating one per
+    /// request is cheap.
+    #[napi(constructor)]
+    pub fn new(target_population: i64) -> Result<Classifier> {
+        if target_population < 0 {
+            return Err(Error::new(Status::InvalidArg,
+                                  "target population must not be negative"));
+        }
+        Ok(Classifier { inner: zip2010::cached_classifier(target_population as u64) })
+    }
+
+    /// The chunk containing `zip`, or `null` if it can't be classified.
+    #[napi]
+    pub fn chunk_for(&self, zip: String) -> Option<String> {
+        self.inner.chunk_for(&zip).map(|c| c.to_owned())
+    }
+
+    /// Classify an array of zip codes at once. `null` zip codes get `null`
+    /// chunks.
+    #[napi]
+    pub fn chunks_for(&self, zips: Vec<Option<String>>) -> Vec<Option<String>> {
+        chunks_for(&self.inner, &zips)
+    }
+
+    /// Classify an array of zip codes on Node's thread pool, returning a
+    /// promise, so that large batches don't block the event loop.
+    #[napi(ts_return_type = "Promise<Array<string | null>>")]
+    pub fn chunks_for_async(&self, zips: Vec<Option<String>>) -> AsyncTask<ChunksFor> {
+        AsyncTask::new(ChunksFor {
+                           classifier: self.inner.clone(),
+                           zips,
+                       })
+    }
+
+    /// The name the command-line tool gives our chunk column, such as
+    /// `geochunk_zip2010_250000`.
+    #[napi(getter)]
+    pub fn column_name(&self) -> String {
+        self.inner.geochunk_column_name()
+    }
+}
+
+/// Look up the chunk for each of `zips`.
+fn chunks_for(classifier: &zip2010::Classifier,
+              zips: &[Option<String>])
+              -> Vec<Option<String>> {
+    zips.iter()
+        .map(|zip| zip.as_ref().and_then(|z| classifier.chunk_for(z)))
+        .map(|chunk| chunk.map(|c| c.to_owned()))
+        .collect()
+}
+
+/// A batch of zip codes to classify on Node's thread pool.
+pub struct ChunksFor {
+    classifier: zip2010::Classifier,
+    zips: Vec<Option<String>>,
+}
+
+impl Task for ChunksFor {
+    type Output = Vec<Option<String>>;
+    type JsValue = Vec<Option<String>>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        Ok(chunks_for(&self.classifier, &self.zips))
+    }
+
+    fn resolve(&mut self, _: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output)
+    }
+}
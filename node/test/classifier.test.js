@@ -0,0 +1,31 @@
+"use strict";
+
+const assert = require("assert");
+const test = require("node:test");
+const { Classifier } = require("..");
+
+const classifier = new Classifier(250000);
+
+test("chunkFor", () => {
+  assert.strictEqual(classifier.chunkFor("90210"), "902_0");
+  assert.strictEqual(classifier.chunkFor("N/A"), null);
+});
+
+test("chunksFor", () => {
+  assert.deepStrictEqual(classifier.chunksFor(["90210", "N/A", null, "02120"]),
+                         ["902_0", null, null, "0212_0"]);
+});
+
+test("chunksForAsync", async () => {
+  assert.deepStrictEqual(await classifier.chunksForAsync(["90210", null]),
+                         ["902_0", null]);
+});
+
+test("columnName", () => {
+  assert.strictEqual(classifier.columnName, "geochunk_zip2010_250000");
+});
+
+test("target populations accepted by the command line", () => {
+  assert.strictEqual(new Classifier(0).columnName, "geochunk_zip2010_0");
+  assert.throws(() => new Classifier(-1), /must not be negative/);
+});
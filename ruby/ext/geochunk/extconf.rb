@@ -0,0 +1,6 @@
+# Build our Rust extension using rb_sys, which runs Cargo for us.
+
+require "mkmf"
+require "rb_sys/mkmf"
+
+create_rust_makefile("geochunk/geochunk_ext")
@@ -0,0 +1,51 @@
+//! Ruby bindings for geochunk's classifiers, built as the native extension
+//! of the `geochunk` gem.
+
+use geochunk::zip2010;
+use magnus::{function, method, prelude::*, Error, Ruby};
+
+/// `Geochunk::Classifier`, which assigns zip codes to chunks of similar
+/// 2010 census population.
+#[magnus::wrap(class = "Geochunk::Classifier", free_immediately, size)]
+struct Classifier(zip2010::Classifier);
+
+impl Classifier {
+    /// `Classifier.new(target_population)`. Classifiers for recently used
+    /// target populations are shared by every instance, so creating one per
+    /// request is cheap. Negative target populations raise `RangeError`.
+    fn new(target_population: u64) -> Classifier {
+        Classifier(zip2010::cached_classifier(target_population))
+    }
+
+    /// `chunk_for(zip)`, which returns `nil` if `zip` can't be classified.
+    fn chunk_for(&self, zip: String) -> Option<String> {
+        self.0.chunk_for(&zip).map(|c| c.to_owned())
+    }
+
+    /// `chunks_for(zips)`, which classifies an array of zip codes at once,
+    /// saving a call into Ruby for each one. `nil` zip codes get `nil`
+    /// chunks.
+    fn chunks_for(&self, zips: Vec<Option<String>>) -> Vec<Option<String>> {
+        zips.iter()
+            .map(|zip| zip.as_ref().and_then(|z| self.0.chunk_for(z)))
+            .map(|chunk| chunk.map(|c| c.to_owned()))
+            .collect()
+    }
+
+    /// `column_name`, the name the command-line tool gives our chunk column,
+    /// such as `geochunk_zip2010_250000`.
+    fn column_name(&self) -> String {
+        self.0.geochunk_column_name()
+    }
+}
+
+#[magnus::init(name = "geochunk_ext")]
+fn init(ruby: &Ruby) -> Result<(), Error> {
+    let module = ruby.define_module("Geochunk")?;
+    let class = module.define_class("Classifier", ruby.class_object())?;
+    class.define_singleton_method("new", function!(Classifier::new, 1))?;
+    class.define_method("chunk_for", method!(Classifier::chunk_for, 1))?;
+    class.define_method("chunks_for", method!(Classifier::chunks_for, 1))?;
+    class.define_method("column_name", method!(Classifier::column_name, 0))?;
+    Ok(())
+}
@@ -0,0 +1,12 @@
+# frozen_string_literal: true
+
+# Split data sets into chunks of similar population, based on zip codes.
+#
+#   classifier = Geochunk::Classifier.new(250_000)
+#   classifier.chunk_for("60614")             # => "6061_0"
+#   classifier.chunks_for(["60614", "N/A"])   # => ["6061_0", nil]
+module Geochunk
+  VERSION = "0.1.5"
+end
+
+require "geochunk/geochunk_ext"
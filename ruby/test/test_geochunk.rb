@@ -0,0 +1,29 @@
+# frozen_string_literal: true
+
+require "minitest/autorun"
+require "geochunk"
+
+class TestGeochunk < Minitest::Test
+  def setup
+    @classifier = Geochunk::Classifier.new(250_000)
+  end
+
+  def test_chunk_for
+    assert_equal "902_0", @classifier.chunk_for("90210")
+    assert_nil @classifier.chunk_for("N/A")
+  end
+
+  def test_chunks_for
+    assert_equal ["902_0", nil, nil, "0212_0"],
+                 @classifier.chunks_for(["90210", "N/A", nil, "02120"])
+  end
+
+  def test_column_name
+    assert_equal "geochunk_zip2010_250000", @classifier.column_name
+  end
+
+  def test_target_populations_accepted_by_the_command_line
+    assert_equal "geochunk_zip2010_0", Geochunk::Classifier.new(0).column_name
+    assert_raises(RangeError) { Geochunk::Classifier.new(-1) }
+  end
+end
@@ -0,0 +1,206 @@
+//! Anonymizing CSV files by replacing zip codes with geochunks, and
+//! suppressing any geochunks which contain too few rows to hide in.
+
+use csv;
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::prelude::*;
+use std::str::from_utf8;
+use std::thread;
+
+use dialect::{Dialect, RaggedRows};
+use errors::*;
+use mmap;
+use zip2010::Classifier;
+
+/// The number of rows of a CSV file in each geochunk.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RowCounts {
+    /// The rows in each chunk. Chunks without any rows may be missing.
+    pub chunks: BTreeMap<String, u64>,
+    /// Rows which aren't in any chunk, because they had no valid zip code,
+    /// or because they were ragged rows which our dialect told us to skip.
+    pub dropped_rows: u64,
+}
+
+impl RowCounts {
+    /// Add the counts from `other` to ours.
+    pub fn add(&mut self, other: RowCounts) {
+        for (chunk, rows) in other.chunks {
+            *self.chunks.entry(chunk).or_insert(0) += rows;
+        }
+        self.dropped_rows += other.dropped_rows;
+    }
+}
+
+/// Count the rows of a CSV file in each geochunk. Rows without a valid zip
+/// code, and ragged rows which `dialect` tells us to skip, are counted as
+/// dropped.
+pub fn count_rows_by_chunk(classifier: &Classifier,
+                           input_column: &str,
+                           dialect: &Dialect,
+                           input: &mut dyn Read)
+                           -> Result<RowCounts> {
+    let mut rdr = dialect.reader_builder().from_reader(input);
+    let headers = rdr.headers()?.to_owned();
+    let zip_col_idx = headers
+        .iter()
+        .position(|h| h == input_column)
+        .ok_or_else(|| Error::no_such_column(input_column))?;
+    let mut counts = HashMap::new();
+    let dropped_rows =
+        count_records(classifier, zip_col_idx, headers.len(), dialect, &mut rdr, &mut counts)?;
+    Ok(owned_counts(counts, dropped_rows))
+}
+
+/// Like `count_rows_by_chunk`, but split `data` into `threads` pieces at
+/// record boundaries, and count them in parallel. This works best with a
+/// memory-mapped file. See `mmap::split_records`.
+pub fn count_rows_by_chunk_parallel(classifier: &Classifier,
+                                    input_column: &str,
+                                    dialect: &Dialect,
+                                    data: &[u8],
+                                    threads: usize)
+                                    -> Result<RowCounts> {
+    let mut rdr = dialect.reader_builder().from_reader(data);
+    let headers = rdr.headers()?.to_owned();
+    let zip_col_idx = headers
+        .iter()
+        .position(|h| h == input_column)
+        .ok_or_else(|| Error::no_such_column(input_column))?;
+    let header_len = headers.len();
+    let body = &data[rdr.position().byte() as usize..];
+    let pieces = mmap::split_records(body, dialect, threads);
+
+    let results = thread::scope(|scope| {
+        let handles = pieces
+            .into_iter()
+            .map(|piece| {
+                scope.spawn(move || -> Result<(HashMap<&str, u64>, u64)> {
+                    let mut rdr = dialect
+                        .reader_builder()
+                        .has_headers(false)
+                        .from_reader(piece);
+                    let mut counts = HashMap::new();
+                    let dropped_rows = count_records(classifier,
+                                                     zip_col_idx,
+                                                     header_len,
+                                                     dialect,
+                                                     &mut rdr,
+                                                     &mut counts)?;
+                    Ok((counts, dropped_rows))
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("counting thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    let mut counts = HashMap::new();
+    let mut dropped_rows = 0;
+    for result in results {
+        let (piece_counts, piece_dropped) = result?;
+        for (chunk, rows) in piece_counts {
+            *counts.entry(chunk).or_insert(0) += rows;
+        }
+        dropped_rows += piece_dropped;
+    }
+    Ok(owned_counts(counts, dropped_rows))
+}
+
+/// Add the rows read by `rdr` to `counts`, given the index of our zip code
+/// column and the number of columns in our header row, and return the
+/// number of rows we dropped. We count using chunk IDs borrowed from
+/// `classifier`, so we never copy them for each row.
+fn count_records<'c, R: Read>(classifier: &'c Classifier,
+                              zip_col_idx: usize,
+                              header_len: usize,
+                              dialect: &Dialect,
+                              rdr: &mut csv::Reader<R>,
+                              counts: &mut HashMap<&'c str, u64>)
+                              -> Result<u64> {
+    let mut dropped_rows = 0;
+    let mut row = csv::ByteRecord::new();
+    while rdr.read_byte_record(&mut row)? {
+        if dialect.on_ragged == RaggedRows::Skip && row.len() != header_len {
+            dropped_rows += 1;
+            continue;
+        }
+        let zip = from_utf8(row.get(zip_col_idx).unwrap_or(b""))
+            .chain_err(|| Error::non_utf8_zip(row.position()))?;
+        match classifier.chunk_for(zip) {
+            Some(chunk) => *counts.entry(chunk).or_insert(0) += 1,
+            None => dropped_rows += 1,
+        }
+    }
+    Ok(dropped_rows)
+}
+
+/// Copy the chunk IDs in `counts`, once we've finished counting.
+fn owned_counts(counts: HashMap<&str, u64>, dropped_rows: u64) -> RowCounts {
+    RowCounts {
+        chunks: counts
+            .into_iter()
+            .map(|(chunk, rows)| (chunk.to_owned(), rows))
+            .collect(),
+        dropped_rows,
+    }
+}
+
+/// Return the chunks which have fewer than `min_rows` rows.
+pub fn chunks_to_suppress(counts: &BTreeMap<String, u64>,
+                          min_rows: u64)
+                          -> HashSet<String> {
+    counts
+        .iter()
+        .filter(|&(_, &count)| count < min_rows)
+        .map(|(chunk, _)| chunk.to_owned())
+        .collect()
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn suppresses_chunks_with_too_few_rows() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let input = "zip\n90210\n90211\n07712\nN/A\n\n";
+    let counts = count_rows_by_chunk(&classifier,
+                                     "zip",
+                                     &Dialect::default(),
+                                     &mut input.as_bytes())
+            .unwrap();
+    assert_eq!(counts.chunks.get("902_0"), Some(&2));
+    assert_eq!(counts.chunks.get("077_0"), Some(&1));
+    assert_eq!(counts.dropped_rows, 1);
+    let suppressed = chunks_to_suppress(&counts.chunks, 2);
+    assert_eq!(suppressed.into_iter().collect::<Vec<_>>(), vec!["077_0"]);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn counts_rows_in_parallel() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut input = "id,zip\n".to_owned();
+    for i in 0..1000 {
+        let zip = if i % 3 == 0 { "07712" } else { "90210" };
+        input.push_str(&format!("{},{}\n", i, zip));
+    }
+    input.push_str("1000,\n");
+    let serial = count_rows_by_chunk(&classifier,
+                                     "zip",
+                                     &Dialect::default(),
+                                     &mut input.as_bytes())
+            .unwrap();
+    let parallel = count_rows_by_chunk_parallel(&classifier,
+                                                "zip",
+                                                &Dialect::default(),
+                                                input.as_bytes(),
+                                                4)
+            .unwrap();
+    assert_eq!(parallel, serial);
+    assert_eq!(parallel.chunks.get("077_0"), Some(&334));
+    assert_eq!(parallel.dropped_rows, 1);
+}
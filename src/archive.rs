@@ -0,0 +1,298 @@
+//! Reading CSV files bundled into tar and zip archives, and writing our
+//! output for each of them into another archive or a directory, for
+//! `geochunk csv --archive`.
+
+#[cfg(test)]
+use env_logger;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use tar;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use errors::*;
+
+/// The kinds of archive we can read and write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    /// An uncompressed tar file.
+    Tar,
+    /// A gzipped tar file.
+    TarGz,
+    /// A zip file.
+    Zip,
+}
+
+impl ArchiveKind {
+    /// Guess the kind of archive at `path` from its name, returning `None`
+    /// if it doesn't look like an archive.
+    pub fn from_path(path: &Path) -> Option<ArchiveKind> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// The extensions of the members we process. Each may also be followed by
+/// `.gz` or `.zst`.
+const CSV_EXTENSIONS: &[&str] = &[".csv", ".tsv", ".txt"];
+
+/// The extensions of compressed members, which we remove from the names of
+/// our output files, since we write them uncompressed.
+const COMPRESSED_EXTENSIONS: &[&str] = &[".gz", ".zst"];
+
+/// Remove any compression extension from `name`.
+fn strip_compression(name: &str) -> &str {
+    let lower = name.to_ascii_lowercase();
+    COMPRESSED_EXTENSIONS
+        .iter()
+        .find(|ext| lower.ends_with(*ext))
+        .map_or(name, |ext| &name[..(name.len() - ext.len())])
+}
+
+/// Should we process the archive member `name`? Besides other kinds of
+/// files, this skips the `._` files which macOS adds to zip files.
+fn is_csv_member(name: &str) -> bool {
+    let base = name.rsplit('/').next().unwrap_or(name);
+    let lower = strip_compression(base).to_ascii_lowercase();
+    !base.starts_with("._") && CSV_EXTENSIONS.iter().any(|ext| lower.ends_with(ext))
+}
+
+/// The name of the output file we write for the member `name`.
+pub fn output_name(name: &str) -> String {
+    strip_compression(name).to_owned()
+}
+
+/// Make sure `name` stays inside the archive or directory it's written to.
+fn check_member_name(name: &str) -> Result<()> {
+    let path = Path::new(name);
+    let safe = path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if name.is_empty() || !safe {
+        return Err(format!("archive member {:?} would be written outside of our output",
+                           name)
+                           .into());
+    }
+    Ok(())
+}
+
+/// Call `f` with the name and contents of each CSV file in the archive at
+/// `path`, in the order they're stored. Other members are skipped.
+pub fn for_each_csv_member<F>(path: &Path, mut f: F) -> Result<()>
+    where F: FnMut(&str, &mut dyn Read) -> Result<()>
+{
+    let kind = ArchiveKind::from_path(path)
+        .ok_or_else(|| format!("{} should be a .tar, .tar.gz, .tgz or .zip file",
+                               path.display()))?;
+    let file = File::open(path)
+        .chain_err(|| format!("could not open {}", path.display()))?;
+    let mut process = |name: &str, member: &mut dyn Read| {
+        if is_csv_member(name) {
+            check_member_name(name)?;
+            f(name, member)
+        } else {
+            warn!("skipping {} in {}, which isn't a CSV file", name, path.display());
+            Ok(())
+        }
+    };
+    match kind {
+        ArchiveKind::Tar => for_each_tar_member(BufReader::new(file), &mut process),
+        ArchiveKind::TarGz => {
+            let input = MultiGzDecoder::new(BufReader::new(file));
+            for_each_tar_member(input, &mut process)
+        }
+        ArchiveKind::Zip => {
+            let mut archive = ZipArchive::new(BufReader::new(file))
+                .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+            for i in 0..archive.len() {
+                let mut member = archive
+                    .by_index(i)
+                    .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+                if member.is_file() {
+                    let name = member
+                        .name()
+                        .map_err(|e| format!("could not read {}: {}", path.display(), e))?
+                        .into_owned();
+                    process(&name, &mut member)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Call `f` with each regular file in a tar archive.
+fn for_each_tar_member<R, F>(input: R, f: &mut F) -> Result<()>
+    where R: Read,
+          F: FnMut(&str, &mut dyn Read) -> Result<()>
+{
+    let mut archive = tar::Archive::new(input);
+    for member in archive.entries()? {
+        let mut member = member?;
+        if member.header().entry_type().is_file() {
+            let name = member.path()?.to_string_lossy().into_owned();
+            f(&name, &mut member)?;
+        }
+    }
+    Ok(())
+}
+
+/// Where we're writing our output files.
+enum Output {
+    /// Write each file into a directory.
+    Dir(PathBuf),
+    /// Write each file into a tar archive.
+    Tar(tar::Builder<BufWriter<File>>),
+    /// Write each file into a gzipped tar archive.
+    TarGz(tar::Builder<GzEncoder<BufWriter<File>>>),
+    /// Write each file into a zip archive.
+    Zip(Box<ZipWriter<BufWriter<File>>>),
+}
+
+/// Writes our output files into an archive or directory.
+///
+/// Tar archives need to know the size of each file before its contents, so
+/// we buffer each file in memory before adding it to a tar archive. Zip
+/// archives and directories are written as we go.
+pub struct ArchiveWriter {
+    output: Output,
+}
+
+impl ArchiveWriter {
+    /// Write to `path`, whose name tells us what kind of archive to write.
+    /// If it isn't named like an archive, we create a directory there.
+    pub fn create(path: &Path) -> Result<ArchiveWriter> {
+        let create = || -> Result<BufWriter<File>> {
+            let file = File::create(path)
+                .chain_err(|| format!("could not create {}", path.display()))?;
+            Ok(BufWriter::new(file))
+        };
+        let output = match ArchiveKind::from_path(path) {
+            None => {
+                fs::create_dir_all(path)
+                    .chain_err(|| format!("could not create {}", path.display()))?;
+                Output::Dir(path.to_owned())
+            }
+            Some(ArchiveKind::Tar) => Output::Tar(tar::Builder::new(create()?)),
+            Some(ArchiveKind::TarGz) => {
+                let gz = GzEncoder::new(create()?, ::flate2::Compression::default());
+                Output::TarGz(tar::Builder::new(gz))
+            }
+            Some(ArchiveKind::Zip) => Output::Zip(Box::new(ZipWriter::new(create()?))),
+        };
+        Ok(ArchiveWriter { output })
+    }
+
+    /// Add a file named `name`, whose contents are written by `f`.
+    pub fn write_member<T, F>(&mut self, name: &str, f: F) -> Result<T>
+        where F: FnOnce(&mut dyn Write) -> Result<T>
+    {
+        check_member_name(name)?;
+        let zip_error = |e| -> Error { format!("could not write zip file: {}", e).into() };
+        match self.output {
+            Output::Dir(ref dir) => {
+                let path = dir.join(name);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .chain_err(|| format!("could not create {}", parent.display()))?;
+                }
+                let file = File::create(&path)
+                    .chain_err(|| format!("could not create {}", path.display()))?;
+                let mut out = BufWriter::new(file);
+                let result = f(&mut out)?;
+                out.flush()?;
+                Ok(result)
+            }
+            Output::Tar(ref mut builder) => append_tar_member(builder, name, f),
+            Output::TarGz(ref mut builder) => append_tar_member(builder, name, f),
+            Output::Zip(ref mut wtr) => {
+                let options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Deflated);
+                wtr.start_file(name, options).map_err(zip_error)?;
+                f(&mut **wtr)
+            }
+        }
+    }
+
+    /// Finish writing our archive.
+    pub fn finish(self) -> Result<()> {
+        match self.output {
+            Output::Dir(_) => {}
+            Output::Tar(builder) => builder.into_inner()?.flush()?,
+            Output::TarGz(builder) => builder.into_inner()?.finish()?.flush()?,
+            Output::Zip(wtr) => {
+                wtr.finish()
+                    .map_err(|e| format!("could not write zip file: {}", e))?
+                    .flush()?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add a file named `name` to a tar archive, buffering its contents.
+fn append_tar_member<W, T, F>(builder: &mut tar::Builder<W>, name: &str, f: F) -> Result<T>
+    where W: Write,
+          F: FnOnce(&mut dyn Write) -> Result<T>
+{
+    let mut contents = vec![];
+    let result = f(&mut contents)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_entry_type(tar::EntryType::Regular);
+    builder.append_data(&mut header, name, io::Cursor::new(contents))?;
+    Ok(result)
+}
+
+#[test]
+fn writes_and_reads_archives() {
+    use std::env;
+    use std::process;
+
+    let _ = env_logger::init();
+    let dir = env::temp_dir().join(format!("geochunk-archive-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in &["out.tar", "out.tar.gz", "out.zip"] {
+        let path = dir.join(name);
+        let mut wtr = ArchiveWriter::create(&path).unwrap();
+        for member in &["a.csv", "sub/b.CSV", "README.md", "__MACOSX/._a.csv"] {
+            wtr.write_member(member, |out| Ok(writeln!(out, "zip\n{}", member)?))
+                .unwrap();
+        }
+        assert!(wtr.write_member("../c.csv", |_| Ok(())).is_err());
+        wtr.finish().unwrap();
+
+        let mut members = vec![];
+        for_each_csv_member(&path, |name, member| {
+                let mut contents = String::new();
+                member.read_to_string(&mut contents)?;
+                members.push((name.to_owned(), contents));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(members,
+                   vec![("a.csv".to_owned(), "zip\na.csv\n".to_owned()),
+                        ("sub/b.CSV".to_owned(), "zip\nsub/b.CSV\n".to_owned())]);
+    }
+
+    let mut wtr = ArchiveWriter::create(&dir.join("out")).unwrap();
+    wtr.write_member("sub/a.csv", |out| Ok(out.write_all(b"zip\n")?))
+        .unwrap();
+    wtr.finish().unwrap();
+    assert_eq!(fs::read(dir.join("out/sub/a.csv")).unwrap(), b"zip\n");
+    assert_eq!(output_name("a.csv.gz"), "a.csv");
+    assert!(for_each_csv_member(&dir.join("out"), |_, _| Ok(())).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,424 @@
+//! Adding geochunk columns to CSV data read from `tokio` streams.
+//!
+//! This does the same work as `transform::transform_csv`, but it never
+//! blocks, so it can be used from inside an async service without a
+//! `spawn_blocking` bridge. It requires the `tokio` feature.
+
+use csv;
+use csv_core;
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::cmp::max;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use errors::*;
+use transform::{synthesize_headers, RowTransformer, Transformed, TransformOptions,
+                TransformStats};
+use zip2010::Classifier;
+
+/// How many bytes of input we try to read at once.
+const INPUT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// How many rows we transform before writing them out.
+const ROWS_PER_BATCH: usize = 1024;
+
+/// Read CSV data from `input`, add a geochunk column for each of
+/// `input_columns`, and write it to `output`. This behaves like
+/// `transform::transform_csv`, except that rows skipped because of
+/// `RaggedRows::Skip` are discarded, and `sort_by_chunk` is not supported.
+///
+/// The returned future resolves to statistics about the rows we
+/// transformed, once all of our output has been written and flushed.
+pub fn transform_csv_async<'a, R, W>(classifier: &'a Classifier,
+                                     input_columns: &[&str],
+                                     options: &'a TransformOptions,
+                                     input: R,
+                                     output: W)
+                                     -> TransformCsv<'a, R, W>
+    where R: AsyncRead + Unpin,
+          W: AsyncWrite + Unpin
+{
+    TransformCsv {
+        classifier,
+        input_columns: input_columns.iter().map(|&c| c.to_owned()).collect(),
+        options,
+        transformer: None,
+        input,
+        in_buf: vec![0; INPUT_BUFFER_SIZE],
+        in_pos: 0,
+        in_len: 0,
+        eof: false,
+        parser: RecordParser::new(options),
+        row: csv::ByteRecord::new(),
+        output,
+        out_buf: vec![],
+        out_pos: 0,
+        finished: false,
+    }
+}
+
+/// A future which transforms a CSV file. Returned by `transform_csv_async`.
+pub struct TransformCsv<'a, R, W> {
+    /// The classifier we use to look up chunks.
+    classifier: &'a Classifier,
+    /// Our zip code columns.
+    input_columns: Vec<String>,
+    /// Our options.
+    options: &'a TransformOptions,
+    /// Our row transformer, which we create once we've seen our headers.
+    transformer: Option<RowTransformer<'a>>,
+    /// Where we read our input.
+    input: R,
+    /// Input which we've read but not parsed.
+    in_buf: Vec<u8>,
+    /// How much of `in_buf` we've parsed.
+    in_pos: usize,
+    /// How much of `in_buf` contains data.
+    in_len: usize,
+    /// Have we reached the end of our input?
+    eof: bool,
+    /// Our CSV parser.
+    parser: RecordParser,
+    /// A buffer for the row we're transforming.
+    row: csv::ByteRecord,
+    /// Where we write our output.
+    output: W,
+    /// Output which we haven't written yet.
+    out_buf: Vec<u8>,
+    /// How much of `out_buf` we've written.
+    out_pos: usize,
+    /// Have we transformed all of our input?
+    finished: bool,
+}
+
+impl<'a, R, W> TransformCsv<'a, R, W> {
+    /// Transform whatever complete rows we have in `in_buf`, up to
+    /// `ROWS_PER_BATCH`, and append them to `out_buf`.
+    fn transform_input(&mut self) -> Result<()> {
+        let TransformCsv {
+            classifier,
+            ref input_columns,
+            options,
+            ref mut transformer,
+            ref in_buf,
+            ref mut in_pos,
+            in_len,
+            eof,
+            ref mut parser,
+            ref mut row,
+            ref mut out_buf,
+            ref mut finished,
+            ..
+        } = *self;
+        let mut wtr = options.dialect.writer_builder().from_writer(out_buf);
+        let mut rows = 0;
+        while rows < ROWS_PER_BATCH && (*in_pos < in_len || eof) {
+            let (parsed, consumed) = parser.parse(&in_buf[*in_pos..in_len], row);
+            *in_pos += consumed;
+            let found_row = match parsed {
+                Parsed::NeedInput => break,
+                Parsed::Row => true,
+                Parsed::End => {
+                    *finished = true;
+                    false
+                }
+            };
+
+            // The first row tells us our headers, even if we don't have a
+            // header row.
+            if transformer.is_none() {
+                let headers = if options.no_header {
+                    synthesize_headers(if found_row { row.len() } else { 0 })
+                } else if found_row {
+                    csv::StringRecord::from_byte_record(row.clone())
+                        .map_err(|e| e.utf8_error().clone())
+                        .chain_err(|| "CSV headers were not valid UTF-8")?
+                } else {
+                    csv::StringRecord::new()
+                };
+                if options.sort_by_chunk {
+                    return Err("cannot sort by chunk while transforming asynchronously"
+                                   .into());
+                }
+                let columns = input_columns.iter().map(|c| &c[..]).collect::<Vec<_>>();
+                let new_transformer =
+                    RowTransformer::new(classifier, &columns, options, headers)?;
+                if let Some(out_headers) = new_transformer.output_headers() {
+                    wtr.write_record(out_headers.iter())?;
+                }
+                *transformer = Some(new_transformer);
+                if !options.no_header {
+                    continue;
+                }
+            }
+
+            if found_row {
+                let transformer = transformer.as_mut().expect("created above");
+                if let Transformed::Row(out_row) = transformer.transform_row(row)? {
+                    wtr.write_byte_record(out_row)?;
+                }
+                rows += 1;
+            } else {
+                break;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a, R, W> Future for TransformCsv<'a, R, W>
+    where R: AsyncRead + Unpin,
+          W: AsyncWrite + Unpin
+{
+    type Output = Result<TransformStats>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<TransformStats>> {
+        let this = self.get_mut();
+        loop {
+            // Write any output we've already transformed.
+            while this.out_pos < this.out_buf.len() {
+                let pending = &this.out_buf[this.out_pos..];
+                match Pin::new(&mut this.output).poll_write(cx, pending)? {
+                    Poll::Ready(0) => {
+                        let err = io::Error::new(io::ErrorKind::WriteZero,
+                                                 "could not write CSV output");
+                        return Poll::Ready(Err(err.into()));
+                    }
+                    Poll::Ready(count) => this.out_pos += count,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            this.out_buf.clear();
+            this.out_pos = 0;
+
+            if this.finished {
+                if Pin::new(&mut this.output).poll_flush(cx)?.is_pending() {
+                    return Poll::Pending;
+                }
+                let stats = this.transformer.as_ref().map(|t| t.stats());
+                return Poll::Ready(Ok(stats.unwrap_or_default()));
+            }
+
+            // Read more input if we've parsed everything we have.
+            if this.in_pos == this.in_len && !this.eof {
+                let mut buf = ReadBuf::new(&mut this.in_buf);
+                if Pin::new(&mut this.input).poll_read(cx, &mut buf)?.is_pending() {
+                    return Poll::Pending;
+                }
+                this.in_len = buf.filled().len();
+                this.in_pos = 0;
+                this.eof = this.in_len == 0;
+            }
+
+            if let Err(err) = this.transform_input() {
+                return Poll::Ready(Err(err));
+            }
+        }
+    }
+}
+
+/// What happened when we tried to parse a row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Parsed {
+    /// We finished parsing a row.
+    Row,
+    /// We need more input to finish the current row.
+    NeedInput,
+    /// There are no more rows.
+    End,
+}
+
+/// An incremental CSV parser, which can be fed input a piece at a time. This
+/// keeps track of row positions the same way as `csv::Reader`, so that our
+/// error messages match.
+struct RecordParser {
+    /// The underlying parser.
+    core: csv_core::Reader,
+    /// The field data for the row we're parsing.
+    fields: Vec<u8>,
+    /// The end offsets of each field in `fields`.
+    ends: Vec<usize>,
+    /// How much of `fields` we've filled in.
+    fields_len: usize,
+    /// How much of `ends` we've filled in.
+    ends_len: usize,
+    /// The position of the row we're parsing, once we've started it.
+    row_pos: Option<csv::Position>,
+    /// How many bytes of input we've consumed.
+    byte: u64,
+    /// How many rows we've parsed.
+    rows: u64,
+}
+
+impl RecordParser {
+    /// Create a parser for the dialect specified in `options`.
+    fn new(options: &TransformOptions) -> RecordParser {
+        let dialect = &options.dialect;
+        let core = csv_core::ReaderBuilder::new()
+            .delimiter(dialect.delimiter)
+            .quote(dialect.quote)
+            .escape(dialect.escape)
+            .comment(dialect.comment)
+            .build();
+        RecordParser {
+            core,
+            fields: vec![0; 1024],
+            ends: vec![0; 16],
+            fields_len: 0,
+            ends_len: 0,
+            row_pos: None,
+            byte: 0,
+            rows: 0,
+        }
+    }
+
+    /// Parse `input` until we've finished a row, storing it in `row`. An
+    /// empty `input` means that we've reached the end of our data. Returns
+    /// what happened and how many bytes of `input` we consumed.
+    fn parse(&mut self, input: &[u8], row: &mut csv::ByteRecord) -> (Parsed, usize) {
+        use csv_core::ReadRecordResult;
+
+        if self.row_pos.is_none() {
+            let mut pos = csv::Position::new();
+            pos.set_byte(self.byte)
+                .set_line(self.core.line())
+                .set_record(self.rows);
+            self.row_pos = Some(pos);
+        }
+        let mut consumed = 0;
+        loop {
+            let (result, nin, nout, nend) =
+                self.core
+                    .read_record(&input[consumed..],
+                                 &mut self.fields[self.fields_len..],
+                                 &mut self.ends[self.ends_len..]);
+            consumed += nin;
+            self.byte += nin as u64;
+            self.fields_len += nout;
+            self.ends_len += nend;
+            match result {
+                ReadRecordResult::InputEmpty => return (Parsed::NeedInput, consumed),
+                ReadRecordResult::OutputFull => {
+                    let len = max(1024, 2 * self.fields.len());
+                    self.fields.resize(len, 0);
+                }
+                ReadRecordResult::OutputEndsFull => {
+                    let len = max(16, 2 * self.ends.len());
+                    self.ends.resize(len, 0);
+                }
+                ReadRecordResult::Record => {
+                    row.clear();
+                    let mut start = 0;
+                    for &end in &self.ends[..self.ends_len] {
+                        row.push_field(&self.fields[start..end]);
+                        start = end;
+                    }
+                    row.set_position(self.row_pos.take());
+                    self.fields_len = 0;
+                    self.ends_len = 0;
+                    self.rows += 1;
+                    return (Parsed::Row, consumed);
+                }
+                ReadRecordResult::End => return (Parsed::End, consumed),
+            }
+        }
+    }
+}
+
+/// An `AsyncRead` which only returns a few bytes at a time, so that we can
+/// test rows which are split across reads.
+#[cfg(all(test, feature = "data-zip2010"))]
+struct Trickle<'a>(&'a [u8]);
+
+#[cfg(all(test, feature = "data-zip2010"))]
+impl<'a> AsyncRead for Trickle<'a> {
+    fn poll_read(mut self: Pin<&mut Self>,
+                 _cx: &mut Context,
+                 buf: &mut ReadBuf)
+                 -> Poll<io::Result<()>> {
+        let count = self.0.len().min(3).min(buf.remaining());
+        buf.put_slice(&self.0[..count]);
+        self.0 = &self.0[count..];
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(all(test, feature = "data-zip2010"))]
+fn block_on<F: Future>(future: F) -> F::Output {
+    use tokio::runtime;
+
+    runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn transforms_csv_asynchronously() {
+    use dialect::{Dialect, RaggedRows};
+    use std::str::from_utf8;
+
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let options = TransformOptions {
+        dialect: Dialect {
+            on_ragged: RaggedRows::Skip,
+            ..Dialect::default()
+        },
+        ..TransformOptions::default()
+    };
+    let input = "name,zip\n\"Doe,\nJ.\",90210\nH. Smith\nA. Jones,N/A\n";
+    let mut output = vec![];
+    let stats = block_on(transform_csv_async(&classifier,
+                                             &["zip"],
+                                             &options,
+                                             Trickle(input.as_bytes()),
+                                             &mut output))
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "name,zip,geochunk_zip2010_250000\n\"Doe,\nJ.\",90210,902_0\n\
+                A. Jones,N/A,\n");
+    assert_eq!(stats,
+               TransformStats {
+                   rows: 2,
+                   invalid_rows: 1,
+                   duplicate_rows: 0,
+                   new_zip_rows: 0,
+                   fallback_rows: 0,
+                   nearest_rows: 0,
+                   skipped_rows: 1,
+               });
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn async_transform_reports_errors_with_line_numbers() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let transform = |input: &'static str, options: TransformOptions| {
+        let mut output = vec![];
+        block_on(transform_csv_async(&classifier,
+                                     &["zip"],
+                                     &options,
+                                     input.as_bytes(),
+                                     &mut output))
+                .unwrap_err()
+                .to_string()
+    };
+    let strict = TransformOptions {
+        strict: true,
+        ..TransformOptions::default()
+    };
+    assert_eq!(transform("zip\n90210\nN/A\n", strict),
+               "Invalid zip code \"N/A\" in column 'zip' at line 3");
+    assert_eq!(transform("zip\n90210,extra\n", TransformOptions::default()),
+               "Found row with 2 fields, but expected 1 at line 2");
+    assert_eq!(transform("", TransformOptions::default()),
+               "No CSV column with name 'zip'");
+}
@@ -0,0 +1,27 @@
+//! An AWS Lambda function which chunks CSV files uploaded to S3. See
+//! `geochunk::lambda` for how to configure it.
+
+extern crate env_logger;
+extern crate error_chain;
+extern crate geochunk;
+
+use error_chain::ChainedError;
+use geochunk::errors::*;
+use std::io::{self, Write};
+use std::process;
+
+/// Answer Lambda invocations, exporting traces if we were asked to.
+fn run() -> Result<()> {
+    #[cfg(feature = "otel")]
+    let _telemetry = geochunk::telemetry::init()?;
+    geochunk::lambda::run_from_env()
+}
+
+fn main() {
+    env_logger::init().expect("Could not initialize logging");
+    if let Err(err) = run() {
+        write!(&mut io::stderr(), "{}", err.display_chain())
+            .expect("Error writing to stderr");
+        process::exit(1);
+    }
+}
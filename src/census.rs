@@ -0,0 +1,276 @@
+//! Support for zip code population files from censuses other than 2010, and
+//! from yearly population estimates, so that historical data sets can be
+//! chunked using the population of their own time.
+//!
+//! Census files use the same layout as our built-in `zip2010.csv`: a header
+//! row, followed by a zip code and a population on each row. Estimate files
+//! have a column for each year instead. We read them at runtime instead of
+//! building them into our executable.
+//!
+//! Populations may be formatted for people, like `"1,234"`, and fractional
+//! estimates are rounded using a `Rounding`. See `number::parse_weight`.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+
+use errors::*;
+use number::{self, Rounding};
+
+/// Read a zip code population CSV file. Zip codes with leading zeros may be
+/// given as numbers, but each zip code may only appear once.
+pub fn load_zip_populations(input: &mut dyn Read,
+                            rounding: Rounding)
+                            -> Result<Vec<(String, u64)>> {
+    let mut rdr = csv::Reader::from_reader(input);
+    load_column(&mut rdr, 1, rounding)
+}
+
+/// Read a CSV file of yearly zip code population estimates, with zip codes
+/// in the first column and a column for each year, and return the estimates
+/// for `year`. Year columns may be named like `2015` or `POPESTIMATE2015`.
+pub fn load_zip_estimates(input: &mut dyn Read,
+                          year: u16,
+                          rounding: Rounding)
+                          -> Result<Vec<(String, u64)>> {
+    let mut rdr = csv::Reader::from_reader(input);
+    let year = year.to_string();
+    let col_idx = rdr.headers()?
+        .iter()
+        .skip(1)
+        .position(|h| h.trim().ends_with(&year))
+        .ok_or_else(|| Error::no_such_column(&year[..]))?;
+    load_column(&mut rdr, col_idx + 1, rounding)
+}
+
+/// List the years with columns in a CSV file of yearly zip code population
+/// estimates, as accepted by `load_zip_estimates`.
+pub fn estimate_years(input: &mut dyn Read) -> Result<Vec<u16>> {
+    let mut rdr = csv::Reader::from_reader(input);
+    let mut years = rdr.headers()?
+        .iter()
+        .skip(1)
+        .filter_map(|h| {
+            let h = h.trim();
+            h.get(h.len().saturating_sub(4)..)
+                .filter(|y| y.len() == 4)
+                .and_then(|y| y.parse().ok())
+        })
+        .collect::<Vec<u16>>();
+    years.sort();
+    years.dedup();
+    Ok(years)
+}
+
+/// Read the zip codes in the first column of `rdr`, and the populations in
+/// column `pop_idx`.
+fn load_column(rdr: &mut csv::Reader<&mut dyn Read>,
+               pop_idx: usize,
+               rounding: Rounding)
+               -> Result<Vec<(String, u64)>> {
+    let mut seen = HashSet::new();
+    let mut populations = vec![];
+    for row in rdr.records() {
+        let row = row?;
+        let (zip, pop) = match (row.get(0), row.get(pop_idx)) {
+            (Some(zip), Some(pop)) => (zip, pop),
+            _ => return Err(Error::invalid_weight("", row.position())),
+        };
+        let zip = normalize_zip(zip)
+            .ok_or_else(|| Error::invalid_weight(&row[0], row.position()))?;
+        let pop = number::parse_weight(pop, rounding)
+            .ok_or_else(|| Error::invalid_weight(pop, row.position()))?;
+        if !seen.insert(zip.clone()) {
+            return Err(format!("zip code {} appears more than once", zip).into());
+        }
+        populations.push((zip, pop));
+    }
+    populations.sort();
+    Ok(populations)
+}
+
+/// Pad `zip` with leading zeros, in case it was stored as a number, and
+/// return it if it's a valid 5-digit zip code. Blank fields aren't zip
+/// codes, so we don't pad them into `00000`.
+pub(crate) fn normalize_zip(zip: &str) -> Option<String> {
+    let zip = zip.trim();
+    if zip.is_empty() {
+        return None;
+    }
+    let zip = format!("{:0>5}", zip);
+    if zip.len() == 5 && zip.bytes().all(|b| b.is_ascii_digit()) {
+        Some(zip)
+    } else {
+        None
+    }
+}
+
+/// What we found when checking a zip code population file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DataReport {
+    /// The number of valid rows.
+    pub zips: u64,
+    /// The total population of the valid rows.
+    pub population: u64,
+    /// Everything wrong with the file, in order.
+    pub problems: Vec<DataProblem>,
+}
+
+/// A row which `load_zip_populations` would reject.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataProblem {
+    /// The line the row starts on.
+    pub line: u64,
+    /// What's wrong with it.
+    pub problem: String,
+}
+
+/// Check a zip code population file in the same layout as our built-in
+/// data, reporting every row which `load_zip_populations` would reject
+/// instead of stopping at the first one. A duplicated zip code would
+/// otherwise be counted twice, which skews our chunk boundaries.
+pub fn validate_zip_populations(input: &mut dyn Read,
+                                rounding: Rounding)
+                                -> Result<DataReport> {
+    let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+    let mut report = DataReport::default();
+    // Like `load_zip_populations`, we allow extra columns, so long as every
+    // row has as many fields as our header.
+    let columns = rdr.headers()?.len();
+    if columns < 2 {
+        report.problems.push(DataProblem {
+                                 line: 1,
+                                 problem: format!("expected at least 2 columns, found {}",
+                                                  columns),
+                             });
+        return Ok(report);
+    }
+    let mut first_line_for_zip = HashMap::new();
+    for row in rdr.records() {
+        let row = row?;
+        let line = row.position().map_or(0, |p| p.line());
+        let mut problem = |problem: String| {
+            report.problems.push(DataProblem { line, problem });
+        };
+        if row.len() != columns {
+            problem(format!("expected {} fields, found {}", columns, row.len()));
+            continue;
+        }
+        let zip = match normalize_zip(&row[0]) {
+            Some(zip) => zip,
+            None if row[0].trim().is_empty() => {
+                problem("zip code is blank".to_owned());
+                continue;
+            }
+            None => {
+                problem(format!("{:?} is not a 5-digit zip code", &row[0]));
+                continue;
+            }
+        };
+        let pop = match number::parse_weight(&row[1], rounding) {
+            Some(pop) => pop,
+            None => {
+                problem(format!("{:?} is not a valid population", &row[1]));
+                continue;
+            }
+        };
+        if let Some(first_line) = first_line_for_zip.insert(zip.clone(), line) {
+            problem(format!("zip code {} already appeared on line {}",
+                            zip,
+                            first_line));
+            // Keep reporting duplicates against the first occurrence.
+            first_line_for_zip.insert(zip, first_line);
+            continue;
+        }
+        report.zips += 1;
+        report.population += pop;
+    }
+    Ok(report)
+}
+
+#[test]
+fn loads_zip_populations() {
+    let _ = env_logger::init();
+    let load = |input: &str, rounding| {
+        load_zip_populations(&mut input.as_bytes(), rounding)
+    };
+    let input = "Zip Code ZCTA,2000 Census Population\n90210,21733\n1001,17438\n";
+    assert_eq!(load(input, Rounding::Nearest).unwrap(),
+               vec![("01001".to_owned(), 17438), ("90210".to_owned(), 21733)]);
+
+    let invalid = ["zip,pop\n90210,lots\n",
+                   "zip,pop\n90210,\"1,5\"\n",
+                   "zip,pop\n902101,5\n",
+                   "zip,pop\n90210,5\n90210,6\n"];
+    for input in &invalid {
+        assert!(load(input, Rounding::Nearest).is_err(), "{:?}", input);
+    }
+
+    // Spreadsheet exports often format their numbers for people.
+    let input = "zip,pop\n90210,\"21,733.4\"\n1001,\"'17 438.5'\"\n";
+    assert_eq!(load(input, Rounding::Nearest).unwrap(),
+               vec![("01001".to_owned(), 17439), ("90210".to_owned(), 21733)]);
+    assert_eq!(load(input, Rounding::Up).unwrap(),
+               vec![("01001".to_owned(), 17439), ("90210".to_owned(), 21734)]);
+}
+
+#[test]
+fn normalizes_zips() {
+    let _ = env_logger::init();
+    assert_eq!(normalize_zip("1001"), Some("01001".to_owned()));
+    assert_eq!(normalize_zip(" 90210 "), Some("90210".to_owned()));
+    for invalid in &["", "   ", "902101", "9021O"] {
+        assert_eq!(normalize_zip(invalid), None, "{:?}", invalid);
+    }
+    assert!(load_zip_populations(&mut "zip,pop\n,5000\n".as_bytes(), Rounding::Nearest)
+                .is_err());
+}
+
+#[test]
+fn validates_zip_populations() {
+    let _ = env_logger::init();
+    let input = "zip,pop\n90210,21733\n1001,17438\n902101,5\n07712,lots\n\
+                 90210,5\n07712,1,2\n90210,6\n,5000\n";
+    let report = validate_zip_populations(&mut input.as_bytes(), Rounding::Nearest)
+        .unwrap();
+    assert_eq!((report.zips, report.population), (2, 39171));
+    let problems = report
+        .problems
+        .iter()
+        .map(|p| (p.line, &p.problem[..]))
+        .collect::<Vec<_>>();
+    assert_eq!(problems,
+               vec![(4, "\"902101\" is not a 5-digit zip code"),
+                    (5, "\"lots\" is not a valid population"),
+                    (6, "zip code 90210 already appeared on line 2"),
+                    (7, "expected 2 fields, found 3"),
+                    (8, "zip code 90210 already appeared on line 2"),
+                    (9, "zip code is blank")]);
+
+    // We accept extra columns, just like `load_zip_populations`.
+    let input = "zip,pop,state\n90210,21733,CA\n1001,17438,MA\n";
+    let report = validate_zip_populations(&mut input.as_bytes(), Rounding::Nearest)
+        .unwrap();
+    assert_eq!((report.zips, report.problems.len()), (2, 0));
+    assert_eq!(load_zip_populations(&mut input.as_bytes(), Rounding::Nearest)
+                   .unwrap()
+                   .len(),
+               2);
+    let report = validate_zip_populations(&mut "zip\n90210\n".as_bytes(),
+                                          Rounding::Nearest)
+        .unwrap();
+    assert_eq!(report.problems[0].problem, "expected at least 2 columns, found 1");
+}
+
+#[test]
+fn loads_zip_estimates_for_a_year() {
+    let _ = env_logger::init();
+    let input = "zip,POPESTIMATE2014,POPESTIMATE2015\n90210,20000,20100\n07712,1,2\n";
+    let load = |year| load_zip_estimates(&mut input.as_bytes(), year, Rounding::Down);
+    assert_eq!(load(2015).unwrap(),
+               vec![("07712".to_owned(), 2), ("90210".to_owned(), 20100)]);
+    assert!(load(2016).is_err());
+    assert_eq!(estimate_years(&mut input.as_bytes()).unwrap(), vec![2014, 2015]);
+}
@@ -0,0 +1,173 @@
+//! Parsing chunk IDs we've already written, so that code reading stored IDs
+//! can recover the zip code prefix or vintage they came from without
+//! writing its own regexes.
+
+#[cfg(test)]
+use env_logger;
+use std::fmt;
+use std::str::FromStr;
+
+use errors::*;
+
+/// How a chunk ID was written. See `zip2010::Naming`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdFormat {
+    /// A prefix and the index of the chunk within it, like `902_0`.
+    Prefix,
+    /// A dense integer index, like `571`.
+    Integer,
+    /// A dense integer index padded with zeros, like `0571`. Padded IDs
+    /// which happen to start with a non-zero digit look just like
+    /// `Integer` IDs, and are parsed as them.
+    Sortable,
+    /// An HMAC of the chunk's prefixes, like `3d0b94fa7c2e1685`.
+    Hashed,
+}
+
+/// A parsed chunk ID, optionally qualified by the scheme it came from, like
+/// `zip2010:902_0`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkId {
+    /// How the ID was written.
+    pub format: IdFormat,
+    /// The scheme the ID came from, like `zip2010`, if it was qualified.
+    pub scheme: Option<String>,
+    /// The year of the scheme's population data, like `2010` for
+    /// `zip2010`, if the scheme's name ends with one.
+    pub vintage: Option<u16>,
+    /// The zip code prefix the chunk was split from, like `902`, for
+    /// `Prefix` IDs.
+    pub prefix: Option<String>,
+    /// The index in the ID. For `Prefix` IDs, this is the index of the
+    /// chunk among those split from the same prefix. For `Integer` and
+    /// `Sortable` IDs, it's the chunk's dense index, as returned by
+    /// `Classifier::chunk_index`.
+    pub index: Option<usize>,
+    /// The ID without its scheme.
+    id: String,
+}
+
+impl ChunkId {
+    /// Parse `id`, which may be qualified by a scheme as in
+    /// `zip2010:902_0`. IDs which don't look like any of our `IdFormat`s,
+    /// such as the chunk for unknown zip codes or IDs built from templates,
+    /// are an error.
+    pub fn parse(id: &str) -> Result<ChunkId> {
+        let (scheme, bare) = match id.rsplit_once(':') {
+            Some((scheme, bare)) => {
+                let valid = !scheme.is_empty() &&
+                            scheme
+                                .bytes()
+                                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+                if !valid {
+                    return Err(format!("chunk ID {:?} has an invalid scheme", id).into());
+                }
+                (Some(scheme), bare)
+            }
+            None => (None, id),
+        };
+        let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        let parse_index = |index: &str| {
+            index
+                .parse::<usize>()
+                .map_err(|_| format!("chunk ID {:?} has an index which is too large", id))
+        };
+        let (format, prefix, index) = match bare.split_once('_') {
+            Some((prefix, index)) if is_digits(prefix) && prefix.len() <= 5 &&
+                                     is_digits(index) => {
+                (IdFormat::Prefix, Some(prefix.to_owned()), Some(parse_index(index)?))
+            }
+            Some(_) => return Err(format!("chunk ID {:?} isn't in a format we know", id).into()),
+            None if is_digits(bare) => {
+                let format = if bare.len() > 1 && bare.starts_with('0') {
+                    IdFormat::Sortable
+                } else {
+                    IdFormat::Integer
+                };
+                (format, None, Some(parse_index(bare)?))
+            }
+            None if bare.len() == 16 &&
+                    bare.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) => {
+                (IdFormat::Hashed, None, None)
+            }
+            None => return Err(format!("chunk ID {:?} isn't in a format we know", id).into()),
+        };
+        let vintage = scheme.and_then(|scheme| {
+            let digits = scheme.trim_start_matches(|c: char| !c.is_ascii_digit());
+            if digits.len() == 4 && is_digits(digits) {
+                digits.parse().ok()
+            } else {
+                None
+            }
+        });
+        Ok(ChunkId {
+               format,
+               scheme: scheme.map(|s| s.to_owned()),
+               vintage,
+               prefix,
+               index,
+               id: bare.to_owned(),
+           })
+    }
+
+    /// The ID without its scheme, like `902_0`, which is what a classifier
+    /// returns from `chunk_for`.
+    pub fn bare_id(&self) -> &str {
+        &self.id
+    }
+
+    /// The first three digits of our prefix, if we have one that long.
+    pub fn zip3(&self) -> Option<&str> {
+        self.prefix.as_ref().and_then(|prefix| prefix.get(..3))
+    }
+}
+
+impl FromStr for ChunkId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ChunkId> {
+        ChunkId::parse(s)
+    }
+}
+
+/// Write the ID the way it was parsed, qualified by its scheme if it has
+/// one.
+impl fmt::Display for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.scheme {
+            Some(ref scheme) => write!(f, "{}:{}", scheme, self.id),
+            None => self.id.fmt(f),
+        }
+    }
+}
+
+#[test]
+fn parses_chunk_ids() {
+    let _ = env_logger::init();
+    let id = ChunkId::parse("zip2010:0213_2").unwrap();
+    assert_eq!(id.format, IdFormat::Prefix);
+    assert_eq!(id.scheme.as_ref().map(|s| &s[..]), Some("zip2010"));
+    assert_eq!(id.vintage, Some(2010));
+    assert_eq!(id.prefix.as_ref().map(|s| &s[..]), Some("0213"));
+    assert_eq!(id.zip3(), Some("021"));
+    assert_eq!(id.index, Some(2));
+    assert_eq!(id.bare_id(), "0213_2");
+    assert_eq!(id.to_string(), "zip2010:0213_2");
+
+    let id = ChunkId::parse("01_0").unwrap();
+    assert_eq!((id.scheme.is_none(), id.vintage, id.zip3()), (true, None, None));
+    assert_eq!(ChunkId::parse("zip-est2015:571").unwrap().vintage, Some(2015));
+    assert_eq!(ChunkId::parse("soi_returns:571").unwrap().vintage, None);
+
+    let id = ChunkId::parse("0571").unwrap();
+    assert_eq!((id.format, id.index), (IdFormat::Sortable, Some(571)));
+    let id = ChunkId::parse("0").unwrap();
+    assert_eq!((id.format, id.index), (IdFormat::Integer, Some(0)));
+    let id = ChunkId::parse("3d0b94fa7c2e1685").unwrap();
+    assert_eq!((id.format, id.index), (IdFormat::Hashed, None));
+
+    for bad in &["", "unknown", "902_", "_0", "902_x", "123456_0", ":902_0", "a b:902_0",
+                 "CA-902-0001", "3D0B94FA7C2E1685"] {
+        assert!(ChunkId::parse(bad).is_err(), "{:?}", bad);
+    }
+}
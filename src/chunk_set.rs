@@ -0,0 +1,166 @@
+//! Sets of geochunks, for selecting regions in code.
+
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::collections::BTreeSet;
+use std::collections::btree_set;
+use std::iter::FromIterator;
+
+use zip2010::Classifier;
+
+/// A set of chunk IDs from a single classifier. These can be combined with
+/// the usual set operations, so that selections like "every chunk touching
+/// the Northeast, minus these three metro areas" can be written directly.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkSet {
+    chunks: BTreeSet<String>,
+}
+
+impl ChunkSet {
+    /// Create an empty set.
+    pub fn new() -> ChunkSet {
+        ChunkSet::default()
+    }
+
+    /// Every chunk containing at least one of `zips`. Zip codes which can't
+    /// be classified are ignored.
+    pub fn from_zips<I, S>(classifier: &Classifier, zips: I) -> ChunkSet
+        where I: IntoIterator<Item = S>,
+              S: AsRef<str>
+    {
+        zips.into_iter()
+            .filter_map(|zip| classifier.chunk_for(zip.as_ref()).map(|c| c.to_owned()))
+            .collect()
+    }
+
+    /// Every chunk containing at least one zip code starting with `prefix`,
+    /// such as `"0"` for New England.
+    pub fn touching_prefix(classifier: &Classifier, prefix: &str) -> ChunkSet {
+        ChunkSet::from_zips(classifier,
+                            all_zips().filter(|zip| zip.starts_with(prefix)))
+    }
+
+    /// Every 5-digit zip code which `classifier` assigns to one of our
+    /// chunks, in order.
+    pub fn to_zips(&self, classifier: &Classifier) -> Vec<String> {
+        all_zips()
+            .filter(|zip| {
+                        classifier
+                            .chunk_for(zip)
+                            .is_some_and(|chunk| self.contains(chunk))
+                    })
+            .collect()
+    }
+
+    /// Add a chunk to this set. Returns false if it was already present.
+    pub fn insert<S: Into<String>>(&mut self, chunk_id: S) -> bool {
+        self.chunks.insert(chunk_id.into())
+    }
+
+    /// Remove a chunk from this set. Returns false if it wasn't present.
+    pub fn remove(&mut self, chunk_id: &str) -> bool {
+        self.chunks.remove(chunk_id)
+    }
+
+    /// Is `chunk_id` in this set?
+    pub fn contains(&self, chunk_id: &str) -> bool {
+        self.chunks.contains(chunk_id)
+    }
+
+    /// How many chunks are in this set?
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Is this set empty?
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Iterate over our chunk IDs, in sorted order.
+    pub fn iter(&self) -> btree_set::Iter<'_, String> {
+        self.chunks.iter()
+    }
+
+    /// Chunks in either set.
+    pub fn union(&self, other: &ChunkSet) -> ChunkSet {
+        self.chunks.union(&other.chunks).cloned().collect()
+    }
+
+    /// Chunks in both sets.
+    pub fn intersection(&self, other: &ChunkSet) -> ChunkSet {
+        self.chunks.intersection(&other.chunks).cloned().collect()
+    }
+
+    /// Chunks in this set but not in `other`.
+    pub fn difference(&self, other: &ChunkSet) -> ChunkSet {
+        self.chunks.difference(&other.chunks).cloned().collect()
+    }
+
+    /// Total population of our chunks, according to `classifier`.
+    pub fn population(&self, classifier: &Classifier) -> u64 {
+        self.iter()
+            .filter_map(|chunk| classifier.chunk_population(chunk))
+            .sum()
+    }
+}
+
+impl<S: Into<String>> FromIterator<S> for ChunkSet {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> ChunkSet {
+        ChunkSet { chunks: iter.into_iter().map(|c| c.into()).collect() }
+    }
+}
+
+impl<S: Into<String>> Extend<S> for ChunkSet {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, iter: I) {
+        self.chunks.extend(iter.into_iter().map(|c| c.into()));
+    }
+}
+
+impl IntoIterator for ChunkSet {
+    type Item = String;
+    type IntoIter = btree_set::IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ChunkSet {
+    type Item = &'a String;
+    type IntoIter = btree_set::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.iter()
+    }
+}
+
+/// Every possible 5-digit zip code, in order.
+fn all_zips() -> impl Iterator<Item = String> {
+    (0..100000).map(|zip_int| format!("{:05}", zip_int))
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn combines_chunk_sets() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let new_england = ChunkSet::touching_prefix(&classifier, "0");
+    let metros = ChunkSet::from_zips(&classifier, ["02108", "90210", "bogus"]);
+    assert_eq!(metros.len(), 2);
+    assert!(metros.contains("902_0"));
+
+    let rest = new_england.difference(&metros);
+    assert_eq!(rest.len(), new_england.len() - 1);
+    assert_eq!(new_england.intersection(&metros).len(), 1);
+    assert_eq!(new_england.union(&metros).len(), new_england.len() + 1);
+    assert_eq!(rest.union(&metros.intersection(&new_england)), new_england);
+
+    let zips = metros.to_zips(&classifier);
+    assert!(zips.contains(&"90210".to_owned()));
+    assert!(zips.iter().all(|zip| metros.contains(classifier.chunk_for(zip).unwrap())));
+    assert_eq!(ChunkSet::from_zips(&classifier, &zips), metros);
+    assert_eq!(metros.population(&classifier),
+               classifier.chunk_population("902_0").unwrap() +
+               classifier.chunk_population(classifier.chunk_for("02108").unwrap()).unwrap());
+}
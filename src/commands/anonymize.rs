@@ -0,0 +1,78 @@
+//! `geochunk anonymize`: replace zip codes with geochunks, suppressing
+//! small chunks.
+
+use std::io;
+use std::io::prelude::*;
+
+use geochunk::{anonymize, transform};
+use geochunk::errors::*;
+use geochunk::transform::TransformOptions;
+
+use super::{ChunkColumnOptions, ChunkOptions, ChunkSpec, CsvOptions};
+
+pub const USAGE: &str = concat!("
+Replace zip codes in a CSV file with geochunks, blanking out any geochunks
+with fewer than --min-rows rows.
+
+Usage:
+  geochunk anonymize [options] [--drop-column=<col>]... <spec> <input-column> <input-file>
+  geochunk anonymize --help
+
+Options:
+  --drop-column=<col>      Remove a column, such as ZIP+4 or city, from the
+                           anonymized output.
+  --min-rows=<n>           Suppress geochunks with fewer than <n> rows in the
+                           anonymized output [default: 10].
+",
+                                common_options!(),
+                                chunk_column_options!(),
+                                chunk_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    arg_input_column: String,
+    arg_input_file: String,
+    flag_drop_column: Vec<String>,
+    flag_min_rows: u64,
+}
+
+/// Run `geochunk anonymize`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk_column: ChunkColumnOptions = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let column = &args.arg_input_column;
+    let path = &args.arg_input_file;
+
+    // We need to read our input twice: once to count rows in each chunk, and
+    // once to write our output.
+    let open_input = || csv.open_csv_input(csv.open_input_file(path)?);
+    let mut input = open_input()?;
+    let dialect = csv.dialect(input.fill_buf()?)?;
+    let counts = anonymize::count_rows_by_chunk(&classifier, column, &dialect, &mut input)
+        .chain_err(|| format!("error processing {}", path))?;
+    let options = TransformOptions {
+        replace: true,
+        drop_columns: args.flag_drop_column.clone(),
+        suppressed_chunks: anonymize::chunks_to_suppress(&counts.chunks, args.flag_min_rows),
+        output_column: chunk_column.flag_output_column.clone(),
+        strict: chunk_column.flag_strict,
+        dialect,
+        ..TransformOptions::default()
+    };
+    let stdout = io::stdout();
+    let stats = transform::transform_csv(&classifier,
+                                         &[column],
+                                         &options,
+                                         &mut open_input()?,
+                                         &mut stdout.lock())
+            .chain_err(|| format!("error processing {}", path))?;
+    chunk_column.exit_code_for_stats(stats)
+}
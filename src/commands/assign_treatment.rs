@@ -0,0 +1,89 @@
+//! `geochunk assign-treatment`: assign chunks to experiment arms.
+
+use std::fs::File;
+
+use geochunk::experiment;
+use geochunk::errors::*;
+use geochunk::experiment::ArmAssignment;
+use geochunk::transform::TransformOptions;
+
+use super::{ChunkColumnOptions, ChunkOptions, ChunkSpec, CsvOptions};
+
+pub const USAGE: &str = concat!("
+Assign each chunk of <spec> to one of --arms at random, so that each arm has
+a similar population, and add a column containing the arm of each row, like
+csv. The same --seed always gives the same arms. With --matched-pairs,
+similar chunks are paired up first, and each pair is split between the arms.
+
+Usage:
+  geochunk assign-treatment [options] --arms=<list> <spec> <input-column> [<input-file>]
+  geochunk assign-treatment --help
+
+Options:
+  --arms=<list>            A comma-separated list of experiment arms, such as
+                           \"control,testA,testB\".
+  --seed=<n>               The random seed used to assign chunks to arms
+                           [default: 0].
+  --matched-pairs          Match each chunk with the chunks most similar in
+                           population, and in density if --gazetteer is
+                           given, and put each chunk of a pair in a
+                           different arm.
+  --pairs-file=<path>      Write the pair, arm, population and density of each
+                           chunk to <path>.
+",
+                                common_options!(),
+                                chunk_column_options!(),
+                                chunk_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    arg_input_column: String,
+    arg_input_file: Option<String>,
+    flag_arms: String,
+    flag_seed: u64,
+    flag_matched_pairs: bool,
+    flag_pairs_file: Option<String>,
+}
+
+/// Run `geochunk assign-treatment`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk_column: ChunkColumnOptions = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let arms = args.flag_arms
+        .split(',')
+        .map(|arm| arm.trim().to_owned())
+        .collect::<Vec<_>>();
+    let densities = chunk.load_zctas()?
+        .map(|zctas| experiment::chunk_densities(&classifier, &zctas));
+    let assignment = if args.flag_matched_pairs {
+        ArmAssignment::matched_pairs(&classifier, &arms, args.flag_seed, densities.as_ref())?
+    } else {
+        ArmAssignment::new(&classifier, &arms, args.flag_seed)?
+    };
+    if let Some(ref path) = args.flag_pairs_file {
+        let mut file = File::create(path)
+            .chain_err(|| format!("could not create {}", path))?;
+        assignment
+            .write_pairs(&classifier, densities.as_ref(), &mut file)
+            .chain_err(|| format!("could not write {}", path))?;
+    }
+    let options = TransformOptions {
+        arms: Some(assignment),
+        ..TransformOptions::default()
+    };
+    let stats = super::transform_input(&classifier,
+                                       &args.arg_input_column,
+                                       args.arg_input_file.as_deref(),
+                                       &chunk_column,
+                                       &csv,
+                                       options)?;
+    chunk_column.exit_code_for_stats(stats)
+}
@@ -0,0 +1,70 @@
+//! `geochunk build-geography`: build chunks from a boundaries file.
+
+use std::fs::File;
+use std::path::Path;
+
+use geochunk::errors::*;
+use geochunk::geography::Geography;
+
+use super::EXIT_SUCCESS;
+
+pub const USAGE: &str = concat!("
+Build chunks of about <population> from the regions in a .shp, .gpkg or
+.geojson <boundaries-file>, and write them to <geography-file> for use by
+locate.
+
+Usage:
+  geochunk build-geography [options] --weight-field=<name> <boundaries-file> <population> <geography-file>
+  geochunk build-geography --help
+
+Options:
+  --weight-field=<name>    The attribute containing the weight of each region,
+                           such as its population.
+  --id-field=<name>        The attribute identifying each region. By default,
+                           regions are numbered from 1 in the order they
+                           appear.
+  --weight-rounding=<mode>
+                           How to round fractional weights: nearest, down or
+                           up [default: nearest].
+",
+                                common_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_boundaries_file: String,
+    arg_population: u64,
+    arg_geography_file: String,
+    flag_weight_field: String,
+    flag_id_field: Option<String>,
+    flag_weight_rounding: String,
+}
+
+/// Run `geochunk build-geography`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+
+    let path = &args.arg_boundaries_file;
+    let shapes = super::load_boundaries(path)?;
+    let name = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("geography");
+    let geography = Geography::build(name,
+                                     shapes,
+                                     &args.flag_weight_field,
+                                     args.flag_id_field.as_deref(),
+                                     args.arg_population,
+                                     args.flag_weight_rounding.parse()?)
+            .chain_err(|| format!("could not build a geography from {}", path))?;
+    let out_path = &args.arg_geography_file;
+    let mut file = File::create(out_path)
+        .chain_err(|| format!("could not create {}", out_path))?;
+    geography.write(&mut file)
+        .chain_err(|| format!("could not write {}", out_path))?;
+    eprintln!("built {} chunks from {} regions",
+              geography.chunk_count(),
+              geography.regions().len());
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,49 @@
+//! `geochunk completions`: print a shell completion script.
+
+use std::io;
+
+use geochunk::completions;
+use geochunk::errors::*;
+
+use super::{COMMANDS, EXIT_SUCCESS};
+
+pub const USAGE: &str = "
+Print a completion script for <shell>, which may be bash, zsh or fish.
+
+Usage:
+  geochunk completions [options] <shell>
+  geochunk completions --help
+
+Options:
+  --help                   Show this screen.
+";
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_shell: String,
+}
+
+/// Run `geochunk completions`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+
+    let shell = args.arg_shell.parse()?;
+    let commands = COMMANDS.iter().map(|c| c.name).collect::<Vec<_>>();
+    let mut options = super::all_options();
+    options.push("--version");
+    let types = super::registry()
+        .schemes()
+        .map(|s| s.name())
+        .collect::<Vec<_>>();
+    let types = types.iter().map(|t| &t[..]).collect::<Vec<_>>();
+    let words = completions::CompletionWords {
+        commands: &commands,
+        options: &options,
+        types: &types,
+    };
+    let stdout = io::stdout();
+    completions::write_completions(shell, &words, &mut stdout.lock())?;
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,382 @@
+//! `geochunk copartition`: split two CSV files into the same chunks.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use geochunk::{manifest, partition};
+use geochunk::dialect::Dialect;
+use geochunk::errors::*;
+use geochunk::format::OutputFormat;
+use geochunk::manifest::Manifest;
+#[cfg(feature = "orc")]
+use geochunk::orc;
+#[cfg(feature = "parquet")]
+use geochunk::parquet_files;
+use geochunk::shard::{ShardInfo, ShardLimits};
+use geochunk::zip2010::Classifier;
+
+use super::{ChunkOptions, ChunkSpec, CsvOptions, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Split two CSV files into <output-dir>/<chunk>/<file>, using the same chunks
+for both, so that they can be joined on zip code one chunk at a time. Every
+chunk gets a pair of files, even if it has no rows. Prints the rows written
+to each chunk, and writes <output-dir>/manifest.json listing each file with
+its chunk, row count, size and SHA-256 checksum. With --io-uring, we also
+write our output using io_uring, which can be faster when writing thousands
+of chunk files.
+
+Usage:
+  geochunk copartition [options] <spec> <output-dir> <left-column> <left-file> <right-column> <right-file>
+  geochunk copartition --help
+
+Options:
+  --format=<fmt>           The output format: csv, orc or parquet
+                           [default: csv]. orc writes ORC files, which
+                           requires the orc feature. parquet writes Parquet
+                           files with a geochunk column, which requires the
+                           parquet feature.
+  --output-column=<name>   With --format=parquet, the name of the geochunk
+                           column.
+  --orc-compression=<codec>
+                           With --format=orc, how to compress our output:
+                           none, zlib, snappy, lz4 or zstd [default: zlib].
+  --parquet-compression=<codec>
+                           With --format=parquet, how to compress our output:
+                           none, snappy, gzip or zstd [default: snappy].
+  --sort-by-zip            With --format=parquet, sort each file by zip code,
+                           so that queries can skip more of it.
+  --max-rows-per-file=<n>  Split each output file into shards of at most <n>
+                           rows, named like people-00000.csv.
+  --max-bytes-per-file=<n>
+                           Split each output file into shards of at most <n>
+                           bytes.
+  --skip-if-done           Do nothing if <output-dir> already contains the
+                           complete output of a run with the same inputs,
+                           options and data files.
+",
+                                common_options!(),
+                                chunk_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    arg_output_dir: String,
+    arg_left_column: String,
+    arg_left_file: String,
+    arg_right_column: String,
+    arg_right_file: String,
+    flag_format: String,
+    #[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+    flag_output_column: Option<String>,
+    #[cfg_attr(not(feature = "orc"), allow(dead_code))]
+    flag_orc_compression: String,
+    #[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+    flag_parquet_compression: String,
+    flag_sort_by_zip: bool,
+    flag_max_rows_per_file: Option<u64>,
+    flag_max_bytes_per_file: Option<u64>,
+    flag_skip_if_done: bool,
+}
+
+impl Args {
+    /// How large our output files may be.
+    fn shard_limits(&self) -> ShardLimits {
+        ShardLimits {
+            max_rows: self.flag_max_rows_per_file,
+            max_bytes: self.flag_max_bytes_per_file,
+        }
+    }
+}
+
+/// The options which affect our output, which we hash to decide whether
+/// `--skip-if-done` can skip a job. These include any options from our
+/// config file, which affect our output just as much. Data files are listed
+/// by path here, and their contents are hashed separately.
+#[derive(Debug, Serialize)]
+struct CopartitionParams<'a> {
+    version: &'a str,
+    spec: String,
+    left_column: &'a str,
+    right_column: &'a str,
+    soi_file: &'a Option<String>,
+    census2000_file: &'a Option<String>,
+    estimates_file: &'a Option<String>,
+    weight_rounding: &'a str,
+    gazetteer: &'a Option<String>,
+    dma_file: &'a Option<String>,
+    mx_cp_file: &'a Option<String>,
+    density_breaks: &'a Option<String>,
+    chunk_id_salt_file: &'a Option<String>,
+    id_template: &'a Option<String>,
+    zip_patch: &'a Option<String>,
+    population_noise: Option<f64>,
+    noise_seed: Option<u64>,
+    new_zip_chunk: &'a Option<String>,
+    new_zip_fallback: bool,
+    output_column: &'a Option<String>,
+    delimiter: &'a Option<String>,
+    quote: &'a str,
+    escape: &'a Option<String>,
+    comment_char: &'a Option<String>,
+    quote_style: &'a str,
+    compression: &'a str,
+    encoding: &'a str,
+    on_ragged: &'a str,
+    max_rows_per_file: Option<u64>,
+    max_bytes_per_file: Option<u64>,
+    format: &'a str,
+    orc_compression: &'a str,
+    parquet_compression: &'a str,
+    sort_by_zip: bool,
+}
+
+/// Describe the options which affect our output, in a stable form, so that
+/// changing anything else, such as `--io-uring`, doesn't make a finished job
+/// look out of date.
+fn copartition_params(args: &Args, chunk: &ChunkOptions, csv: &CsvOptions) -> Result<String> {
+    let params = CopartitionParams {
+        version: env!("CARGO_PKG_VERSION"),
+        spec: args.arg_spec.to_string(),
+        left_column: &args.arg_left_column,
+        right_column: &args.arg_right_column,
+        soi_file: &chunk.flag_soi_file,
+        census2000_file: &chunk.flag_census2000_file,
+        estimates_file: &chunk.flag_estimates_file,
+        weight_rounding: &chunk.flag_weight_rounding,
+        gazetteer: &chunk.flag_gazetteer,
+        dma_file: &chunk.flag_dma_file,
+        mx_cp_file: &chunk.flag_mx_cp_file,
+        density_breaks: &chunk.flag_density_breaks,
+        chunk_id_salt_file: &chunk.flag_chunk_id_salt_file,
+        id_template: &chunk.flag_id_template,
+        zip_patch: &chunk.flag_zip_patch,
+        population_noise: chunk.flag_population_noise,
+        noise_seed: chunk.flag_noise_seed,
+        new_zip_chunk: &chunk.flag_new_zip_chunk,
+        new_zip_fallback: chunk.flag_new_zip_fallback,
+        output_column: &args.flag_output_column,
+        delimiter: &csv.flag_delimiter,
+        quote: &csv.flag_quote,
+        escape: &csv.flag_escape,
+        comment_char: &csv.flag_comment_char,
+        quote_style: &csv.flag_quote_style,
+        compression: &csv.flag_compression,
+        encoding: &csv.flag_encoding,
+        on_ragged: &csv.flag_on_ragged,
+        max_rows_per_file: args.flag_max_rows_per_file,
+        max_bytes_per_file: args.flag_max_bytes_per_file,
+        format: &args.flag_format,
+        orc_compression: &args.flag_orc_compression,
+        parquet_compression: &args.flag_parquet_compression,
+        sort_by_zip: args.flag_sort_by_zip,
+    };
+    serde_json::to_string(&params).chain_err(|| "could not describe job options")
+}
+
+/// Like `orc::partition_csv`, compressing our output as requested by `args`.
+#[cfg(feature = "orc")]
+fn partition_csv_to_orc(args: &Args,
+                        classifier: &Classifier,
+                        column: &str,
+                        dialect: &Dialect,
+                        input: &mut dyn Read,
+                        output_dir: &Path,
+                        file_name: &str)
+                        -> Result<BTreeMap<String, Vec<ShardInfo>>> {
+    let compression = args.flag_orc_compression.parse()?;
+    orc::partition_csv(classifier,
+                       column,
+                       dialect,
+                       input,
+                       output_dir,
+                       file_name,
+                       compression)
+}
+
+/// We were built without ORC support.
+#[cfg(not(feature = "orc"))]
+fn partition_csv_to_orc(_: &Args,
+                        _: &Classifier,
+                        _: &str,
+                        _: &Dialect,
+                        _: &mut dyn Read,
+                        _: &Path,
+                        _: &str)
+                        -> Result<BTreeMap<String, Vec<ShardInfo>>> {
+    Err("--format=orc requires the orc feature".into())
+}
+
+/// Like `parquet_files::partition_csv`, using the options in `args`.
+#[cfg(feature = "parquet")]
+fn partition_csv_to_parquet(args: &Args,
+                            classifier: &Classifier,
+                            column: &str,
+                            dialect: &Dialect,
+                            input: &mut dyn Read,
+                            output_dir: &Path,
+                            file_name: &str)
+                            -> Result<BTreeMap<String, Vec<ShardInfo>>> {
+    let options = parquet_files::ParquetOptions {
+        chunk_column: args.flag_output_column
+            .clone()
+            .unwrap_or_else(|| classifier.geochunk_column_name()),
+        sort_by_zip: args.flag_sort_by_zip,
+        compression: args.flag_parquet_compression.parse()?,
+    };
+    parquet_files::partition_csv(classifier,
+                                 column,
+                                 dialect,
+                                 input,
+                                 output_dir,
+                                 file_name,
+                                 &options)
+}
+
+/// We were built without Parquet support.
+#[cfg(not(feature = "parquet"))]
+fn partition_csv_to_parquet(_: &Args,
+                            _: &Classifier,
+                            _: &str,
+                            _: &Dialect,
+                            _: &mut dyn Read,
+                            _: &Path,
+                            _: &str)
+                            -> Result<BTreeMap<String, Vec<ShardInfo>>> {
+    Err("--format=parquet requires the parquet feature".into())
+}
+
+/// The name to use for `path` in each chunk directory. We drop any
+/// compression extension, because we write uncompressed output.
+fn partition_file_name(path: &str) -> Result<String> {
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("cannot find a file name in {}", path))?;
+    Ok(name.strip_suffix(".gz")
+           .or_else(|| name.strip_suffix(".zst"))
+           .unwrap_or(name)
+           .to_owned())
+}
+
+/// Run `geochunk copartition`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec.clone())?;
+    let output_dir = Path::new(&args.arg_output_dir);
+    let inputs = [(&args.arg_left_column, &args.arg_left_file),
+                  (&args.arg_right_column, &args.arg_right_file)];
+    let format = args.flag_format.parse()?;
+    let extension = match format {
+        OutputFormat::Csv => None,
+        OutputFormat::Orc => Some("orc"),
+        OutputFormat::Parquet => Some("parquet"),
+        _ => return Err("copartition only supports --format=csv, orc or parquet".into()),
+    };
+    if extension.is_some() && !args.shard_limits().is_unlimited() {
+        return Err(format!("--format={} cannot split its output into shards",
+                           args.flag_format)
+                           .into());
+    }
+    if extension.is_some() && csv.flag_io_uring {
+        return Err(format!("--io-uring cannot be used with --format={}", args.flag_format)
+                       .into());
+    }
+    if args.flag_sort_by_zip && format != OutputFormat::Parquet {
+        return Err("--sort-by-zip requires --format=parquet".into());
+    }
+    let job_hash = if args.flag_skip_if_done {
+        let paths = inputs
+            .iter()
+            .map(|&(_, path)| Path::new(path))
+            .chain(chunk.data_files().into_iter().map(Path::new))
+            .collect::<Vec<_>>();
+        let hash = manifest::hash_job(&copartition_params(&args, &chunk, &csv)?, &paths)?;
+        if let Some(existing) = Manifest::read_from_dir(output_dir)? {
+            if existing.job_hash.as_ref() == Some(&hash) && existing.is_complete(output_dir) {
+                eprintln!("{} is already up to date, skipping", output_dir.display());
+                return Ok(EXIT_SUCCESS);
+            }
+        }
+        Some(hash)
+    } else {
+        None
+    };
+    let mut names = vec![];
+    let mut shards = vec![];
+    for &(column, path) in &inputs {
+        let mut name = partition_file_name(path)?;
+        if let Some(extension) = extension {
+            name = Path::new(&name)
+                .with_extension(extension)
+                .to_string_lossy()
+                .into_owned();
+        }
+        if names.contains(&name) {
+            return Err(format!("both input files would be written to {}", name).into());
+        }
+        let mut input = csv.open_csv_input(csv.open_input_file(path)?)?;
+        let dialect = csv.dialect(input.fill_buf()?)?;
+        let file_shards = match format {
+            OutputFormat::Orc => {
+                partition_csv_to_orc(&args,
+                                     &classifier,
+                                     column,
+                                     &dialect,
+                                     &mut input,
+                                     output_dir,
+                                     &name)
+            }
+            OutputFormat::Parquet => {
+                partition_csv_to_parquet(&args,
+                                         &classifier,
+                                         column,
+                                         &dialect,
+                                         &mut input,
+                                         output_dir,
+                                         &name)
+            }
+            _ => {
+                partition::partition_csv(&classifier,
+                                         column,
+                                         &dialect,
+                                         &mut input,
+                                         output_dir,
+                                         &name,
+                                         args.shard_limits(),
+                                         csv.flag_io_uring)
+            }
+        };
+        shards.push(file_shards.chain_err(|| format!("error processing {}", path))?);
+        names.push(name);
+    }
+    let mut manifest = Manifest::new(&classifier);
+    manifest.job_hash = job_hash;
+    for file_shards in &shards {
+        manifest.add_shards(output_dir, file_shards)?;
+    }
+    manifest.write_to_dir(output_dir)?;
+    let stdout = io::stdout();
+    let mut wtr = csv::Writer::from_writer(stdout.lock());
+    wtr.write_record(["chunk", &names[0], &names[1]])?;
+    let rows = |files: &BTreeMap<String, Vec<ShardInfo>>, chunk: &str| {
+        files[chunk]
+            .iter()
+            .map(|s| s.rows)
+            .sum::<u64>()
+            .to_string()
+    };
+    for chunk in classifier.chunk_ids() {
+        wtr.write_record([chunk, &rows(&shards[0], chunk), &rows(&shards[1], chunk)])?;
+    }
+    wtr.flush()?;
+    Ok(EXIT_SUCCESS)
+}
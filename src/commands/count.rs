@@ -0,0 +1,185 @@
+//! `geochunk count`: count the rows of CSV files in each chunk.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+
+use geochunk::{anonymize, encoding, jobs};
+use geochunk::anonymize::RowCounts;
+use geochunk::compression::Compression;
+use geochunk::encoding::Encoding;
+use geochunk::errors::*;
+use geochunk::format::{self, OutputFormat};
+use geochunk::mmap::Mmap;
+use geochunk::zip2010::Classifier;
+
+use super::{ChunkOptions, ChunkSpec, CsvOptions, EXIT_SUCCESS, EXIT_WARNINGS};
+
+pub const USAGE: &str = concat!("
+Count the rows of a CSV file in each geochunk, without writing the whole file
+back out. Rows without a valid zip code aren't in any chunk, so we report
+how many of them there were on standard error.
+
+Usage:
+  geochunk count [options] <spec> <input-column> [<input-file>...]
+  geochunk count --help
+
+Options:
+  --format=<fmt>           The output format: csv, json or yaml
+                           [default: csv].
+  --json                   The same as --format=json.
+  --threads=<n>            Split an uncompressed UTF-8 <input-file> into <n>
+                           pieces and count them in parallel [default: 1].
+  --jobs=<n>               How many input files to process at once
+                           [default: 1].
+",
+                                common_options!(),
+                                chunk_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    arg_input_column: String,
+    arg_input_file: Vec<String>,
+    flag_format: String,
+    flag_json: bool,
+    flag_threads: usize,
+    flag_jobs: usize,
+}
+
+/// Count the rows in each chunk of `column` in `paths`, or of standard
+/// input. We process up to `jobs` files at once, reporting on each as it
+/// finishes, and split each file into `threads` pieces.
+pub fn count_input_rows(classifier: &Classifier,
+                        column: &str,
+                        paths: &[String],
+                        csv: &CsvOptions,
+                        jobs: usize,
+                        threads: usize)
+                        -> Result<RowCounts> {
+    if paths.len() <= 1 {
+        return count_file_rows(classifier,
+                               column,
+                               paths.first().map(String::as_str),
+                               csv,
+                               threads);
+    }
+    let mut finished = 0;
+    let progress = |path: &str, counts: &RowCounts| {
+        finished += 1;
+        eprintln!("[{}/{}] {}: {} rows",
+                  finished,
+                  paths.len(),
+                  path,
+                  counts.chunks.values().sum::<u64>() + counts.dropped_rows);
+    };
+    let count = |path: &str| count_file_rows(classifier, column, Some(path), csv, threads);
+    let file_counts = jobs::run_jobs(paths, jobs, count, progress)?;
+    let mut counts = RowCounts::default();
+    for file_counts in file_counts {
+        counts.add(file_counts);
+    }
+    Ok(counts)
+}
+
+/// Decide how to exit after counting rows, telling the user about any rows
+/// which `count_input_rows` couldn't put in a chunk, since they're missing
+/// from our totals.
+pub fn exit_code_for_counts(counts: &RowCounts) -> i32 {
+    if counts.dropped_rows > 0 {
+        eprintln!("Warning: {} rows had no valid zip code, or the wrong number of \
+                   columns, and were not counted",
+                  counts.dropped_rows);
+        EXIT_WARNINGS
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+/// Count the rows in each chunk of `path`, or of standard input.
+fn count_file_rows(classifier: &Classifier,
+                   column: &str,
+                   path: Option<&str>,
+                   csv: &CsvOptions,
+                   threads: usize)
+                   -> Result<RowCounts> {
+    let count = |input: Box<dyn Read>| -> Result<RowCounts> {
+        let mut input = csv.open_csv_input(input)?;
+        let dialect = csv.dialect(input.fill_buf()?)?;
+        anonymize::count_rows_by_chunk(classifier, column, &dialect, &mut input)
+    };
+    // We can only split a file into pieces if it isn't compressed or
+    // transcoded.
+    let can_split = |data: &[u8]| -> Result<bool> {
+        let compression = match csv.flag_compression.parse()? {
+            Compression::Auto => Compression::detect(data),
+            other => other,
+        };
+        let encoding: Encoding = csv.flag_encoding.parse()?;
+        Ok(compression == Compression::None && encoding == Encoding::Utf8)
+    };
+    match path {
+        Some(path) if threads > 1 => {
+            let file = super::open_data_file(path)?;
+            let mapped = Mmap::open(&file)
+                .chain_err(|| format!("could not read {}", path))?
+                .ok_or("--threads needs a regular input file, not a pipe")?;
+            let mut data = &mapped[..];
+            encoding::skip_bom(&mut data)?;
+            if !can_split(data)? {
+                return Err("--threads needs an uncompressed UTF-8 input file".into());
+            }
+            let dialect = csv.dialect(data)?;
+            anonymize::count_rows_by_chunk_parallel(classifier, column, &dialect, data, threads)
+                .chain_err(|| format!("error processing {}", path))
+        }
+        Some(path) => {
+            count(csv.open_input_file(path)?).chain_err(|| format!("error processing {}", path))
+        }
+        None => count(Box::new(io::stdin())).chain_err(|| "error processing standard input"),
+    }
+}
+
+/// Run `geochunk count`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let counts = count_input_rows(&classifier,
+                                  &args.arg_input_column,
+                                  &args.arg_input_file,
+                                  &csv,
+                                  args.flag_jobs,
+                                  args.flag_threads)?;
+    // Include chunks without any rows, which are often the most interesting
+    // ones.
+    let rows_for = |chunk: &str| counts.chunks.get(chunk).cloned().unwrap_or(0);
+    let stdout = io::stdout();
+    let format = if args.flag_json {
+        OutputFormat::Json
+    } else {
+        args.flag_format.parse()?
+    };
+    if format == OutputFormat::Csv {
+        let mut wtr = csv::Writer::from_writer(stdout.lock());
+        wtr.write_record([&classifier.geochunk_column_name()[..], "rows"])?;
+        for chunk in classifier.chunk_ids() {
+            wtr.write_record([&chunk[..], &rows_for(chunk).to_string()])?;
+        }
+        wtr.flush()?;
+    } else {
+        let all_counts = classifier
+            .chunk_ids()
+            .iter()
+            .map(|c| (&c[..], rows_for(c)))
+            .collect::<BTreeMap<_, _>>();
+        format::write_structured(format, &all_counts, &mut stdout.lock())
+            .chain_err(|| "could not write counts")?;
+    }
+    Ok(exit_code_for_counts(&counts))
+}
@@ -0,0 +1,41 @@
+//! `geochunk crosswalk`: compare the chunks of two specs.
+
+use std::io;
+
+use geochunk::crosswalk;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Show how the population of each chunk in <from-spec> is divided among the
+chunks in <to-spec>.
+
+Usage:
+  geochunk crosswalk [options] <from-spec> <to-spec>
+  geochunk crosswalk --help
+
+Options:
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_from_spec: ChunkSpec,
+    arg_to_spec: ChunkSpec,
+}
+
+/// Run `geochunk crosswalk`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let from = chunk.build_classifier(args.arg_from_spec)?;
+    let to = chunk.build_classifier(args.arg_to_spec)?;
+    let stdout = io::stdout();
+    crosswalk::write_crosswalk(&from, &to, &mut stdout.lock())?;
+    Ok(EXIT_SUCCESS)
+}
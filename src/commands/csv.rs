@@ -0,0 +1,622 @@
+//! `geochunk csv`: add geochunk columns to a CSV file.
+
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use geochunk::{archive, census, gazetteer, in_place, scheme, transform};
+use geochunk::archive::ArchiveWriter;
+use geochunk::compression::Compression;
+use geochunk::districts::DistrictTable;
+use geochunk::dma::DmaTable;
+use geochunk::errors::*;
+use geochunk::format::OutputFormat;
+use geochunk::points::PointIndex;
+use geochunk::shard::ShardLimits;
+use geochunk::territory::Territories;
+use geochunk::transform::{ChunkPosition, TransformOptions, TransformStats};
+use geochunk::vintage::Vintages;
+use geochunk::zip2010::{Classifier, SpecOptions};
+
+use super::{ChunkColumnOptions, ChunkOptions, ChunkSpec, ChunkType, Config, CsvOptions};
+
+pub const USAGE: &str = concat!("
+Add a geochunk column to a CSV file (used in a pipeline). If more than one
+<input-column> is given, add one geochunk column for each. If <type>,
+<population> and <input-column> are omitted, they must be given by the
+config file or the environment, and we read from <input-file> if present.
+
+Usage:
+  geochunk csv [options] <type> <population> <input-column>...
+  geochunk csv [options] [<input-file>]
+  geochunk csv --help
+
+Options:
+  --replace                Replace the zip code column with the geochunk
+                           column, instead of adding a new column.
+  --insert-after=<col>     Add geochunk columns after <col>, instead of at the
+                           end of each row.
+  --prepend                Add geochunk columns at the start of each row.
+  --add-prefix             Also add a column with the zip code prefix used to
+                           find each geochunk.
+  --add-chunk-population   Also add a column with the population of each
+                           geochunk.
+  --add-assignment-method  Also add a column describing how each zip code
+                           was assigned to its geochunk: exact-5-digit,
+                           prefix-fallback-<n> when we used its first <n>
+                           digits, new-zip-fallback or new-zip-chunk.
+  --add-density            Also add a column with the population density of
+                           each zip code, in people per square mile, using
+                           the land areas from --gazetteer.
+  --add-urbanicity         Also add a column classifying each zip code as
+                           urban, suburban or rural by its population
+                           density, using the land areas from --gazetteer.
+                           This only approximates the census urban/rural
+                           split, which we don't have by zip code: below 500
+                           people per square mile is rural, and 3,000 or
+                           more is urban.
+  --add-timezone           Also add a column with the IANA time zone of each
+                           zip code, such as America/Chicago.
+  --add-dma                Also add a column with the designated market area
+                           of each zip code, from --dma-file.
+  --add-cd                 Also add a column with the congressional district
+                           of each zip code, from --cd-file. Zip codes split
+                           between districts get the one with most of their
+                           weight.
+  --cd-file=<path>         A CSV crosswalk from zip codes to congressional
+                           districts, with zip, district and optional weight
+                           columns, such as HUD's ZIP-CD file.
+  --add-sld                Also add a column with the state legislative
+                           district of each zip code, from --sld-file.
+  --sld-file=<path>        A crosswalk from zip codes to state legislative
+                           districts, such as state house districts, laid
+                           out like the one for --cd-file.
+  --coordinates            Read a latitude and a longitude from our two input
+                           columns, and find the zip code of each point using
+                           the ZCTA internal points in --gazetteer. Points
+                           outside every ZCTA get an empty chunk. Unless
+                           we're given --zcta-boundaries, we treat each ZCTA
+                           as a circle with the same area, so points near the
+                           edge of an irregular ZCTA may count as outside it.
+  --nearest-within=<miles>
+                           With --coordinates, assign points outside every
+                           ZCTA to the nearest ZCTA within <miles>, and add a
+                           column which is true for points assigned this way.
+  --zcta-boundaries=<path>
+                           With --coordinates, use the ZCTA polygons in a
+                           .shp, .gpkg or .geojson file, such as the Census
+                           ZCTA cartographic boundary file, to find the ZCTA
+                           containing each point.
+  --flag-new-zips          Also add a column which is true for zip codes
+                           whose prefix has no census population, which are
+                           usually new since the census, and report how many
+                           rows had them.
+  --add-territory=<path>   Also add a column with the territory of each
+                           geochunk, from a TOML file mapping territory names
+                           to lists of chunk IDs.
+  --vintage-from-column=<col>
+                           Choose a population vintage for each row, using
+                           the year of the date in <col>. We use zip2010, plus
+                           zip2000 and zip-est* if their files are given,
+                           picking the latest vintage no later than each date,
+                           or <type> for rows without a date. Also add a
+                           column naming the vintage used.
+  --chunk-as-int           Output a dense integer index for each geochunk,
+                           instead of its ID.
+  --chunk-index-file=<path>
+                           Write a CSV file mapping each integer index to its
+                           geochunk ID.
+  --no-header              The input has no header row, so columns are
+                           specified by number, starting at 1.
+  --synthesize-header      With --no-header, write a header row anyway, naming
+                           input columns column_1, column_2, etc.
+  --in-place=<path>        Read <path> instead of standard input, and replace
+                           it with our output once we've finished. <path> may
+                           not be compressed.
+  --sort-by-chunk          Group output rows by the geochunk of the first
+                           <input-column>, using temporary files if there are
+                           too many rows to sort in memory.
+  --output=<path>          Write to <path> instead of standard output.
+  --archive=<path>         Read the .tar, .tar.gz, .tgz or .zip archive at
+                           <path>, add geochunk columns to each CSV file
+                           inside it, and write them to --output, which may
+                           be an archive of the same kinds or a directory.
+  --max-rows-per-file=<n>  With --output, split each output file into shards
+                           of at most <n> rows, named like people-00000.csv.
+  --max-bytes-per-file=<n>
+                           With --output, split each output file into shards
+                           of at most <n> bytes.
+  --rejects-file=<path>    Write rows skipped by --on-ragged=skip to <path>.
+  --format=<fmt>           The output format: csv, proto or orc
+                           [default: csv]. proto writes length-delimited
+                           protobuf messages; see proto/geochunk.proto. orc
+                           writes an ORC file, which requires the orc
+                           feature.
+  --orc-compression=<codec>
+                           With --format=orc, how to compress our output:
+                           none, zlib, snappy, lz4 or zstd [default: zlib].
+",
+                                common_options!(),
+                                chunk_column_options!(),
+                                chunk_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_type: Option<ChunkType>,
+    arg_population: Option<u64>,
+    /// Options for `arg_type`, which can only be given using a spec from our
+    /// config file or environment.
+    #[serde(skip)]
+    spec_options: SpecOptions,
+    arg_input_column: Vec<String>,
+    arg_input_file: Option<String>,
+    flag_replace: bool,
+    flag_insert_after: Option<String>,
+    flag_prepend: bool,
+    flag_add_prefix: bool,
+    flag_add_chunk_population: bool,
+    flag_add_assignment_method: bool,
+    flag_add_density: bool,
+    flag_add_urbanicity: bool,
+    flag_add_timezone: bool,
+    flag_add_dma: bool,
+    flag_add_cd: bool,
+    flag_cd_file: Option<String>,
+    flag_add_sld: bool,
+    flag_sld_file: Option<String>,
+    flag_coordinates: bool,
+    flag_nearest_within: Option<f64>,
+    flag_zcta_boundaries: Option<String>,
+    flag_flag_new_zips: bool,
+    flag_add_territory: Option<String>,
+    flag_vintage_from_column: Option<String>,
+    flag_chunk_as_int: bool,
+    flag_chunk_index_file: Option<String>,
+    flag_no_header: bool,
+    flag_synthesize_header: bool,
+    flag_in_place: Option<String>,
+    flag_sort_by_chunk: bool,
+    flag_output: Option<String>,
+    flag_archive: Option<String>,
+    flag_max_rows_per_file: Option<u64>,
+    flag_max_bytes_per_file: Option<u64>,
+    flag_rejects_file: Option<String>,
+    flag_format: String,
+    #[cfg_attr(not(feature = "orc"), allow(dead_code))]
+    flag_orc_compression: String,
+}
+
+impl Args {
+    /// Get the chunk spec from our separate `<type>` and `<population>`
+    /// arguments.
+    fn spec(&self) -> Result<ChunkSpec> {
+        match (self.arg_type.clone(), self.arg_population) {
+            (Some(chunk_type), Some(population)) => {
+                Ok(ChunkSpec {
+                       chunk_type,
+                       population,
+                       options: self.spec_options.clone(),
+                   })
+            }
+            _ => Err("no <type> and <population> given on the command line, in \
+                      the config file or in GEOCHUNK_SPEC"
+                             .into()),
+        }
+    }
+
+    /// Use `spec` unless we were given a `<type>` and `<population>`.
+    fn default_spec(&mut self, spec: ChunkSpec) {
+        if self.arg_type.is_none() {
+            self.arg_type = Some(spec.chunk_type);
+            self.arg_population = Some(spec.population);
+            self.spec_options = spec.options;
+        }
+    }
+
+    /// Fill in our arguments from `GEOCHUNK_SPEC` and
+    /// `GEOCHUNK_INPUT_COLUMNS`, unless they were given on the command line.
+    /// Input columns are separated by commas.
+    fn apply_env(&mut self) -> Result<()> {
+        if let Ok(spec) = env::var("GEOCHUNK_SPEC") {
+            let spec = spec.parse()
+                .map_err(|e| format!("could not parse GEOCHUNK_SPEC: {}", e))?;
+            self.default_spec(spec);
+        }
+        if let Ok(columns) = env::var("GEOCHUNK_INPUT_COLUMNS") {
+            if self.arg_input_column.is_empty() {
+                self.arg_input_column = columns
+                    .split(',')
+                    .map(|c| c.trim().to_owned())
+                    .collect();
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in our arguments from the `spec` and `input-columns` keys of
+    /// `config`, unless they were given on the command line or in the
+    /// environment.
+    fn apply_config(&mut self, config: Config) {
+        if let Some(spec) = config.spec {
+            self.default_spec(spec);
+        }
+        if let Some(columns) = config.input_columns {
+            if self.arg_input_column.is_empty() {
+                self.arg_input_column = columns;
+            }
+        }
+    }
+
+    /// Where should we put our geochunk columns?
+    fn chunk_position(&self) -> Result<ChunkPosition> {
+        match (self.flag_prepend, &self.flag_insert_after) {
+            (false, None) => Ok(ChunkPosition::Append),
+            (true, None) => Ok(ChunkPosition::Prepend),
+            (false, Some(col)) => Ok(ChunkPosition::InsertAfter(col.clone())),
+            (true, Some(_)) => Err("cannot use both --prepend and --insert-after".into()),
+        }
+    }
+
+    /// How large our output files may be.
+    fn shard_limits(&self) -> ShardLimits {
+        ShardLimits {
+            max_rows: self.flag_max_rows_per_file,
+            max_bytes: self.flag_max_bytes_per_file,
+        }
+    }
+}
+
+/// Like `transform::transform_csv_to_orc`, compressing our output as
+/// requested by `args`.
+#[cfg(feature = "orc")]
+fn transform_csv_to_orc(args: &Args,
+                        classifier: &Classifier,
+                        columns: &[&str],
+                        options: &TransformOptions,
+                        input: &mut dyn Read,
+                        output: &mut dyn Write,
+                        rejects: Option<&mut dyn Write>)
+                        -> Result<TransformStats> {
+    let compression = args.flag_orc_compression.parse()?;
+    transform::transform_csv_to_orc(classifier,
+                                    columns,
+                                    options,
+                                    input,
+                                    output,
+                                    compression,
+                                    rejects)
+}
+
+/// We were built without ORC support.
+#[cfg(not(feature = "orc"))]
+fn transform_csv_to_orc(_: &Args,
+                        _: &Classifier,
+                        _: &[&str],
+                        _: &TransformOptions,
+                        _: &mut dyn Read,
+                        _: &mut dyn Write,
+                        _: Option<&mut dyn Write>)
+                        -> Result<TransformStats> {
+    Err("--format=orc requires the orc feature".into())
+}
+
+/// Generate a table of chunks for each population vintage we have data for,
+/// chosen per row using the dates in `column`, with `spec` for rows without
+/// a date.
+fn build_vintages(chunk: &ChunkOptions, spec: ChunkSpec, column: &str) -> Result<Vintages> {
+    let mut chunk_types = vec![(2010, "zip2010".to_owned())];
+    if chunk.flag_census2000_file.is_some() {
+        chunk_types.push((2000, "zip2000".to_owned()));
+    }
+    if let Some(ref path) = chunk.flag_estimates_file {
+        let years = census::estimate_years(&mut super::open_data_file(path)?)
+            .chain_err(|| format!("could not read {}", path))?;
+        chunk_types.extend(years
+                               .into_iter()
+                               .filter(|y| scheme::ESTIMATE_YEARS.contains(y))
+                               .map(|y| (y, format!("zip-est{}", y))));
+    }
+    if !chunk_types.iter().any(|(_, t)| t == spec.chunk_type.name()) {
+        return Err(format!("--vintage-from-column has no {} vintage",
+                           spec.chunk_type.name())
+                           .into());
+    }
+    let mut vintages = vec![];
+    for (year, chunk_type) in chunk_types {
+        let vintage_spec = ChunkSpec {
+            chunk_type: chunk_type.parse()?,
+            population: spec.population,
+            options: spec.options.clone(),
+        };
+        vintages.push((year, chunk_type, chunk.build_classifier(vintage_spec)?));
+    }
+    Vintages::new(column, vintages, spec.chunk_type.name())
+}
+
+/// Load a territories file mapping names to chunk IDs in `classifier`.
+fn load_territories(classifier: &Classifier, path: &str) -> Result<Territories> {
+    let text = std::fs::read_to_string(path)
+        .chain_err(|| format!("could not read {}", path))?;
+    let map = toml::from_str(&text).chain_err(|| format!("could not parse {}", path))?;
+    Territories::new(classifier, map).chain_err(|| format!("invalid territories in {}", path))
+}
+
+/// Load a district crosswalk for `--cd-file` or `--sld-file`.
+fn load_districts(path: &str) -> Result<DistrictTable> {
+    let mut file = super::open_data_file(path)?;
+    DistrictTable::load(&mut file).chain_err(|| format!("could not read {}", path))
+}
+
+/// Run `geochunk csv`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, config) = super::parse_command_line(USAGE, argv)?;
+    let mut args: Args = super::deserialize(&map);
+    let chunk_column: ChunkColumnOptions = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+    args.apply_env()?;
+    args.apply_config(config);
+
+    let spec = args.spec()?;
+    super::check_us_only_options(&spec,
+                                 &[("--add-density", args.flag_add_density),
+                                   ("--add-urbanicity", args.flag_add_urbanicity),
+                                   ("--add-timezone", args.flag_add_timezone),
+                                   ("--add-dma", args.flag_add_dma),
+                                   ("--add-cd", args.flag_add_cd),
+                                   ("--add-sld", args.flag_add_sld),
+                                   ("--coordinates", args.flag_coordinates)])?;
+    let classifier = chunk.build_classifier(spec.clone())?;
+    if let Some(ref path) = args.flag_chunk_index_file {
+        let mut file = File::create(path)
+            .chain_err(|| format!("could not create {}", path))?;
+        classifier.export_chunk_index(&mut file)
+            .chain_err(|| format!("could not write {}", path))?;
+    }
+    let mut rejects = match args.flag_rejects_file {
+        Some(ref path) => {
+            Some(File::create(path)
+                     .chain_err(|| format!("could not create {}", path))?)
+        }
+        None => None,
+    };
+    if args.arg_input_column.is_empty() {
+        return Err("no <input-column> given on the command line, in the \
+                    config file or in GEOCHUNK_INPUT_COLUMNS"
+                           .into());
+    }
+    let territories = match args.flag_add_territory {
+        Some(ref path) => Some(load_territories(&classifier, path)?),
+        None => None,
+    };
+    let vintages = match args.flag_vintage_from_column {
+        Some(ref column) => Some(build_vintages(&chunk, spec, column)?),
+        None => None,
+    };
+    let limits = args.shard_limits();
+    if args.flag_output.is_some() && args.flag_in_place.is_some() {
+        return Err("cannot use both --output and --in-place".into());
+    }
+    if let Some(ref path) = args.flag_in_place {
+        // We'd replace a compressed file with uncompressed CSV under the
+        // same name, so refuse before we touch it.
+        let mut start = vec![];
+        super::open_data_file(path)?
+            .take(4)
+            .read_to_end(&mut start)
+            .chain_err(|| format!("could not read {}", path))?;
+        let compression = match csv.flag_compression.parse()? {
+            Compression::Auto => Compression::detect(&start),
+            other => other,
+        };
+        if compression != Compression::None {
+            return Err(format!("cannot use --in-place with compressed file {}", path).into());
+        }
+    }
+    if args.flag_output.is_none() && !limits.is_unlimited() {
+        return Err("--max-rows-per-file and --max-bytes-per-file need --output".into());
+    }
+    let format = args.flag_format.parse()?;
+    match format {
+        OutputFormat::Csv | OutputFormat::Proto | OutputFormat::Orc => {}
+        _ => return Err("csv only supports --format=csv, proto or orc".into()),
+    }
+    if format == OutputFormat::Proto &&
+       (args.flag_output.is_some() || args.flag_in_place.is_some()) {
+        return Err("--format=proto can only write to standard output".into());
+    }
+    if format == OutputFormat::Orc && !limits.is_unlimited() {
+        return Err("--format=orc cannot split its output into shards".into());
+    }
+    if args.flag_add_density && chunk.flag_gazetteer.is_none() {
+        return Err("--add-density requires --gazetteer".into());
+    }
+    if args.flag_add_urbanicity && chunk.flag_gazetteer.is_none() {
+        return Err("--add-urbanicity requires --gazetteer".into());
+    }
+    let dmas = if args.flag_add_dma {
+        let path = chunk.flag_dma_file
+            .as_ref()
+            .ok_or("--add-dma requires --dma-file")?;
+        let mut file = super::open_data_file(path)?;
+        let rounding = chunk.flag_weight_rounding.parse()?;
+        Some(DmaTable::load(&mut file, rounding)
+                 .chain_err(|| format!("could not read {}", path))?)
+    } else {
+        None
+    };
+    let points = if args.flag_coordinates {
+        let path = chunk.flag_gazetteer
+            .as_ref()
+            .ok_or("--coordinates requires --gazetteer")?;
+        let mut file = super::open_data_file(path)?;
+        let points = gazetteer::load_zcta_points(&mut file)
+            .chain_err(|| format!("could not read {}", path))?;
+        let mut index = PointIndex::new(points);
+        if let Some(ref path) = args.flag_zcta_boundaries {
+            index = index.with_boundaries(super::load_boundaries(path)?)
+                .chain_err(|| format!("could not read {}", path))?;
+        }
+        Some(index)
+    } else if args.flag_nearest_within.is_some() {
+        return Err("--nearest-within requires --coordinates".into());
+    } else if args.flag_zcta_boundaries.is_some() {
+        return Err("--zcta-boundaries requires --coordinates".into());
+    } else {
+        None
+    };
+    let congressional_districts = if args.flag_add_cd {
+        let path = args.flag_cd_file
+            .as_ref()
+            .ok_or("--add-cd requires --cd-file")?;
+        Some(load_districts(path)?)
+    } else {
+        None
+    };
+    let legislative_districts = if args.flag_add_sld {
+        let path = args.flag_sld_file
+            .as_ref()
+            .ok_or("--add-sld requires --sld-file")?;
+        Some(load_districts(path)?)
+    } else {
+        None
+    };
+    let columns = args.arg_input_column
+        .iter()
+        .map(|c| &c[..])
+        .collect::<Vec<_>>();
+    // If we're not given `output`, we write to `--output`.
+    let mut process = |input: Box<dyn Read + '_>, output: Option<&mut dyn Write>| {
+        let mut input = csv.open_csv_input(input)?;
+        let options = TransformOptions {
+            replace: args.flag_replace,
+            output_column: chunk_column.flag_output_column.clone(),
+            position: args.chunk_position()?,
+            add_prefix: args.flag_add_prefix,
+            add_chunk_population: args.flag_add_chunk_population,
+            add_assignment_method: args.flag_add_assignment_method,
+            add_density: args.flag_add_density,
+            add_urbanicity: args.flag_add_urbanicity,
+            add_time_zone: args.flag_add_timezone,
+            dmas: dmas.clone(),
+            congressional_districts: congressional_districts.clone(),
+            legislative_districts: legislative_districts.clone(),
+            points: points.clone(),
+            nearest_within: args.flag_nearest_within,
+            flag_new_zips: args.flag_flag_new_zips,
+            flag_fallback_zips: chunk.flag_new_zip_fallback,
+            territories: territories.clone(),
+            chunk_as_int: args.flag_chunk_as_int,
+            no_header: args.flag_no_header,
+            synthesize_header: args.flag_synthesize_header,
+            strict: chunk_column.flag_strict,
+            dialect: csv.dialect(input.fill_buf()?)?,
+            sort_by_chunk: args.flag_sort_by_chunk,
+            vintages: vintages.clone(),
+            ..TransformOptions::default()
+        };
+        let rejects = rejects.as_mut().map(|f| f as &mut dyn Write);
+        match output {
+            Some(output) if format == OutputFormat::Orc => {
+                transform_csv_to_orc(&args,
+                                     &classifier,
+                                     &columns,
+                                     &options,
+                                     &mut input,
+                                     output,
+                                     rejects)
+            }
+            Some(output) if format == OutputFormat::Proto => {
+                transform::transform_csv_to_proto(&classifier,
+                                                  &columns,
+                                                  &options,
+                                                  &mut input,
+                                                  output,
+                                                  rejects)
+            }
+            Some(output) => {
+                transform::transform_csv_with_rejects(&classifier,
+                                                      &columns,
+                                                      &options,
+                                                      &mut input,
+                                                      output,
+                                                      rejects)
+            }
+            None => {
+                let path = args.flag_output.as_ref().expect("checked above");
+                transform::transform_csv_to_shards(&classifier,
+                                                   &columns,
+                                                   &options,
+                                                   &mut input,
+                                                   Path::new(path),
+                                                   limits,
+                                                   rejects)
+                    .map(|(stats, _)| stats)
+            }
+        }
+    };
+    if let Some(ref path) = args.flag_archive {
+        if args.flag_in_place.is_some() || args.arg_input_file.is_some() ||
+           format != OutputFormat::Csv || !limits.is_unlimited() {
+            return Err("--archive cannot be used with <input-file>, --in-place, \
+                        --format or shard limits"
+                               .into());
+        }
+        let output_path = args.flag_output
+            .as_ref()
+            .ok_or("--archive needs --output")?;
+        let mut output = ArchiveWriter::create(Path::new(output_path))?;
+        let mut stats = TransformStats::default();
+        archive::for_each_csv_member(Path::new(path), |name, member| {
+                let output_name = archive::output_name(name);
+                stats += output
+                    .write_member(&output_name, |out| process(Box::new(member), Some(out)))
+                    .chain_err(|| format!("error processing {} in {}", name, path))?;
+                Ok(())
+            })?;
+        output.finish()
+            .chain_err(|| format!("could not write {}", output_path))?;
+        return chunk_column.exit_code_for_stats(stats);
+    }
+    let stats = match args.flag_in_place {
+        Some(ref path) => {
+            in_place::rewrite_in_place(Path::new(path), |input, output| {
+                    process(Box::new(input), Some(output))
+                })
+                .chain_err(|| format!("error processing {}", path))?
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            // ORC files are written in one piece, even with `--output`.
+            let mut orc_file = match args.flag_output {
+                Some(ref path) if format == OutputFormat::Orc => {
+                    Some(File::create(path)
+                             .chain_err(|| format!("could not create {}", path))?)
+                }
+                _ => None,
+            };
+            let output = match (args.flag_output.as_ref(), orc_file.as_mut()) {
+                (_, Some(file)) => Some(file as &mut dyn Write),
+                (Some(_), None) => None,
+                (None, None) => Some(&mut stdout as &mut dyn Write),
+            };
+            match args.arg_input_file {
+                Some(ref path) => {
+                    process(csv.open_input_file(path)?, output)
+                        .chain_err(|| format!("error processing {}", path))?
+                }
+                None => {
+                    let stdin = io::stdin();
+                    process(Box::new(stdin.lock()), output)
+                        .chain_err(|| "error processing standard input")?
+                }
+            }
+        }
+    };
+    chunk_column.exit_code_for_stats(stats)
+}
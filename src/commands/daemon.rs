@@ -0,0 +1,60 @@
+//! `geochunk daemon`: answer zip code lookups on a Unix socket.
+
+#[cfg(unix)]
+use std::path::Path;
+
+#[cfg(unix)]
+use geochunk::daemon;
+use geochunk::errors::*;
+use geochunk::zip2010::Classifier;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Listen on the Unix socket given by --socket, and answer each line sent to it
+with the chunk ID of that zip code, or an empty line if it has none. This
+lets many short-lived processes share one classifier.
+
+Usage:
+  geochunk daemon [options] --socket=<path> <spec>
+  geochunk daemon --help
+
+Options:
+  --socket=<path>          The Unix socket to listen on.
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    flag_socket: String,
+}
+
+/// Answer requests for `classifier` on a Unix socket at `path`.
+#[cfg(unix)]
+fn run_daemon(classifier: &Classifier, path: &str) -> Result<()> {
+    let listener = daemon::bind(Path::new(path))?;
+    eprintln!("{}: listening on {}",
+              classifier.geochunk_column_name(),
+              path);
+    daemon::serve(&listener, classifier)
+}
+
+/// Unix sockets aren't available on this platform.
+#[cfg(not(unix))]
+fn run_daemon(_: &Classifier, _: &str) -> Result<()> {
+    Err("geochunk daemon requires Unix sockets".into())
+}
+
+/// Run `geochunk daemon`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    run_daemon(&classifier, &args.flag_socket)?;
+    Ok(EXIT_SUCCESS)
+}
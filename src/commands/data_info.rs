@@ -0,0 +1,33 @@
+//! `geochunk data-info`: describe our built-in census data.
+
+use geochunk::errors::*;
+use geochunk::zip2010::DataInfo;
+
+use super::EXIT_SUCCESS;
+
+pub const USAGE: &str = "
+Describe the census data built into geochunk. Metadata for zip2010 exports
+includes the same description.
+
+Usage:
+  geochunk data-info [options]
+  geochunk data-info --help
+
+Options:
+  --help                   Show this screen.
+";
+
+/// Run `geochunk data-info`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    super::parse_command_line(USAGE, argv)?;
+
+    let info = DataInfo::census2010();
+    println!("source: {}", info.source);
+    if let Some(vintage) = info.vintage {
+        println!("vintage: {}", vintage);
+    }
+    println!("rows: {}", info.rows);
+    println!("total_population: {}", info.total_population);
+    println!("sha256: {}", info.sha256);
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,59 @@
+//! `geochunk data-validate`: check a zip code population file.
+
+use std::io;
+
+use geochunk::census;
+use geochunk::errors::*;
+
+use super::EXIT_SUCCESS;
+
+pub const USAGE: &str = concat!("
+Check a zip code population file, laid out like our built-in data, for
+duplicate zip codes, invalid zip codes and invalid populations. Prints each
+problem, with its line number.
+
+Usage:
+  geochunk data-validate [options] <population-file>
+  geochunk data-validate --help
+
+Options:
+  --weight-rounding=<mode>
+                           How to round fractional populations: nearest, down
+                           or up [default: nearest].
+",
+                                common_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_population_file: String,
+    flag_weight_rounding: String,
+}
+
+/// Run `geochunk data-validate`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+
+    let path = &args.arg_population_file;
+    let rounding = args.flag_weight_rounding.parse()?;
+    let mut file = super::open_data_file(path)?;
+    let report = census::validate_zip_populations(&mut file, rounding)
+        .chain_err(|| format!("could not read {}", path))?;
+    if !report.problems.is_empty() {
+        let stdout = io::stdout();
+        let mut wtr = csv::Writer::from_writer(stdout.lock());
+        wtr.write_record(["line", "problem"])?;
+        for p in &report.problems {
+            wtr.write_record([&p.line.to_string()[..], &p.problem])?;
+        }
+        wtr.flush()?;
+        let count = report.problems.len();
+        return Err(format!("{} rows of {} have problems", count, path).into());
+    }
+    println!("{}: {} zip codes, with a total population of {}",
+             path,
+             report.zips,
+             report.population);
+    Ok(EXIT_SUCCESS)
+}
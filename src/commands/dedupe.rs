@@ -0,0 +1,58 @@
+//! `geochunk dedupe`: add a geochunk column and drop duplicate rows.
+
+use geochunk::errors::*;
+use geochunk::transform::TransformOptions;
+
+use super::{ChunkColumnOptions, ChunkOptions, ChunkSpec, CsvOptions};
+
+pub const USAGE: &str = concat!("
+Add a geochunk column to a CSV file, like csv, and remove any row with the
+same --key as an earlier row. Rows with an empty --key are always kept.
+
+Usage:
+  geochunk dedupe [options] --key=<col> <spec> <input-column> [<input-file>]
+  geochunk dedupe --help
+
+Options:
+  --key=<col>              The column identifying duplicate rows, such as an
+                           email address.
+  --within-chunk           Only remove rows which are also in the same
+                           geochunk as an earlier row.
+",
+                                common_options!(),
+                                chunk_column_options!(),
+                                chunk_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    arg_input_column: String,
+    arg_input_file: Option<String>,
+    flag_key: String,
+    flag_within_chunk: bool,
+}
+
+/// Run `geochunk dedupe`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk_column: ChunkColumnOptions = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let options = TransformOptions {
+        dedupe_key: Some(args.flag_key),
+        dedupe_within_chunk: args.flag_within_chunk,
+        ..TransformOptions::default()
+    };
+    let stats = super::transform_input(&classifier,
+                                       &args.arg_input_column,
+                                       args.arg_input_file.as_deref(),
+                                       &chunk_column,
+                                       &csv,
+                                       options)?;
+    chunk_column.exit_code_for_stats(stats)
+}
@@ -0,0 +1,73 @@
+//! `geochunk expand`: list the zip codes in a set of chunks.
+
+use std::io;
+use std::io::prelude::*;
+
+use geochunk::chunk_set::ChunkSet;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+List every zip code in the chunks given by --chunks, for building
+suppression lists from chunk-level selections.
+
+Usage:
+  geochunk expand [options] <spec>
+  geochunk expand --help
+
+Options:
+  --chunks=<path>          Read chunk IDs from <path>, one per line, instead
+                           of from standard input.
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    flag_chunks: Option<String>,
+}
+
+/// Run `geochunk expand`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let mut chunk_list = String::new();
+    match args.flag_chunks {
+        Some(ref path) => {
+            super::open_data_file(path)?
+                .read_to_string(&mut chunk_list)
+                .chain_err(|| format!("could not read {}", path))?;
+        }
+        None => {
+            io::stdin()
+                .read_to_string(&mut chunk_list)
+                .chain_err(|| "could not read standard input")?;
+        }
+    }
+    let chunks = chunk_list
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<ChunkSet>();
+    if let Some(unknown) = chunks.iter().find(|c| classifier.chunk_index(c).is_none()) {
+        return Err(format!("no chunk {:?} in {}",
+                           unknown,
+                           classifier.geochunk_column_name())
+                           .into());
+    }
+    let stdout = io::stdout();
+    let mut wtr = csv::Writer::from_writer(stdout.lock());
+    wtr.write_record(["zip", &classifier.geochunk_column_name()])?;
+    for zip in chunks.to_zips(&classifier) {
+        let chunk = classifier.chunk_for(&zip).expect("zip should have a chunk");
+        wtr.write_record([&zip[..], chunk])?;
+    }
+    wtr.flush()?;
+    Ok(EXIT_SUCCESS)
+}
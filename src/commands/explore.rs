@@ -0,0 +1,52 @@
+//! `geochunk explore`: interactively browse the chunks of a spec.
+
+use std::io;
+use std::io::IsTerminal;
+
+use geochunk::explore;
+#[cfg(feature = "tui")]
+use geochunk::explore_tui;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Interactively browse the chunks of <spec>, look up zip codes and prefixes,
+and compare other target populations. In a terminal, this opens a
+full-screen interface; otherwise it reads one command per line (type help
+for a list).
+
+Usage:
+  geochunk explore [options] <spec>
+  geochunk explore --help
+
+Options:
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+}
+
+/// Run `geochunk explore`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let spec = args.arg_spec;
+    let classifier = chunk.build_classifier(spec.clone())?;
+    let mut rebuild = |population| chunk.build_classifier(spec.with_population(population));
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if cfg!(feature = "tui") && stdin.is_terminal() && stdout.is_terminal() {
+        #[cfg(feature = "tui")]
+        explore_tui::explore(classifier, &mut rebuild)?;
+    } else {
+        explore::explore(classifier, &mut rebuild, &mut stdin.lock(), &mut stdout.lock())?;
+    }
+    Ok(EXIT_SUCCESS)
+}
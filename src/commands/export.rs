@@ -0,0 +1,109 @@
+//! `geochunk export`: export the geochunk mapping for use by another
+//! program.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use geochunk::{proto, snowflake};
+use geochunk::errors::*;
+use geochunk::format::{self, OutputFormat};
+use geochunk::zip2010::Metadata;
+
+use super::{ChunkOptions, ChunkSpec, ChunkType, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Export the geochunk mapping for use by another program.
+
+Usage:
+  geochunk export [options] <type> <population>
+  geochunk export --help
+
+Options:
+  --format=<fmt>           The output format: csv, json or yaml
+                           [default: csv]. proto writes length-delimited
+                           protobuf messages; see proto/geochunk.proto.
+  --metadata=<path>        Write JSON metadata describing the exported chunks,
+                           including the algorithm version.
+  --snowflake=<kind>       Print what's needed to assign these chunks inside
+                           Snowflake instead: sql for a lookup table and
+                           function, or lambda for a CloudFormation template
+                           deploying geochunk-lambda as an external function.
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_type: ChunkType,
+    arg_population: u64,
+    flag_format: String,
+    flag_metadata: Option<String>,
+    flag_snowflake: Option<String>,
+}
+
+/// Our mapping from zip codes to chunks, printed by `geochunk export
+/// --format`.
+#[derive(Debug, Serialize)]
+struct ExportReport<'a> {
+    /// Where our chunks came from.
+    metadata: Metadata,
+    /// The chunk for every 5-digit zip code.
+    chunk_for_zip: BTreeMap<String, &'a str>,
+}
+
+/// Run `geochunk export`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let spec = ChunkSpec {
+        chunk_type: args.arg_type,
+        population: args.arg_population,
+        options: Default::default(),
+    };
+    let classifier = chunk.build_classifier(spec.clone())?;
+    let stdout = io::stdout();
+    let deployment = match args.flag_snowflake {
+        Some(ref kind) => Some(kind.parse()?),
+        None => None,
+    };
+    match (deployment, args.flag_format.parse()?) {
+        (Some(snowflake::Deployment::Sql), _) => {
+            snowflake::write_lookup_script(&classifier, &mut stdout.lock())?
+        }
+        (Some(snowflake::Deployment::Lambda), _) => {
+            snowflake::write_lambda_template(&spec.to_string(),
+                                             &classifier,
+                                             &mut stdout.lock())?
+        }
+        (None, OutputFormat::Csv) => classifier.export(&mut stdout.lock())?,
+        (None, OutputFormat::Proto) => proto::write_export(&classifier, &mut stdout.lock())?,
+        (None, format) => {
+            let chunk_for_zip = (0..100000)
+                .map(|zip_int| {
+                    let zip = format!("{:05}", zip_int);
+                    let chunk_id = classifier.chunk_for(&zip)
+                        .expect("all zip codes should have a chunk");
+                    (zip, chunk_id)
+                })
+                .collect();
+            let report = ExportReport {
+                metadata: classifier.metadata(),
+                chunk_for_zip,
+            };
+            format::write_structured(format, &report, &mut stdout.lock())?;
+        }
+    }
+    if let Some(ref path) = args.flag_metadata {
+        let mut file = File::create(path)
+            .chain_err(|| format!("could not create {}", path))?;
+        serde_json::to_writer_pretty(&mut file, &classifier.metadata())
+            .chain_err(|| format!("could not write {}", path))?;
+        writeln!(file)?;
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,48 @@
+//! `geochunk export-lookml`: print a LookML dimension which assigns zip
+//! codes to chunks.
+
+use std::io;
+
+use geochunk::lookml;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Print a LookML dimension which assigns zip codes to the chunks of <spec>, so
+Looker users can group by geochunk.
+
+Usage:
+  geochunk export-lookml [options] <spec>
+  geochunk export-lookml --help
+
+Options:
+  --output-column=<name>   The name of the dimension.
+  --zip-sql=<sql>          The SQL for the zip code to classify
+                           [default: ${zip}].
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    flag_output_column: Option<String>,
+    flag_zip_sql: String,
+}
+
+/// Run `geochunk export-lookml`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let name = args.flag_output_column
+        .clone()
+        .unwrap_or_else(|| classifier.geochunk_column_name());
+    let stdout = io::stdout();
+    lookml::write_dimension(&classifier, &name, &args.flag_zip_sql, &mut stdout.lock())?;
+    Ok(EXIT_SUCCESS)
+}
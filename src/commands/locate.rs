@@ -0,0 +1,130 @@
+//! `geochunk locate`: find the chunk containing each point in a CSV file.
+
+use std::io;
+use std::io::prelude::*;
+
+use geochunk::{gazetteer, number};
+use geochunk::errors::*;
+use geochunk::geography::{GeoClassifier, Geography};
+use geochunk::s2::S2Classifier;
+use geochunk::zip2010::PrefixPopulation;
+
+use super::{CsvOptions, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Add the chunk containing the point in <lat-column> and <lng-column> of each
+row of a CSV file, using a <geography-file> from build-geography, or chunks
+of S2 cells. Also add a column naming the region or cell containing the
+point.
+
+Usage:
+  geochunk locate [options] --s2=<population> <lat-column> <lng-column> [<input-file>]
+  geochunk locate [options] <geography-file> <lat-column> <lng-column> [<input-file>]
+  geochunk locate --help
+
+Options:
+  --s2=<population>        Use chunks of S2 cells with about <population>
+                           people each instead of a geography file, weighted
+                           using the ZCTAs in --gazetteer.
+  --gazetteer=<path>       A Census Gazetteer ZCTA file, for --s2.
+  --output-column=<name>   The name of the chunk column to add.
+",
+                                common_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_geography_file: Option<String>,
+    arg_lat_column: String,
+    arg_lng_column: String,
+    arg_input_file: Option<String>,
+    flag_s2: Option<u64>,
+    flag_gazetteer: Option<String>,
+    flag_output_column: Option<String>,
+}
+
+/// Run `geochunk locate`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+
+    let classifier: Box<dyn GeoClassifier> = match args.flag_s2 {
+        Some(target) => {
+            let path = args.flag_gazetteer
+                .as_ref()
+                .ok_or("--s2 requires --gazetteer")?;
+            let mut file = super::open_data_file(path)?;
+            let population = PrefixPopulation::census2010();
+            let points = gazetteer::load_zcta_points(&mut file)
+                .chain_err(|| format!("could not read {}", path))?
+                .into_iter()
+                .map(|point| (point.lat, point.lng, population.lookup(&point.zip)))
+                .collect::<Vec<_>>();
+            Box::new(S2Classifier::new(&points, target))
+        }
+        None => {
+            let path = args.arg_geography_file
+                .as_ref()
+                .expect("Geography file should have been required by docopt");
+            Box::new(Geography::load(&mut super::open_data_file(path)?)
+                         .chain_err(|| format!("could not read {}", path))?)
+        }
+    };
+    let input: Box<dyn Read> = match args.arg_input_file {
+        Some(ref path) => csv.open_input_file(path)?,
+        None => Box::new(io::stdin()),
+    };
+    let mut input = csv.open_csv_input(input)?;
+    let dialect = csv.dialect(input.fill_buf()?)?;
+    let mut rdr = dialect.reader_builder().from_reader(input);
+    let mut headers = rdr.headers()?.to_owned();
+    let find_column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| Error::no_such_column(name))
+    };
+    let lat_idx = find_column(&args.arg_lat_column)?;
+    let lng_idx = find_column(&args.arg_lng_column)?;
+    let column = args.flag_output_column
+        .clone()
+        .unwrap_or_else(|| format!("geochunk_{}", classifier.name()));
+    headers.push_field(&column);
+    headers.push_field(&format!("{}_{}", column, classifier.area_kind()));
+    let stdout = io::stdout();
+    let mut wtr = dialect.writer_builder().from_writer(stdout.lock());
+    wtr.write_record(&headers)?;
+    let (mut rows, mut outside) = (0, 0);
+    let mut row = csv::StringRecord::new();
+    while rdr.read_record(&mut row)? {
+        let (lat, lng) = (&row[lat_idx], &row[lng_idx]);
+        let found = number::parse_number(lat)
+            .zip(number::parse_number(lng))
+            .and_then(|(lat, lng)| classifier.locate(lat, lng));
+        rows += 1;
+        if found.is_none() && !(lat.is_empty() && lng.is_empty()) {
+            outside += 1;
+        }
+        match found {
+            Some((chunk, area)) => {
+                row.push_field(chunk);
+                row.push_field(&area);
+            }
+            None => {
+                row.push_field("");
+                row.push_field("");
+            }
+        }
+        wtr.write_record(&row)?;
+    }
+    wtr.flush()?;
+    if outside > 0 {
+        eprintln!("{} of {} rows had points outside every {}",
+                  outside,
+                  rows,
+                  classifier.area_kind());
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,1106 @@
+//! The subcommands of `geochunk`. Each command has its own module, with its
+//! own docopt usage and `Args`, and a `run` function which parses the
+//! command line and returns our exit code. Groups of options shared by
+//! several commands, such as the options which control how chunks are
+//! built, are defined here, along with a struct to deserialize each group
+//! into from the same parsed command line.
+
+use docopt::{ArgvMap, Docopt};
+use serde::{Deserialize, Deserializer};
+use serde::de::{DeserializeOwned, Error as DeError};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::result;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use geochunk::{compression, density, encoding, geography, geopackage, scheme, shapefile,
+               transform};
+use geochunk::density::DensityClasses;
+use geochunk::dialect::{self, Dialect};
+use geochunk::encoding::{Encoding, Transcoder};
+use geochunk::errors::*;
+use geochunk::gazetteer::{self, Zcta};
+use geochunk::id_template::IdTemplate;
+use geochunk::mmap::Mmap;
+use geochunk::noise::LaplaceNoise;
+use geochunk::number::Rounding;
+use geochunk::scheme::{DataSource, Scheme, SchemeRegistry};
+use geochunk::shapefile::Shape;
+use geochunk::transform::{TransformOptions, TransformStats};
+use geochunk::uring::RingReader;
+use geochunk::zip2010::{Classifier, Naming, PrefixClasses, SpecOptions};
+use geochunk::zip_patch::ZipPatch;
+
+/// Options accepted by every command which takes options. See
+/// `parse_command_line`.
+macro_rules! common_options {
+    () => {
+        "  --config=<path>          Read default options from a TOML file. By default,
+                           we use geochunk.toml if it exists.
+  --help                   Show this screen.
+"
+    };
+}
+
+/// The options in `ChunkOptions`, for commands which build chunks.
+macro_rules! chunk_options {
+    () => {
+        "
+Chunk options:
+  --soi-file=<path>        An IRS SOI zip code CSV file, for the soi_* types.
+  --census2000-file=<path>
+                           A CSV file of 2000 Census zip code populations,
+                           laid out like our built-in 2010 data, for the
+                           zip2000 type.
+  --estimates-file=<path>  A CSV file of yearly zip code population
+                           estimates, with a column for each year, for the
+                           zip-est* types.
+  --weight-rounding=<mode>
+                           How to round fractional weights in the files
+                           above: nearest, down or up [default: nearest].
+                           Weights may also use thousands separators, like
+                           \"1,234\" or \"1 234\".
+  --gazetteer=<path>       A Census Gazetteer ZCTA file, for the area type,
+                           for --density-breaks, and for any other options
+                           which need the land area or location of each
+                           ZCTA.
+  --density-breaks=<list>  Never put zip codes from different density classes
+                           in the same chunk. Classes are separated by a list
+                           of densities in people per square mile, such as
+                           \"100,1000\", or \"urbanicity\" to keep urban,
+                           suburban and rural zip codes apart.
+  --dma-file=<path>        A CSV file with zip, dma and optional population
+                           columns assigning zip codes to designated market
+                           areas, for the dma type.
+  --mx-cp-file=<path>      A CSV file with the INEGI population of Mexican
+                           postal codes, for the mx_cp type.
+  --construction-log=<path>
+                           Write a JSON log of every decision made while
+                           building chunks, for auditing or for checking that
+                           two environments built identical chunks.
+  --zip-patch=<path>       Add zip codes created since the census from a CSV
+                           file with zip, population and parent columns. See
+                           geochunk patch-info.
+  --chunk-id-salt-file=<path>
+                           Name chunks using a hash of their zip code
+                           prefixes, keyed with a secret salt read from
+                           <path>, so that chunk IDs can be shared without
+                           revealing which zip codes they contain.
+  --id-template=<tmpl>     Name chunks by filling in a template, such as
+                           \"{state}-{prefix}-{index:03}\". Variables are id
+                           (the usual chunk ID), prefix (the zip code prefix
+                           it was split from), index (its integer index),
+                           state (the state with most of its population) and
+                           vintage (its data set, such as zip2010).
+                           {index:03} pads with zeros to 3 digits.
+  --population-noise=<scale>
+                           Add Laplace noise with the specified scale to
+                           every chunk population we output, such as the
+                           column added by csv --add-chunk-population.
+                           Chunks contain the same zip codes as without
+                           noise.
+  --noise-seed=<n>         The secret seed for --population-noise, which
+                           makes the noise repeatable. By default, we pick a
+                           random seed.
+  --new-zip-chunk=<id>     Put zip codes whose prefix has no census population
+                           in a separate chunk named <id>, such as NEW, for
+                           review.
+  --new-zip-fallback       Put zip codes whose prefix has no census population
+                           in the most populous chunk sharing their first
+                           three digits, instead of the chunk their prefix
+                           was packed into. Commands which write rows also
+                           add a column which is true for zip codes assigned
+                           this way, and report how many rows had them.
+"
+    };
+}
+
+/// The options in `ChunkColumnOptions`, for commands which add a geochunk
+/// column to CSV rows.
+macro_rules! chunk_column_options {
+    () => {
+        "
+Chunk column options:
+  --output-column=<name>   The name of the geochunk column to add.
+  --strict                 Fail on the first zip code which can't be assigned
+                           to a geochunk, instead of leaving it blank.
+  --fail-on-invalid-pct=<pct>
+                           Fail if more than <pct> percent of rows have zip
+                           codes which can't be assigned to a geochunk.
+"
+    };
+}
+
+/// The options in `CsvOptions`, for commands which read CSV files.
+macro_rules! csv_options {
+    () => {
+        "
+CSV options:
+  --delimiter=<char>       The field delimiter, such as \";\" or \"tab\". By
+                           default, we guess it from the first line of input.
+  --quote=<char>           The character used to quote fields [default: \"].
+  --escape=<char>          Escape quotes inside quoted fields with <char>,
+                           instead of by doubling them.
+  --comment-char=<char>    Skip input lines starting with <char>.
+  --quote-style=<style>    When to quote output fields: necessary, always,
+                           non-numeric or never [default: necessary].
+  --compression=<kind>     How the input is compressed: auto, none, gzip or
+                           zstd [default: auto].
+  --encoding=<name>        The character encoding of the input: utf-8, latin1
+                           or windows-1252 [default: utf-8].
+  --on-ragged=<policy>     What to do with rows with more or fewer fields than
+                           the header row: error, pad (padding or truncating
+                           them to fit) or skip [default: error].
+  --io-uring               Read input files using Linux's io_uring interface.
+                           Needs a build with the io-uring feature.
+"
+    };
+}
+
+pub mod anonymize;
+pub mod assign_treatment;
+pub mod build_geography;
+pub mod completions;
+pub mod copartition;
+pub mod count;
+pub mod crosswalk;
+pub mod csv;
+pub mod daemon;
+pub mod data_info;
+pub mod data_validate;
+pub mod dedupe;
+pub mod expand;
+pub mod explore;
+pub mod export;
+pub mod export_lookml;
+pub mod locate;
+pub mod patch_info;
+pub mod plan;
+pub mod self_check;
+pub mod serve;
+pub mod skew;
+pub mod specs;
+pub mod table;
+pub mod territories;
+pub mod verify;
+pub mod verify_golden;
+
+/// One of our subcommands.
+pub struct Command {
+    /// The name of the command, such as `csv`.
+    pub name: &'static str,
+    /// The docopt usage of the command, including all of its options.
+    pub usage: &'static str,
+    /// Run the command, given our complete command line, returning our exit
+    /// code.
+    pub run: fn(Vec<String>) -> Result<i32>,
+}
+
+/// Every subcommand, in the order they're listed by `--help`.
+pub const COMMANDS: &[Command] = &[
+    Command { name: "export", usage: export::USAGE, run: export::run },
+    Command { name: "export-lookml", usage: export_lookml::USAGE, run: export_lookml::run },
+    Command { name: "plan", usage: plan::USAGE, run: plan::run },
+    Command { name: "explore", usage: explore::USAGE, run: explore::run },
+    Command { name: "csv", usage: csv::USAGE, run: csv::run },
+    Command { name: "table", usage: table::USAGE, run: table::run },
+    Command { name: "crosswalk", usage: crosswalk::USAGE, run: crosswalk::run },
+    Command { name: "verify-golden", usage: verify_golden::USAGE, run: verify_golden::run },
+    Command { name: "verify", usage: verify::USAGE, run: verify::run },
+    Command { name: "self-check", usage: self_check::USAGE, run: self_check::run },
+    Command { name: "daemon", usage: daemon::USAGE, run: daemon::run },
+    Command { name: "serve", usage: serve::USAGE, run: serve::run },
+    Command { name: "anonymize", usage: anonymize::USAGE, run: anonymize::run },
+    Command { name: "expand", usage: expand::USAGE, run: expand::run },
+    Command { name: "count", usage: count::USAGE, run: count::run },
+    Command { name: "skew", usage: skew::USAGE, run: skew::run },
+    Command { name: "dedupe", usage: dedupe::USAGE, run: dedupe::run },
+    Command { name: "copartition", usage: copartition::USAGE, run: copartition::run },
+    Command { name: "territories", usage: territories::USAGE, run: territories::run },
+    Command {
+        name: "assign-treatment",
+        usage: assign_treatment::USAGE,
+        run: assign_treatment::run,
+    },
+    Command {
+        name: "build-geography",
+        usage: build_geography::USAGE,
+        run: build_geography::run,
+    },
+    Command { name: "locate", usage: locate::USAGE, run: locate::run },
+    Command { name: "data-info", usage: data_info::USAGE, run: data_info::run },
+    Command { name: "data-validate", usage: data_validate::USAGE, run: data_validate::run },
+    Command { name: "patch-info", usage: patch_info::USAGE, run: patch_info::run },
+    Command { name: "specs", usage: specs::USAGE, run: specs::run },
+    Command { name: "completions", usage: completions::USAGE, run: completions::run },
+];
+
+/// Look up the command named `name`, such as `csv`.
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// We succeeded.
+pub const EXIT_SUCCESS: i32 = 0;
+/// Our input or arguments were invalid.
+pub const EXIT_INVALID_INPUT: i32 = 1;
+/// We couldn't read or write a file.
+pub const EXIT_IO_ERROR: i32 = 2;
+/// We succeeded, but some rows had invalid zip codes.
+pub const EXIT_WARNINGS: i32 = 3;
+
+/// The chunk schemes built into `geochunk`.
+pub fn registry() -> &'static SchemeRegistry {
+    static REGISTRY: OnceLock<SchemeRegistry> = OnceLock::new();
+    REGISTRY.get_or_init(SchemeRegistry::builtin)
+}
+
+/// Specify what data set we should use for generating chunks, using the
+/// name of a scheme in our registry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkType(String);
+
+impl ChunkType {
+    /// Every chunk type we support.
+    fn all() -> Vec<ChunkType> {
+        registry()
+            .schemes()
+            .map(|s| ChunkType(s.name()))
+            .collect()
+    }
+
+    /// The name of this chunk type on the command line.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// The scheme we use to build chunks of this type.
+    pub fn scheme(&self) -> &'static dyn Scheme {
+        registry()
+            .find(&self.0)
+            .expect("chunk types should always be registered")
+    }
+}
+
+impl FromStr for ChunkType {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        ChunkType::all().into_iter().find(|t| t.name() == s)
+            .ok_or_else(|| format!("Unknown chunk type \"{}\", try --help", s))
+    }
+}
+
+// Implement the `Deserialize` interface so that `docopt` can automatically
+// parse this argument type from a string value.
+impl<'de> Deserialize<'de> for ChunkType {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+/// A chunk type and a target population, written as `zip2010-250000`,
+/// optionally followed by classifier options, as in
+/// `zip2010-250000[naming=int]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkSpec {
+    pub chunk_type: ChunkType,
+    pub population: u64,
+    pub options: SpecOptions,
+}
+
+impl FromStr for ChunkSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let (chunk_type, population, options) =
+            scheme::parse_spec(s).map_err(|e| e.to_string())?;
+        Ok(ChunkSpec {
+               chunk_type: chunk_type.parse()?,
+               population,
+               options,
+           })
+    }
+}
+
+impl fmt::Display for ChunkSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.chunk_type.name(), self.population)?;
+        if !self.options.is_empty() {
+            write!(f, "[{}]", self.options)?;
+        }
+        Ok(())
+    }
+}
+
+impl ChunkSpec {
+    /// The same spec, with a different target population.
+    pub fn with_population(&self, population: u64) -> ChunkSpec {
+        ChunkSpec {
+            chunk_type: self.chunk_type.clone(),
+            population,
+            options: self.options.clone(),
+        }
+    }
+
+    /// Recover the spec used to build a geochunk column, given its name, such
+    /// as `geochunk_zip2010_250000`. Other options, like density classes, are
+    /// not recovered, so check the name of the rebuilt column.
+    pub fn from_column_name(name: &str) -> result::Result<ChunkSpec, String> {
+        let err = || format!("Cannot parse geochunk column name \"{}\"", name);
+        let rest = name.strip_prefix("geochunk_").ok_or_else(err)?;
+        let mut parts = rest.rsplitn(2, '_');
+        let population = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(err)?;
+        let weight_name = parts.next().ok_or_else(err)?;
+        let chunk_type = weight_name
+            .split("_density_")
+            .next()
+            .expect("split always returns at least one item");
+        Ok(ChunkSpec {
+               chunk_type: chunk_type.parse()?,
+               population,
+               options: SpecOptions::default(),
+           })
+    }
+}
+
+impl<'de> Deserialize<'de> for ChunkSpec {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+/// The config file we use if `--config` isn't specified.
+const DEFAULT_CONFIG_PATH: &str = "geochunk.toml";
+
+/// Default options loaded from a TOML config file. Each key has the same name
+/// as the corresponding command-line option without the leading `--`, plus
+/// `spec` and `input-columns` for the `csv` command.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub spec: Option<ChunkSpec>,
+    pub input_columns: Option<Vec<String>>,
+    soi_file: Option<String>,
+    census2000_file: Option<String>,
+    estimates_file: Option<String>,
+    weight_rounding: Option<String>,
+    gazetteer: Option<String>,
+    density_breaks: Option<String>,
+    zip_patch: Option<String>,
+    replace: Option<bool>,
+    output_column: Option<String>,
+    insert_after: Option<String>,
+    prepend: Option<bool>,
+    add_prefix: Option<bool>,
+    add_chunk_population: Option<bool>,
+    add_assignment_method: Option<bool>,
+    add_territory: Option<String>,
+    chunk_as_int: Option<bool>,
+    no_header: Option<bool>,
+    synthesize_header: Option<bool>,
+    delimiter: Option<String>,
+    quote: Option<String>,
+    escape: Option<String>,
+    comment_char: Option<String>,
+    quote_style: Option<String>,
+    compression: Option<String>,
+    encoding: Option<String>,
+    strict: Option<bool>,
+    fail_on_invalid_pct: Option<f64>,
+    on_ragged: Option<String>,
+    sort_by_chunk: Option<bool>,
+    drop_columns: Option<Vec<String>>,
+    min_rows: Option<u64>,
+}
+
+/// A value in our config file, which we can pass to a command as an option.
+trait ConfigValue {
+    /// Write `flag` with this value as command-line options, adding them to
+    /// `options`.
+    fn add_options(&self, flag: &str, options: &mut Vec<String>);
+}
+
+impl ConfigValue for String {
+    fn add_options(&self, flag: &str, options: &mut Vec<String>) {
+        options.push(format!("{}={}", flag, self));
+    }
+}
+
+impl ConfigValue for bool {
+    fn add_options(&self, flag: &str, options: &mut Vec<String>) {
+        if *self {
+            options.push(flag.to_owned());
+        }
+    }
+}
+
+impl ConfigValue for u64 {
+    fn add_options(&self, flag: &str, options: &mut Vec<String>) {
+        options.push(format!("{}={}", flag, self));
+    }
+}
+
+impl ConfigValue for f64 {
+    fn add_options(&self, flag: &str, options: &mut Vec<String>) {
+        options.push(format!("{}={}", flag, self));
+    }
+}
+
+impl ConfigValue for Vec<String> {
+    fn add_options(&self, flag: &str, options: &mut Vec<String>) {
+        for value in self {
+            value.add_options(flag, options);
+        }
+    }
+}
+
+impl Config {
+    /// Load our config from `path`.
+    fn load(path: &str) -> Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .chain_err(|| format!("could not read {}", path))?;
+        toml::from_str(&text).chain_err(|| format!("could not parse {}", path))
+    }
+
+    /// Our options, written as command-line options, such as
+    /// `--output-column=chunk`.
+    fn options(&self) -> Vec<String> {
+        let mut options = vec![];
+        macro_rules! option_from_config {
+            ($key:ident, $flag:expr) => {
+                if let Some(ref value) = self.$key {
+                    value.add_options($flag, &mut options);
+                }
+            };
+        }
+        option_from_config!(soi_file, "--soi-file");
+        option_from_config!(census2000_file, "--census2000-file");
+        option_from_config!(estimates_file, "--estimates-file");
+        option_from_config!(weight_rounding, "--weight-rounding");
+        option_from_config!(gazetteer, "--gazetteer");
+        option_from_config!(density_breaks, "--density-breaks");
+        option_from_config!(zip_patch, "--zip-patch");
+        option_from_config!(replace, "--replace");
+        option_from_config!(output_column, "--output-column");
+        option_from_config!(insert_after, "--insert-after");
+        option_from_config!(prepend, "--prepend");
+        option_from_config!(add_prefix, "--add-prefix");
+        option_from_config!(add_chunk_population, "--add-chunk-population");
+        option_from_config!(add_assignment_method, "--add-assignment-method");
+        option_from_config!(add_territory, "--add-territory");
+        option_from_config!(chunk_as_int, "--chunk-as-int");
+        option_from_config!(no_header, "--no-header");
+        option_from_config!(synthesize_header, "--synthesize-header");
+        option_from_config!(delimiter, "--delimiter");
+        option_from_config!(quote, "--quote");
+        option_from_config!(escape, "--escape");
+        option_from_config!(comment_char, "--comment-char");
+        option_from_config!(quote_style, "--quote-style");
+        option_from_config!(compression, "--compression");
+        option_from_config!(encoding, "--encoding");
+        option_from_config!(strict, "--strict");
+        option_from_config!(fail_on_invalid_pct, "--fail-on-invalid-pct");
+        option_from_config!(on_ragged, "--on-ragged");
+        option_from_config!(sort_by_chunk, "--sort-by-chunk");
+        option_from_config!(drop_columns, "--drop-column");
+        option_from_config!(min_rows, "--min-rows");
+        options
+    }
+}
+
+/// The prefix of environment variables which set our options, such as
+/// `GEOCHUNK_OUTPUT_COLUMN`.
+const ENV_PREFIX: &str = "GEOCHUNK_";
+
+/// Environment variables which set our arguments instead of an option. See
+/// `csv::Args::apply_env`.
+const ENV_ARGUMENTS: &[&str] = &["GEOCHUNK_SPEC", "GEOCHUNK_INPUT_COLUMNS"];
+
+/// Extract the options described by a docopt `usage` string. Options which
+/// take a value end in `=`, like `--delimiter=`.
+pub fn usage_options(usage: &str) -> Vec<&str> {
+    let mut options = vec![];
+    for line in usage.lines() {
+        if let Some(opt) = line.split_whitespace().next().filter(|w| w.starts_with("--")) {
+            let opt = match opt.find('=') {
+                Some(idx) => &opt[..(idx + 1)],
+                None => opt,
+            };
+            if !options.contains(&opt) {
+                options.push(opt);
+            }
+        }
+    }
+    options
+}
+
+/// Every option taken by any of our commands.
+pub fn all_options() -> Vec<&'static str> {
+    let mut options = vec![];
+    for command in COMMANDS {
+        for opt in usage_options(command.usage) {
+            if !options.contains(&opt) {
+                options.push(opt);
+            }
+        }
+    }
+    options
+}
+
+/// The options set by `GEOCHUNK_*` environment variables, so that
+/// `GEOCHUNK_OUTPUT_COLUMN=chunk` works like `--output-column=chunk`.
+/// Options without a value, like `--strict`, are set using `true` or
+/// `false`. We ignore variables which don't match any option, after a
+/// warning.
+fn env_options() -> Result<Vec<String>> {
+    let vars = env::vars()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect::<BTreeMap<_, _>>();
+    let known = all_options();
+    let mut options = vec![];
+    for (name, value) in vars {
+        if ENV_ARGUMENTS.contains(&&name[..]) {
+            continue;
+        }
+        let flag = format!("--{}",
+                           name[ENV_PREFIX.len()..].to_lowercase().replace('_', "-"));
+        let with_value = format!("{}=", flag);
+        if known.contains(&&with_value[..]) {
+            options.push(format!("{}{}", with_value, value));
+        } else if known.contains(&&flag[..]) && flag != "--help" {
+            match &value[..] {
+                "true" | "1" => options.push(flag),
+                "false" | "0" | "" => {}
+                _ => return Err(format!("{} should be true or false", name).into()),
+            }
+        } else {
+            // Other tools, like our Lambda handler, have `GEOCHUNK_*`
+            // variables of their own, so we only warn about these.
+            eprintln!("Warning: ignoring environment variable {}, which doesn't match \
+                       any option",
+                      name);
+        }
+    }
+    Ok(options)
+}
+
+/// Add `options` to `argv`, right after the command and before any `--`,
+/// unless `argv` already has them or they aren't among `accepted`.
+fn add_default_options(mut argv: Vec<String>,
+                       options: Vec<String>,
+                       accepted: &[&str])
+                       -> Vec<String> {
+    let mut added = vec![];
+    for option in options {
+        let flag = option.split('=').next().expect("split always returns an item");
+        let with_value = format!("{}=", flag);
+        let given = argv[2..]
+            .iter()
+            .any(|a| a == flag || a.starts_with(&with_value));
+        if !given && (accepted.contains(&flag) || accepted.contains(&&with_value[..])) {
+            added.push(option);
+        }
+    }
+    if !added.is_empty() {
+        let rest = argv.split_off(2);
+        argv.extend(added);
+        argv.extend(rest);
+    }
+    argv
+}
+
+/// Parse `argv` using the docopt `usage` of its command, exiting with a
+/// usage message if it doesn't match. Any options which the command takes
+/// but which weren't given are filled in from `GEOCHUNK_*` environment
+/// variables, and then from our config file, which is also returned.
+pub fn parse_command_line(usage: &str, argv: Vec<String>) -> Result<(ArgvMap, Config)> {
+    let parse = |argv: &[String]| {
+        Docopt::new(usage)
+            .and_then(|d| d.argv(argv.iter()).parse())
+            .unwrap_or_else(|e| e.exit())
+    };
+    // `--help` only matches a usage pattern alone, so don't add defaults to
+    // it. Either way, docopt exits.
+    if argv.iter().skip(2).any(|arg| arg == "--help") {
+        parse(&argv);
+        unreachable!("docopt should have exited for --help");
+    }
+
+    let accepted = usage_options(usage);
+    let argv = add_default_options(argv, env_options()?, &accepted);
+    let map = parse(&argv);
+
+    // Load our config file, if we have one.
+    let config_path = match map.get_str("--config") {
+        "" if Path::new(DEFAULT_CONFIG_PATH).exists() => Some(DEFAULT_CONFIG_PATH.to_owned()),
+        "" => None,
+        path => Some(path.to_owned()),
+    };
+    match config_path {
+        Some(path) => {
+            let config = Config::load(&path)?;
+            let argv = add_default_options(argv, config.options(), &accepted);
+            Ok((parse(&argv), config))
+        }
+        None => Ok((map, Config::default())),
+    }
+}
+
+/// Deserialize one of our argument structs from `map`, exiting with a usage
+/// message if we can't.
+pub fn deserialize<T>(map: &ArgvMap) -> T
+    where T: DeserializeOwned + fmt::Debug
+{
+    let args: T = map.clone().deserialize().unwrap_or_else(|e| e.exit());
+    trace!("{:?}", args);
+    args
+}
+
+/// The options controlling how we build chunks. See `chunk_options!`.
+#[derive(Debug, Deserialize)]
+pub struct ChunkOptions {
+    pub flag_soi_file: Option<String>,
+    pub flag_census2000_file: Option<String>,
+    pub flag_estimates_file: Option<String>,
+    pub flag_weight_rounding: String,
+    pub flag_gazetteer: Option<String>,
+    pub flag_density_breaks: Option<String>,
+    pub flag_dma_file: Option<String>,
+    pub flag_mx_cp_file: Option<String>,
+    pub flag_construction_log: Option<String>,
+    pub flag_zip_patch: Option<String>,
+    pub flag_chunk_id_salt_file: Option<String>,
+    pub flag_id_template: Option<String>,
+    pub flag_population_noise: Option<f64>,
+    pub flag_noise_seed: Option<u64>,
+    pub flag_new_zip_chunk: Option<String>,
+    pub flag_new_zip_fallback: bool,
+}
+
+impl ChunkOptions {
+    /// The data files named by our options, whose contents can affect our
+    /// chunks.
+    pub fn data_files(&self) -> Vec<&str> {
+        [&self.flag_soi_file,
+         &self.flag_census2000_file,
+         &self.flag_estimates_file,
+         &self.flag_gazetteer,
+         &self.flag_dma_file,
+         &self.flag_mx_cp_file,
+         &self.flag_zip_patch,
+         &self.flag_chunk_id_salt_file]
+            .iter()
+            .filter_map(|path| path.as_deref())
+            .collect()
+    }
+
+    /// Load the Gazetteer file specified by `--gazetteer`, if any.
+    pub fn load_zctas(&self) -> Result<Option<Vec<Zcta>>> {
+        match self.flag_gazetteer {
+            Some(ref path) => {
+                let mut file = open_data_file(path)?;
+                Ok(Some(gazetteer::load_zctas(&mut file)
+                            .chain_err(|| format!("could not read {}", path))?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Generate our table of chunks for `spec`, loading any data files we
+    /// need.
+    pub fn build_classifier(&self, spec: ChunkSpec) -> Result<Classifier> {
+        let weight_name = spec.chunk_type.name();
+        check_us_only_options(&spec,
+                              &[("--density-breaks", self.flag_density_breaks.is_some())])?;
+        if !spec.chunk_type.scheme().uses_us_zip_codes() {
+            let template = self.flag_id_template.as_ref().map(|t| t.parse::<IdTemplate>());
+            if let Some(template) = template {
+                if template?.uses_state() {
+                    return Err(format!("--id-template variable {{state}} only works with \
+                                        US zip codes, so it cannot be used with the {} \
+                                        type",
+                                       weight_name)
+                                       .into());
+                }
+            }
+        }
+        let zctas = self.load_zctas()?;
+        let sources = ChunkDataSource {
+            options: self,
+            rounding: self.flag_weight_rounding.parse()?,
+        };
+        let prefix_population = spec.chunk_type.scheme().prefix_population(&sources)?;
+        let data_info = spec.chunk_type
+            .scheme()
+            .data_info(&sources, &prefix_population)?;
+        let scheme_classes = spec.chunk_type.scheme().prefix_classes(&sources)?;
+        if spec.options.respect_states {
+            if !spec.chunk_type.scheme().uses_us_zip_codes() || scheme_classes.is_some() {
+                return Err(format!("spec option respect_states cannot be used with the {} \
+                                    type",
+                                   weight_name)
+                                   .into());
+            }
+            if self.flag_density_breaks.is_some() {
+                return Err("spec option respect_states cannot be used with --density-breaks"
+                               .into());
+            }
+        }
+        let classes: Option<Box<dyn PrefixClasses>> = match self.flag_density_breaks {
+            Some(_) if scheme_classes.is_some() => {
+                return Err(format!("--density-breaks cannot be used with the {} type",
+                                   weight_name)
+                                   .into());
+            }
+            Some(ref breaks) => {
+                let zctas = zctas
+                    .as_ref()
+                    .ok_or("--density-breaks requires --gazetteer")?;
+                let breaks = density::parse_breaks(breaks)?;
+                Some(Box::new(DensityClasses::new(zctas, breaks)))
+            }
+            None => {
+                spec.options
+                    .state_classes(&prefix_population)
+                    .map(|s| Box::new(s) as Box<dyn PrefixClasses>)
+                    .or(scheme_classes)
+            }
+        };
+        let (classifier, mut log) =
+            Classifier::from_prefix_population_with_minimum(weight_name,
+                                                            &prefix_population,
+                                                            spec.population,
+                                                            spec.options.min_pop.unwrap_or(0),
+                                                            classes.as_deref());
+        if let Some(ref path) = self.flag_construction_log {
+            log.metadata.census_data = data_info.clone();
+            let mut file = File::create(path)
+                .chain_err(|| format!("could not create {}", path))?;
+            serde_json::to_writer_pretty(&mut file, &log)
+                .chain_err(|| format!("could not write {}", path))?;
+            writeln!(file)?;
+        }
+        let classifier = match data_info {
+            Some(info) => classifier.with_data_info(info),
+            None => classifier,
+        };
+        let classifier = match zctas {
+            Some(ref zctas) => classifier.with_land_areas(zctas),
+            None => classifier,
+        };
+        let classifier = match self.flag_zip_patch {
+            Some(ref path) => {
+                let patch = load_zip_patch(path)?;
+                classifier
+                    .with_zip_patch(&patch)
+                    .chain_err(|| format!("could not apply {}", path))?
+            }
+            None => classifier,
+        };
+        let classifier = match self.flag_id_template {
+            Some(ref template) => {
+                if self.flag_chunk_id_salt_file.is_some() || spec.options.naming.is_some() {
+                    return Err("--id-template cannot be used with --chunk-id-salt-file or \
+                                the naming spec option"
+                                       .into());
+                }
+                classifier.with_naming(&Naming::Template(template.parse()?))?
+            }
+            None => classifier,
+        };
+        let classifier = match self.flag_chunk_id_salt_file {
+            Some(ref path) => {
+                let salt = std::fs::read(path)
+                    .chain_err(|| format!("could not read {}", path))?;
+                // Ignore any trailing newline added by an editor.
+                let salt = salt.strip_suffix(b"\n").unwrap_or(&salt);
+                if salt.is_empty() {
+                    return Err(format!("{} does not contain a salt", path).into());
+                }
+                classifier.with_naming(&Naming::Hashed(salt.to_vec()))?
+            }
+            None => classifier,
+        };
+        let classifier = spec.options.apply(classifier)?;
+        let classifier = match self.flag_new_zip_chunk {
+            Some(ref chunk_id) => classifier.with_new_zip_chunk(chunk_id)?,
+            None => classifier,
+        };
+        let classifier = if self.flag_new_zip_fallback {
+            classifier.with_new_zip_fallback()?
+        } else {
+            classifier
+        };
+        match self.flag_population_noise {
+            Some(scale) => {
+                let noise = LaplaceNoise::new(scale, self.flag_noise_seed)?;
+                Ok(classifier.with_population_noise(&noise))
+            }
+            None => Ok(classifier),
+        }
+    }
+}
+
+/// The data files given by our `ChunkOptions`, which chunk schemes load
+/// their weights from.
+struct ChunkDataSource<'a> {
+    options: &'a ChunkOptions,
+    rounding: Rounding,
+}
+
+impl<'a> DataSource for ChunkDataSource<'a> {
+    fn open(&self, option: &str) -> Result<Option<(String, Box<dyn Read>)>> {
+        let path = match option {
+            "--census2000-file" => &self.options.flag_census2000_file,
+            "--estimates-file" => &self.options.flag_estimates_file,
+            "--soi-file" => &self.options.flag_soi_file,
+            "--gazetteer" => &self.options.flag_gazetteer,
+            "--dma-file" => &self.options.flag_dma_file,
+            "--mx-cp-file" => &self.options.flag_mx_cp_file,
+            _ => return Err(format!("unknown data file option {}", option).into()),
+        };
+        match *path {
+            Some(ref path) => {
+                let file = open_data_file(path)?;
+                Ok(Some((path.clone(), Box::new(file))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+}
+
+/// Make sure none of `options` were given, if they look things up using
+/// US zip codes, such as states or time zones, and `spec` uses some other
+/// kind of postal code.
+pub fn check_us_only_options(spec: &ChunkSpec, options: &[(&str, bool)]) -> Result<()> {
+    if spec.chunk_type.scheme().uses_us_zip_codes() {
+        return Ok(());
+    }
+    match options.iter().find(|&&(_, given)| given) {
+        Some(&(option, _)) => {
+            Err(format!("{} only works with US zip codes, so it cannot be used with the \
+                         {} type",
+                        option,
+                        spec.chunk_type.name())
+                        .into())
+        }
+        None => Ok(()),
+    }
+}
+
+/// The options controlling the geochunk column we add to CSV rows. See
+/// `chunk_column_options!`.
+#[derive(Debug, Deserialize)]
+pub struct ChunkColumnOptions {
+    pub flag_output_column: Option<String>,
+    pub flag_strict: bool,
+    pub flag_fail_on_invalid_pct: Option<f64>,
+}
+
+impl ChunkColumnOptions {
+    /// Decide how to exit, given how many rows had invalid zip codes or were
+    /// skipped.
+    pub fn exit_code_for_stats(&self, stats: TransformStats) -> Result<i32> {
+        if stats.new_zip_rows > 0 {
+            eprintln!("{} of {} rows had zip codes whose prefix has no census population",
+                      stats.new_zip_rows,
+                      stats.rows);
+        }
+        if stats.fallback_rows > 0 {
+            eprintln!("{} of {} rows had new zip codes assigned by --new-zip-fallback",
+                      stats.fallback_rows,
+                      stats.rows);
+        }
+        if stats.nearest_rows > 0 {
+            eprintln!("{} of {} rows had points outside every ZCTA assigned by \
+                       --nearest-within",
+                      stats.nearest_rows,
+                      stats.rows);
+        }
+        match self.flag_fail_on_invalid_pct {
+            Some(max_pct) if stats.invalid_pct() > max_pct => {
+                Err(format!("{:.2}% of rows had invalid zip codes, which is more than \
+                             --fail-on-invalid-pct={}",
+                            stats.invalid_pct(),
+                            max_pct)
+                            .into())
+            }
+            _ => {
+                if stats.invalid_rows > 0 {
+                    eprintln!("Warning: {} of {} rows had invalid zip codes",
+                              stats.invalid_rows,
+                              stats.rows);
+                }
+                if stats.skipped_rows > 0 {
+                    eprintln!("Warning: skipped {} rows with the wrong number of columns",
+                              stats.skipped_rows);
+                }
+                if stats.invalid_rows > 0 || stats.skipped_rows > 0 {
+                    Ok(EXIT_WARNINGS)
+                } else {
+                    Ok(EXIT_SUCCESS)
+                }
+            }
+        }
+    }
+}
+
+/// The options controlling how we read CSV files. See `csv_options!`.
+#[derive(Debug, Deserialize)]
+pub struct CsvOptions {
+    pub flag_delimiter: Option<String>,
+    pub flag_quote: String,
+    pub flag_escape: Option<String>,
+    pub flag_comment_char: Option<String>,
+    pub flag_quote_style: String,
+    pub flag_compression: String,
+    pub flag_encoding: String,
+    pub flag_on_ragged: String,
+    pub flag_io_uring: bool,
+}
+
+impl CsvOptions {
+    /// How is our CSV input formatted? If no delimiter was specified, we
+    /// guess it from `sample`, which should be the start of our input.
+    pub fn dialect(&self, sample: &[u8]) -> Result<Dialect> {
+        let parse_opt = |c: &Option<String>| match *c {
+            Some(ref c) => dialect::parse_char(c).map(Some),
+            None => Ok(None),
+        };
+        let quote = dialect::parse_char(&self.flag_quote)?;
+        let delimiter = match parse_opt(&self.flag_delimiter)? {
+            Some(delimiter) => delimiter,
+            None => dialect::sniff_delimiter(sample, quote).unwrap_or(b','),
+        };
+        Ok(Dialect {
+               delimiter,
+               quote,
+               escape: parse_opt(&self.flag_escape)?,
+               comment: parse_opt(&self.flag_comment_char)?,
+               on_ragged: self.flag_on_ragged.parse()?,
+               quote_style: self.flag_quote_style.parse()?,
+           })
+    }
+
+    /// Prepare to read CSV input, decompressing and converting it to UTF-8
+    /// if necessary.
+    pub fn open_csv_input<'a>(&self,
+                              input: Box<dyn Read + 'a>)
+                              -> Result<Box<dyn BufRead + 'a>> {
+        let mut input = compression::decompress(input, self.flag_compression.parse()?)?;
+        match self.flag_encoding.parse()? {
+            Encoding::Utf8 => {
+                encoding::skip_bom(&mut input)?;
+                Ok(input)
+            }
+            other => Ok(Box::new(io::BufReader::new(Transcoder::new(input, other)))),
+        }
+    }
+
+    /// Open a CSV input file. We map regular files into memory, which saves
+    /// copying huge files through `read` calls, unless we've been asked to
+    /// use `io_uring`. Pipes and other special files are read normally.
+    pub fn open_input_file(&self, path: &str) -> Result<Box<dyn Read>> {
+        let file = open_data_file(path)?;
+        if self.flag_io_uring {
+            let rdr = RingReader::new(file).chain_err(|| "could not set up io_uring")?;
+            return Ok(Box::new(rdr));
+        }
+        match Mmap::open(&file).chain_err(|| format!("could not read {}", path))? {
+            Some(mapped) => Ok(Box::new(io::Cursor::new(mapped))),
+            None => Ok(Box::new(file)),
+        }
+    }
+}
+
+/// Open a data file specified on the command line.
+pub fn open_data_file(path: &str) -> Result<File> {
+    File::open(path).chain_err(|| format!("could not open {}", path))
+}
+
+/// Load the polygons and attributes in a shapefile, GeoPackage or GeoJSON
+/// file.
+pub fn load_boundaries(path: &str) -> Result<Vec<Shape>> {
+    let path = Path::new(path);
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let shapes = match extension.as_ref().map(|e| &e[..]) {
+        Some("shp") => {
+            let dbf_path = path.with_extension("dbf");
+            let dbf = dbf_path.to_string_lossy();
+            shapefile::read_shapefile(&mut open_data_file(&path.to_string_lossy())?,
+                                      &mut open_data_file(&dbf)?)
+        }
+        Some("geojson") | Some("json") => {
+            geography::load_geojson(&mut open_data_file(&path.to_string_lossy())?)
+        }
+        Some("gpkg") => {
+            geopackage::read_geopackage(&mut open_data_file(&path.to_string_lossy())?)
+        }
+        _ => return Err("boundaries should be a .shp, .gpkg or .geojson file".into()),
+    };
+    shapes.chain_err(|| format!("could not read {}", path.display()))
+}
+
+/// Load a patch file listing zip codes created since the census.
+pub fn load_zip_patch(path: &str) -> Result<ZipPatch> {
+    let mut file = open_data_file(path)?;
+    ZipPatch::load(&mut file).chain_err(|| format!("could not read {}", path))
+}
+
+/// Add a geochunk column for `column` to `input_file`, or to standard input,
+/// and write it to standard output. We fill in the output column,
+/// strictness and dialect of `options` from our other arguments.
+pub fn transform_input(classifier: &Classifier,
+                       column: &str,
+                       input_file: Option<&str>,
+                       chunk_column: &ChunkColumnOptions,
+                       csv: &CsvOptions,
+                       options: TransformOptions)
+                       -> Result<TransformStats> {
+    let transform = |input: Box<dyn Read>| -> Result<TransformStats> {
+        let mut input = csv.open_csv_input(input)?;
+        let options = TransformOptions {
+            output_column: chunk_column.flag_output_column.clone(),
+            strict: chunk_column.flag_strict,
+            dialect: csv.dialect(input.fill_buf()?)?,
+            ..options.clone()
+        };
+        let stdout = io::stdout();
+        transform::transform_csv(classifier,
+                                 &[column],
+                                 &options,
+                                 &mut input,
+                                 &mut stdout.lock())
+    };
+    match input_file {
+        Some(path) => {
+            transform(csv.open_input_file(path)?)
+                .chain_err(|| format!("error processing {}", path))
+        }
+        None => transform(Box::new(io::stdin())).chain_err(|| "error processing standard input"),
+    }
+}
@@ -0,0 +1,39 @@
+//! `geochunk patch-info`: describe a `--zip-patch` file.
+
+use geochunk::errors::*;
+
+use super::EXIT_SUCCESS;
+
+pub const USAGE: &str = "
+Describe a --zip-patch file. Each row of the file adds a new zip code, which
+joins the chunk of its parent zip code if it has one, and otherwise the
+chunk of its prefix. Either way, its estimated population is added to that
+chunk.
+
+Usage:
+  geochunk patch-info [options] <patch-file>
+  geochunk patch-info --help
+
+Options:
+  --help                   Show this screen.
+";
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_patch_file: String,
+}
+
+/// Run `geochunk patch-info`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+
+    let info = super::load_zip_patch(&args.arg_patch_file)?.info();
+    println!("zips: {}", info.zips);
+    println!("estimated_zips: {}", info.estimated_zips);
+    println!("parented_zips: {}", info.parented_zips);
+    println!("estimated_population: {}", info.estimated_population);
+    println!("sha256: {}", info.sha256);
+    Ok(EXIT_SUCCESS)
+}
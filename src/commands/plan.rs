@@ -0,0 +1,64 @@
+//! `geochunk plan`: list each chunk with its population and prefixes.
+
+use std::io;
+
+use geochunk::proto;
+use geochunk::errors::*;
+use geochunk::format::{self, OutputFormat};
+use geochunk::zip2010::{ChunkSummary, Metadata};
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+List each chunk of <spec> with its population and the zip code prefixes it
+contains, to review chunks before using them.
+
+Usage:
+  geochunk plan [options] <spec>
+  geochunk plan --help
+
+Options:
+  --format=<fmt>           The output format: csv, json or yaml
+                           [default: csv]. proto writes length-delimited
+                           protobuf messages; see proto/geochunk.proto.
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    flag_format: String,
+}
+
+/// Our chunks, printed by `geochunk plan --format`.
+#[derive(Debug, Serialize)]
+struct PlanReport {
+    /// Where our chunks came from.
+    metadata: Metadata,
+    /// Each of our chunks, in chunk index order.
+    chunks: Vec<ChunkSummary>,
+}
+
+/// Run `geochunk plan`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let stdout = io::stdout();
+    match args.flag_format.parse()? {
+        OutputFormat::Csv => classifier.export_plan(&mut stdout.lock())?,
+        OutputFormat::Proto => proto::write_plan(&classifier, &mut stdout.lock())?,
+        format => {
+            let report = PlanReport {
+                metadata: classifier.metadata(),
+                chunks: classifier.chunk_summaries(),
+            };
+            format::write_structured(format, &report, &mut stdout.lock())?;
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,55 @@
+//! `geochunk self-check`: a smoke test of the chunks of a spec.
+
+use geochunk::self_check;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Check that every zip code is in exactly one chunk of <spec>, that chunk
+populations add up, and that malformed and Unicode input is handled safely,
+and fail if not. This is meant as a smoke test for deployment pipelines.
+Chunks built with --population-noise always fail, because their populations
+don't add up.
+
+Usage:
+  geochunk self-check [options] <spec>
+  geochunk self-check --help
+
+Options:
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+}
+
+/// Run `geochunk self-check`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let report = self_check::self_check(&classifier);
+    if !report.is_ok() {
+        for problem in &report.problems {
+            println!("{}", problem);
+        }
+        let hidden = report.problem_count - report.problems.len() as u64;
+        if hidden > 0 {
+            println!("... and {} more", hidden);
+        }
+        return Err(format!("self-check found {} problems with {}",
+                           report.problem_count,
+                           classifier.geochunk_column_name())
+                           .into());
+    }
+    println!("{}: classified {} inputs without any problems",
+             classifier.geochunk_column_name(),
+             report.inputs);
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,65 @@
+//! `geochunk serve`: add geochunk columns to Arrow Flight record batches.
+
+#[cfg(feature = "flight")]
+use geochunk::flight;
+use geochunk::errors::*;
+use geochunk::zip2010::Classifier;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Listen on the address given by --listen for Arrow Flight DoExchange calls,
+and stream back each record batch sent to us with a geochunk column for its
+<input-column> appended. This requires the flight feature.
+
+Usage:
+  geochunk serve [options] --flight --listen=<addr> <spec> <input-column>
+  geochunk serve --help
+
+Options:
+  --flight                 Speak the Arrow Flight protocol.
+  --listen=<addr>          The address to listen on, such as
+                           \"127.0.0.1:8815\".
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    arg_input_column: String,
+    flag_listen: String,
+}
+
+/// Answer Arrow Flight requests for `classifier` on `addr`, classifying the
+/// zip codes in `column`.
+#[cfg(feature = "flight")]
+fn run_flight_server(classifier: Classifier, column: &str, addr: &str) -> Result<()> {
+    let addr = addr.parse()
+        .map_err(|_| format!("--listen should be an address like 127.0.0.1:8815, \
+                              not {:?}",
+                             addr))?;
+    let listener = flight::bind(addr)?;
+    eprintln!("{}: listening on {}",
+              classifier.geochunk_column_name(),
+              listener.local_addr()?);
+    flight::serve(listener, flight::ChunkService::new(classifier, column))
+}
+
+/// We were built without Arrow Flight support.
+#[cfg(not(feature = "flight"))]
+fn run_flight_server(_: Classifier, _: &str, _: &str) -> Result<()> {
+    Err("geochunk serve --flight requires the flight feature".into())
+}
+
+/// Run `geochunk serve`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    run_flight_server(classifier, &args.arg_input_column, &args.flag_listen)?;
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,73 @@
+//! `geochunk skew`: compare the rows in each chunk to its population.
+
+use std::io;
+
+use geochunk::skew;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, CsvOptions};
+use super::count::{count_input_rows, exit_code_for_counts};
+
+pub const USAGE: &str = concat!("
+Show the chunks of a CSV file with the most and fewest rows compared to their
+population, to see whether the input is distributed like the population.
+
+Usage:
+  geochunk skew [options] <spec> <input-column> [<input-file>...]
+  geochunk skew --help
+
+Options:
+  --top=<n>                How many of the most over- and under-represented
+                           chunks to show [default: 10].
+  --jobs=<n>               How many input files to process at once
+                           [default: 1].
+",
+                                common_options!(),
+                                chunk_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    arg_input_column: String,
+    arg_input_file: Vec<String>,
+    flag_top: usize,
+    flag_jobs: usize,
+}
+
+/// Run `geochunk skew`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let counts = count_input_rows(&classifier,
+                                  &args.arg_input_column,
+                                  &args.arg_input_file,
+                                  &csv,
+                                  args.flag_jobs,
+                                  1)?;
+    let skews = skew::chunk_skew(&classifier, &counts.chunks);
+    let high_count = args.flag_top.min(skews.len());
+    let low_count = args.flag_top.min(skews.len() - high_count);
+    let high = skews[..high_count].iter().map(|s| ("high", s));
+    let low = skews[(skews.len() - low_count)..]
+        .iter()
+        .rev()
+        .map(|s| ("low", s));
+    let stdout = io::stdout();
+    let mut wtr = csv::Writer::from_writer(stdout.lock());
+    wtr.write_record(["skew", "chunk", "rows", "population", "ratio"])?;
+    for (kind, s) in high.chain(low) {
+        wtr.write_record([kind,
+                          &s.chunk,
+                          &s.rows.to_string(),
+                          &s.population.to_string(),
+                          &format!("{:.3}", s.ratio)])?;
+    }
+    wtr.flush()?;
+    Ok(exit_code_for_counts(&counts))
+}
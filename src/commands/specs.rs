@@ -0,0 +1,55 @@
+//! `geochunk specs`: list the available chunk types.
+
+use std::io;
+use std::io::prelude::*;
+
+use geochunk::errors::*;
+
+use super::EXIT_SUCCESS;
+
+pub const USAGE: &str = "
+List the available chunk types as JSON.
+
+Usage:
+  geochunk specs [options]
+  geochunk specs --help
+
+Options:
+  --help                   Show this screen.
+";
+
+/// A description of an available chunk type.
+#[derive(Debug, Serialize)]
+struct SpecInfo {
+    /// The chunk type, such as `zip2010`.
+    #[serde(rename = "type")]
+    chunk_type: String,
+    /// A pattern for specs using this type, such as `zip2010-<population>`.
+    pattern: String,
+    /// What this chunk type weights zip codes by.
+    description: String,
+    /// Options which must be given to use this chunk type.
+    required_options: &'static [&'static str],
+}
+
+/// Run `geochunk specs`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    super::parse_command_line(USAGE, argv)?;
+
+    let specs = super::registry()
+        .schemes()
+        .map(|s| {
+                 SpecInfo {
+                     chunk_type: s.name(),
+                     pattern: format!("{}-<population>", s.name()),
+                     description: s.description(),
+                     required_options: s.required_options(),
+                 }
+             })
+        .collect::<Vec<_>>();
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout, &specs).chain_err(|| "could not write specs")?;
+    writeln!(stdout)?;
+    Ok(EXIT_SUCCESS)
+}
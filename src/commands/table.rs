@@ -0,0 +1,80 @@
+//! `geochunk table`: add a geochunk column to a typed table file.
+
+#[cfg(feature = "tables")]
+use std::path::Path;
+
+#[cfg(feature = "tables")]
+use geochunk::table;
+use geochunk::errors::*;
+use geochunk::zip2010::Classifier;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Read a Parquet, Avro or Arrow IPC file, and write it to <output-file> in the
+same format, with a geochunk column for <input-column> appended. Other
+columns keep their types, and <input-column> may hold strings or integers.
+The format is chosen by file extension: .parquet, .avro, or .arrow or
+.feather for Arrow IPC. This requires the tables feature.
+
+Usage:
+  geochunk table [options] <spec> <input-column> <input-file> <output-file>
+  geochunk table --help
+
+Options:
+  --output-column=<name>   The name of the geochunk column to add.
+  --parquet-compression=<codec>
+                           How to compress Parquet output: none, snappy, gzip
+                           or zstd [default: snappy].
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    #[cfg_attr(not(feature = "tables"), allow(dead_code))]
+    arg_input_column: String,
+    arg_input_file: String,
+    #[cfg_attr(not(feature = "tables"), allow(dead_code))]
+    arg_output_file: String,
+    #[cfg_attr(not(feature = "tables"), allow(dead_code))]
+    flag_output_column: Option<String>,
+    #[cfg_attr(not(feature = "tables"), allow(dead_code))]
+    flag_parquet_compression: String,
+}
+
+/// Like `table::chunk_table`, using the options in `args`.
+#[cfg(feature = "tables")]
+fn chunk_table(args: &Args, classifier: &Classifier) -> Result<u64> {
+    let options = table::TableOptions {
+        chunk_column: args.flag_output_column
+            .clone()
+            .unwrap_or_else(|| classifier.geochunk_column_name()),
+        parquet_compression: args.flag_parquet_compression.parse()?,
+    };
+    table::chunk_table(classifier,
+                       &args.arg_input_column,
+                       Path::new(&args.arg_input_file),
+                       Path::new(&args.arg_output_file),
+                       &options)
+}
+
+/// We were built without support for typed table formats.
+#[cfg(not(feature = "tables"))]
+fn chunk_table(_: &Args, _: &Classifier) -> Result<u64> {
+    Err("geochunk table requires the tables feature".into())
+}
+
+/// Run `geochunk table`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec.clone())?;
+    chunk_table(&args, &classifier)
+        .chain_err(|| format!("error processing {}", args.arg_input_file))?;
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,63 @@
+//! `geochunk territories`: merge chunks into balanced territories.
+
+use std::io;
+
+use geochunk::territory;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Merge the chunks of <spec> into --groups territories with similar
+populations, and print the territory of each chunk.
+
+Usage:
+  geochunk territories [options] --groups=<n> <spec>
+  geochunk territories --help
+
+Options:
+  --groups=<n>             How many territories to make.
+  --contiguous             Make each territory a run of consecutive chunk IDs,
+                           which keeps it roughly together geographically.
+  --toml                   Print a file for csv --add-territory instead of
+                           CSV.
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    flag_groups: usize,
+    flag_contiguous: bool,
+    flag_toml: bool,
+}
+
+/// Run `geochunk territories`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let territories =
+        territory::balance_territories(&classifier, args.flag_groups, args.flag_contiguous)?;
+    if args.flag_toml {
+        let text = toml::to_string(&territories.to_map())
+            .chain_err(|| "could not format territories as TOML")?;
+        print!("{}", text);
+    } else {
+        let stdout = io::stdout();
+        let mut wtr = csv::Writer::from_writer(stdout.lock());
+        wtr.write_record([&classifier.geochunk_column_name()[..], "territory"])?;
+        for chunk in classifier.chunk_ids() {
+            let territory = territories
+                .territory_for_chunk(chunk)
+                .expect("every chunk should be in a territory");
+            wtr.write_record([chunk, territory])?;
+        }
+        wtr.flush()?;
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,74 @@
+//! `geochunk verify`: check the geochunk column of a CSV file.
+
+use std::io;
+use std::io::prelude::*;
+
+use geochunk::golden;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, CsvOptions, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Recompute the geochunk of every row in a CSV file which was already chunked
+using <spec>, and fail if any have changed.
+
+Usage:
+  geochunk verify [options] <spec> <input-column> <input-file>
+  geochunk verify --help
+
+Options:
+  --output-column=<name>   The name of the geochunk column to check.
+",
+                                common_options!(),
+                                chunk_options!(),
+                                csv_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_spec: ChunkSpec,
+    arg_input_column: String,
+    arg_input_file: String,
+    flag_output_column: Option<String>,
+}
+
+/// Run `geochunk verify`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+    let csv: CsvOptions = super::deserialize(&map);
+
+    let classifier = chunk.build_classifier(args.arg_spec)?;
+    let chunk_column = args.flag_output_column
+        .clone()
+        .unwrap_or_else(|| classifier.geochunk_column_name());
+    let path = &args.arg_input_file;
+    let mut input = csv.open_csv_input(csv.open_input_file(path)?)?;
+    let dialect = csv.dialect(input.fill_buf()?)?;
+    let mismatches = golden::verify_chunk_column(&classifier,
+                                                 &args.arg_input_column,
+                                                 &chunk_column,
+                                                 &dialect,
+                                                 &mut input)
+            .chain_err(|| format!("error processing {}", path))?;
+    if !mismatches.is_empty() {
+        let stdout = io::stdout();
+        let mut wtr = csv::Writer::from_writer(stdout.lock());
+        wtr.write_record(["line", "zip", "expected", "actual"])?;
+        for m in &mismatches {
+            let line = m.line.to_string();
+            let m = &m.mismatch;
+            wtr.write_record([&line[..],
+                              &m.zip,
+                              &m.expected,
+                              m.actual.as_ref().map_or("", |a| &a[..])])?;
+        }
+        wtr.flush()?;
+        return Err(format!("{} rows have chunks which don't match {}",
+                           mismatches.len(),
+                           chunk_column)
+                           .into());
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,56 @@
+//! `geochunk verify-golden`: check an earlier export against our current
+//! chunks.
+
+use std::io;
+
+use geochunk::golden;
+use geochunk::errors::*;
+
+use super::{ChunkOptions, ChunkSpec, EXIT_SUCCESS};
+
+pub const USAGE: &str = concat!("
+Fail if any zip code in an earlier export would now be assigned to a
+different chunk.
+
+Usage:
+  geochunk verify-golden [options] <golden-file>
+  geochunk verify-golden --help
+
+Options:
+",
+                                common_options!(),
+                                chunk_options!());
+
+/// Our command-line arguments.
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_golden_file: String,
+}
+
+/// Run `geochunk verify-golden`.
+pub fn run(argv: Vec<String>) -> Result<i32> {
+    let (map, _) = super::parse_command_line(USAGE, argv)?;
+    let args: Args = super::deserialize(&map);
+    let chunk: ChunkOptions = super::deserialize(&map);
+
+    let path = &args.arg_golden_file;
+    let column = golden::golden_column_name(&mut super::open_data_file(path)?)?;
+    let spec = ChunkSpec::from_column_name(&column)?;
+    let classifier = chunk.build_classifier(spec)?;
+    let mismatches = golden::verify_golden(&classifier, &mut super::open_data_file(path)?)?;
+    if !mismatches.is_empty() {
+        let stdout = io::stdout();
+        let mut wtr = csv::Writer::from_writer(stdout.lock());
+        wtr.write_record(["zip", "expected", "actual"])?;
+        for m in &mismatches {
+            wtr.write_record([&m.zip[..],
+                              &m.expected,
+                              m.actual.as_ref().map_or("", |a| &a[..])])?;
+        }
+        wtr.flush()?;
+        return Err(format!("{} zip codes are now assigned to different chunks",
+                           mismatches.len())
+                           .into());
+    }
+    Ok(EXIT_SUCCESS)
+}
@@ -0,0 +1,142 @@
+//! Generating shell completion scripts.
+
+#[cfg(test)]
+use env_logger;
+use std::io::{self, prelude::*};
+use std::str::FromStr;
+
+use errors::*;
+
+/// A shell which we can generate completions for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    /// GNU Bash.
+    Bash,
+    /// The Z shell.
+    Zsh,
+    /// The friendly interactive shell.
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Shell> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("cannot generate completions for shell {:?}", s).into()),
+        }
+    }
+}
+
+/// The words which we want to complete.
+#[derive(Clone, Debug)]
+pub struct CompletionWords<'a> {
+    /// Our subcommands, like `csv`.
+    pub commands: &'a [&'a str],
+    /// Our options, like `--replace`. Options which take a value should end
+    /// in `=`, like `--delimiter=`.
+    pub options: &'a [&'a str],
+    /// Chunk types, which we complete after a subcommand.
+    pub types: &'a [&'a str],
+}
+
+/// Write a completion script for `shell` to `out`.
+pub fn write_completions(shell: Shell,
+                         words: &CompletionWords,
+                         out: &mut dyn Write)
+                         -> io::Result<()> {
+    let commands = words.commands.join(" ");
+    let options = words.options.join(" ");
+    let types = words.types.join(" ");
+    match shell {
+        Shell::Bash => {
+            write!(out,
+                   r#"_geochunk() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=( $(compgen -W "{options}" -- "$cur") )
+        [[ "$COMPREPLY" == *= ]] && compopt -o nospace
+    elif [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=( $(compgen -W "{commands}" -- "$cur") )
+    elif [ "$COMP_CWORD" -eq 2 ]; then
+        COMPREPLY=( $(compgen -W "{types}" -f -- "$cur") )
+    else
+        COMPREPLY=( $(compgen -f -- "$cur") )
+    fi
+}}
+complete -F _geochunk geochunk
+"#,
+                   options = options,
+                   commands = commands,
+                   types = types)
+        }
+        Shell::Zsh => {
+            write!(out,
+                   r#"#compdef geochunk
+_geochunk() {{
+    local -a commands options types
+    commands=({commands})
+    options=({options})
+    types=({types})
+    if [[ $PREFIX == -* ]]; then
+        compadd -S '' -a options
+    elif (( CURRENT == 2 )); then
+        compadd -a commands
+    elif (( CURRENT == 3 )); then
+        compadd -a types
+        _files
+    else
+        _files
+    fi
+}}
+_geochunk "$@"
+"#,
+                   commands = commands,
+                   options = options,
+                   types = types)
+        }
+        Shell::Fish => {
+            writeln!(out,
+                     "complete -c geochunk -n __fish_use_subcommand -f -a '{}'",
+                     commands)?;
+            writeln!(out,
+                     "complete -c geochunk -n 'not __fish_use_subcommand' -a '{}'",
+                     types)?;
+            for option in words.options {
+                let name = option.trim_start_matches("--");
+                if let Some(name) = name.strip_suffix('=') {
+                    writeln!(out, "complete -c geochunk -l {} -r", name)?;
+                } else {
+                    writeln!(out, "complete -c geochunk -l {}", name)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn generates_completions_for_each_shell() {
+    let _ = env_logger::init();
+    let words = CompletionWords {
+        commands: &["export", "csv"],
+        options: &["--replace", "--delimiter="],
+        types: &["zip2010"],
+    };
+    let generate = |shell| {
+        let mut out = vec![];
+        write_completions(shell, &words, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let bash = generate(Shell::Bash);
+    assert!(bash.contains("compgen -W \"export csv\""));
+    assert!(bash.contains("complete -F _geochunk geochunk"));
+    assert!(generate(Shell::Zsh).contains("options=(--replace --delimiter=)"));
+    let fish = generate(Shell::Fish);
+    assert!(fish.contains("complete -c geochunk -l replace\n"));
+    assert!(fish.contains("complete -c geochunk -l delimiter -r\n"));
+    assert!("tcsh".parse::<Shell>().is_err());
+}
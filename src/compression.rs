@@ -0,0 +1,124 @@
+//! Reading compressed input files.
+
+#[cfg(test)]
+use env_logger;
+use flate2::read::MultiGzDecoder;
+use ruzstd::StreamingDecoder;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::str::FromStr;
+
+use errors::*;
+
+/// The first bytes of a gzip file.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// The first bytes of a zstd frame.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// How is our input compressed?
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Look at the first few bytes of our input and guess.
+    #[default]
+    Auto,
+    /// Not compressed.
+    None,
+    /// Compressed with gzip.
+    Gzip,
+    /// Compressed with zstd.
+    Zstd,
+}
+
+impl Compression {
+    /// Guess the compression of a file from its first few bytes. If we don't
+    /// recognize the data, we assume it's uncompressed.
+    pub fn detect(start: &[u8]) -> Compression {
+        if start.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if start.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Compression> {
+        match s {
+            "auto" => Ok(Compression::Auto),
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression {:?}", s).into()),
+        }
+    }
+}
+
+/// Wrap `input` in a decompressor, if necessary. The returned reader is
+/// buffered, so callers can use `fill_buf` to peek at the decompressed data.
+pub fn decompress<'a>(input: Box<dyn Read + 'a>,
+                      compression: Compression)
+                      -> Result<Box<dyn BufRead + 'a>> {
+    let mut input = BufReader::new(input);
+    let compression = match compression {
+        Compression::Auto => Compression::detect(input.fill_buf()?),
+        other => other,
+    };
+    debug!("reading input with compression {:?}", compression);
+    Ok(match compression {
+           Compression::Auto => unreachable!("compression already detected"),
+           Compression::None => Box::new(input),
+           Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(input))),
+           Compression::Zstd => {
+               let decoder = StreamingDecoder::new(input)
+                   .map_err(|e| format!("could not read zstd data: {}", e))?;
+               Box::new(BufReader::new(decoder))
+           }
+       })
+}
+
+#[cfg(test)]
+fn decompress_to_string(data: &[u8], compression: Compression) -> String {
+    let mut out = String::new();
+    decompress(Box::new(data), compression)
+        .unwrap()
+        .read_to_string(&mut out)
+        .unwrap();
+    out
+}
+
+#[test]
+fn detects_and_decompresses_gzip() {
+    use flate2;
+    use flate2::write::GzEncoder;
+
+    let _ = env_logger::init();
+    let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(b"zip\n90210\n").unwrap();
+    let data = encoder.finish().unwrap();
+    assert_eq!(Compression::detect(&data), Compression::Gzip);
+    assert_eq!(decompress_to_string(&data, Compression::Auto), "zip\n90210\n");
+}
+
+#[test]
+fn detects_and_decompresses_zstd() {
+    let _ = env_logger::init();
+    // A zstd frame containing a single uncompressed block.
+    let mut data = ZSTD_MAGIC.to_owned();
+    data.extend_from_slice(&[0x20, 10, 0x51, 0x00, 0x00]);
+    data.extend_from_slice(b"zip\n90210\n");
+    assert_eq!(Compression::detect(&data), Compression::Zstd);
+    assert_eq!(decompress_to_string(&data, Compression::Auto), "zip\n90210\n");
+}
+
+#[test]
+fn passes_through_plain_text() {
+    let _ = env_logger::init();
+    assert_eq!(Compression::detect(b"zip\n"), Compression::None);
+    assert_eq!(decompress_to_string(b"zip\n", Compression::Auto), "zip\n");
+    assert_eq!(decompress_to_string(b"", Compression::Auto), "");
+}
@@ -0,0 +1,76 @@
+//! Correspondence tables between two different sets of geochunks.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+
+use errors::*;
+use zip2010::{Classifier, PrefixPopulation};
+
+/// Write a population-weighted crosswalk from the chunks of `from` to the
+/// chunks of `to` as CSV. There will be one row for each pair of chunks that
+/// share any zip codes, containing the 2010 census population of those zip
+/// codes, and the fraction of the `from` chunk's population that it
+/// represents. This can be used to approximately restate aggregates keyed by
+/// `from` chunks in terms of `to` chunks.
+pub fn write_crosswalk(from: &Classifier,
+                       to: &Classifier,
+                       out: &mut dyn Write)
+                       -> Result<()> {
+    let population = PrefixPopulation::census2010();
+
+    // Total up the population shared by each pair of chunks.
+    let mut from_totals = BTreeMap::<&str, u64>::new();
+    let mut pair_totals = BTreeMap::<(&str, &str), u64>::new();
+    for zip_int in 0..100000 {
+        let zip = format!("{:05}", zip_int);
+        let from_chunk = from.chunk_for(&zip)
+            .expect("all zip codes should have a chunk");
+        let to_chunk = to.chunk_for(&zip)
+            .expect("all zip codes should have a chunk");
+        let pop = population.lookup(&zip);
+        *from_totals.entry(from_chunk).or_insert(0) += pop;
+        *pair_totals.entry((from_chunk, to_chunk)).or_insert(0) += pop;
+    }
+
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(out);
+    wtr.serialize((from.geochunk_column_name(),
+                   to.geochunk_column_name(),
+                   "population",
+                   "fraction"))?;
+    for (&(from_chunk, to_chunk), &pop) in &pair_totals {
+        let from_total = from_totals[from_chunk];
+        let fraction = if from_total == 0 {
+            0.0
+        } else {
+            pop as f64 / from_total as f64
+        };
+        // We format fractions ourselves, because newer releases of `csv`
+        // write whole numbers like `1` as `1.0`.
+        wtr.serialize((from_chunk, to_chunk, pop, fraction.to_string()))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn crosswalk_fractions_add_up_for_each_chunk() {
+    let _ = env_logger::init();
+    let from = Classifier::new(250000);
+    let to = Classifier::new(1000000);
+    let mut out = vec![];
+    write_crosswalk(&from, &to, &mut out).unwrap();
+
+    let mut rdr = csv::Reader::from_reader(&out[..]);
+    let mut sums = BTreeMap::<String, f64>::new();
+    for row in rdr.deserialize() {
+        let (from_chunk, _, _, fraction): (String, String, u64, f64) =
+            row.unwrap();
+        *sums.entry(from_chunk).or_insert(0.0) += fraction;
+    }
+    assert!((sums["010_0"] - 1.0).abs() < 1e-9);
+    assert!((sums["077_1"] - 1.0).abs() < 1e-9);
+}
@@ -0,0 +1,133 @@
+//! A long-running server which classifies zip codes sent over a Unix
+//! socket, so that many short-lived local processes can share one warm
+//! classifier instead of each paying to build their own.
+//!
+//! Clients send one zip code per line, and we answer each line with a line
+//! containing its chunk ID, or an empty line if it can't be classified.
+//! Clients may send as many lines as they like before reading our answers:
+//!
+//! ```text
+//! $ printf '90210\nN/A\n02120\n' | nc -U /tmp/geochunk.sock
+//! 902_0
+//!
+//! 0212_0
+//! ```
+
+#[cfg(test)]
+use env_logger;
+use std::fs;
+use std::io::{self, BufReader};
+use std::io::prelude::*;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+
+use errors::*;
+use zip2010::Classifier;
+
+/// Listen on a Unix socket at `path`. If a socket file is already there,
+/// but nobody is listening on it, we replace it, since it was probably left
+/// behind by a daemon which didn't exit cleanly. We never replace anything
+/// which isn't a socket.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => {
+            if !metadata.file_type().is_socket() {
+                return Err(format!("{} already exists, and isn't a socket", path.display())
+                               .into());
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(format!("another daemon is already listening on {}",
+                                   path.display())
+                                   .into());
+            }
+            let err = || format!("could not remove stale socket {}", path.display());
+            fs::remove_file(path).chain_err(err)?;
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err).chain_err(|| format!("could not check {}", path.display()));
+        }
+    }
+    UnixListener::bind(path)
+        .chain_err(|| format!("could not listen on {}", path.display()))
+}
+
+/// Answer requests on `listener` forever, handling each client on its own
+/// thread. Errors talking to one client are logged, and don't affect any
+/// other clients.
+pub fn serve(listener: &UnixListener, classifier: &Classifier) -> Result<()> {
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("could not accept connection: {}", err);
+                    continue;
+                }
+            };
+            scope.spawn(move || {
+                if let Err(err) = handle_client(classifier, &stream, &stream) {
+                    warn!("error talking to client: {}", err);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Answer each zip code in `input` by writing its chunk ID to `output`. We
+/// flush our answers whenever we've run out of buffered requests, so that
+/// clients which wait for each answer get it right away.
+pub fn handle_client<R: Read, W: Write>(classifier: &Classifier,
+                                        input: R,
+                                        output: W)
+                                        -> Result<()> {
+    trace_span!("daemon_client");
+    let mut input = BufReader::new(input);
+    let mut output = io::BufWriter::new(output);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        let zip = line.trim_end_matches(['\r', '\n']);
+        writeln!(output, "{}", classifier.chunk_for(zip).unwrap_or(""))?;
+        if input.buffer().is_empty() {
+            output.flush()?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[test]
+fn answers_requests() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut output = vec![];
+    handle_client(&classifier, &b"90210\r\nN/A\n\n02120"[..], &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "902_0\n\n\n0212_0\n");
+}
+
+#[test]
+fn only_replaces_stale_sockets() {
+    let _ = env_logger::init();
+    let dir = ::std::env::temp_dir().join(format!("geochunk-daemon-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let file = dir.join("notasock");
+    fs::write(&file, "keep me").unwrap();
+    assert!(bind(&file).is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "keep me");
+
+    let sock = dir.join("geochunk.sock");
+    let listener = bind(&sock).unwrap();
+    assert!(bind(&sock).is_err());
+    drop(listener);
+    // The socket file is still there, but nobody is listening on it.
+    bind(&sock).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,171 @@
+//! Support for keeping zip codes with very different population densities
+//! out of the same chunk, and for classifying zip codes as urban, suburban
+//! or rural.
+
+#[cfg(test)]
+use env_logger;
+use std::result;
+
+use errors::*;
+use gazetteer::SQUARE_METERS_PER_SQUARE_MILE;
+#[cfg(feature = "data-zip2010")]
+use gazetteer::Zcta;
+use zip2010::{PrefixClasses, PrefixPopulation};
+
+/// Divides zip code prefixes into classes by population density. A prefix
+/// with a density below `breaks[0]` is in class 0, one with a density below
+/// `breaks[1]` is in class 1, and so on.
+#[derive(Clone, Debug)]
+pub struct DensityClasses {
+    /// The census population of each prefix.
+    population: PrefixPopulation,
+    /// The land area of each prefix, in square meters.
+    land_area: PrefixPopulation,
+    /// Class boundaries, in people per square mile, in ascending order.
+    breaks: Vec<f64>,
+}
+
+impl DensityClasses {
+    /// Create density classes using 2010 census population, the specified
+    /// land areas, and class boundaries in people per square mile.
+    #[cfg(feature = "data-zip2010")]
+    pub fn new(zctas: &[Zcta], breaks: Vec<f64>) -> DensityClasses {
+        let land_areas = zctas.iter().map(|z| (z.zip.clone(), z.land_area));
+        DensityClasses {
+            population: PrefixPopulation::census2010(),
+            land_area: PrefixPopulation::from_weights(land_areas),
+            breaks,
+        }
+    }
+
+    /// A short name describing these classes, for use in column names.
+    pub fn name(&self) -> String {
+        let breaks = self.breaks
+            .iter()
+            .map(|b| b.to_string())
+            .collect::<Vec<_>>();
+        format!("density_{}", breaks.join("_"))
+    }
+
+    /// Return the population density of a zip code prefix, in people per
+    /// square mile. Prefixes without any land area have a density of zero.
+    pub fn density_for_prefix(&self, prefix: &str) -> f64 {
+        let land_area = self.land_area.lookup(prefix);
+        if land_area == 0 {
+            return 0.0;
+        }
+        let sq_mi = land_area as f64 / SQUARE_METERS_PER_SQUARE_MILE;
+        self.population.lookup(prefix) as f64 / sq_mi
+    }
+
+    /// Return the density class of a zip code prefix.
+    pub fn class_for_prefix(&self, prefix: &str) -> usize {
+        let density = self.density_for_prefix(prefix);
+        self.breaks.iter().take_while(|&&b| b <= density).count()
+    }
+}
+
+impl PrefixClasses for DensityClasses {
+    fn name(&self) -> Option<String> {
+        Some(DensityClasses::name(self))
+    }
+
+    fn class_for_prefix(&self, prefix: &str) -> Option<usize> {
+        Some(DensityClasses::class_for_prefix(self, prefix))
+    }
+}
+
+/// The density breaks between rural, suburban and urban zip codes, in
+/// people per square mile. We don't have the census urbanized area
+/// percentage of each zip code, so we approximate it by density: 500 people
+/// per square mile is roughly the lowest density the census counts as
+/// urban, and above 3,000 most land is built up.
+pub const URBANICITY_BREAKS: [f64; 2] = [500.0, 3000.0];
+
+/// How built up a zip code is, estimated from its population density.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Urbanicity {
+    /// Below 500 people per square mile.
+    Rural,
+    /// From 500 to 3,000 people per square mile.
+    Suburban,
+    /// 3,000 or more people per square mile.
+    Urban,
+}
+
+impl Urbanicity {
+    /// Classify a population density, in people per square mile.
+    pub fn for_density(density: f64) -> Urbanicity {
+        match URBANICITY_BREAKS.iter().take_while(|&&b| b <= density).count() {
+            0 => Urbanicity::Rural,
+            1 => Urbanicity::Suburban,
+            _ => Urbanicity::Urban,
+        }
+    }
+
+    /// The name of this class, for use in output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Urbanicity::Rural => "rural",
+            Urbanicity::Suburban => "suburban",
+            Urbanicity::Urban => "urban",
+        }
+    }
+}
+
+/// Parse a comma-separated list of density class boundaries, like
+/// `"100,1000"`. The special value `urbanicity` uses
+/// `URBANICITY_BREAKS`.
+pub fn parse_breaks(s: &str) -> Result<Vec<f64>> {
+    if s.trim() == "urbanicity" {
+        return Ok(URBANICITY_BREAKS.to_vec());
+    }
+    let breaks = s.split(',')
+        .map(|b| b.trim().parse::<f64>())
+        .collect::<result::Result<Vec<f64>, _>>()
+        .chain_err(|| format!("invalid density breaks {:?}", s))?;
+    if breaks.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(format!("density breaks must be ascending: {:?}", s).into());
+    }
+    Ok(breaks)
+}
+
+#[test]
+fn parses_density_breaks() {
+    let _ = env_logger::init();
+    assert_eq!(parse_breaks("100, 1000.5").unwrap(), vec![100.0, 1000.5]);
+    assert!(parse_breaks("1000,100").is_err());
+    assert!(parse_breaks("dense").is_err());
+    assert_eq!(parse_breaks("urbanicity").unwrap(), URBANICITY_BREAKS.to_vec());
+}
+
+#[test]
+fn classifies_urbanicity() {
+    let _ = env_logger::init();
+    assert_eq!(Urbanicity::for_density(0.0), Urbanicity::Rural);
+    assert_eq!(Urbanicity::for_density(499.9), Urbanicity::Rural);
+    assert_eq!(Urbanicity::for_density(500.0), Urbanicity::Suburban);
+    assert_eq!(Urbanicity::for_density(3000.0).name(), "urban");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn classifies_prefixes_by_density() {
+    let _ = env_logger::init();
+    let sq_mi = SQUARE_METERS_PER_SQUARE_MILE as u64;
+    let zctas = vec![// 01001 has 16,769 people.
+                     Zcta {
+                         zip: "01001".to_owned(),
+                         land_area: 100 * sq_mi,
+                     },
+                     // 10001 has 21,102 people.
+                     Zcta {
+                         zip: "10001".to_owned(),
+                         land_area: sq_mi,
+                     }];
+    let classes = DensityClasses::new(&zctas, vec![100.0, 1000.0]);
+    assert_eq!(classes.name(), "density_100_1000");
+    assert_eq!(classes.class_for_prefix("01001"), 1);
+    assert_eq!(classes.class_for_prefix("10001"), 2);
+    assert_eq!(classes.class_for_prefix("99999"), 0);
+}
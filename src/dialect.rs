@@ -0,0 +1,217 @@
+//! Describing the many variations of CSV that we might need to read.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::str::FromStr;
+
+use errors::*;
+
+/// When should we put quotes around output fields?
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// Only when a field contains a delimiter, quote or line break.
+    #[default]
+    Necessary,
+    /// Always.
+    Always,
+    /// For every field that isn't a number.
+    NonNumeric,
+    /// Never, even if this produces invalid CSV.
+    Never,
+}
+
+impl FromStr for QuoteStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<QuoteStyle> {
+        match s {
+            "necessary" => Ok(QuoteStyle::Necessary),
+            "always" => Ok(QuoteStyle::Always),
+            "non-numeric" => Ok(QuoteStyle::NonNumeric),
+            "never" => Ok(QuoteStyle::Never),
+            _ => Err(format!("unknown quote style {:?}", s).into()),
+        }
+    }
+}
+
+impl From<QuoteStyle> for csv::QuoteStyle {
+    fn from(style: QuoteStyle) -> csv::QuoteStyle {
+        match style {
+            QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            QuoteStyle::Always => csv::QuoteStyle::Always,
+            QuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            QuoteStyle::Never => csv::QuoteStyle::Never,
+        }
+    }
+}
+
+/// What should we do with rows that have more or fewer fields than the
+/// header row?
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RaggedRows {
+    /// Fail with an error.
+    #[default]
+    Error,
+    /// Pad short rows with empty fields, and truncate long rows.
+    Pad,
+    /// Skip them.
+    Skip,
+}
+
+impl FromStr for RaggedRows {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RaggedRows> {
+        match s {
+            "error" => Ok(RaggedRows::Error),
+            "pad" => Ok(RaggedRows::Pad),
+            "skip" => Ok(RaggedRows::Skip),
+            _ => Err(format!("unknown ragged row policy {:?}", s).into()),
+        }
+    }
+}
+
+/// The details of how a CSV file is formatted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dialect {
+    /// The character separating fields.
+    pub delimiter: u8,
+    /// The character used to quote fields.
+    pub quote: u8,
+    /// The character used to escape quotes inside a quoted field. If this is
+    /// `None`, quotes are escaped by doubling them.
+    pub escape: Option<u8>,
+    /// Skip input lines starting with this character.
+    pub comment: Option<u8>,
+    /// What to do with rows that have a different number of fields than the
+    /// header row.
+    pub on_ragged: RaggedRows,
+    /// When to quote output fields.
+    pub quote_style: QuoteStyle,
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            comment: None,
+            on_ragged: RaggedRows::default(),
+            quote_style: QuoteStyle::default(),
+        }
+    }
+}
+
+impl Dialect {
+    /// Create a CSV reader builder for this dialect.
+    pub fn reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .escape(self.escape)
+            .comment(self.comment)
+            .flexible(self.on_ragged != RaggedRows::Error);
+        builder
+    }
+
+    /// Create a CSV writer builder for this dialect. We never write headers
+    /// automatically.
+    pub fn writer_builder(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder
+            .has_headers(false)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .quote_style(self.quote_style.into())
+            .flexible(self.on_ragged != RaggedRows::Error);
+        if let Some(escape) = self.escape {
+            builder.double_quote(false).escape(escape);
+        }
+        builder
+    }
+}
+
+/// Delimiters which we know how to detect, in order of preference.
+const SNIFFED_DELIMITERS: &[u8] = b",\t|;";
+
+/// Guess the delimiter of a CSV file from a sample of its first few bytes,
+/// by counting possible delimiters outside of quotes on the first line. If
+/// we can't find any, return `None`.
+pub fn sniff_delimiter(sample: &[u8], quote: u8) -> Option<u8> {
+    let mut counts = [0; 4];
+    let mut in_quotes = false;
+    for &b in sample {
+        if b == quote {
+            in_quotes = !in_quotes;
+        } else if !in_quotes {
+            if b == b'\n' {
+                break;
+            }
+            if let Some(i) = SNIFFED_DELIMITERS.iter().position(|&d| d == b) {
+                counts[i] += 1;
+            }
+        }
+    }
+    let mut best = None;
+    for (i, &count) in counts.iter().enumerate() {
+        if count > 0 && best.is_none_or(|b: usize| count > counts[b]) {
+            best = Some(i);
+        }
+    }
+    best.map(|i| SNIFFED_DELIMITERS[i])
+}
+
+/// Parse a single-character dialect option, like a delimiter. We accept any
+/// single ASCII character, plus `\t` or `tab` for tabs.
+pub fn parse_char(s: &str) -> Result<u8> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(format!("expected a single ASCII character, found {:?}", s).into()),
+    }
+}
+
+#[test]
+fn parses_dialect_characters() {
+    let _ = env_logger::init();
+    assert_eq!(parse_char("|").unwrap(), b'|');
+    assert_eq!(parse_char("\\t").unwrap(), b'\t');
+    assert_eq!(parse_char("tab").unwrap(), b'\t');
+    assert!(parse_char("").is_err());
+    assert!(parse_char("ab").is_err());
+    assert!(parse_char("é").is_err());
+}
+
+#[test]
+fn sniffs_delimiters() {
+    let _ = env_logger::init();
+    assert_eq!(sniff_delimiter(b"name,zip\n", b'"'), Some(b','));
+    assert_eq!(sniff_delimiter(b"name\tzip\tstate\n", b'"'), Some(b'\t'));
+    assert_eq!(sniff_delimiter(b"\"a,b\"|zip\na,b,c,d\n", b'"'), Some(b'|'));
+    assert_eq!(sniff_delimiter(b"a;b;c,d", b'"'), Some(b';'));
+    assert_eq!(sniff_delimiter(b"a,b;c\n", b'"'), Some(b','));
+    assert_eq!(sniff_delimiter(b"zip\n1,2,3\n", b'"'), None);
+}
+
+#[test]
+fn round_trips_escaped_quotes() {
+    let _ = env_logger::init();
+    let dialect = Dialect {
+        escape: Some(b'\\'),
+        ..Dialect::default()
+    };
+    let input = "a\n\"say \\\"hi\\\"\"\n";
+    let mut rdr = dialect.reader_builder().from_reader(input.as_bytes());
+    let mut output = vec![];
+    {
+        let mut wtr = dialect.writer_builder().from_writer(&mut output);
+        wtr.write_record(rdr.headers().unwrap()).unwrap();
+        for row in rdr.records() {
+            wtr.write_record(&row.unwrap()).unwrap();
+        }
+    }
+    assert_eq!(String::from_utf8(output).unwrap(), input);
+}
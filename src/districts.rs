@@ -0,0 +1,125 @@
+//! Assigning zip codes to congressional and state legislative districts.
+//! Districts are redrawn every ten years, and zip codes often straddle
+//! them, so rather than building in a crosswalk which would go stale, we
+//! read one at runtime, such as HUD's ZIP-CD crosswalk or a Geocorr export.
+//! Each zip code gets the single district containing most of its weight.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+use census::normalize_zip;
+use errors::*;
+use number::parse_number;
+
+/// Columns which may contain the district, in the order we look for them.
+const DISTRICT_COLUMNS: &[&str] = &["district", "cd", "sldu", "sldl"];
+
+/// Columns which may contain the share of a zip code in each district, in
+/// the order we look for them. `res_ratio` and `tot_ratio` are used by HUD,
+/// and `afact` by Geocorr.
+const WEIGHT_COLUMNS: &[&str] = &["population", "res_ratio", "tot_ratio", "afact"];
+
+/// The dominant district of each zip code.
+#[derive(Clone, Debug)]
+pub struct DistrictTable {
+    /// The district of each 5-digit zip code.
+    district_for_zip: HashMap<String, String>,
+}
+
+impl DistrictTable {
+    /// Read a CSV crosswalk with a `zip` column, a `district` (or `cd`,
+    /// `sldu` or `sldl`) column and an optional weight column, which may be
+    /// `population`, `res_ratio`, `tot_ratio` or `afact`. A zip code may
+    /// appear once for each district it overlaps, and is assigned to the
+    /// one with the largest total weight. Without a weight column, every
+    /// row counts the same. Ties go to the district which sorts first.
+    pub fn load(input: &mut dyn Read) -> Result<DistrictTable> {
+        let mut rdr = csv::Reader::from_reader(input);
+        let headers = rdr.headers()?.to_owned();
+        let find_column = |names: &[&str]| {
+            names.iter().filter_map(|name| {
+                headers
+                    .iter()
+                    .position(|h| h.trim().eq_ignore_ascii_case(name))
+            }).next()
+        };
+        let zip_idx = find_column(&["zip"]).ok_or_else(|| Error::no_such_column("zip"))?;
+        let district_idx = find_column(DISTRICT_COLUMNS)
+            .ok_or_else(|| Error::no_such_column("district"))?;
+        let weight_idx = find_column(WEIGHT_COLUMNS);
+
+        let mut weights = HashMap::<(String, String), f64>::new();
+        for row in rdr.records() {
+            let row = row?;
+            let zip = normalize_zip(&row[zip_idx])
+                .ok_or_else(|| Error::invalid_weight(&row[zip_idx], row.position()))?;
+            let district = row[district_idx].trim();
+            if district.is_empty() {
+                return Err(format!("zip code {} has no district", zip).into());
+            }
+            let weight = match weight_idx {
+                Some(idx) => {
+                    parse_number(&row[idx])
+                        .filter(|w| *w >= 0.0)
+                        .ok_or_else(|| Error::invalid_weight(&row[idx], row.position()))?
+                }
+                None => 1.0,
+            };
+            *weights.entry((zip, district.to_owned())).or_insert(0.0) += weight;
+        }
+
+        let mut best = HashMap::<String, (String, f64)>::new();
+        for ((zip, district), weight) in weights {
+            let better = match best.get(&zip) {
+                Some(&(ref current, current_weight)) => {
+                    weight > current_weight || (weight == current_weight && district < *current)
+                }
+                None => true,
+            };
+            if better {
+                best.insert(zip, (district, weight));
+            }
+        }
+        let district_for_zip = best.into_iter()
+            .map(|(zip, (district, _))| (zip, district))
+            .collect();
+        Ok(DistrictTable { district_for_zip })
+    }
+
+    /// The district of a zip code. ZIP+4 codes are looked up using their
+    /// first five digits.
+    pub fn district_for_zip(&self, zip: &str) -> Option<&str> {
+        self.district_for_zip
+            .get(zip.get(..5)?)
+            .map(|district| &district[..])
+    }
+}
+
+#[test]
+fn loads_district_crosswalks() {
+    let _ = env_logger::init();
+    let input = "\
+ZIP,CD,RES_RATIO
+90210,0630,0.2
+90210,0633,0.8
+2138,2505,1
+10001,3610,0.5
+10001,3612,0.5
+";
+    let table = DistrictTable::load(&mut input.as_bytes()).unwrap();
+    assert_eq!(table.district_for_zip("90210-1234"), Some("0633"));
+    assert_eq!(table.district_for_zip("02138"), Some("2505"));
+    assert_eq!(table.district_for_zip("10001"), Some("3610"));
+    assert_eq!(table.district_for_zip("07712"), None);
+
+    let unweighted = "zip,sldu\n90210,026\n90210,024\n90210,026\n";
+    let table = DistrictTable::load(&mut unweighted.as_bytes()).unwrap();
+    assert_eq!(table.district_for_zip("90210"), Some("026"));
+    for bad in &["zip\n90210\n", "zip,cd\nN/A,0630\n", "zip,cd\n90210,\n",
+                 "zip,cd,afact\n90210,0630,x\n", "zip,cd,afact\n90210,0630,-1\n"] {
+        assert!(DistrictTable::load(&mut bad.as_bytes()).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,160 @@
+//! Support for designated market areas, or DMAs, the media markets used to
+//! plan TV and radio buys. The official zip code assignments are licensed,
+//! so we read them at runtime from a CSV file supplied by the user, with a
+//! `zip` column, a `dma` column and an optional `population` column.
+//!
+//! These files are used both to add a DMA column to our output, and by the
+//! `dma` chunk type, which builds chunks that never cross a DMA boundary.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+use census::normalize_zip;
+use errors::*;
+use number::{self, Rounding};
+use zip2010::{PrefixClasses, PrefixPopulation};
+
+/// The DMA of each zip code, and optionally their populations.
+#[derive(Clone, Debug)]
+pub struct DmaTable {
+    /// The DMA of each 5-digit zip code.
+    dma_for_zip: HashMap<String, String>,
+    /// The population of each zip code, if our file has a population
+    /// column.
+    populations: Option<Vec<(String, u64)>>,
+    /// For every prefix of every zip code in our file, the index of the DMA
+    /// with the most population among its zip codes. Without populations,
+    /// we count zip codes instead.
+    class_for_prefix: HashMap<String, usize>,
+}
+
+impl DmaTable {
+    /// Read a CSV file assigning zip codes to DMAs.
+    pub fn load(input: &mut dyn Read, rounding: Rounding) -> Result<DmaTable> {
+        let mut rdr = csv::Reader::from_reader(input);
+        let headers = rdr.headers()?.to_owned();
+        let find_column = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(name))
+        };
+        let zip_idx = find_column("zip").ok_or_else(|| Error::no_such_column("zip"))?;
+        let dma_idx = find_column("dma").ok_or_else(|| Error::no_such_column("dma"))?;
+        let pop_idx = find_column("population");
+
+        let mut dma_for_zip = HashMap::new();
+        let mut populations = pop_idx.map(|_| vec![]);
+        for row in rdr.records() {
+            let row = row?;
+            let zip = normalize_zip(&row[zip_idx])
+                .ok_or_else(|| Error::invalid_weight(&row[zip_idx], row.position()))?;
+            let dma = row[dma_idx].trim();
+            if dma.is_empty() {
+                return Err(format!("zip code {} has no DMA", zip).into());
+            }
+            if let (Some(idx), Some(populations)) = (pop_idx, populations.as_mut()) {
+                let pop = number::parse_weight(&row[idx], rounding)
+                    .ok_or_else(|| Error::invalid_weight(&row[idx], row.position()))?;
+                populations.push((zip.clone(), pop));
+            }
+            if dma_for_zip.insert(zip.clone(), dma.to_owned()).is_some() {
+                return Err(format!("zip code {} appears more than once", zip).into());
+            }
+        }
+
+        let mut dmas = dma_for_zip.values().cloned().collect::<Vec<_>>();
+        dmas.sort();
+        dmas.dedup();
+        let pop_for_zip = populations
+            .as_ref()
+            .map(|pops| pops.iter().cloned().collect::<HashMap<_, _>>());
+        let mut weights = HashMap::<(&str, usize), u64>::new();
+        for (zip, dma) in &dma_for_zip {
+            let class = dmas.binary_search(dma).expect("every DMA should be listed");
+            let weight = pop_for_zip.as_ref().map_or(1, |pops| pops[zip]);
+            for len in 0..=zip.len() {
+                *weights.entry((&zip[..len], class)).or_insert(0) += weight;
+            }
+        }
+        let mut best = HashMap::<&str, (u64, Reverse<usize>)>::new();
+        for ((prefix, class), weight) in weights {
+            let candidate = (weight, Reverse(class));
+            let entry = best.entry(prefix).or_insert(candidate);
+            if candidate > *entry {
+                *entry = candidate;
+            }
+        }
+        let class_for_prefix = best.into_iter()
+            .map(|(prefix, (_, Reverse(class)))| (prefix.to_owned(), class))
+            .collect();
+
+        Ok(DmaTable {
+               dma_for_zip,
+               populations,
+               class_for_prefix,
+           })
+    }
+
+    /// The DMA of a zip code. ZIP+4 codes are looked up using their first
+    /// five digits.
+    pub fn dma_for_zip(&self, zip: &str) -> Option<&str> {
+        self.dma_for_zip
+            .get(zip.get(..5)?)
+            .map(|dma| &dma[..])
+    }
+
+    /// The population of every zip code prefix, if our file has a
+    /// population column.
+    pub fn prefix_population(&self) -> Option<PrefixPopulation> {
+        self.populations
+            .as_ref()
+            .map(|pops| PrefixPopulation::from_weights(pops.iter().cloned()))
+    }
+}
+
+/// Keep zip codes in different DMAs out of the same chunk, classifying each
+/// prefix by the DMA containing most of its population.
+impl PrefixClasses for DmaTable {
+    fn name(&self) -> Option<String> {
+        None
+    }
+
+    fn class_for_prefix(&self, prefix: &str) -> Option<usize> {
+        self.class_for_prefix.get(prefix).cloned()
+    }
+}
+
+#[test]
+fn loads_dma_files() {
+    let _ = env_logger::init();
+    let input = "\
+ZIP,DMA,Population
+90210,803,100
+90211,803,50
+90212,825,200
+2138,506,10
+";
+    let table = DmaTable::load(&mut input.as_bytes(), Rounding::default()).unwrap();
+    assert_eq!(table.dma_for_zip("90210-1234"), Some("803"));
+    assert_eq!(table.dma_for_zip("02138"), Some("506"));
+    assert_eq!(table.dma_for_zip("10001"), None);
+    assert_eq!(table.prefix_population().unwrap().lookup("9021"), 350);
+    // DMAs are numbered in sorted order: 506, 803, 825.
+    assert_eq!(table.class_for_prefix("90210"), Some(1));
+    assert_eq!(table.class_for_prefix("9021"), Some(2));
+    assert_eq!(table.class_for_prefix("021"), Some(0));
+    assert_eq!(table.class_for_prefix("100"), None);
+
+    let no_pops = DmaTable::load(&mut "zip,dma\n90210,803\n".as_bytes(),
+                                 Rounding::default())
+            .unwrap();
+    assert!(no_pops.prefix_population().is_none());
+    for bad in &["zip\n90210\n", "zip,dma\nN/A,803\n", "zip,dma\n90210,\n",
+                 "zip,dma\n90210,803\n90210,825\n"] {
+        assert!(DmaTable::load(&mut bad.as_bytes(), Rounding::default()).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Reading CSV files which aren't in UTF-8.
+
+#[cfg(test)]
+use env_logger;
+use std::cmp::min;
+use std::io::{self, prelude::*};
+use std::str::FromStr;
+
+use errors::*;
+
+/// The UTF-8 byte order mark, which some Windows programs put at the start
+/// of CSV files.
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Windows-1252 characters 0x80 to 0x9F, which differ from Latin-1. Unused
+/// bytes are mapped to the corresponding control characters, as web
+/// browsers do.
+const WINDOWS_1252_HIGH: [char; 32] =
+    ['\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}',
+     '\u{2021}', '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}',
+     '\u{17d}', '\u{8f}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}',
+     '\u{2022}', '\u{2013}', '\u{2014}', '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}',
+     '\u{153}', '\u{9d}', '\u{17e}', '\u{178}'];
+
+/// The character encoding of our input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8, which we also use for our output.
+    #[default]
+    Utf8,
+    /// ISO-8859-1, where every byte is the Unicode character with the same
+    /// value.
+    Latin1,
+    /// Windows code page 1252, which is a superset of Latin-1's printable
+    /// characters.
+    Windows1252,
+}
+
+impl Encoding {
+    /// Decode a single byte of input.
+    fn decode_byte(self, b: u8) -> char {
+        match self {
+            Encoding::Windows1252 if (0x80..0xa0).contains(&b) => {
+                WINDOWS_1252_HIGH[(b - 0x80) as usize]
+            }
+            _ => char::from(b),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Encoding> {
+        match &s.to_ascii_lowercase()[..] {
+            "utf8" | "utf-8" => Ok(Encoding::Utf8),
+            "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Ok(Encoding::Windows1252),
+            _ => Err(format!("unknown encoding {:?}", s).into()),
+        }
+    }
+}
+
+/// Skip over a UTF-8 byte order mark at the start of `input`, if present.
+pub fn skip_bom(input: &mut dyn BufRead) -> io::Result<()> {
+    if input.fill_buf()?.starts_with(UTF8_BOM) {
+        input.consume(UTF8_BOM.len());
+    }
+    Ok(())
+}
+
+/// A reader which converts input in a single-byte encoding to UTF-8.
+pub struct Transcoder<R: Read> {
+    /// Where we read our undecoded input.
+    inner: R,
+    /// The encoding of our input.
+    encoding: Encoding,
+    /// A buffer for reading undecoded input.
+    input_buf: Vec<u8>,
+    /// Decoded UTF-8 data that hasn't been read yet.
+    output_buf: Vec<u8>,
+    /// How much of `output_buf` has already been read.
+    output_pos: usize,
+}
+
+impl<R: Read> Transcoder<R> {
+    /// Create a new transcoder reading from `inner`.
+    pub fn new(inner: R, encoding: Encoding) -> Transcoder<R> {
+        Transcoder {
+            inner,
+            encoding,
+            input_buf: vec![0; 8 * 1024],
+            output_buf: vec![],
+            output_pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Transcoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.encoding == Encoding::Utf8 {
+            return self.inner.read(buf);
+        }
+        if self.output_pos == self.output_buf.len() {
+            let count = self.inner.read(&mut self.input_buf)?;
+            self.output_buf.clear();
+            self.output_pos = 0;
+            let mut encoded = [0; 4];
+            for &b in &self.input_buf[..count] {
+                let c = self.encoding.decode_byte(b);
+                self.output_buf
+                    .extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+            }
+        }
+        let available = &self.output_buf[self.output_pos..];
+        let count = min(available.len(), buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.output_pos += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+fn transcode(input: &[u8], encoding: Encoding) -> String {
+    let mut out = String::new();
+    Transcoder::new(input, encoding)
+        .read_to_string(&mut out)
+        .unwrap();
+    out
+}
+
+#[test]
+fn transcodes_single_byte_encodings() {
+    let _ = env_logger::init();
+    assert_eq!(transcode(b"S\xe3o Paulo", Encoding::Latin1), "São Paulo");
+    assert_eq!(transcode(b"\x93Caf\xe9\x94 \x80", Encoding::Windows1252),
+               "\u{201c}Café\u{201d} €");
+    assert_eq!(transcode("Café".as_bytes(), Encoding::Utf8), "Café");
+}
+
+#[test]
+fn skips_utf8_bom() {
+    let _ = env_logger::init();
+    let mut input = &b"\xef\xbb\xbfzip\n"[..];
+    skip_bom(&mut input).unwrap();
+    assert_eq!(input, b"zip\n");
+    let mut input = &b"zip\n"[..];
+    skip_bom(&mut input).unwrap();
+    assert_eq!(input, b"zip\n");
+}
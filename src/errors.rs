@@ -1,5 +1,9 @@
 //! A module to hold `Error`, etc., types generated by `error-chain`.
 
+// `error_chain!` checks for a `cfg` that it sets from its own build script,
+// which rustc doesn't know about when compiling our crate.
+#![allow(unexpected_cfgs)]
+
 use csv;
 use std::io;
 
@@ -10,25 +14,100 @@ error_chain! {
     }
 
     errors {
+        InvalidWeight(value: String, pos: Option<csv::Position>) {
+            description("Invalid zip code or weight in weight file")
+            display("Invalid zip code or weight {:?}{}", value, at_line(pos))
+        }
+        InvalidZip(value: String, column: String, pos: Option<csv::Position>) {
+            description("Invalid zip code in CSV file")
+            display("Invalid zip code {:?} in column '{}'{}",
+                    value,
+                    column,
+                    at_line(pos))
+        }
         NoSuchColumn(name: String) {
             description("Cannot find specified CSV column")
             display("No CSV column with name '{}'", name)
         }
         NonUtf8Zip(pos: Option<csv::Position>) {
             description("Zip code column contained non-UTF8 data")
-            display("Non-UTF8 zip code data at line {:?}",
-                    pos.as_ref().map(|p| p.line()))
+            display("Non-UTF8 zip code data{}", at_line(pos))
+        }
+        RaggedRow(len: usize, expected: usize, pos: Option<csv::Position>) {
+            description("CSV row had the wrong number of fields")
+            display("Found row with {} fields, but expected {}{}",
+                    len,
+                    expected,
+                    at_line(pos))
         }
     }
 }
 
+/// Describe where an error occurred, if we know.
+fn at_line(pos: &Option<csv::Position>) -> String {
+    match *pos {
+        Some(ref pos) => format!(" at line {}", pos.line()),
+        None => "".to_owned(),
+    }
+}
+
 impl Error {
     /// Return an `Error` for `ErrorKind::NoSuchColumn`.
     pub fn no_such_column<S: Into<String>>(name: S) -> Error {
         ErrorKind::NoSuchColumn(name.into()).into()
     }
 
+    /// Return an `Error` for `ErrorKind::InvalidWeight`.
+    pub fn invalid_weight<S: Into<String>>(value: S,
+                                           pos: Option<&csv::Position>)
+                                           -> Error {
+        ErrorKind::InvalidWeight(value.into(), pos.map(|p| p.to_owned())).into()
+    }
+
+    /// Return an `Error` for `ErrorKind::InvalidZip`.
+    pub fn invalid_zip<S1, S2>(value: S1,
+                               column: S2,
+                               pos: Option<&csv::Position>)
+                               -> Error
+        where S1: Into<String>,
+              S2: Into<String>
+    {
+        ErrorKind::InvalidZip(value.into(), column.into(), pos.map(|p| p.to_owned()))
+            .into()
+    }
+
+    /// Return an `Error` for `ErrorKind::NonUtf8Zip`.
     pub fn non_utf8_zip(pos: Option<&csv::Position>) -> Error {
         ErrorKind::NonUtf8Zip(pos.map(|p| p.to_owned())).into()
     }
+
+    /// Return an `Error` for `ErrorKind::RaggedRow`.
+    pub fn ragged_row(len: usize, expected: usize, pos: Option<&csv::Position>) -> Error {
+        ErrorKind::RaggedRow(len, expected, pos.map(|p| p.to_owned())).into()
+    }
+}
+
+impl Error {
+    /// Was this error caused by an I/O error, such as a missing file or a
+    /// closed pipe, instead of by bad input?
+    pub fn is_io_error(&self) -> bool {
+        match self.0 {
+            ErrorKind::Io(_) => return true,
+            ErrorKind::Csv(ref err) if is_csv_io_error(err) => return true,
+            _ => {}
+        }
+        match self.1.next_error {
+            Some(ref next) => {
+                next.downcast_ref::<io::Error>().is_some() ||
+                next.downcast_ref::<csv::Error>().is_some_and(is_csv_io_error) ||
+                next.downcast_ref::<Error>().is_some_and(Error::is_io_error)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Was this CSV error caused by an I/O error?
+fn is_csv_io_error(err: &csv::Error) -> bool {
+    matches!(*err.kind(), csv::ErrorKind::Io(_))
 }
@@ -0,0 +1,344 @@
+//! Assigning whole geochunks to the arms of an experiment.
+//!
+//! Geo-experiments need to randomize by geography, not by individual, so
+//! that neighbors who see each other's treatment end up in the same arm.
+
+use csv;
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::io::prelude::*;
+
+use errors::*;
+use gazetteer::Zcta;
+use zip2010::Classifier;
+
+/// The experiment arm assigned to each chunk.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArmAssignment {
+    /// The names of our arms, in the order they were given.
+    arms: Vec<String>,
+    /// The arm of each chunk.
+    arm_for_chunk: HashMap<String, String>,
+    /// Sets of similar chunks, each containing one chunk from each arm
+    /// (except possibly the last). We call these "pairs", even when we have
+    /// more than two arms.
+    pairs: Vec<Vec<String>>,
+}
+
+impl ArmAssignment {
+    /// Randomly assign each chunk in `classifier` to one of `arms`, so that
+    /// every arm gets a similar population. The same `seed` always gives the
+    /// same assignment.
+    ///
+    /// We sort chunks by population, and shuffle the arms within each run of
+    /// `arms.len()` chunks, so that each arm gets one chunk of each size.
+    pub fn new(classifier: &Classifier, arms: &[String], seed: u64) -> Result<ArmAssignment> {
+        check_arms(arms)?;
+        let mut chunks = classifier.chunk_ids().iter().collect::<Vec<_>>();
+        chunks.sort_by_key(|&c| (Reverse(chunk_population(classifier, c)), c));
+        let pairs = chunks
+            .chunks(arms.len())
+            .map(|pair| pair.iter().map(|&c| c.to_owned()).collect())
+            .collect();
+        Ok(ArmAssignment::from_pairs(arms, seed, pairs))
+    }
+
+    /// Like `new`, but match each chunk with the most similar unmatched
+    /// chunks, using both population and population density, before
+    /// assigning arms within each pair. This usually makes our arms more
+    /// alike than `new` does, which makes experiments more powerful.
+    ///
+    /// `densities` should be computed by `chunk_densities`. If it's `None`,
+    /// we only match on population.
+    pub fn matched_pairs(classifier: &Classifier,
+                         arms: &[String],
+                         seed: u64,
+                         densities: Option<&HashMap<String, f64>>)
+                         -> Result<ArmAssignment> {
+        check_arms(arms)?;
+
+        // Describe each chunk by its log population and log density, scaled
+        // so that both vary by about the same amount.
+        let chunk_ids = classifier.chunk_ids();
+        let mut features = chunk_ids
+            .iter()
+            .map(|c| {
+                     let pop = chunk_population(classifier, c) as f64;
+                     let density = densities
+                         .and_then(|d| d.get(c))
+                         .cloned()
+                         .unwrap_or(0.0);
+                     [(pop + 1.0).ln(), (density + 1.0).ln()]
+                 })
+            .collect::<Vec<_>>();
+        for dim in 0..2 {
+            let sd = std_dev(features.iter().map(|f| f[dim]));
+            for f in &mut features {
+                f[dim] = if sd > 0.0 { f[dim] / sd } else { 0.0 };
+            }
+        }
+        let distance = |a: usize, b: usize| {
+            let (fa, fb) = (features[a], features[b]);
+            (fa[0] - fb[0]).powi(2) + (fa[1] - fb[1]).powi(2)
+        };
+
+        // Starting with the most populous chunk, greedily match each
+        // unmatched chunk with its nearest unmatched neighbors.
+        let mut order = (0..chunk_ids.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| (Reverse(chunk_population(classifier, &chunk_ids[i])), i));
+        let mut matched = vec![false; chunk_ids.len()];
+        let mut pairs = vec![];
+        for &i in &order {
+            if matched[i] {
+                continue;
+            }
+            matched[i] = true;
+            let mut candidates = order
+                .iter()
+                .cloned()
+                .filter(|&j| !matched[j])
+                .map(|j| (distance(i, j), j))
+                .collect::<Vec<_>>();
+            let by_distance =
+                |a: &(f64, usize), b: &(f64, usize)| {
+                    a.partial_cmp(b).expect("distances should not be NaN")
+                };
+            let wanted = (arms.len() - 1).min(candidates.len());
+            if wanted < candidates.len() {
+                candidates.select_nth_unstable_by(wanted, by_distance);
+            }
+            candidates.truncate(wanted);
+            candidates.sort_by(by_distance);
+            let mut pair = vec![chunk_ids[i].clone()];
+            for &(_, j) in &candidates {
+                matched[j] = true;
+                pair.push(chunk_ids[j].clone());
+            }
+            pairs.push(pair);
+        }
+        Ok(ArmAssignment::from_pairs(arms, seed, pairs))
+    }
+
+    /// Randomly assign the chunks in each of `pairs` to different arms.
+    fn from_pairs(arms: &[String], seed: u64, pairs: Vec<Vec<String>>) -> ArmAssignment {
+        let mut rng = SplitMix64::new(seed);
+        let mut arm_for_chunk = HashMap::new();
+        for pair in &pairs {
+            let mut order = (0..arms.len()).collect::<Vec<_>>();
+            rng.shuffle(&mut order);
+            for (chunk, &arm) in pair.iter().zip(&order) {
+                arm_for_chunk.insert(chunk.to_owned(), arms[arm].clone());
+            }
+        }
+        ArmAssignment {
+            arms: arms.to_owned(),
+            arm_for_chunk,
+            pairs,
+        }
+    }
+
+    /// The names of our arms.
+    pub fn arms(&self) -> &[String] {
+        &self.arms
+    }
+
+    /// The arm containing `chunk_id`, if any.
+    pub fn arm_for_chunk(&self, chunk_id: &str) -> Option<&str> {
+        self.arm_for_chunk.get(chunk_id).map(|arm| &arm[..])
+    }
+
+    /// The total population of each arm.
+    pub fn arm_populations(&self, classifier: &Classifier) -> BTreeMap<String, u64> {
+        let mut pops = self.arms
+            .iter()
+            .map(|arm| (arm.to_owned(), 0))
+            .collect::<BTreeMap<_, _>>();
+        for (chunk, arm) in &self.arm_for_chunk {
+            *pops.get_mut(arm).expect("every arm should have a population") +=
+                chunk_population(classifier, chunk);
+        }
+        pops
+    }
+
+    /// Our pairs of similar chunks. Each pair contains one chunk from each
+    /// arm, except possibly the last, which may be smaller.
+    pub fn pairs(&self) -> &[Vec<String>] {
+        &self.pairs
+    }
+
+    /// Write a CSV file listing the pair, arm, population and density of
+    /// each chunk, so that an experiment can be analyzed pair by pair.
+    pub fn write_pairs(&self,
+                       classifier: &Classifier,
+                       densities: Option<&HashMap<String, f64>>,
+                       out: &mut dyn Write)
+                       -> Result<()> {
+        let mut wtr = csv::Writer::from_writer(out);
+        wtr.write_record([&classifier.geochunk_column_name()[..],
+                          "pair",
+                          "arm",
+                          "population",
+                          "density"])?;
+        for (i, pair) in self.pairs.iter().enumerate() {
+            for chunk in pair {
+                let density = densities
+                    .and_then(|d| d.get(chunk))
+                    .map(|d| format!("{:.1}", d))
+                    .unwrap_or_default();
+                wtr.write_record([chunk,
+                                  &(i + 1).to_string(),
+                                  self.arm_for_chunk(chunk).unwrap_or(""),
+                                  &chunk_population(classifier, chunk).to_string(),
+                                  &density])?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// The population density of each chunk in `classifier`, in people per
+/// square mile, using the land areas in `zctas`. Chunks without any land
+/// area are left out.
+pub fn chunk_densities(classifier: &Classifier, zctas: &[Zcta]) -> HashMap<String, f64> {
+    let mut areas = HashMap::new();
+    for zcta in zctas {
+        if let Some(chunk) = classifier.chunk_for(&zcta.zip) {
+            *areas.entry(chunk).or_insert(0.0) += zcta.land_area_sq_mi();
+        }
+    }
+    areas
+        .into_iter()
+        .filter(|&(_, area)| area > 0.0)
+        .map(|(chunk, area)| {
+                 (chunk.to_owned(), chunk_population(classifier, chunk) as f64 / area)
+             })
+        .collect()
+}
+
+/// The population of `chunk_id`, which must exist in `classifier`.
+fn chunk_population(classifier: &Classifier, chunk_id: &str) -> u64 {
+    classifier
+        .chunk_population(chunk_id)
+        .expect("all chunks should have a population")
+}
+
+/// The standard deviation of `values`.
+fn std_dev<I: Iterator<Item = f64> + Clone>(values: I) -> f64 {
+    let count = values.clone().count() as f64;
+    if count == 0.0 {
+        return 0.0;
+    }
+    let mean = values.clone().sum::<f64>() / count;
+    (values.map(|v| (v - mean).powi(2)).sum::<f64>() / count).sqrt()
+}
+
+/// Make sure we've been given at least two distinct, non-empty arm names.
+fn check_arms(arms: &[String]) -> Result<()> {
+    if arms.len() < 2 {
+        return Err("an experiment needs at least two arms".into());
+    }
+    for (i, arm) in arms.iter().enumerate() {
+        if arm.is_empty() {
+            return Err("arm names cannot be empty".into());
+        }
+        if arms[..i].contains(arm) {
+            return Err(format!("arm {:?} was given more than once", arm).into());
+        }
+    }
+    Ok(())
+}
+
+/// A small, fast random number generator, which gives the same results on
+/// every platform. See http://xoshiro.di.unimi.it/splitmix64.c.
+struct SplitMix64 {
+    /// Our current state.
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// Create a generator from a seed.
+    fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+
+    /// Return a random `u64`.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Shuffle `items` into a random order.
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn assigns_chunks_to_balanced_arms() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let arms = vec!["control".to_owned(), "testA".to_owned(), "testB".to_owned()];
+    let assignment = ArmAssignment::new(&classifier, &arms, 42).unwrap();
+    assert_eq!(assignment, ArmAssignment::new(&classifier, &arms, 42).unwrap());
+    assert_ne!(assignment, ArmAssignment::new(&classifier, &arms, 43).unwrap());
+    assert!(classifier
+                .chunk_ids()
+                .iter()
+                .all(|c| assignment.arm_for_chunk(c).is_some()));
+
+    let pops = assignment.arm_populations(&classifier);
+    let max = *pops.values().max().unwrap();
+    let min = *pops.values().min().unwrap();
+    assert!(max - min < 250000, "unbalanced arms: {:?}", pops);
+
+    assert!(ArmAssignment::new(&classifier, &arms[..1], 42).is_err());
+    let dup = vec!["control".to_owned(), "control".to_owned()];
+    assert!(ArmAssignment::new(&classifier, &dup, 42).is_err());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn assigns_matched_pairs_to_different_arms() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let arms = vec!["control".to_owned(), "test".to_owned()];
+    let zctas = vec![Zcta {
+                         zip: "90210".to_owned(),
+                         land_area: 1_000_000_000,
+                     },
+                     Zcta {
+                         zip: "07712".to_owned(),
+                         land_area: 0,
+                     }];
+    let densities = chunk_densities(&classifier, &zctas);
+    assert_eq!(densities.len(), 1);
+    assert!(densities["902_0"] > 0.0);
+
+    let assignment = ArmAssignment::matched_pairs(&classifier, &arms, 42, Some(&densities))
+        .unwrap();
+    let pairs = assignment.pairs();
+    assert_eq!(pairs.iter().map(|p| p.len()).sum::<usize>(),
+               classifier.chunk_ids().len());
+    for pair in pairs.iter().filter(|p| p.len() == 2) {
+        assert_ne!(assignment.arm_for_chunk(&pair[0]),
+                   assignment.arm_for_chunk(&pair[1]));
+    }
+
+    let mut out = vec![];
+    assignment
+        .write_pairs(&classifier, Some(&densities), &mut out)
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("geochunk_zip2010_250000,pair,arm,population,density\n"));
+    assert!(out.contains(",1,"));
+}
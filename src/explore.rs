@@ -0,0 +1,291 @@
+//! An interactive explorer for chunk plans, so analysts can browse chunks,
+//! look up zip codes and compare target populations without rerunning
+//! `geochunk plan` for every question.
+//!
+//! This explorer reads one command per line, so it works in scripts, and
+//! whenever `explore_tui`'s full-screen interface isn't available:
+//!
+//! ```text
+//! > zip 90210
+//! 90210 is in chunk 902_0, found using prefix 9021
+//! > compare 100000
+//! ```
+
+#[cfg(test)]
+use env_logger;
+use std::io::prelude::*;
+
+use errors::*;
+use zip2010::{ChunkSummary, Classifier};
+
+/// The most chunks we list at once.
+const MAX_LISTED_CHUNKS: usize = 50;
+
+/// Statistics about the chunks built for one target population.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PlanStats {
+    /// The population we wanted in each chunk.
+    pub(crate) target: u64,
+    /// The number of chunks.
+    pub(crate) chunks: u64,
+    /// The median chunk population.
+    pub(crate) median: u64,
+    /// The largest chunk population.
+    pub(crate) max: u64,
+    /// The number of chunks with less than half the target population.
+    pub(crate) small: u64,
+}
+
+impl PlanStats {
+    /// Compute statistics for `classifier`.
+    pub(crate) fn new(classifier: &Classifier) -> PlanStats {
+        let target = classifier.metadata().target_population;
+        let mut pops = classifier
+            .chunk_ids()
+            .iter()
+            .map(|c| {
+                     classifier
+                         .chunk_population(c)
+                         .expect("all chunks should have a population")
+                 })
+            .collect::<Vec<_>>();
+        pops.sort();
+        PlanStats {
+            target,
+            chunks: pops.len() as u64,
+            median: pops.get(pops.len() / 2).cloned().unwrap_or(0),
+            max: pops.last().cloned().unwrap_or(0),
+            small: pops.iter().filter(|&&p| p < target / 2).count() as u64,
+        }
+    }
+}
+
+/// The state of an exploration session.
+struct Explorer<'a> {
+    /// The chunks we're exploring.
+    classifier: Classifier,
+    /// A summary of each chunk in `classifier`.
+    summaries: Vec<ChunkSummary>,
+    /// Builds chunks for a different target population.
+    rebuild: &'a mut dyn FnMut(u64) -> Result<Classifier>,
+}
+
+/// Explore `classifier`, reading commands from `input` until it ends or we
+/// see `quit`. `rebuild` is used to build chunks for other target
+/// populations, using the same data and options.
+pub fn explore(classifier: Classifier,
+               rebuild: &mut dyn FnMut(u64) -> Result<Classifier>,
+               input: &mut dyn BufRead,
+               output: &mut dyn Write)
+               -> Result<()> {
+    let mut explorer = Explorer {
+        summaries: classifier.chunk_summaries(),
+        classifier,
+        rebuild,
+    };
+    writeln!(output, "Type \"help\" for a list of commands.")?;
+    explorer.show_stats(output)?;
+    let mut line = String::new();
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let result = match words[..] {
+            [] => Ok(()),
+            ["quit"] | ["exit"] => return Ok(()),
+            ["help"] => show_help(output),
+            ["summary"] => explorer.show_stats(output),
+            ["chunks"] => explorer.list_chunks("", output),
+            ["chunks", start] => explorer.list_chunks(start, output),
+            ["chunk", id] => explorer.show_chunk(id, output),
+            ["zip", zip] => explorer.show_zip(zip, output),
+            ["prefix", prefix] => explorer.show_prefix(prefix, output),
+            ["target", pop] => explorer.set_target(pop, output),
+            ["compare", pop] => explorer.compare(pop, output),
+            _ => Err(format!("unknown command {:?}", line.trim()).into()),
+        };
+        // Mistakes shouldn't end the session, but write errors should.
+        match result {
+            Err(Error(ErrorKind::Io(err), _)) => return Err(err.into()),
+            Err(err) => writeln!(output, "error: {}", err)?,
+            Ok(()) => {}
+        }
+    }
+}
+
+/// List our commands.
+fn show_help(output: &mut dyn Write) -> Result<()> {
+    writeln!(output,
+             "Commands:
+  summary            Show statistics about the current chunks.
+  chunks [<start>]   List chunks, or those whose IDs start with <start>.
+  chunk <id>         Show the population and prefixes of a chunk.
+  zip <zip>          Show the chunk containing a zip code.
+  prefix <prefix>    Show the chunks containing zip codes with a prefix.
+  target <pop>       Switch to chunks built for another target population.
+  compare <pop>      Compare the current chunks to another target population.
+  quit               Stop exploring.")?;
+    Ok(())
+}
+
+impl<'a> Explorer<'a> {
+    /// Show statistics about our chunks.
+    fn show_stats(&self, output: &mut dyn Write) -> Result<()> {
+        let stats = PlanStats::new(&self.classifier);
+        writeln!(output,
+                 "{}: {} chunks, median population {}, largest {}, {} under half \
+                  the target",
+                 self.classifier.geochunk_column_name(),
+                 stats.chunks,
+                 stats.median,
+                 stats.max,
+                 stats.small)?;
+        Ok(())
+    }
+
+    /// List the chunks whose IDs start with `start`.
+    fn list_chunks(&self, start: &str, output: &mut dyn Write) -> Result<()> {
+        let matching = self.summaries
+            .iter()
+            .filter(|s| s.id.starts_with(start))
+            .collect::<Vec<_>>();
+        for summary in matching.iter().take(MAX_LISTED_CHUNKS) {
+            writeln!(output,
+                     "{:<12} population {:>9}, {} prefixes",
+                     summary.id,
+                     summary.population,
+                     summary.prefixes.len())?;
+        }
+        if matching.len() > MAX_LISTED_CHUNKS {
+            writeln!(output,
+                     "... and {} more",
+                     matching.len() - MAX_LISTED_CHUNKS)?;
+        }
+        Ok(())
+    }
+
+    /// Show the details of chunk `id`.
+    fn show_chunk(&self, id: &str, output: &mut dyn Write) -> Result<()> {
+        let summary = self.summaries
+            .iter()
+            .find(|s| s.id == id)
+            .ok_or_else(|| format!("no chunk {:?}", id))?;
+        writeln!(output,
+                 "chunk {} (index {}): population {}\nprefixes: {}",
+                 summary.id,
+                 summary.index,
+                 summary.population,
+                 summary.prefixes.join(" "))?;
+        Ok(())
+    }
+
+    /// Show the chunk containing `zip`.
+    fn show_zip(&self, zip: &str, output: &mut dyn Write) -> Result<()> {
+        let (prefix, chunk) = self.classifier
+            .prefix_and_chunk_for(zip)
+            .ok_or_else(|| format!("{:?} is not in any chunk", zip))?;
+        writeln!(output,
+                 "{} is in chunk {}, found using prefix {}",
+                 zip,
+                 chunk,
+                 prefix)?;
+        Ok(())
+    }
+
+    /// Show the population of `prefix`, and the chunks containing zip codes
+    /// which start with it.
+    fn show_prefix(&self, prefix: &str, output: &mut dyn Write) -> Result<()> {
+        let population = self.classifier
+            .population_for(prefix)
+            .ok_or_else(|| format!("invalid prefix {:?}", prefix))?;
+        let chunks = chunks_touching_prefix(&self.summaries, prefix);
+        writeln!(output,
+                 "prefix {}: population {}, in {} chunks: {}",
+                 prefix,
+                 population,
+                 chunks.len(),
+                 chunks.join(" "))?;
+        Ok(())
+    }
+
+    /// Build chunks for the target population `pop`.
+    fn rebuild(&mut self, pop: &str) -> Result<Classifier> {
+        let pop = pop.parse::<u64>()
+            .ok()
+            .filter(|&p| p > 0)
+            .ok_or_else(|| format!("invalid target population {:?}", pop))?;
+        (self.rebuild)(pop)
+    }
+
+    /// Switch to chunks built for the target population `pop`.
+    fn set_target(&mut self, pop: &str, output: &mut dyn Write) -> Result<()> {
+        self.classifier = self.rebuild(pop)?;
+        self.summaries = self.classifier.chunk_summaries();
+        self.show_stats(output)
+    }
+
+    /// Compare our chunks to those built for the target population `pop`.
+    fn compare(&mut self, pop: &str, output: &mut dyn Write) -> Result<()> {
+        let other = self.rebuild(pop)?;
+        let rows = comparison_rows(PlanStats::new(&self.classifier), PlanStats::new(&other));
+        for &(name, ours, theirs) in &rows {
+            writeln!(output, "{:<20} {:>12} {:>12}", name, ours, theirs)?;
+        }
+        Ok(())
+    }
+}
+
+/// The IDs of the chunks in `summaries` containing zip codes which start
+/// with `prefix`.
+pub(crate) fn chunks_touching_prefix<'s>(summaries: &'s [ChunkSummary],
+                                         prefix: &str)
+                                         -> Vec<&'s str> {
+    summaries
+        .iter()
+        .filter(|s| {
+                    s.prefixes
+                        .iter()
+                        .any(|p| p.starts_with(prefix) || prefix.starts_with(&p[..]))
+                })
+        .map(|s| &s.id[..])
+        .collect()
+}
+
+/// The rows of a table comparing `ours` to `theirs`.
+pub(crate) fn comparison_rows(ours: PlanStats,
+                              theirs: PlanStats)
+                              -> [(&'static str, u64, u64); 5] {
+    [("target", ours.target, theirs.target),
+     ("chunks", ours.chunks, theirs.chunks),
+     ("median population", ours.median, theirs.median),
+     ("largest population", ours.max, theirs.max),
+     ("under half target", ours.small, theirs.small)]
+}
+
+#[test]
+fn explores_chunks() {
+    let _ = env_logger::init();
+    let mut rebuild = |pop| Ok(Classifier::new(pop));
+    let input = "zip 90210\nchunk 902_0\nprefix 9021\nchunks 90\n\
+                 bogus\ncompare 100000\ntarget 500000\nzip 90210\nquit\nzip 07712\n";
+    let mut output = vec![];
+    explore(Classifier::new(250000),
+            &mut rebuild,
+            &mut input.as_bytes(),
+            &mut output)
+            .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("> 90210 is in chunk 902_0, found using prefix 9021\n"));
+    assert!(output.contains("chunk 902_0 (index "));
+    assert!(output.contains("prefix 9021: population "));
+    assert!(output.contains("\n902_0        population "));
+    assert!(output.contains("error: unknown command \"bogus\"\n"));
+    assert!(output.contains("> target                     250000       100000\n"));
+    assert!(output.contains("geochunk_zip2010_500000: "));
+    assert!(!output.contains("07712"));
+}
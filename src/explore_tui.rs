@@ -0,0 +1,520 @@
+//! A full-screen terminal interface for exploring chunk plans. Analysts can
+//! scroll through chunks, drill down from a chunk's prefixes into the zip
+//! codes beneath them, search for zip codes and compare target populations.
+//!
+//! `geochunk explore` uses this when stdin and stdout are both terminals,
+//! and falls back to the line-by-line explorer in `explore` otherwise.
+
+#[cfg(test)]
+use env_logger;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use errors::*;
+use explore::{chunks_touching_prefix, comparison_rows, PlanStats};
+use zip2010::{ChunkSummary, Classifier};
+
+/// The number of digits in a zip code, below which we can't drill down.
+const ZIP_CODE_LENGTH: usize = 5;
+
+/// Which list our arrow keys move through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Focus {
+    /// The list of chunks on the left.
+    Chunks,
+    /// The prefixes of the selected chunk, or beneath a prefix we've
+    /// drilled into, on the right.
+    Prefixes,
+}
+
+/// A question we're asking at the bottom of the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Prompt {
+    /// Only list chunks whose IDs start with this.
+    Filter,
+    /// Find the chunk containing a zip code.
+    Zip,
+    /// Switch to another target population.
+    Target,
+    /// Compare our chunks to another target population.
+    Compare,
+}
+
+impl Prompt {
+    /// The label we show before the user's answer.
+    fn label(self) -> &'static str {
+        match self {
+            Prompt::Filter => "Chunks starting with: ",
+            Prompt::Zip => "Find zip code: ",
+            Prompt::Target => "Switch to target population: ",
+            Prompt::Compare => "Compare to target population: ",
+        }
+    }
+}
+
+/// One row of the prefix list.
+struct PrefixRow {
+    /// The zip code prefix.
+    prefix: String,
+    /// Its population.
+    population: u64,
+    /// The chunks containing zip codes which start with it.
+    chunks: Vec<String>,
+}
+
+/// The state of our interface.
+struct App<'a> {
+    /// The chunks we're exploring.
+    classifier: Classifier,
+    /// A summary of each chunk in `classifier`.
+    summaries: Vec<ChunkSummary>,
+    /// Builds chunks for a different target population.
+    rebuild: &'a mut dyn FnMut(u64) -> Result<Classifier>,
+    /// Statistics about `classifier`.
+    stats: PlanStats,
+    /// Statistics about the chunks we're comparing against, if any.
+    comparison: Option<PlanStats>,
+    /// Only chunks whose IDs start with this are listed.
+    filter: String,
+    /// The indices in `summaries` of the chunks we list.
+    listed: Vec<usize>,
+    /// Our selection in `listed`.
+    chunk_state: ListState,
+    /// The prefixes we've drilled into, innermost last. When this is
+    /// empty, we list the prefixes of the selected chunk.
+    drilled: Vec<String>,
+    /// The rows of our prefix list.
+    prefix_rows: Vec<PrefixRow>,
+    /// Our selection in `prefix_rows`.
+    prefix_state: ListState,
+    /// Which list has the focus.
+    focus: Focus,
+    /// The question we're asking, and the answer typed so far.
+    prompt: Option<(Prompt, String)>,
+    /// A message for the status line.
+    message: String,
+}
+
+impl<'a> App<'a> {
+    /// Create an interface exploring `classifier`.
+    fn new(classifier: Classifier, rebuild: &'a mut dyn FnMut(u64) -> Result<Classifier>) -> App<'a> {
+        let mut app = App {
+            stats: PlanStats::new(&classifier),
+            summaries: classifier.chunk_summaries(),
+            classifier,
+            rebuild,
+            comparison: None,
+            filter: String::new(),
+            listed: vec![],
+            chunk_state: ListState::default(),
+            drilled: vec![],
+            prefix_rows: vec![],
+            prefix_state: ListState::default(),
+            focus: Focus::Chunks,
+            prompt: None,
+            message: "/ filter  z zip  t target  c compare  Tab switch lists  \
+                      Enter drill down  Esc back  q quit"
+                    .to_owned(),
+        };
+        app.refilter();
+        app
+    }
+
+    /// The chunk which is selected, if any.
+    fn selected_chunk(&self) -> Option<&ChunkSummary> {
+        self.chunk_state
+            .selected()
+            .and_then(|i| self.listed.get(i))
+            .map(|&i| &self.summaries[i])
+    }
+
+    /// Recompute which chunks we list, after changing `filter` or our
+    /// chunks.
+    fn refilter(&mut self) {
+        self.listed = (0..self.summaries.len())
+            .filter(|&i| self.summaries[i].id.starts_with(&self.filter))
+            .collect();
+        self.chunk_state
+            .select(if self.listed.is_empty() { None } else { Some(0) });
+        self.reset_prefixes();
+    }
+
+    /// Stop drilling down, and list the prefixes of the selected chunk.
+    fn reset_prefixes(&mut self) {
+        self.drilled.clear();
+        self.refresh_prefixes();
+    }
+
+    /// Recompute our prefix list, after changing the selected chunk or
+    /// drilling up or down.
+    fn refresh_prefixes(&mut self) {
+        let prefixes = match self.drilled.last() {
+            Some(parent) => {
+                (0..10)
+                    .map(|digit| format!("{}{}", parent, digit))
+                    .collect::<Vec<_>>()
+            }
+            None => {
+                self.selected_chunk()
+                    .map(|summary| summary.prefixes.clone())
+                    .unwrap_or_default()
+            }
+        };
+        self.prefix_rows = prefixes
+            .into_iter()
+            .map(|prefix| {
+                PrefixRow {
+                    population: self.classifier.population_for(&prefix).unwrap_or(0),
+                    chunks: chunks_touching_prefix(&self.summaries, &prefix)
+                        .into_iter()
+                        .map(|c| c.to_owned())
+                        .collect(),
+                    prefix,
+                }
+            })
+            .collect();
+        self.prefix_state
+            .select(if self.prefix_rows.is_empty() { None } else { Some(0) });
+    }
+
+    /// Handle a key press, returning false if we should quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if let Some((prompt, mut answer)) = self.prompt.take() {
+            match key.code {
+                KeyCode::Enter => self.answer(prompt, answer.trim()),
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    answer.pop();
+                    self.prompt = Some((prompt, answer));
+                }
+                KeyCode::Char(c) => {
+                    answer.push(c);
+                    self.prompt = Some((prompt, answer));
+                }
+                _ => self.prompt = Some((prompt, answer)),
+            }
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return false
+            }
+            KeyCode::Char('/') => self.prompt = Some((Prompt::Filter, String::new())),
+            KeyCode::Char('z') => self.prompt = Some((Prompt::Zip, String::new())),
+            KeyCode::Char('t') => self.prompt = Some((Prompt::Target, String::new())),
+            KeyCode::Char('c') => self.prompt = Some((Prompt::Compare, String::new())),
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Chunks => Focus::Prefixes,
+                    Focus::Prefixes => Focus::Chunks,
+                };
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Right | KeyCode::Enter => self.drill_down(),
+            KeyCode::Left | KeyCode::Esc | KeyCode::Backspace => self.drill_up(),
+            _ => {}
+        }
+        true
+    }
+
+    /// Move the selection in the focused list by `delta` rows.
+    fn move_selection(&mut self, delta: isize) {
+        let (state, len) = match self.focus {
+            Focus::Chunks => (&mut self.chunk_state, self.listed.len()),
+            Focus::Prefixes => (&mut self.prefix_state, self.prefix_rows.len()),
+        };
+        if let Some(selected) = state.selected() {
+            let max = len.saturating_sub(1) as isize;
+            state.select(Some((selected as isize + delta).clamp(0, max) as usize));
+        }
+        if self.focus == Focus::Chunks {
+            self.reset_prefixes();
+        }
+    }
+
+    /// Drill into the selected prefix, listing the prefixes one digit
+    /// longer.
+    fn drill_down(&mut self) {
+        if self.focus == Focus::Chunks {
+            self.focus = Focus::Prefixes;
+            return;
+        }
+        let prefix = match self.prefix_state.selected().and_then(|i| self.prefix_rows.get(i)) {
+            Some(row) => row.prefix.clone(),
+            None => return,
+        };
+        if prefix.len() >= ZIP_CODE_LENGTH {
+            self.message = format!("{} is a complete zip code", prefix);
+            return;
+        }
+        self.drilled.push(prefix);
+        self.refresh_prefixes();
+    }
+
+    /// Go back up from the prefix we drilled into.
+    fn drill_up(&mut self) {
+        if self.drilled.pop().is_some() {
+            self.refresh_prefixes();
+        } else {
+            self.focus = Focus::Chunks;
+        }
+    }
+
+    /// Act on the answer to `prompt`.
+    fn answer(&mut self, prompt: Prompt, answer: &str) {
+        let result = match prompt {
+            Prompt::Filter => {
+                self.filter = answer.to_owned();
+                self.refilter();
+                Ok(())
+            }
+            Prompt::Zip => self.find_zip(answer),
+            Prompt::Target => {
+                self.rebuild(answer).map(|classifier| {
+                    self.stats = PlanStats::new(&classifier);
+                    self.summaries = classifier.chunk_summaries();
+                    self.classifier = classifier;
+                    self.comparison = None;
+                    self.refilter();
+                })
+            }
+            Prompt::Compare => {
+                self.rebuild(answer)
+                    .map(|other| self.comparison = Some(PlanStats::new(&other)))
+            }
+        };
+        if let Err(err) = result {
+            self.message = format!("error: {}", err);
+        }
+    }
+
+    /// Build chunks for the target population `pop`.
+    fn rebuild(&mut self, pop: &str) -> Result<Classifier> {
+        let pop = pop.parse::<u64>()
+            .ok()
+            .filter(|&p| p > 0)
+            .ok_or_else(|| format!("invalid target population {:?}", pop))?;
+        (self.rebuild)(pop)
+    }
+
+    /// Select the chunk containing `zip`, and drill down to `zip`'s prefix.
+    fn find_zip(&mut self, zip: &str) -> Result<()> {
+        let (prefix, chunk) = self.classifier
+            .prefix_and_chunk_for(zip)
+            .map(|(p, c)| (p.to_owned(), c.to_owned()))
+            .ok_or_else(|| format!("{:?} is not in any chunk", zip))?;
+        self.message = format!("{} is in chunk {}, found using prefix {}", zip, chunk, prefix);
+        if !chunk.starts_with(&self.filter) {
+            self.filter.clear();
+            self.refilter();
+        }
+        if let Some(pos) = self.listed.iter().position(|&i| self.summaries[i].id == chunk) {
+            self.chunk_state.select(Some(pos));
+            self.reset_prefixes();
+            if let Some(pos) = self.prefix_rows.iter().position(|r| r.prefix == prefix) {
+                self.prefix_state.select(Some(pos));
+                self.focus = Focus::Prefixes;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw our interface.
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, status] = Layout::vertical([Constraint::Length(3),
+                                                       Constraint::Min(3),
+                                                       Constraint::Length(1)])
+                .areas(frame.area());
+        self.draw_stats(frame, header);
+        let [chunks, details] = Layout::horizontal([Constraint::Length(36), Constraint::Min(20)])
+            .areas(body);
+        self.draw_chunks(frame, chunks);
+        self.draw_prefixes(frame, details);
+        let status_line = match self.prompt {
+            Some((prompt, ref answer)) => format!("{}{}", prompt.label(), answer),
+            None => self.message.clone(),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+
+    /// Draw statistics about our chunks, and any comparison.
+    fn draw_stats(&self, frame: &mut Frame, area: Rect) {
+        let line = match self.comparison {
+            Some(theirs) => {
+                comparison_rows(self.stats, theirs)
+                    .iter()
+                    .map(|&(name, ours, theirs)| format!("{} {} vs {}", name, ours, theirs))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            }
+            None => {
+                format!("{} chunks, median population {}, largest {}, {} under half the \
+                         target",
+                        self.stats.chunks,
+                        self.stats.median,
+                        self.stats.max,
+                        self.stats.small)
+            }
+        };
+        let block = Block::bordered().title(self.classifier.geochunk_column_name());
+        frame.render_widget(Paragraph::new(line).block(block), area);
+    }
+
+    /// Draw the list of chunks.
+    fn draw_chunks(&mut self, frame: &mut Frame, area: Rect) {
+        let items = self.listed
+            .iter()
+            .map(|&i| {
+                let summary = &self.summaries[i];
+                ListItem::new(format!("{:<12} {:>10}", summary.id, summary.population))
+            })
+            .collect::<Vec<_>>();
+        let title = if self.filter.is_empty() {
+            format!("Chunks ({})", self.listed.len())
+        } else {
+            format!("Chunks {}* ({})", self.filter, self.listed.len())
+        };
+        let list = List::new(items)
+            .block(focused_block(title, self.focus == Focus::Chunks))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.chunk_state);
+    }
+
+    /// Draw the selected chunk, and the prefixes we're looking at.
+    fn draw_prefixes(&mut self, frame: &mut Frame, area: Rect) {
+        let title = match (self.drilled.last(), self.selected_chunk()) {
+            (Some(parent), _) => format!("Zip codes starting with {}", parent),
+            (None, Some(summary)) => {
+                format!("Chunk {} (index {}), population {}",
+                        summary.id,
+                        summary.index,
+                        summary.population)
+            }
+            (None, None) => "No chunk selected".to_owned(),
+        };
+        let items = self.prefix_rows
+            .iter()
+            .map(|row| {
+                ListItem::new(Line::from(format!("{:<6} {:>10}  {}",
+                                                 row.prefix,
+                                                 row.population,
+                                                 row.chunks.join(" "))))
+            })
+            .collect::<Vec<_>>();
+        let list = List::new(items)
+            .block(focused_block(title, self.focus == Focus::Prefixes))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.prefix_state);
+    }
+}
+
+/// A bordered block with `title`, drawn in bold if it has the focus.
+fn focused_block(title: String, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(title);
+    if focused {
+        block.border_style(Style::new().add_modifier(Modifier::BOLD))
+    } else {
+        block
+    }
+}
+
+/// Explore `classifier` in a full-screen terminal interface, until the user
+/// quits. `rebuild` is used to build chunks for other target populations,
+/// using the same data and options.
+pub fn explore(classifier: Classifier,
+               rebuild: &mut dyn FnMut(u64) -> Result<Classifier>)
+               -> Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = run(&mut terminal, App::new(classifier, rebuild));
+    ratatui::try_restore()?;
+    result
+}
+
+/// Draw `app` and handle key presses until the user quits.
+fn run(terminal: &mut DefaultTerminal, mut app: App) -> Result<()> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !app.handle_key(key) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn press(app: &mut App, keys: &str) {
+    for c in keys.chars() {
+        let code = match c {
+            '\n' => KeyCode::Enter,
+            '\t' => KeyCode::Tab,
+            '<' => KeyCode::Esc,
+            c => KeyCode::Char(c),
+        };
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+}
+
+#[cfg(test)]
+fn screen(app: &mut App) -> String {
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+    terminal.draw(|frame| app.draw(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    (0..buffer.area.height)
+        .map(|y| {
+                 (0..buffer.area.width)
+                     .map(|x| buffer[(x, y)].symbol())
+                     .collect::<String>()
+             })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn browses_chunks_and_drills_into_prefixes() {
+    let _ = env_logger::init();
+    let mut rebuild = |pop| Ok(Classifier::new(pop));
+    let mut app = App::new(Classifier::new(250000), &mut rebuild);
+    assert!(screen(&mut app).contains("geochunk_zip2010_250000"));
+
+    // Find a zip code, which selects its chunk and prefix.
+    press(&mut app, "z90210\n");
+    let shown = screen(&mut app);
+    assert!(shown.contains("90210 is in chunk 902_0, found using prefix 9021"), "{}", shown);
+    assert!(shown.contains("Chunk 902_0 (index "), "{}", shown);
+    assert_eq!(app.prefix_rows[app.prefix_state.selected().unwrap()].prefix, "9021");
+
+    // Drill down into the zip codes beneath our prefix, and back up.
+    press(&mut app, "\n");
+    let shown = screen(&mut app);
+    assert!(shown.contains("Zip codes starting with 9021"), "{}", shown);
+    assert!(shown.contains("90210 "), "{}", shown);
+    press(&mut app, "<");
+    assert!(app.drilled.is_empty());
+
+    // Filter the chunk list.
+    press(&mut app, "/902\n");
+    assert!(app.listed.iter().all(|&i| app.summaries[i].id.starts_with("902")));
+    assert!(screen(&mut app).contains("Chunks 902* ("));
+
+    // Compare and switch target populations, and report mistakes.
+    press(&mut app, "c500000\n");
+    assert!(screen(&mut app).contains("target 250000 vs 500000"));
+    press(&mut app, "tlots\n");
+    assert!(screen(&mut app).contains("error: invalid target population \"lots\""));
+    press(&mut app, "t500000\n");
+    assert!(screen(&mut app).contains("geochunk_zip2010_500000"));
+    assert!(!app.handle_key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE)));
+}
@@ -0,0 +1,294 @@
+//! An Arrow Flight service which adds a geochunk column to record batches,
+//! so that Python and Java programs can chunk large data sets over the
+//! network without converting them to CSV. This requires the `flight`
+//! feature.
+//!
+//! Clients call `DoExchange`, sending record batches containing a string or
+//! integer column of zip codes, and we stream back each batch with a chunk ID
+//! column appended. Zip codes which can't be classified get a null chunk.
+//! Using `pyarrow`:
+//!
+//! ```text
+//! client = pyarrow.flight.connect("grpc://localhost:8815")
+//! descriptor = pyarrow.flight.FlightDescriptor.for_command(b"")
+//! writer, reader = client.do_exchange(descriptor)
+//! writer.begin(table.schema)
+//! writer.write_table(table)
+//! writer.done_writing()
+//! chunked = reader.read_all()
+//! ```
+
+#[cfg(test)]
+use arrow_array::cast::AsArray;
+use arrow_array::RecordBatch;
+#[cfg(test)]
+use arrow_array::{ArrayRef, StringArray};
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor,
+                   FlightInfo, HandshakeRequest, HandshakeResponse, PollInfo,
+                   PutResult, SchemaResult, Ticket};
+#[cfg(test)]
+use arrow_schema::{DataType, Field, Schema};
+#[cfg(test)]
+use env_logger;
+use futures::future::{self, BoxFuture};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use errors::*;
+use record_batch;
+use zip2010::Classifier;
+
+/// Append a chunk ID column to `batch`, classifying the zip codes in its
+/// column `column`.
+pub fn add_chunk_column(classifier: &Classifier,
+                        column: &str,
+                        batch: &RecordBatch)
+                        -> Result<RecordBatch> {
+    record_batch::add_chunk_column(classifier,
+                                   column,
+                                   &classifier.geochunk_column_name(),
+                                   batch)
+}
+
+/// Our Flight service, which only implements `DoExchange`.
+#[derive(Clone)]
+pub struct ChunkService {
+    classifier: Arc<Classifier>,
+    column: String,
+}
+
+impl ChunkService {
+    /// Create a service which classifies the zip codes in `column` using
+    /// `classifier`.
+    pub fn new(classifier: Classifier, column: &str) -> ChunkService {
+        ChunkService {
+            classifier: Arc::new(classifier),
+            column: column.to_owned(),
+        }
+    }
+}
+
+/// A stream of responses to a Flight call.
+type FlightStream<T> = BoxStream<'static, ::std::result::Result<T, Status>>;
+
+/// The future returned by each method of `FlightService`.
+type FlightFuture<'a, T> = BoxFuture<'a, ::std::result::Result<Response<T>, Status>>;
+
+/// Reject a call to a Flight method we don't support.
+fn unimplemented<'a, T: Send + 'a>(method: &str) -> FlightFuture<'a, T> {
+    let message = format!("geochunk only supports DoExchange, not {}", method);
+    Box::pin(future::err(Status::unimplemented(message)))
+}
+
+// `FlightService` is declared using `async_trait`, so we write out the
+// boxed futures it expects by hand.
+impl FlightService for ChunkService {
+    type HandshakeStream = FlightStream<HandshakeResponse>;
+    type ListFlightsStream = FlightStream<FlightInfo>;
+    type DoGetStream = FlightStream<FlightData>;
+    type DoPutStream = FlightStream<PutResult>;
+    type DoExchangeStream = FlightStream<FlightData>;
+    type DoActionStream = FlightStream<::arrow_flight::Result>;
+    type ListActionsStream = FlightStream<ActionType>;
+
+    fn handshake<'a, 'b>(&'a self,
+                         _: Request<Streaming<HandshakeRequest>>)
+                         -> FlightFuture<'b, Self::HandshakeStream>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("Handshake")
+    }
+
+    fn list_flights<'a, 'b>(&'a self,
+                            _: Request<Criteria>)
+                            -> FlightFuture<'b, Self::ListFlightsStream>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("ListFlights")
+    }
+
+    fn get_flight_info<'a, 'b>(&'a self,
+                               _: Request<FlightDescriptor>)
+                               -> FlightFuture<'b, FlightInfo>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("GetFlightInfo")
+    }
+
+    fn poll_flight_info<'a, 'b>(&'a self,
+                                _: Request<FlightDescriptor>)
+                                -> FlightFuture<'b, PollInfo>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("PollFlightInfo")
+    }
+
+    fn get_schema<'a, 'b>(&'a self,
+                          _: Request<FlightDescriptor>)
+                          -> FlightFuture<'b, SchemaResult>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("GetSchema")
+    }
+
+    fn do_get<'a, 'b>(&'a self,
+                      _: Request<Ticket>)
+                      -> FlightFuture<'b, Self::DoGetStream>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("DoGet")
+    }
+
+    fn do_put<'a, 'b>(&'a self,
+                      _: Request<Streaming<FlightData>>)
+                      -> FlightFuture<'b, Self::DoPutStream>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("DoPut")
+    }
+
+    fn do_exchange<'a, 'b>(&'a self,
+                           request: Request<Streaming<FlightData>>)
+                           -> FlightFuture<'b, Self::DoExchangeStream>
+        where 'a: 'b, Self: 'b
+    {
+        let service = self.clone();
+        let input = request.into_inner().map_err(FlightError::from);
+        let batches = FlightRecordBatchStream::new_from_flight_data(input);
+        let batches = batches.map(move |batch| {
+            add_chunk_column(&service.classifier, &service.column, &batch?)
+                .map_err(|e| Status::invalid_argument(e.to_string()).into())
+        });
+        let output = FlightDataEncoderBuilder::new()
+            .build(batches)
+            .map_err(Status::from)
+            .boxed();
+        Box::pin(future::ok(Response::new(output)))
+    }
+
+    fn do_action<'a, 'b>(&'a self,
+                         _: Request<Action>)
+                         -> FlightFuture<'b, Self::DoActionStream>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("DoAction")
+    }
+
+    fn list_actions<'a, 'b>(&'a self,
+                            _: Request<Empty>)
+                            -> FlightFuture<'b, Self::ListActionsStream>
+        where 'a: 'b, Self: 'b
+    {
+        unimplemented("ListActions")
+    }
+}
+
+/// A socket we're ready to serve Flight requests on, along with the
+/// runtime which will serve them.
+pub struct Listener {
+    runtime: Runtime,
+    incoming: TcpIncoming,
+}
+
+impl Listener {
+    /// The address we're listening on. This is useful when binding to port
+    /// 0, which picks any free port.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.incoming.local_addr()?)
+    }
+}
+
+/// Listen for Flight requests on `addr`.
+pub fn bind(addr: SocketAddr) -> Result<Listener> {
+    let runtime = Runtime::new().chain_err(|| "could not start async runtime")?;
+    let incoming = {
+        let _guard = runtime.enter();
+        TcpIncoming::bind(addr).chain_err(|| format!("could not listen on {}", addr))?
+    };
+    Ok(Listener { runtime, incoming })
+}
+
+/// Answer Flight requests on `listener` forever.
+pub fn serve(listener: Listener, service: ChunkService) -> Result<()> {
+    let server = Server::builder()
+        .add_service(FlightServiceServer::new(service))
+        .serve_with_incoming(listener.incoming);
+    listener
+        .runtime
+        .block_on(server)
+        .chain_err(|| "Flight server failed")
+}
+
+#[cfg(test)]
+fn zip_batch(zips: Vec<Option<&str>>) -> RecordBatch {
+    let schema = Schema::new(vec![Field::new("zip", DataType::Utf8, true)]);
+    let zips = Arc::new(StringArray::from(zips)) as ArrayRef;
+    RecordBatch::try_new(Arc::new(schema), vec![zips]).unwrap()
+}
+
+#[test]
+fn adds_chunk_columns() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let batch = zip_batch(vec![Some("90210"), None, Some("N/A")]);
+    let chunked = add_chunk_column(&classifier, "zip", &batch).unwrap();
+    assert_eq!(chunked.schema().field(1).name(), "geochunk_zip2010_250000");
+    let chunks = chunked.column(1).as_string::<i32>();
+    assert_eq!(chunks.iter().collect::<Vec<_>>(), vec![Some("902_0"), None, None]);
+    assert!(add_chunk_column(&classifier, "postcode", &batch).is_err());
+}
+
+#[test]
+fn exchanges_record_batches() {
+    use arrow_flight::FlightClient;
+    use std::thread;
+    use tonic::transport::Endpoint;
+
+    let _ = env_logger::init();
+    let listener = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let service = ChunkService::new(Classifier::new(250000), "zip");
+        serve(listener, service).unwrap();
+    });
+
+    let runtime = Runtime::new().unwrap();
+    let channel = runtime
+        .block_on(Endpoint::from_shared(url).unwrap().connect())
+        .unwrap();
+    let mut client = FlightClient::new(channel);
+    let batches = vec![Ok(zip_batch(vec![Some("90210")])),
+                       Ok(zip_batch(vec![Some("02120"), Some("?")]))];
+    let input = FlightDataEncoderBuilder::new()
+        .with_flight_descriptor(Some(FlightDescriptor::new_cmd(vec![])))
+        .build(futures::stream::iter(batches));
+    let output = runtime.block_on(client.do_exchange(input)).unwrap();
+    let chunked = runtime
+        .block_on(output.try_collect::<Vec<_>>())
+        .unwrap();
+    let chunks = chunked
+        .iter()
+        .flat_map(|b| b.column(1).as_string::<i32>().iter().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(chunks, vec![Some("902_0"), Some("0212_0"), None]);
+
+    let schema = Schema::new(vec![Field::new("postcode", DataType::Utf8, true)]);
+    let postcodes = Arc::new(StringArray::from(vec!["90210"])) as ArrayRef;
+    let batch = RecordBatch::try_new(Arc::new(schema), vec![postcodes]).unwrap();
+    let input = FlightDataEncoderBuilder::new()
+        .with_flight_descriptor(Some(FlightDescriptor::new_cmd(vec![])))
+        .build(futures::stream::iter(vec![Ok(batch)]));
+    let result = runtime
+        .block_on(client.do_exchange(input))
+        .and_then(|output| runtime.block_on(output.try_collect::<Vec<_>>()));
+    assert!(result.is_err());
+}
@@ -0,0 +1,192 @@
+//! Writing our reports as JSON or YAML, for orchestration code which wants
+//! to read them without parsing CSV.
+//!
+//! We write YAML ourselves, using double-quoted strings with JSON escapes,
+//! which YAML also accepts. This keeps our output predictable: every string
+//! is quoted, so chunk IDs like `010_0` are never mistaken for numbers.
+//! Mapping keys are written in sorted order.
+
+#[cfg(test)]
+use env_logger;
+use serde::Serialize;
+use serde_json::{self, Value};
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use errors::*;
+
+/// How should we format a report?
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A CSV file.
+    #[default]
+    Csv,
+    /// A pretty-printed JSON document.
+    Json,
+    /// A YAML document.
+    Yaml,
+    /// A stream of length-delimited protobuf messages. Like CSV, this
+    /// depends on the shape of each report. See `proto`.
+    Proto,
+    /// An ORC file, for `csv` and `copartition`. This needs the `orc`
+    /// feature. See `orc`.
+    Orc,
+    /// A Parquet file, for `copartition`. This needs the `parquet`
+    /// feature. See `parquet_files`.
+    Parquet,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "proto" => Ok(OutputFormat::Proto),
+            "orc" => Ok(OutputFormat::Orc),
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(format!("unknown output format {:?}", s).into()),
+        }
+    }
+}
+
+/// Write `value` to `out` as JSON or YAML. CSV and protobuf output depend
+/// on the shape of each report, so callers must write them themselves.
+pub fn write_structured<T>(format: OutputFormat,
+                           value: &T,
+                           out: &mut dyn Write)
+                           -> Result<()>
+where
+    T: Serialize,
+{
+    match format {
+        OutputFormat::Csv => Err("cannot write this value as CSV".into()),
+        OutputFormat::Proto => Err("cannot write this value as protobuf".into()),
+        OutputFormat::Orc => Err("cannot write this value as ORC".into()),
+        OutputFormat::Parquet => Err("cannot write this value as Parquet".into()),
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, value)
+                .chain_err(|| "could not write JSON")?;
+            writeln!(out)?;
+            Ok(())
+        }
+        OutputFormat::Yaml => {
+            let mut yaml = String::new();
+            let value = serde_json::to_value(value).chain_err(|| "could not write YAML")?;
+            write_yaml(&value, 0, &mut yaml);
+            Ok(out.write_all(yaml.as_bytes())?)
+        }
+    }
+}
+
+/// Append `value` to `yaml` as a block, indented by `indent` spaces.
+fn write_yaml(value: &Value, indent: usize, yaml: &mut String) {
+    let pad = " ".repeat(indent);
+    match *value {
+        Value::Object(ref map) if !map.is_empty() => {
+            for (key, value) in map {
+                yaml.push_str(&pad);
+                yaml.push_str(&yaml_key(key));
+                yaml.push(':');
+                write_yaml_child(value, indent, yaml);
+            }
+        }
+        Value::Array(ref items) if items.iter().any(is_collection) => {
+            for item in items {
+                // Write each item indented as if it were a mapping value, and
+                // then replace the start of its first line with a dash.
+                let mut item_yaml = String::new();
+                write_yaml(item, indent + 2, &mut item_yaml);
+                yaml.push_str(&pad);
+                yaml.push_str("- ");
+                yaml.push_str(&item_yaml[indent + 2..]);
+            }
+        }
+        _ => {
+            yaml.push_str(&pad);
+            yaml.push_str(&yaml_flow(value));
+            yaml.push('\n');
+        }
+    }
+}
+
+/// Append `value` to `yaml` after a `key:` on the current line.
+fn write_yaml_child(value: &Value, indent: usize, yaml: &mut String) {
+    if is_collection(value) {
+        yaml.push('\n');
+        let child_indent = match *value {
+            Value::Array(_) => indent,
+            _ => indent + 2,
+        };
+        write_yaml(value, child_indent, yaml);
+    } else {
+        yaml.push(' ');
+        yaml.push_str(&yaml_flow(value));
+        yaml.push('\n');
+    }
+}
+
+/// Does `value` need to be written as a block, instead of on one line?
+fn is_collection(value: &Value) -> bool {
+    match *value {
+        Value::Object(ref map) => !map.is_empty(),
+        Value::Array(ref items) => items.iter().any(is_collection),
+        _ => false,
+    }
+}
+
+/// Format a scalar, or a collection of scalars, on a single line. JSON
+/// syntax is valid YAML flow syntax.
+fn yaml_flow(value: &Value) -> String {
+    match *value {
+        Value::Array(ref items) => {
+            let items = items.iter().map(yaml_flow).collect::<Vec<_>>();
+            format!("[{}]", items.join(", "))
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Format a mapping key, quoting it unless it's a plain identifier.
+fn yaml_key(key: &str) -> String {
+    let plain = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) &&
+                key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        key.to_owned()
+    } else {
+        Value::String(key.to_owned()).to_string()
+    }
+}
+
+#[test]
+fn writes_yaml() {
+    let _ = env_logger::init();
+    let value: Value = serde_json::from_str(r#"{
+        "column": "geochunk_zip2010_250000",
+        "empty": {},
+        "chunks": [
+            {"id": "010_0", "population": 210857, "prefixes": ["0100", "0101"]},
+            {"id": "010_1", "population": 0, "prefixes": [], "adjusted": null}
+        ],
+        "counts": {"010_0": 2}
+    }"#)
+            .unwrap();
+    let mut out = vec![];
+    write_structured(OutputFormat::Yaml, &value, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+               "chunks:\n\
+                - id: \"010_0\"\n  population: 210857\n  prefixes: [\"0100\", \"0101\"]\n\
+                - adjusted: null\n  id: \"010_1\"\n  population: 0\n  prefixes: []\n\
+                column: \"geochunk_zip2010_250000\"\n\
+                counts:\n  \"010_0\": 2\n\
+                empty: {}\n");
+}
+
+#[test]
+fn parses_output_formats() {
+    let _ = env_logger::init();
+    assert_eq!("yaml".parse::<OutputFormat>().unwrap(), OutputFormat::Yaml);
+    assert!("xml".parse::<OutputFormat>().is_err());
+}
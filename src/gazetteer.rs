@@ -0,0 +1,162 @@
+//! Support for the Census Gazetteer ZCTA files, which contain the land area
+//! and an internal point of each zip code tabulation area.
+//!
+//! These files are available from
+//! https://www.census.gov/geographies/reference-files/time-series/geo/gazetteer-files.html
+//! and we read them at runtime instead of building them into our executable.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use serde::de::DeserializeOwned;
+use std::io::prelude::*;
+
+use errors::*;
+
+/// The number of square meters in a square mile.
+pub const SQUARE_METERS_PER_SQUARE_MILE: f64 = 2_589_988.110336;
+
+/// Information about a single zip code tabulation area.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zcta {
+    /// The 5-digit zip code.
+    pub zip: String,
+    /// The land area, in square meters.
+    pub land_area: u64,
+}
+
+impl Zcta {
+    /// The land area, in square miles.
+    pub fn land_area_sq_mi(&self) -> f64 {
+        self.land_area as f64 / SQUARE_METERS_PER_SQUARE_MILE
+    }
+}
+
+/// The internal point of a zip code tabulation area, which the Census
+/// Bureau places inside the ZCTA, near its center.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZctaPoint {
+    /// The 5-digit zip code.
+    pub zip: String,
+    /// The latitude of the internal point, in degrees.
+    pub lat: f64,
+    /// The longitude of the internal point, in degrees.
+    pub lng: f64,
+    /// The total land and water area, in square meters.
+    pub area: u64,
+}
+
+/// A row of a Gazetteer ZCTA file, with just the columns `load_zctas`
+/// needs.
+#[derive(Debug, Deserialize)]
+struct ZctaRow {
+    #[serde(rename = "GEOID")]
+    zip: String,
+    #[serde(rename = "ALAND")]
+    land_area: u64,
+}
+
+/// A row of a Gazetteer ZCTA file, with the columns `load_zcta_points`
+/// needs.
+#[derive(Debug, Deserialize)]
+struct ZctaPointRow {
+    #[serde(rename = "GEOID")]
+    zip: String,
+    #[serde(rename = "ALAND")]
+    land_area: u64,
+    #[serde(rename = "AWATER")]
+    water_area: u64,
+    #[serde(rename = "INTPTLAT")]
+    lat: f64,
+    #[serde(rename = "INTPTLONG")]
+    lng: f64,
+}
+
+/// Read a tab-separated Gazetteer ZCTA file.
+pub fn load_zctas(input: &mut dyn Read) -> Result<Vec<Zcta>> {
+    load_rows(input, |row: ZctaRow, pos| {
+        Ok(Zcta {
+               zip: check_zip(row.zip, pos)?,
+               land_area: row.land_area,
+           })
+    })
+}
+
+/// Read the internal point of each ZCTA from a tab-separated Gazetteer ZCTA
+/// file.
+pub fn load_zcta_points(input: &mut dyn Read) -> Result<Vec<ZctaPoint>> {
+    load_rows(input, |row: ZctaPointRow, pos| {
+        Ok(ZctaPoint {
+               zip: check_zip(row.zip, pos)?,
+               lat: row.lat,
+               lng: row.lng,
+               area: row.land_area + row.water_area,
+           })
+    })
+}
+
+/// Deserialize each row of a tab-separated Gazetteer file, and convert it
+/// using `convert`, which is passed the row's position for error messages.
+/// Some vintages of these files pad their headers with whitespace, so we
+/// trim everything before matching up columns.
+fn load_rows<R, T, F>(input: &mut dyn Read, mut convert: F) -> Result<Vec<T>>
+where
+    R: DeserializeOwned,
+    F: FnMut(R, Option<&csv::Position>) -> Result<T>,
+{
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    let headers = rdr.headers()?.to_owned();
+    let mut record = csv::StringRecord::new();
+    let mut rows = vec![];
+    while rdr.read_record(&mut record)? {
+        let row = record.deserialize(Some(&headers))?;
+        rows.push(convert(row, record.position())?);
+    }
+    Ok(rows)
+}
+
+/// Make sure `zip` is a 5-digit zip code.
+fn check_zip(zip: String, pos: Option<&csv::Position>) -> Result<String> {
+    if zip.len() != 5 || !zip.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::invalid_weight(zip, pos));
+    }
+    Ok(zip)
+}
+
+#[test]
+fn loads_land_areas_from_gazetteer_file() {
+    let _ = env_logger::init();
+    let input = "\
+GEOID\tPOP10\tALAND\tAWATER\tALAND_SQMI\tINTPTLAT\tINTPTLONG               
+00601\t18570\t166659789\t799296\t64.348\t18.180555\t-66.749961
+10001\t21102\t1640604\t0\t0.633\t40.750633\t-73.997177
+";
+    let zctas = load_zctas(&mut input.as_bytes()).unwrap();
+    assert_eq!(zctas.len(), 2);
+    assert_eq!(zctas[1],
+               Zcta {
+                   zip: "10001".to_owned(),
+                   land_area: 1640604,
+               });
+    assert!((zctas[0].land_area_sq_mi() - 64.348).abs() < 0.001);
+
+    let points = load_zcta_points(&mut input.as_bytes()).unwrap();
+    assert_eq!(points[1],
+               ZctaPoint {
+                   zip: "10001".to_owned(),
+                   lat: 40.750633,
+                   lng: -73.997177,
+                   area: 1640604,
+               });
+    assert_eq!(points[0].area, 166659789 + 799296);
+    assert!(load_zcta_points(&mut "GEOID\tALAND\n10001\t1640604\n".as_bytes()).is_err());
+
+    // Errors tell us where the bad row is.
+    let err = load_zctas(&mut "GEOID\tALAND\n10001\tlots\n".as_bytes()).unwrap_err();
+    assert!(err.to_string().contains("line: 2"), "{}", err);
+    let err = load_zctas(&mut "GEOID\tALAND\n1001\t1\n".as_bytes()).unwrap_err();
+    assert!(err.to_string().contains("line 2"), "{}", err);
+}
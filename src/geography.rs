@@ -0,0 +1,426 @@
+//! Custom geographies built from your own polygons, such as sales regions
+//! or delivery zones, instead of zip code prefixes. We group neighboring
+//! polygons into chunks of similar weight, and find the chunk of a point by
+//! checking which polygon contains it.
+//!
+//! Polygons are read from shapefiles, GeoPackages or GeoJSON files, and
+//! must use longitude and latitude coordinates, such as WGS 84. A built
+//! geography is saved as a JSON file which can be loaded again with
+//! `Geography::load`.
+
+#[cfg(test)]
+use env_logger;
+use serde_json::{self, Value};
+use std::io::prelude::*;
+
+use errors::*;
+use number::{self, Rounding};
+use shapefile::Shape;
+
+/// A closed ring of `(longitude, latitude)` points.
+pub type Ring = Vec<(f64, f64)>;
+
+/// The version of our geography file format.
+const FORMAT_VERSION: u32 = 1;
+
+/// One of the polygons making up a geography.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    /// The region's ID, from the input file.
+    pub id: String,
+    /// The region's weight, such as its population.
+    pub weight: u64,
+    /// The ID of the chunk containing this region.
+    pub chunk: String,
+    /// The region's rings. A point is inside the region if it's inside an
+    /// odd number of rings, which handles holes without needing to know
+    /// which rings are holes.
+    pub rings: Vec<Ring>,
+}
+
+impl Region {
+    /// The bounding box of our rings, as `(min_lng, min_lat, max_lng,
+    /// max_lat)`.
+    fn bbox(&self) -> (f64, f64, f64, f64) {
+        rings_bbox(&self.rings)
+    }
+
+    /// Is `(lng, lat)` inside this region?
+    fn contains(&self, lng: f64, lat: f64) -> bool {
+        rings_contain(&self.rings, lng, lat)
+    }
+}
+
+/// The bounding box of `rings`, as `(min_lng, min_lat, max_lng, max_lat)`.
+pub fn rings_bbox(rings: &[Ring]) -> (f64, f64, f64, f64) {
+    let mut bbox = (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for &(lng, lat) in rings.iter().flatten() {
+        bbox = (bbox.0.min(lng), bbox.1.min(lat), bbox.2.max(lng), bbox.3.max(lat));
+    }
+    bbox
+}
+
+/// Is `(lng, lat)` inside an odd number of `rings`? This is how we treat
+/// holes without needing to know which rings are holes.
+pub fn rings_contain(rings: &[Ring], lng: f64, lat: f64) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        for (i, &(x1, y1)) in ring.iter().enumerate() {
+            let (x2, y2) = ring[(i + 1) % ring.len()];
+            if (y1 > lat) != (y2 > lat) && lng < x1 + (lat - y1) * (x2 - x1) / (y2 - y1) {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// Something which assigns points to chunks, such as a `Geography` or an
+/// `S2Classifier`.
+pub trait GeoClassifier {
+    /// A name for the chunks, for use in column names, such as
+    /// `regions_500000`.
+    fn name(&self) -> String;
+
+    /// What we call the areas which make up each chunk, such as `region`.
+    fn area_kind(&self) -> &'static str;
+
+    /// Find the chunk containing `(lat, lng)`, and the ID of the area
+    /// within it.
+    fn locate(&self, lat: f64, lng: f64) -> Option<(&str, String)>;
+}
+
+/// A set of regions grouped into chunks.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Geography {
+    /// The version of the format this was saved with.
+    format_version: u32,
+    /// The name of this geography, for use in column names.
+    pub name: String,
+    /// The approximate weight we want in each chunk.
+    pub target_population: u64,
+    /// Our regions, in chunk order.
+    regions: Vec<Region>,
+    /// The bounding box of each region, which we compute when loading.
+    #[serde(skip)]
+    bboxes: Vec<(f64, f64, f64, f64)>,
+}
+
+impl Geography {
+    /// Group `shapes` into chunks of about `target_population`, weighting
+    /// each by its `weight_field` attribute. Regions are identified by
+    /// their `id_field` attribute, or by their position in the input
+    /// starting from 1.
+    pub fn build(name: &str,
+                 shapes: Vec<Shape>,
+                 weight_field: &str,
+                 id_field: Option<&str>,
+                 target_population: u64,
+                 rounding: Rounding)
+                 -> Result<Geography> {
+        if shapes.is_empty() {
+            return Err("no shapes to build a geography from".into());
+        }
+        let mut regions = vec![];
+        for (i, shape) in shapes.into_iter().enumerate() {
+            let weight = shape
+                .attributes
+                .get(weight_field)
+                .ok_or_else(|| Error::no_such_column(weight_field))?;
+            let weight = number::parse_weight(weight, rounding)
+                .ok_or_else(|| format!("shape {} has an invalid weight {:?}", i + 1, weight))?;
+            let id = match id_field {
+                Some(field) => {
+                    shape
+                        .attributes
+                        .get(field)
+                        .ok_or_else(|| Error::no_such_column(field))?
+                        .clone()
+                }
+                None => (i + 1).to_string(),
+            };
+            let in_range = shape
+                .rings
+                .iter()
+                .flatten()
+                .all(|&(lng, lat)| lng.abs() <= 180.0 && lat.abs() <= 90.0);
+            if !in_range {
+                return Err(format!("region {} has coordinates which aren't a longitude and \
+                                    latitude, so it may need to be reprojected to WGS 84",
+                                   id)
+                                   .into());
+            }
+            regions.push(Region {
+                             id,
+                             weight,
+                             chunk: String::new(),
+                             rings: shape.rings,
+                         });
+        }
+
+        // Put neighboring regions next to each other by sorting them along a
+        // Z-order curve through the centers of their bounding boxes, then
+        // fill chunks in that order, the way we fill chunks with zip code
+        // prefixes.
+        regions.sort_by_cached_key(|region| {
+                                       let (x1, y1, x2, y2) = region.bbox();
+                                       morton_code((x1 + x2) / 2.0, (y1 + y2) / 2.0)
+                                   });
+        let mut chunk_idx = 0;
+        let mut chunk_weight = 0;
+        for region in &mut regions {
+            if chunk_weight > 0 && chunk_weight + region.weight > target_population {
+                chunk_idx += 1;
+                chunk_weight = 0;
+            }
+            chunk_weight += region.weight;
+            region.chunk = chunk_idx.to_string();
+        }
+        let mut geography = Geography {
+            format_version: FORMAT_VERSION,
+            name: name.to_owned(),
+            target_population,
+            regions,
+            bboxes: vec![],
+        };
+        geography.compute_bboxes();
+        Ok(geography)
+    }
+
+    /// Load a geography saved by `write`.
+    pub fn load(input: &mut dyn Read) -> Result<Geography> {
+        let mut geography: Geography =
+            serde_json::from_reader(input).chain_err(|| "could not parse geography file")?;
+        if geography.format_version != FORMAT_VERSION {
+            return Err(format!("geography file has format version {}, but we only \
+                                support version {}",
+                               geography.format_version,
+                               FORMAT_VERSION)
+                               .into());
+        }
+        geography.compute_bboxes();
+        Ok(geography)
+    }
+
+    /// Save this geography as JSON.
+    pub fn write(&self, output: &mut dyn Write) -> Result<()> {
+        serde_json::to_writer(&mut *output, self).chain_err(|| "could not write geography")?;
+        writeln!(output)?;
+        Ok(())
+    }
+
+    /// Our regions, in chunk order.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// The number of chunks in this geography.
+    pub fn chunk_count(&self) -> usize {
+        self.regions
+            .last()
+            .map_or(0, |region| region.chunk.parse::<usize>().unwrap_or(0) + 1)
+    }
+
+    /// Find the region containing `(lat, lng)`. If regions overlap, we
+    /// return the first one in chunk order.
+    pub fn region_for_point(&self, lat: f64, lng: f64) -> Option<&Region> {
+        self.regions
+            .iter()
+            .zip(&self.bboxes)
+            .find(|&(region, &(x1, y1, x2, y2))| {
+                      x1 <= lng && lng <= x2 && y1 <= lat && lat <= y2 &&
+                      region.contains(lng, lat)
+                  })
+            .map(|(region, _)| region)
+    }
+
+    /// Find the chunk containing `(lat, lng)`.
+    pub fn chunk_for_point(&self, lat: f64, lng: f64) -> Option<&str> {
+        self.region_for_point(lat, lng).map(|region| &region.chunk[..])
+    }
+
+    /// Fill in `bboxes`.
+    fn compute_bboxes(&mut self) {
+        self.bboxes = self.regions.iter().map(|r| r.bbox()).collect();
+    }
+}
+
+impl GeoClassifier for Geography {
+    fn name(&self) -> String {
+        format!("{}_{}", self.name, self.target_population)
+    }
+
+    fn area_kind(&self) -> &'static str {
+        "region"
+    }
+
+    fn locate(&self, lat: f64, lng: f64) -> Option<(&str, String)> {
+        self.region_for_point(lat, lng)
+            .map(|region| (&region.chunk[..], region.id.clone()))
+    }
+}
+
+/// Read the polygons and multipolygons from a GeoJSON feature collection,
+/// with their properties. Other kinds of geometry are an error.
+pub fn load_geojson(input: &mut dyn Read) -> Result<Vec<Shape>> {
+    let json: Value = serde_json::from_reader(input).chain_err(|| "could not parse GeoJSON")?;
+    let features = json.get("features")
+        .and_then(|f| f.as_array())
+        .ok_or("GeoJSON file should contain a FeatureCollection")?;
+    let mut shapes = vec![];
+    for (i, feature) in features.iter().enumerate() {
+        let geometry = &feature["geometry"];
+        let polygons = match geometry["type"].as_str() {
+            Some("Polygon") => vec![&geometry["coordinates"]],
+            Some("MultiPolygon") => {
+                geometry["coordinates"]
+                    .as_array()
+                    .map(|polygons| polygons.iter().collect())
+                    .unwrap_or_default()
+            }
+            other => {
+                return Err(format!("feature {} has geometry {:?}, expected polygons",
+                                   i + 1,
+                                   other.unwrap_or("null"))
+                                   .into())
+            }
+        };
+        let mut rings = vec![];
+        for polygon in polygons {
+            for ring in polygon.as_array().into_iter().flatten() {
+                let ring = ring.as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|point| match (point[0].as_f64(), point[1].as_f64()) {
+                             (Some(lng), Some(lat)) => Ok((lng, lat)),
+                             _ => Err(format!("feature {} has an invalid point", i + 1)),
+                         })
+                    .collect::<::std::result::Result<Ring, _>>()?;
+                rings.push(ring);
+            }
+        }
+        let attributes = feature["properties"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| {
+                     let value = match *value {
+                         Value::String(ref s) => s.clone(),
+                         Value::Null => String::new(),
+                         ref other => other.to_string(),
+                     };
+                     (key.clone(), value)
+                 })
+            .collect();
+        shapes.push(Shape { rings, attributes });
+    }
+    Ok(shapes)
+}
+
+/// Interleave the bits of a longitude and latitude, scaled to 16 bits
+/// each, so that nearby points usually have nearby codes.
+fn morton_code(lng: f64, lat: f64) -> u32 {
+    let scale = |value: f64, range: f64| ((value + range) / (2.0 * range) * 65535.0) as u32;
+    let (x, y) = (scale(lng, 180.0), scale(lat, 90.0));
+    (0..16).fold(0, |code, bit| {
+        code | ((x >> bit) & 1) << (2 * bit) | ((y >> bit) & 1) << (2 * bit + 1)
+    })
+}
+
+#[cfg(test)]
+fn test_shapes() -> Vec<Shape> {
+    // Four unit squares in a row from west to east, listed out of order.
+    let square = |name: &str, west: f64, pop: u64| {
+        let rings = vec![vec![(west, 40.0),
+                              (west, 41.0),
+                              (west + 1.0, 41.0),
+                              (west + 1.0, 40.0),
+                              (west, 40.0)]];
+        let attributes = vec![("name".to_owned(), name.to_owned()),
+                              ("pop".to_owned(), pop.to_string())];
+        Shape {
+            rings,
+            attributes: attributes.into_iter().collect(),
+        }
+    };
+    vec![square("c", -98.0, 100),
+         square("a", -100.0, 100),
+         square("d", -97.0, 300),
+         square("b", -99.0, 100)]
+}
+
+#[test]
+fn builds_geographies() {
+    let _ = env_logger::init();
+    let geography =
+        Geography::build("regions", test_shapes(), "pop", Some("name"), 250, Rounding::default())
+            .unwrap();
+    let ids = geography
+        .regions()
+        .iter()
+        .map(|r| (&r.id[..], &r.chunk[..]))
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![("a", "0"), ("b", "0"), ("c", "1"), ("d", "2")]);
+    assert_eq!(geography.chunk_count(), 3);
+    assert_eq!(geography.chunk_for_point(40.5, -99.5), Some("0"));
+    assert_eq!(geography.region_for_point(40.5, -96.5).unwrap().id, "d");
+    assert_eq!(geography.chunk_for_point(42.0, -99.5), None);
+    assert_eq!(geography.name(), "regions_250");
+    assert_eq!(geography.locate(40.5, -96.5), Some(("2", "d".to_owned())));
+
+    let mut saved = vec![];
+    geography.write(&mut saved).unwrap();
+    let loaded = Geography::load(&mut &saved[..]).unwrap();
+    assert_eq!(loaded, geography);
+    assert_eq!(loaded.chunk_for_point(40.5, -97.5), Some("1"));
+
+    let unnamed = Geography::build("regions", test_shapes(), "pop", None, 250, Rounding::default())
+        .unwrap();
+    assert_eq!(unnamed.regions()[0].id, "2");
+    assert!(Geography::build("regions", test_shapes(), "people", None, 250, Rounding::default())
+                .is_err());
+    assert!(Geography::build("regions", vec![], "pop", None, 250, Rounding::default()).is_err());
+}
+
+#[test]
+fn respects_holes() {
+    let _ = env_logger::init();
+    let region = Region {
+        id: "donut".to_owned(),
+        weight: 1,
+        chunk: "0".to_owned(),
+        rings: vec![vec![(0.0, 0.0), (0.0, 3.0), (3.0, 3.0), (3.0, 0.0), (0.0, 0.0)],
+                    vec![(1.0, 1.0), (2.0, 1.0), (2.0, 2.0), (1.0, 2.0), (1.0, 1.0)]],
+    };
+    assert!(region.contains(0.5, 0.5));
+    assert!(!region.contains(1.5, 1.5));
+    assert!(!region.contains(3.5, 1.5));
+}
+
+#[test]
+fn loads_geojson() {
+    let _ = env_logger::init();
+    let input = r#"{
+        "type": "FeatureCollection",
+        "features": [
+            {"type": "Feature", "properties": {"zone": "north", "pop": 1200.4},
+             "geometry": {"type": "Polygon",
+                          "coordinates": [[[0, 0], [0, 1], [1, 1], [0, 0]]]}},
+            {"type": "Feature", "properties": {"zone": "south", "pop": null},
+             "geometry": {"type": "MultiPolygon",
+                          "coordinates": [[[[0, 0], [0, -1], [1, -1], [0, 0]]],
+                                          [[[5, 5], [5, 6], [6, 6], [5, 5]]]]}}
+        ]
+    }"#;
+    let shapes = load_geojson(&mut input.as_bytes()).unwrap();
+    assert_eq!(shapes.len(), 2);
+    assert_eq!(shapes[0].attributes["zone"], "north");
+    assert_eq!(shapes[0].attributes["pop"], "1200.4");
+    assert_eq!(shapes[1].attributes["pop"], "");
+    assert_eq!(shapes[1].rings.len(), 2);
+    assert_eq!(shapes[1].rings[1][2], (6.0, 6.0));
+
+    let point = r#"{"features": [{"geometry": {"type": "Point", "coordinates": [0, 0]}}]}"#;
+    assert!(load_geojson(&mut point.as_bytes()).is_err());
+    assert!(load_geojson(&mut "[]".as_bytes()).is_err());
+}
@@ -0,0 +1,651 @@
+//! A minimal reader for GeoPackage files, which is just enough to read the
+//! polygons and attributes used by `geography`. A GeoPackage is an SQLite
+//! database, so we read just enough of SQLite's file format to walk the
+//! rows of a table: see https://www.sqlite.org/fileformat.html and
+//! https://www.geopackage.org/spec/ for details.
+
+#[cfg(test)]
+use env_logger;
+use std::io::prelude::*;
+
+use errors::*;
+use geography::Ring;
+use shapefile::Shape;
+
+/// The header at the start of every SQLite database.
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+
+/// The SQLite b-tree page types which hold table rows.
+const INTERIOR_TABLE_PAGE: u8 = 0x05;
+const LEAF_TABLE_PAGE: u8 = 0x0d;
+
+/// The WKB geometry types we read, which are all polygons. GeoPackage uses
+/// ISO WKB, which adds 1000 to the type for `Z`, 2000 for `M` and 3000 for
+/// `ZM` geometries.
+const WKB_POLYGON: u32 = 3;
+const WKB_MULTIPOLYGON: u32 = 6;
+
+/// A value stored in an SQLite row.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    /// Convert this value to text, the way we store attributes.
+    fn to_attribute(&self) -> String {
+        match *self {
+            Value::Null | Value::Blob(_) => String::new(),
+            Value::Integer(i) => i.to_string(),
+            Value::Real(f) => f.to_string(),
+            Value::Text(ref s) => s.trim().to_owned(),
+        }
+    }
+}
+
+/// An SQLite database read into memory.
+struct Database {
+    /// The whole database file.
+    data: Vec<u8>,
+    /// The size of each page.
+    page_size: usize,
+    /// The number of bytes of each page we can use, leaving out space
+    /// reserved for extensions.
+    usable_size: usize,
+}
+
+/// An SQLite table, with its column names and rows.
+struct Table {
+    /// The names of our columns, in order.
+    columns: Vec<String>,
+    /// Our rows, with one value per column.
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    /// The index of the column `name`.
+    fn column(&self, name: &str) -> Result<usize> {
+        self.columns
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::no_such_column(name))
+    }
+}
+
+/// Read the polygons in the feature table of a GeoPackage, and the other
+/// columns of each row as attributes.
+pub fn read_geopackage(input: &mut dyn Read) -> Result<Vec<Shape>> {
+    let mut data = vec![];
+    input.read_to_end(&mut data)?;
+    let db = Database::new(data)?;
+
+    // Find the table containing our features, and its geometry column.
+    let geometry_columns = db.table("gpkg_geometry_columns")?;
+    let table_idx = geometry_columns.column("table_name")?;
+    let column_idx = geometry_columns.column("column_name")?;
+    let (table_name, geometry_column) = match geometry_columns.rows.len() {
+        1 => {
+            let row = &geometry_columns.rows[0];
+            (row[table_idx].to_attribute(), row[column_idx].to_attribute())
+        }
+        0 => return Err("GeoPackage doesn't contain any features".into()),
+        _ => {
+            let names = geometry_columns.rows
+                .iter()
+                .map(|row| row[table_idx].to_attribute())
+                .collect::<Vec<_>>();
+            return Err(format!("GeoPackage contains several feature tables ({}), but we \
+                                can only read one",
+                               names.join(", "))
+                               .into());
+        }
+    };
+
+    let features = db.table(&table_name)?;
+    let geometry_idx = features.column(&geometry_column)?;
+    let mut shapes = vec![];
+    for row in &features.rows {
+        let rings = match row[geometry_idx] {
+            Value::Blob(ref blob) => read_gpb(blob)?,
+            Value::Null => vec![],
+            _ => return Err(format!("{} contains a value which isn't a geometry",
+                                    geometry_column)
+                                    .into()),
+        };
+        let attributes = features.columns
+            .iter()
+            .zip(row)
+            .enumerate()
+            .filter(|&(i, _)| i != geometry_idx)
+            .map(|(_, (name, value))| (name.clone(), value.to_attribute()))
+            .collect();
+        shapes.push(Shape { rings, attributes });
+    }
+    Ok(shapes)
+}
+
+impl Database {
+    /// Check the header of an SQLite database.
+    fn new(data: Vec<u8>) -> Result<Database> {
+        if data.len() < 100 || !data.starts_with(SQLITE_MAGIC) {
+            return Err("not a GeoPackage file".into());
+        }
+        let page_size = match be_u16(&data, 16) {
+            1 => 65536,
+            size => size as usize,
+        };
+        let reserved = data[20] as usize;
+        // A text encoding of 2 or 3 means UTF-16.
+        if be_u32(&data, 56) > 1 {
+            return Err("GeoPackage text should be UTF-8".into());
+        }
+        if page_size < 512 || reserved >= page_size - 480 {
+            return Err("invalid GeoPackage header".into());
+        }
+        Ok(Database {
+               data,
+               page_size,
+               usable_size: page_size - reserved,
+           })
+    }
+
+    /// Find the table `name` in `sqlite_master`, which is always on page 1,
+    /// and read it.
+    fn table(&self, name: &str) -> Result<Table> {
+        let mut master = vec![];
+        self.read_rows(1, &mut master, 0)?;
+        for (_, row) in master {
+            let field = |i: usize| row.get(i).map(|v| v.to_attribute()).unwrap_or_default();
+            if field(0) != "table" || !field(1).eq_ignore_ascii_case(name) {
+                continue;
+            }
+            let root_page = match row.get(3) {
+                Some(&Value::Integer(page)) => page as usize,
+                _ => return Err(format!("invalid root page for table {}", name).into()),
+            };
+            let (columns, rowid_column) = parse_columns(&field(4))?;
+            let mut rows = vec![];
+            self.read_rows(root_page, &mut rows, 0)?;
+            let rows = rows.into_iter()
+                .map(|(rowid, mut values)| {
+                         // Columns added by `ALTER TABLE` may be missing from
+                         // older rows.
+                         values.resize(columns.len(), Value::Null);
+                         // An `INTEGER PRIMARY KEY` is stored as the rowid.
+                         if let Some(i) = rowid_column {
+                             values[i] = Value::Integer(rowid);
+                         }
+                         values
+                     })
+                .collect();
+            return Ok(Table { columns, rows });
+        }
+        Err(format!("GeoPackage has no table {}", name).into())
+    }
+
+    /// The contents of page `number`, which counts from 1.
+    fn page(&self, number: usize) -> Result<&[u8]> {
+        let start = number.checked_sub(1).ok_or("invalid GeoPackage page number")? *
+                    self.page_size;
+        self.data
+            .get(start..start + self.usable_size)
+            .ok_or_else(|| "truncated GeoPackage file".into())
+    }
+
+    /// Append the rowid and values of each row in the table b-tree rooted
+    /// at `page_number` to `rows`.
+    fn read_rows(&self,
+                 page_number: usize,
+                 rows: &mut Vec<(i64, Vec<Value>)>,
+                 depth: usize)
+                 -> Result<()> {
+        // Real b-trees are never this deep, so we must be in a loop.
+        if depth > 64 {
+            return Err("GeoPackage table is corrupt".into());
+        }
+        let page = self.page(page_number)?;
+        // The first page starts with the database header.
+        let header = if page_number == 1 { 100 } else { 0 };
+        let kind = *page.get(header).ok_or("truncated GeoPackage page")?;
+        let num_cells = be_u16(page, header + 3) as usize;
+        let cells_start = header + if kind == INTERIOR_TABLE_PAGE { 12 } else { 8 };
+        if cells_start + 2 * num_cells > page.len() {
+            return Err("GeoPackage table is corrupt".into());
+        }
+        for i in 0..num_cells {
+            let cell = be_u16(page, cells_start + 2 * i) as usize;
+            if cell + 4 > page.len() {
+                return Err("GeoPackage table is corrupt".into());
+            }
+            match kind {
+                INTERIOR_TABLE_PAGE => {
+                    self.read_rows(be_u32(page, cell) as usize, rows, depth + 1)?;
+                }
+                LEAF_TABLE_PAGE => {
+                    let (payload_len, len1) = read_varint(&page[cell..])?;
+                    let (rowid, len2) = read_varint(&page[cell + len1..])?;
+                    let payload = self.payload(page, cell + len1 + len2, payload_len as usize)?;
+                    rows.push((rowid as i64, read_record(&payload)?));
+                }
+                _ => return Err("GeoPackage table is corrupt".into()),
+            }
+        }
+        if kind == INTERIOR_TABLE_PAGE {
+            self.read_rows(be_u32(page, header + 8) as usize, rows, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Read a cell's payload of `len` bytes, starting at `start` of `page`
+    /// and continuing onto overflow pages if it doesn't fit.
+    fn payload(&self, page: &[u8], start: usize, len: usize) -> Result<Vec<u8>> {
+        let usable = self.usable_size;
+        let max_local = usable - 35;
+        let local = if len <= max_local {
+            len
+        } else {
+            let min_local = (usable - 12) * 32 / 255 - 23;
+            let local = min_local + (len - min_local) % (usable - 4);
+            if local <= max_local { local } else { min_local }
+        };
+        let mut payload = page.get(start..start + local)
+            .ok_or("truncated GeoPackage cell")?
+            .to_owned();
+        if local < len {
+            let mut next = be_u32(page.get(start + local..start + local + 4)
+                                      .ok_or("truncated GeoPackage cell")?,
+                                  0) as usize;
+            while payload.len() < len {
+                let overflow = self.page(next)?;
+                let wanted = (len - payload.len()).min(usable - 4);
+                payload.extend_from_slice(&overflow[4..4 + wanted]);
+                next = be_u32(overflow, 0) as usize;
+            }
+        }
+        Ok(payload)
+    }
+}
+
+/// Read an SQLite record, which is a list of column types followed by the
+/// values themselves.
+fn read_record(record: &[u8]) -> Result<Vec<Value>> {
+    let (header_len, mut at) = read_varint(record)?;
+    let header_len = header_len as usize;
+    let mut types = vec![];
+    while at < header_len {
+        let (serial_type, len) = read_varint(record.get(at..header_len)
+                                                 .ok_or("truncated GeoPackage record")?)?;
+        types.push(serial_type);
+        at += len;
+    }
+    let mut values = vec![];
+    let mut at = header_len;
+    for serial_type in types {
+        let len = match serial_type {
+            0 | 8 | 9 => 0,
+            1..=4 => serial_type as usize,
+            5 => 6,
+            6 | 7 => 8,
+            _ if serial_type >= 12 => (serial_type as usize - 12) / 2,
+            _ => return Err("invalid GeoPackage record".into()),
+        };
+        let bytes = record.get(at..at + len).ok_or("truncated GeoPackage record")?;
+        at += len;
+        values.push(match serial_type {
+                        0 => Value::Null,
+                        1..=6 => {
+                            // Sign-extend big-endian integers of any length.
+                            let first = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+                            Value::Integer(bytes.iter().fold(first, |n, &b| n << 8 | b as i64))
+                        }
+                        7 => {
+                            let mut buf = [0; 8];
+                            buf.copy_from_slice(bytes);
+                            Value::Real(f64::from_be_bytes(buf))
+                        }
+                        8 => Value::Integer(0),
+                        9 => Value::Integer(1),
+                        _ if serial_type % 2 == 0 => Value::Blob(bytes.to_owned()),
+                        _ => Value::Text(String::from_utf8_lossy(bytes).into_owned()),
+                    });
+    }
+    Ok(values)
+}
+
+/// Read an SQLite varint, returning its value and length. Varints store
+/// seven bits in each byte, most significant first, with the high bit set
+/// on every byte except the last. The ninth byte, if any, holds eight bits.
+fn read_varint(data: &[u8]) -> Result<(u64, usize)> {
+    let mut value = 0;
+    for (i, &byte) in data.iter().take(9).enumerate() {
+        if i == 8 {
+            return Ok((value << 8 | byte as u64, 9));
+        }
+        value = value << 7 | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err("truncated GeoPackage varint".into())
+}
+
+/// Get the column names from the `CREATE TABLE` statement of a table, and
+/// the index of its `INTEGER PRIMARY KEY` column, if any.
+fn parse_columns(sql: &str) -> Result<(Vec<String>, Option<usize>)> {
+    let (start, end) = match (sql.find('('), sql.rfind(')')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Err(format!("could not parse {:?}", sql).into()),
+    };
+    // Split our column definitions at commas which aren't inside
+    // parentheses or quotes.
+    let mut definitions = vec![];
+    let (mut depth, mut quote, mut from) = (0, None, start + 1);
+    for (i, c) in sql[..end].char_indices().skip_while(|&(i, _)| i <= start) {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') | (None, '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                definitions.push(&sql[from..i]);
+                from = i + 1;
+            }
+            _ => {}
+        }
+    }
+    definitions.push(&sql[from..end]);
+
+    let mut columns = vec![];
+    let mut rowid_column = None;
+    for definition in definitions {
+        let definition = definition.trim();
+        let upper = definition.to_ascii_uppercase();
+        let constraints = ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+        if constraints.iter().any(|c| upper.split_whitespace().next() == Some(c)) {
+            continue;
+        }
+        // Names may be quoted, in which case the type starts after the
+        // closing quote.
+        let (name, type_start) = match definition.chars().next() {
+            Some(q @ '"') | Some(q @ '\'') | Some(q @ '`') | Some(q @ '[') => {
+                let close = if q == '[' { ']' } else { q };
+                let name = definition[1..].split(close).next().unwrap_or_default();
+                (name, (name.len() + 2).min(definition.len()))
+            }
+            _ => {
+                let name = definition.split_whitespace().next().unwrap_or_default();
+                (name, name.len())
+            }
+        };
+        let words = upper[type_start..].split_whitespace().collect::<Vec<_>>();
+        if words.starts_with(&["INTEGER"]) && words.windows(2).any(|w| w == ["PRIMARY", "KEY"]) {
+            rowid_column = Some(columns.len());
+        }
+        columns.push(name.to_owned());
+    }
+    Ok((columns, rowid_column))
+}
+
+/// Read a GeoPackage geometry, which is a short header followed by a
+/// standard WKB geometry.
+fn read_gpb(blob: &[u8]) -> Result<Vec<Ring>> {
+    if blob.len() < 8 || &blob[..2] != b"GP" {
+        return Err("invalid GeoPackage geometry".into());
+    }
+    let flags = blob[3];
+    // Empty geometries have no rings.
+    if flags & 0x10 != 0 {
+        return Ok(vec![]);
+    }
+    let envelope_len = match (flags >> 1) & 0b111 {
+        0 => 0,
+        1 => 32,
+        2 | 3 => 48,
+        4 => 64,
+        _ => return Err("invalid GeoPackage geometry envelope".into()),
+    };
+    let mut wkb = blob.get(8 + envelope_len..).ok_or("truncated GeoPackage geometry")?;
+    let rings = read_wkb_polygons(&mut wkb)?;
+    Ok(rings)
+}
+
+/// Read the rings of a WKB polygon or multipolygon, advancing `wkb` past
+/// it.
+fn read_wkb_polygons(wkb: &mut &[u8]) -> Result<Vec<Ring>> {
+    let little_endian = match take(wkb, 1)?[0] {
+        0 => false,
+        1 => true,
+        _ => return Err("invalid WKB byte order".into()),
+    };
+    let read_u32 = |wkb: &mut &[u8]| -> Result<u32> {
+        let bytes = take(wkb, 4)?;
+        Ok(if little_endian { le_u32(bytes, 0) } else { be_u32(bytes, 0) })
+    };
+    let geometry_type = read_u32(wkb)?;
+    let dimensions = match geometry_type / 1000 {
+        0 => 2,
+        1 | 2 => 3,
+        3 => 4,
+        _ => return Err(format!("unsupported WKB geometry type {}", geometry_type).into()),
+    };
+    match geometry_type % 1000 {
+        WKB_POLYGON => {
+            let mut rings = vec![];
+            for _ in 0..read_u32(wkb)? {
+                let num_points = read_u32(wkb)? as usize;
+                let points = take(wkb, num_points * dimensions * 8)?;
+                let coord = |at: usize| {
+                    let mut buf = [0; 8];
+                    buf.copy_from_slice(&points[at..at + 8]);
+                    if little_endian { f64::from_le_bytes(buf) } else { f64::from_be_bytes(buf) }
+                };
+                let ring = (0..num_points)
+                    .map(|i| {
+                             let at = i * dimensions * 8;
+                             (coord(at), coord(at + 8))
+                         })
+                    .collect();
+                rings.push(ring);
+            }
+            Ok(rings)
+        }
+        WKB_MULTIPOLYGON => {
+            let mut rings = vec![];
+            for _ in 0..read_u32(wkb)? {
+                rings.extend(read_wkb_polygons(wkb)?);
+            }
+            Ok(rings)
+        }
+        _ => Err(format!("unsupported WKB geometry type {}, expected polygons", geometry_type)
+                     .into()),
+    }
+}
+
+/// Split the first `len` bytes off `data`.
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err("truncated WKB geometry".into());
+    }
+    let (first, rest) = data.split_at(len);
+    *data = rest;
+    Ok(first)
+}
+
+/// Read a big-endian `u16` at `offset`.
+fn be_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+/// Read a big-endian `u32` at `offset`.
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Read a little-endian `u32` at `offset`.
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Encode `n` as an SQLite varint, for testing. We only need values below
+/// 2^56, which don't use the special ninth byte.
+#[cfg(test)]
+fn varint(mut n: u64) -> Vec<u8> {
+    let mut bytes = vec![(n & 0x7f) as u8];
+    n >>= 7;
+    while n > 0 {
+        bytes.insert(0, (n & 0x7f) as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes
+}
+
+/// Encode `values` as an SQLite record, for testing.
+#[cfg(test)]
+fn write_test_record(values: &[Value]) -> Vec<u8> {
+    let mut types = vec![];
+    let mut body = vec![];
+    for value in values {
+        match *value {
+            Value::Null => types.extend(varint(0)),
+            Value::Integer(i) => {
+                types.extend(varint(6));
+                body.extend_from_slice(&i.to_be_bytes());
+            }
+            Value::Real(f) => {
+                types.extend(varint(7));
+                body.extend_from_slice(&f.to_be_bytes());
+            }
+            Value::Text(ref s) => {
+                types.extend(varint(13 + 2 * s.len() as u64));
+                body.extend_from_slice(s.as_bytes());
+            }
+            Value::Blob(ref b) => {
+                types.extend(varint(12 + 2 * b.len() as u64));
+                body.extend_from_slice(b);
+            }
+        }
+    }
+    // Our headers are always short enough for a one-byte length.
+    let mut record = varint(types.len() as u64 + 1);
+    record.extend(types);
+    record.extend(body);
+    record
+}
+
+/// Build a GeoPackage with a `regions` table containing `shapes`, each
+/// with a `name` and `pop` column, for testing. Each table fits on one
+/// 4096-byte page.
+#[cfg(test)]
+fn write_test_geopackage(shapes: &[(&str, u64, Vec<Ring>)]) -> Vec<u8> {
+    const PAGE_SIZE: usize = 4096;
+    let text = |s: &str| Value::Text(s.to_owned());
+    let master = vec![vec![text("table"),
+                           text("gpkg_geometry_columns"),
+                           text("gpkg_geometry_columns"),
+                           Value::Integer(2),
+                           text("CREATE TABLE gpkg_geometry_columns (table_name TEXT NOT \
+                                 NULL, column_name TEXT NOT NULL, geometry_type_name TEXT \
+                                 NOT NULL, srs_id INTEGER NOT NULL, z TINYINT NOT NULL, m \
+                                 TINYINT NOT NULL, CONSTRAINT pk_geom_cols PRIMARY KEY \
+                                 (table_name, column_name))")],
+                      vec![text("table"),
+                           text("regions"),
+                           text("regions"),
+                           Value::Integer(3),
+                           text("CREATE TABLE \"regions\" (\"fid\" INTEGER PRIMARY KEY \
+                                 AUTOINCREMENT NOT NULL, \"geom\" POLYGON, \"name\" \
+                                 TEXT(10), \"pop\" REAL)")]];
+    let geometry_columns = vec![vec![text("regions"),
+                                     text("geom"),
+                                     text("POLYGON"),
+                                     Value::Integer(4326),
+                                     Value::Integer(0),
+                                     Value::Integer(0)]];
+    let regions = shapes
+        .iter()
+        .map(|&(name, pop, ref rings)| {
+            // A little-endian header with no envelope, followed by a
+            // little-endian WKB polygon.
+            let mut geom = b"GP\x00\x01".to_vec();
+            geom.extend_from_slice(&4326i32.to_le_bytes());
+            geom.push(1);
+            geom.extend_from_slice(&WKB_POLYGON.to_le_bytes());
+            geom.extend_from_slice(&(rings.len() as u32).to_le_bytes());
+            for ring in rings {
+                geom.extend_from_slice(&(ring.len() as u32).to_le_bytes());
+                for &(x, y) in ring {
+                    geom.extend_from_slice(&x.to_le_bytes());
+                    geom.extend_from_slice(&y.to_le_bytes());
+                }
+            }
+            vec![Value::Null, Value::Blob(geom), text(name), Value::Real(pop as f64)]
+        })
+        .collect::<Vec<_>>();
+
+    let mut data = vec![];
+    for (i, table) in [master, geometry_columns, regions].iter().enumerate() {
+        let mut page = vec![0; PAGE_SIZE];
+        let header = if i == 0 { 100 } else { 0 };
+        page[header] = LEAF_TABLE_PAGE;
+        page[header + 3..header + 5].copy_from_slice(&(table.len() as u16).to_be_bytes());
+        let mut end = PAGE_SIZE;
+        for (j, row) in table.iter().enumerate() {
+            // Each cell is the length of its record, its rowid, and the
+            // record itself.
+            let record = write_test_record(row);
+            let mut cell = varint(record.len() as u64);
+            cell.extend(varint(j as u64 + 1));
+            cell.extend(record);
+            end -= cell.len();
+            page[end..end + cell.len()].copy_from_slice(&cell);
+            let pointer = header + 8 + 2 * j;
+            page[pointer..pointer + 2].copy_from_slice(&(end as u16).to_be_bytes());
+        }
+        data.extend(page);
+    }
+    data[..16].copy_from_slice(SQLITE_MAGIC);
+    data[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    data[56..60].copy_from_slice(&1u32.to_be_bytes());
+    data
+}
+
+#[test]
+fn reads_geopackages() {
+    let _ = env_logger::init();
+    let square = vec![(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+    let hole = vec![(0.2, 0.2), (0.8, 0.2), (0.8, 0.8), (0.2, 0.8), (0.2, 0.2)];
+    let gpkg = write_test_geopackage(&[("north", 100, vec![square.clone()]),
+                                       ("south", 250, vec![square.clone(), hole.clone()])]);
+    let shapes = read_geopackage(&mut &gpkg[..]).unwrap();
+    assert_eq!(shapes.len(), 2);
+    assert_eq!(shapes[1].rings, vec![square, hole]);
+    assert_eq!(shapes[0].attributes["name"], "north");
+    assert_eq!(shapes[0].attributes["fid"], "1");
+    assert_eq!(shapes[1].attributes["pop"], "250");
+    assert!(!shapes[1].attributes.contains_key("geom"));
+
+    assert!(read_geopackage(&mut &gpkg[..4096]).is_err());
+    assert!(read_geopackage(&mut &b"GeoJSON"[..]).is_err());
+}
+
+#[test]
+fn parses_sqlite_records() {
+    let _ = env_logger::init();
+    assert_eq!(read_varint(&[0x05]).unwrap(), (5, 1));
+    assert_eq!(read_varint(&[0x81, 0x00]).unwrap(), (128, 2));
+    assert!(read_varint(&[0x81]).is_err());
+    // Two columns: a 1-byte integer of -2, and the text "hi".
+    assert_eq!(read_record(&[3, 1, 17, 0xfe, b'h', b'i']).unwrap(),
+               vec![Value::Integer(-2), Value::Text("hi".to_owned())]);
+    let (columns, rowid) = parse_columns("CREATE TABLE t (\"id\" INTEGER PRIMARY KEY, [a b] \
+                                          NUMERIC(10, 2), c, UNIQUE (c))")
+        .unwrap();
+    assert_eq!(columns, vec!["id", "a b", "c"]);
+    assert_eq!(rowid, Some(0));
+}
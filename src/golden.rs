@@ -0,0 +1,178 @@
+//! Checking that we still assign chunks the same way as an earlier build.
+//!
+//! We promise that chunk IDs are stable for a given `ALGORITHM_VERSION`,
+//! because people use them as join keys across years of stored data. A
+//! "golden" mapping is just the output of `Classifier::export` from an
+//! earlier build, which we can compare against our current chunks.
+
+use csv;
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::io::prelude::*;
+
+use dialect::Dialect;
+use errors::*;
+use zip2010::Classifier;
+
+/// A zip code which we now assign to a different chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The zip code.
+    pub zip: String,
+    /// The chunk ID in the golden mapping.
+    pub expected: String,
+    /// The chunk ID we would assign now, if any.
+    pub actual: Option<String>,
+}
+
+/// Return the geochunk column name used by a golden mapping file.
+pub fn golden_column_name(input: &mut dyn Read) -> Result<String> {
+    let mut rdr = csv::Reader::from_reader(input);
+    let headers = rdr.headers()?;
+    match headers.get(1) {
+        Some(column) if headers.get(0) == Some("zip") => Ok(column.to_owned()),
+        _ => Err("golden mapping should have columns zip,geochunk_*".into()),
+    }
+}
+
+/// Compare a golden mapping against `classifier`, and return every zip code
+/// which is now assigned differently.
+pub fn verify_golden(classifier: &Classifier,
+                     input: &mut dyn Read)
+                     -> Result<Vec<Mismatch>> {
+    let mut rdr = csv::Reader::from_reader(input);
+    let column = rdr.headers()?.get(1).unwrap_or("").to_owned();
+    if column != classifier.geochunk_column_name() {
+        return Err(format!("golden mapping has column {:?}, but we expected {:?}",
+                           column,
+                           classifier.geochunk_column_name())
+                           .into());
+    }
+
+    let mut mismatches = vec![];
+    for row in rdr.deserialize() {
+        let (zip, expected): (String, String) = row?;
+        let actual = classifier.chunk_for(&zip);
+        if actual != Some(&expected[..]) {
+            mismatches.push(Mismatch {
+                                zip,
+                                expected,
+                                actual: actual.map(|a| a.to_owned()),
+                            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// A row of a CSV file whose stored chunk doesn't match the chunk we would
+/// assign now.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowMismatch {
+    /// The line number of the row.
+    pub line: u64,
+    /// The differing zip code and chunks. An empty stored chunk is treated
+    /// as matching a zip code which we can't classify.
+    pub mismatch: Mismatch,
+}
+
+/// Recompute the chunk for every row of a CSV file which already has a
+/// chunk column, and return every row where the stored chunk differs. This
+/// detects files which were chunked by a different version of geochunk, or
+/// with different parameters.
+pub fn verify_chunk_column(classifier: &Classifier,
+                           zip_column: &str,
+                           chunk_column: &str,
+                           dialect: &Dialect,
+                           input: &mut dyn Read)
+                           -> Result<Vec<RowMismatch>> {
+    let mut rdr = dialect.reader_builder().from_reader(input);
+    let headers = rdr.headers()?.to_owned();
+    let find_column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| Error::no_such_column(name))
+    };
+    let zip_col_idx = find_column(zip_column)?;
+    let chunk_col_idx = find_column(chunk_column)?;
+
+    let mut mismatches = vec![];
+    let mut row = csv::StringRecord::new();
+    while rdr.read_record(&mut row)? {
+        let zip = row.get(zip_col_idx).unwrap_or("");
+        let expected = row.get(chunk_col_idx).unwrap_or("");
+        let actual = classifier.chunk_for(zip);
+        if actual.unwrap_or("") != expected {
+            mismatches.push(RowMismatch {
+                                line: row.position().map_or(0, |p| p.line()),
+                                mismatch: Mismatch {
+                                    zip: zip.to_owned(),
+                                    expected: expected.to_owned(),
+                                    actual: actual.map(|a| a.to_owned()),
+                                },
+                            });
+        }
+    }
+    Ok(mismatches)
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn exported_mapping_verifies_against_itself() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut golden = vec![];
+    classifier.export(&mut golden).unwrap();
+    assert_eq!(golden_column_name(&mut &golden[..]).unwrap(),
+               "geochunk_zip2010_250000");
+    assert!(verify_golden(&classifier, &mut &golden[..])
+                .unwrap()
+                .is_empty());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn reports_changed_chunks() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let golden = "zip,geochunk_zip2010_250000\n01000,010_0\n07720,077_0\n";
+    let mismatches = verify_golden(&classifier, &mut golden.as_bytes()).unwrap();
+    assert_eq!(mismatches,
+               vec![Mismatch {
+                        zip: "07720".to_owned(),
+                        expected: "077_0".to_owned(),
+                        actual: Some("077_1".to_owned()),
+                    }]);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn rejects_golden_mappings_with_other_parameters() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let golden = "zip,geochunk_zip2010_500000\n01000,01_0\n";
+    assert!(verify_golden(&classifier, &mut golden.as_bytes()).is_err());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn reports_rows_with_stale_chunks() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let input = "zip,chunk\n90210,902_0\n07720,077_0\n,\nbogus,\n";
+    let mismatches = verify_chunk_column(&classifier,
+                                         "zip",
+                                         "chunk",
+                                         &Dialect::default(),
+                                         &mut input.as_bytes())
+            .unwrap();
+    assert_eq!(mismatches,
+               vec![RowMismatch {
+                        line: 3,
+                        mismatch: Mismatch {
+                            zip: "07720".to_owned(),
+                            expected: "077_0".to_owned(),
+                            actual: Some("077_1".to_owned()),
+                        },
+                    }]);
+}
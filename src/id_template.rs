@@ -0,0 +1,217 @@
+//! Templates for naming chunks, like `{state}-{prefix}-{index:03}`, so that
+//! chunk IDs can follow downstream naming conventions without a separate
+//! mapping. See `Naming::Template`.
+
+#[cfg(test)]
+use env_logger;
+use std::fmt;
+use std::str::FromStr;
+
+use errors::*;
+
+/// A value we can substitute into a template.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Var {
+    /// The chunk's usual ID, like `902_0`.
+    Id,
+    /// The zip code prefix the chunk was split from, like `902`.
+    Prefix,
+    /// The chunk's dense integer index.
+    Index,
+    /// The state with the most of the chunk's population.
+    State,
+    /// The data set used to build the chunk, like `zip2010`.
+    Vintage,
+}
+
+impl FromStr for Var {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Var> {
+        match s {
+            "id" => Ok(Var::Id),
+            "prefix" => Ok(Var::Prefix),
+            "index" => Ok(Var::Index),
+            "state" => Ok(Var::State),
+            "vintage" => Ok(Var::Vintage),
+            _ => {
+                Err(format!("unknown chunk ID template variable {:?}, expected id, \
+                             prefix, index, state or vintage",
+                            s)
+                            .into())
+            }
+        }
+    }
+}
+
+/// A piece of a template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    /// Text copied into every ID.
+    Text(String),
+    /// A variable, padded with leading zeros to at least `width` characters.
+    Var { var: Var, width: usize },
+}
+
+/// A parsed chunk ID template. Variables are written in braces, like
+/// `{prefix}`, and may be padded with zeros using `{index:03}`. Literal
+/// braces are written `{{` and `}}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdTemplate {
+    /// The template as it was written.
+    source: String,
+    /// Our parsed template.
+    parts: Vec<Part>,
+}
+
+/// The values substituted into a template for one chunk.
+#[derive(Clone, Debug)]
+pub struct ChunkVars<'a> {
+    /// The chunk's usual ID.
+    pub id: &'a str,
+    /// The zip code prefix the chunk was split from.
+    pub prefix: &'a str,
+    /// The chunk's dense integer index.
+    pub index: usize,
+    /// The state with the most of the chunk's population, or an empty
+    /// string if it has none.
+    pub state: &'a str,
+    /// The name of the data set used to build the chunk.
+    pub vintage: &'a str,
+}
+
+impl IdTemplate {
+    /// Does our template use `{state}`, which we look up using US zip
+    /// codes?
+    pub fn uses_state(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(*part, Part::Var { var: Var::State, .. }))
+    }
+
+    /// Fill in our template for one chunk.
+    pub fn render(&self, vars: &ChunkVars) -> String {
+        let mut id = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref text) => id.push_str(text),
+                Part::Var { var, width } => {
+                    let value = match var {
+                        Var::Id => vars.id.to_owned(),
+                        Var::Prefix => vars.prefix.to_owned(),
+                        Var::Index => vars.index.to_string(),
+                        Var::State => vars.state.to_owned(),
+                        Var::Vintage => vars.vintage.to_owned(),
+                    };
+                    for _ in value.len()..width {
+                        id.push('0');
+                    }
+                    id.push_str(&value);
+                }
+            }
+        }
+        id
+    }
+}
+
+impl FromStr for IdTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IdTemplate> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => {
+                                return Err(format!("unclosed {{ in chunk ID template {:?}",
+                                                   s)
+                                                   .into())
+                            }
+                        }
+                    }
+                    let (name, width) = match field.find(':') {
+                        Some(i) => {
+                            let spec = &field[i + 1..];
+                            let width = spec.strip_prefix('0')
+                                .and_then(|w| w.parse::<usize>().ok())
+                                .ok_or_else(|| {
+                                    format!("chunk ID template padding should look like \
+                                             {{index:03}}, not {{{}}}",
+                                            field)
+                                })?;
+                            (&field[..i], width)
+                        }
+                        None => (&field[..], 0),
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(text.split_off(0)));
+                    }
+                    parts.push(Part::Var {
+                                   var: name.trim().parse()?,
+                                   width,
+                               });
+                }
+                '}' => {
+                    return Err(format!("unmatched }} in chunk ID template {:?}", s).into())
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if !parts.iter().any(|p| matches!(*p, Part::Var { .. })) {
+            return Err(format!("chunk ID template {:?} doesn't use any variables, so \
+                                every chunk would have the same ID",
+                               s)
+                               .into());
+        }
+        Ok(IdTemplate {
+               source: s.to_owned(),
+               parts,
+           })
+    }
+}
+
+impl fmt::Display for IdTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+#[test]
+fn renders_templates() {
+    let _ = env_logger::init();
+    let vars = ChunkVars {
+        id: "902_0",
+        prefix: "902",
+        index: 7,
+        state: "CA",
+        vintage: "zip2010",
+    };
+    let render = |template: &str| template.parse::<IdTemplate>().unwrap().render(&vars);
+    assert_eq!(render("{state}-{prefix}-{index:03}"), "CA-902-007");
+    assert_eq!(render("{vintage}/{id}"), "zip2010/902_0");
+    assert_eq!(render("{{{index}}}"), "{7}");
+    assert_eq!(render("{index:01}"), "7");
+    assert!("{state}-{index}".parse::<IdTemplate>().unwrap().uses_state());
+    assert!(!"{{state}}-{index}".parse::<IdTemplate>().unwrap().uses_state());
+    for bad in &["chunk", "{index", "index}", "{zip}", "{index:3}", "{index:0x}"] {
+        assert!(bad.parse::<IdTemplate>().is_err(), "{}", bad);
+    }
+}
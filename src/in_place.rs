@@ -0,0 +1,132 @@
+//! Replacing a file with a transformed version of itself, without ever
+//! leaving a half-written file behind.
+
+#[cfg(test)]
+use env_logger;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use errors::*;
+
+/// How many temporary file names we try before giving up.
+const MAX_TEMP_ATTEMPTS: u32 = 100;
+
+/// Read `path`, pass it to `transform` along with a temporary output file,
+/// and rename the output over `path` if `transform` succeeds. The output
+/// file is created in the same directory as `path`, so that the rename is
+/// atomic, and it is given the same permissions as `path`. If `path` is a
+/// symlink, we replace the file it points to, not the link. If anything
+/// fails, `path` is left untouched. Returns whatever `transform` returns.
+pub fn rewrite_in_place<T, F>(path: &Path, transform: F) -> Result<T>
+    where F: FnOnce(File, &mut dyn Write) -> Result<T>
+{
+    let path = &fs::canonicalize(path)
+                    .chain_err(|| format!("could not open {}", path.display()))?;
+    let input = File::open(path)
+        .chain_err(|| format!("could not open {}", path.display()))?;
+    let permissions = input.metadata()?.permissions();
+    let (tmp_path, tmp) = create_temp_for(path)?;
+    let result = (|| -> Result<T> {
+        let mut output = BufWriter::new(tmp);
+        let value = transform(input, &mut output)?;
+        let tmp = output.into_inner().map_err(|e| e.into_error())?;
+        tmp.sync_all()?;
+        fs::set_permissions(&tmp_path, permissions)?;
+        fs::rename(&tmp_path, path)
+            .chain_err(|| format!("could not replace {}", path.display()))?;
+        Ok(value)
+    })();
+    if result.is_err() {
+        // Don't hide our original error if we can't clean up.
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Create a new temporary file next to `path`. We never open an existing
+/// file or follow a symlink here, so if another file already has the name
+/// we want, we try the next one.
+fn create_temp_for(path: &Path) -> Result<(PathBuf, File)> {
+    for attempt in 0..MAX_TEMP_ATTEMPTS {
+        let tmp_path = temp_path_for(path, attempt)?;
+        match OpenOptions::new().write(true).create_new(true).open(&tmp_path) {
+            Ok(tmp) => return Ok((tmp_path, tmp)),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err)
+                    .chain_err(|| format!("could not create {}", tmp_path.display()))
+            }
+        }
+    }
+    Err(format!("could not find an unused temporary file name for {}",
+                path.display())
+                .into())
+}
+
+/// Choose the temporary file name next to `path` for our `attempt`th try.
+fn temp_path_for(path: &Path, attempt: u32) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file", path.display()))?;
+    let mut tmp_name = ".".to_owned();
+    tmp_name.push_str(&name.to_string_lossy());
+    tmp_name.push_str(&format!(".geochunk-{}-{}.tmp", process::id(), attempt));
+    Ok(path.with_file_name(tmp_name))
+}
+
+#[test]
+fn replaces_file_only_on_success() {
+    use std::env;
+    use std::io::copy;
+
+    let _ = env_logger::init();
+    let path = env::temp_dir().join(format!("geochunk-in-place-{}.txt", process::id()));
+    fs::write(&path, "before").unwrap();
+
+    let result = rewrite_in_place(&path, |_, output| -> Result<()> {
+        output.write_all(b"partial")?;
+        Err("failed".into())
+    });
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "before");
+    assert!(!temp_path_for(&path, 0).unwrap().exists());
+
+    rewrite_in_place(&path, |mut input, output| {
+        copy(&mut input, output)?;
+        output.write_all(b" and after")?;
+        Ok(())
+    }).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "before and after");
+
+    // Never overwrite a file which happens to have our temporary name.
+    let taken = temp_path_for(&path, 0).unwrap();
+    fs::write(&taken, "someone else's").unwrap();
+    rewrite_in_place(&path, |_, output| Ok(output.write_all(b"again")?)).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "again");
+    assert_eq!(fs::read_to_string(&taken).unwrap(), "someone else's");
+    fs::remove_file(&taken).unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn replaces_target_of_symlink() {
+    use std::env;
+    use std::os::unix::fs::symlink;
+
+    let _ = env_logger::init();
+    let dir = env::temp_dir();
+    let target = dir.join(format!("geochunk-in-place-target-{}.txt", process::id()));
+    let link = dir.join(format!("geochunk-in-place-link-{}.txt", process::id()));
+    fs::write(&target, "before").unwrap();
+    let _ = fs::remove_file(&link);
+    symlink(&target, &link).unwrap();
+
+    rewrite_in_place(&link, |_, output| Ok(output.write_all(b"after")?)).unwrap();
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    assert_eq!(fs::read_to_string(&target).unwrap(), "after");
+    fs::remove_file(&link).unwrap();
+    fs::remove_file(&target).unwrap();
+}
@@ -0,0 +1,85 @@
+//! Assigning geochunks to the items of an iterator.
+
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+
+use zip2010::Classifier;
+
+/// Extension methods for classifying the records produced by an iterator.
+///
+/// ```
+/// # #[cfg(feature = "data-zip2010")] {
+/// use geochunk::iter::ClassifyZipExt;
+/// use geochunk::zip2010::Classifier;
+///
+/// struct Customer {
+///     name: String,
+///     zip: String,
+/// }
+///
+/// let classifier = Classifier::new(250000);
+/// let customers = vec![Customer { name: "J. Doe".to_owned(), zip: "90210".to_owned() }];
+/// for (customer, chunk) in customers.into_iter().classify_zip(&classifier, |c| &c.zip) {
+///     println!("{}: {:?}", customer.name, chunk);
+/// }
+/// # }
+/// ```
+pub trait ClassifyZipExt: Iterator + Sized {
+    /// Pair each item with the geochunk for the zip code returned by
+    /// `zip_for`, or `None` if the zip code can't be classified.
+    fn classify_zip<'c, F>(self, classifier: &'c Classifier, zip_for: F) -> ClassifyZip<'c, Self, F>
+        where F: for<'r> FnMut(&'r Self::Item) -> &'r str
+    {
+        ClassifyZip {
+            inner: self,
+            classifier,
+            zip_for,
+        }
+    }
+}
+
+impl<I: Iterator> ClassifyZipExt for I {}
+
+/// An iterator which pairs records with their geochunks. Returned by
+/// `ClassifyZipExt::classify_zip`.
+#[derive(Clone)]
+pub struct ClassifyZip<'c, I, F> {
+    /// The records we're classifying.
+    inner: I,
+    /// The classifier to use.
+    classifier: &'c Classifier,
+    /// Find the zip code of a record.
+    zip_for: F,
+}
+
+impl<'c, I, F> Iterator for ClassifyZip<'c, I, F>
+    where I: Iterator,
+          F: for<'r> FnMut(&'r I::Item) -> &'r str
+{
+    type Item = (I::Item, Option<&'c str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.inner.next()?;
+        let chunk = self.classifier.chunk_for((self.zip_for)(&record));
+        Some((record, chunk))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn classifies_iterator_items() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let records = vec![("J. Doe", "90210"), ("H. Smith", ""), ("A. Jones", "07712")];
+    let classified = records
+        .into_iter()
+        .classify_zip(&classifier, |r| r.1)
+        .map(|(r, chunk)| (r.0, chunk))
+        .collect::<Vec<_>>();
+    assert_eq!(classified,
+               vec![("J. Doe", Some("902_0")), ("H. Smith", None), ("A. Jones", Some("077_0"))]);
+}
@@ -0,0 +1,107 @@
+//! Processing many input files at once. We run a fixed number of worker
+//! threads, each of which takes the next unprocessed file whenever it
+//! finishes one, so a few huge files don't hold up all the small ones.
+
+#[cfg(test)]
+use env_logger;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use errors::*;
+
+/// Run `job` on each of `paths`, running up to `jobs` at once, and return
+/// the results in the same order as `paths`. We call `progress` on this
+/// thread each time a file is finished. If a job fails, we don't start any
+/// new jobs, and return the first error once the running jobs are done.
+pub fn run_jobs<T, F, P>(paths: &[String],
+                        jobs: usize,
+                        job: F,
+                        mut progress: P)
+                        -> Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(&str) -> Result<T> + Sync,
+    P: FnMut(&str, &T),
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+    let mut results = (0..paths.len()).map(|_| None).collect::<Vec<_>>();
+    let mut first_err = None;
+    // Spans don't follow us onto new threads, so we pass ours along by hand.
+    #[cfg(feature = "otel")]
+    let parent = ::tracing::Span::current();
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, paths.len().max(1)) {
+            let tx = tx.clone();
+            let (next, failed, job) = (&next, &failed, &job);
+            #[cfg(feature = "otel")]
+            let parent = parent.clone();
+            scope.spawn(move || {
+                #[cfg(feature = "otel")]
+                let _parent = parent.entered();
+                while !failed.load(Ordering::SeqCst) {
+                    let idx = next.fetch_add(1, Ordering::SeqCst);
+                    if idx >= paths.len() {
+                        break;
+                    }
+                    trace_span!("job", path = &paths[idx][..]);
+                    let result = job(&paths[idx]);
+                    if result.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    // Our receiver outlives every worker.
+                    tx.send((idx, result)).expect("job results should be received");
+                }
+            });
+        }
+        // Once the workers finish, every sender is gone, which ends our loop.
+        drop(tx);
+        for (idx, result) in rx {
+            match result {
+                Ok(value) => {
+                    progress(&paths[idx], &value);
+                    results[idx] = Some(value);
+                }
+                Err(err) => {
+                    if first_err.is_none() {
+                        first_err = Some(err);
+                    }
+                }
+            }
+        }
+    });
+    if let Some(err) = first_err {
+        return Err(err);
+    }
+    Ok(results
+           .into_iter()
+           .map(|r| r.expect("every job should have finished"))
+           .collect())
+}
+
+#[test]
+fn runs_jobs_concurrently() {
+    let _ = env_logger::init();
+    let paths = (0..20).map(|i| i.to_string()).collect::<Vec<_>>();
+    let mut finished = vec![];
+    let lengths = run_jobs(&paths,
+                           4,
+                           |path| Ok(path.len()),
+                           |path, _| finished.push(path.to_owned()))
+            .unwrap();
+    assert_eq!(lengths, paths.iter().map(|p| p.len()).collect::<Vec<_>>());
+    finished.sort_by_key(|p| p.parse::<u32>().unwrap());
+    assert_eq!(finished, paths);
+
+    let result = run_jobs(&paths,
+                          4,
+                          |path| if path == "7" {
+                              Err("bad file".into())
+                          } else {
+                              Ok(())
+                          },
+                          |_, _| {});
+    assert_eq!(result.unwrap_err().to_string(), "bad file");
+}
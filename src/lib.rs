@@ -0,0 +1,177 @@
+//! Split data sets into chunks of similar population, based on zip codes.
+//!
+//! This library contains the classifiers used by the `geochunk` command-line
+//! tool, for programs which want to assign geochunks directly. Modules which
+//! only the command-line tool needs, such as `archive` and `snowflake`, are
+//! only built with the `cli` feature.
+
+// `error_chain!` can recurse deeply
+#![recursion_limit = "1024"]
+
+// Enable clippy if we were asked to do so.
+#![cfg_attr(feature="clippy", feature(plugin))]
+#![cfg_attr(feature="clippy", plugin(clippy))]
+
+#[cfg(feature = "orc")]
+extern crate arrow;
+#[cfg(any(feature = "dictionary", feature = "flight", feature = "parquet"))]
+extern crate arrow_array;
+#[cfg(feature = "tables")]
+extern crate arrow_avro;
+#[cfg(feature = "flight")]
+extern crate arrow_flight;
+#[cfg(feature = "tables")]
+extern crate arrow_ipc;
+#[cfg(any(feature = "flight", feature = "parquet"))]
+extern crate arrow_schema;
+extern crate csv;
+#[cfg(feature = "tokio")]
+extern crate csv_core;
+#[cfg(test)]
+extern crate env_logger;
+#[macro_use]
+extern crate error_chain;
+#[cfg(feature = "cli")]
+extern crate flate2;
+#[cfg(feature = "flight")]
+extern crate futures;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+extern crate io_uring;
+#[cfg(feature = "data-zip2010")]
+#[macro_use]
+extern crate lazy_static;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+extern crate libc;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "otel")]
+extern crate opentelemetry;
+#[cfg(feature = "otel")]
+extern crate opentelemetry_otlp;
+#[cfg(feature = "otel")]
+extern crate opentelemetry_sdk;
+#[cfg(feature = "orc")]
+extern crate orc_rust;
+#[cfg(feature = "parquet")]
+extern crate parquet;
+#[cfg(feature = "tui")]
+extern crate ratatui;
+#[cfg(feature = "cli")]
+extern crate ruzstd;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
+#[cfg(feature = "cli")]
+extern crate tar;
+#[cfg(any(feature = "tokio", feature = "flight"))]
+extern crate tokio;
+#[cfg(feature = "flight")]
+extern crate tonic;
+#[cfg(feature = "otel")]
+extern crate tracing;
+#[cfg(feature = "otel")]
+extern crate tracing_opentelemetry;
+#[cfg(feature = "otel")]
+extern crate tracing_subscriber;
+#[cfg(feature = "lambda")]
+extern crate ureq;
+#[cfg(feature = "cli")]
+extern crate zip;
+
+/// Enter a `tracing` span which lasts until the end of the enclosing block,
+/// if we were built with the `otel` feature. This takes the same arguments
+/// as `tracing::info_span!`, which are ignored otherwise.
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "otel")]
+        let _span = ::tracing::info_span!($($arg)*).entered();
+    };
+}
+
+pub mod anonymize;
+#[cfg(feature = "cli")]
+pub mod archive;
+#[cfg(feature = "tokio")]
+pub mod async_transform;
+pub mod census;
+pub mod chunk_id;
+pub mod chunk_set;
+#[cfg(feature = "cli")]
+pub mod completions;
+#[cfg(feature = "cli")]
+pub mod compression;
+#[cfg(feature = "data-zip2010")]
+pub mod crosswalk;
+#[cfg(all(unix, feature = "cli"))]
+pub mod daemon;
+pub mod density;
+pub mod dialect;
+pub mod districts;
+pub mod dma;
+#[cfg(feature = "cli")]
+pub mod encoding;
+pub mod errors;
+pub mod experiment;
+#[cfg(feature = "cli")]
+pub mod explore;
+#[cfg(feature = "tui")]
+pub mod explore_tui;
+#[cfg(feature = "flight")]
+pub mod flight;
+pub mod format;
+pub mod gazetteer;
+pub mod geography;
+pub mod geopackage;
+pub mod golden;
+pub mod id_template;
+#[cfg(feature = "cli")]
+pub mod in_place;
+pub mod iter;
+#[cfg(feature = "cli")]
+pub mod jobs;
+#[cfg(feature = "lambda")]
+pub mod lambda;
+#[cfg(feature = "cli")]
+pub mod lookml;
+pub mod manifest;
+pub mod mexico;
+pub mod mmap;
+pub mod noise;
+pub mod number;
+#[cfg(feature = "orc")]
+pub mod orc;
+#[cfg(feature = "parquet")]
+pub mod parquet_files;
+pub mod partition;
+pub mod points;
+pub mod proto;
+#[cfg(any(feature = "flight", feature = "tables"))]
+pub mod record_batch;
+pub mod scheme;
+pub mod self_check;
+pub mod s2;
+pub mod shapefile;
+pub mod shard;
+pub mod skew;
+#[cfg(feature = "cli")]
+pub mod snowflake;
+pub mod soi;
+mod sort;
+#[cfg(feature = "cli")]
+mod sql;
+pub mod states;
+#[cfg(feature = "tables")]
+pub mod table;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod territory;
+pub mod timezones;
+pub mod transform;
+pub mod uring;
+pub mod vintage;
+pub mod zip2010;
+pub mod zip_patch;
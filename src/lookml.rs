@@ -0,0 +1,186 @@
+//! Generating a LookML dimension which assigns zip codes to chunks, so that
+//! Looker users can group by geochunk without loading a mapping table.
+//! Printed by `geochunk export-lookml`.
+//!
+//! The dimension is a SQL `CASE` expression. Rather than listing all
+//! 100,000 zip codes, we find the shortest prefixes whose zip codes all
+//! belong to the same chunk, working from the chunk we assign to every zip
+//! code, so it can't drift from this crate's mapping. Zip codes shorter
+//! than five characters get the unknown chunk, or `NULL`, as they do here.
+//! Values which don't start with five digits may be classified differently.
+
+#[cfg(test)]
+use env_logger;
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+use errors::*;
+use sql::sql_string;
+use zip2010::Classifier;
+
+/// How many prefixes we list on each line of an `IN` clause.
+const PREFIXES_PER_LINE: usize = 10;
+
+/// Find the shortest prefixes of `prefix` whose zip codes are all in the
+/// same chunk, given the chunk for every zip code starting with `prefix`, in
+/// order. If they're all in one chunk, we return it and leave it to our
+/// caller to record. Otherwise we add each uniform prefix to `rules`.
+fn uniform_prefixes<'a>(prefix: &str,
+                        chunks: &[&'a str],
+                        rules: &mut Vec<(String, &'a str)>)
+                        -> Option<&'a str> {
+    if chunks.len() == 1 {
+        return Some(chunks[0]);
+    }
+    let size = chunks.len() / 10;
+    let children = (0..10)
+        .map(|digit| {
+            let child = format!("{}{}", prefix, digit);
+            let zips = &chunks[digit * size..][..size];
+            let chunk = uniform_prefixes(&child, zips, rules);
+            (child, chunk)
+        })
+        .collect::<Vec<_>>();
+    let first = children[0].1;
+    if first.is_some() && children.iter().all(|&(_, chunk)| chunk == first) {
+        // Our children haven't added any rules, so we can stand in for them.
+        return first;
+    }
+    for (child, chunk) in children {
+        if let Some(chunk) = chunk {
+            rules.push((child, chunk));
+        }
+    }
+    None
+}
+
+/// Group the shortest uniform prefixes for `classifier` by chunk. Chunks
+/// appear in the order of their first zip code, and prefixes in sorted
+/// order.
+fn prefixes_by_chunk(classifier: &Classifier) -> Vec<(&str, Vec<String>)> {
+    let zips = (0..100000).map(|z| format!("{:05}", z)).collect::<Vec<_>>();
+    let chunks = zips.iter()
+        .map(|zip| {
+            classifier.chunk_for(zip)
+                .expect("all zip codes should have a chunk")
+        })
+        .collect::<Vec<_>>();
+    let mut rules = vec![];
+    if let Some(chunk) = uniform_prefixes("", &chunks, &mut rules) {
+        rules.push(("".to_owned(), chunk));
+    }
+    rules.sort();
+
+    let mut grouped: Vec<(&str, Vec<String>)> = vec![];
+    let mut index_for_chunk = HashMap::new();
+    for (prefix, chunk) in rules {
+        let index = *index_for_chunk.entry(chunk).or_insert_with(|| {
+            grouped.push((chunk, vec![]));
+            grouped.len() - 1
+        });
+        grouped[index].1.push(prefix);
+    }
+    grouped.sort_by(|a, b| a.1[0].cmp(&b.1[0]));
+    grouped
+}
+
+/// Write a LookML dimension named `name` which assigns the zip code given
+/// by the SQL expression `zip_sql`, such as `${zip}`, to a chunk.
+pub fn write_dimension(classifier: &Classifier,
+                       name: &str,
+                       zip_sql: &str,
+                       out: &mut dyn Write)
+                       -> Result<()> {
+    let metadata = classifier.metadata();
+    let unknown = classifier.chunk_for("").map_or("NULL".to_owned(), sql_string);
+    writeln!(out,
+             "# Generated by geochunk {}, algorithm version {}. Add this to a view",
+             env!("CARGO_PKG_VERSION"),
+             metadata.version)?;
+    writeln!(out, "# with a zip code, and regenerate it instead of editing it.")?;
+    if let Some(ref census_data) = metadata.census_data {
+        census_data.write_comments("#", out)?;
+    }
+    writeln!(out, "dimension: {} {{", name)?;
+    writeln!(out, "  type: string")?;
+    writeln!(out,
+             "  description: \"Geochunks of about {}, weighted by {}.\"",
+             metadata.target_population,
+             metadata.weights)?;
+    writeln!(out, "  sql: CASE")?;
+    writeln!(out, "    WHEN LENGTH({}) < 5 THEN {}", zip_sql, unknown)?;
+    for (chunk, prefixes) in prefixes_by_chunk(classifier) {
+        let mut conditions = vec![];
+        for len in 0..6 {
+            let matching = prefixes.iter()
+                .filter(|p| p.len() == len)
+                .map(|p| sql_string(p))
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                continue;
+            }
+            let lines = matching
+                .chunks(PREFIXES_PER_LINE)
+                .map(|line| line.join(", "))
+                .collect::<Vec<_>>();
+            conditions.push(format!("LEFT({}, {}) IN ({})",
+                                    zip_sql,
+                                    len,
+                                    lines.join(",\n        ")));
+        }
+        writeln!(out,
+                 "    WHEN {} THEN {}",
+                 conditions.join("\n      OR "),
+                 sql_string(chunk))?;
+    }
+    writeln!(out, "    ELSE {}", unknown)?;
+    writeln!(out, "  END ;;")?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+#[test]
+fn finds_uniform_prefixes() {
+    let _ = env_logger::init();
+    let mut chunks = vec!["a"; 100];
+    for chunk in &mut chunks[20..30] {
+        *chunk = "b";
+    }
+    chunks[25] = "c";
+    let mut rules = vec![];
+    assert_eq!(uniform_prefixes("", &chunks, &mut rules), None);
+    rules.sort();
+    assert_eq!(rules.len(), 19);
+    assert_eq!(rules[0], ("0".to_owned(), "a"));
+    assert_eq!(rules[2], ("20".to_owned(), "b"));
+    assert_eq!(rules[7], ("25".to_owned(), "c"));
+    assert_eq!(rules[12], ("3".to_owned(), "a"));
+    assert_eq!(uniform_prefixes("9", &["x"; 10], &mut rules), Some("x"));
+}
+
+#[test]
+fn writes_dimensions() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut out = vec![];
+    write_dimension(&classifier, "geochunk", "${zip}", &mut out).unwrap();
+    let lookml = String::from_utf8(out).unwrap();
+    assert!(lookml.contains("dimension: geochunk {\n"));
+    assert!(lookml.contains("\n# Weight data vintage: 2010\n"));
+    assert!(lookml.contains("    WHEN LENGTH(${zip}) < 5 THEN NULL\n"));
+    assert!(lookml.ends_with("    ELSE NULL\n  END ;;\n}\n"));
+
+    // Every zip code should match exactly one prefix, for the right chunk.
+    let mut chunk_for_prefix = HashMap::new();
+    for (chunk, prefixes) in prefixes_by_chunk(&classifier) {
+        for prefix in prefixes {
+            assert!(chunk_for_prefix.insert(prefix, chunk).is_none());
+        }
+    }
+    for zip in (0..100000).map(|z| format!("{:05}", z)) {
+        let matching = (0..6)
+            .filter_map(|len| chunk_for_prefix.get(&zip[..len]))
+            .collect::<Vec<_>>();
+        assert_eq!(matching, vec![&classifier.chunk_for(&zip).unwrap()]);
+    }
+}
@@ -8,122 +8,248 @@
 extern crate csv;
 extern crate docopt;
 extern crate env_logger;
-#[macro_use]
 extern crate error_chain;
-#[macro_use]
-extern crate lazy_static;
+extern crate geochunk;
 #[macro_use]
 extern crate log;
-extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
 
 use docopt::Docopt;
-use serde::{Deserialize, Deserializer};
-use serde::de::Error as DeError;
+use std::env;
 use std::io;
+use std::io::prelude::*;
 use std::process;
-use std::result;
 
-mod errors;
-mod zip2010;
+use error_chain::ChainedError;
+use geochunk::errors::*;
+#[cfg(feature = "otel")]
+use geochunk::telemetry;
 
-use errors::*;
-
-/// Specify what data set we should use for generating chunks.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum ChunkType {
-    /// Use 2010 census population data.
-    Zip2010,
-}
+mod commands;
 
-// Implement the `Deserialize` interface so that `docopt` can automatically
-// parse this argument type from a string value.
-impl<'de> Deserialize<'de> for ChunkType {
-    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        match &s[..] {
-            "zip2010" => Ok(ChunkType::Zip2010),
-            _ => {
-                let msg = format!("Unknown chunk type \"{}\", try --help", s);
-                Err(D::Error::custom(msg))
-            }
-        }
-    }
-}
-
-const USAGE: &'static str = "
+/// Our top-level usage. Each command has its own usage, with its options,
+/// in `commands`.
+const USAGE: &str = "
 geochunk - Partition data sets by estimated population.
 
 Usage:
-  geochunk export <type> <population>
-  geochunk csv <type> <population> <input-column>
+  geochunk export [options] <type> <population>
+  geochunk export-lookml [options] <spec>
+  geochunk plan [options] <spec>
+  geochunk explore [options] <spec>
+  geochunk csv [options] <type> <population> <input-column>...
+  geochunk csv [options] [<input-file>]
+  geochunk table [options] <spec> <input-column> <input-file> <output-file>
+  geochunk crosswalk [options] <from-spec> <to-spec>
+  geochunk verify-golden [options] <golden-file>
+  geochunk verify [options] <spec> <input-column> <input-file>
+  geochunk self-check [options] <spec>
+  geochunk daemon [options] --socket=<path> <spec>
+  geochunk serve [options] --flight --listen=<addr> <spec> <input-column>
+  geochunk anonymize [options] [--drop-column=<col>]... <spec> <input-column> <input-file>
+  geochunk expand [options] <spec>
+  geochunk count [options] <spec> <input-column> [<input-file>...]
+  geochunk skew [options] <spec> <input-column> [<input-file>...]
+  geochunk dedupe [options] --key=<col> <spec> <input-column> [<input-file>]
+  geochunk copartition [options] <spec> <output-dir> <left-column> <left-file> <right-column> <right-file>
+  geochunk territories [options] --groups=<n> <spec>
+  geochunk assign-treatment [options] --arms=<list> <spec> <input-column> [<input-file>]
+  geochunk build-geography [options] --weight-field=<name> <boundaries-file> <population> <geography-file>
+  geochunk locate [options] --s2=<population> <lat-column> <lng-column> [<input-file>]
+  geochunk locate [options] <geography-file> <lat-column> <lng-column> [<input-file>]
+  geochunk data-info [options]
+  geochunk data-validate [options] <population-file>
+  geochunk patch-info [options] <patch-file>
+  geochunk specs [options]
+  geochunk completions [options] <shell>
   geochunk (--help | --version)
 
 Options:
-  --help        Show this screen.
-  --version     Show version.
+  --help                   Show this screen. After a command, show the
+                           options of that command.
+  --version                Show version.
 
 Commands:
   export        Export the geochunk mapping for use by another program.
-  csv           Add a geochunk column to a CSV file (used in a pipeline).
+  export-lookml Print a LookML dimension which assigns zip codes to the
+                chunks of <spec>, so Looker users can group by geochunk.
+  plan          List each chunk of <spec> with its population and the zip
+                code prefixes it contains, to review chunks before using
+                them.
+  explore       Interactively browse the chunks of <spec>, look up zip codes
+                and prefixes, and compare other target populations. In a
+                terminal, this opens a full-screen interface; otherwise it
+                reads one command per line (type help for a list).
+  csv           Add a geochunk column to a CSV file (used in a pipeline). If
+                more than one <input-column> is given, add one geochunk
+                column for each. If <type>, <population> and <input-column>
+                are omitted, they must be given by the config file, and we
+                read from <input-file> if present.
+  table         Read a Parquet, Avro or Arrow IPC file, and write it to
+                <output-file> in the same format, with a geochunk column
+                for <input-column> appended. Other columns keep their
+                types, and <input-column> may hold strings or integers.
+                The format is chosen by file extension: .parquet, .avro,
+                or .arrow or .feather for Arrow IPC. This requires the
+                tables feature.
+  crosswalk     Show how the population of each chunk in <from-spec> is
+                divided among the chunks in <to-spec>.
+  verify-golden Fail if any zip code in an earlier export would now be
+                assigned to a different chunk.
+  verify        Recompute the geochunk of every row in a CSV file which was
+                already chunked using <spec>, and fail if any have changed.
+  self-check    Check that every zip code is in exactly one chunk of <spec>,
+                that chunk populations add up, and that malformed and
+                Unicode input is handled safely, and fail if not. This is
+                meant as a smoke test for deployment pipelines. Chunks built
+                with --population-noise always fail, because their
+                populations don't add up.
+  daemon        Listen on the Unix socket given by --socket, and answer each
+                line sent to it with the chunk ID of that zip code, or an
+                empty line if it has none. This lets many short-lived
+                processes share one classifier.
+  serve         Listen on the address given by --listen for Arrow Flight
+                DoExchange calls, and stream back each record batch sent to
+                us with a geochunk column for its <input-column> appended.
+                This requires the flight feature.
+  anonymize     Replace zip codes in a CSV file with geochunks, blanking out
+                any geochunks with fewer than --min-rows rows.
+  expand        List every zip code in the chunks given by --chunks, for
+                building suppression lists from chunk-level selections.
+  count         Count the rows of a CSV file in each geochunk, without
+                writing the whole file back out. Rows without a valid zip
+                code aren't in any chunk, so we report how many of them
+                there were on standard error.
+  skew          Show the chunks of a CSV file with the most and fewest rows
+                compared to their population, to see whether the input is
+                distributed like the population.
+  dedupe        Add a geochunk column to a CSV file, like csv, and remove
+                any row with the same --key as an earlier row. Rows with an
+                empty --key are always kept.
+  copartition   Split two CSV files into <output-dir>/<chunk>/<file>, using
+                the same chunks for both, so that they can be joined on zip
+                code one chunk at a time. Every chunk gets a pair of files,
+                even if it has no rows. Prints the rows written to each
+                chunk, and writes <output-dir>/manifest.json listing each
+                file with its chunk, row count, size and SHA-256 checksum.
+  territories   Merge the chunks of <spec> into --groups territories with
+                similar populations, and print the territory of each chunk.
+  assign-treatment
+                Assign each chunk of <spec> to one of --arms at random, so
+                that each arm has a similar population, and add a column
+                containing the arm of each row, like csv. The same --seed
+                always gives the same arms. With --matched-pairs, similar
+                chunks are paired up first, and each pair is split between
+                the arms.
+  data-info     Describe the census data built into geochunk. Metadata for
+                zip2010 exports includes the same description.
+  data-validate Check a zip code population file, laid out like our
+                built-in data, for duplicate zip codes, invalid zip codes
+                and invalid populations. Prints each problem, with its line
+                number.
+  patch-info    Describe a --zip-patch file. Each row of the file adds a new
+                zip code, which joins the chunk of its parent zip code if
+                it has one, and otherwise the chunk of its prefix. Either
+                way, its estimated population is added to that chunk.
+  specs         List the available chunk types as JSON.
+  completions   Print a completion script for <shell>, which may be bash,
+                zsh or fish.
 
 Types:
   zip2010       Use 2010 Census zip code population data.
+  zip2000       Use 2000 Census zip code population data, from
+                --census2000-file.
+  zip-est<year> Use intercensal zip code population estimates for a year
+                from 2011 to 2019, from --estimates-file.
+  soi_returns   Use IRS SOI counts of individual income tax returns.
+  soi_agi       Use IRS SOI adjusted gross income (in thousands of dollars).
+  area          Use Census land area (in hundredths of a square mile).
+  dma           Use the population in --dma-file, never putting zip codes
+                from different designated market areas in the same chunk.
+  mx_cp         Use the INEGI population of Mexican postal codes, from
+                --mx-cp-file.
+                Options which look up US zip codes can't be used with this
+                type, such as --add-timezone, --add-cd or {state} in
+                --id-template.
+
+Specs:
+  A <spec> combines a type and a population, such as \"zip2010-250000\".
+  It may end with a list of options in brackets, such as
+  \"zip2010-250000[naming=int,new_zip_fallback]\". These options are:
+    naming=int          Name chunks using their integer index.
+    naming=sortable     Name chunks using their integer index, padded with
+                        zeros to a fixed width so they sort as strings.
+    unknown_chunk=<id>  Put invalid zip codes in a chunk named <id>.
+    new_zip_chunk=<id>  The same as --new-zip-chunk=<id>.
+    new_zip_fallback    The same as --new-zip-fallback.
+    respect_states      Never put zip codes from different states in the
+                        same chunk.
+    min_pop=<n>         Merge chunks of leftover prefixes with fewer than
+                        <n> people into a neighboring chunk, where possible.
+
+Environment:
+  Each option can also be set by an environment variable, such as
+  GEOCHUNK_OUTPUT_COLUMN=chunk for --output-column=chunk. Options without a
+  value are set to true or false, as in GEOCHUNK_STRICT=true. For csv,
+  GEOCHUNK_SPEC and GEOCHUNK_INPUT_COLUMNS (separated by commas) work like
+  the spec and input-columns config file keys. These override the config
+  file, and are overridden by the command line. Each command only reads the
+  variables of options it accepts. Other GEOCHUNK_* variables are ignored,
+  with a warning.
+
+Exit status:
+  0  Success.
+  1  Invalid arguments or input data.
+  2  An I/O error, such as a missing file.
+  3  Success, but some rows were skipped, or had zip codes which we
+     couldn't put in any chunk.
 ";
 
-/// Our command-line arguments, which can be automatically deserialized by
-/// `docopt`.
-#[derive(Debug, Deserialize)]
-struct Args {
-    cmd_export: bool,
-    cmd_csv: bool,
-    arg_type: Option<ChunkType>,
-    arg_population: Option<u64>,
-    arg_input_column: Option<String>,
-    flag_version: bool,
+/// Call `run`, print out any errors, and exit with an appropriate code.
+fn main() {
+    let code = match run() {
+        Ok(code) => code,
+        Err(ref err) => {
+            write!(&mut io::stderr(), "{}", err.display_chain())
+                .expect("Error writing to stderr");
+            if err.is_io_error() {
+                commands::EXIT_IO_ERROR
+            } else {
+                commands::EXIT_INVALID_INPUT
+            }
+        }
+    };
+    process::exit(code);
 }
 
-// Make a `main` function that calls `run` and prints out any errors.
-quick_main!(run);
-
-/// Our actual `main` function, called by the `quick_main!` macro above.
-fn run() -> Result<()> {
+/// Our actual `main` function, which returns our exit code.
+fn run() -> Result<i32> {
     env_logger::init().expect("Could not initialize logging");
-    let args: Args = Docopt::new(USAGE)
-        .and_then(|d| d.deserialize())
-        .unwrap_or_else(|e| e.exit());
-    trace!("{:?}", args);
+    let argv = env::args().collect::<Vec<_>>();
 
-    // We have to handle `--version` ourselves.
-    if args.flag_version {
-        println!("geochunk {}", env!("CARGO_PKG_VERSION"));
-        process::exit(0);
-    }
+    // Export traces of this command, if we were asked to.
+    #[cfg(feature = "otel")]
+    let _telemetry = telemetry::init()?;
 
-    // Generate our table of chunks.
-    let population = args.arg_population
-        .expect("Population should have been required by docopt");
-    let classifier = zip2010::Classifier::new(population);
-
-    // Dispatch to an appropriate command handler.
-    if args.cmd_export {
-        let stdout = io::stdout();
-        classifier.export(&mut stdout.lock())?;
-    } else if args.cmd_csv {
-        let stdin = io::stdin();
-        let stdout = io::stdout();
-        let column = args.arg_input_column
-            .expect("Column should have been required by docopt");
-        classifier
-            .transform_csv(&column, &mut stdin.lock(), &mut stdout.lock())?;
-    } else {
-        unreachable!("unknown subcommand, should have been caught by docopt");
+    // Dispatch to an appropriate command, which parses its own options.
+    if let Some(command) = argv.get(1).and_then(|name| commands::find(name)) {
+        #[cfg(feature = "otel")]
+        let _root = telemetry::command_span(command.name).entered();
+        return (command.run)(argv);
     }
 
-    Ok(())
+    // Otherwise, we only have `--help` and `--version`, which docopt
+    // handles by exiting.
+    Docopt::new(USAGE)
+        .and_then(|d| {
+                      d.argv(argv.iter())
+                          .version(Some(format!("geochunk {}", env!("CARGO_PKG_VERSION"))))
+                          .parse()
+                  })
+        .unwrap_or_else(|e| e.exit());
+    unreachable!("docopt should have exited for --help or --version");
 }
@@ -0,0 +1,217 @@
+//! Describing the per-chunk files we wrote, so that downstream loaders can
+//! check that they have all of them without listing directories.
+
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+#[cfg(all(test, feature = "data-zip2010"))]
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+#[cfg(all(test, feature = "data-zip2010"))]
+use std::path::PathBuf;
+#[cfg(all(test, feature = "data-zip2010"))]
+use std::process;
+
+use errors::*;
+use shard::ShardInfo;
+use zip2010::{Classifier, Metadata};
+
+/// The name of the manifest we write to an output directory.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// A list of the files written to an output directory.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Metadata describing the chunks used to split our files.
+    pub metadata: Metadata,
+    /// Every file we wrote.
+    pub files: Vec<ManifestFile>,
+    /// A checksum of the inputs and parameters used to write these files,
+    /// from `hash_job`, so that we can tell if the job has already been
+    /// run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_hash: Option<String>,
+}
+
+/// A file listed in a `Manifest`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// The path of this file, relative to the output directory, using `/`
+    /// as a separator.
+    pub path: String,
+    /// The chunk ID of the rows in this file.
+    pub chunk: String,
+    /// The number of rows in this file, not counting headers.
+    pub rows: u64,
+    /// The size of this file, in bytes.
+    pub bytes: u64,
+    /// The SHA-256 checksum of this file, in hex.
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Create an empty manifest for files split using `classifier`.
+    pub fn new(classifier: &Classifier) -> Manifest {
+        Manifest {
+            metadata: classifier.metadata(),
+            files: vec![],
+            job_hash: None,
+        }
+    }
+
+    /// Read the manifest in `output_dir`, or return `None` if there isn't
+    /// one.
+    pub fn read_from_dir(output_dir: &Path) -> Result<Option<Manifest>> {
+        let path = output_dir.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let read_err = || format!("could not read {}", path.display());
+        let file = File::open(&path).chain_err(read_err)?;
+        Ok(Some(serde_json::from_reader(file).chain_err(read_err)?))
+    }
+
+    /// Does every file listed in this manifest exist in `output_dir`, with
+    /// the expected size? We don't recompute checksums.
+    pub fn is_complete(&self, output_dir: &Path) -> bool {
+        self.files.iter().all(|f| {
+            fs::metadata(output_dir.join(&f.path)).is_ok_and(|m| m.len() == f.bytes)
+        })
+    }
+
+    /// Add the shards written for each chunk, such as those returned by
+    /// `partition::partition_csv`. Shard paths must be inside `output_dir`.
+    pub fn add_shards(&mut self,
+                      output_dir: &Path,
+                      shards: &BTreeMap<String, Vec<ShardInfo>>)
+                      -> Result<()> {
+        for (chunk, chunk_shards) in shards {
+            for shard in chunk_shards {
+                let rel_path = shard
+                    .path
+                    .strip_prefix(output_dir)
+                    .chain_err(|| {
+                                   format!("{} is not inside {}",
+                                           shard.path.display(),
+                                           output_dir.display())
+                               })?;
+                let path = rel_path
+                    .iter()
+                    .map(|part| part.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                self.files
+                    .push(ManifestFile {
+                              path,
+                              chunk: chunk.to_owned(),
+                              rows: shard.rows,
+                              bytes: shard.bytes,
+                              sha256: shard.sha256.clone(),
+                          });
+            }
+        }
+        Ok(())
+    }
+
+    /// Write this manifest to `MANIFEST_FILE_NAME` in `output_dir`.
+    pub fn write_to_dir(&self, output_dir: &Path) -> Result<()> {
+        let path = output_dir.join(MANIFEST_FILE_NAME);
+        let write_err = || format!("could not write {}", path.display());
+        let mut file = File::create(&path).chain_err(write_err)?;
+        serde_json::to_writer_pretty(&mut file, self).chain_err(write_err)?;
+        writeln!(file).chain_err(write_err)?;
+        Ok(())
+    }
+}
+
+/// Compute a checksum of `params` and the contents of each of `inputs`. If
+/// `params` describes everything which affects a job's output, the same
+/// checksum means the job would produce the same output.
+pub fn hash_job(params: &str, inputs: &[&Path]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update((params.len() as u64).to_le_bytes());
+    hasher.update(params.as_bytes());
+    for path in inputs {
+        let read_err = || format!("could not read {}", path.display());
+        let mut file = File::open(path).chain_err(read_err)?;
+        hasher.update(file.metadata().chain_err(read_err)?.len().to_le_bytes());
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let count = file.read(&mut buf).chain_err(read_err)?;
+            if count == 0 {
+                break;
+            }
+            hasher.update(&buf[..count]);
+        }
+    }
+    Ok(hasher
+           .finalize()
+           .iter()
+           .map(|b| format!("{:02x}", b))
+           .collect())
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn lists_shards_relative_to_output_dir() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut shards = BTreeMap::new();
+    shards.insert("902_0".to_owned(),
+                  vec![ShardInfo {
+                           path: PathBuf::from("out/902_0/people.csv"),
+                           rows: 2,
+                           bytes: 30,
+                           sha256: "abc".to_owned(),
+                       }]);
+    let mut manifest = Manifest::new(&classifier);
+    manifest.add_shards(Path::new("out"), &shards).unwrap();
+    assert_eq!(manifest.files,
+               vec![ManifestFile {
+                        path: "902_0/people.csv".to_owned(),
+                        chunk: "902_0".to_owned(),
+                        rows: 2,
+                        bytes: 30,
+                        sha256: "abc".to_owned(),
+                    }]);
+    assert!(manifest.add_shards(Path::new("elsewhere"), &shards).is_err());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn recognizes_completed_jobs() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let dir = env::temp_dir().join(format!("geochunk-manifest-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    fs::write(&input, "zip\n90210\n").unwrap();
+    let hash = hash_job("params", &[&input]).unwrap();
+    assert_ne!(hash, hash_job("other params", &[&input]).unwrap());
+    assert_eq!(Manifest::read_from_dir(&dir).unwrap(), None);
+
+    let mut manifest = Manifest::new(&classifier);
+    manifest.job_hash = Some(hash);
+    manifest
+        .files
+        .push(ManifestFile {
+                  path: "input.csv".to_owned(),
+                  chunk: "902_0".to_owned(),
+                  rows: 1,
+                  bytes: 10,
+                  sha256: "unchecked".to_owned(),
+              });
+    manifest.write_to_dir(&dir).unwrap();
+    let read = Manifest::read_from_dir(&dir).unwrap().unwrap();
+    assert_eq!(read, manifest);
+    assert!(read.is_complete(&dir));
+
+    fs::write(&input, "zip\n90210\n07712\n").unwrap();
+    assert!(!read.is_complete(&dir));
+    assert_ne!(read.job_hash.unwrap(), hash_job("params", &[&input]).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,104 @@
+//! Mexican postal codes, or códigos postales. Like US zip codes, they have
+//! five digits, and codes which share a prefix are usually near each other,
+//! so we can chunk them the same way. The first two digits identify a
+//! state, or part of one.
+//!
+//! INEGI publishes census population by locality and by AGEB, not by postal
+//! code, so we don't build in any Mexican data. Instead, we read a CSV file
+//! which assigns INEGI population to postal codes, such as one made by
+//! joining the census to SEPOMEX's postal code catalog.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+use census::normalize_zip;
+use errors::*;
+use number::{self, Rounding};
+
+/// Columns which may contain the postal code, in the order we look for
+/// them. SEPOMEX calls its postal code column `d_codigo`.
+const CP_COLUMNS: &[&str] = &["cp", "codigo_postal", "d_codigo", "zip"];
+
+/// Columns which may contain the population, in the order we look for
+/// them. INEGI calls total population `POBTOT`.
+const POPULATION_COLUMNS: &[&str] = &["population", "pobtot", "poblacion"];
+
+/// Values which INEGI uses for data it has withheld to protect privacy, or
+/// which isn't available. We count these as zero.
+const WITHHELD: &[&str] = &["*", "N/D"];
+
+/// Read a CSV file with a postal code column, such as `cp` or `d_codigo`,
+/// and a population column, such as `population` or `POBTOT`. A postal code
+/// may appear on several rows, such as one for each locality it contains,
+/// in which case we add them up.
+pub fn load_cp_populations(input: &mut dyn Read,
+                           rounding: Rounding)
+                           -> Result<Vec<(String, u64)>> {
+    let mut rdr = csv::Reader::from_reader(input);
+    let headers = rdr.headers()?.to_owned();
+    let find_column = |names: &[&str]| {
+        names.iter().filter_map(|name| {
+            headers
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(name))
+        }).next()
+    };
+    let cp_idx = find_column(CP_COLUMNS).ok_or_else(|| Error::no_such_column("cp"))?;
+    let pop_idx = find_column(POPULATION_COLUMNS)
+        .ok_or_else(|| Error::no_such_column("population"))?;
+
+    let mut totals = HashMap::<String, f64>::new();
+    for row in rdr.records() {
+        let row = row?;
+        // Postal codes in Mexico City start with a zero, which is often lost
+        // when they're stored as numbers.
+        let cp = normalize_zip(&row[cp_idx])
+            .ok_or_else(|| Error::invalid_weight(&row[cp_idx], row.position()))?;
+        let value = row[pop_idx].trim();
+        let pop = if WITHHELD.contains(&value) {
+            0.0
+        } else {
+            number::parse_number(value)
+                .filter(|pop| *pop >= 0.0)
+                .ok_or_else(|| Error::invalid_weight(value, row.position()))?
+        };
+        *totals.entry(cp).or_insert(0.0) += pop;
+    }
+
+    let mut populations = totals
+        .into_iter()
+        .map(|(cp, total)| (cp, rounding.round(total) as u64))
+        .collect::<Vec<_>>();
+    populations.sort();
+    Ok(populations)
+}
+
+#[test]
+fn loads_cp_populations() {
+    let _ = env_logger::init();
+    let input = "\
+CVE_ENT,NOM_LOC,d_codigo,POBTOT
+09,Ciudad de México,6600,1200.4
+09,Ciudad de México,06600,300.4
+14,Guadalajara,44100,900
+14,Tlaquepaque,45500,*
+";
+    let populations = load_cp_populations(&mut input.as_bytes(), Rounding::Nearest).unwrap();
+    assert_eq!(populations,
+               vec![("06600".to_owned(), 1501),
+                    ("44100".to_owned(), 900),
+                    ("45500".to_owned(), 0)]);
+
+    let simple = "cp,population\n01000,25\n";
+    let populations = load_cp_populations(&mut simple.as_bytes(), Rounding::Nearest).unwrap();
+    assert_eq!(populations, vec![("01000".to_owned(), 25)]);
+    for bad in &["cp\n01000\n", "cp,population\nCDMX,25\n", "cp,population\n01000,-5\n",
+                 "cp,population\n01000,lots\n"] {
+        assert!(load_cp_populations(&mut bad.as_bytes(), Rounding::Nearest).is_err(),
+                "{}",
+                bad);
+    }
+}
@@ -0,0 +1,130 @@
+//! Memory-mapping local input files, so that we can read huge files without
+//! copying them through `read` system calls, and split them into byte ranges
+//! which can be scanned in parallel.
+//!
+//! A mapped file must not be truncated while we're using it, or reading the
+//! missing pages will crash our process. This is the usual tradeoff for
+//! memory-mapped input, and it's why we only map files given on the command
+//! line, never standard input. We also only map regular files: pipes,
+//! FIFOs and paths like `/dev/stdin` report a length of zero, so they need
+//! to be read normally. `Mmap` needs the `mmap` feature.
+
+#[cfg(test)]
+use env_logger;
+#[cfg(feature = "mmap")]
+use memmap2;
+#[cfg(feature = "mmap")]
+use std::fs::File;
+#[cfg(feature = "mmap")]
+use std::ops::Deref;
+
+use dialect::Dialect;
+#[cfg(feature = "mmap")]
+use errors::*;
+
+/// A read-only view of the contents of a file.
+#[cfg(feature = "mmap")]
+pub struct Mmap(memmap2::Mmap);
+
+#[cfg(feature = "mmap")]
+impl Mmap {
+    /// Map the contents of `file` into memory, or return `None` if it isn't
+    /// a regular file, in which case it should be read normally.
+    pub fn open(file: &File) -> Result<Option<Mmap>> {
+        if !file.metadata()?.is_file() {
+            return Ok(None);
+        }
+        // SAFETY: The mapping is read-only, and stays valid as long as
+        // nobody truncates the file while we're using it, as described
+        // above.
+        let mapped = unsafe { memmap2::Mmap::map(file)? };
+        Ok(Some(Mmap(mapped)))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Split `data` into at most `parts` pieces of similar size, each ending at
+/// the end of a CSV record. We track quotes so that we never split inside
+/// a quoted field, and skip comment lines, which may contain stray quotes.
+pub fn split_records<'d>(data: &'d [u8], dialect: &Dialect, parts: usize) -> Vec<&'d [u8]> {
+    let target_len = data.len() / parts.max(1) + 1;
+    let mut pieces = vec![];
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut at_line_start = true;
+    let mut i = 0;
+    while i < data.len() {
+        let b = data[i];
+        if at_line_start && !in_quotes && Some(b) == dialect.comment {
+            // Skip to the end of our comment, leaving `i` on the newline.
+            i = data[i..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(data.len(), |p| i + p);
+            continue;
+        }
+        at_line_start = false;
+        if in_quotes && Some(b) == dialect.escape {
+            i += 2;
+            continue;
+        }
+        if b == dialect.quote {
+            in_quotes = !in_quotes;
+        } else if b == b'\n' && !in_quotes {
+            at_line_start = true;
+            if i + 1 - start >= target_len {
+                pieces.push(&data[start..i + 1]);
+                start = i + 1;
+            }
+        }
+        i += 1;
+    }
+    if start < data.len() {
+        pieces.push(&data[start..]);
+    }
+    pieces
+}
+
+#[test]
+#[cfg(feature = "mmap")]
+fn maps_files_into_memory() {
+    let _ = env_logger::init();
+    let file = File::open("Cargo.toml").unwrap();
+    let mapped = Mmap::open(&file).unwrap().unwrap();
+    assert!(mapped.starts_with(b"[package]\n"));
+    assert_eq!(mapped.len() as u64, file.metadata().unwrap().len());
+
+    // We don't map directories, devices or pipes.
+    #[cfg(unix)]
+    assert!(Mmap::open(&File::open("/dev/null").unwrap()).unwrap().is_none());
+}
+
+#[test]
+fn splits_data_at_record_boundaries() {
+    let _ = env_logger::init();
+    let dialect = Dialect {
+        comment: Some(b'#'),
+        ..Dialect::default()
+    };
+    let data = b"a,\"b\nc\"\n# \"\nd,e\nf,g\n";
+    let pieces = split_records(data, &dialect, 4);
+    assert_eq!(pieces,
+               vec![&b"a,\"b\nc\"\n"[..], &b"# \"\nd,e\n"[..], &b"f,g\n"[..]]);
+    assert_eq!(split_records(data, &dialect, 1), vec![&data[..]]);
+    assert!(split_records(b"", &dialect, 4).is_empty());
+}
@@ -0,0 +1,100 @@
+//! Adding Laplace noise to the chunk populations we report, for teams which
+//! publish chunk-level statistics under a differential privacy policy.
+//!
+//! The noise for each chunk depends only on our seed and the chunk ID, so
+//! reporting the same chunk twice gives the same noisy population, instead
+//! of letting readers average away the noise.
+
+#[cfg(test)]
+use env_logger;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use errors::*;
+#[cfg(all(test, feature = "data-zip2010"))]
+use zip2010::Classifier;
+
+/// Laplace noise with a fixed scale.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LaplaceNoise {
+    /// The scale of our noise. For a population with sensitivity 1, this is
+    /// `1 / epsilon`.
+    scale: f64,
+    /// The seed used to generate our noise. Anybody who knows this can
+    /// remove the noise, so it should be kept secret.
+    seed: u64,
+}
+
+impl LaplaceNoise {
+    /// Create noise with the specified scale. If `seed` is `None`, we pick
+    /// one at random, so that our noise can't be reproduced.
+    pub fn new(scale: f64, seed: Option<u64>) -> Result<LaplaceNoise> {
+        if !(scale.is_finite() && scale > 0.0) {
+            return Err(format!("noise scale must be a positive number, not {}", scale).into());
+        }
+        let seed = seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        Ok(LaplaceNoise { scale, seed })
+    }
+
+    /// The noise to add to the value identified by `key`.
+    pub fn sample(&self, key: &str) -> f64 {
+        // Hash our seed and key to get a uniform value in (-0.5, 0.5).
+        let hash = Sha256::new()
+            .chain_update(self.seed.to_le_bytes())
+            .chain_update(key.as_bytes())
+            .finalize();
+        let mut bits = [0; 8];
+        bits.copy_from_slice(&hash[..8]);
+        // Use 53 bits, which is all an `f64` can hold, and avoid 0.
+        let uniform = ((u64::from_le_bytes(bits) >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        let u = uniform - 0.5;
+        -self.scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+    }
+
+    /// Add noise to `population`, rounding to the nearest whole number and
+    /// never going below zero.
+    pub fn perturb(&self, key: &str, population: u64) -> u64 {
+        (population as f64 + self.sample(key)).round().max(0.0) as u64
+    }
+}
+
+#[test]
+fn samples_repeatable_laplace_noise() {
+    let _ = env_logger::init();
+    let noise = LaplaceNoise::new(10.0, Some(42)).unwrap();
+    assert_eq!(noise.sample("902_0"), noise.sample("902_0"));
+    assert_ne!(noise.sample("902_0"), noise.sample("077_0"));
+    assert_ne!(noise.sample("902_0"),
+               LaplaceNoise::new(10.0, Some(43)).unwrap().sample("902_0"));
+    assert_eq!(noise.perturb("902_0", 0), noise.perturb("902_0", 0));
+
+    // A Laplace distribution with scale b has mean 0 and mean absolute
+    // deviation b.
+    let samples = (0..10000)
+        .map(|i| noise.sample(&i.to_string()))
+        .collect::<Vec<_>>();
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    let mad = samples.iter().map(|s| s.abs()).sum::<f64>() / samples.len() as f64;
+    assert!(mean.abs() < 0.5, "mean was {}", mean);
+    assert!((mad - 10.0).abs() < 0.5, "mean absolute deviation was {}", mad);
+
+    assert!(LaplaceNoise::new(0.0, None).is_err());
+    assert!(LaplaceNoise::new(-1.0, None).is_err());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn adds_noise_to_chunk_populations() {
+    let _ = env_logger::init();
+    let exact = Classifier::new(250000);
+    let noise = LaplaceNoise::new(1000.0, Some(42)).unwrap();
+    let noisy = exact.clone().with_population_noise(&noise);
+    assert_eq!(noisy.chunk_for("90210"), exact.chunk_for("90210"));
+    assert_eq!(noisy.chunk_population("902_0"),
+               Some(noise.perturb("902_0", exact.chunk_population("902_0").unwrap())));
+    assert!(exact
+                .chunk_ids()
+                .iter()
+                .any(|c| noisy.chunk_population(c) != exact.chunk_population(c)));
+}
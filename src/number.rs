@@ -0,0 +1,150 @@
+//! Parsing the numbers in weight files supplied by our users. These are
+//! often exported from spreadsheets, so instead of clean integers we see
+//! values like `"1,234"`, `12 345` or `1234.56`.
+
+#[cfg(test)]
+use env_logger;
+use std::str::FromStr;
+
+use errors::*;
+
+/// Characters which people use to separate groups of thousands.
+const GROUP_SEPARATORS: &[char] = &[',', '_', ' ', '\'', '\u{a0}', '\u{202f}'];
+
+/// How to turn a fractional weight into a whole number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round to the nearest whole number, rounding halves away from zero.
+    #[default]
+    Nearest,
+    /// Round towards negative infinity.
+    Down,
+    /// Round towards positive infinity.
+    Up,
+}
+
+impl FromStr for Rounding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Rounding> {
+        match s {
+            "nearest" => Ok(Rounding::Nearest),
+            "down" => Ok(Rounding::Down),
+            "up" => Ok(Rounding::Up),
+            _ => Err(format!("unknown rounding mode {:?}", s).into()),
+        }
+    }
+}
+
+impl Rounding {
+    /// Round `value` to a whole number.
+    pub fn round(self, value: f64) -> f64 {
+        match self {
+            Rounding::Nearest => value.round(),
+            Rounding::Down => value.floor(),
+            Rounding::Up => value.ceil(),
+        }
+    }
+}
+
+/// Parse a number, ignoring surrounding whitespace and quotes, and
+/// thousands separators like those in `1,234,567` or `1 234 567`. Each
+/// separator must be followed by exactly three digits, so that a decimal
+/// comma like `1,5` is rejected instead of being read as 15. Returns `None`
+/// if `value` isn't a finite number.
+pub fn parse_number(value: &str) -> Option<f64> {
+    normalize(value)?.parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+/// Parse a weight like `parse_number`, rounding it to a whole number.
+/// Returns `None` if `value` isn't a number, or if it's negative once
+/// rounded.
+pub fn parse_weight(value: &str, rounding: Rounding) -> Option<u64> {
+    let value = normalize(value)?;
+    // Parse whole numbers exactly, because an `f64` only has 53 bits.
+    if let Ok(n) = value.parse::<u64>() {
+        return Some(n);
+    }
+    let n = rounding.round(value.parse::<f64>().ok()?);
+    if n.is_finite() && n > -1.0 && n < u64::MAX as f64 {
+        Some(n as u64)
+    } else {
+        None
+    }
+}
+
+/// Strip the quotes and separators from `value`, leaving something which
+/// Rust's number parsers understand.
+fn normalize(value: &str) -> Option<String> {
+    let mut value = value.trim();
+    for quote in &['"', '\''] {
+        if value.len() >= 2 && value.starts_with(*quote) && value.ends_with(*quote) {
+            value = value[1..value.len() - 1].trim();
+            break;
+        }
+    }
+    if value.parse::<f64>().is_ok() {
+        return Some(value.to_owned());
+    }
+
+    let (sign, unsigned) = match value.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", value.strip_prefix('+').unwrap_or(value)),
+    };
+    let (whole, fraction) = match unsigned.find('.') {
+        Some(i) => (&unsigned[..i], &unsigned[i..]),
+        None => (unsigned, ""),
+    };
+    let separator = whole.chars().find(|c| !c.is_ascii_digit())?;
+    if !GROUP_SEPARATORS.contains(&separator) {
+        return None;
+    }
+    let groups = whole.split(separator).collect::<Vec<_>>();
+    let grouped = (1..=3).contains(&groups[0].len()) &&
+                  groups[1..].iter().all(|g| g.len() == 3) &&
+                  groups.iter().all(|g| g.bytes().all(|b| b.is_ascii_digit()));
+    if grouped {
+        Some(format!("{}{}{}", sign, groups.concat(), fraction))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn parses_formatted_numbers() {
+    let _ = env_logger::init();
+    let valid = [("1234", 1234.0),
+                 (" 1234 ", 1234.0),
+                 ("\"1234\"", 1234.0),
+                 ("'1,234'", 1234.0),
+                 ("1,234,567", 1234567.0),
+                 ("1 234 567.5", 1234567.5),
+                 ("1\u{a0}234", 1234.0),
+                 ("1_234", 1234.0),
+                 ("-1,234.25", -1234.25),
+                 ("12.5", 12.5),
+                 ("1e3", 1000.0)];
+    for &(value, expected) in &valid {
+        assert_eq!(parse_number(value), Some(expected), "{:?}", value);
+    }
+    let invalid = ["", "lots", "1,5", "1,2345", "1234,567", "1,234 567", "1,,234",
+                   "\"1234", "inf", "NaN", "12,34,567"];
+    for value in &invalid {
+        assert_eq!(parse_number(value), None, "{:?}", value);
+    }
+}
+
+#[test]
+fn rounds_weights() {
+    let _ = env_logger::init();
+    assert_eq!(parse_weight("1,234.5", Rounding::Nearest), Some(1235));
+    assert_eq!(parse_weight("1,234.5", Rounding::Down), Some(1234));
+    assert_eq!(parse_weight("1,234.2", Rounding::Up), Some(1235));
+    assert_eq!(parse_weight("-0.2", Rounding::Nearest), Some(0));
+    assert_eq!(parse_weight("-0.2", Rounding::Down), None);
+    assert_eq!(parse_weight("-5", Rounding::Nearest), None);
+    assert_eq!(parse_weight("18446744073709551615", Rounding::Nearest),
+               Some(u64::MAX));
+    assert_eq!("up".parse::<Rounding>().unwrap(), Rounding::Up);
+    assert!("sideways".parse::<Rounding>().is_err());
+}
@@ -0,0 +1,421 @@
+//! Writing our output as ORC files, which warehouses like Hive can load
+//! without converting them from CSV first. This requires the `orc` feature.
+//!
+//! Every column is written as an ORC `string`, exactly as it would appear
+//! in our CSV output, so that both formats hold the same data. Input files
+//! without a header row get Hive-style column names like `_col0`, since ORC
+//! columns must be named.
+
+use arrow::array::{ArrayRef, StringBuilder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::{RecordBatch, RecordBatchOptions};
+use csv;
+#[cfg(test)]
+use env_logger;
+#[cfg(test)]
+use orc_rust::ArrowReaderBuilder;
+use orc_rust::compression::CompressionType;
+use orc_rust::{ArrowWriter, ArrowWriterBuilder};
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+#[cfg(test)]
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+#[cfg(test)]
+use std::process;
+use std::rc::Rc;
+use std::str::{from_utf8, FromStr};
+use std::sync::Arc;
+
+use dialect::Dialect;
+use errors::*;
+use partition::ChunkedRows;
+use shard::{HashingFile, ShardInfo};
+use zip2010::Classifier;
+
+/// How many rows we collect before encoding them.
+const BATCH_ROWS: usize = 1024;
+
+/// How many bytes of rows `partition_csv` keeps in memory across all its
+/// chunks. ORC files are written a stripe at a time, so once our chunks
+/// hold this much between them, we write out a stripe for each of them.
+const MAX_BUFFERED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// How to compress the ORC files we write.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OrcCompression {
+    /// Don't compress our files.
+    None,
+    /// Compress using zlib, which is Hive's default.
+    #[default]
+    Zlib,
+    /// Compress using Snappy.
+    Snappy,
+    /// Compress using LZ4.
+    Lz4,
+    /// Compress using Zstandard.
+    Zstd,
+}
+
+impl OrcCompression {
+    /// The codec `orc_rust` should use.
+    fn codec(self) -> Option<CompressionType> {
+        match self {
+            OrcCompression::None => None,
+            OrcCompression::Zlib => Some(CompressionType::Zlib),
+            OrcCompression::Snappy => Some(CompressionType::Snappy),
+            OrcCompression::Lz4 => Some(CompressionType::Lz4),
+            OrcCompression::Zstd => Some(CompressionType::Zstd),
+        }
+    }
+}
+
+impl FromStr for OrcCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OrcCompression> {
+        match s {
+            "none" => Ok(OrcCompression::None),
+            "zlib" => Ok(OrcCompression::Zlib),
+            "snappy" => Ok(OrcCompression::Snappy),
+            "lz4" => Ok(OrcCompression::Lz4),
+            "zstd" => Ok(OrcCompression::Zstd),
+            _ => {
+                Err(format!("unknown ORC compression {:?}, expected none, zlib, snappy, \
+                             lz4 or zstd",
+                            s)
+                            .into())
+            }
+        }
+    }
+}
+
+/// Convert an error from `orc_rust` or `arrow` into one of ours.
+fn orc_error<E: ::std::fmt::Display>(err: E) -> Error {
+    format!("could not write ORC data: {}", err).into()
+}
+
+/// Writes CSV rows to an ORC file. See `transform::transform_csv_to_orc`.
+pub struct OrcWriter<W: Write> {
+    /// Where we write our file, until we know our columns.
+    out: Option<W>,
+    compression: OrcCompression,
+    /// Our writer, once we know our columns.
+    wtr: Option<ArrowWriter<W>>,
+    schema: Option<SchemaRef>,
+    /// The rows which we haven't encoded yet, one builder per column.
+    columns: Vec<StringBuilder>,
+    /// How many rows are in `columns`.
+    batch_rows: usize,
+    /// How many bytes of rows we've written since `flush_stripe`, which is
+    /// roughly how much memory they're taking up.
+    stripe_bytes: u64,
+    /// How many rows we've written, not counting headers.
+    rows: u64,
+}
+
+impl<W: Write> OrcWriter<W> {
+    /// Write an ORC file to `out`.
+    pub fn new(out: W, compression: OrcCompression) -> OrcWriter<W> {
+        OrcWriter {
+            out: Some(out),
+            compression,
+            wtr: None,
+            schema: None,
+            columns: vec![],
+            batch_rows: 0,
+            stripe_bytes: 0,
+            rows: 0,
+        }
+    }
+
+    /// Name our columns, and start writing our file. This must be called
+    /// before `write_row`, or else we'll make up names.
+    pub fn write_headers(&mut self, headers: &csv::ByteRecord) -> Result<()> {
+        let out = self.out.take().ok_or("ORC headers were written twice")?;
+        let fields = headers
+            .iter()
+            .map(|name| {
+                     let name = from_utf8(name).chain_err(|| {
+                         "ORC column names must be valid UTF-8; try --encoding"
+                     })?;
+                     Ok(Field::new(name, DataType::Utf8, false))
+                 })
+            .collect::<Result<Vec<_>>>()?;
+        let schema = Arc::new(Schema::new(fields));
+        let mut builder = ArrowWriterBuilder::new(out, schema.clone());
+        if let Some(codec) = self.compression.codec() {
+            builder = builder.with_compression(codec);
+        }
+        self.wtr = Some(builder.try_build().map_err(orc_error)?);
+        self.columns = (0..headers.len()).map(|_| StringBuilder::new()).collect();
+        self.schema = Some(schema);
+        Ok(())
+    }
+
+    /// Write a row.
+    pub fn write_row(&mut self, row: &csv::ByteRecord) -> Result<()> {
+        if self.wtr.is_none() {
+            let headers = (0..row.len())
+                .map(|i| format!("_col{}", i))
+                .collect::<csv::ByteRecord>();
+            self.write_headers(&headers)?;
+        }
+        if row.len() != self.columns.len() {
+            return Err(Error::ragged_row(row.len(), self.columns.len(), row.position()));
+        }
+        if from_utf8(row.as_slice()).is_err() {
+            return Err(format!("row {} is not valid UTF-8, which ORC strings must be; \
+                                try --encoding",
+                               self.rows + 1)
+                               .into());
+        }
+        for (column, field) in self.columns.iter_mut().zip(row) {
+            column.append_value(from_utf8(field).expect("checked above"));
+        }
+        self.rows += 1;
+        self.batch_rows += 1;
+        self.stripe_bytes += row.as_slice().len() as u64;
+        if self.batch_rows >= BATCH_ROWS {
+            self.encode_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Encode the rows in `columns`.
+    fn encode_batch(&mut self) -> Result<()> {
+        if self.batch_rows == 0 {
+            return Ok(());
+        }
+        let (wtr, schema) = match (self.wtr.as_mut(), self.schema.as_ref()) {
+            (Some(wtr), Some(schema)) => (wtr, schema),
+            _ => return Ok(()),
+        };
+        let arrays = self.columns
+            .iter_mut()
+            .map(|c| Arc::new(c.finish()) as ArrayRef)
+            .collect::<Vec<_>>();
+        let options = RecordBatchOptions::new().with_row_count(Some(self.batch_rows));
+        let batch = RecordBatch::try_new_with_options(schema.clone(), arrays, &options)
+            .map_err(orc_error)?;
+        wtr.write(&batch).map_err(orc_error)?;
+        self.batch_rows = 0;
+        Ok(())
+    }
+
+    /// Write out any rows we're holding as a complete stripe.
+    fn flush_stripe(&mut self) -> Result<()> {
+        self.encode_batch()?;
+        if self.stripe_bytes > 0 {
+            if let Some(ref mut wtr) = self.wtr {
+                wtr.flush_stripe().map_err(orc_error)?;
+            }
+            self.stripe_bytes = 0;
+        }
+        Ok(())
+    }
+
+    /// Finish writing our file, and return how many rows we wrote.
+    pub fn finish(mut self) -> Result<u64> {
+        if self.wtr.is_none() {
+            self.write_headers(&csv::ByteRecord::new())?;
+        }
+        self.encode_batch()?;
+        let wtr = self.wtr.take().expect("should have created an ORC writer");
+        wtr.close().map_err(orc_error)?;
+        Ok(self.rows)
+    }
+}
+
+/// A file which we open each time we append to it, so that we can write to
+/// thousands of files without running out of file descriptors. `orc_rust`
+/// only writes whole stripes, so this happens rarely.
+struct AppendFile {
+    path: PathBuf,
+}
+
+impl Write for AppendFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        OpenOptions::new().append(true).open(&self.path)?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The ORC file we're writing for one chunk in `partition_csv`.
+struct ChunkFile {
+    wtr: OrcWriter<HashingFile>,
+    path: PathBuf,
+    hasher: Rc<RefCell<Sha256>>,
+    written: Rc<Cell<u64>>,
+}
+
+/// Like `partition::partition_csv`, but write an ORC file for each chunk.
+/// We don't support splitting these into shards.
+pub fn partition_csv(classifier: &Classifier,
+                     input_column: &str,
+                     dialect: &Dialect,
+                     input: &mut dyn Read,
+                     output_dir: &Path,
+                     file_name: &str,
+                     compression: OrcCompression)
+                     -> Result<BTreeMap<String, Vec<ShardInfo>>> {
+    let mut rows = ChunkedRows::new(classifier, input_column, dialect, input)?;
+    let mut files = HashMap::new();
+    for chunk in classifier.chunk_ids() {
+        let dir = output_dir.join(chunk);
+        fs::create_dir_all(&dir)
+            .chain_err(|| format!("could not create {}", dir.display()))?;
+        let path = dir.join(file_name);
+        File::create(&path)
+            .chain_err(|| format!("could not create {}", path.display()))?;
+        let hasher = Rc::new(RefCell::new(Sha256::new()));
+        let written = Rc::new(Cell::new(0));
+        let file = HashingFile {
+            file: Box::new(AppendFile { path: path.clone() }),
+            hasher: hasher.clone(),
+            written: written.clone(),
+        };
+        let mut wtr = OrcWriter::new(file, compression);
+        wtr.write_headers(rows.headers().as_byte_record())
+            .chain_err(|| format!("could not write {}", path.display()))?;
+        files.insert(chunk.to_owned(),
+                     ChunkFile {
+                         wtr,
+                         path,
+                         hasher,
+                         written,
+                     });
+    }
+
+    let mut row = csv::ByteRecord::new();
+    let mut buffered = 0;
+    while let Some(chunk) = rows.next_row(&mut row)? {
+        let file = files.get_mut(chunk).expect("every chunk should have a file");
+        file.wtr.write_row(&row)?;
+        buffered += row.as_slice().len() as u64;
+        if buffered >= MAX_BUFFERED_BYTES {
+            for file in files.values_mut() {
+                if file.wtr.stripe_bytes > 0 {
+                    let path = &file.path;
+                    file.wtr
+                        .flush_stripe()
+                        .chain_err(|| format!("could not write {}", path.display()))?;
+                }
+            }
+            buffered = 0;
+        }
+    }
+
+    let mut shards = BTreeMap::new();
+    for (chunk, file) in files {
+        let path = file.path;
+        let rows = file.wtr
+            .finish()
+            .chain_err(|| format!("could not write {}", path.display()))?;
+        let sha256 = file.hasher
+            .replace(Sha256::new())
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        shards.insert(chunk,
+                      vec![ShardInfo {
+                               path,
+                               rows,
+                               bytes: file.written.get(),
+                               sha256,
+                           }]);
+    }
+    Ok(shards)
+}
+
+/// Read the ORC file at `path` back in, as strings.
+#[cfg(test)]
+fn read_orc(path: &Path) -> (Vec<String>, Vec<Vec<String>>) {
+    use arrow::array::{Array, RecordBatchReader, StringArray};
+
+    let rdr = ArrowReaderBuilder::try_new(File::open(path).unwrap())
+        .unwrap()
+        .build();
+    let names = rdr.schema()
+        .fields()
+        .iter()
+        .map(|f| f.name().to_owned())
+        .collect();
+    let mut rows = vec![];
+    for batch in rdr {
+        let batch = batch.unwrap();
+        for i in 0..batch.num_rows() {
+            rows.push(batch.columns()
+                          .iter()
+                          .map(|c| {
+                                   let strings = c.as_any().downcast_ref::<StringArray>();
+                                   strings.unwrap().value(i).to_owned()
+                               })
+                          .collect());
+        }
+    }
+    (names, rows)
+}
+
+#[test]
+fn writes_orc_files() {
+    let _ = env_logger::init();
+    let path = env::temp_dir().join(format!("geochunk-orc-{}.orc", process::id()));
+    let mut wtr = OrcWriter::new(File::create(&path).unwrap(), OrcCompression::Zstd);
+    wtr.write_headers(&csv::ByteRecord::from(vec!["zip", "geochunk"])).unwrap();
+    for i in 0..(BATCH_ROWS + 1) {
+        let zip = format!("{:05}", i);
+        wtr.write_row(&csv::ByteRecord::from(vec![&zip[..], ""])).unwrap();
+    }
+    let invalid = csv::ByteRecord::from(vec![&b"\xff"[..], &b""[..]]);
+    assert!(wtr.write_row(&invalid).is_err());
+    assert_eq!(wtr.finish().unwrap(), BATCH_ROWS as u64 + 1);
+
+    let (names, rows) = read_orc(&path);
+    assert_eq!(names, vec!["zip", "geochunk"]);
+    assert_eq!(rows.len(), BATCH_ROWS + 1);
+    assert_eq!(rows[BATCH_ROWS], vec!["01024", ""]);
+    fs::remove_file(&path).unwrap();
+
+    let mut out = vec![];
+    let mut wtr = OrcWriter::new(&mut out, OrcCompression::None);
+    wtr.write_row(&csv::ByteRecord::from(vec!["a", "b"])).unwrap();
+    wtr.finish().unwrap();
+    assert!(out.starts_with(b"ORC"));
+    assert!("brotli".parse::<OrcCompression>().is_err());
+}
+
+#[test]
+fn partitions_rows_into_orc_files() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let dir = env::temp_dir().join(format!("geochunk-orc-partition-{}", process::id()));
+    let input = "name,zip\nJ. Doe,90210\nH. Smith,\nA. Jones,07712\nB. Lee,90211\n";
+    let shards = partition_csv(&classifier,
+                               "zip",
+                               &Dialect::default(),
+                               &mut input.as_bytes(),
+                               &dir,
+                               "people.orc",
+                               OrcCompression::default())
+            .unwrap();
+    assert_eq!(shards.len(), classifier.chunk_ids().len());
+    assert_eq!(shards["902_0"][0].rows, 2);
+    assert_eq!(shards["902_0"][0].bytes,
+               fs::metadata(dir.join("902_0").join("people.orc")).unwrap().len());
+
+    let (names, rows) = read_orc(&dir.join("902_0").join("people.orc"));
+    assert_eq!(names, vec!["name", "zip"]);
+    assert_eq!(rows, vec![vec!["J. Doe", "90210"], vec!["B. Lee", "90211"]]);
+    let (_, rows) = read_orc(&dir.join(&classifier.chunk_ids()[0]).join("people.orc"));
+    assert!(rows.is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,404 @@
+//! Partitioning CSV files into one Parquet file per geochunk, laid out so
+//! that query engines can skip the files they don't need. This requires the
+//! `parquet` feature.
+//!
+//! Each file gets a geochunk column, which holds the same value on every
+//! row. We dictionary-encode it, so it takes almost no space, and write
+//! statistics for every column of every file, so an engine filtering on a
+//! chunk can rule out other files by reading their footers. If we're asked
+//! to sort each file by zip code, we record that in the file, and write
+//! page-level statistics for the zip code column, so filters on zip codes
+//! can skip most of each file as well. Every column is a Parquet string.
+//!
+//! Parquet files are written a row group at a time, and the rows for every
+//! chunk we're writing would have to sit in memory until then. So instead,
+//! we first split our input into temporary CSV files using
+//! `partition::partition_csv`, and then convert them one chunk at a time,
+//! sorting them on the way if necessary.
+
+use arrow_array::builder::StringBuilder;
+use arrow_array::{ArrayRef, RecordBatch, RecordBatchOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use csv;
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::file::metadata::SortingColumn;
+use parquet::file::properties::{EnabledStatistics, WriterProperties};
+use parquet::schema::types::ColumnPath;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+#[cfg(all(test, feature = "data-zip2010"))]
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::{from_utf8, FromStr};
+use std::sync::Arc;
+
+use dialect::Dialect;
+use errors::*;
+use partition;
+use shard::{ShardInfo, ShardLimits};
+use sort::{ExternalSorter, DEFAULT_MAX_BUFFER_BYTES};
+use zip2010::Classifier;
+
+/// How many rows we collect before encoding them.
+const BATCH_ROWS: usize = 8192;
+
+/// What we call each chunk's temporary CSV file.
+const SPILL_FILE_NAME: &str = "rows.csv";
+
+/// How to compress the Parquet files we write.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParquetCompression {
+    /// Don't compress our files.
+    None,
+    /// Compress using Snappy, which most query engines default to.
+    #[default]
+    Snappy,
+    /// Compress using gzip.
+    Gzip,
+    /// Compress using Zstandard.
+    Zstd,
+}
+
+impl ParquetCompression {
+    /// The codec `parquet` should use.
+    pub(crate) fn codec(self) -> Compression {
+        match self {
+            ParquetCompression::None => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Gzip => Compression::GZIP(GzipLevel::default()),
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        }
+    }
+}
+
+impl FromStr for ParquetCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ParquetCompression> {
+        match s {
+            "none" => Ok(ParquetCompression::None),
+            "snappy" => Ok(ParquetCompression::Snappy),
+            "gzip" => Ok(ParquetCompression::Gzip),
+            "zstd" => Ok(ParquetCompression::Zstd),
+            _ => {
+                Err(format!("unknown Parquet compression {:?}, expected none, snappy, \
+                             gzip or zstd",
+                            s)
+                            .into())
+            }
+        }
+    }
+}
+
+/// Options controlling how we write Parquet files.
+#[derive(Clone, Debug, Default)]
+pub struct ParquetOptions {
+    /// The name of the geochunk column we add to each file.
+    pub chunk_column: String,
+    /// Sort the rows of each file by zip code.
+    pub sort_by_zip: bool,
+    /// How to compress our files.
+    pub compression: ParquetCompression,
+}
+
+/// Convert an error from `parquet` or `arrow` into one of ours.
+fn parquet_error<E: ::std::fmt::Display>(err: E) -> Error {
+    format!("could not write Parquet data: {}", err).into()
+}
+
+/// A directory of temporary files, which is removed when dropped.
+struct TempDir {
+    path: PathBuf,
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            warn!("could not remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Like `partition::partition_csv`, but write a Parquet file for each
+/// chunk, with a geochunk column added. We don't support splitting these
+/// into shards. Our temporary files are written inside `output_dir`.
+pub fn partition_csv(classifier: &Classifier,
+                     input_column: &str,
+                     dialect: &Dialect,
+                     input: &mut dyn Read,
+                     output_dir: &Path,
+                     file_name: &str,
+                     options: &ParquetOptions)
+                     -> Result<BTreeMap<String, Vec<ShardInfo>>> {
+    let temp_dir = TempDir {
+        path: output_dir.join(format!(".geochunk-parquet-{}", process::id())),
+    };
+    partition::partition_csv(classifier,
+                             input_column,
+                             dialect,
+                             input,
+                             &temp_dir.path,
+                             SPILL_FILE_NAME,
+                             ShardLimits::default(),
+                             false)?;
+
+    let mut shards = BTreeMap::new();
+    for chunk in classifier.chunk_ids() {
+        let spill_path = temp_dir.path.join(chunk).join(SPILL_FILE_NAME);
+        let spill = File::open(&spill_path)
+            .chain_err(|| format!("could not open {}", spill_path.display()))?;
+        let dir = output_dir.join(chunk);
+        fs::create_dir_all(&dir)
+            .chain_err(|| format!("could not create {}", dir.display()))?;
+        let path = dir.join(file_name);
+        let shard = write_chunk(chunk,
+                                input_column,
+                                dialect,
+                                spill,
+                                &path,
+                                options)
+                .chain_err(|| format!("could not write {}", path.display()))?;
+        shards.insert(chunk.to_owned(), vec![shard]);
+        // Free up space as we go, in case our input is large.
+        fs::remove_file(&spill_path)
+            .chain_err(|| format!("could not remove {}", spill_path.display()))?;
+    }
+    Ok(shards)
+}
+
+/// Convert the rows of `chunk` in the CSV file `spill` into a Parquet file
+/// at `path`.
+fn write_chunk(chunk: &str,
+               input_column: &str,
+               dialect: &Dialect,
+               spill: File,
+               path: &Path,
+               options: &ParquetOptions)
+               -> Result<ShardInfo> {
+    let mut rdr = dialect.reader_builder().from_reader(spill);
+    let headers = rdr.headers()?.to_owned();
+    let zip_col_idx = headers
+        .iter()
+        .position(|h| h == input_column)
+        .ok_or_else(|| Error::no_such_column(input_column))?;
+    if headers.iter().any(|h| h == options.chunk_column) {
+        return Err(format!("input already has a column named {:?}; try --output-column",
+                           options.chunk_column)
+                           .into());
+    }
+
+    let mut fields = headers
+        .iter()
+        .map(|name| Field::new(name, DataType::Utf8, false))
+        .collect::<Vec<_>>();
+    fields.push(Field::new(&options.chunk_column[..], DataType::Utf8, false));
+    let schema = Arc::new(Schema::new(fields));
+    let chunk_path = ColumnPath::from(&options.chunk_column[..]);
+    let mut props = WriterProperties::builder()
+        .set_compression(options.compression.codec())
+        .set_statistics_enabled(EnabledStatistics::Chunk)
+        .set_column_dictionary_enabled(chunk_path, true);
+    if options.sort_by_zip {
+        let sorted = SortingColumn {
+            column_idx: zip_col_idx as i32,
+            descending: false,
+            nulls_first: false,
+        };
+        props = props
+            .set_sorting_columns(Some(vec![sorted]))
+            .set_column_statistics_enabled(ColumnPath::from(&headers[zip_col_idx]),
+                                           EnabledStatistics::Page);
+    }
+    let out = File::create(path)
+        .chain_err(|| format!("could not create {}", path.display()))?;
+    let wtr = ArrowWriter::try_new(out, schema.clone(), Some(props.build()))
+        .map_err(parquet_error)?;
+    let mut wtr = ParquetWriter::new(wtr, schema, chunk);
+
+    let mut row = csv::ByteRecord::new();
+    if options.sort_by_zip {
+        let mut sorter = ExternalSorter::new(DEFAULT_MAX_BUFFER_BYTES);
+        while rdr.read_byte_record(&mut row)? {
+            sorter.push(row[zip_col_idx].to_owned(), row.clone())?;
+        }
+        sorter.finish(|row| wtr.write_row(row))?;
+    } else {
+        while rdr.read_byte_record(&mut row)? {
+            wtr.write_row(&row)?;
+        }
+    }
+    let rows = wtr.finish()?;
+
+    // We only hash the file once it's complete, because `ArrowWriter` needs
+    // to own its output.
+    let mut file = File::open(path)
+        .chain_err(|| format!("could not open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let bytes = io::copy(&mut file, &mut hasher)?;
+    Ok(ShardInfo {
+           path: path.to_owned(),
+           rows,
+           bytes,
+           sha256: hasher
+               .finalize()
+               .iter()
+               .map(|b| format!("{:02x}", b))
+               .collect(),
+       })
+}
+
+/// Collects CSV rows for one chunk into record batches.
+struct ParquetWriter<'a> {
+    wtr: ArrowWriter<File>,
+    schema: SchemaRef,
+    /// The value of our geochunk column.
+    chunk: &'a str,
+    /// The rows which we haven't encoded yet, one builder per column,
+    /// including our geochunk column.
+    columns: Vec<StringBuilder>,
+    /// How many rows are in `columns`.
+    batch_rows: usize,
+    /// How many rows we've written.
+    rows: u64,
+}
+
+impl<'a> ParquetWriter<'a> {
+    /// Write rows for `chunk` using `wtr`.
+    fn new(wtr: ArrowWriter<File>,
+           schema: SchemaRef,
+           chunk: &'a str)
+           -> ParquetWriter<'a> {
+        let columns = schema.fields().iter().map(|_| StringBuilder::new()).collect();
+        ParquetWriter {
+            wtr,
+            schema,
+            chunk,
+            columns,
+            batch_rows: 0,
+            rows: 0,
+        }
+    }
+
+    /// Write a row, adding our geochunk column.
+    fn write_row(&mut self, row: &csv::ByteRecord) -> Result<()> {
+        if from_utf8(row.as_slice()).is_err() {
+            return Err(format!("row {} is not valid UTF-8, which Parquet strings must \
+                                be; try --encoding",
+                               self.rows + 1)
+                               .into());
+        }
+        let (chunk_column, columns) = self.columns
+            .split_last_mut()
+            .expect("should always have a geochunk column");
+        for (column, field) in columns.iter_mut().zip(row) {
+            column.append_value(from_utf8(field).expect("checked above"));
+        }
+        chunk_column.append_value(self.chunk);
+        self.rows += 1;
+        self.batch_rows += 1;
+        if self.batch_rows >= BATCH_ROWS {
+            self.encode_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Encode the rows in `columns`.
+    fn encode_batch(&mut self) -> Result<()> {
+        if self.batch_rows == 0 {
+            return Ok(());
+        }
+        let arrays = self.columns
+            .iter_mut()
+            .map(|c| Arc::new(c.finish()) as ArrayRef)
+            .collect::<Vec<_>>();
+        let options = RecordBatchOptions::new().with_row_count(Some(self.batch_rows));
+        let schema = self.schema.clone();
+        let batch = RecordBatch::try_new_with_options(schema, arrays, &options)
+            .map_err(parquet_error)?;
+        self.wtr.write(&batch).map_err(parquet_error)?;
+        self.batch_rows = 0;
+        Ok(())
+    }
+
+    /// Finish writing our file, and return how many rows we wrote.
+    fn finish(mut self) -> Result<u64> {
+        self.encode_batch()?;
+        self.wtr.close().map_err(parquet_error)?;
+        Ok(self.rows)
+    }
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn partitions_rows_into_parquet_files() {
+    use arrow_array::{Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::basic::Encoding;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let dir = env::temp_dir().join(format!("geochunk-parquet-{}", process::id()));
+    let input = "name,zip\nJ. Doe,90211\nH. Smith,\nA. Jones,07712\nB. Lee,90210\n";
+    let options = ParquetOptions {
+        chunk_column: "geochunk".to_owned(),
+        sort_by_zip: true,
+        ..ParquetOptions::default()
+    };
+    let shards = partition_csv(&classifier,
+                               "zip",
+                               &Dialect::default(),
+                               &mut input.as_bytes(),
+                               &dir,
+                               "people.parquet",
+                               &options)
+            .unwrap();
+    assert_eq!(shards.len(), classifier.chunk_ids().len());
+    let path = dir.join("902_0").join("people.parquet");
+    assert_eq!(shards["902_0"][0].rows, 2);
+    assert_eq!(shards["902_0"][0].bytes, fs::metadata(&path).unwrap().len());
+
+    // Our rows should be sorted by zip code, with a geochunk column.
+    let batches = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap()
+        .collect::<::std::result::Result<Vec<_>, _>>()
+        .unwrap();
+    let strings = |i: usize| {
+        let column = batches[0].column(i);
+        let column = column.as_any().downcast_ref::<StringArray>().unwrap();
+        (0..column.len()).map(|r| column.value(r).to_owned()).collect::<Vec<_>>()
+    };
+    assert_eq!(batches[0].schema().field(2).name(), "geochunk");
+    assert_eq!(strings(0), vec!["B. Lee", "J. Doe"]);
+    assert_eq!(strings(2), vec!["902_0", "902_0"]);
+
+    // Our footer should let query engines prune this file.
+    let rdr = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let metadata = rdr.metadata();
+    let row_group = metadata.row_group(0);
+    let sorted = row_group.sorting_columns().unwrap();
+    assert_eq!(sorted[0].column_idx, 1);
+    let chunk_column = row_group.column(2);
+    assert!(chunk_column.encodings().any(|e| e == Encoding::RLE_DICTIONARY));
+    let stats = chunk_column.statistics().unwrap();
+    assert_eq!(stats.min_bytes_opt(), Some(&b"902_0"[..]));
+    assert_eq!(stats.max_bytes_opt(), Some(&b"902_0"[..]));
+    let stats = row_group.column(1).statistics().unwrap();
+    assert_eq!(stats.min_bytes_opt(), Some(&b"90210"[..]));
+
+    // Empty chunks still get a file, and our temporary files are gone.
+    let empty = dir.join(&classifier.chunk_ids()[0]).join("people.parquet");
+    let rdr = SerializedFileReader::new(File::open(&empty).unwrap()).unwrap();
+    assert_eq!(rdr.metadata().file_metadata().num_rows(), 0);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), classifier.chunk_ids().len());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,247 @@
+//! Splitting CSV files into one file per geochunk.
+//!
+//! If two files are partitioned using the same classifier, every chunk
+//! directory will contain matching pieces of both, so the files can be
+//! joined on zip code one chunk at a time.
+
+use csv;
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(all(test, feature = "data-zip2010"))]
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+#[cfg(all(test, feature = "data-zip2010"))]
+use std::process;
+use std::str::from_utf8;
+
+use dialect::{Dialect, RaggedRows};
+use errors::*;
+use shard::{ShardedWriter, ShardInfo, ShardLimits};
+use uring::RingWriter;
+use zip2010::Classifier;
+
+/// The most per-chunk files we keep open at once. When we need to open
+/// another, we flush and close them all, and reopen them as needed.
+const MAX_OPEN_FILES: usize = 128;
+
+/// Split a CSV file into `<output_dir>/<chunk>/<file_name>`, one file per
+/// geochunk in `classifier`, each starting with the input's header row.
+/// Every chunk gets a file, even if it has no rows, so that files
+/// partitioned with the same classifier always line up. If `limits` are
+/// given, each chunk's file is split into shards named by
+/// `shard::shard_path`. If `io_uring` is true, we write our files in
+/// batches using `uring::RingWriter`. Rows without a valid zip code are left
+/// out. Returns the shards written for each chunk.
+#[allow(clippy::too_many_arguments)]
+pub fn partition_csv(classifier: &Classifier,
+                     input_column: &str,
+                     dialect: &Dialect,
+                     input: &mut dyn Read,
+                     output_dir: &Path,
+                     file_name: &str,
+                     limits: ShardLimits,
+                     io_uring: bool)
+                     -> Result<BTreeMap<String, Vec<ShardInfo>>> {
+    let mut rows = ChunkedRows::new(classifier, input_column, dialect, input)?;
+    let ring = if io_uring {
+        Some(RingWriter::new().chain_err(|| "could not set up io_uring")?)
+    } else {
+        None
+    };
+    let mut files = ChunkFiles {
+        writers: HashMap::new(),
+        open: 0,
+    };
+    for chunk in classifier.chunk_ids() {
+        let dir = output_dir.join(chunk);
+        fs::create_dir_all(&dir)
+            .chain_err(|| format!("could not create {}", dir.display()))?;
+        let mut wtr = ShardedWriter::new(&dir.join(file_name), limits, dialect);
+        if let Some(ref ring) = ring {
+            wtr.use_ring(ring);
+        }
+        wtr.write_headers(rows.headers().as_byte_record())?;
+        wtr.close()?;
+        files.writers.insert(chunk.to_owned(), wtr);
+    }
+
+    let mut row = csv::ByteRecord::new();
+    while let Some(chunk) = rows.next_row(&mut row)? {
+        files.write(chunk, &row)?;
+    }
+    let shards = files
+        .writers
+        .into_iter()
+        .map(|(chunk, wtr)| Ok((chunk, wtr.finish()?)))
+        .collect::<Result<_>>()?;
+    if let Some(ring) = ring {
+        ring.borrow_mut()
+            .flush()
+            .chain_err(|| format!("could not write {} files", file_name))?;
+    }
+    Ok(shards)
+}
+
+/// Reads the rows of a CSV file which should be partitioned, along with
+/// their chunks.
+pub(crate) struct ChunkedRows<'a> {
+    classifier: &'a Classifier,
+    dialect: &'a Dialect,
+    rdr: csv::Reader<&'a mut dyn Read>,
+    headers: csv::StringRecord,
+    /// The index of our zip code column.
+    zip_col_idx: usize,
+}
+
+impl<'a> ChunkedRows<'a> {
+    /// Read the headers of `input`, and find `input_column`.
+    pub(crate) fn new(classifier: &'a Classifier,
+                      input_column: &str,
+                      dialect: &'a Dialect,
+                      input: &'a mut dyn Read)
+                      -> Result<ChunkedRows<'a>> {
+        let mut rdr = dialect.reader_builder().from_reader(input);
+        let headers = rdr.headers()?.to_owned();
+        let zip_col_idx = headers
+            .iter()
+            .position(|h| h == input_column)
+            .ok_or_else(|| Error::no_such_column(input_column))?;
+        Ok(ChunkedRows {
+               classifier,
+               dialect,
+               rdr,
+               headers,
+               zip_col_idx,
+           })
+    }
+
+    /// The header row of our input.
+    pub(crate) fn headers(&self) -> &csv::StringRecord {
+        &self.headers
+    }
+
+    /// Read the next row with a valid zip code into `row`, padding it if
+    /// necessary, and return its chunk. Returns `None` at the end of our
+    /// input.
+    pub(crate) fn next_row(&mut self,
+                           row: &mut csv::ByteRecord)
+                           -> Result<Option<&'a str>> {
+        let len = self.headers.len();
+        while self.rdr.read_byte_record(row)? {
+            if row.len() != len {
+                match self.dialect.on_ragged {
+                    RaggedRows::Error => {
+                        return Err(Error::ragged_row(row.len(), len, row.position()));
+                    }
+                    RaggedRows::Pad => {
+                        row.truncate(len);
+                        while row.len() < len {
+                            row.push_field(b"");
+                        }
+                    }
+                    RaggedRows::Skip => continue,
+                }
+            }
+            let zip = from_utf8(&row[self.zip_col_idx])
+                .chain_err(|| Error::non_utf8_zip(row.position()))?;
+            if let Some(chunk) = self.classifier.chunk_for(zip) {
+                return Ok(Some(chunk));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The per-chunk output files of `partition_csv`.
+struct ChunkFiles<'a> {
+    /// The writer for each chunk.
+    writers: HashMap<String, ShardedWriter<'a>>,
+    /// How many of our writers have an open file.
+    open: usize,
+}
+
+impl<'a> ChunkFiles<'a> {
+    /// Append `row` to the file for `chunk`.
+    fn write(&mut self, chunk: &str, row: &csv::ByteRecord) -> Result<()> {
+        let was_open = self.writers[chunk].is_open();
+        if !was_open && self.open >= MAX_OPEN_FILES {
+            self.close_all()?;
+        }
+        let wtr = self.writers
+            .get_mut(chunk)
+            .expect("every chunk should have a writer");
+        wtr.write_row(row)?;
+        if !was_open && wtr.is_open() {
+            self.open += 1;
+        }
+        Ok(())
+    }
+
+    /// Flush and close all our open files.
+    fn close_all(&mut self) -> Result<()> {
+        for wtr in self.writers.values_mut() {
+            wtr.close()?;
+        }
+        self.open = 0;
+        Ok(())
+    }
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn partitions_rows_by_chunk() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let dir = env::temp_dir().join(format!("geochunk-partition-{}", process::id()));
+    let input = "name,zip\nJ. Doe,90210\nH. Smith,\nA. Jones,07712\nB. Lee,90211\n";
+    let shards = partition_csv(&classifier,
+                               "zip",
+                               &Dialect::default(),
+                               &mut input.as_bytes(),
+                               &dir,
+                               "people.csv",
+                               ShardLimits::default(),
+                               false)
+            .unwrap();
+    assert_eq!(shards.len(), classifier.chunk_ids().len());
+    assert!(shards.values().all(|s| s.len() == 1));
+    assert_eq!(shards["902_0"][0].rows, 2);
+    assert_eq!(shards.values().map(|s| s[0].rows).sum::<u64>(), 3);
+
+    let read = |chunk: &str| fs::read_to_string(dir.join(chunk).join("people.csv")).unwrap();
+    assert_eq!(read("902_0"), "name,zip\nJ. Doe,90210\nB. Lee,90211\n");
+    assert_eq!(read("077_0"), "name,zip\nA. Jones,07712\n");
+    assert_eq!(read(&classifier.chunk_ids()[0]).lines().next(), Some("name,zip"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn shards_large_chunks() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let dir = env::temp_dir().join(format!("geochunk-partition-shards-{}", process::id()));
+    let input = "zip\n90210\n90211\n90210\n07712\n";
+    let limits = ShardLimits {
+        max_rows: Some(2),
+        ..ShardLimits::default()
+    };
+    let shards = partition_csv(&classifier,
+                               "zip",
+                               &Dialect::default(),
+                               &mut input.as_bytes(),
+                               &dir,
+                               "zips.csv",
+                               limits,
+                               false)
+            .unwrap();
+    let rows = |chunk: &str| shards[chunk].iter().map(|s| s.rows).collect::<Vec<_>>();
+    assert_eq!(rows("902_0"), vec![2, 1]);
+    assert_eq!(rows("077_0"), vec![1]);
+    assert_eq!(fs::read_to_string(dir.join("902_0").join("zips-00001.csv")).unwrap(),
+               "zip\n90210\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
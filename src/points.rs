@@ -0,0 +1,363 @@
+//! Finding the zip code of a latitude and longitude, using the internal
+//! points from a Gazetteer ZCTA file, and optionally the ZCTA boundaries
+//! from a Census boundary file.
+//!
+//! With boundaries, a point belongs to the ZCTA whose polygon contains it,
+//! and is otherwise outside every ZCTA, as it might be offshore or across
+//! the border. Without them, we treat each ZCTA as a circle around its
+//! internal point with the same area as the ZCTA, and a point belongs to
+//! the ZCTA with the nearest internal point if it's inside that circle.
+//! This is only an approximation: points near the edge of a long or
+//! irregular ZCTA may fall outside its circle, and be treated as outside
+//! every ZCTA. It's good enough for choosing chunks, which usually contain
+//! many ZCTAs, but use boundaries to tell exactly which points are outside.
+
+#[cfg(test)]
+use env_logger;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use census::normalize_zip;
+use errors::*;
+use gazetteer::{SQUARE_METERS_PER_SQUARE_MILE, ZctaPoint};
+use geography::{self, Ring};
+use shapefile::Shape;
+
+/// The mean radius of the Earth, in miles.
+const EARTH_RADIUS_MILES: f64 = 3958.8;
+
+/// The size of our grid cells, in degrees.
+const CELL_DEGREES: f64 = 1.0;
+
+/// Roughly the shortest distance across a grid cell, in miles, at the
+/// highest latitude of any ZCTA, where degrees of longitude are shortest.
+/// We use this to decide when we can stop searching for a nearest point.
+/// For points far north of every ZCTA, we may stop a little early.
+const MIN_CELL_MILES: f64 = 15.0;
+
+/// Attributes which may contain the zip code of a ZCTA boundary, in the
+/// order we look for them. Census boundary files call it `ZCTA5CE20` or
+/// `ZCTA5CE10`, depending on the census.
+const ZCTA_FIELDS: &[&str] = &["ZCTA5CE20", "ZCTA5CE10", "GEOID20", "GEOID10", "GEOID", "zip"];
+
+/// The great-circle distance between two points, in miles.
+pub fn distance_miles((lat1, lng1): (f64, f64), (lat2, lng2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlng = (lng2 - lng1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_MILES * a.sqrt().min(1.0).asin()
+}
+
+/// Where we found a point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointMatch<'a> {
+    /// The zip code we assigned the point to.
+    pub zip: &'a str,
+    /// The distance from the point to the internal point of `zip`, in miles.
+    pub distance: f64,
+    /// Was the point outside every ZCTA, so that we used the nearest one?
+    /// Without boundaries, this is only approximate: see the module
+    /// documentation.
+    pub nearest: bool,
+}
+
+/// An index of ZCTA internal points, for finding the ZCTA containing or
+/// nearest to a point.
+#[derive(Clone, Debug)]
+pub struct PointIndex {
+    /// Our ZCTAs, with the radius of a circle with the same area, in miles.
+    points: Vec<(ZctaPoint, f64)>,
+    /// The indices of the points in each grid cell.
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    /// The boundaries of our ZCTAs, if we have them.
+    boundaries: Vec<Boundary>,
+    /// The indices of the boundaries overlapping each grid cell.
+    boundary_cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+/// One of the polygons making up a ZCTA.
+#[derive(Clone, Debug)]
+struct Boundary {
+    /// The index of the ZCTA's internal point.
+    point: usize,
+    /// The bounding box of `rings`, as `(min_lng, min_lat, max_lng,
+    /// max_lat)`.
+    bbox: (f64, f64, f64, f64),
+    /// The rings of the polygon, including any holes.
+    rings: Vec<Ring>,
+}
+
+impl PointIndex {
+    /// Build an index of `points`.
+    pub fn new(points: Vec<ZctaPoint>) -> PointIndex {
+        let mut cells = HashMap::<_, Vec<usize>>::new();
+        for (i, point) in points.iter().enumerate() {
+            cells
+                .entry(cell_for(point.lat, point.lng))
+                .or_default()
+                .push(i);
+        }
+        let points = points
+            .into_iter()
+            .map(|point| {
+                     let sq_mi = point.area as f64 / SQUARE_METERS_PER_SQUARE_MILE;
+                     let radius = (sq_mi / PI).sqrt();
+                     (point, radius)
+                 })
+            .collect();
+        PointIndex {
+            points,
+            cells,
+            boundaries: vec![],
+            boundary_cells: HashMap::new(),
+        }
+    }
+
+    /// Use the ZCTA polygons in `shapes`, such as those in a Census ZCTA
+    /// boundary file, to decide which ZCTA contains each point. Each shape
+    /// should have a zip code attribute such as `ZCTA5CE20`. We ignore
+    /// shapes for ZCTAs which have no internal point.
+    pub fn with_boundaries(mut self, shapes: Vec<Shape>) -> Result<PointIndex> {
+        let index_for_zip = self.points
+            .iter()
+            .enumerate()
+            .map(|(i, (point, _))| (point.zip.clone(), i))
+            .collect::<HashMap<_, _>>();
+        for (i, shape) in shapes.into_iter().enumerate() {
+            let field = ZCTA_FIELDS
+                .iter()
+                .filter_map(|name| {
+                                shape.attributes
+                                    .iter()
+                                    .find(|&(k, _)| k.eq_ignore_ascii_case(name))
+                                    .map(|(_, v)| v)
+                            })
+                .next()
+                .ok_or_else(|| Error::no_such_column("ZCTA5CE20"))?;
+            let zip = normalize_zip(field)
+                .ok_or_else(|| format!("shape {} has an invalid zip code {:?}", i + 1, field))?;
+            let point = match index_for_zip.get(&zip) {
+                Some(&point) if !shape.rings.is_empty() => point,
+                _ => continue,
+            };
+            let bbox = geography::rings_bbox(&shape.rings);
+            if bbox.0 < -180.0 || bbox.1 < -90.0 || bbox.2 > 180.0 || bbox.3 > 90.0 {
+                return Err(format!("ZCTA {} has coordinates which aren't a longitude and \
+                                    latitude, so it may need to be reprojected to WGS 84",
+                                   zip)
+                                   .into());
+            }
+            let (min_row, min_col) = cell_for(bbox.1, bbox.0);
+            let (max_row, max_col) = cell_for(bbox.3, bbox.2);
+            for row in min_row..=max_row {
+                for col in min_col..=max_col {
+                    self.boundary_cells
+                        .entry((row, col))
+                        .or_default()
+                        .push(self.boundaries.len());
+                }
+            }
+            self.boundaries.push(Boundary {
+                                     point,
+                                     bbox,
+                                     rings: shape.rings,
+                                 });
+        }
+        Ok(self)
+    }
+
+    /// The number of ZCTAs in our index.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Is our index empty?
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Find the ZCTA containing `(lat, lng)`. If the point is outside every
+    /// ZCTA, and `nearest_within` is a distance in miles, return the ZCTA
+    /// with the nearest internal point within that distance instead. See
+    /// the module documentation for how we decide which points are
+    /// outside every ZCTA.
+    pub fn locate(&self, lat: f64, lng: f64, nearest_within: Option<f64>) -> Option<PointMatch<'_>> {
+        if !lat.is_finite() || !lng.is_finite() || lat.abs() > 90.0 || lng.abs() > 180.0 {
+            return None;
+        }
+        if !self.boundaries.is_empty() {
+            if let Some(i) = self.boundary_containing(lat, lng) {
+                let point = &self.points[i].0;
+                return Some(PointMatch {
+                                zip: &point.zip,
+                                distance: distance_miles((lat, lng), (point.lat, point.lng)),
+                                nearest: false,
+                            });
+            }
+        }
+        let (i, distance) = self.nearest(lat, lng)?;
+        let (ref point, radius) = self.points[i];
+        if distance <= radius && self.boundaries.is_empty() {
+            Some(PointMatch {
+                     zip: &point.zip,
+                     distance,
+                     nearest: false,
+                 })
+        } else if nearest_within.is_some_and(|max| distance <= max) {
+            Some(PointMatch {
+                     zip: &point.zip,
+                     distance,
+                     nearest: true,
+                 })
+        } else {
+            None
+        }
+    }
+
+    /// Find the index of the internal point of the ZCTA whose boundary
+    /// contains `(lat, lng)`.
+    fn boundary_containing(&self, lat: f64, lng: f64) -> Option<usize> {
+        self.boundary_cells
+            .get(&cell_for(lat, lng))?
+            .iter()
+            .map(|&i| &self.boundaries[i])
+            .find(|b| {
+                      let (x1, y1, x2, y2) = b.bbox;
+                      x1 <= lng && lng <= x2 && y1 <= lat && lat <= y2 &&
+                      geography::rings_contain(&b.rings, lng, lat)
+                  })
+            .map(|b| b.point)
+    }
+
+    /// Find the index of the point nearest to `(lat, lng)`, and its
+    /// distance in miles. We search rings of grid cells around the point
+    /// until no unsearched cell could contain anything nearer.
+    fn nearest(&self, lat: f64, lng: f64) -> Option<(usize, f64)> {
+        let (row, col) = cell_for(lat, lng);
+        let max_ring = (360.0 / CELL_DEGREES) as i32;
+        let mut best: Option<(usize, f64)> = None;
+        for ring in 0..=max_ring {
+            if let Some((_, distance)) = best {
+                if f64::from(ring - 1) * MIN_CELL_MILES > distance {
+                    break;
+                }
+            }
+            for r in (row - ring)..=(row + ring) {
+                for c in (col - ring)..=(col + ring) {
+                    if (r - row).abs() != ring && (c - col).abs() != ring {
+                        continue;
+                    }
+                    for &i in self.cells.get(&(r, c)).into_iter().flatten() {
+                        let point = &self.points[i].0;
+                        let distance = distance_miles((lat, lng), (point.lat, point.lng));
+                        if best.is_none_or(|(_, d)| distance < d) {
+                            best = Some((i, distance));
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+/// The grid cell containing `(lat, lng)`.
+fn cell_for(lat: f64, lng: f64) -> (i32, i32) {
+    ((lat / CELL_DEGREES).floor() as i32, (lng / CELL_DEGREES).floor() as i32)
+}
+
+#[test]
+fn measures_distances() {
+    let _ = env_logger::init();
+    // Los Angeles to New York is about 2,450 miles.
+    let distance = distance_miles((34.05, -118.24), (40.71, -74.01));
+    assert!((distance - 2445.0).abs() < 10.0, "{}", distance);
+    assert_eq!(distance_miles((40.0, -74.0), (40.0, -74.0)), 0.0);
+}
+
+#[test]
+fn locates_points() {
+    let _ = env_logger::init();
+    let sq_mi = SQUARE_METERS_PER_SQUARE_MILE as u64;
+    let point = |zip: &str, lat, lng| {
+        ZctaPoint {
+            zip: zip.to_owned(),
+            lat,
+            lng,
+            area: 10 * sq_mi,
+        }
+    };
+    let index = PointIndex::new(vec![point("10001", 40.750633, -73.997177),
+                                     point("90210", 34.100517, -118.41463),
+                                     point("99501", 61.222, -149.856)]);
+    assert_eq!(index.len(), 3);
+
+    // A point a block away is inside 10001.
+    let found = index.locate(40.751, -73.998, None).unwrap();
+    assert_eq!((found.zip, found.nearest), ("10001", false));
+    assert!(found.distance < 0.1);
+
+    // A point in the ocean, about 40 miles east of 10001, is outside every
+    // ZCTA, unless we allow a nearest fallback which is far enough.
+    assert_eq!(index.locate(40.75, -73.24, None), None);
+    assert_eq!(index.locate(40.75, -73.24, Some(20.0)), None);
+    let found = index.locate(40.75, -73.24, Some(50.0)).unwrap();
+    assert_eq!((found.zip, found.nearest), ("10001", true));
+
+    // The nearest point may be several grid cells away.
+    let found = index.locate(55.0, -140.0, Some(1000.0)).unwrap();
+    assert_eq!(found.zip, "99501");
+
+    assert_eq!(index.locate(f64::NAN, -74.0, Some(50.0)), None);
+    assert_eq!(index.locate(91.0, -74.0, Some(50.0)), None);
+    assert_eq!(PointIndex::new(vec![]).locate(40.75, -74.0, Some(50.0)), None);
+}
+
+#[test]
+fn locates_points_using_boundaries() {
+    use std::collections::HashMap;
+
+    let _ = env_logger::init();
+    // A long, thin ZCTA, running 40 miles east and west of its internal
+    // point, which covers far less than a circle that wide.
+    let strip = vec![(-74.5, 39.99), (-74.5, 40.01), (-73.5, 40.01), (-73.5, 39.99),
+                     (-74.5, 39.99)];
+    let shape = |zip: &str, rings: Vec<Ring>| {
+        let mut attributes = HashMap::new();
+        attributes.insert("ZCTA5CE20".to_owned(), zip.to_owned());
+        Shape { rings, attributes }
+    };
+    let points = vec![ZctaPoint {
+                          zip: "10001".to_owned(),
+                          lat: 40.0,
+                          lng: -74.0,
+                          area: 74 * SQUARE_METERS_PER_SQUARE_MILE as u64,
+                      }];
+    let circles = PointIndex::new(points.clone());
+    let index = PointIndex::new(points)
+        .with_boundaries(vec![shape("10001", vec![strip]),
+                              shape("99999", vec![vec![(0.0, 0.0), (0.0, 1.0), (1.0, 0.0)]])])
+        .unwrap();
+
+    // The far end of the strip is outside its circle, but inside the ZCTA.
+    assert_eq!(circles.locate(40.0, -73.6, None), None);
+    let found = index.locate(40.0, -73.6, None).unwrap();
+    assert_eq!((found.zip, found.nearest), ("10001", false));
+    assert!((found.distance - 21.0).abs() < 1.0, "{}", found.distance);
+
+    // Points near the internal point may still be outside the ZCTA.
+    assert!(circles.locate(40.03, -74.0, None).is_some());
+    assert_eq!(index.locate(40.03, -74.0, None), None);
+    let found = index.locate(40.03, -74.0, Some(5.0)).unwrap();
+    assert_eq!((found.zip, found.nearest), ("10001", true));
+
+    // ZCTAs without internal points are ignored.
+    assert_eq!(index.locate(0.2, 0.2, None), None);
+    assert!(PointIndex::new(vec![])
+                .with_boundaries(vec![Shape {
+                                          rings: vec![],
+                                          attributes: HashMap::new(),
+                                      }])
+                .is_err());
+}
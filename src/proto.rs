@@ -0,0 +1,231 @@
+//! Writing our output as a stream of length-delimited protobuf messages,
+//! for consumers which would rather not parse CSV. The messages are defined
+//! in `proto/geochunk.proto`.
+//!
+//! Like our YAML output, we encode these ourselves. We only ever write
+//! strings, integers and nested messages, which takes a few lines of code,
+//! and doesn't need a protobuf compiler at build time.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::io::prelude::*;
+
+use errors::*;
+use zip2010::{ChunkSummary, Classifier, Metadata};
+
+/// The wire type of varint fields.
+const VARINT: u64 = 0;
+
+/// The wire type of strings and nested messages.
+const LENGTH_DELIMITED: u64 = 2;
+
+/// The field numbers of each message type in `Message`.
+const METADATA: u64 = 1;
+const ZIP_CHUNK: u64 = 2;
+const CHUNK: u64 = 3;
+const HEADER: u64 = 4;
+const RECORD: u64 = 5;
+
+/// A protobuf message being encoded.
+#[derive(Clone, Debug, Default)]
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    /// Append `value` as a varint.
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    /// Append a `uint64` field. Like proto3, we leave out zeros.
+    fn uint64(&mut self, field: u64, value: u64) {
+        if value != 0 {
+            self.varint(field << 3 | VARINT);
+            self.varint(value);
+        }
+    }
+
+    /// Append a `string` or `bytes` field, even if it's empty, so that
+    /// repeated fields keep their empty values.
+    fn bytes(&mut self, field: u64, value: &[u8]) {
+        self.varint(field << 3 | LENGTH_DELIMITED);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    /// Append a singular `string` field, leaving it out if it's empty.
+    fn string(&mut self, field: u64, value: &str) {
+        if !value.is_empty() {
+            self.bytes(field, value.as_bytes());
+        }
+    }
+
+    /// Write `message` to `out` as the `field` of a `Message`, preceded by
+    /// its length.
+    fn write_message(message: &Encoder,
+                     field: u64,
+                     out: &mut dyn Write)
+                     -> Result<()> {
+        let mut envelope = Encoder::default();
+        envelope.bytes(field, &message.buf);
+        let mut length = Encoder::default();
+        length.varint(envelope.buf.len() as u64);
+        out.write_all(&length.buf)?;
+        out.write_all(&envelope.buf)?;
+        Ok(())
+    }
+}
+
+/// Write a `Metadata` message.
+pub fn write_metadata(metadata: &Metadata, out: &mut dyn Write) -> Result<()> {
+    let mut message = Encoder::default();
+    message.string(1, &metadata.version);
+    message.string(2, &metadata.column);
+    message.string(3, &metadata.weights);
+    message.uint64(4, metadata.target_population);
+    if let Some(ref census_data) = metadata.census_data {
+        message.string(5, &census_data.sha256);
+        message.string(6, &census_data.source);
+        if let Some(vintage) = census_data.vintage {
+            message.uint64(7, u64::from(vintage));
+        }
+        message.uint64(8, census_data.rows);
+        message.uint64(9, census_data.total_population);
+    }
+    Encoder::write_message(&message, METADATA, out)
+}
+
+/// Write our metadata, followed by a `ZipChunk` message for every 5-digit
+/// zip code, like `Classifier::export`.
+pub fn write_export(classifier: &Classifier, out: &mut dyn Write) -> Result<()> {
+    write_metadata(&classifier.metadata(), out)?;
+    for zip_int in 0..100000 {
+        let zip = format!("{:05}", zip_int);
+        let chunk_id = classifier.chunk_for(&zip)
+            .expect("all zip codes should have a chunk");
+        let mut message = Encoder::default();
+        message.string(1, &zip);
+        message.string(2, chunk_id);
+        Encoder::write_message(&message, ZIP_CHUNK, out)?;
+    }
+    Ok(())
+}
+
+/// Write our metadata, followed by a `Chunk` message for every chunk, like
+/// `Classifier::export_plan`.
+pub fn write_plan(classifier: &Classifier, out: &mut dyn Write) -> Result<()> {
+    write_metadata(&classifier.metadata(), out)?;
+    for summary in classifier.chunk_summaries() {
+        write_chunk(&summary, out)?;
+    }
+    Ok(())
+}
+
+/// Write a `Chunk` message.
+fn write_chunk(summary: &ChunkSummary, out: &mut dyn Write) -> Result<()> {
+    let mut message = Encoder::default();
+    message.string(1, &summary.id);
+    message.uint64(2, summary.index as u64);
+    message.uint64(3, summary.population);
+    for prefix in &summary.prefixes {
+        message.bytes(4, prefix.as_bytes());
+    }
+    Encoder::write_message(&message, CHUNK, out)
+}
+
+/// Writes `Header` and `Record` messages for each row of a transformed CSV
+/// file. See `transform::transform_csv_to_proto`.
+pub struct RecordWriter<'a> {
+    out: &'a mut dyn Write,
+    /// How many rows we've written, for error messages.
+    rows: u64,
+}
+
+impl<'a> RecordWriter<'a> {
+    /// Write messages to `out`.
+    pub fn new(out: &'a mut dyn Write) -> RecordWriter<'a> {
+        RecordWriter { out, rows: 0 }
+    }
+
+    /// Encode `fields` as the repeated string field 1 of a message, checking
+    /// that they're valid UTF-8.
+    fn strings(&self, fields: &csv::ByteRecord) -> Result<Encoder> {
+        let mut message = Encoder::default();
+        for field in fields {
+            if ::std::str::from_utf8(field).is_err() {
+                return Err(format!("row {} is not valid UTF-8, which protobuf strings \
+                                    must be; try --encoding",
+                                   self.rows + 1)
+                                   .into());
+            }
+            message.bytes(1, field);
+        }
+        Ok(message)
+    }
+
+    /// Write a `Header` message listing our columns.
+    pub fn write_header(&mut self, headers: &csv::ByteRecord) -> Result<()> {
+        let message = self.strings(headers)?;
+        Encoder::write_message(&message, HEADER, self.out)
+    }
+
+    /// Write a `Record` message.
+    pub fn write_record(&mut self, row: &csv::ByteRecord) -> Result<()> {
+        let message = self.strings(row)?;
+        self.rows += 1;
+        Encoder::write_message(&message, RECORD, self.out)
+    }
+
+    /// Flush our output.
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+}
+
+#[test]
+fn encodes_messages() {
+    let _ = env_logger::init();
+    let mut message = Encoder::default();
+    message.uint64(2, 300);
+    message.uint64(3, 0);
+    message.string(1, "hi");
+    message.string(4, "");
+    assert_eq!(message.buf, vec![0x10, 0xac, 0x02, 0x0a, 0x02, b'h', b'i']);
+
+    let mut out = vec![];
+    Encoder::write_message(&message, RECORD, &mut out).unwrap();
+    assert_eq!(out[..3], [0x09, 0x2a, 0x07]);
+    assert_eq!(out[3..], message.buf[..]);
+
+    let mut out = vec![];
+    let mut wtr = RecordWriter::new(&mut out);
+    wtr.write_record(&csv::ByteRecord::from(vec!["90210", ""])).unwrap();
+    let invalid = csv::ByteRecord::from(vec![&b"\xff"[..]]);
+    assert!(wtr.write_record(&invalid).is_err());
+    assert_eq!(out, b"\x0b\x2a\x09\x0a\x0590210\x0a\x00");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn writes_exports() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut out = vec![];
+    write_export(&classifier, &mut out).unwrap();
+    // Our metadata describes our census data, so its length takes two
+    // bytes.
+    assert!(out[0] & 0x80 != 0);
+    let metadata_len = (out[0] & 0x7f) as usize + ((out[1] as usize) << 7) + 2;
+    assert_eq!(out[2], (METADATA << 3 | LENGTH_DELIMITED) as u8);
+    // Each `ZipChunk` is a tag and length for its zip code and chunk, inside
+    // a tag and length within `Message`, preceded by a length.
+    let first = &out[metadata_len..];
+    assert_eq!(&first[..11], b"\x0e\x12\x0c\x0a\x0500000\x12");
+    assert_eq!(&first[11..15], b"\x030_0");
+}
@@ -0,0 +1,108 @@
+//! Adding geochunk columns to Arrow record batches, which we share between
+//! our Flight server and `geochunk table`. Every other column of a batch is
+//! passed through untouched, keeping its type.
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Int16Type, Int32Type, Int64Type, UInt16Type, UInt32Type,
+                         UInt64Type};
+use arrow_array::{Array, ArrayRef, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+#[cfg(test)]
+use arrow_array::Int64Array;
+#[cfg(test)]
+use env_logger;
+use std::sync::Arc;
+
+use errors::*;
+use zip2010::Classifier;
+
+/// Format a zip code stored as an integer, which loses its leading zeros,
+/// the way we'd expect to find it in a string column. Numbers too large to
+/// be a 5-digit zip code are treated as ZIP+4 codes.
+fn zip_from_int(zip: i128) -> Option<String> {
+    match zip {
+        0..=99999 => Some(format!("{:05}", zip)),
+        100000..=999999999 => Some(format!("{:09}", zip)),
+        _ => None,
+    }
+}
+
+/// Classify the zip codes in `zips`, which may be strings or integers.
+fn chunk_array(classifier: &Classifier, column: &str, zips: &ArrayRef) -> Result<StringArray> {
+    let chunk_for = |zip: Option<&str>| zip.and_then(|z| classifier.chunk_for(z));
+    let chunk_for_int = |zip: Option<i128>| {
+        zip.and_then(zip_from_int)
+            .and_then(|z| classifier.chunk_for(&z))
+    };
+    macro_rules! chunk_ints {
+        ($type:ty) => {
+            zips.as_primitive::<$type>()
+                .iter()
+                .map(|zip| chunk_for_int(zip.map(i128::from)))
+                .collect()
+        };
+    }
+    Ok(match *zips.data_type() {
+        DataType::Utf8 => zips.as_string::<i32>().iter().map(chunk_for).collect(),
+        DataType::LargeUtf8 => zips.as_string::<i64>().iter().map(chunk_for).collect(),
+        DataType::Utf8View => zips.as_string_view().iter().map(chunk_for).collect(),
+        DataType::Int16 => chunk_ints!(Int16Type),
+        DataType::Int32 => chunk_ints!(Int32Type),
+        DataType::Int64 => chunk_ints!(Int64Type),
+        DataType::UInt16 => chunk_ints!(UInt16Type),
+        DataType::UInt32 => chunk_ints!(UInt32Type),
+        DataType::UInt64 => chunk_ints!(UInt64Type),
+        ref other => {
+            return Err(format!("column {:?} should contain strings or integers, not {}",
+                               column,
+                               other)
+                               .into())
+        }
+    })
+}
+
+/// Append a nullable string column named `chunk_column` to `batch`,
+/// containing the chunk ID of each zip code in its column `column`.
+pub fn add_chunk_column(classifier: &Classifier,
+                        column: &str,
+                        chunk_column: &str,
+                        batch: &RecordBatch)
+                        -> Result<RecordBatch> {
+    trace_span!("add_chunk_column", column, rows = batch.num_rows());
+    let schema = batch.schema();
+    let index = schema
+        .index_of(column)
+        .map_err(|_| format!("record batch has no column {:?}", column))?;
+    if schema.index_of(chunk_column).is_ok() {
+        return Err(format!("record batch already has a column {:?}", chunk_column).into());
+    }
+    let chunks = chunk_array(classifier, column, batch.column(index))?;
+
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    fields.push(Arc::new(Field::new(chunk_column, DataType::Utf8, true)));
+    let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+    let mut columns = batch.columns().to_vec();
+    columns.push(Arc::new(chunks) as ArrayRef);
+    RecordBatch::try_new(Arc::new(schema), columns)
+        .map_err(|e| format!("could not build record batch: {}", e).into())
+}
+
+#[test]
+fn classifies_integer_zip_codes() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let schema = Schema::new(vec![Field::new("zip", DataType::Int64, true)]);
+    let zips = Int64Array::from(vec![Some(90210), Some(2120), Some(21201234), None, Some(-1)]);
+    let batch = RecordBatch::try_new(Arc::new(schema), vec![Arc::new(zips) as ArrayRef])
+        .unwrap();
+    let chunked = add_chunk_column(&classifier, "zip", "chunk", &batch).unwrap();
+    assert_eq!(*chunked.schema().field(0).data_type(), DataType::Int64);
+    let chunks = chunked.column(1).as_string::<i32>();
+    assert_eq!(chunks.iter().collect::<Vec<_>>(),
+               vec![classifier.chunk_for("90210"),
+                    classifier.chunk_for("02120"),
+                    classifier.chunk_for("02120"),
+                    None,
+                    None]);
+    assert!(add_chunk_column(&classifier, "zip", "zip", &batch).is_err());
+}
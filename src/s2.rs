@@ -0,0 +1,292 @@
+//! Chunks made of S2 cells, for teams whose other data is already keyed by
+//! S2 cell. S2 divides each face of a cube around the Earth into a quadtree
+//! of cells, numbered along a Hilbert curve, so that a cell's ID is a prefix
+//! of the IDs of every cell inside it, much like a zip code prefix.
+//!
+//! We weight cells by the population of the ZCTA internal points inside
+//! them, split any cell which is too heavy into its four children, and
+//! pack neighboring light cells into chunks, the same way we build chunks
+//! from zip code prefixes. See http://s2geometry.io/ for details of S2.
+
+#[cfg(test)]
+use env_logger;
+use std::collections::HashMap;
+
+use geography::GeoClassifier;
+
+/// The level of S2's smallest cells, which are about a centimeter across.
+pub const MAX_LEVEL: u32 = 30;
+
+/// The number of bits used for the position along the Hilbert curve of a
+/// cell on its face.
+const POS_BITS: u32 = 2 * MAX_LEVEL + 1;
+
+/// For each Hilbert curve orientation, the position of each `(i, j)`
+/// quadrant, indexed by `i << 1 | j`.
+const IJ_TO_POS: [[u64; 4]; 4] = [[0, 1, 3, 2], [0, 3, 1, 2], [2, 3, 1, 0], [2, 1, 3, 0]];
+
+/// How each position changes the orientation of the Hilbert curve inside
+/// it.
+const POS_TO_ORIENTATION: [usize; 4] = [1, 0, 0, 3];
+
+/// The ID of the leaf cell containing `(lat, lng)`.
+pub fn leaf_cell_for(lat: f64, lng: f64) -> u64 {
+    let (lat, lng) = (lat.to_radians(), lng.to_radians());
+    let (x, y, z) = (lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin());
+
+    // Project the point onto the face of the cube it's closest to.
+    let face = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+        if x >= 0.0 { 0 } else { 3 }
+    } else if y.abs() >= z.abs() {
+        if y >= 0.0 { 1 } else { 4 }
+    } else if z >= 0.0 {
+        2
+    } else {
+        5
+    };
+    let (u, v) = match face {
+        0 => (y / x, z / x),
+        1 => (-x / y, z / y),
+        2 => (-x / z, -y / z),
+        3 => (z / x, y / x),
+        4 => (z / y, -x / y),
+        _ => (-y / z, -x / z),
+    };
+
+    // S2 uses a quadratic transform so that cells are closer to the same
+    // size across each face.
+    let st = |u: f64| if u >= 0.0 {
+        0.5 * (1.0 + 3.0 * u).sqrt()
+    } else {
+        1.0 - 0.5 * (1.0 - 3.0 * u).sqrt()
+    };
+    let max_ij = (1u64 << MAX_LEVEL) - 1;
+    let to_ij = |s: f64| ((s * (1u64 << MAX_LEVEL) as f64).floor().max(0.0) as u64).min(max_ij);
+    let (i, j) = (to_ij(st(u)), to_ij(st(v)));
+
+    // Walk down the Hilbert curve, one level at a time.
+    let mut pos = 0;
+    let mut orientation = face as usize & 1;
+    for level in (0..MAX_LEVEL).rev() {
+        let ij = ((i >> level) & 1) << 1 | ((j >> level) & 1);
+        let quadrant = IJ_TO_POS[orientation][ij as usize];
+        pos = pos << 2 | quadrant;
+        orientation ^= POS_TO_ORIENTATION[quadrant as usize];
+    }
+    (face << POS_BITS) | (pos << 1) | 1
+}
+
+/// The lowest set bit of a cell ID, which marks its level.
+fn lsb(cell: u64) -> u64 {
+    cell & cell.wrapping_neg()
+}
+
+/// The level of a cell, from 0 for a whole face to `MAX_LEVEL` for a leaf.
+pub fn level(cell: u64) -> u32 {
+    MAX_LEVEL - cell.trailing_zeros() / 2
+}
+
+/// The cell containing `cell` at `level`, which must be no deeper than
+/// `cell`'s level.
+pub fn parent(cell: u64, level: u32) -> u64 {
+    let new_lsb = 1u64 << (2 * (MAX_LEVEL - level));
+    (cell & new_lsb.wrapping_neg()) | new_lsb
+}
+
+/// The four children of a cell, in Hilbert curve order.
+fn children(cell: u64) -> [u64; 4] {
+    let new_lsb = lsb(cell) >> 2;
+    let first = cell - lsb(cell) + new_lsb;
+    [first, first + 2 * new_lsb, first + 4 * new_lsb, first + 6 * new_lsb]
+}
+
+/// The usual short form of a cell ID, which is its hexadecimal digits
+/// without trailing zeros, like `89c25`.
+pub fn token(cell: u64) -> String {
+    let hex = format!("{:016x}", cell);
+    hex.trim_end_matches('0').to_owned()
+}
+
+/// A classifier which assigns points to chunks made of S2 cells.
+#[derive(Clone, Debug)]
+pub struct S2Classifier {
+    /// The approximate weight we want in each chunk.
+    target_population: u64,
+    /// The chunk containing each of the cells which make up our chunks.
+    chunk_for_cell: HashMap<u64, usize>,
+    /// The ID of each chunk, which is the token of its first cell.
+    chunk_ids: Vec<String>,
+    /// The weight of each chunk.
+    chunk_weights: Vec<u64>,
+}
+
+impl S2Classifier {
+    /// Build chunks of about `target_population` from a list of weighted
+    /// points, each a latitude, longitude and weight.
+    pub fn new(points: &[(f64, f64, u64)], target_population: u64) -> S2Classifier {
+        let mut cells = points
+            .iter()
+            .map(|&(lat, lng, weight)| (leaf_cell_for(lat, lng), weight))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        // Running totals of our weights, so we can find the weight of any
+        // cell by searching for the range of leaf cells inside it.
+        let mut totals = Vec::with_capacity(cells.len() + 1);
+        totals.push(0);
+        for &(_, weight) in &cells {
+            let total = totals[totals.len() - 1] + weight;
+            totals.push(total);
+        }
+        let mut builder = Builder {
+            leaves: cells.iter().map(|&(cell, _)| cell).collect(),
+            totals,
+            target_population,
+            classifier: S2Classifier {
+                target_population,
+                chunk_for_cell: HashMap::new(),
+                chunk_ids: vec![],
+                chunk_weights: vec![],
+            },
+        };
+        let faces = (0..6)
+            .map(|face| (face << POS_BITS) | (1 << (POS_BITS - 1)))
+            .collect::<Vec<_>>();
+        builder.pack(&faces);
+        builder.classifier
+    }
+
+    /// The IDs of our chunks, in Hilbert curve order.
+    pub fn chunk_ids(&self) -> &[String] {
+        &self.chunk_ids
+    }
+
+    /// The total weight of the points in a chunk.
+    pub fn chunk_population(&self, chunk_id: &str) -> Option<u64> {
+        let idx = self.chunk_ids.iter().position(|id| id == chunk_id)?;
+        Some(self.chunk_weights[idx])
+    }
+
+    /// Find the cell in one of our chunks containing `(lat, lng)`, and the
+    /// index of its chunk.
+    fn cell_for_point(&self, lat: f64, lng: f64) -> Option<(u64, usize)> {
+        if !lat.is_finite() || !lng.is_finite() || lat.abs() > 90.0 || lng.abs() > 180.0 {
+            return None;
+        }
+        let leaf = leaf_cell_for(lat, lng);
+        (0..=MAX_LEVEL)
+            .map(|level| parent(leaf, level))
+            .filter_map(|cell| self.chunk_for_cell.get(&cell).map(|&idx| (cell, idx)))
+            .next()
+    }
+}
+
+impl GeoClassifier for S2Classifier {
+    fn name(&self) -> String {
+        format!("s2_{}", self.target_population)
+    }
+
+    fn area_kind(&self) -> &'static str {
+        "cell"
+    }
+
+    fn locate(&self, lat: f64, lng: f64) -> Option<(&str, String)> {
+        self.cell_for_point(lat, lng)
+            .map(|(cell, idx)| (&self.chunk_ids[idx][..], token(cell)))
+    }
+}
+
+/// The state we need while building an `S2Classifier`.
+struct Builder {
+    /// The leaf cell of each of our points, in order.
+    leaves: Vec<u64>,
+    /// The total weight of the first `n` points, for each `n`.
+    totals: Vec<u64>,
+    /// The approximate weight we want in each chunk.
+    target_population: u64,
+    /// The classifier we're building.
+    classifier: S2Classifier,
+}
+
+impl Builder {
+    /// The total weight and number of the points in `cell`.
+    fn weight_and_count(&self, cell: u64) -> (u64, usize) {
+        let (min, max) = (cell - (lsb(cell) - 1), cell + (lsb(cell) - 1));
+        let start = self.leaves.partition_point(|&leaf| leaf < min);
+        let end = self.leaves.partition_point(|&leaf| leaf <= max);
+        (self.totals[end] - self.totals[start], end - start)
+    }
+
+    /// Pack a run of neighboring cells into chunks, splitting any which are
+    /// too heavy for one chunk.
+    fn pack(&mut self, cells: &[u64]) {
+        let mut current: Option<usize> = None;
+        for &cell in cells {
+            let (weight, count) = self.weight_and_count(cell);
+            if weight > self.target_population && count > 1 && level(cell) < MAX_LEVEL {
+                current = None;
+                self.pack(&children(cell));
+                continue;
+            }
+            let classifier = &mut self.classifier;
+            let idx = match current {
+                Some(idx) if classifier.chunk_weights[idx] + weight <=
+                             self.target_population => idx,
+                _ => {
+                    classifier.chunk_ids.push(token(cell));
+                    classifier.chunk_weights.push(0);
+                    classifier.chunk_ids.len() - 1
+                }
+            };
+            classifier.chunk_weights[idx] += weight;
+            classifier.chunk_for_cell.insert(cell, idx);
+            current = Some(idx);
+        }
+    }
+}
+
+#[test]
+fn computes_cell_ids() {
+    let _ = env_logger::init();
+    // The point at latitude and longitude 0 is the center of face 0.
+    assert_eq!(leaf_cell_for(0.0, 0.0), 0x1000000000000001);
+    // The north pole is on face 2, and the south pole on face 5.
+    assert_eq!(token(parent(leaf_cell_for(90.0, 0.0), 0)), "5");
+    assert_eq!(token(parent(leaf_cell_for(-90.0, 0.0), 0)), "b");
+    // New York is in the level 3 cell 89c, and the level 8 cell 89c25.
+    let nyc = leaf_cell_for(40.7128, -74.0060);
+    assert_eq!(level(nyc), MAX_LEVEL);
+    assert_eq!(token(parent(nyc, 3)), "89c");
+    assert_eq!(token(parent(nyc, 8)), "89c25");
+    assert_eq!(level(parent(nyc, 5)), 5);
+    for &child in &children(parent(nyc, 5)) {
+        assert_eq!(parent(child, 5), parent(nyc, 5));
+        assert_eq!(level(child), 6);
+    }
+    assert!(children(parent(nyc, 5)).contains(&parent(nyc, 6)));
+}
+
+#[test]
+fn builds_s2_chunks() {
+    let _ = env_logger::init();
+    let points = vec![(40.75, -73.99, 150), (40.76, -73.98, 150), (34.10, -118.41, 100)];
+    let classifier = S2Classifier::new(&points, 200);
+    assert_eq!(classifier.name(), "s2_200");
+
+    let (nyc1, cell1) = classifier.locate(40.75, -73.99).unwrap();
+    let (nyc2, cell2) = classifier.locate(40.76, -73.98).unwrap();
+    let (la, _) = classifier.locate(34.10, -118.41).unwrap();
+    assert_ne!(nyc1, nyc2);
+    assert_ne!(cell1, cell2);
+    assert_ne!(nyc1, la);
+    assert_eq!(classifier.chunk_population(nyc1), Some(150));
+    assert_eq!(classifier.chunk_population(la), Some(100));
+    // Every point on Earth is in some chunk, even where nobody lives.
+    assert!(classifier.locate(-45.0, 170.0).is_some());
+    assert_eq!(classifier.locate(91.0, 0.0), None);
+    let total = classifier
+        .chunk_ids()
+        .iter()
+        .map(|id| classifier.chunk_population(id).unwrap())
+        .sum::<u64>();
+    assert_eq!(total, 400);
+}
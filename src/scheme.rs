@@ -0,0 +1,535 @@
+//! A registry of the chunk schemes we know about, such as `zip2010` or
+//! `soi_returns`. Each scheme knows how to weight zip codes, loading
+//! whatever data files it needs, and the command-line tool parses specs
+//! like `zip2010-250000` against this registry.
+//!
+//! Programs using this library can add their own schemes by implementing
+//! `Scheme` and passing it to `SchemeRegistry::register`.
+
+#[cfg(test)]
+use env_logger;
+use std::fmt;
+use std::io::prelude::*;
+use std::ops::RangeInclusive;
+
+use census;
+use dma::DmaTable;
+use errors::*;
+use gazetteer;
+use mexico;
+use number::Rounding;
+use soi::{self, SoiMeasure};
+use zip2010::{Classifier, DataInfo, PrefixClasses, PrefixPopulation, SpecOptions};
+
+/// The years between the 2010 and 2020 censuses, for which we support
+/// intercensal population estimates.
+pub const ESTIMATE_YEARS: RangeInclusive<u16> = 2011..=2019;
+
+/// Split a chunk spec like `zip2010-250000[naming=int]` into the name of
+/// its scheme, its target population and its options. We don't check that
+/// the scheme exists.
+pub fn parse_spec(spec: &str) -> Result<(&str, u64, SpecOptions)> {
+    let bracketed = spec.strip_suffix(']').and_then(|r| r.split_once('['));
+    let (base, options) = match bracketed {
+        Some((base, options)) => {
+            let options = options
+                .parse()
+                .map_err(|e| format!("Chunk spec \"{}\": {}", spec, e))?;
+            (base, options)
+        }
+        None => (spec, SpecOptions::default()),
+    };
+    let mut parts = base.rsplitn(2, '-');
+    let population = parts.next().and_then(|p| p.parse().ok());
+    match (parts.next(), population) {
+        (Some(name), Some(population)) => Ok((name, population, options)),
+        _ => {
+            Err(format!("Chunk spec \"{}\" should look like \"zip2010-250000\"",
+                        spec)
+                        .into())
+        }
+    }
+}
+
+/// Somewhere schemes can find the data files they need.
+pub trait DataSource {
+    /// Open the data file given by the command-line option `option`, such as
+    /// `--soi-file`, returning its path and contents, or `None` if it
+    /// wasn't given.
+    fn open(&self, option: &str) -> Result<Option<(String, Box<dyn Read>)>>;
+
+    /// How to round fractional weights in our data files.
+    fn rounding(&self) -> Rounding;
+}
+
+/// A way of weighting zip codes to build chunks.
+pub trait Scheme: Send + Sync {
+    /// The name of this scheme in chunk specs, such as `zip2010`.
+    fn name(&self) -> String;
+
+    /// A short description of what this scheme weights zip codes by.
+    fn description(&self) -> String;
+
+    /// The command-line options which must be given to use this scheme.
+    fn required_options(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Are our codes US zip codes? If not, tables keyed by US zip code,
+    /// such as states and time zones, don't apply to them.
+    fn uses_us_zip_codes(&self) -> bool {
+        true
+    }
+
+    /// Weight each zip code, loading any data we need from `sources`.
+    fn prefix_population(&self, sources: &dyn DataSource) -> Result<PrefixPopulation>;
+
+    /// Classes of zip code prefixes which should never share a chunk, if
+    /// any, loading any data we need from `sources`.
+    fn prefix_classes(&self, _sources: &dyn DataSource) -> Result<Option<Box<dyn PrefixClasses>>> {
+        Ok(None)
+    }
+
+    /// Describe where `prefix_population`, our weights, came from, for the
+    /// metadata of our chunks. By default, this describes the file given by
+    /// our first required option, if any.
+    fn data_info(&self,
+                 sources: &dyn DataSource,
+                 prefix_population: &PrefixPopulation)
+                 -> Result<Option<DataInfo>> {
+        let option = match self.required_options().first() {
+            Some(option) => option,
+            None => return Ok(None),
+        };
+        match sources.open(option)? {
+            Some((path, mut rdr)) => {
+                let mut contents = vec![];
+                rdr.read_to_end(&mut contents)
+                    .chain_err(|| format!("could not read {}", path))?;
+                Ok(Some(DataInfo::for_file(&path, &contents, prefix_population)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl fmt::Debug for dyn Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Scheme({})", self.name())
+    }
+}
+
+/// Open the data file given by `option`, reporting an error which mentions
+/// `scheme` if it's missing, and pass it to `load`.
+fn load_required<T, F>(sources: &dyn DataSource,
+                       scheme: &dyn Scheme,
+                       option: &str,
+                       load: F)
+                       -> Result<T>
+where
+    F: FnOnce(&mut dyn Read) -> Result<T>,
+{
+    let (path, mut rdr) = sources
+        .open(option)?
+        .ok_or_else(|| format!("the {} type requires {}", scheme.name(), option))?;
+    load(&mut rdr).chain_err(|| format!("could not read {}", path))
+}
+
+/// 2010 Census population, built into this library.
+#[cfg(feature = "data-zip2010")]
+struct Census2010;
+
+#[cfg(feature = "data-zip2010")]
+impl Scheme for Census2010 {
+    fn name(&self) -> String {
+        "zip2010".to_owned()
+    }
+
+    fn description(&self) -> String {
+        "2010 Census zip code population".to_owned()
+    }
+
+    fn prefix_population(&self, _: &dyn DataSource) -> Result<PrefixPopulation> {
+        Ok(PrefixPopulation::census2010())
+    }
+
+    fn data_info(&self, _: &dyn DataSource, _: &PrefixPopulation) -> Result<Option<DataInfo>> {
+        Ok(Some(DataInfo::census2010()))
+    }
+}
+
+/// 2000 Census population, loaded from `--census2000-file`.
+struct Census2000;
+
+impl Scheme for Census2000 {
+    fn name(&self) -> String {
+        "zip2000".to_owned()
+    }
+
+    fn description(&self) -> String {
+        "2000 Census zip code population".to_owned()
+    }
+
+    fn required_options(&self) -> &'static [&'static str] {
+        &["--census2000-file"]
+    }
+
+    fn prefix_population(&self, sources: &dyn DataSource) -> Result<PrefixPopulation> {
+        let rounding = sources.rounding();
+        let weights = load_required(sources, self, "--census2000-file", |rdr| {
+            census::load_zip_populations(rdr, rounding)
+        })?;
+        Ok(PrefixPopulation::from_weights(weights))
+    }
+}
+
+/// Intercensal population estimates for one year, loaded from
+/// `--estimates-file`.
+struct Estimate(u16);
+
+impl Scheme for Estimate {
+    fn name(&self) -> String {
+        format!("zip-est{}", self.0)
+    }
+
+    fn description(&self) -> String {
+        format!("{} intercensal zip code population estimates", self.0)
+    }
+
+    fn required_options(&self) -> &'static [&'static str] {
+        &["--estimates-file"]
+    }
+
+    fn prefix_population(&self, sources: &dyn DataSource) -> Result<PrefixPopulation> {
+        let rounding = sources.rounding();
+        let weights = load_required(sources, self, "--estimates-file", |rdr| {
+            census::load_zip_estimates(rdr, self.0, rounding)
+        })?;
+        Ok(PrefixPopulation::from_weights(weights))
+    }
+}
+
+/// IRS Statistics of Income data, loaded from `--soi-file`.
+struct Soi(SoiMeasure);
+
+impl Scheme for Soi {
+    fn name(&self) -> String {
+        self.0.weight_name().to_owned()
+    }
+
+    fn description(&self) -> String {
+        match self.0 {
+            SoiMeasure::Returns => {
+                "IRS SOI counts of individual income tax returns".to_owned()
+            }
+            SoiMeasure::Agi => {
+                "IRS SOI adjusted gross income, in thousands of dollars".to_owned()
+            }
+        }
+    }
+
+    fn required_options(&self) -> &'static [&'static str] {
+        &["--soi-file"]
+    }
+
+    fn prefix_population(&self, sources: &dyn DataSource) -> Result<PrefixPopulation> {
+        let rounding = sources.rounding();
+        let weights = load_required(sources, self, "--soi-file", |rdr| {
+            soi::load_weights(rdr, self.0, rounding)
+        })?;
+        Ok(PrefixPopulation::from_weights(weights))
+    }
+}
+
+/// Census land area, loaded from `--gazetteer`.
+struct Area;
+
+impl Scheme for Area {
+    fn name(&self) -> String {
+        "area".to_owned()
+    }
+
+    fn description(&self) -> String {
+        "Census land area, in hundredths of a square mile".to_owned()
+    }
+
+    fn required_options(&self) -> &'static [&'static str] {
+        &["--gazetteer"]
+    }
+
+    fn prefix_population(&self, sources: &dyn DataSource) -> Result<PrefixPopulation> {
+        let zctas =
+            load_required(sources, self, "--gazetteer", gazetteer::load_zctas)?;
+        // Many urban ZCTAs cover less than half a square mile, so we use a
+        // finer unit to keep them from rounding down to nothing.
+        let weights = zctas
+            .iter()
+            .map(|z| (z.zip.clone(), (z.land_area_sq_mi() * 100.0).round() as u64));
+        Ok(PrefixPopulation::from_weights(weights))
+    }
+}
+
+/// The population of each designated market area, loaded from
+/// `--dma-file`, with chunks which never cross a DMA boundary.
+struct Dma;
+
+impl Dma {
+    /// Load our DMA file.
+    fn load(&self, sources: &dyn DataSource) -> Result<DmaTable> {
+        let rounding = sources.rounding();
+        load_required(sources, self, "--dma-file", |rdr| DmaTable::load(rdr, rounding))
+    }
+}
+
+impl Scheme for Dma {
+    fn name(&self) -> String {
+        "dma".to_owned()
+    }
+
+    fn description(&self) -> String {
+        "Zip code population, without crossing designated market areas".to_owned()
+    }
+
+    fn required_options(&self) -> &'static [&'static str] {
+        &["--dma-file"]
+    }
+
+    fn prefix_population(&self, sources: &dyn DataSource) -> Result<PrefixPopulation> {
+        self.load(sources)?
+            .prefix_population()
+            .ok_or_else(|| "the dma type requires a population column in --dma-file".into())
+    }
+
+    fn prefix_classes(&self, sources: &dyn DataSource) -> Result<Option<Box<dyn PrefixClasses>>> {
+        Ok(Some(Box::new(self.load(sources)?)))
+    }
+}
+
+/// INEGI population of Mexican postal codes, loaded from `--mx-cp-file`.
+struct MexicoCp;
+
+impl Scheme for MexicoCp {
+    fn name(&self) -> String {
+        "mx_cp".to_owned()
+    }
+
+    fn description(&self) -> String {
+        "INEGI population of Mexican postal codes".to_owned()
+    }
+
+    fn required_options(&self) -> &'static [&'static str] {
+        &["--mx-cp-file"]
+    }
+
+    fn uses_us_zip_codes(&self) -> bool {
+        false
+    }
+
+    fn prefix_population(&self, sources: &dyn DataSource) -> Result<PrefixPopulation> {
+        let rounding = sources.rounding();
+        let weights = load_required(sources, self, "--mx-cp-file", |rdr| {
+            mexico::load_cp_populations(rdr, rounding)
+        })?;
+        Ok(PrefixPopulation::from_weights(weights))
+    }
+}
+
+/// The schemes we can build chunks with, in the order we list them.
+#[derive(Debug, Default)]
+pub struct SchemeRegistry {
+    schemes: Vec<Box<dyn Scheme>>,
+}
+
+impl SchemeRegistry {
+    /// Create an empty registry.
+    pub fn new() -> SchemeRegistry {
+        SchemeRegistry::default()
+    }
+
+    /// Create a registry containing every scheme built into this library.
+    pub fn builtin() -> SchemeRegistry {
+        let mut schemes: Vec<Box<dyn Scheme>> = vec![];
+        #[cfg(feature = "data-zip2010")]
+        schemes.push(Box::new(Census2010));
+        schemes.push(Box::new(Census2000));
+        for year in ESTIMATE_YEARS {
+            schemes.push(Box::new(Estimate(year)));
+        }
+        schemes.push(Box::new(Soi(SoiMeasure::Returns)));
+        schemes.push(Box::new(Soi(SoiMeasure::Agi)));
+        schemes.push(Box::new(Area));
+        schemes.push(Box::new(Dma));
+        schemes.push(Box::new(MexicoCp));
+        SchemeRegistry { schemes }
+    }
+
+    /// Add `scheme` to this registry. Scheme names must be unique.
+    pub fn register(&mut self, scheme: Box<dyn Scheme>) -> Result<()> {
+        let name = scheme.name();
+        if self.find(&name).is_some() {
+            return Err(format!("chunk scheme {} is already registered", name).into());
+        }
+        self.schemes.push(scheme);
+        Ok(())
+    }
+
+    /// Look up the scheme called `name`.
+    pub fn find(&self, name: &str) -> Option<&dyn Scheme> {
+        self.schemes
+            .iter()
+            .find(|s| s.name() == name)
+            .map(|s| &**s)
+    }
+
+    /// Every scheme in this registry, in the order they were registered.
+    pub fn schemes(&self) -> impl Iterator<Item = &dyn Scheme> {
+        self.schemes.iter().map(|s| &**s)
+    }
+
+    /// Weight each zip code using the scheme called `name`.
+    pub fn prefix_population(&self,
+                             name: &str,
+                             sources: &dyn DataSource)
+                             -> Result<PrefixPopulation> {
+        trace_span!("load_weights", scheme = name);
+        self.find(name)
+            .ok_or_else(|| format!("unknown chunk scheme {:?}", name))?
+            .prefix_population(sources)
+    }
+}
+
+/// A `DataSource` with no data files, for environments like AWS Lambda,
+/// where only schemes with built-in data are available.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoFiles;
+
+impl DataSource for NoFiles {
+    fn open(&self, _: &str) -> Result<Option<(String, Box<dyn Read>)>> {
+        Ok(None)
+    }
+
+    fn rounding(&self) -> Rounding {
+        Rounding::Nearest
+    }
+}
+
+/// Build the classifier for a spec like `zip2010-250000[naming=int]`,
+/// using only built-in data. This is how we build chunks wherever we're
+/// configured with nothing but a spec string, so anything else which
+/// promises to match those chunks should check against it.
+pub fn builtin_classifier(spec: &str) -> Result<Classifier> {
+    let (name, population, options) = parse_spec(spec)?;
+    let registry = SchemeRegistry::builtin();
+    let prefix_population = registry.prefix_population(name, &NoFiles)?;
+    let scheme = registry.find(name).expect("scheme should have been found");
+    if options.respect_states && !scheme.uses_us_zip_codes() {
+        return Err(format!("spec option respect_states cannot be used with {}", name).into());
+    }
+    let states = options.state_classes(&prefix_population);
+    let (mut classifier, _) =
+        Classifier::from_prefix_population_with_minimum(name,
+                                                        &prefix_population,
+                                                        population,
+                                                        options.min_pop.unwrap_or(0),
+                                                        states
+                                                            .as_ref()
+                                                            .map(|s| s as &dyn PrefixClasses));
+    if let Some(info) = scheme.data_info(&NoFiles, &prefix_population)? {
+        classifier = classifier.with_data_info(info);
+    }
+    options.apply(classifier)
+}
+
+#[cfg(all(test, feature = "data-zip2010"))]
+struct Uniform;
+
+#[cfg(all(test, feature = "data-zip2010"))]
+impl Scheme for Uniform {
+    fn name(&self) -> String {
+        "uniform".to_owned()
+    }
+
+    fn description(&self) -> String {
+        "One for every zip code".to_owned()
+    }
+
+    fn prefix_population(&self, _: &dyn DataSource) -> Result<PrefixPopulation> {
+        let weights = (0..100_000).map(|z| (format!("{:05}", z), 1));
+        Ok(PrefixPopulation::from_weights(weights))
+    }
+}
+
+#[cfg(test)]
+struct Gazetteer(&'static str);
+
+#[cfg(test)]
+impl DataSource for Gazetteer {
+    fn open(&self, option: &str) -> Result<Option<(String, Box<dyn Read>)>> {
+        assert_eq!(option, "--gazetteer");
+        Ok(Some(("gaz.txt".to_owned(), Box::new(self.0.as_bytes()))))
+    }
+
+    fn rounding(&self) -> Rounding {
+        Rounding::Nearest
+    }
+}
+
+#[test]
+fn weights_land_area_in_hundredths_of_a_square_mile() {
+    let _ = env_logger::init();
+    // 10001 covers about 0.63 square miles, and 10005 about 0.19.
+    let gazetteer = Gazetteer("GEOID\tALAND\n10001\t1640604\n10005\t490022\n");
+    let population = SchemeRegistry::builtin()
+        .prefix_population("area", &gazetteer)
+        .unwrap();
+    assert_eq!(population.lookup("10001"), 63);
+    assert_eq!(population.lookup("10005"), 19);
+
+    // Our metadata describes the gazetteer, not our census data.
+    let registry = SchemeRegistry::builtin();
+    let info = registry.find("area")
+        .unwrap()
+        .data_info(&gazetteer, &population)
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.source, "gaz.txt");
+    assert_eq!(info.vintage, None);
+    assert_eq!((info.rows, info.total_population), (2, 82));
+}
+
+#[test]
+fn parses_specs() {
+    let _ = env_logger::init();
+    let (name, population, options) = parse_spec("zip-est2015-1000").unwrap();
+    assert_eq!((name, population), ("zip-est2015", 1000));
+    assert!(options.is_empty());
+    let (name, _, options) = parse_spec("zip2010-250000[new_zip_fallback]").unwrap();
+    assert_eq!(name, "zip2010");
+    assert!(options.new_zip_fallback);
+    for invalid in &["zip2010", "zip2010-lots", "zip2010-250000[bogus]"] {
+        assert!(parse_spec(invalid).is_err(), "{:?}", invalid);
+    }
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn registers_schemes() {
+    let _ = env_logger::init();
+    let mut registry = SchemeRegistry::builtin();
+    let names = registry.schemes().map(|s| s.name()).collect::<Vec<_>>();
+    assert_eq!(names[0], "zip2010");
+    assert!(names.contains(&"zip-est2015".to_owned()));
+    assert_eq!(registry.find("area").unwrap().required_options(), &["--gazetteer"]);
+    assert!(registry.find("zip2020").is_none());
+
+    let err = registry.prefix_population("soi_agi", &NoFiles).unwrap_err();
+    assert_eq!(err.to_string(), "the soi_agi type requires --soi-file");
+    assert_eq!(registry.find("mx_cp").unwrap().required_options(), &["--mx-cp-file"]);
+    assert!(!registry.find("mx_cp").unwrap().uses_us_zip_codes());
+    assert!(registry.find("zip2000").unwrap().uses_us_zip_codes());
+
+    registry.register(Box::new(Uniform)).unwrap();
+    assert!(registry.register(Box::new(Uniform)).is_err());
+    let population = registry.prefix_population("uniform", &NoFiles).unwrap();
+    assert_eq!(population.lookup(""), 100_000);
+}
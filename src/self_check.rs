@@ -0,0 +1,190 @@
+//! Checking a classifier against every zip code and a variety of malformed
+//! input, so that deployment pipelines can make sure a new build or a new
+//! data file produces sensible chunks before relying on it.
+//!
+//! We check that:
+//!
+//! - Every 5-digit zip code is assigned to exactly one chunk, using a
+//!   prefix of that zip code, and that every way of looking up its chunk
+//!   agrees.
+//! - The population of each chunk is the total population of its zip
+//!   codes.
+//! - Malformed input never panics, and is never classified using anything
+//!   but a prefix of that input.
+
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::panic::{self, AssertUnwindSafe};
+
+#[cfg(all(test, feature = "data-zip2010"))]
+use noise::LaplaceNoise;
+use zip2010::Classifier;
+
+/// The number of problems we describe in a report. We still count the rest.
+const MAX_DESCRIBED_PROBLEMS: usize = 20;
+
+/// Malformed inputs which we check as written. We also generate malformed
+/// versions of every zip code.
+const MALFORMED_INPUTS: &[&str] = &["",
+                                    " ",
+                                    "null",
+                                    "N/A",
+                                    "9021",
+                                    " 90210",
+                                    "-90210",
+                                    "9021é",
+                                    "é9021",
+                                    "\u{661}\u{662}\u{663}\u{664}\u{665}",
+                                    "\u{ff11}\u{ff12}\u{ff13}\u{ff14}\u{ff15}",
+                                    "\u{feff}90210",
+                                    "\u{1F3E0}\u{1F3E0}",
+                                    "\0\0\0\0\0",
+                                    "99999999999999999999"];
+
+/// Characters we substitute into zip codes to make malformed inputs.
+const BAD_CHARS: &[char] = &['x', ' ', '/', ':', '-', 'é', '\u{663}', '\u{ff13}',
+                             '\0'];
+
+/// The results of checking a classifier.
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+    /// The number of inputs we classified.
+    pub inputs: u64,
+    /// The number of problems we found.
+    pub problem_count: u64,
+    /// Descriptions of the first few problems we found.
+    pub problems: Vec<String>,
+}
+
+impl CheckReport {
+    /// Did every check pass?
+    pub fn is_ok(&self) -> bool {
+        self.problem_count == 0
+    }
+
+    /// Record a problem.
+    fn problem(&mut self, problem: String) {
+        self.problem_count += 1;
+        if self.problems.len() < MAX_DESCRIBED_PROBLEMS {
+            self.problems.push(problem);
+        }
+    }
+
+    /// Classify `input` without letting a panic escape, recording a problem
+    /// if it panics.
+    fn classify<'c>(&mut self,
+                    classifier: &'c Classifier,
+                    input: &str)
+                    -> Option<Option<(&'c str, &'c str)>> {
+        self.inputs += 1;
+        let classify = AssertUnwindSafe(|| classifier.prefix_and_chunk_for(input));
+        let result = panic::catch_unwind(classify);
+        if result.is_err() {
+            self.problem(format!("classifying {:?} panicked", input));
+        }
+        result.ok()
+    }
+}
+
+/// Check `classifier`, returning a report of any problems we found.
+pub fn self_check(classifier: &Classifier) -> CheckReport {
+    let mut report = CheckReport::default();
+    check_zips(classifier, &mut report);
+    for &input in MALFORMED_INPUTS {
+        check_malformed(classifier, input, &mut report);
+    }
+    // Make one malformed version of each zip code, changing a different
+    // position and using a different character as we go.
+    for zip_int in 0..100_000 {
+        let mut chars = format!("{:05}", zip_int).chars().collect::<Vec<_>>();
+        let position = zip_int % chars.len();
+        chars[position] = BAD_CHARS[zip_int % BAD_CHARS.len()];
+        let input = chars.into_iter().collect::<String>();
+        check_malformed(classifier, &input, &mut report);
+    }
+    report
+}
+
+/// Check that every 5-digit zip code has exactly one chunk, and that chunk
+/// populations add up.
+fn check_zips(classifier: &Classifier, report: &mut CheckReport) {
+    let zips = (0..100_000)
+        .map(|zip_int| format!("{:05}", zip_int))
+        .collect::<Vec<_>>();
+    let indices = classifier.chunk_indices(zips.iter().map(|z| Some(&z[..])));
+    for (zip, index) in zips.iter().zip(indices) {
+        let (prefix, chunk_id) = match report.classify(classifier, zip) {
+            Some(Some(found)) => found,
+            Some(None) => {
+                report.problem(format!("zip code {} is not in any chunk", zip));
+                continue;
+            }
+            None => continue,
+        };
+        if !zip.starts_with(prefix) {
+            report.problem(format!("zip code {} was classified using prefix {:?}",
+                                   zip,
+                                   prefix));
+        }
+        if index.map(|i| i as usize) != classifier.chunk_index(chunk_id) {
+            report.problem(format!("zip code {} is in chunk {}, but has index {:?}",
+                                   zip,
+                                   chunk_id,
+                                   index));
+        }
+        let zip4 = format!("{}-1234", zip);
+        match report.classify(classifier, &zip4) {
+            Some(found) if found.map(|(_, c)| c) != Some(chunk_id) => {
+                report.problem(format!("{} is in chunk {:?}, but {} is in chunk {}",
+                                       zip4,
+                                       found.map(|(_, c)| c),
+                                       zip,
+                                       chunk_id));
+            }
+            _ => {}
+        }
+    }
+
+    let check = classifier.check_population();
+    for m in &check.mismatched_chunks {
+        report.problem(format!("chunk {} has a population of {}, but its zip codes \
+                                have a total population of {}",
+                               m.chunk_id,
+                               m.population,
+                               m.zip_population));
+    }
+    if check.chunk_population != check.source_population {
+        report.problem(format!("our chunks have a total population of {}, but our \
+                                data has a total population of {}",
+                               check.chunk_population,
+                               check.source_population));
+    }
+}
+
+/// Check that classifying `input`, which isn't a valid zip code, doesn't
+/// panic, and that it's classified using a prefix of `input` if at all.
+fn check_malformed(classifier: &Classifier, input: &str, report: &mut CheckReport) {
+    if let Some(Some((prefix, chunk_id))) = report.classify(classifier, input) {
+        if !input.starts_with(prefix) {
+            report.problem(format!("{:?} was put in chunk {} using prefix {:?}",
+                                   input,
+                                   chunk_id,
+                                   prefix));
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn checks_classifiers() {
+    let _ = env_logger::init();
+    let report = self_check(&Classifier::new(250000));
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert!(report.inputs > 300_000);
+
+    let noise = LaplaceNoise::new(1000.0, Some(1)).unwrap();
+    let report = self_check(&Classifier::new(250000).with_population_noise(&noise));
+    assert!(!report.is_ok());
+    assert_eq!(report.problems.len(), MAX_DESCRIBED_PROBLEMS);
+    assert!(report.problems[0].contains(" has a population of "));
+}
@@ -0,0 +1,242 @@
+//! A minimal reader for ESRI shapefiles, which is just enough to read the
+//! polygons and attributes used by `geography`. A shapefile is really
+//! several files: the `.shp` file contains the shapes, and the `.dbf` file
+//! contains a table of attributes with one record per shape.
+
+#[cfg(test)]
+use env_logger;
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+use errors::*;
+use geography::Ring;
+
+/// The magic number at the start of every `.shp` file.
+const SHP_FILE_CODE: i32 = 9994;
+
+/// The shape types we read, which are all polygons. The `Z` and `M`
+/// variants add elevations or measures after the points, which we ignore.
+const POLYGON: i32 = 5;
+const POLYGON_Z: i32 = 15;
+const POLYGON_M: i32 = 25;
+
+/// One shape from a shapefile, and its attributes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shape {
+    /// The rings of the shape's polygons, including any holes.
+    pub rings: Vec<Ring>,
+    /// The shape's attributes from the `.dbf` file, by field name.
+    pub attributes: HashMap<String, String>,
+}
+
+/// Read the polygons from `shp` and their attributes from `dbf`.
+pub fn read_shapefile(shp: &mut dyn Read, dbf: &mut dyn Read) -> Result<Vec<Shape>> {
+    let rings = read_shp(shp)?;
+    let attributes = read_dbf(dbf)?;
+    if rings.len() != attributes.len() {
+        return Err(format!("shapefile has {} shapes but {} attribute records",
+                           rings.len(),
+                           attributes.len())
+                           .into());
+    }
+    Ok(rings
+           .into_iter()
+           .zip(attributes)
+           .map(|(rings, attributes)| Shape { rings, attributes })
+           .collect())
+}
+
+/// Read the rings of each shape in a `.shp` file. Null shapes have no
+/// rings.
+fn read_shp(input: &mut dyn Read) -> Result<Vec<Vec<Ring>>> {
+    let mut data = vec![];
+    input.read_to_end(&mut data)?;
+    if data.len() < 100 || be_i32(&data, 0) != SHP_FILE_CODE {
+        return Err("not a .shp file".into());
+    }
+    let mut shapes = vec![];
+    let mut offset = 100;
+    while offset < data.len() {
+        if offset + 8 > data.len() {
+            return Err("truncated .shp record header".into());
+        }
+        // Lengths are measured in 16-bit words.
+        let len = be_i32(&data, offset + 4) as usize * 2;
+        let content = data.get(offset + 8..offset + 8 + len)
+            .ok_or("truncated .shp record")?;
+        shapes.push(read_shape(content)?);
+        offset += 8 + len;
+    }
+    Ok(shapes)
+}
+
+/// Read the rings of one shape record.
+fn read_shape(content: &[u8]) -> Result<Vec<Ring>> {
+    if content.len() < 4 {
+        return Err("truncated .shp record".into());
+    }
+    match le_i32(content, 0) {
+        0 => return Ok(vec![]),
+        POLYGON | POLYGON_Z | POLYGON_M => {}
+        other => return Err(format!("unsupported shape type {}, expected polygons", other).into()),
+    }
+    // Skip the shape type and bounding box.
+    if content.len() < 44 {
+        return Err("truncated .shp polygon".into());
+    }
+    let num_parts = le_i32(content, 36) as usize;
+    let num_points = le_i32(content, 40) as usize;
+    let points_offset = 44 + 4 * num_parts;
+    if content.len() < points_offset + 16 * num_points {
+        return Err("truncated .shp polygon".into());
+    }
+    let mut starts = (0..num_parts)
+        .map(|i| le_i32(content, 44 + 4 * i) as usize)
+        .collect::<Vec<_>>();
+    starts.push(num_points);
+    let mut rings = vec![];
+    for window in starts.windows(2) {
+        if window[0] > window[1] {
+            return Err("invalid .shp polygon parts".into());
+        }
+        let ring = (window[0]..window[1])
+            .map(|i| {
+                     let at = points_offset + 16 * i;
+                     (le_f64(content, at), le_f64(content, at + 8))
+                 })
+            .collect();
+        rings.push(ring);
+    }
+    Ok(rings)
+}
+
+/// Read the records of a `.dbf` file, trimming each value.
+fn read_dbf(input: &mut dyn Read) -> Result<Vec<HashMap<String, String>>> {
+    let mut data = vec![];
+    input.read_to_end(&mut data)?;
+    if data.len() < 32 {
+        return Err("not a .dbf file".into());
+    }
+    let num_records = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let header_len = u16::from_le_bytes([data[8], data[9]]) as usize;
+    let record_len = u16::from_le_bytes([data[10], data[11]]) as usize;
+
+    // Field descriptors are 32 bytes each, ending with a 0x0D byte.
+    let mut fields = vec![];
+    let mut offset = 32;
+    while offset < header_len.min(data.len()) && data[offset] != 0x0D {
+        let descriptor = data.get(offset..offset + 32).ok_or("truncated .dbf header")?;
+        let name_len = descriptor[..11].iter().position(|&b| b == 0).unwrap_or(11);
+        let name = String::from_utf8_lossy(&descriptor[..name_len]).trim().to_owned();
+        fields.push((name, descriptor[16] as usize));
+        offset += 32;
+    }
+
+    let mut records = vec![];
+    for i in 0..num_records {
+        let start = header_len + i * record_len;
+        let record = data.get(start..start + record_len)
+            .ok_or("truncated .dbf record")?;
+        // Records start with a deletion flag, which is `*` for deleted
+        // records. Deleted records still have a shape, so we keep them.
+        let mut at = 1;
+        let mut attributes = HashMap::new();
+        for &(ref name, len) in &fields {
+            let value = record.get(at..at + len).ok_or("truncated .dbf record")?;
+            attributes.insert(name.clone(), String::from_utf8_lossy(value).trim().to_owned());
+            at += len;
+        }
+        records.push(attributes);
+    }
+    Ok(records)
+}
+
+/// Read a big-endian `i32` at `offset`.
+fn be_i32(data: &[u8], offset: usize) -> i32 {
+    i32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Read a little-endian `i32` at `offset`.
+fn le_i32(data: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Read a little-endian `f64` at `offset`.
+fn le_f64(data: &[u8], offset: usize) -> f64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    f64::from_le_bytes(bytes)
+}
+
+/// Build a shapefile containing `shapes`, each with a `NAME` and `POP`
+/// attribute, for testing. Returns the `.shp` and `.dbf` files.
+#[cfg(test)]
+fn write_test_shapefile(shapes: &[(&str, u64, Vec<Ring>)]) -> (Vec<u8>, Vec<u8>) {
+    let mut records = vec![];
+    for (i, (_, _, rings)) in shapes.iter().enumerate() {
+        let num_points = rings.iter().map(|r| r.len()).sum::<usize>();
+        let mut content = vec![];
+        content.extend_from_slice(&POLYGON.to_le_bytes());
+        content.extend_from_slice(&[0; 32]);
+        content.extend_from_slice(&(rings.len() as i32).to_le_bytes());
+        content.extend_from_slice(&(num_points as i32).to_le_bytes());
+        let mut start = 0;
+        for ring in rings {
+            content.extend_from_slice(&(start as i32).to_le_bytes());
+            start += ring.len();
+        }
+        for &(x, y) in rings.iter().flatten() {
+            content.extend_from_slice(&x.to_le_bytes());
+            content.extend_from_slice(&y.to_le_bytes());
+        }
+        records.extend_from_slice(&(i as i32 + 1).to_be_bytes());
+        records.extend_from_slice(&(content.len() as i32 / 2).to_be_bytes());
+        records.extend_from_slice(&content);
+    }
+    let mut shp = vec![0; 100];
+    shp[..4].copy_from_slice(&SHP_FILE_CODE.to_be_bytes());
+    shp[24..28].copy_from_slice(&((100 + records.len()) as i32 / 2).to_be_bytes());
+    shp[28..32].copy_from_slice(&1000i32.to_le_bytes());
+    shp[32..36].copy_from_slice(&POLYGON.to_le_bytes());
+    shp.extend_from_slice(&records);
+
+    let fields: &[(&str, u8, usize)] = &[("NAME", b'C', 10), ("POP", b'N', 10)];
+    let header_len = 32 + 32 * fields.len() + 1;
+    let record_len = 1 + fields.iter().map(|f| f.2).sum::<usize>();
+    let mut dbf = vec![0; 32];
+    dbf[0] = 3;
+    dbf[4..8].copy_from_slice(&(shapes.len() as u32).to_le_bytes());
+    dbf[8..10].copy_from_slice(&(header_len as u16).to_le_bytes());
+    dbf[10..12].copy_from_slice(&(record_len as u16).to_le_bytes());
+    for &(name, kind, len) in fields {
+        let mut descriptor = [0; 32];
+        descriptor[..name.len()].copy_from_slice(name.as_bytes());
+        descriptor[11] = kind;
+        descriptor[16] = len as u8;
+        dbf.extend_from_slice(&descriptor);
+    }
+    dbf.push(0x0D);
+    for &(name, pop, _) in shapes {
+        dbf.push(b' ');
+        dbf.extend_from_slice(format!("{:<10}{:>10}", name, pop).as_bytes());
+    }
+    dbf.push(0x1A);
+    (shp, dbf)
+}
+
+#[test]
+fn reads_shapefiles() {
+    let _ = env_logger::init();
+    let square = vec![(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+    let hole = vec![(0.2, 0.2), (0.8, 0.2), (0.8, 0.8), (0.2, 0.8), (0.2, 0.2)];
+    let (shp, dbf) = write_test_shapefile(&[("north", 100, vec![square.clone()]),
+                                            ("south", 250, vec![square.clone(), hole.clone()])]);
+    let shapes = read_shapefile(&mut &shp[..], &mut &dbf[..]).unwrap();
+    assert_eq!(shapes.len(), 2);
+    assert_eq!(shapes[1].rings, vec![square, hole]);
+    assert_eq!(shapes[0].attributes["NAME"], "north");
+    assert_eq!(shapes[1].attributes["POP"], "250");
+
+    assert!(read_shapefile(&mut &dbf[..], &mut &dbf[..]).is_err());
+    assert!(read_shapefile(&mut &shp[..shp.len() - 8], &mut &dbf[..]).is_err());
+}
@@ -0,0 +1,380 @@
+//! Splitting CSV output into shards of bounded size, for loaders which
+//! can't handle files above a certain size.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+#[cfg(test)]
+use std::env;
+#[cfg(test)]
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+#[cfg(test)]
+use std::process;
+use std::rc::Rc;
+
+use dialect::Dialect;
+use errors::*;
+use uring::{RingFile, RingWriter};
+
+/// How large each shard may be. A shard always contains at least one row,
+/// even if that row alone is larger than `max_bytes`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShardLimits {
+    /// The most rows to put in each shard, not counting headers.
+    pub max_rows: Option<u64>,
+    /// The most bytes to put in each shard, including headers.
+    pub max_bytes: Option<u64>,
+}
+
+impl ShardLimits {
+    /// Are we writing everything to a single file?
+    pub fn is_unlimited(&self) -> bool {
+        self.max_rows.is_none() && self.max_bytes.is_none()
+    }
+}
+
+/// A shard which we wrote.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardInfo {
+    /// Where we wrote this shard.
+    pub path: PathBuf,
+    /// The number of rows in this shard, not counting headers.
+    pub rows: u64,
+    /// The size of this shard, in bytes.
+    pub bytes: u64,
+    /// The SHA-256 checksum of this shard, in hex.
+    pub sha256: String,
+}
+
+/// The path of shard number `shard` of `path`, so that shard 3 of
+/// `people.csv` is `people-00003.csv`.
+pub fn shard_path(path: &Path, shard: usize) -> PathBuf {
+    let stem = path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{:05}.{}", stem, shard, ext.to_string_lossy()),
+        None => format!("{}-{:05}", stem, shard),
+    };
+    path.with_file_name(name)
+}
+
+/// Writes CSV rows to `path`, or if we have limits, to a series of shards
+/// named by `shard_path`, each starting with our headers. The current shard
+/// can be closed at any time to free up its file handle, and it will be
+/// reopened when we write to it again.
+pub(crate) struct ShardedWriter<'a> {
+    /// The path we write to, before adding a shard number.
+    path: PathBuf,
+    /// How large each shard may be.
+    limits: ShardLimits,
+    /// How to format our output.
+    dialect: &'a Dialect,
+    /// The headers to write at the start of each shard.
+    headers: Option<csv::ByteRecord>,
+    /// The shards we've started so far. The last one is the current shard.
+    shards: Vec<ShardInfo>,
+    /// The number of bytes in the current shard.
+    shard_bytes: u64,
+    /// Our current shard, if it's open.
+    wtr: Option<csv::Writer<HashingFile>>,
+    /// A checksum of everything written to our current shard.
+    hasher: Rc<RefCell<Sha256>>,
+    /// The number of bytes written to our current shard so far.
+    written: Rc<Cell<u64>>,
+    /// Measures rows, if we have a byte limit.
+    sizer: Option<RowSizer>,
+    /// Writes our shards using `io_uring`, if we've been asked to.
+    ring: Option<Rc<RefCell<RingWriter>>>,
+}
+
+impl<'a> ShardedWriter<'a> {
+    /// Prepare to write to `path`. No files are created until we write
+    /// headers or rows.
+    pub(crate) fn new(path: &Path, limits: ShardLimits, dialect: &'a Dialect) -> ShardedWriter<'a> {
+        ShardedWriter {
+            path: path.to_owned(),
+            limits,
+            dialect,
+            headers: None,
+            shards: vec![],
+            shard_bytes: 0,
+            wtr: None,
+            hasher: Rc::new(RefCell::new(Sha256::new())),
+            written: Rc::new(Cell::new(0)),
+            sizer: limits.max_bytes.map(|_| RowSizer::new(dialect)),
+            ring: None,
+        }
+    }
+
+    /// Write our shards using `ring`. Our output is only complete once
+    /// `ring` has been flushed.
+    pub(crate) fn use_ring(&mut self, ring: &Rc<RefCell<RingWriter>>) {
+        self.ring = Some(ring.clone());
+    }
+
+    /// Write `headers` to the start of each shard, starting with the first.
+    pub(crate) fn write_headers(&mut self, headers: &csv::ByteRecord) -> Result<()> {
+        self.headers = Some(headers.to_owned());
+        self.start_shard()
+    }
+
+    /// Write `row` to the current shard, starting a new one if it's full.
+    pub(crate) fn write_row(&mut self, row: &csv::ByteRecord) -> Result<()> {
+        let size = match self.sizer {
+            Some(ref mut sizer) => sizer.size(row)?,
+            None => 0,
+        };
+        let full = match self.shards.last() {
+            None => true,
+            Some(shard) => {
+                shard.rows > 0 &&
+                (self.limits.max_rows.is_some_and(|max| shard.rows >= max) ||
+                 self.limits.max_bytes.is_some_and(|max| self.shard_bytes + size > max))
+            }
+        };
+        if full {
+            self.start_shard()?;
+        }
+        if self.wtr.is_none() {
+            let path = &self.shards.last().expect("should have a shard").path;
+            // `io_uring` writes at explicit offsets, so it can't append.
+            let file = OpenOptions::new()
+                .append(self.ring.is_none())
+                .write(true)
+                .open(path)
+                .chain_err(|| format!("could not open {}", path.display()))?;
+            self.wtr = Some(self.hashing_writer(file));
+        }
+        self.wtr
+            .as_mut()
+            .expect("should have opened shard")
+            .write_byte_record(row)?;
+        self.shards.last_mut().expect("should have a shard").rows += 1;
+        self.shard_bytes += size;
+        Ok(())
+    }
+
+    /// Is our current shard open?
+    pub(crate) fn is_open(&self) -> bool {
+        self.wtr.is_some()
+    }
+
+    /// Flush and close our current shard, if it's open.
+    pub(crate) fn close(&mut self) -> Result<()> {
+        if let Some(mut wtr) = self.wtr.take() {
+            wtr.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Close our current shard and return every shard we wrote. If we
+    /// weren't given any headers or rows, we create a single empty shard.
+    pub(crate) fn finish(mut self) -> Result<Vec<ShardInfo>> {
+        if self.shards.is_empty() {
+            self.start_shard()?;
+        }
+        self.finish_shard()?;
+        Ok(self.shards)
+    }
+
+    /// Close our current shard for good, and record its checksum and size.
+    fn finish_shard(&mut self) -> Result<()> {
+        self.close()?;
+        let hasher = self.hasher.replace(Sha256::new());
+        let written = self.written.replace(0);
+        if let Some(shard) = self.shards.last_mut() {
+            // We count bytes ourselves, because if we're using `io_uring`,
+            // they may not have reached the file yet.
+            shard.bytes = written;
+            shard.sha256 = hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Wrap `file` in a CSV writer which updates our checksum, and which
+    /// writes after everything already in our current shard.
+    fn hashing_writer(&self, file: File) -> csv::Writer<HashingFile> {
+        let file: Box<dyn Write> = match self.ring {
+            Some(ref ring) => Box::new(RingFile::new(file, self.written.get(), ring)),
+            None => Box::new(file),
+        };
+        self.dialect
+            .writer_builder()
+            .from_writer(HashingFile {
+                             file,
+                             hasher: self.hasher.clone(),
+                             written: self.written.clone(),
+                         })
+    }
+
+    /// Close our current shard, and create a new one starting with our
+    /// headers.
+    fn start_shard(&mut self) -> Result<()> {
+        self.finish_shard()?;
+        let path = if self.limits.is_unlimited() {
+            self.path.clone()
+        } else {
+            shard_path(&self.path, self.shards.len())
+        };
+        let file = File::create(&path)
+            .chain_err(|| format!("could not create {}", path.display()))?;
+        let mut wtr = self.hashing_writer(file);
+        self.shard_bytes = 0;
+        if let Some(ref headers) = self.headers {
+            wtr.write_byte_record(headers)?;
+            if let Some(ref mut sizer) = self.sizer {
+                self.shard_bytes = sizer.size(headers)?;
+            }
+        }
+        self.wtr = Some(wtr);
+        self.shards
+            .push(ShardInfo {
+                      path,
+                      rows: 0,
+                      bytes: 0,
+                      sha256: String::new(),
+                  });
+        Ok(())
+    }
+}
+
+/// A file which updates a checksum with everything written to it.
+pub(crate) struct HashingFile {
+    /// The file we write to.
+    pub(crate) file: Box<dyn Write>,
+    /// Our checksum, shared with the writer which created us.
+    pub(crate) hasher: Rc<RefCell<Sha256>>,
+    /// The number of bytes written, shared with the writer which created us.
+    pub(crate) written: Rc<Cell<u64>>,
+}
+
+impl Write for HashingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.borrow_mut().update(&buf[..written]);
+        self.written.set(self.written.get() + written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Measures how many bytes a row will take up once it's written, by
+/// writing it to a second CSV writer which discards its output.
+struct RowSizer {
+    /// A writer which only counts its output.
+    wtr: csv::Writer<CountingSink>,
+    /// The number of bytes written to `wtr`, shared with its `CountingSink`.
+    bytes: Rc<Cell<u64>>,
+}
+
+impl RowSizer {
+    /// Create a new `RowSizer` for our output dialect.
+    fn new(dialect: &Dialect) -> RowSizer {
+        let bytes = Rc::new(Cell::new(0));
+        let sink = CountingSink { bytes: bytes.clone() };
+        RowSizer {
+            wtr: dialect.writer_builder().from_writer(sink),
+            bytes,
+        }
+    }
+
+    /// How many bytes will `row` take up?
+    fn size(&mut self, row: &csv::ByteRecord) -> Result<u64> {
+        let before = self.bytes.get();
+        self.wtr.write_byte_record(row)?;
+        self.wtr.flush()?;
+        Ok(self.bytes.get() - before)
+    }
+}
+
+/// A writer which discards its input, after counting it.
+struct CountingSink {
+    /// The number of bytes written so far.
+    bytes: Rc<Cell<u64>>,
+}
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.set(self.bytes.get() + buf.len() as u64);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn names_shards() {
+    let _ = env_logger::init();
+    assert_eq!(shard_path(Path::new("out/people.csv"), 3),
+               Path::new("out/people-00003.csv"));
+    assert_eq!(shard_path(Path::new("people"), 12), Path::new("people-00012"));
+}
+
+#[test]
+fn splits_output_into_shards() {
+    let _ = env_logger::init();
+    let dir = env::temp_dir().join(format!("geochunk-shard-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dialect = Dialect::default();
+    let write = |limits: ShardLimits| {
+        let mut wtr = ShardedWriter::new(&dir.join("out.csv"), limits, &dialect);
+        wtr.write_headers(&csv::ByteRecord::from(vec!["zip"])).unwrap();
+        for zip in &["90210", "90211", "07712", "07713", "07714"] {
+            wtr.write_row(&csv::ByteRecord::from(vec![*zip])).unwrap();
+            // Make sure we can reopen a closed shard.
+            wtr.close().unwrap();
+        }
+        wtr.finish()
+            .unwrap()
+            .into_iter()
+            .map(|s| (fs::read_to_string(&s.path).unwrap(), s.rows))
+            .collect::<Vec<_>>()
+    };
+
+    let unlimited = write(ShardLimits::default());
+    assert_eq!(unlimited,
+               vec![("zip\n90210\n90211\n07712\n07713\n07714\n".to_owned(), 5)]);
+    assert!(dir.join("out.csv").exists());
+
+    let by_rows = write(ShardLimits {
+                            max_rows: Some(2),
+                            ..ShardLimits::default()
+                        });
+    assert_eq!(by_rows,
+               vec![("zip\n90210\n90211\n".to_owned(), 2),
+                    ("zip\n07712\n07713\n".to_owned(), 2),
+                    ("zip\n07714\n".to_owned(), 1)]);
+
+    // Each row is 6 bytes, as is the header, so only 2 rows fit in 20 bytes.
+    let by_bytes = write(ShardLimits {
+                             max_bytes: Some(20),
+                             ..ShardLimits::default()
+                         });
+    assert_eq!(by_bytes, by_rows);
+    assert!(dir.join("out-00002.csv").exists());
+
+    let mut wtr = ShardedWriter::new(&dir.join("empty.csv"), ShardLimits::default(), &dialect);
+    wtr.write_headers(&csv::ByteRecord::from(vec!["zip"])).unwrap();
+    let shards = wtr.finish().unwrap();
+    assert_eq!(shards[0].bytes, 4);
+    // The SHA-256 checksum of "zip\n".
+    assert_eq!(shards[0].sha256,
+               "257a4c075a4c2721970ffc7ef0380d0bf7329185de8f34ff9a6ac8336561c34c");
+    fs::remove_dir_all(&dir).unwrap();
+}
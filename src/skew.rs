@@ -0,0 +1,93 @@
+//! Comparing the rows in each chunk to the population we expected.
+//!
+//! Chunks are balanced by census population, which works well when our data
+//! is spread out like the population. If it isn't, some chunks will have far
+//! more rows than others, and it may be better to build chunks weighted by
+//! the input data itself.
+
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use zip2010::Classifier;
+
+/// How many rows a chunk has, compared to its share of the population.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkSkew {
+    /// The chunk ID.
+    pub chunk: String,
+    /// The number of rows in this chunk.
+    pub rows: u64,
+    /// The population of this chunk.
+    pub population: u64,
+    /// The number of rows in this chunk, divided by the number we would
+    /// expect if rows were distributed like the population. This is 1.0 for
+    /// a perfectly balanced chunk.
+    pub ratio: f64,
+}
+
+/// Compute the skew of every chunk with a non-zero population, given
+/// row counts from `anonymize::count_rows_by_chunk`. The results are sorted
+/// from most over-represented to most under-represented.
+pub fn chunk_skew(classifier: &Classifier, counts: &BTreeMap<String, u64>) -> Vec<ChunkSkew> {
+    let populations = classifier
+        .chunk_ids()
+        .iter()
+        .map(|c| {
+                 (c, classifier
+                         .chunk_population(c)
+                         .expect("all chunks should have a population"))
+             })
+        .filter(|&(_, pop)| pop > 0)
+        .collect::<Vec<_>>();
+    let total_population = populations.iter().map(|&(_, pop)| pop).sum::<u64>();
+    let total_rows = populations
+        .iter()
+        .map(|&(c, _)| counts.get(c).cloned().unwrap_or(0))
+        .sum::<u64>();
+
+    let mut skews = populations
+        .into_iter()
+        .map(|(chunk, population)| {
+            let rows = counts.get(chunk).cloned().unwrap_or(0);
+            let expected = total_rows as f64 * population as f64 / total_population as f64;
+            ChunkSkew {
+                chunk: chunk.to_owned(),
+                rows,
+                population,
+                ratio: if expected == 0.0 { 0.0 } else { rows as f64 / expected },
+            }
+        })
+        .collect::<Vec<_>>();
+    skews.sort_by(|a, b| {
+                      b.ratio
+                          .partial_cmp(&a.ratio)
+                          .unwrap_or(Ordering::Equal)
+                          .then_with(|| a.chunk.cmp(&b.chunk))
+                  });
+    skews
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn finds_over_and_under_represented_chunks() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut counts = BTreeMap::new();
+    counts.insert("902_0".to_owned(), 1000);
+    counts.insert("077_0".to_owned(), 1);
+    let skews = chunk_skew(&classifier, &counts);
+    let populated = classifier
+        .chunk_ids()
+        .iter()
+        .filter(|c| classifier.chunk_population(c) != Some(0))
+        .count();
+    assert_eq!(skews.len(), populated);
+    assert_eq!(skews[0].chunk, "902_0");
+    assert_eq!(skews[0].rows, 1000);
+    assert!(skews[0].ratio > 1.0);
+    assert!(skews.iter().position(|s| s.chunk == "077_0").unwrap() == 1);
+    assert_eq!(skews.last().unwrap().ratio, 0.0);
+    assert_eq!(skews.iter().map(|s| s.rows).sum::<u64>(), 1001);
+}
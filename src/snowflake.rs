@@ -0,0 +1,334 @@
+//! Generating what's needed to call geochunk from Snowflake, so that chunks
+//! assigned inside the warehouse match the ones assigned by this binary.
+//! Printed by `geochunk export --snowflake=<kind>`.
+//!
+//! There are two ways to do this:
+//!
+//! - `sql`: a script which creates a lookup table containing the chunk for
+//!   every 5-digit zip code, and a SQL function which looks zip codes up in
+//!   it. This needs nothing outside Snowflake, but values which don't start
+//!   with five digits get the unknown chunk (or `NULL`), even where the
+//!   binary would have found a chunk for their first few digits.
+//! - `lambda`: a CloudFormation template which deploys `geochunk-lambda`
+//!   behind API Gateway, to be called as a Snowflake external function. This
+//!   runs the same classifier as the binary, so it handles every value the
+//!   same way, but every query calls out to AWS.
+
+#[cfg(test)]
+use env_logger;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use errors::*;
+use scheme;
+use sql::sql_string;
+use zip2010::{Classifier, Metadata};
+
+/// How many rows we insert per statement. Snowflake allows up to 16,384.
+const ROWS_PER_INSERT: u32 = 10_000;
+
+/// Which Snowflake artifacts to generate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deployment {
+    /// A SQL script creating a lookup table and a function.
+    Sql,
+    /// A CloudFormation template for an external function.
+    Lambda,
+}
+
+impl FromStr for Deployment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Deployment> {
+        match s {
+            "sql" => Ok(Deployment::Sql),
+            "lambda" => Ok(Deployment::Lambda),
+            _ => Err(format!("unknown Snowflake deployment {:?}, try sql or lambda", s)
+                         .into()),
+        }
+    }
+}
+
+/// The name of our Snowflake function, which is also the name of our
+/// geochunk column, made safe to use as an unquoted identifier.
+fn function_name(classifier: &Classifier) -> String {
+    classifier
+        .geochunk_column_name()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Describe where our chunks came from, as lines of a comment starting with
+/// `comment`.
+fn write_header(metadata: &Metadata,
+                comment: &str,
+                out: &mut dyn Write)
+                -> Result<()> {
+    writeln!(out,
+             "{} Generated by geochunk {} for {}.",
+             comment,
+             env!("CARGO_PKG_VERSION"),
+             metadata.column)?;
+    writeln!(out, "{} Chunk algorithm version: {}", comment, metadata.version)?;
+    if let Some(ref census_data) = metadata.census_data {
+        census_data.write_comments(comment, out)?;
+    }
+    Ok(())
+}
+
+/// Write a Snowflake script which creates a table mapping every 5-digit zip
+/// code to its chunk, and a function which looks up the chunk for a zip
+/// code, or ZIP+4 code.
+pub fn write_lookup_script(classifier: &Classifier,
+                           out: &mut dyn Write)
+                           -> Result<()> {
+    let function = function_name(classifier);
+    let table = format!("{}_lookup", function);
+    write_header(&classifier.metadata(), "--", out)?;
+    writeln!(out)?;
+    writeln!(out, "CREATE OR REPLACE TABLE {} (", table)?;
+    writeln!(out, "    zip VARCHAR(5) NOT NULL PRIMARY KEY,")?;
+    writeln!(out, "    geochunk VARCHAR NOT NULL")?;
+    writeln!(out, ");")?;
+    for zip_int in 0..100000 {
+        let (first, last) = (zip_int % ROWS_PER_INSERT == 0,
+                             zip_int % ROWS_PER_INSERT == ROWS_PER_INSERT - 1);
+        if first {
+            writeln!(out)?;
+            writeln!(out, "INSERT INTO {} (zip, geochunk) VALUES", table)?;
+        }
+        let zip = format!("{:05}", zip_int);
+        let chunk_id = classifier.chunk_for(&zip)
+            .expect("all zip codes should have a chunk");
+        let end = if last { ";" } else { "," };
+        writeln!(out, "    ('{}', {}){}", zip, sql_string(chunk_id), end)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "-- Only the first five characters of each zip code are used.")?;
+    writeln!(out, "CREATE OR REPLACE FUNCTION {}(zip VARCHAR)", function)?;
+    writeln!(out, "RETURNS VARCHAR")?;
+    writeln!(out, "AS $$")?;
+    let lookup = format!("SELECT l.geochunk FROM {} l WHERE l.zip = LEFT(zip, 5)",
+                         table);
+    match classifier.chunk_for("") {
+        Some(unknown) => {
+            writeln!(out, "    COALESCE(({}), {})", lookup, sql_string(unknown))?
+        }
+        None => writeln!(out, "    {}", lookup)?,
+    }
+    writeln!(out, "$$;")?;
+    Ok(())
+}
+
+/// Write a CloudFormation template which deploys `geochunk-lambda` as a
+/// Snowflake external function using `spec`. Since the function is only
+/// given `spec`, we check that it builds the same chunks as `classifier`.
+pub fn write_lambda_template(spec: &str,
+                             classifier: &Classifier,
+                             out: &mut dyn Write)
+                             -> Result<()> {
+    let deployed = scheme::builtin_classifier(spec)?;
+    let mismatch = (0..100000).map(|z| format!("{:05}", z)).find(|zip| {
+        deployed.chunk_for(zip) != classifier.chunk_for(zip)
+    });
+    if let Some(zip) = mismatch {
+        return Err(format!("geochunk-lambda can't build these chunks from {:?} (it \
+                            puts {} in a different chunk), so use --snowflake=sql \
+                            instead",
+                           spec,
+                           zip)
+                           .into());
+    }
+
+    let function = function_name(classifier);
+    write_header(&classifier.metadata(), "#", out)?;
+    write!(out,
+           "#
+# Deploy this with the geochunk-lambda binary, built for Amazon Linux with
+# `--features lambda`, renamed to `bootstrap`, and zipped into CodeBucket.
+# Then create the API integration in Snowflake, using this stack's outputs:
+#
+#     CREATE OR REPLACE API INTEGRATION geochunk_api
+#       API_PROVIDER = aws_api_gateway
+#       API_AWS_ROLE_ARN = '<SnowflakeRoleArn>'
+#       API_ALLOWED_PREFIXES = ('<Url>')
+#       ENABLED = TRUE;
+#
+# Update this stack, setting SnowflakeUserArn and SnowflakeExternalId to
+# the API_AWS_IAM_USER_ARN and API_AWS_EXTERNAL_ID shown by `DESCRIBE
+# INTEGRATION geochunk_api`, and create the function:
+#
+#     CREATE OR REPLACE EXTERNAL FUNCTION {function}(zip VARCHAR)
+#       RETURNS VARCHAR
+#       API_INTEGRATION = geochunk_api
+#       AS '<Url>';
+
+AWSTemplateFormatVersion: \"2010-09-09\"
+Description: geochunk {column} as a Snowflake external function
+
+Parameters:
+  CodeBucket:
+    Type: String
+    Description: The S3 bucket containing the zipped geochunk-lambda bootstrap.
+  CodeKey:
+    Type: String
+    Description: The key of the zipped geochunk-lambda bootstrap.
+  Architecture:
+    Type: String
+    Default: x86_64
+    AllowedValues: [x86_64, arm64]
+  SnowflakeUserArn:
+    Type: String
+    Default: \"\"
+    Description: API_AWS_IAM_USER_ARN, from DESCRIBE INTEGRATION.
+  SnowflakeExternalId:
+    Type: String
+    Default: \"\"
+    Description: API_AWS_EXTERNAL_ID, from DESCRIBE INTEGRATION.
+
+Conditions:
+  HasSnowflakeUser: !Not [!Equals [!Ref SnowflakeUserArn, \"\"]]
+  HasSnowflakeExternalId: !Not [!Equals [!Ref SnowflakeExternalId, \"\"]]
+
+Resources:
+  FunctionRole:
+    Type: AWS::IAM::Role
+    Properties:
+      AssumeRolePolicyDocument:
+        Version: \"2012-10-17\"
+        Statement:
+          - Effect: Allow
+            Principal: {{ Service: lambda.amazonaws.com }}
+            Action: sts:AssumeRole
+      ManagedPolicyArns:
+        - arn:aws:iam::aws:policy/service-role/AWSLambdaBasicExecutionRole
+
+  Function:
+    Type: AWS::Lambda::Function
+    Properties:
+      Runtime: provided.al2023
+      Handler: bootstrap
+      Architectures: [!Ref Architecture]
+      MemorySize: 512
+      Timeout: 30
+      Role: !GetAtt FunctionRole.Arn
+      Code:
+        S3Bucket: !Ref CodeBucket
+        S3Key: !Ref CodeKey
+      Environment:
+        Variables:
+          GEOCHUNK_HANDLER: snowflake
+          GEOCHUNK_SPEC: \"{spec}\"
+
+  SnowflakeRole:
+    Type: AWS::IAM::Role
+    Properties:
+      AssumeRolePolicyDocument:
+        Version: \"2012-10-17\"
+        Statement:
+          - Effect: Allow
+            Principal:
+              AWS: !If
+                - HasSnowflakeUser
+                - !Ref SnowflakeUserArn
+                - !Sub \"arn:aws:iam::${{AWS::AccountId}}:root\"
+            Action: sts:AssumeRole
+            Condition: !If
+              - HasSnowflakeExternalId
+              - StringEquals: {{ sts:ExternalId: !Ref SnowflakeExternalId }}
+              - !Ref AWS::NoValue
+
+  Api:
+    Type: AWS::ApiGateway::RestApi
+    Properties:
+      Name: {function}
+      EndpointConfiguration: {{ Types: [REGIONAL] }}
+      Policy:
+        Version: \"2012-10-17\"
+        Statement:
+          - Effect: Allow
+            Principal: {{ AWS: !GetAtt SnowflakeRole.Arn }}
+            Action: execute-api:Invoke
+            Resource: execute-api:/*
+
+  ApiResource:
+    Type: AWS::ApiGateway::Resource
+    Properties:
+      RestApiId: !Ref Api
+      ParentId: !GetAtt Api.RootResourceId
+      PathPart: geochunk
+
+  ApiMethod:
+    Type: AWS::ApiGateway::Method
+    Properties:
+      RestApiId: !Ref Api
+      ResourceId: !Ref ApiResource
+      HttpMethod: POST
+      AuthorizationType: AWS_IAM
+      Integration:
+        Type: AWS_PROXY
+        IntegrationHttpMethod: POST
+        Uri: !Sub \"arn:aws:apigateway:${{AWS::Region}}:lambda:path/2015-03-31/functions/${{Function.Arn}}/invocations\"
+
+  ApiDeployment:
+    Type: AWS::ApiGateway::Deployment
+    DependsOn: ApiMethod
+    Properties:
+      RestApiId: !Ref Api
+      StageName: prod
+
+  ApiPermission:
+    Type: AWS::Lambda::Permission
+    Properties:
+      FunctionName: !Ref Function
+      Action: lambda:InvokeFunction
+      Principal: apigateway.amazonaws.com
+      SourceArn: !Sub \"arn:aws:execute-api:${{AWS::Region}}:${{AWS::AccountId}}:${{Api}}/*/POST/geochunk\"
+
+Outputs:
+  SnowflakeRoleArn:
+    Value: !GetAtt SnowflakeRole.Arn
+  Url:
+    Value: !Sub \"https://${{Api}}.execute-api.${{AWS::Region}}.amazonaws.com/prod/geochunk\"
+",
+           function = function,
+           column = classifier.geochunk_column_name(),
+           spec = spec)?;
+    Ok(())
+}
+
+#[test]
+fn writes_lookup_scripts() {
+    let _ = env_logger::init();
+    let mut out = vec![];
+    write_lookup_script(&Classifier::new(250000), &mut out).unwrap();
+    let script = String::from_utf8(out).unwrap();
+    assert!(script.contains("OR REPLACE TABLE geochunk_zip2010_250000_lookup ("));
+    assert_eq!(script.matches("INSERT INTO").count(), 10);
+    assert!(script.contains("    ('00000', '0_0'),\n"));
+    assert!(script.contains("    ('90210', '902_0'),\n"));
+    assert!(script.contains("    ('99999', '99_3');\n"));
+    assert!(script.contains("WHERE l.zip = LEFT(zip, 5)\n$$;\n"));
+
+    let spec = "zip2010-250000[unknown_chunk=it's unknown]";
+    let mut out = vec![];
+    write_lookup_script(&scheme::builtin_classifier(spec).unwrap(), &mut out).unwrap();
+    let script = String::from_utf8(out).unwrap();
+    assert!(script.contains("LEFT(zip, 5)), 'it''s unknown')\n"));
+}
+
+#[test]
+fn writes_lambda_templates() {
+    let _ = env_logger::init();
+    let mut out = vec![];
+    let classifier = Classifier::new(250000);
+    write_lambda_template("zip2010-250000", &classifier, &mut out).unwrap();
+    let template = String::from_utf8(out).unwrap();
+    assert!(template.contains("GEOCHUNK_SPEC: \"zip2010-250000\"\n"));
+    assert!(template.contains("FUNCTION geochunk_zip2010_250000(zip VARCHAR)"));
+    assert!(write_lambda_template("zip2010-100000",
+                                  &Classifier::new(250000),
+                                  &mut vec![])
+                    .is_err());
+}
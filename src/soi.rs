@@ -0,0 +1,120 @@
+//! Support for weighting chunks using IRS Statistics of Income (SOI) zip code
+//! data, so that chunks can be balanced by economic activity instead of by
+//! headcount.
+//!
+//! The IRS publishes these files at
+//! https://www.irs.gov/statistics/soi-tax-stats-individual-income-tax-statistics-zip-code-data-soi
+//! and we read them at runtime instead of building them into our executable.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+use errors::*;
+use number::{self, Rounding};
+
+/// Which SOI measurement should we use to weight each zip code?
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoiMeasure {
+    /// The number of individual income tax returns filed.
+    Returns,
+    /// Adjusted gross income, in thousands of dollars.
+    Agi,
+}
+
+impl SoiMeasure {
+    /// The name we use for this measurement in our output column names.
+    pub fn weight_name(self) -> &'static str {
+        match self {
+            SoiMeasure::Returns => "soi_returns",
+            SoiMeasure::Agi => "soi_agi",
+        }
+    }
+
+    /// The name of the SOI column containing this measurement.
+    fn column_name(self) -> &'static str {
+        match self {
+            SoiMeasure::Returns => "N1",
+            SoiMeasure::Agi => "A00100",
+        }
+    }
+}
+
+/// Read an SOI zip code CSV file and return the total weight for each zip
+/// code. Files broken down by AGI bracket have several rows per zip code,
+/// which we add together. The SOI pseudo-zips `00000` (state totals) and
+/// `99999` (all other zip codes) are skipped, and negative totals are
+/// treated as zero. Fractional values are rounded using `rounding`.
+pub fn load_weights(input: &mut dyn Read,
+                    measure: SoiMeasure,
+                    rounding: Rounding)
+                    -> Result<Vec<(String, u64)>> {
+    let mut rdr = csv::Reader::from_reader(input);
+
+    // The IRS has changed the capitalization of its headers over the years,
+    // so we look them up case-insensitively.
+    let headers = rdr.headers()?.to_owned();
+    let find_column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::no_such_column(name))
+    };
+    let zip_col_idx = find_column("zipcode")?;
+    let weight_col_idx = find_column(measure.column_name())?;
+
+    let mut totals = HashMap::<String, i64>::new();
+    for row in rdr.records() {
+        let row = row?;
+        // Zip codes with leading zeros sometimes arrive as numbers.
+        let zip = format!("{:0>5}", row[zip_col_idx].trim());
+        if zip.len() != 5 || !zip.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Error::invalid_weight(&row[zip_col_idx], row.position()));
+        }
+        if zip == "00000" || zip == "99999" {
+            continue;
+        }
+        let value = &row[weight_col_idx];
+        let weight = number::parse_number(value)
+            .ok_or_else(|| Error::invalid_weight(value, row.position()))?;
+        *totals.entry(zip).or_insert(0) += rounding.round(weight) as i64;
+    }
+
+    let mut weights = totals
+        .into_iter()
+        .map(|(zip, total)| (zip, if total < 0 { 0 } else { total as u64 }))
+        .collect::<Vec<_>>();
+    weights.sort();
+    Ok(weights)
+}
+
+#[test]
+fn sums_weights_by_zip_and_skips_totals() {
+    let _ = env_logger::init();
+    let input = "\
+STATEFIPS,STATE,zipcode,agi_stub,N1,A00100
+01,AL,0,1,1000,5000
+01,AL,35004,1,100,2000
+01,AL,35004,2,50,-3000
+01,AL,35005,1,20,300
+02,AK,99999,1,70,900
+";
+    let load = |measure| {
+        load_weights(&mut input.as_bytes(), measure, Rounding::Nearest)
+    };
+    let returns = load(SoiMeasure::Returns).unwrap();
+    assert_eq!(returns,
+               vec![("35004".to_owned(), 150), ("35005".to_owned(), 20)]);
+    let agi = load(SoiMeasure::Agi).unwrap();
+    assert_eq!(agi, vec![("35004".to_owned(), 0), ("35005".to_owned(), 300)]);
+}
+
+#[test]
+fn rejects_non_numeric_weights() {
+    let _ = env_logger::init();
+    let input = "ZIPCODE,N1\n35004,lots\n";
+    let measure = SoiMeasure::Returns;
+    assert!(load_weights(&mut input.as_bytes(), measure, Rounding::Up).is_err());
+}
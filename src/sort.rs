@@ -0,0 +1,360 @@
+//! Sorting CSV rows by geochunk or zip code, spilling to temporary files
+//! when we have more rows than we want to hold in memory.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::mem;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use errors::*;
+
+/// How many bytes of rows we buffer before writing a sorted run to disk.
+pub(crate) const DEFAULT_MAX_BUFFER_BYTES: usize = 64 * 1024 * 1024;
+
+/// A rough estimate of the memory used by each row, in addition to its
+/// field data.
+const ROW_OVERHEAD_BYTES: usize = 64;
+
+/// The most runs we merge at once. Each run we're merging holds a file
+/// open, so merging thousands of runs at once could use up our file
+/// descriptors. If we have more runs than this, we merge them in groups
+/// first, making fewer, longer runs.
+const MAX_MERGE_RUNS: usize = 64;
+
+/// Used to give each of our temporary files a unique name.
+static NEXT_RUN_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A row waiting to be sorted.
+struct SortRow {
+    /// The key we sort by, compared byte by byte. Rows with equal keys keep
+    /// their input order.
+    key: Vec<u8>,
+    /// The position of this row in our input.
+    seq: u64,
+    /// The row itself.
+    row: csv::ByteRecord,
+}
+
+/// Sorts rows by key, keeping rows with the same key in their original
+/// order. Rows are buffered in memory until we reach `max_buffer_bytes`,
+/// and then written to a temporary file as a sorted run. When we're done,
+/// the runs are merged.
+pub(crate) struct ExternalSorter {
+    /// How many bytes of rows to buffer before spilling to disk.
+    max_buffer_bytes: usize,
+    /// Rows which haven't been written to a run yet.
+    buffer: Vec<SortRow>,
+    /// Roughly how much memory `buffer` uses.
+    buffer_bytes: usize,
+    /// The sorted runs we've written to disk.
+    runs: Vec<Run>,
+    /// The most runs to merge at once.
+    max_merge_runs: usize,
+    /// The position of the next row we're given.
+    next_seq: u64,
+}
+
+impl ExternalSorter {
+    /// Create a sorter which buffers up to `max_buffer_bytes` of rows in
+    /// memory.
+    pub(crate) fn new(max_buffer_bytes: usize) -> ExternalSorter {
+        ExternalSorter {
+            max_buffer_bytes,
+            buffer: vec![],
+            buffer_bytes: 0,
+            runs: vec![],
+            max_merge_runs: MAX_MERGE_RUNS,
+            next_seq: 0,
+        }
+    }
+
+    /// Add a row with the specified sort key. To sort by number, pass its
+    /// big-endian bytes.
+    pub(crate) fn push(&mut self, key: Vec<u8>, row: csv::ByteRecord) -> Result<()> {
+        self.buffer_bytes += key.len() + row.as_slice().len() + ROW_OVERHEAD_BYTES;
+        self.buffer
+            .push(SortRow {
+                      key,
+                      seq: self.next_seq,
+                      row,
+                  });
+        self.next_seq += 1;
+        if self.buffer_bytes >= self.max_buffer_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Pass all our rows to `write`, in order.
+    pub(crate) fn finish<F>(mut self, mut write: F) -> Result<()>
+        where F: FnMut(&csv::ByteRecord) -> Result<()>
+    {
+        if self.runs.is_empty() {
+            self.buffer.sort_unstable_by(|a, b| (&a.key, a.seq).cmp(&(&b.key, b.seq)));
+            for sort_row in &self.buffer {
+                write(&sort_row.row)?;
+            }
+            return Ok(());
+        }
+
+        self.spill()?;
+        let mut runs = mem::take(&mut self.runs);
+        while runs.len() > self.max_merge_runs {
+            let mut merged = vec![];
+            for group in runs.chunks(self.max_merge_runs) {
+                let mut writer = RunWriter::new()?;
+                merge_runs(group, |sort_row| writer.push(sort_row))?;
+                let run = writer.finish()?;
+                debug!("merged {} runs into {}", group.len(), run.path.display());
+                merged.push(run);
+            }
+            runs = merged;
+        }
+        merge_runs(&runs, |sort_row| write(&sort_row.row))
+    }
+
+    /// Sort our buffered rows and write them to a new run.
+    fn spill(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let mut buffer = mem::take(&mut self.buffer);
+        self.buffer_bytes = 0;
+        buffer.sort_unstable_by(|a, b| (&a.key, a.seq).cmp(&(&b.key, b.seq)));
+        let mut writer = RunWriter::new()?;
+        for sort_row in &buffer {
+            writer.push(sort_row)?;
+        }
+        let run = writer.finish()?;
+        debug!("wrote {} sorted rows to {}", run.rows, run.path.display());
+        self.runs.push(run);
+        Ok(())
+    }
+}
+
+/// A temporary file containing sorted rows, which is deleted when dropped.
+struct Run {
+    /// The path of our temporary file.
+    path: PathBuf,
+    /// The number of rows in this run.
+    rows: u64,
+}
+
+impl Run {
+    /// Open this run for reading.
+    fn open(&self) -> Result<RunReader> {
+        let file = File::open(&self.path)
+            .chain_err(|| format!("could not open {}", self.path.display()))?;
+        Ok(RunReader {
+               input: BufReader::new(file),
+               remaining: self.rows,
+           })
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("could not remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Writes sorted rows to a new `Run`.
+struct RunWriter {
+    /// The run we're writing. We create this first, so that its file is
+    /// removed if we fail.
+    run: Run,
+    /// Our temporary file.
+    out: BufWriter<File>,
+}
+
+impl RunWriter {
+    /// Create a new temporary file.
+    fn new() -> Result<RunWriter> {
+        let path = env::temp_dir().join(format!("geochunk-sort-{}-{}.tmp",
+                                                process::id(),
+                                                NEXT_RUN_ID.fetch_add(1, Ordering::SeqCst)));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .chain_err(|| format!("could not create {}", path.display()))?;
+        Ok(RunWriter {
+               run: Run { path, rows: 0 },
+               out: BufWriter::new(file),
+           })
+    }
+
+    /// Write the next row, which must not sort before any we've written.
+    fn push(&mut self, sort_row: &SortRow) -> Result<()> {
+        write_row(&mut self.out, sort_row)
+            .chain_err(|| format!("could not write {}", self.run.path.display()))?;
+        self.run.rows += 1;
+        Ok(())
+    }
+
+    /// Finish writing our run.
+    fn finish(mut self) -> Result<Run> {
+        self.out
+            .flush()
+            .chain_err(|| format!("could not write {}", self.run.path.display()))?;
+        Ok(self.run)
+    }
+}
+
+/// Merge the rows of `runs`, passing them to `write` in order.
+fn merge_runs<F>(runs: &[Run], mut write: F) -> Result<()>
+    where F: FnMut(&SortRow) -> Result<()>
+{
+    let mut readers = runs.iter()
+        .map(|run| run.open())
+        .collect::<Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::new();
+    let mut current = Vec::with_capacity(readers.len());
+    for (i, reader) in readers.iter_mut().enumerate() {
+        let sort_row = reader.next_row()?;
+        if let Some(ref r) = sort_row {
+            heap.push(Reverse((r.key.clone(), r.seq, i)));
+        }
+        current.push(sort_row);
+    }
+    while let Some(Reverse((_, _, i))) = heap.pop() {
+        write(current[i].as_ref().expect("heap entry should have a row"))?;
+        current[i] = readers[i].next_row()?;
+        if let Some(ref r) = current[i] {
+            heap.push(Reverse((r.key.clone(), r.seq, i)));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the rows of a `Run` back in order.
+struct RunReader {
+    /// Our temporary file.
+    input: BufReader<File>,
+    /// The number of rows we haven't read yet.
+    remaining: u64,
+}
+
+impl RunReader {
+    /// Read the next row, or return `None` if there are no more.
+    fn next_row(&mut self) -> Result<Option<SortRow>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        Ok(Some(read_row(&mut self.input)?))
+    }
+}
+
+/// Write a row to a run. Each row is written as the length and data of its
+/// key, its sequence number and its field count, followed by the length and
+/// data of each field, with all numbers in little-endian order.
+fn write_row(out: &mut dyn Write, sort_row: &SortRow) -> io::Result<()> {
+    out.write_all(&(sort_row.key.len() as u64).to_le_bytes())?;
+    out.write_all(&sort_row.key)?;
+    out.write_all(&sort_row.seq.to_le_bytes())?;
+    out.write_all(&(sort_row.row.len() as u64).to_le_bytes())?;
+    for field in &sort_row.row {
+        out.write_all(&(field.len() as u64).to_le_bytes())?;
+        out.write_all(field)?;
+    }
+    Ok(())
+}
+
+/// Read a row written by `write_row`.
+fn read_row(input: &mut dyn Read) -> io::Result<SortRow> {
+    let mut key = vec![0; read_u64(input)? as usize];
+    input.read_exact(&mut key)?;
+    let seq = read_u64(input)?;
+    let field_count = read_u64(input)?;
+    let mut row = csv::ByteRecord::new();
+    let mut field = vec![];
+    for _ in 0..field_count {
+        field.resize(read_u64(input)? as usize, 0);
+        input.read_exact(&mut field)?;
+        row.push_field(&field);
+    }
+    Ok(SortRow { key, seq, row })
+}
+
+/// Read a little-endian `u64`.
+fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+fn sort_rows(max_buffer_bytes: usize, rows: &[(u64, &str)]) -> (String, usize) {
+    sort_rows_merging(max_buffer_bytes, MAX_MERGE_RUNS, rows)
+}
+
+#[cfg(test)]
+fn sort_rows_merging(max_buffer_bytes: usize,
+                     max_merge_runs: usize,
+                     rows: &[(u64, &str)])
+                     -> (String, usize) {
+    let mut sorter = ExternalSorter::new(max_buffer_bytes);
+    sorter.max_merge_runs = max_merge_runs;
+    for &(key, value) in rows {
+        sorter
+            .push(key.to_be_bytes().to_vec(),
+                  csv::ByteRecord::from(vec![key.to_string(), value.to_owned()]))
+            .unwrap();
+    }
+    let runs = sorter.runs.len();
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    sorter
+        .finish(|row| Ok(wtr.write_byte_record(row)?))
+        .unwrap();
+    (String::from_utf8(wtr.into_inner().unwrap()).unwrap(), runs)
+}
+
+#[test]
+fn sorts_rows_in_memory_and_on_disk() {
+    let _ = env_logger::init();
+    let rows = [(2, "a"), (1, "b"), (2, "c"), (0, "d,e"), (1, ""), (2, "f")];
+    let expected = "0,\"d,e\"\n1,b\n1,\n2,a\n2,c\n2,f\n";
+    let (in_memory, runs) = sort_rows(DEFAULT_MAX_BUFFER_BYTES, &rows);
+    assert_eq!(runs, 0);
+    assert_eq!(in_memory, expected);
+    let (on_disk, runs) = sort_rows(1, &rows);
+    assert_eq!(runs, rows.len());
+    assert_eq!(on_disk, expected);
+    // With only two runs at a time, we need several passes to merge six
+    // runs, but rows with equal keys still keep their order.
+    let (merged, _) = sort_rows_merging(1, 2, &rows);
+    assert_eq!(merged, expected);
+}
+
+#[test]
+fn sorts_rows_by_bytes() {
+    let _ = env_logger::init();
+    let mut sorter = ExternalSorter::new(DEFAULT_MAX_BUFFER_BYTES);
+    for zip in &["90210-1234", "9021", "90210", "07712"] {
+        sorter
+            .push(zip.as_bytes().to_vec(), csv::ByteRecord::from(vec![*zip]))
+            .unwrap();
+    }
+    let mut zips = vec![];
+    sorter
+        .finish(|row| {
+                    zips.push(String::from_utf8(row[0].to_vec()).unwrap());
+                    Ok(())
+                })
+        .unwrap();
+    assert_eq!(zips, vec!["07712", "9021", "90210", "90210-1234"]);
+}
@@ -0,0 +1,19 @@
+//! Writing values into the SQL we generate for data warehouses.
+
+#[cfg(test)]
+use env_logger;
+
+/// Quote `s` as a SQL string literal. Besides doubling single quotes, we
+/// escape backslashes, which Snowflake and several other warehouses treat
+/// as the start of an escape sequence inside string literals.
+pub fn sql_string(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+#[test]
+fn quotes_sql_strings() {
+    let _ = env_logger::init();
+    assert_eq!(sql_string("902_0"), "'902_0'");
+    assert_eq!(sql_string("O'Hare"), "'O''Hare'");
+    assert_eq!(sql_string(r"a\'b"), r"'a\\''b'");
+}
@@ -0,0 +1,181 @@
+//! Looking up the state of a zip code from its first three digits, using
+//! the USPS assignment of 3-digit prefixes to states. A handful of zip codes
+//! are in a different state from the rest of their prefix, so this is only
+//! meant for labelling chunks, not for addressing mail.
+
+#[cfg(test)]
+use env_logger;
+use std::collections::HashMap;
+
+use zip2010::{PrefixClasses, PrefixPopulation};
+
+/// Ranges of 3-digit zip code prefixes, and the postal abbreviation of the
+/// state, territory or military "state" they belong to, in order.
+const PREFIX_STATES: &[(u16, u16, &str)] = &[(5, 5, "NY"),
+                                             (6, 7, "PR"),
+                                             (8, 8, "VI"),
+                                             (9, 9, "PR"),
+                                             (10, 27, "MA"),
+                                             (28, 29, "RI"),
+                                             (30, 38, "NH"),
+                                             (39, 49, "ME"),
+                                             (50, 54, "VT"),
+                                             (55, 55, "MA"),
+                                             (56, 59, "VT"),
+                                             (60, 69, "CT"),
+                                             (70, 89, "NJ"),
+                                             (90, 99, "AE"),
+                                             (100, 149, "NY"),
+                                             (150, 196, "PA"),
+                                             (197, 199, "DE"),
+                                             (200, 200, "DC"),
+                                             (201, 201, "VA"),
+                                             (202, 205, "DC"),
+                                             (206, 219, "MD"),
+                                             (220, 246, "VA"),
+                                             (247, 268, "WV"),
+                                             (270, 289, "NC"),
+                                             (290, 299, "SC"),
+                                             (300, 319, "GA"),
+                                             (320, 339, "FL"),
+                                             (340, 340, "AA"),
+                                             (341, 349, "FL"),
+                                             (350, 369, "AL"),
+                                             (370, 385, "TN"),
+                                             (386, 397, "MS"),
+                                             (398, 399, "GA"),
+                                             (400, 427, "KY"),
+                                             (430, 459, "OH"),
+                                             (460, 479, "IN"),
+                                             (480, 499, "MI"),
+                                             (500, 528, "IA"),
+                                             (530, 549, "WI"),
+                                             (550, 567, "MN"),
+                                             (569, 569, "DC"),
+                                             (570, 577, "SD"),
+                                             (580, 588, "ND"),
+                                             (590, 599, "MT"),
+                                             (600, 629, "IL"),
+                                             (630, 658, "MO"),
+                                             (660, 679, "KS"),
+                                             (680, 693, "NE"),
+                                             (700, 714, "LA"),
+                                             (716, 729, "AR"),
+                                             (730, 732, "OK"),
+                                             (733, 733, "TX"),
+                                             (734, 749, "OK"),
+                                             (750, 799, "TX"),
+                                             (800, 816, "CO"),
+                                             (820, 831, "WY"),
+                                             (832, 838, "ID"),
+                                             (840, 847, "UT"),
+                                             (850, 865, "AZ"),
+                                             (870, 884, "NM"),
+                                             (885, 885, "TX"),
+                                             (889, 898, "NV"),
+                                             (900, 961, "CA"),
+                                             (962, 966, "AP"),
+                                             (967, 968, "HI"),
+                                             (969, 969, "GU"),
+                                             (970, 979, "OR"),
+                                             (980, 994, "WA"),
+                                             (995, 999, "AK")];
+
+/// The postal abbreviation of the state containing the zip code or prefix
+/// `zip`, which needs at least three digits. Returns `None` for prefixes
+/// which aren't assigned to any state.
+pub fn state_for_zip(zip: &str) -> Option<&'static str> {
+    let prefix = zip.get(..3)?;
+    if !prefix.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let prefix = prefix.parse::<u16>().ok()?;
+    PREFIX_STATES
+        .iter()
+        .find(|&&(first, last, _)| first <= prefix && prefix <= last)
+        .map(|&(_, _, state)| state)
+}
+
+/// Divides zip code prefixes into classes by state, so that we can build
+/// chunks which never cross a state line.
+#[derive(Clone, Debug)]
+pub struct StateClasses {
+    /// The class of each 1- and 2-digit prefix, taken from the first
+    /// populated 3-digit prefix inside it. Longer prefixes don't need this,
+    /// since they start with a 3-digit prefix of their own.
+    short_prefixes: HashMap<String, usize>,
+}
+
+impl StateClasses {
+    /// Create state classes for the zip codes in `population`.
+    pub fn new(population: &PrefixPopulation) -> StateClasses {
+        let mut short_prefixes = HashMap::new();
+        for zip3 in 0..1000 {
+            let zip3 = format!("{:03}", zip3);
+            if population.lookup(&zip3) == 0 {
+                continue;
+            }
+            if let Some(class) = state_class(&zip3) {
+                for len in 1..3 {
+                    short_prefixes.entry(zip3[..len].to_owned()).or_insert(class);
+                }
+            }
+        }
+        StateClasses { short_prefixes }
+    }
+}
+
+impl PrefixClasses for StateClasses {
+    fn name(&self) -> Option<String> {
+        Some("states".to_owned())
+    }
+
+    fn class_for_prefix(&self, prefix: &str) -> Option<usize> {
+        if prefix.len() < 3 {
+            self.short_prefixes.get(prefix).cloned()
+        } else {
+            state_class(prefix)
+        }
+    }
+}
+
+/// A number identifying the state of `zip`, built from the letters of its
+/// postal abbreviation.
+fn state_class(zip: &str) -> Option<usize> {
+    state_for_zip(zip).map(|state| {
+        state
+            .bytes()
+            .fold(0, |class, b| class << 8 | usize::from(b))
+    })
+}
+
+#[test]
+fn looks_up_states() {
+    let _ = env_logger::init();
+    assert_eq!(state_for_zip("90210"), Some("CA"));
+    assert_eq!(state_for_zip("021"), Some("MA"));
+    assert_eq!(state_for_zip("07712"), Some("NJ"));
+    assert_eq!(state_for_zip("20500"), Some("DC"));
+    assert_eq!(state_for_zip("99501"), Some("AK"));
+    assert_eq!(state_for_zip("00100"), None);
+    assert_eq!(state_for_zip("02"), None);
+    assert_eq!(state_for_zip("N/A"), None);
+    for window in PREFIX_STATES.windows(2) {
+        assert!(window[0].1 < window[1].0);
+    }
+}
+
+#[test]
+fn classifies_prefixes_by_state() {
+    let _ = env_logger::init();
+    let population = PrefixPopulation::from_weights(vec![("02138".to_owned(), 10),
+                                                         ("03101".to_owned(), 10)]);
+    let classes = StateClasses::new(&population);
+    assert_eq!(classes.name(), Some("states".to_owned()));
+    assert_eq!(classes.class_for_prefix("021"), state_class("02138"));
+    assert_ne!(classes.class_for_prefix("021"), classes.class_for_prefix("031"));
+    assert_eq!(classes.class_for_prefix("0"), classes.class_for_prefix("021"));
+    assert_eq!(classes.class_for_prefix("03"), classes.class_for_prefix("031"));
+    assert_eq!(classes.class_for_prefix("9"), None);
+    assert_eq!(classes.class_for_prefix("001"), None);
+}
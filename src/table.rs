@@ -0,0 +1,311 @@
+//! Adding a geochunk column to Parquet, Avro and Arrow IPC files without
+//! converting them to CSV, for `geochunk table`. This requires the `tables`
+//! feature.
+//!
+//! We read each file as Arrow record batches, so every column keeps its
+//! original type, including integers, timestamps and decimals, and write
+//! the batches back out in the same format with a string column of chunk
+//! IDs appended. In Parquet files, the chunk column is dictionary-encoded,
+//! so it takes very little space. Avro files keep their compression codec.
+
+use arrow_array::{RecordBatch, RecordBatchReader};
+use arrow_avro::reader::ReaderBuilder;
+use arrow_avro::schema::SCHEMA_METADATA_KEY;
+use arrow_avro::writer::format::AvroOcfFormat;
+use arrow_avro::writer::WriterBuilder;
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{Schema, SchemaRef};
+#[cfg(test)]
+use env_logger;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use parquet::schema::types::ColumnPath;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+#[cfg(test)]
+use std::sync::Arc;
+
+use errors::*;
+use parquet_files::ParquetCompression;
+use record_batch::add_chunk_column;
+use zip2010::Classifier;
+
+/// The file formats we can add a geochunk column to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableFormat {
+    /// A Parquet file.
+    Parquet,
+    /// An Avro object container file.
+    Avro,
+    /// An Arrow IPC file, also known as a Feather file.
+    ArrowIpc,
+}
+
+impl TableFormat {
+    /// Guess the format of `path` from its extension.
+    pub fn from_path(path: &Path) -> Result<TableFormat> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("parquet") => Ok(TableFormat::Parquet),
+            Some("avro") => Ok(TableFormat::Avro),
+            Some("arrow") | Some("feather") | Some("ipc") => Ok(TableFormat::ArrowIpc),
+            _ => {
+                Err(format!("don't know the format of {}; expected a .parquet, .avro, \
+                             .arrow or .feather file",
+                            path.display())
+                            .into())
+            }
+        }
+    }
+}
+
+/// Options controlling how we write tables.
+#[derive(Clone, Debug, Default)]
+pub struct TableOptions {
+    /// The name of the geochunk column we add.
+    pub chunk_column: String,
+    /// How to compress Parquet output.
+    pub parquet_compression: ParquetCompression,
+}
+
+/// Convert an error from one of the Arrow libraries into one of ours.
+fn arrow_error<E: fmt::Display>(err: E) -> Error {
+    err.to_string().into()
+}
+
+/// Read the table at `input`, and write it to `output` in the same format,
+/// adding a column with the geochunk of each zip code in `input_column`.
+/// Returns the number of rows written.
+pub fn chunk_table(classifier: &Classifier,
+                   input_column: &str,
+                   input: &Path,
+                   output: &Path,
+                   options: &TableOptions)
+                   -> Result<u64> {
+    trace_span!("chunk_table", input = %input.display());
+    let format = TableFormat::from_path(input)?;
+    if TableFormat::from_path(output)? != format {
+        return Err(format!("{} should have the same format as {}",
+                           output.display(),
+                           input.display())
+                           .into());
+    }
+    // Creating our output would truncate our input before we read it.
+    if let (Ok(input), Ok(output)) = (fs::canonicalize(input), fs::canonicalize(output)) {
+        if input == output {
+            return Err(format!("cannot overwrite {} while reading it", input.display())
+                           .into());
+        }
+    }
+
+    let file = File::open(input)
+        .chain_err(|| format!("could not open {}", input.display()))?;
+    let chunker = Chunker {
+        classifier,
+        input_column,
+        chunk_column: &options.chunk_column,
+    };
+    match format {
+        TableFormat::Parquet => {
+            let batches = ParquetRecordBatchReaderBuilder::try_new(file)
+                .and_then(|builder| builder.build())
+                .map_err(arrow_error)?;
+            let schema = chunker.schema(batches.schema())?;
+            let props = WriterProperties::builder()
+                .set_compression(options.parquet_compression.codec())
+                .set_column_dictionary_enabled(ColumnPath::from(&options.chunk_column[..]),
+                                               true)
+                .build();
+            let mut wtr = ArrowWriter::try_new(create(output)?, schema, Some(props))
+                .map_err(arrow_error)?;
+            let rows = chunker.copy(batches, |batch| wtr.write(batch).map_err(arrow_error))?;
+            wtr.close().map_err(arrow_error)?;
+            Ok(rows)
+        }
+        TableFormat::Avro => {
+            let batches = ReaderBuilder::new()
+                .build(BufReader::new(file))
+                .map_err(arrow_error)?;
+            let compression = batches
+                .avro_header()
+                .compression()
+                .map_err(arrow_error)?;
+            // The Avro schema from our input doesn't mention our chunk
+            // column, so we let the writer build a new one from our fields.
+            let schema = chunker.schema(batches.schema())?;
+            let mut metadata = schema.metadata().clone();
+            metadata.remove(SCHEMA_METADATA_KEY);
+            let schema = Schema::new_with_metadata(schema.fields().clone(), metadata);
+            let mut wtr = WriterBuilder::new(schema)
+                .with_compression(compression)
+                .build::<_, AvroOcfFormat>(create(output)?)
+                .map_err(arrow_error)?;
+            let rows = chunker.copy(batches, |batch| wtr.write(batch).map_err(arrow_error))?;
+            wtr.finish().map_err(arrow_error)?;
+            Ok(rows)
+        }
+        TableFormat::ArrowIpc => {
+            let batches = FileReader::try_new_buffered(file, None).map_err(arrow_error)?;
+            let schema = chunker.schema(batches.schema())?;
+            let mut wtr = FileWriter::try_new(create(output)?, &schema)
+                .map_err(arrow_error)?;
+            let rows = chunker.copy(batches, |batch| wtr.write(batch).map_err(arrow_error))?;
+            wtr.finish().map_err(arrow_error)?;
+            Ok(rows)
+        }
+    }
+}
+
+/// Create our output file.
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    let file = File::create(path)
+        .chain_err(|| format!("could not create {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Adds chunk columns to the record batches of a table.
+struct Chunker<'a> {
+    classifier: &'a Classifier,
+    input_column: &'a str,
+    chunk_column: &'a str,
+}
+
+impl<'a> Chunker<'a> {
+    /// The schema of our output, given the schema of our input. This also
+    /// checks that `input_column` exists and contains zip codes, before we
+    /// create our output.
+    fn schema(&self, input: SchemaRef) -> Result<SchemaRef> {
+        let empty = RecordBatch::new_empty(input);
+        let chunked = add_chunk_column(self.classifier,
+                                       self.input_column,
+                                       self.chunk_column,
+                                       &empty)?;
+        Ok(chunked.schema())
+    }
+
+    /// Add a chunk column to each of `batches`, and pass it to `write`.
+    /// Returns the number of rows written.
+    fn copy<I, E, F>(&self, batches: I, mut write: F) -> Result<u64>
+        where I: Iterator<Item = ::std::result::Result<RecordBatch, E>>,
+              E: fmt::Display,
+              F: FnMut(&RecordBatch) -> Result<()>
+    {
+        let mut rows = 0;
+        for batch in batches {
+            let batch = batch.map_err(arrow_error)?;
+            let chunked = add_chunk_column(self.classifier,
+                                           self.input_column,
+                                           self.chunk_column,
+                                           &batch)?;
+            write(&chunked)?;
+            rows += batch.num_rows() as u64;
+        }
+        Ok(rows)
+    }
+}
+
+#[test]
+fn preserves_column_types() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::{ArrayRef, Decimal128Array, Int32Array, StringArray,
+                      TimestampMicrosecondArray};
+    use arrow_avro::writer::AvroWriter;
+    use arrow_schema::{DataType, Field, TimeUnit};
+    use std::env;
+    use std::process;
+
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let schema = Schema::new(vec![Field::new("id", DataType::Int32, false),
+                                  Field::new("zip", DataType::Utf8, true),
+                                  Field::new("seen",
+                                             DataType::Timestamp(TimeUnit::Microsecond,
+                                                                 Some("+00:00".into())),
+                                             false),
+                                  Field::new("spend", DataType::Decimal128(10, 2), false)]);
+    let schema = Arc::new(schema);
+    let columns = vec![Arc::new(Int32Array::from(vec![1, 2])) as ArrayRef,
+                       Arc::new(StringArray::from(vec![Some("90210"), None])),
+                       Arc::new(TimestampMicrosecondArray::from(vec![0, 1000000])
+                                    .with_timezone("+00:00")),
+                       Arc::new(Decimal128Array::from(vec![1234, 5])
+                                    .with_precision_and_scale(10, 2)
+                                    .unwrap())];
+    let batch = RecordBatch::try_new(schema.clone(), columns).unwrap();
+
+    let dir = env::temp_dir().join(format!("geochunk-table-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let options = TableOptions {
+        chunk_column: "geochunk".to_owned(),
+        ..TableOptions::default()
+    };
+    for &ext in &["parquet", "avro", "arrow"] {
+        let input = dir.join(format!("people.{}", ext));
+        let output = dir.join(format!("chunked.{}", ext));
+        let file = File::create(&input).unwrap();
+        match ext {
+            "parquet" => {
+                let mut wtr = ArrowWriter::try_new(file, schema.clone(), None).unwrap();
+                wtr.write(&batch).unwrap();
+                wtr.close().unwrap();
+            }
+            "avro" => {
+                let mut wtr = AvroWriter::new(file, (*schema).clone()).unwrap();
+                wtr.write(&batch).unwrap();
+                wtr.finish().unwrap();
+            }
+            _ => {
+                let mut wtr = FileWriter::try_new(file, &schema).unwrap();
+                wtr.write(&batch).unwrap();
+                wtr.finish().unwrap();
+            }
+        }
+        assert_eq!(chunk_table(&classifier, "zip", &input, &output, &options).unwrap(),
+                   2);
+
+        let file = File::open(&output).unwrap();
+        let batches = match ext {
+            "parquet" => {
+                ParquetRecordBatchReaderBuilder::try_new(file)
+                    .unwrap()
+                    .build()
+                    .unwrap()
+                    .collect::<::std::result::Result<Vec<_>, _>>()
+                    .unwrap()
+            }
+            "avro" => {
+                ReaderBuilder::new()
+                    .build(BufReader::new(file))
+                    .unwrap()
+                    .collect::<::std::result::Result<Vec<_>, _>>()
+                    .unwrap()
+            }
+            _ => {
+                FileReader::try_new(file, None)
+                    .unwrap()
+                    .collect::<::std::result::Result<Vec<_>, _>>()
+                    .unwrap()
+            }
+        };
+        let chunked = &batches[0];
+        for (i, field) in schema.fields().iter().enumerate() {
+            assert_eq!(chunked.schema().field(i).data_type(),
+                       field.data_type(),
+                       "{} in {}",
+                       field.name(),
+                       ext);
+        }
+        assert_eq!(chunked.schema().field(4).name(), "geochunk");
+        let chunks = chunked.column(4).as_string::<i32>();
+        assert_eq!(chunks.iter().collect::<Vec<_>>(), vec![Some("902_0"), None]);
+    }
+
+    let input = dir.join("people.parquet");
+    assert!(chunk_table(&classifier, "zip", &input, &input, &options).is_err());
+    assert!(chunk_table(&classifier, "zip", &input, &dir.join("out.avro"), &options)
+                .is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,136 @@
+//! Exporting traces of what geochunk spends its time on, using OpenTelemetry.
+//! This requires the `otel` feature.
+//!
+//! We trace classifier construction, each file we process and each request
+//! to our servers using `tracing` spans, and send them to an OTLP collector
+//! over HTTP once `init` has been called. The exporter is configured using
+//! the standard `OTEL_*` environment variables, and does nothing unless
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` is
+//! set. When geochunk is one stage of a larger job, pass the job's trace
+//! context in `TRACEPARENT` (and optionally `TRACESTATE`), and our spans
+//! will appear as part of that trace.
+
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::Context;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+#[cfg(test)]
+use env_logger;
+#[cfg(test)]
+use opentelemetry::trace::TraceContextExt;
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+use tracing::{self, Span};
+use tracing_opentelemetry::{self, OpenTelemetrySpanExt};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use errors::*;
+
+/// The variables which tell us where to export traces.
+const ENDPOINT_VARS: &[&str] = &["OTEL_EXPORTER_OTLP_ENDPOINT",
+                                 "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"];
+
+/// The provider we export spans with, once `init` has been called.
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Exports traces until it's dropped, at which point any spans which haven't
+/// been sent yet are flushed.
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(err) = self.provider.shutdown() {
+            warn!("could not export traces: {}", err);
+        }
+    }
+}
+
+/// Start exporting traces, if our environment says where to send them.
+/// Returns `None` if it doesn't, or if `OTEL_SDK_DISABLED` is `true`.
+pub fn init() -> Result<Option<Telemetry>> {
+    let configured = ENDPOINT_VARS.iter().any(|v| env::var_os(v).is_some());
+    let disabled = env::var("OTEL_SDK_DISABLED")
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    if !configured || disabled {
+        return Ok(None);
+    }
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| format!("could not create OTLP exporter: {}", e))?;
+    let mut resource = Resource::builder();
+    if env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name("geochunk");
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build();
+    let tracer = provider.tracer("geochunk");
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .map_err(|e| format!("could not install tracing subscriber: {}", e))?;
+    PROVIDER
+        .set(provider.clone())
+        .map_err(|_| "telemetry was already initialized")?;
+    Ok(Some(Telemetry { provider }))
+}
+
+/// Export any spans which haven't been sent yet. This is useful in
+/// environments like AWS Lambda, which freeze us between requests.
+pub fn flush() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(err) = provider.force_flush() {
+            warn!("could not export traces: {}", err);
+        }
+    }
+}
+
+/// Create the root span for running `command`, which belongs to the trace
+/// given by `TRACEPARENT`, if it's set.
+pub fn command_span(command: &str) -> Span {
+    let span = tracing::info_span!("geochunk", command);
+    if let Ok(traceparent) = env::var("TRACEPARENT") {
+        let tracestate = env::var("TRACESTATE").ok();
+        let parent = parent_context(&traceparent, tracestate.as_ref().map(|s| &s[..]));
+        if let Err(err) = span.set_parent(parent) {
+            warn!("could not use TRACEPARENT: {}", err);
+        }
+    }
+    span
+}
+
+/// Parse a W3C trace context, as found in `TRACEPARENT` and `TRACESTATE`.
+fn parent_context(traceparent: &str, tracestate: Option<&str>) -> Context {
+    let mut carrier = HashMap::new();
+    carrier.insert("traceparent".to_owned(), traceparent.to_owned());
+    if let Some(tracestate) = tracestate {
+        carrier.insert("tracestate".to_owned(), tracestate.to_owned());
+    }
+    TraceContextPropagator::new().extract(&carrier)
+}
+
+#[test]
+fn parses_trace_context() {
+    let _ = env_logger::init();
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let cx = parent_context(traceparent, Some("vendor=value"));
+    let span = cx.span();
+    let parent = span.span_context();
+    assert!(parent.is_remote());
+    assert_eq!(parent.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(parent.span_id().to_string(), "00f067aa0ba902b7");
+    assert_eq!(parent.trace_state().header(), "vendor=value");
+    assert!(!parent_context("garbage", None).span().span_context().is_valid());
+}
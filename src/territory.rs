@@ -0,0 +1,271 @@
+//! Named groups of geochunks, such as sales territories.
+//!
+//! Territories are usually loaded from a `territories.toml` file mapping
+//! each name to a list of chunk IDs:
+//!
+//! ```toml
+//! Northeast-A = ["010_0", "011_0", "012_0"]
+//! Northeast-B = ["013_0"]
+//! ```
+
+#[cfg(all(test, feature = "data-zip2010"))]
+use env_logger;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+use chunk_set::ChunkSet;
+use errors::*;
+use zip2010::Classifier;
+
+/// A set of named territories, each containing one or more chunks. No chunk
+/// may belong to more than one territory, but chunks don't need to belong
+/// to any.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Territories {
+    /// The chunks in each territory.
+    territories: BTreeMap<String, ChunkSet>,
+    /// The territory of each chunk.
+    territory_for_chunk: HashMap<String, String>,
+}
+
+impl Territories {
+    /// Build territories from a map of names to chunk IDs, checking that
+    /// every chunk exists in `classifier`, and that no chunk is in more than
+    /// one territory.
+    pub fn new(classifier: &Classifier,
+               territories: BTreeMap<String, Vec<String>>)
+               -> Result<Territories> {
+        let mut result = Territories::default();
+        for (name, chunks) in territories {
+            for chunk in &chunks {
+                if classifier.chunk_index(chunk).is_none() {
+                    return Err(format!("territory {:?} contains unknown chunk {:?}",
+                                       name,
+                                       chunk)
+                                       .into());
+                }
+                if let Some(other) = result
+                       .territory_for_chunk
+                       .insert(chunk.to_owned(), name.clone()) {
+                    if other != name {
+                        return Err(format!("chunk {:?} is in both {:?} and {:?}",
+                                           chunk,
+                                           other,
+                                           name)
+                                           .into());
+                    }
+                }
+            }
+            result.territories.insert(name, chunks.into_iter().collect());
+        }
+        Ok(result)
+    }
+
+    /// The names of all our territories, in sorted order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.territories.keys().map(|name| &name[..])
+    }
+
+    /// The chunks in the territory `name`, if it exists.
+    pub fn chunks(&self, name: &str) -> Option<&ChunkSet> {
+        self.territories.get(name)
+    }
+
+    /// The territory containing `chunk_id`, if any.
+    pub fn territory_for_chunk(&self, chunk_id: &str) -> Option<&str> {
+        self.territory_for_chunk.get(chunk_id).map(|name| &name[..])
+    }
+
+    /// Our territories as a map from names to chunk IDs, in the same form
+    /// accepted by `Territories::new`.
+    pub fn to_map(&self) -> BTreeMap<String, Vec<String>> {
+        self.territories
+            .iter()
+            .map(|(name, chunks)| (name.to_owned(), chunks.iter().cloned().collect()))
+            .collect()
+    }
+
+    /// The territory containing `zip`, if any.
+    pub fn territory_for_zip(&self, classifier: &Classifier, zip: &str) -> Option<&str> {
+        classifier
+            .chunk_for(zip)
+            .and_then(|chunk| self.territory_for_chunk(chunk))
+    }
+}
+
+/// Merge all the chunks in `classifier` into `groups` territories with
+/// similar populations, named `territory_1`, `territory_2`, etc. (padded
+/// with zeros so that they sort correctly).
+///
+/// If `contiguous` is true, each territory is a consecutive run of chunk
+/// IDs. Because chunk IDs are based on zip code prefixes, this keeps
+/// territories roughly together geographically, but it will usually leave
+/// them less balanced.
+pub fn balance_territories(classifier: &Classifier,
+                           groups: usize,
+                           contiguous: bool)
+                           -> Result<Territories> {
+    let chunk_ids = classifier.chunk_ids();
+    if groups == 0 || groups > chunk_ids.len() {
+        return Err(format!("cannot make {} territories from {} chunks",
+                           groups,
+                           chunk_ids.len())
+                           .into());
+    }
+    let pops = chunk_ids
+        .iter()
+        .map(|c| {
+                 classifier
+                     .chunk_population(c)
+                     .expect("all chunks should have a population")
+             })
+        .collect::<Vec<_>>();
+    let group_for_chunk = if contiguous {
+        contiguous_groups(&pops, groups)
+    } else {
+        balanced_groups(&pops, groups)
+    };
+
+    let width = groups.to_string().len();
+    let mut map = BTreeMap::new();
+    for (chunk, &group) in chunk_ids.iter().zip(&group_for_chunk) {
+        map.entry(format!("territory_{:0width$}", group + 1, width = width))
+            .or_insert_with(Vec::new)
+            .push(chunk.to_owned());
+    }
+    Territories::new(classifier, map)
+}
+
+/// Assign each of `pops` to one of `groups`, by repeatedly adding the
+/// largest remaining population to the smallest group.
+fn balanced_groups(pops: &[u64], groups: usize) -> Vec<usize> {
+    let mut order = (0..pops.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| (Reverse(pops[i]), i));
+    let mut totals = (0..groups)
+        .map(|g| Reverse((0, g)))
+        .collect::<BinaryHeap<_>>();
+    let mut result = vec![0; pops.len()];
+    for i in order {
+        let Reverse((total, group)) = totals.pop().expect("groups should not be empty");
+        result[i] = group;
+        totals.push(Reverse((total + pops[i], group)));
+    }
+    result
+}
+
+/// Split `pops` into exactly `groups` consecutive runs, keeping the largest
+/// run as small as possible.
+fn contiguous_groups(pops: &[u64], groups: usize) -> Vec<usize> {
+    // Greedily pack chunks into runs no larger than `max`, returning the
+    // start of each run.
+    let pack = |max: u64| {
+        let mut starts = vec![0];
+        let mut total = 0;
+        for (i, &pop) in pops.iter().enumerate() {
+            if total + pop > max && total > 0 {
+                starts.push(i);
+                total = 0;
+            }
+            total += pop;
+        }
+        starts
+    };
+
+    // Find the smallest `max` which needs no more than `groups` runs.
+    let mut lo = pops.iter().cloned().max().unwrap_or(0);
+    let mut hi = pops.iter().sum::<u64>();
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pack(mid).len() <= groups {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    let mut starts = pack(lo);
+
+    // If we have too few runs, split the most populous runs in two, which
+    // never makes the largest run any larger.
+    let run_total = |starts: &[usize], r: usize| {
+        let end = starts.get(r + 1).cloned().unwrap_or(pops.len());
+        pops[starts[r]..end].iter().sum::<u64>()
+    };
+    while starts.len() < groups {
+        let (r, _) = (0..starts.len())
+            .filter(|&r| starts.get(r + 1).cloned().unwrap_or(pops.len()) - starts[r] > 1)
+            .map(|r| (r, run_total(&starts, r)))
+            .max_by_key(|&(r, total)| (total, Reverse(r)))
+            .expect("should have a run with more than one chunk");
+        let end = starts.get(r + 1).cloned().unwrap_or(pops.len());
+        // Split where the two halves are closest in size.
+        let total = run_total(&starts, r);
+        let mut best = (u64::MAX, starts[r] + 1);
+        let mut left = 0;
+        for split in (starts[r] + 1)..end {
+            left += pops[split - 1];
+            let larger = left.max(total - left);
+            if larger < best.0 {
+                best = (larger, split);
+            }
+        }
+        starts.insert(r + 1, best.1);
+    }
+
+    let mut result = vec![0; pops.len()];
+    for (r, &start) in starts.iter().enumerate() {
+        let end = starts.get(r + 1).cloned().unwrap_or(pops.len());
+        for group in &mut result[start..end] {
+            *group = r;
+        }
+    }
+    result
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn looks_up_territories() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut map = BTreeMap::new();
+    map.insert("West".to_owned(), vec!["902_0".to_owned()]);
+    map.insert("Shore".to_owned(), vec!["077_0".to_owned(), "078_0".to_owned()]);
+    let territories = Territories::new(&classifier, map.clone()).unwrap();
+    assert_eq!(territories.names().collect::<Vec<_>>(), vec!["Shore", "West"]);
+    assert_eq!(territories.chunks("Shore").unwrap().len(), 2);
+    assert_eq!(territories.territory_for_chunk("902_0"), Some("West"));
+    assert_eq!(territories.territory_for_zip(&classifier, "07712"), Some("Shore"));
+    assert_eq!(territories.territory_for_zip(&classifier, "02108"), None);
+
+    map.insert("Overlap".to_owned(), vec!["902_0".to_owned()]);
+    assert!(Territories::new(&classifier, map.clone()).is_err());
+    map.remove("Overlap");
+    map.insert("Bogus".to_owned(), vec!["999_9".to_owned()]);
+    assert!(Territories::new(&classifier, map).is_err());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn groups_chunks_into_balanced_territories() {
+    let _ = env_logger::init();
+    assert_eq!(balanced_groups(&[5, 1, 4, 2, 3], 2), vec![0, 0, 1, 0, 1]);
+    assert_eq!(contiguous_groups(&[5, 1, 4, 2, 3], 2), vec![0, 0, 1, 1, 1]);
+    assert_eq!(contiguous_groups(&[1, 1, 1, 1, 10], 4), vec![0, 1, 2, 2, 3]);
+
+    let classifier = Classifier::new(250000);
+    for &contiguous in &[false, true] {
+        let territories = balance_territories(&classifier, 40, contiguous).unwrap();
+        assert_eq!(territories.names().count(), 40);
+        assert_eq!(territories.names().next(), Some("territory_01"));
+        let pops = territories
+            .names()
+            .map(|name| territories.chunks(name).unwrap().population(&classifier))
+            .collect::<Vec<_>>();
+        let total = pops.iter().sum::<u64>();
+        let max = *pops.iter().max().unwrap();
+        assert!(max < total / 40 * 2, "unbalanced territories: {:?}", pops);
+        assert!(classifier.chunk_ids()
+                    .iter()
+                    .all(|c| territories.territory_for_chunk(c).is_some()));
+    }
+    assert!(balance_territories(&classifier, 0, false).is_err());
+}
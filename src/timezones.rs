@@ -0,0 +1,570 @@
+//! Looking up the IANA time zone of a zip code. Most 3-digit zip code
+//! prefixes lie in a single time zone, and we look those up by prefix. The
+//! zip codes in prefixes which straddle a boundary, such as in the Florida
+//! panhandle, western Kentucky or the Dakotas, are listed individually when
+//! their zone differs from the one used by most of their prefix's
+//! population.
+//!
+//! Our zones come from the `zipcodes` project's zip code database
+//! (https://github.com/seanpianka/zipcodes, MIT license).
+
+#[cfg(test)]
+use env_logger;
+
+/// Ranges of 3-digit zip code prefixes, and the IANA time zone used by most
+/// of their population, in order. Military prefixes and prefixes which
+/// aren't assigned are left out.
+const PREFIX_TIME_ZONES: &[(u16, u16, &str)] =
+    &[(5, 5, "America/New_York"),
+      (6, 7, "America/Puerto_Rico"),
+      (8, 8, "America/St_Thomas"),
+      (9, 9, "America/Puerto_Rico"),
+      (10, 89, "America/New_York"),
+      (100, 323, "America/New_York"),
+      (324, 325, "America/Chicago"),
+      (326, 339, "America/New_York"),
+      (341, 349, "America/New_York"),
+      (350, 372, "America/Chicago"),
+      (373, 374, "America/New_York"),
+      (375, 375, "America/Chicago"),
+      (376, 379, "America/New_York"),
+      (380, 397, "America/Chicago"),
+      (398, 401, "America/New_York"),
+      (402, 402, "America/Kentucky/Louisville"),
+      (403, 418, "America/New_York"),
+      (420, 424, "America/Chicago"),
+      (425, 425, "America/New_York"),
+      (426, 426, "America/Kentucky/Monticello"),
+      (427, 427, "America/New_York"),
+      (430, 459, "America/New_York"),
+      (460, 462, "America/Indiana/Indianapolis"),
+      (463, 464, "America/Chicago"),
+      (465, 469, "America/Indiana/Indianapolis"),
+      (470, 470, "America/New_York"),
+      (471, 471, "America/Kentucky/Louisville"),
+      (472, 474, "America/Indiana/Indianapolis"),
+      (475, 475, "America/Indiana/Vincennes"),
+      (476, 477, "America/Chicago"),
+      (478, 479, "America/Indiana/Indianapolis"),
+      (480, 499, "America/Detroit"),
+      (500, 528, "America/Chicago"),
+      (530, 567, "America/Chicago"),
+      (569, 569, "America/New_York"),
+      (570, 575, "America/Chicago"),
+      (576, 577, "America/Denver"),
+      (580, 585, "America/Chicago"),
+      (586, 586, "America/Denver"),
+      (587, 588, "America/Chicago"),
+      (590, 599, "America/Denver"),
+      (600, 658, "America/Chicago"),
+      (660, 692, "America/Chicago"),
+      (693, 693, "America/Denver"),
+      (700, 714, "America/Chicago"),
+      (716, 797, "America/Chicago"),
+      (798, 816, "America/Denver"),
+      (820, 831, "America/Denver"),
+      (832, 834, "America/Boise"),
+      (835, 835, "America/Los_Angeles"),
+      (836, 837, "America/Boise"),
+      (838, 838, "America/Los_Angeles"),
+      (840, 847, "America/Denver"),
+      (850, 864, "America/Phoenix"),
+      (865, 865, "America/Denver"),
+      (870, 885, "America/Denver"),
+      (889, 898, "America/Los_Angeles"),
+      (900, 961, "America/Los_Angeles"),
+      (967, 968, "Pacific/Honolulu"),
+      (969, 969, "Pacific/Guam"),
+      (970, 978, "America/Los_Angeles"),
+      (979, 979, "America/Boise"),
+      (980, 994, "America/Los_Angeles"),
+      (995, 997, "America/Anchorage"),
+      (998, 998, "America/Juneau"),
+      (999, 999, "America/Sitka")];
+
+/// Zip codes whose time zone differs from their prefix's entry in
+/// `PREFIX_TIME_ZONES`, sorted by zip code.
+const ZIP_TIME_ZONES: &[(&str, &str)] =
+    &[("32456", "America/New_York"),
+      ("32457", "America/New_York"),
+      ("37301", "America/Chicago"),
+      ("37305", "America/Chicago"),
+      ("37306", "America/Chicago"),
+      ("37313", "America/Chicago"),
+      ("37318", "America/Chicago"),
+      ("37324", "America/Chicago"),
+      ("37327", "America/Chicago"),
+      ("37328", "America/Chicago"),
+      ("37330", "America/Chicago"),
+      ("37334", "America/Chicago"),
+      ("37335", "America/Chicago"),
+      ("37338", "America/Chicago"),
+      ("37339", "America/Chicago"),
+      ("37340", "America/Chicago"),
+      ("37342", "America/Chicago"),
+      ("37345", "America/Chicago"),
+      ("37347", "America/Chicago"),
+      ("37348", "America/Chicago"),
+      ("37349", "America/Chicago"),
+      ("37352", "America/Chicago"),
+      ("37355", "America/Chicago"),
+      ("37356", "America/Chicago"),
+      ("37357", "America/Chicago"),
+      ("37359", "America/Chicago"),
+      ("37360", "America/Chicago"),
+      ("37365", "America/Chicago"),
+      ("37366", "America/Chicago"),
+      ("37367", "America/Chicago"),
+      ("37374", "America/Chicago"),
+      ("37375", "America/Chicago"),
+      ("37376", "America/Chicago"),
+      ("37378", "America/Chicago"),
+      ("37380", "America/Chicago"),
+      ("37382", "America/Chicago"),
+      ("37383", "America/Chicago"),
+      ("37387", "America/Chicago"),
+      ("37388", "America/Chicago"),
+      ("37389", "America/Chicago"),
+      ("37394", "America/Chicago"),
+      ("37396", "America/Chicago"),
+      ("37397", "America/Chicago"),
+      ("37398", "America/Chicago"),
+      ("37405", "America/Chicago"),
+      ("37723", "America/Chicago"),
+      ("40018", "America/Kentucky/Louisville"),
+      ("40023", "America/Kentucky/Louisville"),
+      ("40025", "America/Kentucky/Louisville"),
+      ("40027", "America/Kentucky/Louisville"),
+      ("40041", "America/Kentucky/Louisville"),
+      ("40059", "America/Kentucky/Louisville"),
+      ("40111", "America/Chicago"),
+      ("40115", "America/Chicago"),
+      ("40118", "America/Kentucky/Louisville"),
+      ("40119", "America/Chicago"),
+      ("40140", "America/Chicago"),
+      ("40143", "America/Chicago"),
+      ("40144", "America/Chicago"),
+      ("40145", "America/Chicago"),
+      ("40146", "America/Chicago"),
+      ("40152", "America/Chicago"),
+      ("40153", "America/Chicago"),
+      ("40170", "America/Chicago"),
+      ("40171", "America/Chicago"),
+      ("40176", "America/Chicago"),
+      ("40178", "America/Chicago"),
+      ("42602", "America/Chicago"),
+      ("42603", "America/Chicago"),
+      ("42629", "America/Chicago"),
+      ("42631", "America/New_York"),
+      ("42634", "America/New_York"),
+      ("42635", "America/New_York"),
+      ("42638", "America/New_York"),
+      ("42642", "America/Chicago"),
+      ("42647", "America/New_York"),
+      ("42649", "America/New_York"),
+      ("42653", "America/New_York"),
+      ("42712", "America/Chicago"),
+      ("42713", "America/Chicago"),
+      ("42715", "America/Chicago"),
+      ("42717", "America/Chicago"),
+      ("42720", "America/Chicago"),
+      ("42721", "America/Chicago"),
+      ("42722", "America/Chicago"),
+      ("42726", "America/Chicago"),
+      ("42728", "America/Chicago"),
+      ("42729", "America/Chicago"),
+      ("42731", "America/Chicago"),
+      ("42741", "America/Chicago"),
+      ("42742", "America/Chicago"),
+      ("42743", "America/Chicago"),
+      ("42746", "America/Chicago"),
+      ("42749", "America/Chicago"),
+      ("42753", "America/Chicago"),
+      ("42754", "America/Chicago"),
+      ("42755", "America/Chicago"),
+      ("42759", "America/Chicago"),
+      ("42762", "America/Chicago"),
+      ("42764", "America/Chicago"),
+      ("42765", "America/Chicago"),
+      ("42782", "America/Chicago"),
+      ("46366", "America/Indiana/Knox"),
+      ("46374", "America/Indiana/Knox"),
+      ("46531", "America/Indiana/Knox"),
+      ("46532", "America/Indiana/Knox"),
+      ("46534", "America/Indiana/Knox"),
+      ("46960", "America/Indiana/Winamac"),
+      ("46968", "America/Indiana/Knox"),
+      ("46985", "America/Indiana/Winamac"),
+      ("46996", "America/Indiana/Winamac"),
+      ("47003", "America/Indiana/Indianapolis"),
+      ("47006", "America/Indiana/Indianapolis"),
+      ("47010", "America/Indiana/Indianapolis"),
+      ("47011", "America/Indiana/Vevay"),
+      ("47012", "America/Indiana/Indianapolis"),
+      ("47016", "America/Indiana/Indianapolis"),
+      ("47017", "America/Indiana/Indianapolis"),
+      ("47019", "America/Indiana/Vevay"),
+      ("47020", "America/Indiana/Vevay"),
+      ("47021", "America/Indiana/Indianapolis"),
+      ("47023", "America/Indiana/Indianapolis"),
+      ("47024", "America/Indiana/Indianapolis"),
+      ("47030", "America/Indiana/Indianapolis"),
+      ("47031", "America/Indiana/Indianapolis"),
+      ("47033", "America/Indiana/Indianapolis"),
+      ("47034", "America/Indiana/Indianapolis"),
+      ("47035", "America/Indiana/Indianapolis"),
+      ("47036", "America/Indiana/Indianapolis"),
+      ("47037", "America/Indiana/Indianapolis"),
+      ("47038", "America/Indiana/Vevay"),
+      ("47039", "America/Indiana/Indianapolis"),
+      ("47041", "America/Indiana/Indianapolis"),
+      ("47042", "America/Indiana/Indianapolis"),
+      ("47043", "America/Indiana/Vevay"),
+      ("47102", "America/Indiana/Indianapolis"),
+      ("47108", "America/Indiana/Indianapolis"),
+      ("47116", "America/Indiana/Marengo"),
+      ("47118", "America/Indiana/Marengo"),
+      ("47120", "America/Indiana/Indianapolis"),
+      ("47123", "America/Indiana/Marengo"),
+      ("47125", "America/Indiana/Indianapolis"),
+      ("47137", "America/Indiana/Marengo"),
+      ("47138", "America/Indiana/Indianapolis"),
+      ("47139", "America/Indiana/Indianapolis"),
+      ("47140", "America/Indiana/Marengo"),
+      ("47145", "America/Indiana/Marengo"),
+      ("47147", "America/Indiana/Indianapolis"),
+      ("47165", "America/Indiana/Indianapolis"),
+      ("47167", "America/Indiana/Indianapolis"),
+      ("47170", "America/Indiana/Indianapolis"),
+      ("47174", "America/Indiana/Marengo"),
+      ("47175", "America/Indiana/Marengo"),
+      ("47514", "America/Indiana/Tell_City"),
+      ("47515", "America/Indiana/Tell_City"),
+      ("47520", "America/Indiana/Tell_City"),
+      ("47523", "America/Chicago"),
+      ("47525", "America/Indiana/Tell_City"),
+      ("47531", "America/Chicago"),
+      ("47536", "America/Chicago"),
+      ("47537", "America/Chicago"),
+      ("47550", "America/Chicago"),
+      ("47551", "America/Indiana/Tell_City"),
+      ("47552", "America/Chicago"),
+      ("47556", "America/Chicago"),
+      ("47564", "America/Indiana/Petersburg"),
+      ("47567", "America/Indiana/Petersburg"),
+      ("47574", "America/Indiana/Tell_City"),
+      ("47576", "America/Indiana/Tell_City"),
+      ("47577", "America/Chicago"),
+      ("47579", "America/Chicago"),
+      ("47584", "America/Indiana/Petersburg"),
+      ("47585", "America/Indiana/Petersburg"),
+      ("47586", "America/Indiana/Tell_City"),
+      ("47588", "America/Indiana/Tell_City"),
+      ("47590", "America/Indiana/Petersburg"),
+      ("47598", "America/Indiana/Petersburg"),
+      ("47922", "America/Chicago"),
+      ("47943", "America/Chicago"),
+      ("47946", "America/Indiana/Winamac"),
+      ("47948", "America/Chicago"),
+      ("47951", "America/Chicago"),
+      ("47957", "America/Indiana/Winamac"),
+      ("47963", "America/Chicago"),
+      ("47964", "America/Chicago"),
+      ("47977", "America/Chicago"),
+      ("47978", "America/Chicago"),
+      ("49801", "America/Menominee"),
+      ("49802", "America/Menominee"),
+      ("49812", "America/Menominee"),
+      ("49815", "America/Menominee"),
+      ("49821", "America/Menominee"),
+      ("49831", "America/Menominee"),
+      ("49834", "America/Menominee"),
+      ("49845", "America/Menominee"),
+      ("49847", "America/Menominee"),
+      ("49848", "America/Menominee"),
+      ("49852", "America/Menominee"),
+      ("49858", "America/Menominee"),
+      ("49863", "America/Menominee"),
+      ("49870", "America/Menominee"),
+      ("49873", "America/Menominee"),
+      ("49874", "America/Menominee"),
+      ("49876", "America/Menominee"),
+      ("49877", "America/Menominee"),
+      ("49881", "America/Menominee"),
+      ("49886", "America/Menominee"),
+      ("49887", "America/Menominee"),
+      ("49892", "America/Menominee"),
+      ("49893", "America/Menominee"),
+      ("49896", "America/Menominee"),
+      ("49902", "America/Menominee"),
+      ("49903", "America/Menominee"),
+      ("49911", "America/Menominee"),
+      ("49915", "America/Menominee"),
+      ("49920", "America/Menominee"),
+      ("49927", "America/Menominee"),
+      ("49935", "America/Menominee"),
+      ("49938", "America/Menominee"),
+      ("49947", "America/Menominee"),
+      ("49959", "America/Menominee"),
+      ("49964", "America/Menominee"),
+      ("49968", "America/Menominee"),
+      ("49969", "America/Menominee"),
+      ("57521", "America/Denver"),
+      ("57537", "America/Denver"),
+      ("57543", "America/Denver"),
+      ("57547", "America/Denver"),
+      ("57551", "America/Denver"),
+      ("57552", "America/Denver"),
+      ("57553", "America/Denver"),
+      ("57567", "America/Denver"),
+      ("57574", "America/Denver"),
+      ("57577", "America/Denver"),
+      ("57601", "America/Chicago"),
+      ("57631", "America/Chicago"),
+      ("57632", "America/Chicago"),
+      ("57646", "America/Chicago"),
+      ("57648", "America/Chicago"),
+      ("58520", "America/North_Dakota/New_Salem"),
+      ("58523", "America/North_Dakota/Beulah"),
+      ("58529", "America/Denver"),
+      ("58530", "America/North_Dakota/Center"),
+      ("58533", "America/Denver"),
+      ("58535", "America/North_Dakota/New_Salem"),
+      ("58541", "America/North_Dakota/Beulah"),
+      ("58545", "America/North_Dakota/Beulah"),
+      ("58554", "America/North_Dakota/New_Salem"),
+      ("58562", "America/Denver"),
+      ("58563", "America/North_Dakota/New_Salem"),
+      ("58564", "America/Denver"),
+      ("58566", "America/North_Dakota/New_Salem"),
+      ("58569", "America/Denver"),
+      ("58571", "America/North_Dakota/Beulah"),
+      ("58580", "America/North_Dakota/Beulah"),
+      ("58631", "America/North_Dakota/New_Salem"),
+      ("58638", "America/North_Dakota/New_Salem"),
+      ("67733", "America/Denver"),
+      ("67735", "America/Denver"),
+      ("67741", "America/Denver"),
+      ("67758", "America/Denver"),
+      ("67761", "America/Denver"),
+      ("67762", "America/Denver"),
+      ("67836", "America/Denver"),
+      ("67878", "America/Denver"),
+      ("67879", "America/Denver"),
+      ("69021", "America/Denver"),
+      ("69023", "America/Denver"),
+      ("69027", "America/Denver"),
+      ("69030", "America/Denver"),
+      ("69033", "America/Denver"),
+      ("69037", "America/Denver"),
+      ("69041", "America/Denver"),
+      ("69045", "America/Denver"),
+      ("69121", "America/Denver"),
+      ("69122", "America/Denver"),
+      ("69125", "America/Denver"),
+      ("69127", "America/Denver"),
+      ("69128", "America/Denver"),
+      ("69129", "America/Denver"),
+      ("69131", "America/Denver"),
+      ("69133", "America/Denver"),
+      ("69134", "America/Denver"),
+      ("69140", "America/Denver"),
+      ("69141", "America/Denver"),
+      ("69144", "America/Denver"),
+      ("69145", "America/Denver"),
+      ("69146", "America/Denver"),
+      ("69147", "America/Denver"),
+      ("69148", "America/Denver"),
+      ("69149", "America/Denver"),
+      ("69150", "America/Denver"),
+      ("69152", "America/Denver"),
+      ("69153", "America/Denver"),
+      ("69154", "America/Denver"),
+      ("69155", "America/Denver"),
+      ("69156", "America/Denver"),
+      ("69160", "America/Denver"),
+      ("69162", "America/Denver"),
+      ("69168", "America/Denver"),
+      ("69190", "America/Denver"),
+      ("69211", "America/Denver"),
+      ("69216", "America/Denver"),
+      ("69218", "America/Denver"),
+      ("69219", "America/Denver"),
+      ("79830", "America/Chicago"),
+      ("79831", "America/Chicago"),
+      ("79832", "America/Chicago"),
+      ("79834", "America/Chicago"),
+      ("79842", "America/Chicago"),
+      ("79843", "America/Chicago"),
+      ("79845", "America/Chicago"),
+      ("79846", "America/Chicago"),
+      ("79848", "America/Chicago"),
+      ("79852", "America/Chicago"),
+      ("79854", "America/Chicago"),
+      ("79855", "America/Chicago"),
+      ("79942", "America/Chicago"),
+      ("83414", "America/Denver"),
+      ("83522", "America/Boise"),
+      ("83547", "America/Boise"),
+      ("83549", "America/Boise"),
+      ("86003", "America/Denver"),
+      ("86016", "America/Denver"),
+      ("86020", "America/Denver"),
+      ("86031", "America/Denver"),
+      ("86033", "America/Denver"),
+      ("86035", "America/Denver"),
+      ("86040", "America/Denver"),
+      ("86044", "America/Denver"),
+      ("86053", "America/Denver"),
+      ("86054", "America/Denver"),
+      ("86502", "America/Phoenix"),
+      ("86512", "America/Phoenix"),
+      ("86555", "America/Phoenix"),
+      ("96799", "Pacific/Pago_Pago"),
+      ("96939", "Pacific/Palau"),
+      ("96940", "Pacific/Palau"),
+      ("96941", "Pacific/Chuuk"),
+      ("96942", "Pacific/Chuuk"),
+      ("96943", "Pacific/Chuuk"),
+      ("96944", "Pacific/Chuuk"),
+      ("96950", "Pacific/Saipan"),
+      ("96951", "Pacific/Saipan"),
+      ("96952", "Pacific/Saipan"),
+      ("96960", "Pacific/Majuro"),
+      ("96970", "Pacific/Majuro"),
+      ("97904", "America/Los_Angeles"),
+      ("97905", "America/Los_Angeles"),
+      ("97907", "America/Los_Angeles"),
+      ("99546", "America/Adak"),
+      ("99547", "America/Adak"),
+      ("99553", "America/Nome"),
+      ("99554", "America/Nome"),
+      ("99561", "America/Nome"),
+      ("99563", "America/Nome"),
+      ("99571", "America/Nome"),
+      ("99578", "America/Nome"),
+      ("99581", "America/Nome"),
+      ("99583", "America/Nome"),
+      ("99585", "America/Nome"),
+      ("99591", "America/Nome"),
+      ("99604", "America/Nome"),
+      ("99609", "America/Nome"),
+      ("99612", "America/Nome"),
+      ("99614", "America/Nome"),
+      ("99620", "America/Nome"),
+      ("99622", "America/Nome"),
+      ("99630", "America/Nome"),
+      ("99632", "America/Nome"),
+      ("99637", "America/Nome"),
+      ("99638", "America/Nome"),
+      ("99641", "America/Nome"),
+      ("99650", "America/Nome"),
+      ("99658", "America/Nome"),
+      ("99659", "America/Nome"),
+      ("99660", "America/Nome"),
+      ("99662", "America/Nome"),
+      ("99666", "America/Nome"),
+      ("99671", "America/Nome"),
+      ("99680", "America/Nome"),
+      ("99681", "America/Nome"),
+      ("99685", "America/Nome"),
+      ("99689", "America/Yakutat"),
+      ("99690", "America/Nome"),
+      ("99692", "America/Nome"),
+      ("99736", "America/Nome"),
+      ("99739", "America/Nome"),
+      ("99742", "America/Nome"),
+      ("99750", "America/Nome"),
+      ("99759", "America/Nome"),
+      ("99761", "America/Nome"),
+      ("99762", "America/Nome"),
+      ("99766", "America/Nome"),
+      ("99769", "America/Nome"),
+      ("99771", "America/Nome"),
+      ("99772", "America/Nome"),
+      ("99778", "America/Nome"),
+      ("99783", "America/Nome"),
+      ("99784", "America/Nome"),
+      ("99785", "America/Nome"),
+      ("99830", "America/Sitka"),
+      ("99833", "America/Sitka"),
+      ("99835", "America/Sitka"),
+      ("99922", "America/Juneau"),
+      ("99926", "America/Metlakatla")];
+
+/// The IANA time zone of the zip code or prefix `zip`, like
+/// `America/Chicago`, which needs at least three digits. A 5-digit zip code
+/// gets its own zone, and a shorter prefix gets the zone used by most of its
+/// population. Returns `None` for military and unassigned prefixes.
+pub fn time_zone_for_zip(zip: &str) -> Option<&'static str> {
+    let prefix = zip.get(..3)?;
+    if !prefix.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let prefix = prefix.parse::<u16>().ok()?;
+    let prefix_zone = PREFIX_TIME_ZONES
+        .iter()
+        .find(|&&(first, last, _)| first <= prefix && prefix <= last)
+        .map(|&(_, _, zone)| zone)?;
+    let zip_zone = zip.get(..5)
+        .and_then(|zip5| {
+                      ZIP_TIME_ZONES
+                          .binary_search_by_key(&zip5, |&(z, _)| z)
+                          .ok()
+                  })
+        .map(|i| ZIP_TIME_ZONES[i].1);
+    Some(zip_zone.unwrap_or(prefix_zone))
+}
+
+#[test]
+fn looks_up_time_zones() {
+    let _ = env_logger::init();
+    assert_eq!(time_zone_for_zip("90210"), Some("America/Los_Angeles"));
+    assert_eq!(time_zone_for_zip("02138"), Some("America/New_York"));
+    assert_eq!(time_zone_for_zip("32501"), Some("America/Chicago"));
+    assert_eq!(time_zone_for_zip("85001-1234"), Some("America/Phoenix"));
+    assert_eq!(time_zone_for_zip("79901"), Some("America/Denver"));
+    assert_eq!(time_zone_for_zip("96813"), Some("Pacific/Honolulu"));
+    assert_eq!(time_zone_for_zip("09001"), None);
+    assert_eq!(time_zone_for_zip("02"), None);
+    assert_eq!(time_zone_for_zip("N/A"), None);
+    for window in PREFIX_TIME_ZONES.windows(2) {
+        assert!(window[0].1 < window[1].0);
+    }
+    for window in ZIP_TIME_ZONES.windows(2) {
+        assert!(window[0].0 < window[1].0);
+    }
+}
+
+#[test]
+fn looks_up_zip_codes_in_split_prefixes() {
+    let _ = env_logger::init();
+    // The Florida panhandle: Panama City is Central, Port St. Joe Eastern.
+    assert_eq!(time_zone_for_zip("32401"), Some("America/Chicago"));
+    assert_eq!(time_zone_for_zip("32456"), Some("America/New_York"));
+    // Kentucky: Elizabethtown is Eastern, Hardinsburg and Albany Central.
+    assert_eq!(time_zone_for_zip("42701"), Some("America/New_York"));
+    assert_eq!(time_zone_for_zip("40143"), Some("America/Chicago"));
+    assert_eq!(time_zone_for_zip("42602"), Some("America/Chicago"));
+    // Indiana: La Porte and Boonville are Central.
+    assert_eq!(time_zone_for_zip("46350"), Some("America/Chicago"));
+    assert_eq!(time_zone_for_zip("47601"), Some("America/Chicago"));
+    assert_eq!(time_zone_for_zip("47591"), Some("America/Indiana/Vincennes"));
+    // The Dakotas and Nebraska: Pierre and North Platte are Central, while
+    // Rapid City, Dickinson and Ogallala are Mountain.
+    assert_eq!(time_zone_for_zip("57501"), Some("America/Chicago"));
+    assert_eq!(time_zone_for_zip("57701"), Some("America/Denver"));
+    assert_eq!(time_zone_for_zip("58601"), Some("America/Denver"));
+    assert_eq!(time_zone_for_zip("69101"), Some("America/Chicago"));
+    assert_eq!(time_zone_for_zip("69153"), Some("America/Denver"));
+    // Idaho and Oregon: Lewiston is Pacific, Ontario Mountain.
+    assert_eq!(time_zone_for_zip("83501"), Some("America/Los_Angeles"));
+    assert_eq!(time_zone_for_zip("97914"), Some("America/Boise"));
+    // Arizona: Flagstaff skips daylight saving time, the Navajo Nation
+    // doesn't.
+    assert_eq!(time_zone_for_zip("86001"), Some("America/Phoenix"));
+    assert_eq!(time_zone_for_zip("86515"), Some("America/Denver"));
+    // A prefix on its own gets the zone used by most of its population.
+    assert_eq!(time_zone_for_zip("324"), Some("America/Chicago"));
+    assert_eq!(time_zone_for_zip("3245"), Some("America/Chicago"));
+}
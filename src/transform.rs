@@ -0,0 +1,1560 @@
+//! Adding geochunk columns to CSV files.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+#[cfg(all(test, feature = "data-zip2010"))]
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::io::prelude::*;
+use std::ops::AddAssign;
+use std::path::Path;
+use std::str::from_utf8;
+
+use density::Urbanicity;
+use dialect::{Dialect, RaggedRows};
+use districts::DistrictTable;
+use dma::DmaTable;
+use errors::*;
+use experiment::ArmAssignment;
+use number::parse_number;
+#[cfg(feature = "orc")]
+use orc::{OrcCompression, OrcWriter};
+use points::PointIndex;
+use proto::{self, RecordWriter};
+use shard::{ShardedWriter, ShardInfo, ShardLimits};
+use sort::{ExternalSorter, DEFAULT_MAX_BUFFER_BYTES};
+use territory::Territories;
+use timezones::time_zone_for_zip;
+use vintage::Vintages;
+use zip2010::Classifier;
+
+/// Options controlling how we add geochunks to a CSV file.
+#[derive(Clone, Debug, Default)]
+pub struct TransformOptions {
+    /// Replace the zip code column with the geochunk column, instead of
+    /// appending a new column. This keeps raw zip codes out of our output.
+    pub replace: bool,
+    /// Columns to remove from the output entirely, such as ZIP+4 or city
+    /// columns which could identify people.
+    pub drop_columns: Vec<String>,
+    /// Chunks which should be written as empty values.
+    pub suppressed_chunks: HashSet<String>,
+    /// The name to use for our geochunk column, instead of the default name
+    /// from `Classifier::geochunk_column_name`.
+    pub output_column: Option<String>,
+    /// Where to put our geochunk columns.
+    pub position: ChunkPosition,
+    /// Add a column containing the zip code prefix used to find each chunk.
+    pub add_prefix: bool,
+    /// Add a column containing the population of each chunk.
+    pub add_chunk_population: bool,
+    /// Add a column describing how each zip code was assigned to its chunk.
+    /// See `Classifier::assignment_method`.
+    pub add_assignment_method: bool,
+    /// Add a column containing the population density of each zip code, in
+    /// people per square mile. See `Classifier::density_for`.
+    pub add_density: bool,
+    /// Add a column classifying each zip code as `urban`, `suburban` or
+    /// `rural` by its population density. See `density::Urbanicity`.
+    pub add_urbanicity: bool,
+    /// Add a column containing the IANA time zone of each zip code. See
+    /// `timezones::time_zone_for_zip`.
+    pub add_time_zone: bool,
+    /// Add a column containing the designated market area of each zip code,
+    /// or an empty value if it isn't in our table.
+    pub dmas: Option<DmaTable>,
+    /// Treat our two input columns as a latitude and a longitude, and find
+    /// the zip code of each point using this index, instead of reading zip
+    /// codes. See `points::PointIndex`.
+    pub points: Option<PointIndex>,
+    /// With `points`, assign points outside every ZCTA to the ZCTA with the
+    /// nearest internal point within this many miles, add a column which is
+    /// `true` for points assigned this way, and count them in
+    /// `TransformStats::nearest_rows`.
+    pub nearest_within: Option<f64>,
+    /// Add a column containing the congressional district of each zip
+    /// code, or an empty value if it isn't in our crosswalk.
+    pub congressional_districts: Option<DistrictTable>,
+    /// Add a column containing the state legislative district of each zip
+    /// code, or an empty value if it isn't in our crosswalk.
+    pub legislative_districts: Option<DistrictTable>,
+    /// Add a column containing the territory of each chunk, or an empty
+    /// value if it isn't in a territory.
+    pub territories: Option<Territories>,
+    /// Add a column containing the experiment arm of each chunk.
+    pub arms: Option<ArmAssignment>,
+    /// Add a column which is `true` for zip codes whose prefix has no
+    /// population, and count them in `TransformStats::new_zip_rows`. See
+    /// `Classifier::is_new_zip`.
+    pub flag_new_zips: bool,
+    /// Add a column which is `true` for zip codes assigned to a chunk by
+    /// the fallback from `Classifier::with_new_zip_fallback`, and count them
+    /// in `TransformStats::fallback_rows`.
+    pub flag_fallback_zips: bool,
+    /// Choose a population vintage for each row using the date in a column,
+    /// instead of always using our classifier, and add a column containing
+    /// the name of the vintage we used.
+    pub vintages: Option<Vintages>,
+    /// Output the dense integer index of each chunk, instead of its ID. See
+    /// `Classifier::chunk_index`.
+    pub chunk_as_int: bool,
+    /// Our input has no header row, so columns are specified using 1-based
+    /// indices, like `"3"`.
+    pub no_header: bool,
+    /// When `no_header` is set, write a header row anyway, naming input
+    /// columns `column_1`, `column_2`, etc.
+    pub synthesize_header: bool,
+    /// Fail on the first row containing a zip code which we can't assign to
+    /// a geochunk, instead of leaving its geochunk empty. Empty zip codes are
+    /// still allowed.
+    pub strict: bool,
+    /// How our input is formatted. We write our output in the same dialect.
+    pub dialect: Dialect,
+    /// Group our output rows by the geochunk of the first zip code column,
+    /// in chunk index order, with rows lacking a geochunk at the end. Rows
+    /// in the same chunk keep their input order. If there are too many rows
+    /// to sort in memory, we use temporary files.
+    pub sort_by_chunk: bool,
+    /// Drop any row with the same value in this column as an earlier row,
+    /// such as an email address. Rows with an empty value are always kept.
+    pub dedupe_key: Option<String>,
+    /// With `dedupe_key`, only drop rows which are also in the same geochunk
+    /// as the earlier row, using the first zip code column.
+    pub dedupe_within_chunk: bool,
+}
+
+/// Where should we put our geochunk columns in our output?
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChunkPosition {
+    /// After all the other columns.
+    #[default]
+    Append,
+    /// Before all the other columns.
+    Prepend,
+    /// Immediately after the named column.
+    InsertAfter(String),
+}
+
+/// A field in our output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputField {
+    /// A field copied from our input, by index.
+    Input(usize),
+    /// Information about the geochunk for one of our zip code columns, by
+    /// index into our list of zip code columns.
+    Chunk(usize, ChunkField),
+}
+
+/// Information we can output about a geochunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChunkField {
+    /// The geochunk ID.
+    Id,
+    /// The zip code prefix used to find the geochunk.
+    Prefix,
+    /// The population of the geochunk.
+    Population,
+    /// How the zip code was assigned to the geochunk.
+    Method,
+    /// The population density of the zip code.
+    Density,
+    /// Whether the zip code is urban, suburban or rural.
+    Urbanicity,
+    /// The time zone of the zip code.
+    TimeZone,
+    /// The designated market area of the zip code.
+    Dma,
+    /// The congressional district of the zip code.
+    CongressionalDistrict,
+    /// The state legislative district of the zip code.
+    LegislativeDistrict,
+    /// The territory containing the geochunk.
+    Territory,
+    /// The experiment arm of the geochunk.
+    Arm,
+    /// Whether the zip code's prefix has no population.
+    NewZip,
+    /// Whether the zip code was assigned using our new zip fallback.
+    Fallback,
+    /// Whether the point was assigned to the nearest ZCTA.
+    Nearest,
+    /// The population vintage used to find the geochunk.
+    Vintage,
+}
+
+impl ChunkField {
+    /// The suffix to add to the geochunk column name for this field.
+    fn column_suffix(self) -> &'static str {
+        match self {
+            ChunkField::Id => "",
+            ChunkField::Prefix => "_prefix",
+            ChunkField::Population => "_population",
+            ChunkField::Method => "_method",
+            ChunkField::Density => "_density",
+            ChunkField::Urbanicity => "_urbanicity",
+            ChunkField::TimeZone => "_timezone",
+            ChunkField::Dma => "_dma",
+            ChunkField::CongressionalDistrict => "_cd",
+            ChunkField::LegislativeDistrict => "_sld",
+            ChunkField::Territory => "_territory",
+            ChunkField::Arm => "_arm",
+            ChunkField::NewZip => "_new_zip",
+            ChunkField::Fallback => "_fallback",
+            ChunkField::Nearest => "_nearest",
+            ChunkField::Vintage => "_vintage",
+        }
+    }
+}
+
+/// Statistics about the rows we transformed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransformStats {
+    /// The number of rows we wrote, not counting headers.
+    pub rows: u64,
+    /// The number of rows with a non-empty zip code that we couldn't assign
+    /// to a geochunk.
+    pub invalid_rows: u64,
+    /// The number of rows we dropped because of `dedupe_key`.
+    pub duplicate_rows: u64,
+    /// The number of rows with a zip code whose prefix has no population,
+    /// if `flag_new_zips` was set.
+    pub new_zip_rows: u64,
+    /// The number of rows with a zip code assigned using our new zip
+    /// fallback, if `flag_fallback_zips` was set.
+    pub fallback_rows: u64,
+    /// The number of rows with a point outside every ZCTA which we assigned
+    /// to the nearest one, if `nearest_within` was set.
+    pub nearest_rows: u64,
+    /// The number of ragged rows we skipped because of `RaggedRows::Skip`.
+    pub skipped_rows: u64,
+}
+
+impl TransformStats {
+    /// The percentage of rows which were invalid.
+    pub fn invalid_pct(&self) -> f64 {
+        if self.rows == 0 {
+            0.0
+        } else {
+            100.0 * self.invalid_rows as f64 / self.rows as f64
+        }
+    }
+}
+
+/// Combine the statistics for several files.
+impl AddAssign for TransformStats {
+    fn add_assign(&mut self, other: TransformStats) {
+        self.rows += other.rows;
+        self.invalid_rows += other.invalid_rows;
+        self.duplicate_rows += other.duplicate_rows;
+        self.new_zip_rows += other.new_zip_rows;
+        self.fallback_rows += other.fallback_rows;
+        self.nearest_rows += other.nearest_rows;
+        self.skipped_rows += other.skipped_rows;
+    }
+}
+
+/// Read a CSV file, add a geochunk column for each of `input_columns`, and
+/// write it back out again.
+pub fn transform_csv(classifier: &Classifier,
+                     input_columns: &[&str],
+                     options: &TransformOptions,
+                     input: &mut dyn Read,
+                     output: &mut dyn Write)
+                     -> Result<TransformStats> {
+    transform_csv_with_rejects(classifier, input_columns, options, input, output, None)
+}
+
+/// Like `transform_csv`, but write any rows skipped because of
+/// `RaggedRows::Skip` to `rejects`, exactly as they appeared in our input.
+pub fn transform_csv_with_rejects(classifier: &Classifier,
+                                  input_columns: &[&str],
+                                  options: &TransformOptions,
+                                  input: &mut dyn Read,
+                                  output: &mut dyn Write,
+                                  rejects: Option<&mut dyn Write>)
+                                  -> Result<TransformStats> {
+    let mut wtr = options.dialect.writer_builder().from_writer(output);
+    let stats = transform_rows(classifier, input_columns, options, input, &mut wtr, rejects)?;
+    wtr.flush()?;
+    Ok(stats)
+}
+
+/// Like `transform_csv_with_rejects`, but write our output to `path`,
+/// split into shards according to `limits`. Returns the shards we wrote.
+pub fn transform_csv_to_shards(classifier: &Classifier,
+                               input_columns: &[&str],
+                               options: &TransformOptions,
+                               input: &mut dyn Read,
+                               path: &Path,
+                               limits: ShardLimits,
+                               rejects: Option<&mut dyn Write>)
+                               -> Result<(TransformStats, Vec<ShardInfo>)> {
+    let mut wtr = ShardedWriter::new(path, limits, &options.dialect);
+    let stats = transform_rows(classifier, input_columns, options, input, &mut wtr, rejects)?;
+    Ok((stats, wtr.finish()?))
+}
+
+/// Like `transform_csv_with_rejects`, but write our metadata and each row
+/// to `output` as length-delimited protobuf messages. See `proto`.
+pub fn transform_csv_to_proto(classifier: &Classifier,
+                              input_columns: &[&str],
+                              options: &TransformOptions,
+                              input: &mut dyn Read,
+                              output: &mut dyn Write,
+                              rejects: Option<&mut dyn Write>)
+                              -> Result<TransformStats> {
+    proto::write_metadata(&classifier.metadata(), output)?;
+    let mut wtr = RecordWriter::new(output);
+    let stats = transform_rows(classifier, input_columns, options, input, &mut wtr, rejects)?;
+    wtr.flush()?;
+    Ok(stats)
+}
+
+/// Like `transform_csv_with_rejects`, but write our output to `output` as
+/// an ORC file, compressed using `compression`. See `orc`.
+#[cfg(feature = "orc")]
+pub fn transform_csv_to_orc(classifier: &Classifier,
+                            input_columns: &[&str],
+                            options: &TransformOptions,
+                            input: &mut dyn Read,
+                            output: &mut dyn Write,
+                            compression: OrcCompression,
+                            rejects: Option<&mut dyn Write>)
+                            -> Result<TransformStats> {
+    let mut wtr = OrcWriter::new(output, compression);
+    let stats = transform_rows(classifier, input_columns, options, input, &mut wtr, rejects)?;
+    wtr.finish()?;
+    Ok(stats)
+}
+
+/// Somewhere `transform_rows` can write its output.
+trait RowSink {
+    /// Write our header row.
+    fn write_headers(&mut self, headers: &csv::ByteRecord) -> Result<()>;
+
+    /// Write a row.
+    fn write_row(&mut self, row: &csv::ByteRecord) -> Result<()>;
+}
+
+impl<W: Write> RowSink for csv::Writer<W> {
+    fn write_headers(&mut self, headers: &csv::ByteRecord) -> Result<()> {
+        Ok(self.write_byte_record(headers)?)
+    }
+
+    fn write_row(&mut self, row: &csv::ByteRecord) -> Result<()> {
+        Ok(self.write_byte_record(row)?)
+    }
+}
+
+impl<'a> RowSink for ShardedWriter<'a> {
+    fn write_headers(&mut self, headers: &csv::ByteRecord) -> Result<()> {
+        ShardedWriter::write_headers(self, headers)
+    }
+
+    fn write_row(&mut self, row: &csv::ByteRecord) -> Result<()> {
+        ShardedWriter::write_row(self, row)
+    }
+}
+
+impl<'a> RowSink for RecordWriter<'a> {
+    fn write_headers(&mut self, headers: &csv::ByteRecord) -> Result<()> {
+        self.write_header(headers)
+    }
+
+    fn write_row(&mut self, row: &csv::ByteRecord) -> Result<()> {
+        self.write_record(row)
+    }
+}
+
+#[cfg(feature = "orc")]
+impl<W: Write> RowSink for OrcWriter<W> {
+    fn write_headers(&mut self, headers: &csv::ByteRecord) -> Result<()> {
+        OrcWriter::write_headers(self, headers)
+    }
+
+    fn write_row(&mut self, row: &csv::ByteRecord) -> Result<()> {
+        OrcWriter::write_row(self, row)
+    }
+}
+
+/// Do the work of `transform_csv_with_rejects`, writing our output to
+/// `wtr`.
+fn transform_rows(classifier: &Classifier,
+                  input_columns: &[&str],
+                  options: &TransformOptions,
+                  input: &mut dyn Read,
+                  wtr: &mut dyn RowSink,
+                  rejects: Option<&mut dyn Write>)
+                  -> Result<TransformStats> {
+    trace_span!("transform_csv", columns = ?input_columns);
+    let mut rdr = options
+        .dialect
+        .reader_builder()
+        .has_headers(!options.no_header)
+        .from_reader(input);
+    let mut sorter = if options.sort_by_chunk {
+        Some(ExternalSorter::new(DEFAULT_MAX_BUFFER_BYTES))
+    } else {
+        None
+    };
+
+    // Extract our headers, or make some up if we don't have any.
+    let headers = if options.no_header {
+        synthesize_headers(rdr.byte_headers()?.len())
+    } else {
+        rdr.headers()?.to_owned()
+    };
+    let mut transformer = RowTransformer::new(classifier, input_columns, options, headers)?;
+    if let Some(out_headers) = transformer.output_headers() {
+        wtr.write_headers(out_headers.as_byte_record())?;
+    }
+    let mut rejects = match rejects {
+        Some(rejects) => {
+            let mut rejects_wtr = options.dialect.writer_builder().from_writer(rejects);
+            if !options.no_header {
+                rejects_wtr.write_record(transformer.input_headers().iter())?;
+            }
+            Some(rejects_wtr)
+        }
+        None => None,
+    };
+
+    // According to BurntSushi at
+    // https://github.com/BurntSushi/rust-csv/issues/76 ,
+    // this should be the fastest way to write this loop.  This matters
+    // because we may have millions of rows and hundreds of columns.
+    let mut row = csv::ByteRecord::new();
+    while rdr.read_byte_record(&mut row)? {
+        match transformer.transform_row(&mut row)? {
+            Transformed::Row(out_row) => {
+                if let Some(ref mut sorter) = sorter {
+                    let out_row = out_row.clone();
+                    let key = transformer
+                        .first_chunk()
+                        .and_then(|chunk| classifier.chunk_index(chunk))
+                        .map_or(u64::MAX, |idx| idx as u64);
+                    sorter.push(key.to_be_bytes().to_vec(), out_row)?;
+                } else {
+                    wtr.write_row(out_row)?;
+                }
+            }
+            Transformed::Ragged => {
+                if let Some(ref mut rejects) = rejects {
+                    rejects.write_byte_record(&row)?;
+                }
+            }
+            Transformed::Duplicate => {}
+        }
+    }
+    if let Some(sorter) = sorter {
+        sorter.finish(|row| wtr.write_row(row))?;
+    }
+    Ok(transformer.stats())
+}
+
+/// Make up header names for a file with `len` columns and no header row.
+pub(crate) fn synthesize_headers(len: usize) -> csv::StringRecord {
+    (1..(len + 1))
+        .map(|i| format!("column_{}", i))
+        .collect::<csv::StringRecord>()
+}
+
+/// What `RowTransformer::transform_row` did with a row.
+pub(crate) enum Transformed<'r> {
+    /// The row we should write.
+    Row(&'r csv::ByteRecord),
+    /// The row should be skipped because of `RaggedRows::Skip`.
+    Ragged,
+    /// The row should be skipped because of `TransformOptions::dedupe_key`.
+    Duplicate,
+}
+
+/// Adds geochunk columns to one row at a time. This does all the work of
+/// `transform_csv`, except for reading and writing, so that we can share it
+/// with other ways of doing I/O.
+pub(crate) struct RowTransformer<'a> {
+    /// Our options.
+    options: &'a TransformOptions,
+    /// The headers of our input, possibly synthesized.
+    headers: csv::StringRecord,
+    /// The headers of our output.
+    out_headers: csv::StringRecord,
+    /// The indices of our zip code columns. With `points`, this is our
+    /// latitude column.
+    zip_col_idxs: Vec<usize>,
+    /// The index of our longitude column, with `points`.
+    lng_col_idx: Option<usize>,
+    /// The fields we output, in order.
+    fields: Vec<OutputField>,
+    /// Can we append our chunks to our input row without copying it?
+    append_only: bool,
+    /// A buffer for our output row, if we need to copy.
+    out_row: csv::ByteRecord,
+    /// A buffer for the prefix and chunk of each zip code column.
+    chunks: Vec<Option<(&'a str, &'a str)>>,
+    /// A buffer for what we know about the zip code in each zip code column,
+    /// besides its chunk.
+    zip_info: Vec<ZipInfo<'a>>,
+    /// The index of our `dedupe_key` column, if any.
+    dedupe_col_idx: Option<usize>,
+    /// The index of our `vintages` date column, if any.
+    date_col_idx: Option<usize>,
+    /// The name of the vintage and the classifier used for the last row we
+    /// transformed. Without `vintages`, this is always our classifier, with
+    /// an empty name.
+    vintage: (&'a str, &'a Classifier),
+    /// The chunks and keys we've already seen, if we're deduplicating.
+    seen: HashSet<(Option<&'a str>, Vec<u8>)>,
+    /// What we've done so far.
+    stats: TransformStats,
+}
+
+impl<'a> RowTransformer<'a> {
+    /// Prepare to transform rows with the specified `headers`.
+    pub(crate) fn new(classifier: &'a Classifier,
+                      input_columns: &[&str],
+                      options: &'a TransformOptions,
+                      headers: csv::StringRecord)
+                      -> Result<RowTransformer<'a>> {
+        let find_column = |name: &str| if options.no_header {
+            name.parse::<usize>()
+                .ok()
+                .and_then(|i| i.checked_sub(1))
+                .filter(|&i| i < headers.len())
+                .ok_or_else(|| Error::no_such_column(name))
+        } else {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| Error::no_such_column(name))
+        };
+
+        // Look up the header indices for our zip code columns, and any
+        // columns we want to drop.
+        let mut zip_col_idxs = input_columns
+            .iter()
+            .map(|c| find_column(c))
+            .collect::<Result<Vec<usize>>>()?;
+        let lng_col_idx = match options.points {
+            Some(_) if zip_col_idxs.len() != 2 => {
+                return Err("points need exactly two columns, a latitude and a longitude"
+                               .into());
+            }
+            Some(_) if options.replace => {
+                return Err("cannot replace latitude and longitude columns".into());
+            }
+            Some(_) => zip_col_idxs.pop(),
+            None => None,
+        };
+        let drop_col_idxs = options
+            .drop_columns
+            .iter()
+            .map(|c| find_column(c))
+            .collect::<Result<HashSet<usize>>>()?;
+        if zip_col_idxs.iter().chain(&lng_col_idx).any(|i| drop_col_idxs.contains(i)) {
+            return Err("cannot drop a zip code column".into());
+        }
+        let dedupe_col_idx = match options.dedupe_key {
+            Some(ref name) => Some(find_column(name)?),
+            None => None,
+        };
+        // Chunks from different vintages can't be compared, so we can't use
+        // anything which was built for a single classifier.
+        let date_col_idx = match options.vintages {
+            Some(_) if options.sort_by_chunk || options.dedupe_within_chunk => {
+                return Err("cannot compare chunks from different population vintages"
+                               .into());
+            }
+            Some(_) if options.territories.is_some() || options.arms.is_some() ||
+                       options.chunk_as_int => {
+                return Err("cannot use a single chunk table with population vintages"
+                               .into());
+            }
+            Some(ref vintages) => Some(find_column(vintages.column())?),
+            None => None,
+        };
+
+        // Decide which fields to output, in what order.
+        let mut chunk_field_kinds = vec![ChunkField::Id];
+        if options.add_prefix {
+            chunk_field_kinds.push(ChunkField::Prefix);
+        }
+        if options.add_chunk_population {
+            chunk_field_kinds.push(ChunkField::Population);
+        }
+        if options.add_assignment_method {
+            chunk_field_kinds.push(ChunkField::Method);
+        }
+        if options.add_density {
+            if !classifier.has_land_areas() {
+                return Err("cannot add population densities without land areas".into());
+            }
+            chunk_field_kinds.push(ChunkField::Density);
+        }
+        if options.add_urbanicity {
+            if !classifier.has_land_areas() {
+                return Err("cannot classify urbanicity without land areas".into());
+            }
+            chunk_field_kinds.push(ChunkField::Urbanicity);
+        }
+        if options.add_time_zone {
+            chunk_field_kinds.push(ChunkField::TimeZone);
+        }
+        if options.dmas.is_some() {
+            chunk_field_kinds.push(ChunkField::Dma);
+        }
+        if options.congressional_districts.is_some() {
+            chunk_field_kinds.push(ChunkField::CongressionalDistrict);
+        }
+        if options.legislative_districts.is_some() {
+            chunk_field_kinds.push(ChunkField::LegislativeDistrict);
+        }
+        if options.territories.is_some() {
+            chunk_field_kinds.push(ChunkField::Territory);
+        }
+        if options.arms.is_some() {
+            chunk_field_kinds.push(ChunkField::Arm);
+        }
+        if options.flag_new_zips {
+            chunk_field_kinds.push(ChunkField::NewZip);
+        }
+        if options.flag_fallback_zips {
+            chunk_field_kinds.push(ChunkField::Fallback);
+        }
+        if options.points.is_some() && options.nearest_within.is_some() {
+            chunk_field_kinds.push(ChunkField::Nearest);
+        }
+        if options.vintages.is_some() {
+            chunk_field_kinds.push(ChunkField::Vintage);
+        }
+        let fields_for_zip = |zip_idx: usize| {
+            chunk_field_kinds
+                .iter()
+                .map(move |&kind| OutputField::Chunk(zip_idx, kind))
+        };
+        let chunk_fields = (0..zip_col_idxs.len()).flat_map(&fields_for_zip);
+        let insert_after_idx = match options.position {
+            _ if options.replace && options.position != ChunkPosition::Append => {
+                return Err("cannot choose the position of replaced columns".into());
+            }
+            ChunkPosition::InsertAfter(ref name) => {
+                let idx = find_column(name)?;
+                if drop_col_idxs.contains(&idx) {
+                    return Err("cannot insert after a dropped column".into());
+                }
+                Some(idx)
+            }
+            _ => None,
+        };
+        let mut fields = vec![];
+        if options.position == ChunkPosition::Prepend {
+            fields.extend(chunk_fields.clone());
+        }
+        for i in 0..headers.len() {
+            match zip_col_idxs.iter().position(|&z| z == i) {
+                Some(zip_idx) if options.replace => fields.extend(fields_for_zip(zip_idx)),
+                _ if drop_col_idxs.contains(&i) => {}
+                _ => fields.push(OutputField::Input(i)),
+            }
+            if insert_after_idx == Some(i) {
+                fields.extend(chunk_fields.clone());
+            }
+        }
+        if !options.replace && options.position == ChunkPosition::Append {
+            fields.extend(chunk_fields);
+        }
+
+        // If we're just appending chunks to each row, we can skip copying.
+        let append_only = fields
+            .iter()
+            .enumerate()
+            .all(|(i, &f)| i >= headers.len() || f == OutputField::Input(i));
+
+        // Build our output headers. If we have more than one zip code column,
+        // we need to include the input column name in each geochunk column
+        // name.
+        let column_name = match (&options.output_column, &options.vintages) {
+            (Some(name), _) => name.clone(),
+            (None, Some(vintages)) => vintages.geochunk_column_name(),
+            (None, None) => classifier.geochunk_column_name(),
+        };
+        let mut out_headers = csv::StringRecord::new();
+        for &field in &fields {
+            match field {
+                OutputField::Input(i) => out_headers.push_field(&headers[i]),
+                OutputField::Chunk(_, kind) if zip_col_idxs.len() == 1 => {
+                    out_headers
+                        .push_field(&format!("{}{}", column_name, kind.column_suffix()))
+                }
+                OutputField::Chunk(zip_idx, kind) => {
+                    out_headers.push_field(&format!("{}_{}{}",
+                                                    input_columns[zip_idx],
+                                                    column_name,
+                                                    kind.column_suffix()))
+                }
+            }
+        }
+
+        Ok(RowTransformer {
+               options,
+               headers,
+               out_headers,
+               chunks: Vec::with_capacity(zip_col_idxs.len()),
+               zip_info: Vec::with_capacity(zip_col_idxs.len()),
+               zip_col_idxs,
+               lng_col_idx,
+               fields,
+               append_only,
+               out_row: csv::ByteRecord::new(),
+               dedupe_col_idx,
+               date_col_idx,
+               vintage: ("", classifier),
+               seen: HashSet::new(),
+               stats: TransformStats::default(),
+           })
+    }
+
+    /// The headers of our input.
+    pub(crate) fn input_headers(&self) -> &csv::StringRecord {
+        &self.headers
+    }
+
+    /// The header row we should write, if any.
+    pub(crate) fn output_headers(&self) -> Option<&csv::StringRecord> {
+        if !self.options.no_header || self.options.synthesize_header {
+            Some(&self.out_headers)
+        } else {
+            None
+        }
+    }
+
+    /// Transform `row`, returning the row we should write, or why it should
+    /// be skipped. If it should be skipped, `row` is left untouched.
+    pub(crate) fn transform_row<'r>(&'r mut self,
+                                    row: &'r mut csv::ByteRecord)
+                                    -> Result<Transformed<'r>> {
+        if row.len() != self.headers.len() {
+            match self.options.dialect.on_ragged {
+                RaggedRows::Error => {
+                    return Err(Error::ragged_row(row.len(),
+                                                 self.headers.len(),
+                                                 row.position()));
+                }
+                RaggedRows::Pad => {
+                    row.truncate(self.headers.len());
+                    while row.len() < self.headers.len() {
+                        row.push_field(b"");
+                    }
+                }
+                RaggedRows::Skip => {
+                    self.stats.skipped_rows += 1;
+                    return Ok(Transformed::Ragged);
+                }
+            }
+        }
+        if let (Some(idx), Some(vintages)) =
+            (self.date_col_idx, self.options.vintages.as_ref()) {
+            let date = from_utf8(&row[idx]).unwrap_or("");
+            self.vintage = vintages.for_date(date);
+        }
+        let classifier = self.vintage.1;
+        self.chunks.clear();
+        self.zip_info.clear();
+        let mut invalid = false;
+        let mut new_zip = false;
+        let mut fallback = false;
+        let mut nearest = false;
+        for &zip_col_idx in &self.zip_col_idxs {
+            let input = from_utf8(&row[zip_col_idx])
+                .chain_err(|| Error::non_utf8_zip(row.position()))?;
+            let (zip, point) = match (self.options.points.as_ref(), self.lng_col_idx) {
+                (Some(points), Some(lng_col_idx)) => {
+                    let lng = from_utf8(&row[lng_col_idx]).unwrap_or("");
+                    let found = parse_number(input)
+                        .zip(parse_number(lng))
+                        .and_then(|(lat, lng)| {
+                                      points.locate(lat, lng, self.options.nearest_within)
+                                  });
+                    let point = if input.is_empty() && lng.is_empty() {
+                        String::new()
+                    } else {
+                        format!("{},{}", input, lng)
+                    };
+                    match found {
+                        Some(found) => {
+                            nearest |= found.nearest;
+                            (found.zip, point)
+                        }
+                        None => ("", point),
+                    }
+                }
+                _ => (input, String::new()),
+            };
+            let found = classifier.prefix_and_chunk_for(zip);
+            if found.is_none() && !(zip.is_empty() && point.is_empty()) {
+                if self.options.strict {
+                    let value = if point.is_empty() { zip } else { &point };
+                    return Err(Error::invalid_zip(value,
+                                                  &self.headers[zip_col_idx],
+                                                  row.position()));
+                }
+                invalid = true;
+            }
+            if self.options.flag_new_zips && classifier.is_new_zip(zip) {
+                new_zip = true;
+            }
+            if self.options.flag_fallback_zips &&
+               found.is_some_and(|(prefix, _)| classifier.is_fallback_prefix(prefix)) {
+                fallback = true;
+            }
+            let mut info = ZipInfo {
+                nearest,
+                ..ZipInfo::default()
+            };
+            if self.options.add_density || self.options.add_urbanicity {
+                info.density = classifier.density_for(zip);
+            }
+            if self.options.add_time_zone {
+                info.time_zone = time_zone_for_zip(zip);
+            }
+            if let Some(ref dmas) = self.options.dmas {
+                info.dma = dmas.dma_for_zip(zip);
+            }
+            if let Some(ref districts) = self.options.congressional_districts {
+                info.congressional_district = districts.district_for_zip(zip);
+            }
+            if let Some(ref districts) = self.options.legislative_districts {
+                info.legislative_district = districts.district_for_zip(zip);
+            }
+            self.zip_info.push(info);
+            self.chunks.push(match found {
+                                 Some((_, chunk)) if self.options
+                                                         .suppressed_chunks
+                                                         .contains(chunk) => None,
+                                 found => found,
+                             });
+        }
+        if let Some(idx) = self.dedupe_col_idx {
+            let key = &row[idx];
+            if !key.is_empty() {
+                let chunk = if self.options.dedupe_within_chunk {
+                    self.first_chunk()
+                } else {
+                    None
+                };
+                if !self.seen.insert((chunk, key.to_owned())) {
+                    self.stats.duplicate_rows += 1;
+                    return Ok(Transformed::Duplicate);
+                }
+            }
+        }
+        self.stats.rows += 1;
+        if invalid {
+            self.stats.invalid_rows += 1;
+        }
+        if new_zip {
+            self.stats.new_zip_rows += 1;
+        }
+        if fallback {
+            self.stats.fallback_rows += 1;
+        }
+        if nearest {
+            self.stats.nearest_rows += 1;
+        }
+        if self.append_only {
+            for &field in &self.fields[self.headers.len()..] {
+                push_field(row,
+                           None,
+                           field,
+                           &self.chunks,
+                           &self.zip_info,
+                           self.vintage,
+                           self.options);
+            }
+            Ok(Transformed::Row(row))
+        } else {
+            self.out_row.clear();
+            for &field in &self.fields {
+                push_field(&mut self.out_row,
+                           Some(row),
+                           field,
+                           &self.chunks,
+                           &self.zip_info,
+                           self.vintage,
+                           self.options);
+            }
+            Ok(Transformed::Row(&self.out_row))
+        }
+    }
+
+    /// The geochunk of the first zip code column in the last row we
+    /// transformed, unless it was suppressed.
+    pub(crate) fn first_chunk(&self) -> Option<&'a str> {
+        self.chunks.first().and_then(|c| c.map(|(_, chunk)| chunk))
+    }
+
+    /// Statistics about the rows we've transformed so far.
+    pub(crate) fn stats(&self) -> TransformStats {
+        self.stats
+    }
+}
+
+/// What we know about a zip code besides its chunk, for the columns which
+/// describe the zip code itself. Fields we aren't outputting are `None`.
+#[derive(Clone, Copy, Debug, Default)]
+struct ZipInfo<'a> {
+    /// The zip code's population density.
+    density: Option<f64>,
+    /// The zip code's time zone.
+    time_zone: Option<&'static str>,
+    /// The zip code's designated market area.
+    dma: Option<&'a str>,
+    /// The zip code's congressional district.
+    congressional_district: Option<&'a str>,
+    /// The zip code's state legislative district.
+    legislative_district: Option<&'a str>,
+    /// Was the zip code the nearest one to a point outside every ZCTA?
+    nearest: bool,
+}
+
+/// Push a field onto `out`, given our input row (if we need it), the prefix
+/// and chunk ID for each of our zip code columns along with what else we
+/// know about their zip codes, and the vintage and classifier used to find
+/// them.
+fn push_field(out: &mut csv::ByteRecord,
+              row: Option<&csv::ByteRecord>,
+              field: OutputField,
+              chunks: &[Option<(&str, &str)>],
+              zip_info: &[ZipInfo<'_>],
+              (vintage, classifier): (&str, &Classifier),
+              options: &TransformOptions) {
+    match field {
+        OutputField::Input(i) => {
+            out.push_field(&row.expect("need input row to copy fields")[i])
+        }
+        // If there's no chunk, just output the empty string, which is
+        // as CSV null.
+        OutputField::Chunk(zip_idx, _) if chunks[zip_idx].is_none() => {
+            out.push_field(b"")
+        }
+        OutputField::Chunk(zip_idx, kind) => {
+            let (prefix, chunk) = chunks[zip_idx].expect("checked above");
+            match kind {
+                ChunkField::Id if options.chunk_as_int => {
+                    let idx = classifier
+                        .chunk_index(chunk)
+                        .expect("all chunks should have an index");
+                    push_int_field(out, idx as u64)
+                }
+                ChunkField::Id => out.push_field(chunk.as_bytes()),
+                ChunkField::Prefix => out.push_field(prefix.as_bytes()),
+                ChunkField::Population => {
+                    let pop = classifier
+                        .chunk_population(chunk)
+                        .expect("all chunks should have a population");
+                    push_int_field(out, pop)
+                }
+                ChunkField::Method => {
+                    let method = classifier.assignment_method_for(prefix, chunk);
+                    out.push_field(method.name().as_bytes())
+                }
+                ChunkField::Density => {
+                    match zip_info[zip_idx].density {
+                        Some(density) => out.push_field(format!("{:.1}", density).as_bytes()),
+                        None => out.push_field(b""),
+                    }
+                }
+                ChunkField::Urbanicity => {
+                    match zip_info[zip_idx].density {
+                        Some(density) => {
+                            out.push_field(Urbanicity::for_density(density).name().as_bytes())
+                        }
+                        None => out.push_field(b""),
+                    }
+                }
+                ChunkField::TimeZone => {
+                    let time_zone = zip_info[zip_idx].time_zone.unwrap_or("");
+                    out.push_field(time_zone.as_bytes())
+                }
+                ChunkField::Dma => {
+                    let dma = zip_info[zip_idx].dma.unwrap_or("");
+                    out.push_field(dma.as_bytes())
+                }
+                ChunkField::CongressionalDistrict => {
+                    let district = zip_info[zip_idx].congressional_district.unwrap_or("");
+                    out.push_field(district.as_bytes())
+                }
+                ChunkField::LegislativeDistrict => {
+                    let district = zip_info[zip_idx].legislative_district.unwrap_or("");
+                    out.push_field(district.as_bytes())
+                }
+                ChunkField::Territory => {
+                    let territory = options
+                        .territories
+                        .as_ref()
+                        .and_then(|t| t.territory_for_chunk(chunk))
+                        .unwrap_or("");
+                    out.push_field(territory.as_bytes())
+                }
+                ChunkField::Arm => {
+                    let arm = options
+                        .arms
+                        .as_ref()
+                        .and_then(|a| a.arm_for_chunk(chunk))
+                        .unwrap_or("");
+                    out.push_field(arm.as_bytes())
+                }
+                ChunkField::NewZip => {
+                    let is_new = classifier.is_new_prefix(prefix);
+                    out.push_field(if is_new { b"true" } else { b"false" })
+                }
+                ChunkField::Nearest => {
+                    let nearest = zip_info[zip_idx].nearest;
+                    out.push_field(if nearest { b"true" } else { b"false" })
+                }
+                ChunkField::Fallback => {
+                    let is_fallback = classifier.is_fallback_prefix(prefix);
+                    out.push_field(if is_fallback { b"true" } else { b"false" })
+                }
+                ChunkField::Vintage => out.push_field(vintage.as_bytes()),
+            }
+        }
+    }
+}
+
+/// Append `n` to `out`, without allocating a string for it, since we may
+/// do this for every row.
+fn push_int_field(out: &mut csv::ByteRecord, n: u64) {
+    let mut buf = [0; 20];
+    let mut unused = &mut buf[..];
+    write!(unused, "{}", n).expect("a u64 should fit in 20 bytes");
+    let len = 20 - unused.len();
+    out.push_field(&buf[..len]);
+}
+
+#[test]
+fn pushes_integer_fields() {
+    let _ = env_logger::init();
+    let mut out = csv::ByteRecord::new();
+    push_int_field(&mut out, 0);
+    push_int_field(&mut out, u64::MAX);
+    assert_eq!(out, vec!["0", "18446744073709551615"]);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn replaces_zip_column_with_chunk() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let options = TransformOptions {
+        replace: true,
+        ..TransformOptions::default()
+    };
+    let input = "name,postcode,state\nJ. Doe,90210,CA\nH. Smith,,\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["postcode"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "name,geochunk_zip2010_250000,state\nJ. Doe,902_0,CA\nH. Smith,,\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn drops_columns_and_suppresses_chunks() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut options = TransformOptions::default();
+    options.drop_columns.push("city".to_owned());
+    options.suppressed_chunks.insert("077_0".to_owned());
+    let input = "city,zip\nBeverly Hills,90210\nAsbury Park,07712\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "zip,geochunk_zip2010_250000\n90210,902_0\n07712,\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn adds_a_chunk_column_for_each_zip_column() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let input = "home_zip,work_zip\n90210,07712\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["home_zip", "work_zip"],
+                  &TransformOptions::default(),
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "home_zip,work_zip,home_zip_geochunk_zip2010_250000,\
+                work_zip_geochunk_zip2010_250000\n90210,07712,902_0,077_0\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn names_and_positions_chunk_column() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let input = "name,zip,state\nJ. Doe,90210,CA\n";
+    let transform = |position| {
+        let options = TransformOptions {
+            output_column: Some("chunk".to_owned()),
+            position,
+            ..TransformOptions::default()
+        };
+        let mut output = vec![];
+        transform_csv(&classifier,
+                      &["zip"],
+                      &options,
+                      &mut input.as_bytes(),
+                      &mut output)
+                .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    assert_eq!(transform(ChunkPosition::Prepend),
+               "chunk,name,zip,state\n902_0,J. Doe,90210,CA\n");
+    assert_eq!(transform(ChunkPosition::InsertAfter("zip".to_owned())),
+               "name,zip,chunk,state\nJ. Doe,90210,902_0,CA\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn adds_prefix_and_population_columns() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let options = TransformOptions {
+        add_prefix: true,
+        add_chunk_population: true,
+        ..TransformOptions::default()
+    };
+    let input = "name,zip\nA,01000\nB,\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "name,zip,geochunk_zip2010_250000,geochunk_zip2010_250000_prefix,\
+                geochunk_zip2010_250000_population\nA,01000,010_0,0100,210857\n\
+                B,,,,\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn handles_files_without_headers() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let transform = |synthesize_header| {
+        let options = TransformOptions {
+            no_header: true,
+            synthesize_header,
+            ..TransformOptions::default()
+        };
+        let mut output = vec![];
+        transform_csv(&classifier,
+                      &["2"],
+                      &options,
+                      &mut "J. Doe,90210\n".as_bytes(),
+                      &mut output)
+                .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    assert_eq!(transform(false), "J. Doe,90210,902_0\n");
+    assert_eq!(transform(true),
+               "column_1,column_2,geochunk_zip2010_250000\nJ. Doe,90210,902_0\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn preserves_input_dialect() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let options = TransformOptions {
+        dialect: Dialect {
+            delimiter: b';',
+            comment: Some(b'#'),
+            on_ragged: RaggedRows::Pad,
+            ..Dialect::default()
+        },
+        ..TransformOptions::default()
+    };
+    let input = "# Exported data\nname;zip\n\"Doe; J.\";90210;\nH. Smith\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "name;zip;geochunk_zip2010_250000\n\"Doe; J.\";90210;902_0\nH. Smith;;\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn strict_mode_rejects_invalid_zips() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let options = TransformOptions {
+        strict: true,
+        ..TransformOptions::default()
+    };
+    let input = "name,zip\nJ. Doe,90210\nH. Smith,\nA. Jones,N/A\n";
+    let mut output = vec![];
+    let err = transform_csv(&classifier,
+                            &["zip"],
+                            &options,
+                            &mut input.as_bytes(),
+                            &mut output)
+            .unwrap_err();
+    assert_eq!(err.to_string(),
+               "Invalid zip code \"N/A\" in column 'zip' at line 4");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn counts_invalid_rows() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let input = "zip\n90210\n\"\"\nN/A\n07712\n";
+    let mut output = vec![];
+    let stats = transform_csv(&classifier,
+                              &["zip"],
+                              &TransformOptions::default(),
+                              &mut input.as_bytes(),
+                              &mut output)
+            .unwrap();
+    assert_eq!(stats,
+               TransformStats {
+                   rows: 4,
+                   invalid_rows: 1,
+                   duplicate_rows: 0,
+                   new_zip_rows: 0,
+                   fallback_rows: 0,
+                   nearest_rows: 0,
+                   skipped_rows: 0,
+               });
+    assert_eq!(stats.invalid_pct(), 25.0);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn skips_ragged_rows() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let options = TransformOptions {
+        dialect: Dialect {
+            on_ragged: RaggedRows::Skip,
+            ..Dialect::default()
+        },
+        ..TransformOptions::default()
+    };
+    let input = "name,zip\nJ. Doe,90210\nH. Smith\nA. Jones,07712,extra\n";
+    let mut output = vec![];
+    let mut rejects = vec![];
+    let stats = transform_csv_with_rejects(&classifier,
+                                           &["zip"],
+                                           &options,
+                                           &mut input.as_bytes(),
+                                           &mut output,
+                                           Some(&mut rejects))
+            .unwrap();
+    assert_eq!(stats.skipped_rows, 2);
+    assert_eq!(from_utf8(&output).unwrap(),
+               "name,zip,geochunk_zip2010_250000\nJ. Doe,90210,902_0\n");
+    assert_eq!(from_utf8(&rejects).unwrap(),
+               "name,zip\nH. Smith\nA. Jones,07712,extra\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn sorts_rows_by_chunk() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let options = TransformOptions {
+        sort_by_chunk: true,
+        ..TransformOptions::default()
+    };
+    let input = "name,zip\nA,90210\nB,\nC,07712\nD,90211\nE,07712\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "name,zip,geochunk_zip2010_250000\n\
+                C,07712,077_0\nE,07712,077_0\n\
+                A,90210,902_0\nD,90211,902_0\n\
+                B,,\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn drops_duplicate_rows() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let input = "email,zip\na@example.com,90210\nb@example.com,90210\n\
+                 a@example.com,07712\n,90210\n,90210\na@example.com,90211\n";
+    let dedupe = |within_chunk: bool| {
+        let options = TransformOptions {
+            dedupe_key: Some("email".to_owned()),
+            dedupe_within_chunk: within_chunk,
+            ..TransformOptions::default()
+        };
+        let mut output = vec![];
+        let stats = transform_csv(&classifier,
+                                  &["zip"],
+                                  &options,
+                                  &mut input.as_bytes(),
+                                  &mut output)
+                .unwrap();
+        (String::from_utf8(output).unwrap(), stats.duplicate_rows)
+    };
+    assert_eq!(dedupe(false),
+               ("email,zip,geochunk_zip2010_250000\n\
+                 a@example.com,90210,902_0\nb@example.com,90210,902_0\n\
+                 ,90210,902_0\n,90210,902_0\n"
+                        .to_owned(),
+                2));
+    assert_eq!(dedupe(true),
+               ("email,zip,geochunk_zip2010_250000\n\
+                 a@example.com,90210,902_0\nb@example.com,90210,902_0\n\
+                 a@example.com,07712,077_0\n,90210,902_0\n,90210,902_0\n"
+                        .to_owned(),
+                1));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn adds_territory_column() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut map = BTreeMap::new();
+    map.insert("West".to_owned(), vec!["902_0".to_owned()]);
+    let options = TransformOptions {
+        territories: Some(Territories::new(&classifier, map).unwrap()),
+        ..TransformOptions::default()
+    };
+    let input = "zip\n90210\n07712\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_territory\n\
+                90210,902_0,West\n07712,077_0,\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn adds_density_column() {
+    use gazetteer::{SQUARE_METERS_PER_SQUARE_MILE, Zcta};
+
+    let _ = env_logger::init();
+    let options = TransformOptions {
+        add_density: true,
+        ..TransformOptions::default()
+    };
+    let input = "zip\n90210\n07712\n";
+    let mut output = vec![];
+    let classifier = Classifier::new(250000);
+    assert!(transform_csv(&classifier,
+                          &["zip"],
+                          &options,
+                          &mut input.as_bytes(),
+                          &mut output)
+                    .is_err());
+
+    let zctas = vec![Zcta {
+                         zip: "90210".to_owned(),
+                         land_area: SQUARE_METERS_PER_SQUARE_MILE as u64 * 10,
+                     }];
+    let classifier = classifier.with_land_areas(&zctas);
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    let density = classifier.population_for("90210").unwrap() as f64 / 10.0;
+    assert_eq!(from_utf8(&output).unwrap(),
+               format!("zip,geochunk_zip2010_250000,geochunk_zip2010_250000_density\n\
+                        90210,902_0,{:.1}\n07712,077_0,\n",
+                       density));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn adds_time_zone_column() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let options = TransformOptions {
+        add_time_zone: true,
+        ..TransformOptions::default()
+    };
+    let input = "zip\n90210\n32501\n32456\nN/A\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_timezone\n\
+                90210,902_0,America/Los_Angeles\n32501,325_0,America/Chicago\n\
+                32456,324_1,America/New_York\nN/A,,\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn adds_dma_column() {
+    use number::Rounding;
+
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let dmas = "zip,dma\n90210,803\n";
+    let options = TransformOptions {
+        dmas: Some(DmaTable::load(&mut dmas.as_bytes(), Rounding::default()).unwrap()),
+        ..TransformOptions::default()
+    };
+    let input = "zip\n90210\n07712\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_dma\n\
+                90210,902_0,803\n07712,077_0,\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn adds_district_columns() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let cds = "zip,cd,res_ratio\n90210,0630,0.2\n90210,0633,0.8\n";
+    let slds = "zip,sldl\n90210,050\n";
+    let options = TransformOptions {
+        congressional_districts: Some(DistrictTable::load(&mut cds.as_bytes()).unwrap()),
+        legislative_districts: Some(DistrictTable::load(&mut slds.as_bytes()).unwrap()),
+        ..TransformOptions::default()
+    };
+    let input = "zip\n90210\n07712\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_cd,\
+                geochunk_zip2010_250000_sld\n90210,902_0,0633,050\n07712,077_0,,\n");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn finds_chunks_for_points() {
+    use gazetteer::{SQUARE_METERS_PER_SQUARE_MILE, ZctaPoint};
+
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let points = vec![ZctaPoint {
+                          zip: "10001".to_owned(),
+                          lat: 40.750633,
+                          lng: -73.997177,
+                          area: 10 * SQUARE_METERS_PER_SQUARE_MILE as u64,
+                      }];
+    let mut options = TransformOptions {
+        points: Some(PointIndex::new(points)),
+        ..TransformOptions::default()
+    };
+    let input = "lat,lng\n40.751,-73.998\n40.75,-73.24\n,\n";
+    let mut output = vec![];
+    let stats = transform_csv(&classifier,
+                              &["lat", "lng"],
+                              &options,
+                              &mut input.as_bytes(),
+                              &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "lat,lng,geochunk_zip2010_250000\n40.751,-73.998,100_0\n40.75,-73.24,\n,,\n");
+    assert_eq!((stats.invalid_rows, stats.nearest_rows), (1, 0));
+
+    options.nearest_within = Some(50.0);
+    output.clear();
+    let stats = transform_csv(&classifier,
+                              &["lat", "lng"],
+                              &options,
+                              &mut input.as_bytes(),
+                              &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "lat,lng,geochunk_zip2010_250000,geochunk_zip2010_250000_nearest\n\
+                40.751,-73.998,100_0,false\n40.75,-73.24,100_0,true\n,,,\n");
+    assert_eq!((stats.invalid_rows, stats.nearest_rows), (0, 1));
+
+    assert!(transform_csv(&classifier,
+                          &["lat"],
+                          &options,
+                          &mut input.as_bytes(),
+                          &mut vec![])
+                    .is_err());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn chooses_vintages_by_date() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let weights = vec![("90210".to_owned(), 300000), ("90211".to_owned(), 300000)];
+    let old = Classifier::with_weights("old", weights, 250000);
+    let vintages = vec![(2000, "old".to_owned(), old),
+                        (2010, "zip2010".to_owned(), classifier.clone())];
+    let options = TransformOptions {
+        vintages: Some(Vintages::new("date", vintages, "zip2010").unwrap()),
+        ..TransformOptions::default()
+    };
+    let input = "date,zip\n2005-01-01,90210\n2005-01-01,07712\n2012-01-01,07712\n,07712\n";
+    let mut output = vec![];
+    transform_csv(&classifier,
+                  &["zip"],
+                  &options,
+                  &mut input.as_bytes(),
+                  &mut output)
+            .unwrap();
+    assert_eq!(from_utf8(&output).unwrap(),
+               "date,zip,geochunk_vintage_250000,geochunk_vintage_250000_vintage\n\
+                2005-01-01,90210,90210,old\n2005-01-01,07712,_0,old\n\
+                2012-01-01,07712,077_0,zip2010\n,07712,077_0,zip2010\n");
+
+    let options = TransformOptions { sort_by_chunk: true, ..options };
+    assert!(transform_csv(&classifier,
+                          &["zip"],
+                          &options,
+                          &mut input.as_bytes(),
+                          &mut vec![])
+                    .is_err());
+}
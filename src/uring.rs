@@ -0,0 +1,512 @@
+//! Reading and writing files using Linux's `io_uring` interface, which lets
+//! us hand the kernel many reads or writes in a single system call. This
+//! helps most when we're writing to thousands of per-chunk files at once.
+//!
+//! We use the `io-uring` crate, and only the two operations we need, which
+//! were added in Linux 5.6. On other systems, or without the `io-uring`
+//! feature, creating a ring fails, and callers should fall back to ordinary
+//! I/O or report the error.
+
+#[cfg(all(test, target_os = "linux", feature = "io-uring"))]
+use env_logger;
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fs::File;
+#[cfg(all(test, target_os = "linux", feature = "io-uring"))]
+use std::fs;
+#[cfg(all(test, target_os = "linux", feature = "io-uring"))]
+use std::env;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+#[cfg(all(test, target_os = "linux", feature = "io-uring"))]
+use std::process;
+use std::rc::Rc;
+
+/// How many operations we hand to the kernel at once.
+const RING_ENTRIES: u32 = 256;
+
+/// How many bytes of writes we queue up before handing them to the kernel.
+const MAX_PENDING_BYTES: usize = 8 * 1024 * 1024;
+
+/// How many bytes we read at once.
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// A pair of queues shared with the kernel: one where we submit operations,
+/// and one where the kernel reports their results.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+struct Ring {
+    /// Our queues.
+    ring: io_uring::IoUring,
+    /// How many operations we've queued whose results we haven't seen.
+    in_flight: usize,
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+impl Ring {
+    /// Create a ring which can hold `entries` operations at once.
+    fn new(entries: u32) -> io::Result<Ring> {
+        Ok(Ring {
+               ring: io_uring::IoUring::new(entries)?,
+               in_flight: 0,
+           })
+    }
+
+    /// How many operations can we have in flight at once?
+    fn capacity(&self) -> usize {
+        self.ring.params().sq_entries() as usize
+    }
+
+    /// Queue a read from `file` at `offset` into `len` bytes at `buf`.
+    ///
+    /// SAFETY: `buf` must stay valid until `complete` or `drain` returns
+    /// this operation's result.
+    unsafe fn push_read(&mut self,
+                        file: &File,
+                        offset: u64,
+                        buf: *mut u8,
+                        len: usize,
+                        user_data: u64)
+                        -> bool {
+        use io_uring::{opcode, types};
+        use std::os::unix::io::AsRawFd;
+
+        let entry = opcode::Read::new(types::Fd(file.as_raw_fd()), buf, io_len(len))
+            .offset(offset)
+            .build()
+            .user_data(user_data);
+        self.push(&entry)
+    }
+
+    /// Queue a write of `len` bytes at `buf` to `file` at `offset`.
+    ///
+    /// SAFETY: `buf` must stay valid until `complete` or `drain` returns
+    /// this operation's result.
+    unsafe fn push_write(&mut self,
+                         file: &File,
+                         offset: u64,
+                         buf: *const u8,
+                         len: usize,
+                         user_data: u64)
+                         -> bool {
+        use io_uring::{opcode, types};
+        use std::os::unix::io::AsRawFd;
+
+        let entry = opcode::Write::new(types::Fd(file.as_raw_fd()), buf, io_len(len))
+            .offset(offset)
+            .build()
+            .user_data(user_data);
+        self.push(&entry)
+    }
+
+    /// Add an operation to our submission queue, returning false if it's
+    /// full. The kernel takes its own reference to the operation's file
+    /// when we submit it, so only its buffer needs to outlive it.
+    unsafe fn push(&mut self, entry: &io_uring::squeue::Entry) -> bool {
+        if self.ring.submission().push(entry).is_err() {
+            return false;
+        }
+        self.in_flight += 1;
+        true
+    }
+
+    /// Submit our queued operations, and wait until `count` operations have
+    /// completed. Returns `(user_data, result)` for each, where negative
+    /// results are error codes.
+    fn complete(&mut self, count: usize) -> io::Result<Vec<(u64, i32)>> {
+        let mut done = Vec::with_capacity(count);
+        loop {
+            match self.ring.submit_and_wait(count - done.len()) {
+                Ok(_) => {}
+                // These just mean "try again", and leave our queues intact.
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted ||
+                                err.kind() == io::ErrorKind::WouldBlock ||
+                                err.raw_os_error() == Some(libc::EBUSY) => {}
+                Err(err) => return Err(err),
+            }
+            for cqe in self.ring.completion().take(count - done.len()) {
+                done.push((cqe.user_data(), cqe.result()));
+                self.in_flight -= 1;
+            }
+            if done.len() >= count {
+                return Ok(done);
+            }
+        }
+    }
+
+    /// Wait for every operation we've queued to finish, ignoring their
+    /// results, so their buffers can be freed.
+    fn drain(&mut self) -> io::Result<()> {
+        let in_flight = self.in_flight;
+        self.complete(in_flight).map(|_| ())
+    }
+}
+
+/// Clamp a buffer length to what a single ring operation can transfer.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn io_len(len: usize) -> u32 {
+    min(len, u32::MAX as usize) as u32
+}
+
+/// A stand-in for `Ring` on systems without `io_uring`, or when we were
+/// built without the `io-uring` feature, which can never be created.
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+enum Ring {}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+impl Ring {
+    fn new(_entries: u32) -> io::Result<Ring> {
+        Err(io::Error::other("io_uring is only available on Linux, with the io-uring \
+                              feature"))
+    }
+
+    fn capacity(&self) -> usize {
+        match *self {}
+    }
+
+    unsafe fn push_read(&mut self, _: &File, _: u64, _: *mut u8, _: usize, _: u64)
+                        -> bool {
+        match *self {}
+    }
+
+    unsafe fn push_write(&mut self, _: &File, _: u64, _: *const u8, _: usize, _: u64)
+                         -> bool {
+        match *self {}
+    }
+
+    fn complete(&mut self, _count: usize) -> io::Result<Vec<(u64, i32)>> {
+        match *self {}
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        match *self {}
+    }
+}
+
+/// Convert the result of a ring operation into a byte count.
+fn op_result(res: i32) -> io::Result<usize> {
+    if res < 0 {
+        Err(io::Error::from_raw_os_error(-res))
+    } else {
+        Ok(res as usize)
+    }
+}
+
+/// A write which we haven't handed to the kernel yet.
+struct PendingWrite {
+    /// The file to write to, which we keep open until we've written to it.
+    file: Rc<File>,
+    /// Where to write in `file`.
+    offset: u64,
+    /// The data to write.
+    data: Vec<u8>,
+    /// How much of `data` has been written.
+    written: usize,
+}
+
+/// Queues up writes to many files, and hands them to the kernel in
+/// batches. Files are written using `RingFile`.
+pub struct RingWriter {
+    /// The ring we use to write.
+    ring: Ring,
+    /// Writes we haven't made yet.
+    pending: Vec<PendingWrite>,
+    /// The index in `pending` of the latest write to each file, using the
+    /// address of its `Rc` as a key.
+    latest_for_file: HashMap<*const File, usize>,
+    /// The number of bytes in `pending`.
+    pending_bytes: usize,
+    /// Buffers from a batch of writes which failed part way through, which
+    /// the kernel may still be reading, and which we keep until it's done.
+    stranded: Vec<Vec<u8>>,
+}
+
+impl RingWriter {
+    /// Create a new `RingWriter`. This fails if `io_uring` is unavailable.
+    pub fn new() -> io::Result<Rc<RefCell<RingWriter>>> {
+        Ok(Rc::new(RefCell::new(RingWriter {
+                                    ring: Ring::new(RING_ENTRIES)?,
+                                    pending: vec![],
+                                    latest_for_file: HashMap::new(),
+                                    pending_bytes: 0,
+                                    stranded: vec![],
+                                })))
+    }
+
+    /// Queue a write of `data` to `file` at `offset`.
+    fn queue(&mut self, file: &Rc<File>, offset: u64, data: &[u8]) -> io::Result<()> {
+        let key = Rc::as_ptr(file);
+        let ends_at = |write: &PendingWrite| write.offset + write.data.len() as u64;
+        match self.latest_for_file.get(&key) {
+            Some(&i) if ends_at(&self.pending[i]) == offset => {
+                self.pending[i].data.extend_from_slice(data);
+            }
+            _ => {
+                self.latest_for_file.insert(key, self.pending.len());
+                self.pending
+                    .push(PendingWrite {
+                              file: file.clone(),
+                              offset,
+                              data: data.to_owned(),
+                              written: 0,
+                          });
+            }
+        }
+        self.pending_bytes += data.len();
+        // Every pending write keeps a file open, so don't let them pile up.
+        if self.pending_bytes >= MAX_PENDING_BYTES ||
+           self.pending.len() >= self.ring.capacity() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write everything we've queued, and close any files which are no
+    /// longer used anywhere else.
+    pub fn flush(&mut self) -> io::Result<()> {
+        // Finish any batch which failed earlier, so its results don't get
+        // mixed up with ours.
+        if !self.stranded.is_empty() {
+            self.ring.drain()?;
+            self.stranded.clear();
+        }
+        let mut pending = mem::take(&mut self.pending);
+        self.latest_for_file.clear();
+        self.pending_bytes = 0;
+        let mut unfinished = (0..pending.len())
+            .filter(|&i| !pending[i].data.is_empty())
+            .collect::<Vec<_>>();
+        let mut result = Ok(());
+        while !unfinished.is_empty() && result.is_ok() {
+            let batch_start = unfinished.len().saturating_sub(self.ring.capacity());
+            let batch = unfinished.split_off(batch_start);
+            for &i in &batch {
+                let write = &pending[i];
+                let rest = &write.data[write.written..];
+                // SAFETY: `pending` outlives this batch, unless `complete`
+                // fails, in which case we move its buffers to `stranded`.
+                let pushed = unsafe {
+                    self.ring
+                        .push_write(&write.file,
+                                    write.offset + write.written as u64,
+                                    rest.as_ptr(),
+                                    rest.len(),
+                                    i as u64)
+                };
+                assert!(pushed, "ring should have room for a full batch");
+            }
+            let done = match self.ring.complete(batch.len()) {
+                Ok(done) => done,
+                Err(err) => {
+                    // The kernel may still be reading from our buffers, but
+                    // it has its own references to our files.
+                    self.stranded = pending.into_iter().map(|w| w.data).collect();
+                    return Err(err);
+                }
+            };
+            for (i, res) in done {
+                let write = &mut pending[i as usize];
+                match op_result(res) {
+                    Ok(0) => result = Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => {
+                        write.written += n;
+                        if write.written < write.data.len() {
+                            unfinished.push(i as usize);
+                        }
+                    }
+                    Err(err) => result = Err(err),
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for RingWriter {
+    /// Write anything still queued. Callers should call `flush` first if
+    /// they want to see errors.
+    fn drop(&mut self) {
+        let _ = self.flush();
+        if !self.stranded.is_empty() && self.ring.drain().is_err() {
+            // The kernel won't tell us when it's done with these, so we
+            // can never free them.
+            for buf in self.stranded.drain(..) {
+                mem::forget(buf);
+            }
+        }
+    }
+}
+
+/// A file written using a shared `RingWriter`. Our writes are queued, and
+/// only reach the file when the `RingWriter` is flushed, which may happen
+/// after we've been dropped.
+pub struct RingFile {
+    /// The file we write to.
+    file: Rc<File>,
+    /// Where our next write goes.
+    offset: u64,
+    /// The writer which makes our writes.
+    writer: Rc<RefCell<RingWriter>>,
+}
+
+impl RingFile {
+    /// Write to `file` starting at `offset`, using `writer`.
+    pub fn new(file: File, offset: u64, writer: &Rc<RefCell<RingWriter>>) -> RingFile {
+        RingFile {
+            file: Rc::new(file),
+            offset,
+            writer: writer.clone(),
+        }
+    }
+}
+
+impl Write for RingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer
+            .borrow_mut()
+            .queue(&self.file, self.offset, buf)?;
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    /// Does nothing, because we write in batches.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads a file using `io_uring`, reading the next block in the background
+/// while the caller works on the current one.
+pub struct RingReader {
+    /// The ring we use to read.
+    ring: Ring,
+    /// The file we read from.
+    file: File,
+    /// The block we're currently returning data from.
+    current: Vec<u8>,
+    /// How much of `current` we've returned.
+    pos: usize,
+    /// The block the kernel is reading into, unless we've reached the end
+    /// of our file.
+    next: Option<Vec<u8>>,
+    /// Where in our file the block after `current` starts.
+    offset: u64,
+}
+
+impl RingReader {
+    /// Start reading `file`. This fails if `io_uring` is unavailable.
+    pub fn new(file: File) -> io::Result<RingReader> {
+        let mut rdr = RingReader {
+            ring: Ring::new(1)?,
+            file,
+            current: vec![],
+            pos: 0,
+            next: None,
+            offset: 0,
+        };
+        rdr.start_read(vec![0; READ_BUFFER_SIZE])?;
+        Ok(rdr)
+    }
+
+    /// Start reading the block at `offset` into `buf`.
+    fn start_read(&mut self, mut buf: Vec<u8>) -> io::Result<()> {
+        // SAFETY: We keep `buf` in `self.next` until the read completes, and
+        // moving a `Vec` doesn't move its contents.
+        let pushed = unsafe {
+            self.ring
+                .push_read(&self.file, self.offset, buf.as_mut_ptr(), buf.len(), 0)
+        };
+        assert!(pushed, "ring should be empty before we start reading");
+        self.next = Some(buf);
+        // Submit our read without waiting for it.
+        self.ring.complete(0)?;
+        Ok(())
+    }
+
+    /// Wait for our background read, and return how many bytes it read.
+    fn finish_read(&mut self) -> io::Result<usize> {
+        let done = self.ring.complete(1)?;
+        op_result(done[0].1)
+    }
+}
+
+impl Read for RingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.current.len() {
+            if self.next.is_none() {
+                return Ok(0);
+            }
+            // If this fails, we keep our buffer, which the kernel may still
+            // be writing to, and wait for it again next time.
+            let read = self.finish_read()?;
+            let mut next = self.next.take().expect("should have a read in flight");
+            next.truncate(read);
+            if next.is_empty() {
+                return Ok(0);
+            }
+            self.offset += next.len() as u64;
+            let mut old = mem::replace(&mut self.current, next);
+            self.pos = 0;
+            old.resize(READ_BUFFER_SIZE, 0);
+            self.start_read(old)?;
+        }
+        let len = min(buf.len(), self.current.len() - self.pos);
+        buf[..len].copy_from_slice(&self.current[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Drop for RingReader {
+    fn drop(&mut self) {
+        if self.next.is_some() && self.ring.drain().is_err() {
+            // The kernel won't tell us when it's done writing to our
+            // buffer, so we can never free it.
+            mem::forget(self.next.take());
+        }
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn reads_and_writes_files() {
+    let _ = env_logger::init();
+    let writer = match RingWriter::new() {
+        Ok(writer) => writer,
+        // Some systems don't allow `io_uring`.
+        Err(_) => return,
+    };
+    let dir = env::temp_dir().join(format!("geochunk-uring-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let paths = (0..300)
+        .map(|i| dir.join(format!("{}.txt", i)))
+        .collect::<Vec<_>>();
+    let mut files = paths
+        .iter()
+        .map(|p| RingFile::new(File::create(p).unwrap(), 0, &writer))
+        .collect::<Vec<_>>();
+    for (i, file) in files.iter_mut().enumerate() {
+        writeln!(file, "file {}", i).unwrap();
+    }
+    // Reopen a file we've dropped, and keep appending to it.
+    files.truncate(1);
+    let file = File::options().write(true).open(&paths[1]).unwrap();
+    let mut reopened = RingFile::new(file, 7, &writer);
+    writeln!(files[0], "more").unwrap();
+    writeln!(reopened, "more").unwrap();
+    writer.borrow_mut().flush().unwrap();
+    assert_eq!(fs::read_to_string(&paths[0]).unwrap(), "file 0\nmore\n");
+    assert_eq!(fs::read_to_string(&paths[1]).unwrap(), "file 1\nmore\n");
+    assert_eq!(fs::read_to_string(&paths[299]).unwrap(), "file 299\n");
+
+    let data = (0..READ_BUFFER_SIZE * 2 + 10)
+        .map(|i| i as u8)
+        .collect::<Vec<_>>();
+    fs::write(dir.join("big.bin"), &data).unwrap();
+    let mut rdr = RingReader::new(File::open(dir.join("big.bin")).unwrap()).unwrap();
+    let mut read = vec![];
+    rdr.read_to_end(&mut read).unwrap();
+    assert!(read == data);
+    fs::remove_dir_all(&dir).unwrap();
+}
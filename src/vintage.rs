@@ -0,0 +1,138 @@
+//! Choosing a population vintage for each row, based on a date column, so
+//! that data sets mixing records from many years can be chunked in a single
+//! pass, using the population of each record's own time.
+//!
+//! Chunk IDs from different vintages are unrelated, even when they look the
+//! same, so we also output the name of the vintage we used for each row.
+
+#[cfg(test)]
+use env_logger;
+use std::fmt;
+
+use errors::*;
+use zip2010::Classifier;
+
+/// A classifier for each of several population vintages, and the column
+/// containing the date of each row.
+#[derive(Clone)]
+pub struct Vintages {
+    /// The column containing the date of each row.
+    column: String,
+    /// Our vintages, as `(year, name, classifier)`, sorted by year.
+    vintages: Vec<(u16, String, Classifier)>,
+    /// The index of the vintage to use for rows without a usable date.
+    default_idx: usize,
+}
+
+impl Vintages {
+    /// Choose between `vintages`, listed as `(year, name, classifier)`,
+    /// using the dates in `column`. Rows without a usable date use the
+    /// vintage named `default`. All our classifiers must use the same
+    /// target population.
+    pub fn new(column: &str,
+               mut vintages: Vec<(u16, String, Classifier)>,
+               default: &str)
+               -> Result<Vintages> {
+        vintages.sort_by_key(|&(year, _, _)| year);
+        if vintages.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err("cannot use two population vintages from the same year".into());
+        }
+        let target = |v: &(u16, String, Classifier)| v.2.metadata().target_population;
+        if vintages.windows(2).any(|w| target(&w[0]) != target(&w[1])) {
+            return Err("all population vintages must use the same target population"
+                           .into());
+        }
+        let default_idx = vintages
+            .iter()
+            .position(|(_, name, _)| name == default)
+            .ok_or_else(|| format!("no population vintage named {}", default))?;
+        Ok(Vintages {
+               column: column.to_owned(),
+               vintages,
+               default_idx,
+           })
+    }
+
+    /// The column containing the date of each row.
+    pub fn column(&self) -> &str {
+        &self.column
+    }
+
+    /// The name of our geochunk column, like `geochunk_vintage_250000`.
+    pub fn geochunk_column_name(&self) -> String {
+        let metadata = self.vintages[self.default_idx].2.metadata();
+        format!("geochunk_vintage_{}", metadata.target_population)
+    }
+
+    /// The name and classifier of the vintage to use for a row dated `date`.
+    /// We use the latest vintage from no later than the year of `date`, or
+    /// our earliest vintage for older dates.
+    pub fn for_date(&self, date: &str) -> (&str, &Classifier) {
+        let idx = match parse_year(date) {
+            Some(year) => {
+                self.vintages
+                    .iter()
+                    .rposition(|&(y, _, _)| y <= year)
+                    .unwrap_or(0)
+            }
+            None => self.default_idx,
+        };
+        let (_, ref name, ref classifier) = self.vintages[idx];
+        (name, classifier)
+    }
+}
+
+impl fmt::Debug for Vintages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.vintages
+            .iter()
+            .map(|(_, name, _)| &name[..])
+            .collect::<Vec<_>>();
+        f.debug_struct("Vintages")
+            .field("column", &self.column)
+            .field("vintages", &names)
+            .field("default", &names[self.default_idx])
+            .finish()
+    }
+}
+
+/// Find the year in a date like `2015-06-01`, `6/1/2015` or `2015`, by
+/// looking for the first run of exactly four digits.
+fn parse_year(date: &str) -> Option<u16> {
+    date.split(|c: char| !c.is_ascii_digit())
+        .find(|part| part.len() == 4)
+        .and_then(|part| part.parse().ok())
+}
+
+#[test]
+fn parses_years_from_dates() {
+    let _ = env_logger::init();
+    assert_eq!(parse_year("2015-06-01"), Some(2015));
+    assert_eq!(parse_year("6/1/2015"), Some(2015));
+    assert_eq!(parse_year("2003"), Some(2003));
+    assert_eq!(parse_year("6/1/15"), None);
+    assert_eq!(parse_year(""), None);
+}
+
+#[test]
+fn picks_vintages_by_date() {
+    let _ = env_logger::init();
+    let classifier =
+        |name: &str| Classifier::with_weights(name, vec![("90210".to_owned(), 1)], 250000);
+    let vintages = Vintages::new("event_date",
+                                 vec![(2010, "zip2010".to_owned(), classifier("zip2010")),
+                                      (2000, "zip2000".to_owned(), classifier("zip2000"))],
+                                 "zip2010")
+            .unwrap();
+    assert_eq!(vintages.geochunk_column_name(), "geochunk_vintage_250000");
+    assert_eq!(vintages.for_date("1995-01-01").0, "zip2000");
+    assert_eq!(vintages.for_date("2009-12-31").0, "zip2000");
+    assert_eq!(vintages.for_date("2010-01-01").0, "zip2010");
+    assert_eq!(vintages.for_date("2021-01-01").0, "zip2010");
+    assert_eq!(vintages.for_date("unknown").0, "zip2010");
+
+    assert!(Vintages::new("event_date",
+                          vec![(2010, "zip2010".to_owned(), classifier("zip2010"))],
+                          "zip2000")
+                    .is_err());
+}
@@ -1,53 +1,1203 @@
 //! Support for chunks based on 2010 census population data.
 
+#[cfg(feature = "dictionary")]
+use arrow_array::types::UInt32Type;
+#[cfg(feature = "dictionary")]
+use arrow_array::{DictionaryArray, StringArray, UInt32Array};
 use csv;
 #[cfg(test)]
 use env_logger;
-use regex::Regex;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::cmp::Reverse;
 use std::collections::hash_map::Entry;
 use std::default::Default;
+use std::fmt;
 use std::io::prelude::*;
-use std::str::from_utf8;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 
+use density::DensityClasses;
 use errors::*;
+use gazetteer::{SQUARE_METERS_PER_SQUARE_MILE, Zcta, ZctaPoint};
+use id_template::{ChunkVars, IdTemplate};
+use noise::LaplaceNoise;
+use points::distance_miles;
+use states::{StateClasses, state_for_zip};
+use transform::{self, TransformOptions};
+use zip_patch::{PatchInfo, ZipPatch};
 
 /// The length of a basic zip code, in digits.
 const ZIP_CODE_LENGTH: usize = 5;
 
-/// Classifies Zip codes into geochunks based on 2010 census population data.
+/// The number of basic zip codes, from `00000` to `99999`.
+const ZIP_CODE_COUNT: usize = 100_000;
+
+/// An entry in `ClassifierData::zip_table` for a zip code without a chunk.
+const NO_CHUNK: u32 = u32::MAX;
+
+/// The version of our chunking algorithm and built-in data. For any given
+/// version, weights and target population, every zip code will always be
+/// assigned to the same chunk. If we ever need to change that, we'll bump
+/// this version.
+pub const ALGORITHM_VERSION: &str = "v1";
+
+/// Metadata describing how a set of chunks was built, so that stored chunk
+/// IDs can be traced back to the parameters which produced them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// The value of `ALGORITHM_VERSION` used to build these chunks.
+    pub version: String,
+    /// The name of the column containing chunk IDs.
+    pub column: String,
+    /// The name of the data used to weight each zip code.
+    pub weights: String,
+    /// The approximate population we want in each chunk.
+    pub target_population: u64,
+    /// The smallest population we allowed in a chunk of leftover prefixes,
+    /// if any. See `Classifier::from_prefix_population_with_minimum`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_population: Option<u64>,
+    /// Information about the data used to weight each zip code: our
+    /// built-in census data, or the file our weights were loaded from, if
+    /// we know it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub census_data: Option<DataInfo>,
+    /// Manual changes made to these chunks after they were built.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adjustments: Vec<Adjustment>,
+    /// A summary of the patch used to add new zip codes, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zip_patch: Option<PatchInfo>,
+}
+
+/// A record of how a set of chunks was built, for verifying that two
+/// environments built identical chunks, or for auditing how zip codes were
+/// grouped.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstructionLog {
+    /// Metadata describing the chunks we built.
+    pub metadata: Metadata,
+    /// Every decision we made, in order.
+    pub steps: Vec<ConstructionStep>,
+}
+
+/// A decision made while building chunks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum ConstructionStep {
+    /// `prefix` was small enough, or long enough, to become a chunk of its
+    /// own.
+    Chunk { prefix: String, population: u64 },
+    /// `prefix` was too large, so we looked at each of its children.
+    Split { prefix: String, population: u64 },
+    /// `prefix` was a leftover child which was packed into `chunk`.
+    Pack {
+        prefix: String,
+        population: u64,
+        chunk: String,
+    },
+}
+
+/// Provenance information about the data used to weight each zip code,
+/// such as the census data built into this library.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataInfo {
+    /// Where this data came from, such as the path of the file we loaded.
+    pub source: String,
+    /// The year of the census, if we know it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vintage: Option<u16>,
+    /// The number of zip codes in the data.
+    pub rows: u64,
+    /// The total population of all zip codes, or their total weight.
+    pub total_population: u64,
+    /// The SHA-256 checksum of the CSV file the data came from, in hex.
+    pub sha256: String,
+}
+
+impl DataInfo {
+    /// Describe the census data built into this library.
+    #[cfg(feature = "data-zip2010")]
+    pub fn census2010() -> DataInfo {
+        let mut rows = 0;
+        let mut total_population = 0;
+        for (_, pop) in census2010_rows() {
+            rows += 1;
+            total_population += pop;
+        }
+        let sha256 = CENSUS2010_SHA256.to_owned();
+        DataInfo {
+            source: "U.S. Census Bureau, 2010 Census population by ZIP Code \
+                     Tabulation Area (ZCTA)"
+                    .to_owned(),
+            vintage: Some(2010),
+            rows,
+            total_population,
+            sha256,
+        }
+    }
+
+    /// Describe the weights in `prefix_population`, which were loaded from
+    /// the file at `path` containing `contents`.
+    pub fn for_file(path: &str,
+                    contents: &[u8],
+                    prefix_population: &PrefixPopulation)
+                    -> DataInfo {
+        let sha256 = Sha256::digest(contents)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        DataInfo {
+            source: path.to_owned(),
+            vintage: None,
+            rows: prefix_population.maps[ZIP_CODE_LENGTH].len() as u64,
+            total_population: prefix_population.lookup(""),
+            sha256,
+        }
+    }
+
+    /// Describe this data on lines starting with `comment`, such as `#` or
+    /// `--`, so that we can embed it in exported files.
+    pub fn write_comments(&self, comment: &str, out: &mut dyn Write) -> Result<()> {
+        writeln!(out, "{} Weight data: {}", comment, self.source)?;
+        if let Some(vintage) = self.vintage {
+            writeln!(out, "{} Weight data vintage: {}", comment, vintage)?;
+        }
+        writeln!(out, "{} Weight data rows: {}", comment, self.rows)?;
+        writeln!(out,
+                 "{} Weight data total: {}",
+                 comment,
+                 self.total_population)?;
+        writeln!(out, "{} Weight data SHA-256: {}", comment, self.sha256)?;
+        Ok(())
+    }
+}
+
+/// Classifies Zip codes into geochunks based on 2010 census population data,
+/// or on some other per-zip weight supplied by the caller.
+///
+/// The chunk mapping is shared behind an `Arc`, so cloning a classifier is
+/// cheap, and classifiers are `Send + Sync`, so a single classifier can be
+/// shared between threads or async tasks.
+#[derive(Clone)]
 pub struct Classifier {
+    /// Our chunk mapping, which never changes once it has been built.
+    data: Arc<ClassifierData>,
+}
+
+/// The data used by a `Classifier`.
+#[derive(Clone)]
+struct ClassifierData {
+    /// The name of the data set used to weight each zip code.
+    weight_name: String,
+    /// Where our weights came from, if we know. See
+    /// `Classifier::with_data_info`.
+    census_data: Option<DataInfo>,
     /// The approximate number of people we want to put in each chunk.
     target_population: u64,
+    /// The smallest population we allowed in a chunk of leftover prefixes,
+    /// if any.
+    min_population: Option<u64>,
     /// Map from zip code prefixes to chunk IDs.
     chunk_id_for_prefix: HashMap<String, String>,
+    /// Map from chunk IDs to the total weight of the zip codes in each.
+    population_for_chunk: HashMap<String, u64>,
+    /// All our chunk IDs, in sorted order.
+    chunk_ids: Vec<String>,
+    /// Map from chunk IDs to their index in `chunk_ids`.
+    index_for_chunk: HashMap<String, usize>,
+    /// The population of every zip code prefix.
+    prefix_population: PrefixPopulation,
+    /// The land area of every zip code prefix, in square meters, if we've
+    /// been given one. See `Classifier::with_land_areas`.
+    land_area: Option<PrefixPopulation>,
+    /// The internal point of each ZCTA, if we've been given them to use
+    /// instead of our built-in zip code locations. See
+    /// `Classifier::with_centroids`.
+    zcta_points: Option<Vec<ZctaPoint>>,
+    /// The chunk to use for zip codes we can't classify, if any.
+    unknown_chunk: Option<String>,
+    /// The chunk to use for zip codes whose prefix has no population, if
+    /// any. See `Classifier::is_new_zip`.
+    new_zip_chunk: Option<String>,
+    /// Should zip codes whose prefix has no population use the most
+    /// populous chunk sharing their first three digits? See
+    /// `Classifier::with_new_zip_fallback`.
+    new_zip_fallback: bool,
+    /// A summary of the patch used to add new zip codes, if any.
+    zip_patch: Option<PatchInfo>,
+    /// The zip codes added by our patch.
+    patched_zips: HashSet<String>,
+    /// Manual changes made to our chunks using a `ChunkPlan`.
+    adjustments: Vec<Adjustment>,
+    /// For each 5-digit zip code, the index of its chunk shifted left by 3
+    /// bits, plus the length of the prefix used to find it, or `NO_CHUNK`.
+    /// This is built the first time we need it, and cleared by `data_mut`.
+    zip_table: OnceLock<Vec<u32>>,
+    /// For `new_zip_fallback`, the index of the most populous chunk
+    /// containing zip codes with each 3-digit prefix. This is built the
+    /// first time we need it, and cleared by `data_mut`.
+    fallback_table: OnceLock<HashMap<String, usize>>,
+    /// The centroid of each chunk, indexed like `chunk_ids`. This is built
+    /// the first time we need it, and cleared by `data_mut`.
+    centroid_table: OnceLock<Vec<Option<(f64, f64)>>>,
+}
+
+impl ClassifierData {
+    /// Is `chunk_id` our unknown chunk or our new zip chunk, which don't
+    /// contain any prefixes?
+    fn is_extra_chunk(&self, chunk_id: &str) -> bool {
+        self.unknown_chunk.as_ref().is_some_and(|c| c == chunk_id) ||
+        self.new_zip_chunk.as_ref().is_some_and(|c| c == chunk_id)
+    }
+}
+
+/// Get a shared classifier for the 2010 census with the specified target
+/// population, building it the first time it's requested. These classifiers
+/// are never freed, so this is meant for a handful of target populations
+/// which are used for the life of the program. Use `cached_classifier` if
+/// the target population comes from your users.
+#[cfg(feature = "data-zip2010")]
+pub fn classifier(target_population: u64) -> &'static Classifier {
+    use std::sync::Mutex;
+
+    lazy_static! {
+        /// Our shared classifiers, by target population.
+        static ref CLASSIFIERS: Mutex<HashMap<u64, &'static Classifier>> =
+            Mutex::new(HashMap::new());
+    }
+    let mut classifiers = CLASSIFIERS.lock().expect("classifier cache lock poisoned");
+    classifiers
+        .entry(target_population)
+        .or_insert_with(|| Box::leak(Box::new(Classifier::new(target_population))))
+}
+
+/// How many classifiers `cached_classifier` keeps around.
+#[cfg(feature = "data-zip2010")]
+pub const CACHED_CLASSIFIERS: usize = 8;
+
+/// Get a classifier for the 2010 census with the specified target
+/// population, sharing its data with other recent requests for the same
+/// target population. Unlike `classifier`, we only keep the
+/// `CACHED_CLASSIFIERS` most recently used target populations, and forget
+/// the rest, whose data is freed once every clone of it is dropped. This is
+/// meant for bindings and servers, which build classifiers for whatever
+/// target populations their callers ask for.
+#[cfg(feature = "data-zip2010")]
+pub fn cached_classifier(target_population: u64) -> Classifier {
+    use std::sync::Mutex;
+
+    lazy_static! {
+        /// Our cached classifiers, from least to most recently used.
+        static ref CLASSIFIERS: Mutex<Vec<(u64, Classifier)>> = Mutex::new(vec![]);
+    }
+    let mut classifiers = CLASSIFIERS.lock().expect("classifier cache lock poisoned");
+    let entry = match classifiers
+              .iter()
+              .position(|&(population, _)| population == target_population) {
+        Some(i) => classifiers.remove(i),
+        None => (target_population, Classifier::new(target_population)),
+    };
+    let classifier = entry.1.clone();
+    classifiers.push(entry);
+    if classifiers.len() > CACHED_CLASSIFIERS {
+        classifiers.remove(0);
+    }
+    classifier
 }
 
 impl Classifier {
     /// Create a new classifier, specifying how many people we'd ideally
     /// want to see in each chunk.
+    #[cfg(feature = "data-zip2010")]
     pub fn new(target_population: u64) -> Classifier {
-        let prefix_population = PrefixPopulation::new();
+        Classifier::from_prefix_population("zip2010",
+                                           &PrefixPopulation::census2010(),
+                                           target_population,
+                                           None)
+                .with_data_info(DataInfo::census2010())
+    }
+
+    /// Create a new classifier using custom per-zip weights instead of
+    /// census population. `weight_name` identifies the weights in our output
+    /// column name, and `target_population` is measured in the same units as
+    /// the weights.
+    pub fn with_weights<I>(weight_name: &str,
+                           weights: I,
+                           target_population: u64)
+                           -> Classifier
+    where
+        I: IntoIterator<Item = (String, u64)>,
+    {
+        Classifier::from_prefix_population(weight_name,
+                                           &PrefixPopulation::from_weights(weights),
+                                           target_population,
+                                           None)
+    }
+
+    /// Build our chunks using the specified prefix populations. If `classes`
+    /// is specified, such as `DensityClasses`, zip code prefixes in
+    /// different classes will never be grouped into the same chunk.
+    pub fn from_prefix_population(weight_name: &str,
+                                  prefix_population: &PrefixPopulation,
+                                  target_population: u64,
+                                  classes: Option<&dyn PrefixClasses>)
+                                  -> Classifier {
+        Classifier::build(weight_name,
+                          prefix_population,
+                          target_population,
+                          0,
+                          classes,
+                          None)
+    }
+
+    /// Like `from_prefix_population`, but also return a log of every
+    /// decision we made while building our chunks. Since chunk construction
+    /// is deterministic, two classifiers with identical logs will always
+    /// assign zip codes to the same chunks.
+    pub fn from_prefix_population_with_log(weight_name: &str,
+                                           prefix_population: &PrefixPopulation,
+                                           target_population: u64,
+                                           classes: Option<&dyn PrefixClasses>)
+                                           -> (Classifier, ConstructionLog) {
+        Classifier::from_prefix_population_with_minimum(weight_name,
+                                                        prefix_population,
+                                                        target_population,
+                                                        0,
+                                                        classes)
+    }
+
+    /// Like `from_prefix_population_with_log`, but merge any chunk of
+    /// leftover prefixes with a population below `min_population` into a
+    /// neighboring chunk split from the same prefix, as long as there's one
+    /// it can join without mixing classes. The merged chunk may be larger
+    /// than `target_population`. A `min_population` of 0 changes nothing.
+    pub fn from_prefix_population_with_minimum(weight_name: &str,
+                                               prefix_population: &PrefixPopulation,
+                                               target_population: u64,
+                                               min_population: u64,
+                                               classes: Option<&dyn PrefixClasses>)
+                                               -> (Classifier, ConstructionLog) {
+        let mut steps = vec![];
+        let classifier = Classifier::build(weight_name,
+                                           prefix_population,
+                                           target_population,
+                                           min_population,
+                                           classes,
+                                           Some(&mut steps));
+        let log = ConstructionLog {
+            metadata: classifier.metadata(),
+            steps,
+        };
+        (classifier, log)
+    }
+
+    /// Build our chunks, recording what we do in `log` if present.
+    fn build(weight_name: &str,
+             prefix_population: &PrefixPopulation,
+             target_population: u64,
+             min_population: u64,
+             classes: Option<&dyn PrefixClasses>,
+             log: Option<&mut Vec<ConstructionStep>>)
+             -> Classifier {
+        trace_span!("build_classifier", weight_name, target_population);
         let mut chunk_id_for_prefix = HashMap::<String, String>::new();
         prefix_population.build_chunks_recursive(target_population,
+                                                 min_population,
                                                  "",
-                                                 &mut chunk_id_for_prefix);
+                                                 classes,
+                                                 &mut chunk_id_for_prefix,
+                                                 log);
+        let population_for_chunk =
+            population_for_chunks(&chunk_id_for_prefix, prefix_population);
+        let mut chunk_ids = population_for_chunk.keys().cloned().collect::<Vec<_>>();
+        chunk_ids.sort();
+        let index_for_chunk = chunk_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.clone(), i))
+            .collect();
+        let weight_name = match classes.and_then(|c| c.name()) {
+            Some(name) => format!("{}_{}", weight_name, name),
+            None => weight_name.to_owned(),
+        };
         Classifier {
-            target_population: target_population,
-            chunk_id_for_prefix: chunk_id_for_prefix,
+            data: Arc::new(ClassifierData {
+                               weight_name,
+                               census_data: None,
+                               target_population,
+                               min_population: Some(min_population).filter(|&m| m > 0),
+                               chunk_id_for_prefix,
+                               population_for_chunk,
+                               chunk_ids,
+                               index_for_chunk,
+                               prefix_population: prefix_population.clone(),
+                               land_area: None,
+                               zcta_points: None,
+                               unknown_chunk: None,
+                               new_zip_chunk: None,
+                               new_zip_fallback: false,
+                               zip_patch: None,
+                               patched_zips: HashSet::new(),
+                               adjustments: vec![],
+                               zip_table: OnceLock::new(),
+                               fallback_table: OnceLock::new(),
+                               centroid_table: OnceLock::new(),
+                           }),
+        }
+    }
+
+    /// Record where our weights came from, for our metadata. Classifiers
+    /// built from our built-in census data do this automatically.
+    pub fn with_data_info(mut self, info: DataInfo) -> Classifier {
+        Arc::make_mut(&mut self.data).census_data = Some(info);
+        self
+    }
+
+    /// Get mutable access to our data, copying it first if it's shared with
+    /// another classifier.
+    fn data_mut(&mut self) -> &mut ClassifierData {
+        let data = Arc::make_mut(&mut self.data);
+        // Our caller may change our chunks, so we need to rebuild these.
+        data.zip_table = OnceLock::new();
+        data.fallback_table = OnceLock::new();
+        data.centroid_table = OnceLock::new();
+        data
+    }
+
+    /// Rename our chunks as specified by `naming`, keeping them in the same
+    /// order. Renaming chunks never changes which zip codes they contain.
+    pub fn with_naming(mut self, naming: &Naming) -> Result<Classifier> {
+        match *naming {
+            Naming::Prefix => {}
+            Naming::Integer => {
+                let index_for_chunk = self.data.index_for_chunk.clone();
+                self.rename_chunks(|chunk_id| index_for_chunk[chunk_id].to_string())?;
+            }
+            Naming::Sortable => {
+                let index_for_chunk = self.data.index_for_chunk.clone();
+                let width = self.data.chunk_ids.len().saturating_sub(1).to_string().len();
+                self.rename_chunks(|chunk_id| {
+                        format!("{:0width$}", index_for_chunk[chunk_id], width = width)
+                    })?;
+            }
+            Naming::Hashed(ref salt) => {
+                let mut prefixes_for_chunk = HashMap::new();
+                // Leave out patched zip codes, so that patching doesn't
+                // rename any chunks.
+                let prefixes = self.data
+                    .chunk_id_for_prefix
+                    .iter()
+                    .filter(|&(prefix, _)| !self.data.patched_zips.contains(prefix));
+                for (prefix, chunk_id) in prefixes {
+                    prefixes_for_chunk
+                        .entry(&chunk_id[..])
+                        .or_insert_with(Vec::new)
+                        .push(&prefix[..]);
+                }
+                let new_names = self.data
+                    .chunk_ids
+                    .iter()
+                    .map(|chunk_id| {
+                        // Keep the names of our unknown and new zip chunks,
+                        // which have no prefixes, so that they're easy to
+                        // recognize.
+                        if self.data.is_extra_chunk(chunk_id) {
+                            return (chunk_id.clone(), chunk_id.clone());
+                        }
+                        let mut prefixes = prefixes_for_chunk
+                            .get(&chunk_id[..])
+                            .cloned()
+                            .unwrap_or_default();
+                        prefixes.sort_unstable();
+                        let mac = hmac_sha256(salt, prefixes.join(",").as_bytes());
+                        let name = mac[..8].iter().map(|b| format!("{:02x}", b)).collect();
+                        (chunk_id.clone(), name)
+                    })
+                    .collect::<HashMap<String, String>>();
+                self.rename_chunks(|chunk_id| new_names[chunk_id].clone())?;
+            }
+            Naming::Template(ref template) => {
+                let states = self.chunk_states();
+                let new_names = self.data
+                    .chunk_ids
+                    .iter()
+                    .enumerate()
+                    .map(|(index, chunk_id)| {
+                        if self.data.is_extra_chunk(chunk_id) {
+                            return (chunk_id.clone(), chunk_id.clone());
+                        }
+                        let vars = ChunkVars {
+                            id: chunk_id,
+                            prefix: chunk_id.rsplit_once('_').map_or(&chunk_id[..], |(p, _)| p),
+                            index,
+                            state: states.get(&chunk_id[..]).cloned().unwrap_or_default(),
+                            vintage: &self.data.weight_name,
+                        };
+                        (chunk_id.clone(), template.render(&vars))
+                    })
+                    .collect::<HashMap<String, String>>();
+                self.rename_chunks(|chunk_id| new_names[chunk_id].clone())
+                    .chain_err(|| format!("chunk ID template {:?} should give every chunk a \
+                                           different ID",
+                                          template.to_string()))?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// The state with the most population in each chunk, looked up using
+    /// the first three digits of its zip codes. Chunks without any
+    /// population use the first state their prefixes belong to.
+    fn chunk_states(&self) -> HashMap<&str, &'static str> {
+        let data = &self.data;
+        let mut populations = HashMap::<&str, BTreeMap<&'static str, u64>>::new();
+        for (prefix, chunk_id) in &data.chunk_id_for_prefix {
+            let mut add = |zip3: &str| {
+                if let Some(state) = state_for_zip(zip3) {
+                    *populations
+                         .entry(chunk_id)
+                         .or_default()
+                         .entry(state)
+                         .or_insert(0) += data.prefix_population.lookup(zip3);
+                }
+            };
+            if prefix.len() >= 3 {
+                add(prefix);
+            } else {
+                // Short prefixes may span several states.
+                let digits = 3 - prefix.len();
+                for n in 0..10u32.pow(digits as u32) {
+                    add(&format!("{}{:0width$}", prefix, n, width = digits));
+                }
+            }
+        }
+        populations
+            .into_iter()
+            .filter_map(|(chunk_id, states)| {
+                states
+                    .into_iter()
+                    .max_by_key(|&(state, pop)| (pop, Reverse(state)))
+                    .map(|(state, _)| (chunk_id, state))
+            })
+            .collect()
+    }
+
+    /// Add the zip codes in `patch` to our chunks. Each zip code is put in
+    /// the chunk of its parent, if it has one, and otherwise stays in the
+    /// chunk its prefix was assigned to. Its estimated population is added
+    /// to that chunk. All other zip codes keep their chunks.
+    pub fn with_zip_patch(mut self, patch: &ZipPatch) -> Result<Classifier> {
+        if self.data.zip_patch.is_some() {
+            return Err("classifier has already been patched".into());
+        }
+        let mut chunks = vec![];
+        for entry in patch.entries() {
+            if self.data.prefix_population.lookup(&entry.zip) > 0 {
+                return Err(format!("zip code {} is already in our data", entry.zip).into());
+            }
+            let zip = entry.parent.as_ref().unwrap_or(&entry.zip);
+            let (_, chunk_id) = self.lookup_prefix_and_chunk(zip)
+                .ok_or_else(|| format!("cannot find a chunk for zip code {}", zip))?;
+            chunks.push(chunk_id.to_owned());
+        }
+        let data = self.data_mut();
+        for (entry, chunk_id) in patch.entries().iter().zip(chunks) {
+            let pop = entry.population.unwrap_or(0);
+            data.prefix_population.add_weight(&entry.zip, pop);
+            *data.population_for_chunk
+                 .get_mut(&chunk_id)
+                 .expect("all chunks should have a population") += pop;
+            data.chunk_id_for_prefix.insert(entry.zip.clone(), chunk_id);
+            data.patched_zips.insert(entry.zip.clone());
+        }
+        data.zip_patch = Some(patch.info());
+        Ok(self)
+    }
+
+    /// Use the land areas of `zctas` for `density_for`. This doesn't change
+    /// our chunks.
+    pub fn with_land_areas(mut self, zctas: &[Zcta]) -> Classifier {
+        let land_areas = zctas.iter().map(|z| (z.zip.clone(), z.land_area));
+        self.data_mut().land_area = Some(PrefixPopulation::from_weights(land_areas));
+        self
+    }
+
+    /// Use the internal points of `points` instead of our built-in zip code
+    /// locations to find the centroid of each chunk, for `chunk_centroid`
+    /// and `chunks_within_radius`. This doesn't change our chunks.
+    pub fn with_centroids(mut self, points: &[ZctaPoint]) -> Classifier {
+        self.data_mut().zcta_points = Some(points.to_vec());
+        self
+    }
+
+    /// Replace the population of each chunk with a noisy one, for
+    /// publishing chunk-level statistics under a differential privacy
+    /// policy. Our chunks still contain exactly the same zip codes, but
+    /// anything which uses `chunk_population` will see the noisy values.
+    pub fn with_population_noise(mut self, noise: &LaplaceNoise) -> Classifier {
+        let data = self.data_mut();
+        let chunk_ids = data.chunk_ids
+            .iter()
+            .filter(|c| !data.is_extra_chunk(c))
+            .cloned()
+            .collect::<Vec<_>>();
+        for chunk_id in chunk_ids {
+            let pop = data.population_for_chunk
+                .get_mut(&chunk_id)
+                .expect("all chunks should have a population");
+            *pop = noise.perturb(&chunk_id, *pop);
+        }
+        self
+    }
+
+    /// Rename each chunk to `new_name(chunk_id)`, keeping them in the same
+    /// order.
+    fn rename_chunks<F>(&mut self, new_name: F) -> Result<()>
+        where F: Fn(&str) -> String
+    {
+        let data = self.data_mut();
+        data.chunk_id_for_prefix = data.chunk_id_for_prefix
+            .drain()
+            .map(|(prefix, chunk_id)| (prefix, new_name(&chunk_id)))
+            .collect();
+        data.population_for_chunk = data.population_for_chunk
+            .drain()
+            .map(|(chunk_id, pop)| (new_name(&chunk_id), pop))
+            .collect();
+        data.unknown_chunk = data.unknown_chunk.as_ref().map(|c| new_name(c));
+        data.new_zip_chunk = data.new_zip_chunk.as_ref().map(|c| new_name(c));
+        // Keep our chunks in index order, instead of re-sorting the new names.
+        data.chunk_ids = data.chunk_ids.iter().map(|c| new_name(c)).collect();
+        data.index_for_chunk = data.chunk_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.clone(), i))
+            .collect();
+        if data.index_for_chunk.len() != data.chunk_ids.len() {
+            return Err("renaming chunks gave two chunks the same name".into());
+        }
+        Ok(())
+    }
+
+    /// Add a chunk for zip codes we can't classify. It comes after all our
+    /// other chunks, and has a population of 0.
+    fn add_unknown_chunk(&mut self, chunk_id: String) -> Result<()> {
+        self.add_empty_chunk(&chunk_id)?;
+        self.data_mut().unknown_chunk = Some(chunk_id);
+        Ok(())
+    }
+
+    /// Send zip codes whose prefix has no population, which are usually
+    /// zip codes created since the census, to a separate chunk for review,
+    /// instead of the chunk containing their prefix. See `is_new_zip`. The
+    /// new chunk comes after all our other chunks, and has a population of
+    /// 0.
+    pub fn with_new_zip_chunk(mut self, chunk_id: &str) -> Result<Classifier> {
+        self.check_no_new_zip_policy()?;
+        self.add_empty_chunk(chunk_id)?;
+        self.data_mut().new_zip_chunk = Some(chunk_id.to_owned());
+        Ok(self)
+    }
+
+    /// Send zip codes whose prefix has no population to the most populous
+    /// chunk containing zip codes with the same first three digits, instead
+    /// of whichever chunk their prefix happened to be packed into. This
+    /// keeps new zip codes near their neighbors, in the chunk where they're
+    /// least likely to skew populations. If no zip code with those three
+    /// digits has any population, we keep the chunk of their prefix. See
+    /// `is_fallback_zip`.
+    pub fn with_new_zip_fallback(mut self) -> Result<Classifier> {
+        self.check_no_new_zip_policy()?;
+        self.data_mut().new_zip_fallback = true;
+        Ok(self)
+    }
+
+    /// Make sure we don't already have a policy for new zip codes.
+    fn check_no_new_zip_policy(&self) -> Result<()> {
+        if self.data.new_zip_chunk.is_some() || self.data.new_zip_fallback {
+            return Err("classifier already has a policy for new zip codes".into());
+        }
+        Ok(())
+    }
+
+    /// Add a chunk with no prefixes and a population of 0, after all our
+    /// other chunks.
+    fn add_empty_chunk(&mut self, chunk_id: &str) -> Result<()> {
+        let data = self.data_mut();
+        if data.index_for_chunk.contains_key(chunk_id) {
+            return Err(format!("chunk {:?} already exists", chunk_id).into());
         }
+        data.population_for_chunk.insert(chunk_id.to_owned(), 0);
+        data.index_for_chunk.insert(chunk_id.to_owned(), data.chunk_ids.len());
+        data.chunk_ids.push(chunk_id.to_owned());
+        Ok(())
     }
 
     /// Return the column name to use for the geochunk column.  This encodes
     /// the parameters we used to configure the geochunks, to help prevent
     /// messing them up in the real world.
     pub fn geochunk_column_name(&self) -> String {
-        format!("geochunk_zip2010_{}", self.target_population)
+        format!("geochunk_{}_{}", self.data.weight_name, self.data.target_population)
+    }
+
+    /// Return metadata describing how these chunks were built.
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            version: ALGORITHM_VERSION.to_owned(),
+            column: self.geochunk_column_name(),
+            weights: self.data.weight_name.clone(),
+            target_population: self.data.target_population,
+            min_population: self.data.min_population,
+            census_data: self.data.census_data.clone(),
+            adjustments: self.data.adjustments.clone(),
+            zip_patch: self.data.zip_patch.clone(),
+        }
     }
 
     /// Given a zip code, return the geochunk identifier.  Returns `None` if the
     /// zip code is invalid.
     pub fn chunk_for(&self, zip: &str) -> Option<&str> {
+        self.prefix_and_chunk_for(zip).map(|(_, chunk_id)| chunk_id)
+    }
+
+    /// Given a zip code, return the zip code prefix which we used to assign
+    /// it to a geochunk. Returns `None` if the zip code is invalid.
+    pub fn prefix_for(&self, zip: &str) -> Option<&str> {
+        self.prefix_and_chunk_for(zip).map(|(prefix, _)| prefix)
+    }
+
+    /// Return the total population of a geochunk, measured in the same units
+    /// as the weights used to build it. Returns `None` if there is no such
+    /// chunk.
+    pub fn chunk_population(&self, chunk_id: &str) -> Option<u64> {
+        self.data.population_for_chunk.get(chunk_id).cloned()
+    }
+
+    /// Return the population of a zip code or zip code prefix, measured in
+    /// the same units as the weights used to build this classifier. ZIP+4
+    /// codes are looked up using their first five digits. Returns `None` if
+    /// `zip_or_prefix` isn't made of digits. Prefixes which don't match any
+    /// zip codes have a population of 0.
+    pub fn population_for(&self, zip_or_prefix: &str) -> Option<u64> {
+        let prefix = lookup_prefix(zip_or_prefix)?;
+        Some(self.data.prefix_population.lookup(prefix))
+    }
+
+    /// Return the population density of a zip code or zip code prefix, in
+    /// people per square mile, or in our weights per square mile if this
+    /// classifier isn't weighted by population. This uses the land areas
+    /// from `with_land_areas`, and returns `None` if we don't have any, if
+    /// `zip_or_prefix` isn't made of digits, or if it has no land area.
+    pub fn density_for(&self, zip_or_prefix: &str) -> Option<f64> {
+        let land_area = self.data.land_area.as_ref()?;
+        let prefix = lookup_prefix(zip_or_prefix)?;
+        let sq_m = land_area.lookup(prefix);
+        if sq_m == 0 {
+            return None;
+        }
+        let sq_mi = sq_m as f64 / SQUARE_METERS_PER_SQUARE_MILE;
+        Some(self.data.prefix_population.lookup(prefix) as f64 / sq_mi)
+    }
+
+    /// Do we have land areas for `density_for`?
+    pub fn has_land_areas(&self) -> bool {
+        self.data.land_area.is_some()
+    }
+
+    /// Return the centroid of a chunk as a latitude and longitude, averaging
+    /// the locations of its zip codes, weighted by their population. These
+    /// are built in, or come from `with_centroids`. Returns `None` if none
+    /// of the chunk's zip codes have a location.
+    pub fn chunk_centroid(&self, chunk_id: &str) -> Option<(f64, f64)> {
+        let idx = *self.data.index_for_chunk.get(chunk_id)?;
+        self.centroid_table()?[idx]
+    }
+
+    /// Return the IDs of the chunks whose centroid is within `miles` of
+    /// `(lat, lng)`, nearest first, for selecting the chunks around a
+    /// store or event. This uses straight-line distances over the surface
+    /// of the Earth, not drive times. Chunks without a centroid are never
+    /// returned.
+    pub fn chunks_within_radius(&self, lat: f64, lng: f64, miles: f64) -> Vec<&str> {
+        let mut found = self.centroid_table()
+            .unwrap_or(&[])
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, centroid)| {
+                            let distance = distance_miles((lat, lng), (*centroid)?);
+                            if distance <= miles {
+                                Some((distance, idx))
+                            } else {
+                                None
+                            }
+                        })
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| a.partial_cmp(b).expect("distances should be comparable"));
+        found
+            .into_iter()
+            .map(|(_, idx)| &self.data.chunk_ids[idx][..])
+            .collect()
+    }
+
+    /// Our table of chunk centroids, if we have zip code locations.
+    fn centroid_table(&self) -> Option<&[Option<(f64, f64)>]> {
+        if self.data.zcta_points.is_none() && !cfg!(feature = "data-zip2010") {
+            return None;
+        }
+        let table = self.data.centroid_table.get_or_init(|| {
+            // For each chunk, the total weight of its points, their
+            // weighted sums, and their unweighted sums and count, which we
+            // use if all its points have no population.
+            let mut sums = vec![(0u64, 0.0, 0.0, 0u64, 0.0, 0.0); self.data.chunk_ids.len()];
+            for (zip, lat, lng) in self.zip_locations() {
+                let idx = match self.lookup_prefix_and_chunk(zip) {
+                    Some((_, chunk_id)) => self.data.index_for_chunk[chunk_id],
+                    None => continue,
+                };
+                let weight = self.data.prefix_population.lookup(zip);
+                let sum = &mut sums[idx];
+                sum.0 += weight;
+                sum.1 += weight as f64 * lat;
+                sum.2 += weight as f64 * lng;
+                sum.3 += 1;
+                sum.4 += lat;
+                sum.5 += lng;
+            }
+            sums.into_iter()
+                .map(|(weight, lat, lng, count, plain_lat, plain_lng)| match (weight, count) {
+                         (_, 0) => None,
+                         (0, _) => Some((plain_lat / count as f64, plain_lng / count as f64)),
+                         _ => Some((lat / weight as f64, lng / weight as f64)),
+                     })
+                .collect()
+        });
+        Some(table)
+    }
+
+    /// The zip codes and locations we use to find chunk centroids: the
+    /// points from `with_centroids`, or our built-in locations.
+    fn zip_locations(&self) -> Vec<(&str, f64, f64)> {
+        match self.data.zcta_points {
+            Some(ref points) => points.iter().map(|p| (&p.zip[..], p.lat, p.lng)).collect(),
+            #[cfg(feature = "data-zip2010")]
+            None => ZIP_CENTROID_ROWS.to_vec(),
+            #[cfg(not(feature = "data-zip2010"))]
+            None => vec![],
+        }
+    }
+
+    /// Return the total population of the data used to build this
+    /// classifier, including any zip codes added by a patch.
+    pub fn total_population(&self) -> u64 {
+        self.data.prefix_population.lookup("")
+    }
+
+    /// Check that our chunk populations add up to the population of our
+    /// data, and that each chunk's population is the total population of
+    /// the zip codes we assign to it. This will fail for chunks built using
+    /// `with_population_noise`.
+    pub fn check_population(&self) -> PopulationCheck {
+        let mut zip_population = vec![0; self.data.chunk_ids.len()];
+        let zips = (0..ZIP_CODE_COUNT)
+            .map(|zip_int| format!("{:05}", zip_int))
+            .collect::<Vec<_>>();
+        let indices = self.chunk_indices(zips.iter().map(|z| Some(&z[..])));
+        for (zip, index) in zips.iter().zip(indices) {
+            if let Some(index) = index {
+                let pop = self.data.prefix_population.lookup(zip);
+                zip_population[index as usize] += pop;
+            }
+        }
+        let mut check = PopulationCheck {
+            source_population: self.total_population(),
+            chunk_population: 0,
+            mismatched_chunks: vec![],
+        };
+        let chunks = self.data.chunk_ids.iter().zip(&zip_population);
+        for (chunk_id, &zip_population) in chunks {
+            let population = self.chunk_population(chunk_id)
+                .expect("all chunks should have a population");
+            check.chunk_population += population;
+            if population != zip_population {
+                check.mismatched_chunks.push(ChunkPopulationMismatch {
+                                                 chunk_id: chunk_id.clone(),
+                                                 population,
+                                                 zip_population,
+                                             });
+            }
+        }
+        check
+    }
+
+    /// Given the number of input rows for each zip code, predict how many rows
+    /// will end up in each chunk. Every chunk is included, even if we expect
+    /// it to be empty. Zip codes which can't be assigned to a chunk are
+    /// ignored.
+    pub fn estimate_distribution(&self,
+                                 zip_counts: &HashMap<String, u64>)
+                                 -> BTreeMap<String, u64> {
+        let mut distribution = self.data.chunk_ids
+            .iter()
+            .map(|id| (id.clone(), 0))
+            .collect::<BTreeMap<_, _>>();
+        for (zip, &count) in zip_counts {
+            if let Some(chunk_id) = self.chunk_for(zip) {
+                *distribution
+                     .get_mut(chunk_id)
+                     .expect("every chunk should be in distribution") += count;
+            }
+        }
+        distribution
+    }
+
+    /// Return the population of every zip code prefix used to build this
+    /// classifier.
+    pub fn prefix_population(&self) -> &PrefixPopulation {
+        &self.data.prefix_population
+    }
+
+    /// Return all our chunk IDs, in sorted order.
+    pub fn chunk_ids(&self) -> &[String] {
+        &self.data.chunk_ids
+    }
+
+    /// Return a dense integer index for a chunk ID, which can be used instead
+    /// of the ID itself. These are assigned in sorted chunk ID order, so
+    /// they're as stable as the chunk IDs. Returns `None` if there is no such
+    /// chunk.
+    pub fn chunk_index(&self, chunk_id: &str) -> Option<usize> {
+        self.data.index_for_chunk.get(chunk_id).cloned()
+    }
+
+    /// Classify a whole column of zip codes at once, returning the index of
+    /// each zip code's chunk in `chunk_ids`, or `None` for missing or
+    /// invalid zip codes.
+    ///
+    /// This is a dictionary encoding of our chunk IDs, so columnar tools
+    /// can use it without copying chunk IDs for every row. For Arrow's
+    /// `StringArray`, see `chunk_dictionary`.
+    pub fn chunk_indices<'z, I>(&self, zips: I) -> Vec<Option<u32>>
+    where
+        I: IntoIterator<Item = Option<&'z str>>,
+    {
+        let table = self.zip_table();
+        let uses_table =
+            self.data.new_zip_chunk.is_none() && !self.data.new_zip_fallback;
+        zips.into_iter()
+            .map(|zip| {
+                let zip = zip?;
+                match zip_index(zip.as_bytes()).map(|i| table[i as usize]) {
+                    Some(entry) if uses_table && entry != NO_CHUNK => Some(entry >> 3),
+                    _ => {
+                        let (_, chunk_id) = self.prefix_and_chunk_for(zip)?;
+                        Some(self.data.index_for_chunk[chunk_id] as u32)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Classify an Arrow column of zip codes, returning a `DictionaryArray`
+    /// whose keys are the indices from `chunk_indices`, and whose values are
+    /// our `chunk_ids`. Missing or invalid zip codes are null.
+    #[cfg(feature = "dictionary")]
+    pub fn chunk_dictionary(&self, zips: &StringArray) -> DictionaryArray<UInt32Type> {
+        let keys = UInt32Array::from(self.chunk_indices(zips.iter()));
+        let values = StringArray::from_iter_values(&self.data.chunk_ids);
+        DictionaryArray::try_new(keys, Arc::new(values))
+            .expect("chunk indices should be valid dictionary keys")
+    }
+
+    /// Write a CSV file mapping each chunk index back to its chunk ID.
+    pub fn export_chunk_index(&self, out: &mut dyn Write) -> Result<()> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(out);
+        wtr.serialize(("index", self.geochunk_column_name()))?;
+        for (i, chunk_id) in self.data.chunk_ids.iter().enumerate() {
+            wtr.serialize((i, chunk_id))?;
+        }
+        Ok(())
+    }
+
+    /// Describe each of our chunks, in chunk index order.
+    pub fn chunk_summaries(&self) -> Vec<ChunkSummary> {
+        let mut prefixes_for_chunk = HashMap::<&str, Vec<String>>::new();
+        for (prefix, chunk_id) in &self.data.chunk_id_for_prefix {
+            prefixes_for_chunk.entry(chunk_id).or_default().push(prefix.clone());
+        }
+        self.data
+            .chunk_ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| {
+                let mut prefixes = prefixes_for_chunk.remove(&id[..]).unwrap_or_default();
+                prefixes.sort();
+                ChunkSummary {
+                    id: id.clone(),
+                    index,
+                    population: self.chunk_population(id)
+                        .expect("all chunks should have a population"),
+                    prefixes,
+                }
+            })
+            .collect()
+    }
+
+    /// Write a CSV file listing each chunk, its population, and the zip code
+    /// prefixes it contains, separated by spaces. This lets people review
+    /// our chunks before using them.
+    pub fn export_plan(&self, out: &mut dyn Write) -> Result<()> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(out);
+        wtr.serialize([&self.geochunk_column_name()[..], "population", "prefixes"])?;
+        for summary in self.chunk_summaries() {
+            wtr.serialize((summary.id, summary.population, summary.prefixes.join(" ")))?;
+        }
+        Ok(())
+    }
+
+    /// Given a zip code, return the matching prefix and geochunk identifier.
+    /// Returns `None` if the zip code is invalid, unless we were built with
+    /// `UnknownPolicy::Chunk`, in which case invalid zip codes are assigned
+    /// to that chunk with an empty prefix.
+    pub fn prefix_and_chunk_for(&self, zip: &str) -> Option<(&str, &str)> {
+        match self.lookup_prefix_and_chunk(zip) {
+            Some((prefix, _)) if self.data.new_zip_chunk.is_some() &&
+                                 self.is_new_prefix(prefix) => {
+                self.data.new_zip_chunk.as_ref().map(|c| (prefix, &c[..]))
+            }
+            Some((prefix, _)) if self.is_fallback_prefix(prefix) => {
+                let idx = self.fallback_table()[&prefix[..3]];
+                Some((prefix, &self.data.chunk_ids[idx][..]))
+            }
+            found => found.or_else(|| self.data.unknown_chunk.as_ref().map(|c| ("", &c[..]))),
+        }
+    }
+
+    /// Is `zip` a valid zip code whose prefix has no population in our
+    /// data? This usually means that it was created after the census, and
+    /// it was assigned to whichever leftover chunk its prefix went to.
+    pub fn is_new_zip(&self, zip: &str) -> bool {
+        self.lookup_prefix_and_chunk(zip)
+            .is_some_and(|(prefix, _)| self.is_new_prefix(prefix))
+    }
+
+    /// Does `prefix` have no population, without being added by our patch?
+    pub(crate) fn is_new_prefix(&self, prefix: &str) -> bool {
+        self.data.prefix_population.lookup(prefix) == 0 &&
+        !self.data.patched_zips.contains(prefix)
+    }
+
+    /// Describe how `zip` was assigned to a chunk. Returns `None` if it
+    /// isn't in any chunk.
+    pub fn assignment_method(&self, zip: &str) -> Option<AssignmentMethod> {
+        self.prefix_and_chunk_for(zip)
+            .map(|(prefix, chunk_id)| self.assignment_method_for(prefix, chunk_id))
+    }
+
+    /// Describe how a zip code was assigned to `chunk_id`, given the prefix
+    /// returned by `prefix_and_chunk_for`.
+    pub(crate) fn assignment_method_for(&self,
+                                        prefix: &str,
+                                        chunk_id: &str)
+                                        -> AssignmentMethod {
+        let data = &self.data;
+        if data.unknown_chunk.as_ref().is_some_and(|c| c == chunk_id) {
+            AssignmentMethod::UnknownPolicy
+        } else if data.new_zip_chunk.as_ref().is_some_and(|c| c == chunk_id) {
+            AssignmentMethod::NewZipChunk
+        } else if self.is_fallback_prefix(prefix) {
+            AssignmentMethod::NewZipFallback
+        } else if prefix.len() == ZIP_CODE_LENGTH {
+            AssignmentMethod::Exact
+        } else {
+            AssignmentMethod::Prefix(prefix.len())
+        }
+    }
+
+    /// Was `zip` assigned to a chunk using the fallback chosen by
+    /// `with_new_zip_fallback`?
+    pub fn is_fallback_zip(&self, zip: &str) -> bool {
+        self.lookup_prefix_and_chunk(zip)
+            .is_some_and(|(prefix, _)| self.is_fallback_prefix(prefix))
+    }
+
+    /// Do zip codes found using `prefix` use our fallback chunk for new zip
+    /// codes?
+    pub(crate) fn is_fallback_prefix(&self, prefix: &str) -> bool {
+        self.data.new_zip_fallback && self.is_new_prefix(prefix) &&
+        prefix.get(..3).is_some_and(|p| self.fallback_table().contains_key(p))
+    }
+
+    /// Our table mapping 3-digit prefixes to the most populous chunk
+    /// containing zip codes which start with them. Ties go to the chunk with
+    /// the lowest index.
+    fn fallback_table(&self) -> &HashMap<String, usize> {
+        self.data.fallback_table.get_or_init(|| {
+            let data = &self.data;
+            let mut table = HashMap::<String, usize>::new();
+            for (prefix, chunk_id) in &data.chunk_id_for_prefix {
+                if prefix.len() < 3 || data.prefix_population.lookup(prefix) == 0 {
+                    continue;
+                }
+                let idx = data.index_for_chunk[chunk_id];
+                let rank = |i: usize| {
+                    let pop = data.population_for_chunk[&data.chunk_ids[i]];
+                    (pop, Reverse(i))
+                };
+                let best = table.entry(prefix[..3].to_owned()).or_insert(idx);
+                if rank(idx) > rank(*best) {
+                    *best = idx;
+                }
+            }
+            table
+        })
+    }
+
+    /// Look up the prefix and chunk for a zip code in our table.
+    fn lookup_prefix_and_chunk(&self, zip: &str) -> Option<(&str, &str)> {
+        // Most zip codes start with five digits, so we can look them up
+        // directly in `zip_table`.
+        if let Some(zip_idx) = zip_index(zip.as_bytes()) {
+            let entry = self.zip_table()[zip_idx as usize];
+            if entry == NO_CHUNK {
+                return None;
+            }
+            let prefix_len = (entry & 0b111) as usize;
+            let (prefix, _) = self.data
+                .chunk_id_for_prefix
+                .get_key_value(&zip[..prefix_len])
+                .expect("zip table should only contain known prefixes");
+            return Some((prefix, &self.data.chunk_ids[(entry >> 3) as usize]));
+        }
+        self.search_prefix_and_chunk(zip)
+    }
+
+    /// Our table mapping each 5-digit zip code to its chunk and prefix.
+    fn zip_table(&self) -> &[u32] {
+        self.data.zip_table.get_or_init(|| {
+            (0..ZIP_CODE_COUNT)
+                .map(|zip_int| {
+                    let zip = format!("{:05}", zip_int);
+                    match self.search_prefix_and_chunk(&zip) {
+                        Some((prefix, chunk_id)) => {
+                            let idx = self.data.index_for_chunk[chunk_id] as u32;
+                            idx << 3 | prefix.len() as u32
+                        }
+                        None => NO_CHUNK,
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// Look up the prefix and chunk for a zip code by trying each of its
+    /// prefixes, from longest to shortest.
+    fn search_prefix_and_chunk(&self, zip: &str) -> Option<(&str, &str)> {
         if zip.len() < ZIP_CODE_LENGTH {
             // We may see empty zip codes (which is how CSV typically represents
             // a null field), or we may see corrupt or invalid zip codes. We map
@@ -55,21 +1205,20 @@ impl Classifier {
             return None;
         }
 
-        // Look for increasingly shorter prefixes in our table.
+        // Look for increasingly shorter prefixes in our table. Prefixes
+        // which would split a multi-byte character can't match anything.
         for i_rev in 0..(ZIP_CODE_LENGTH + 1) {
             let i = ZIP_CODE_LENGTH - i_rev;
-            if let Some(chunk_id) = self.chunk_id_for_prefix.get(&zip[..i]) {
-                return Some(chunk_id);
+            let found = zip.get(..i)
+                .and_then(|p| self.data.chunk_id_for_prefix.get_key_value(p));
+            if let Some((prefix, chunk_id)) = found {
+                return Some((prefix, chunk_id));
             }
         }
 
         // We couldn't find a chunk for this zip code, so let's make sure
         // it actually is a zip code, and handle it appropriately.
-        lazy_static! {
-            static ref ZIP_RE: Regex = Regex::new("^[0-9]{5}")
-                .expect("cannot parse zip code regular expression");
-        }
-        if ZIP_RE.is_match(zip) {
+        if zip_index(zip.as_bytes()).is_some() {
             // This looks like a ZIP code, and we should have handled it.
             unreachable!("shoud have found chunk for zip code {:?}", zip);
         } else {
@@ -79,7 +1228,7 @@ impl Classifier {
     }
 
     /// Export this mapping as a CSV file.
-    pub fn export(&self, out: &mut Write) -> Result<()> {
+    pub fn export(&self, out: &mut dyn Write) -> Result<()> {
         let mut wtr = csv::WriterBuilder::new()
             .has_headers(false)
             .from_writer(out);
@@ -89,7 +1238,7 @@ impl Classifier {
             let chunk_id = self.chunk_for(&zip)
                 // This is a genuine assertion failure.
                 .expect("all zip codes should have a chunk");
-            wtr.serialize([&zip[..], &chunk_id])?;
+            wtr.serialize([&zip[..], chunk_id])?;
         }
         Ok(())
     }
@@ -97,46 +1246,77 @@ impl Classifier {
     /// Read a CSV file, add a geochunk column, and write it back out again.
     pub fn transform_csv(&self,
                          input_column: &str,
-                         input: &mut Read,
-                         output: &mut Write)
+                         input: &mut dyn Read,
+                         output: &mut dyn Write)
                          -> Result<()> {
-        let mut rdr = csv::Reader::from_reader(input);
-        let mut wtr = csv::WriterBuilder::new()
-            .has_headers(false)
-            .from_writer(output);
+        let options = TransformOptions::default();
+        transform::transform_csv(self, &[input_column], &options, input, output)?;
+        Ok(())
+    }
+}
+
+/// The prefix to look up for a zip code or zip code prefix, using the first
+/// five digits of ZIP+4 codes, or `None` if it isn't made of digits.
+fn lookup_prefix(zip_or_prefix: &str) -> Option<&str> {
+    let digits = zip_or_prefix
+        .bytes()
+        .take_while(|b| b.is_ascii_digit())
+        .count();
+    match digits {
+        _ if digits == zip_or_prefix.len() && digits <= ZIP_CODE_LENGTH => Some(zip_or_prefix),
+        ZIP_CODE_LENGTH if zip_or_prefix.as_bytes()[digits] == b'-' => {
+            Some(&zip_or_prefix[..ZIP_CODE_LENGTH])
+        }
+        _ => None,
+    }
+}
 
-        // Extract our headers.
-        let mut headers = rdr.headers()?.to_owned();
+/// If `zip` starts with five ASCII digits, return them as a number from 0
+/// to 99999. We check all five digits at once using a single 64-bit word,
+/// because this runs for every zip code we classify.
+fn zip_index(zip: &[u8]) -> Option<u32> {
+    if zip.len() < ZIP_CODE_LENGTH {
+        return None;
+    }
+    let mut bytes = [b'0'; 8];
+    bytes[..ZIP_CODE_LENGTH].copy_from_slice(&zip[..ZIP_CODE_LENGTH]);
+    // Subtracting `'0'` from each byte leaves a value from 0 to 9 for
+    // digits. Any other byte either underflows or, once we add 0x76, sets
+    // its high bit. When every byte is a digit, nothing carries between
+    // bytes.
+    let digits = u64::from_le_bytes(bytes).wrapping_sub(0x3030_3030_3030_3030);
+    let high_bits = digits | digits.wrapping_add(0x7676_7676_7676_7676);
+    if high_bits & 0x8080_8080_8080_8080 != 0 {
+        return None;
+    }
+    Some(digits.to_le_bytes()[..ZIP_CODE_LENGTH]
+             .iter()
+             .fold(0, |acc, &d| acc * 10 + u32::from(d)))
+}
 
-        // Look up the header index for our zip code column.
-        let zip_col_idx = headers
-            .iter()
-            .position(|h| h == input_column)
-            .ok_or_else(|| Error::no_such_column(input_column))?;
-
-        // Add our output column and write our headers.
-        headers.push_field(&self.geochunk_column_name());
-        wtr.write_record(headers.iter())?;
-
-        // According to BurntSushi at
-        // https://github.com/BurntSushi/rust-csv/issues/76 ,
-        // this should be the fastest way to write this loop.  This matters
-        // because we may have millions of rows and hundreds of columns.
-        let mut row = csv::ByteRecord::new();
-        while rdr.read_byte_record(&mut row)? {
-            let zip = from_utf8(&row[zip_col_idx])
-                .chain_err(|| Error::non_utf8_zip(row.position()))?
-                .to_owned();
-            // If there's no chunk, just output the empty string, which is
-            // as CSV null.
-            row.push_field(self.chunk_for(&zip).unwrap_or("").as_bytes());
-            wtr.write_byte_record(&row)?;
+#[test]
+fn parses_zip_indices() {
+    let _ = env_logger::init();
+    assert_eq!(zip_index(b"00000"), Some(0));
+    assert_eq!(zip_index(b"90210"), Some(90210));
+    assert_eq!(zip_index(b"99577-0727"), Some(99577));
+    let invalid_zips =
+        [&b"9021"[..], b"9021x", b"/0210", b":0210", b" 90210", b"\xff0210"];
+    for invalid in &invalid_zips {
+        assert_eq!(zip_index(invalid), None, "{:?}", invalid);
+    }
+    // Check every possible byte in every position.
+    for pos in 0..ZIP_CODE_LENGTH {
+        for b in 0..=255u8 {
+            let mut zip = *b"12345";
+            zip[pos] = b;
+            assert_eq!(zip_index(&zip).is_some(), b.is_ascii_digit(), "{:?}", zip);
         }
-        Ok(())
     }
 }
 
 #[test]
+#[cfg(feature = "data-zip2010")]
 fn classifies_sample_zip_codes_as_expected() {
     let _ = env_logger::init();
     let classifier = Classifier::new(250000);
@@ -146,69 +1326,1113 @@ fn classifies_sample_zip_codes_as_expected() {
 }
 
 #[test]
-fn does_not_assign_geochunks_to_missing_or_invalid_zips() {
+#[cfg(feature = "data-zip2010")]
+fn zip_table_matches_prefix_search() {
     let _ = env_logger::init();
     let classifier = Classifier::new(250000);
-    assert!(classifier.chunk_for("").is_none());
-    assert!(classifier.chunk_for("0").is_none());
-    assert!(classifier.chunk_for("None").is_none());
+    for zip_int in (0..ZIP_CODE_COUNT).step_by(7) {
+        let zip = format!("{:05}", zip_int);
+        assert_eq!(classifier.lookup_prefix_and_chunk(&zip),
+                   classifier.search_prefix_and_chunk(&zip),
+                   "{}",
+                   zip);
+    }
 }
 
 #[test]
-fn does_not_panic_on_corner_cases() {
+#[cfg(feature = "data-zip2010")]
+fn classifies_columns_of_zip_codes() {
     let _ = env_logger::init();
     let classifier = Classifier::new(250000);
-    // I don't actually care whether or not this is mapped to a geochunk or
-    // not, because we don't try to do detailed validation until _after_
-    // lookup fails, for performance reasons. All I care about is that this
-    // doesn't crash.
-    classifier.chunk_for("815XX");
+    let zips = [Some("90210"), None, Some("bad"), Some("07712-1234"), Some("90210")];
+    let indices = classifier.chunk_indices(zips.iter().cloned());
+    let chunk_ids = indices
+        .iter()
+        .map(|i| i.map(|i| &classifier.chunk_ids()[i as usize][..]))
+        .collect::<Vec<_>>();
+    assert_eq!(chunk_ids,
+               vec![Some("902_0"), None, None, Some("077_0"), Some("902_0")]);
+
+    // Chunks for new zip codes aren't in our lookup table.
+    let with_new = classifier.with_new_zip_chunk("new").unwrap();
+    let new_idx = with_new.chunk_index("new").unwrap() as u32;
+    let zips = [Some("00000"), Some("90210")];
+    assert_eq!(with_new.chunk_indices(zips.iter().cloned())[0], Some(new_idx));
 }
 
-type PrefixPopulationMaps = [HashMap<String, u64>; ZIP_CODE_LENGTH + 1];
+#[test]
+#[cfg(all(feature = "dictionary", feature = "data-zip2010"))]
+fn classifies_arrow_columns_into_dictionaries() {
+    use arrow_array::Array;
 
-/// Directly include our zip code population data in our application binary
-/// for ease of distribution and packaging.
-const ZIP_POPULATION_CSV: &'static str = include_str!("zip2010.csv");
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let zips = StringArray::from(vec![Some("90210"), None, Some("bad"), Some("90210")]);
+    let dictionary = classifier.chunk_dictionary(&zips);
+    assert_eq!(dictionary.len(), 4);
+    assert_eq!(dictionary.null_count(), 2);
+    assert_eq!(dictionary.values().len(), classifier.chunk_ids().len());
+    let chunks = dictionary
+        .downcast_dict::<StringArray>()
+        .unwrap()
+        .into_iter()
+        .collect::<Vec<_>>();
+    assert_eq!(chunks, vec![Some("902_0"), None, None, Some("902_0")]);
+}
 
-/// The population associated with a zip code prefix.
-struct PrefixPopulation {
-    maps: PrefixPopulationMaps,
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn reports_prefixes_and_chunk_populations() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    assert_eq!(classifier.prefix_for("01000").unwrap(), "0100");
+    assert_eq!(classifier.chunk_population("010_0"), Some(210857));
+    assert_eq!(classifier.chunk_population("nonexistent"), None);
+    assert!(classifier.prefix_for("").is_none());
 }
 
-impl PrefixPopulation {
-    fn new() -> PrefixPopulation {
-        let mut maps = PrefixPopulationMaps::default();
-
-        let mut rdr = csv::Reader::from_reader(ZIP_POPULATION_CSV.as_bytes());
-        for row in rdr.records() {
-            let (zip, pop): (String, u64) =
-                row.expect("Invalid CSV data built into executable")
-                    .deserialize(None)
-                    .expect("Invalid CSV data built into executable");
-
-            // For each prefix of this zip code, increment the population of
-            // that prefix.
-            for prefix_len in 0..maps.len() {
-                // This is a very long way of writing `(... ||= 0) += pop`.
-                match maps[prefix_len].entry(zip[0..prefix_len].to_owned()) {
-                    Entry::Vacant(vacant) => {
-                        vacant.insert(pop);
-                    }
-                    Entry::Occupied(mut occupied) => {
-                        *occupied.get_mut() += pop;
-                    }
-                }
-            }
-        }
+#[test]
+fn exports_chunk_plan() {
+    let _ = env_logger::init();
+    let weights = vec![("10001".to_owned(), 300),
+                       ("10002".to_owned(), 300),
+                       ("20001".to_owned(), 100)];
+    let classifier = Classifier::with_weights("test", weights, 250);
+    let mut out = vec![];
+    classifier.export_plan(&mut out).unwrap();
+    let plan = String::from_utf8(out).unwrap();
+    assert!(plan.starts_with("geochunk_test_250,population,prefixes\n\
+                              10001,300,10001\n10002,300,10002\n\
+                              1000_0,0,10000 10003 10004"));
+    assert!(plan.ends_with("\n_0,100,0 2 3 4 5 6 7 8 9\n"));
+}
 
-        PrefixPopulation { maps: maps }
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn reports_population_of_zips_and_prefixes() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    assert_eq!(classifier.population_for(""), Some(308739931));
+    assert_eq!(classifier.population_for("0100"),
+               Some(classifier.prefix_population().lookup("0100")));
+    let zip_pop = classifier.population_for("99577").unwrap();
+    assert!(zip_pop > 0);
+    assert_eq!(classifier.population_for("99577-0727"), Some(zip_pop));
+    assert_eq!(classifier.population_for("00000"), Some(0));
+    assert_eq!(classifier.population_for("995770"), None);
+    assert_eq!(classifier.population_for("N/A"), None);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn computes_densities_from_land_areas() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    assert!(!classifier.has_land_areas());
+    assert_eq!(classifier.density_for("99577"), None);
+    let zctas = vec![Zcta {
+                         zip: "99577".to_owned(),
+                         land_area: SQUARE_METERS_PER_SQUARE_MILE as u64 * 100,
+                     }];
+    let classifier = classifier.with_land_areas(&zctas);
+    assert!(classifier.has_land_areas());
+    let zip_pop = classifier.population_for("99577").unwrap() as f64;
+    let density = classifier.density_for("99577-0727").unwrap();
+    assert!((density - zip_pop / 100.0).abs() < 0.01);
+    assert_eq!(classifier.density_for("90210"), None);
+    assert_eq!(classifier.density_for("N/A"), None);
+    assert_eq!(classifier.chunk_for("99577"), Classifier::new(250000).chunk_for("99577"));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn finds_chunks_within_a_radius() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let point = |zip: &str, lat, lng| {
+        ZctaPoint {
+            zip: zip.to_owned(),
+            lat,
+            lng,
+            area: 0,
+        }
+    };
+    let points = vec![point("90210", 34.100517, -118.41463),
+                      point("90211", 34.065, -118.383),
+                      point("10001", 40.750633, -73.997177),
+                      point("00000", 0.0, 0.0)];
+    let classifier = classifier.with_centroids(&points);
+
+    // Our centroid is weighted by population.
+    let pop_90210 = classifier.population_for("90210").unwrap() as f64;
+    let pop_90211 = classifier.population_for("90211").unwrap() as f64;
+    let (lat, _) = classifier.chunk_centroid("902_0").unwrap();
+    let expected = (pop_90210 * 34.100517 + pop_90211 * 34.065) / (pop_90210 + pop_90211);
+    assert!((lat - expected).abs() < 1e-9);
+    assert_eq!(classifier.chunk_centroid("021_0"), None);
+    // 00000 has no population, so we use the average of its chunk's points.
+    assert_eq!(classifier.chunk_centroid(classifier.chunk_for("00000").unwrap()),
+               Some((0.0, 0.0)));
+
+    assert_eq!(classifier.chunks_within_radius(34.08, -118.40, 10.0), vec!["902_0"]);
+    assert_eq!(classifier.chunks_within_radius(34.08, -118.40, 3000.0),
+               vec!["902_0", "100_0"]);
+    assert_eq!(classifier.chunks_within_radius(10.0, 10.0, 10.0), Vec::<&str>::new());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn finds_chunks_within_a_radius_using_built_in_locations() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    // Every chunk with people in it has a centroid.
+    for chunk_id in classifier.chunk_ids() {
+        if classifier.chunk_population(chunk_id) > Some(0) {
+            assert!(classifier.chunk_centroid(chunk_id).is_some(), "{}", chunk_id);
+        }
+    }
+    let (lat, lng) = classifier.chunk_centroid(classifier.chunk_for("90210").unwrap()).unwrap();
+    assert!(distance_miles((lat, lng), (34.09, -118.41)) < 25.0);
+
+    // Around Beverly Hills, nearest first.
+    let nearby = classifier.chunks_within_radius(34.09, -118.41, 15.0);
+    let distances = nearby
+        .iter()
+        .map(|c| distance_miles((34.09, -118.41), classifier.chunk_centroid(c).unwrap()))
+        .collect::<Vec<_>>();
+    assert!(distances.windows(2).all(|w| w[0] <= w[1] && w[1] <= 15.0));
+    assert!(nearby.contains(&classifier.chunk_for("90210").unwrap()));
+    assert!(nearby.contains(&classifier.chunk_for("90012").unwrap()));
+    assert!(!nearby.contains(&classifier.chunk_for("10001").unwrap()));
+    assert!(classifier.chunks_within_radius(0.0, 0.0, 100.0).is_empty());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn estimates_rows_per_chunk() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let mut zip_counts = HashMap::new();
+    zip_counts.insert("90210".to_owned(), 10);
+    zip_counts.insert("90211-1234".to_owned(), 5);
+    zip_counts.insert("07712".to_owned(), 2);
+    zip_counts.insert("N/A".to_owned(), 100);
+    let distribution = classifier.estimate_distribution(&zip_counts);
+    assert_eq!(distribution.len(), classifier.chunk_ids().len());
+    assert_eq!(distribution["902_0"], 15);
+    assert_eq!(distribution["077_0"], 2);
+    assert_eq!(distribution.values().sum::<u64>(), 17);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn assigns_dense_chunk_indices() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let ids = classifier.chunk_ids();
+    assert_eq!(ids[0], "010_0");
+    assert_eq!(classifier.chunk_index("010_0"), Some(0));
+    let last = ids.len() - 1;
+    assert_eq!(classifier.chunk_index(&ids[last]), Some(last));
+    assert_eq!(classifier.chunk_index("nonexistent"), None);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn does_not_assign_geochunks_to_missing_or_invalid_zips() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    assert!(classifier.chunk_for("").is_none());
+    assert!(classifier.chunk_for("0").is_none());
+    assert!(classifier.chunk_for("None").is_none());
+}
+
+#[test]
+fn classifies_using_custom_weights() {
+    let _ = env_logger::init();
+    let weights = vec![("10001".to_owned(), 300),
+                       ("10002".to_owned(), 300),
+                       ("20001".to_owned(), 100)];
+    let classifier = Classifier::with_weights("test", weights, 250);
+    assert_eq!(classifier.geochunk_column_name(), "geochunk_test_250");
+    assert_eq!(classifier.chunk_for("10001").unwrap(), "10001");
+    assert_eq!(classifier.chunk_for("10002").unwrap(), "10002");
+    assert_eq!(classifier.chunk_for("20001").unwrap(), "_0");
+    // Zip codes with no weight at all must still be assigned somewhere.
+    assert_eq!(classifier.chunk_for("99999").unwrap(), "_0");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn describes_built_in_census_data() {
+    let _ = env_logger::init();
+    let info = DataInfo::census2010();
+    assert_eq!(info.vintage, Some(2010));
+    assert_eq!(info.rows, 33092);
+    assert_eq!(info.total_population, 308739931);
+    assert_eq!(info.sha256.len(), 64);
+    assert_eq!(Classifier::new(250000).metadata().census_data, Some(info));
+
+    // Other weights only describe the file they came from, if we're told it.
+    let weights = vec![("10001".to_owned(), 150), ("20001".to_owned(), 100)];
+    let population = PrefixPopulation::from_weights(weights.clone());
+    let classifier = Classifier::with_weights("test", weights, 250);
+    assert_eq!(classifier.metadata().census_data, None);
+    let info = DataInfo::for_file("test.csv", b"zip,pop\n", &population);
+    assert_eq!(info.source, "test.csv");
+    assert_eq!(info.vintage, None);
+    assert_eq!(info.rows, 2);
+    assert_eq!(info.total_population, 250);
+    assert_eq!(info.sha256,
+               "f596237f590497e1352df600a7bb935bf8a305dfa54c86fce4bed82e8eddfd6a");
+    let classifier = classifier.with_data_info(info.clone());
+    assert_eq!(classifier.metadata().census_data, Some(info));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn does_not_panic_on_corner_cases() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    // I don't actually care whether or not this is mapped to a geochunk or
+    // not, because we don't try to do detailed validation until _after_
+    // lookup fails, for performance reasons. All I care about is that this
+    // doesn't crash.
+    classifier.chunk_for("815XX");
+}
+
+type PrefixPopulationMaps = [HashMap<String, u64>; ZIP_CODE_LENGTH + 1];
+
+/// A census data set which we can use to build chunks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Vintage {
+    /// The 2010 census.
+    #[default]
+    V2010,
+}
+
+/// How should we name our chunks?
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Naming {
+    /// Name chunks after the zip code prefix they were split from, like
+    /// `"902_0"`.
+    #[default]
+    Prefix,
+    /// Name chunks using their dense integer index, like `"571"`. See
+    /// `Classifier::chunk_index`.
+    Integer,
+    /// Name chunks using their dense integer index, padded with zeros so
+    /// that every ID has the same width, like `"0571"`. Unlike `Integer`
+    /// and `Prefix` names, these sort in index order as strings.
+    Sortable,
+    /// Name chunks using an HMAC-SHA256 of the sorted list of prefixes in
+    /// each chunk, keyed with this secret salt, like `"3d0b94fa7c2e1685"`.
+    /// These names are the same every time we use the same salt, but they
+    /// don't reveal which zip codes are in each chunk to anyone without it.
+    Hashed(Vec<u8>),
+    /// Name chunks by filling in a template, like
+    /// `"{state}-{prefix}-{index:03}"` for `"CA-902-571"`. This is based on
+    /// our usual prefix names, so it must be used on a classifier which
+    /// hasn't already been renamed.
+    Template(IdTemplate),
+}
+
+/// What should we do with zip codes which we can't assign to a chunk?
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum UnknownPolicy {
+    /// Don't assign them to any chunk.
+    #[default]
+    Null,
+    /// Assign them to a chunk with the specified ID, which has a population
+    /// of 0. This includes empty zip codes.
+    Chunk(String),
+}
+
+/// How a zip code was assigned to its chunk, for weighting or excluding
+/// low-confidence assignments. See `Classifier::assignment_method`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssignmentMethod {
+    /// The full 5-digit zip code was one of our chunk prefixes.
+    Exact,
+    /// We used a shorter prefix of the zip code, with this many digits.
+    Prefix(usize),
+    /// The zip code's prefix has no population, so we used the fallback
+    /// from `Classifier::with_new_zip_fallback`.
+    NewZipFallback,
+    /// The zip code's prefix has no population, so we used the chunk from
+    /// `Classifier::with_new_zip_chunk`.
+    NewZipChunk,
+    /// The zip code was invalid, so we used the chunk from
+    /// `UnknownPolicy::Chunk`.
+    UnknownPolicy,
+}
+
+impl AssignmentMethod {
+    /// The name we use for this method in our output.
+    pub fn name(self) -> &'static str {
+        match self {
+            AssignmentMethod::Exact => "exact-5-digit",
+            AssignmentMethod::Prefix(4) => "prefix-fallback-4",
+            AssignmentMethod::Prefix(3) => "prefix-fallback-3",
+            AssignmentMethod::Prefix(2) => "prefix-fallback-2",
+            AssignmentMethod::Prefix(1) => "prefix-fallback-1",
+            AssignmentMethod::Prefix(_) => "prefix-fallback-0",
+            AssignmentMethod::NewZipFallback => "new-zip-fallback",
+            AssignmentMethod::NewZipChunk => "new-zip-chunk",
+            AssignmentMethod::UnknownPolicy => "unknown-policy",
+        }
+    }
+}
+
+/// Configures and builds a `Classifier`.
+///
+/// ```
+/// # #[cfg(feature = "data-zip2010")] {
+/// use geochunk::zip2010::{ClassifierBuilder, Naming, Vintage};
+///
+/// let classifier = ClassifierBuilder::new()
+///     .vintage(Vintage::V2010)
+///     .target_population(250_000)
+///     .naming(Naming::Integer)
+///     .build()
+///     .unwrap();
+/// let chunk_id = classifier.chunk_for("90210").unwrap();
+/// assert_eq!(classifier.chunk_index(chunk_id), chunk_id.parse().ok());
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ClassifierBuilder {
+    vintage: Vintage,
+    weights: Option<(String, PrefixPopulation)>,
+    target_population: Option<u64>,
+    density: Option<DensityClasses>,
+    respect_states: bool,
+    min_population: u64,
+    naming: Naming,
+    unknown_policy: UnknownPolicy,
+    new_zip_chunk: Option<String>,
+    new_zip_fallback: bool,
+    zip_patch: Option<ZipPatch>,
+}
+
+impl ClassifierBuilder {
+    /// Create a new builder using our default options.
+    pub fn new() -> ClassifierBuilder {
+        ClassifierBuilder::default()
+    }
+
+    /// Which census data should we use? This is ignored if `weights` is
+    /// called.
+    pub fn vintage(mut self, vintage: Vintage) -> ClassifierBuilder {
+        self.vintage = vintage;
+        self
+    }
+
+    /// Weight zip codes using custom data, instead of census population.
+    /// `weight_name` identifies the weights in our output column name.
+    pub fn weights(mut self,
+                   weight_name: &str,
+                   weights: PrefixPopulation)
+                   -> ClassifierBuilder {
+        self.weights = Some((weight_name.to_owned(), weights));
+        self
+    }
+
+    /// How many people would we ideally want to see in each chunk? This is
+    /// required.
+    pub fn target_population(mut self, target_population: u64) -> ClassifierBuilder {
+        self.target_population = Some(target_population);
+        self
+    }
+
+    /// Never combine zip code prefixes from different density classes into
+    /// the same chunk.
+    pub fn density(mut self, density: DensityClasses) -> ClassifierBuilder {
+        self.density = Some(density);
+        self
+    }
+
+    /// Never combine zip code prefixes from different states into the same
+    /// chunk. This can't be combined with `density`.
+    pub fn respect_states(mut self) -> ClassifierBuilder {
+        self.respect_states = true;
+        self
+    }
+
+    /// Merge chunks of leftover prefixes with fewer than `min_population`
+    /// people into a neighboring chunk where we can. See
+    /// `Classifier::from_prefix_population_with_minimum`.
+    pub fn min_population(mut self, min_population: u64) -> ClassifierBuilder {
+        self.min_population = min_population;
+        self
+    }
+
+    /// How should we name our chunks?
+    pub fn naming(mut self, naming: Naming) -> ClassifierBuilder {
+        self.naming = naming;
+        self
+    }
+
+    /// What should we do with zip codes we can't classify?
+    pub fn unknown_policy(mut self, unknown_policy: UnknownPolicy) -> ClassifierBuilder {
+        self.unknown_policy = unknown_policy;
+        self
+    }
+
+    /// Send zip codes whose prefix has no population to a separate chunk.
+    /// See `Classifier::with_new_zip_chunk`.
+    pub fn new_zip_chunk(mut self, chunk_id: &str) -> ClassifierBuilder {
+        self.new_zip_chunk = Some(chunk_id.to_owned());
+        self
+    }
+
+    /// Send zip codes whose prefix has no population to the most populous
+    /// chunk sharing their first three digits. See
+    /// `Classifier::with_new_zip_fallback`.
+    pub fn new_zip_fallback(mut self) -> ClassifierBuilder {
+        self.new_zip_fallback = true;
+        self
+    }
+
+    /// Use any options given in a chunk spec, replacing our current ones.
+    pub fn options(mut self, options: &SpecOptions) -> ClassifierBuilder {
+        if let Some(ref naming) = options.naming {
+            self.naming = naming.clone();
+        }
+        if let Some(ref chunk_id) = options.unknown_chunk {
+            self.unknown_policy = UnknownPolicy::Chunk(chunk_id.clone());
+        }
+        if options.new_zip_chunk.is_some() || options.new_zip_fallback {
+            self.new_zip_chunk = options.new_zip_chunk.clone();
+            self.new_zip_fallback = options.new_zip_fallback;
+        }
+        if options.respect_states {
+            self.respect_states = true;
+        }
+        if let Some(min_population) = options.min_pop {
+            self.min_population = min_population;
+        }
+        self
+    }
+
+    /// Add new zip codes from a patch. See `Classifier::with_zip_patch`.
+    pub fn zip_patch(mut self, patch: ZipPatch) -> ClassifierBuilder {
+        self.zip_patch = Some(patch);
+        self
+    }
+
+    /// Build our classifier.
+    pub fn build(self) -> Result<Classifier> {
+        let target_population = self.target_population
+            .ok_or("must specify a target population")?;
+        let (weight_name, prefix_population, census_data) = match self.weights {
+            Some((weight_name, weights)) => (weight_name, weights, None),
+            None => {
+                match self.vintage {
+                    #[cfg(feature = "data-zip2010")]
+                    Vintage::V2010 => {
+                        ("zip2010".to_owned(),
+                         PrefixPopulation::census2010(),
+                         Some(DataInfo::census2010()))
+                    }
+                    #[cfg(not(feature = "data-zip2010"))]
+                    Vintage::V2010 => {
+                        return Err("2010 census data requires the data-zip2010 feature"
+                                       .into());
+                    }
+                }
+            }
+        };
+        let states = if self.respect_states {
+            if self.density.is_some() {
+                return Err("can't keep both states and density classes apart".into());
+            }
+            Some(StateClasses::new(&prefix_population))
+        } else {
+            None
+        };
+        let classes = match (&self.density, &states) {
+            (Some(density), _) => Some(density as &dyn PrefixClasses),
+            (None, Some(states)) => Some(states as &dyn PrefixClasses),
+            (None, None) => None,
+        };
+        let (mut classifier, _) =
+            Classifier::from_prefix_population_with_minimum(&weight_name,
+                                                            &prefix_population,
+                                                            target_population,
+                                                            self.min_population,
+                                                            classes);
+        if let Some(info) = census_data {
+            classifier = classifier.with_data_info(info);
+        }
+        if let Some(ref patch) = self.zip_patch {
+            classifier = classifier.with_zip_patch(patch)?;
+        }
+        classifier = classifier.with_naming(&self.naming)?;
+        if let UnknownPolicy::Chunk(chunk_id) = self.unknown_policy {
+            classifier.add_unknown_chunk(chunk_id)?;
+        }
+        if let Some(chunk_id) = self.new_zip_chunk {
+            classifier = classifier.with_new_zip_chunk(&chunk_id)?;
+        }
+        if self.new_zip_fallback {
+            classifier = classifier.with_new_zip_fallback()?;
+        }
+        Ok(classifier)
+    }
+}
+
+/// Classifier options which can be written as part of a chunk spec, such
+/// as the `[naming=int,new_zip_fallback]` in
+/// `zip2010-250000[naming=int,new_zip_fallback]`, so that tools which only
+/// pass around a single spec string can still use them. These are parsed
+/// from a comma-separated list of `name=value` pairs and flags:
+///
+/// - `naming=prefix`, `naming=int` or `naming=sortable`: see `Naming`.
+/// - `unknown_chunk=<id>`: see `UnknownPolicy::Chunk`.
+/// - `new_zip_chunk=<id>`: see `Classifier::with_new_zip_chunk`.
+/// - `new_zip_fallback`: see `Classifier::with_new_zip_fallback`.
+/// - `respect_states`: never put zip codes from different states in the
+///   same chunk. See `StateClasses`.
+/// - `min_pop=<n>`: merge small chunks of leftover prefixes into their
+///   neighbors. See `Classifier::from_prefix_population_with_minimum`.
+///
+/// `respect_states` and `min_pop` change how chunks are built, so they're
+/// used by `ClassifierBuilder::options` and `scheme::builtin_classifier`,
+/// but not by `SpecOptions::apply`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpecOptions {
+    /// How should we name our chunks, if not after their prefixes?
+    pub naming: Option<Naming>,
+    /// The chunk for zip codes we can't classify, if any.
+    pub unknown_chunk: Option<String>,
+    /// The chunk for zip codes whose prefix has no population, if any.
+    pub new_zip_chunk: Option<String>,
+    /// Should zip codes whose prefix has no population join the most
+    /// populous chunk sharing their first three digits?
+    pub new_zip_fallback: bool,
+    /// Should we keep zip codes from different states in separate chunks?
+    pub respect_states: bool,
+    /// The smallest population we want in a chunk of leftover prefixes, if
+    /// any.
+    pub min_pop: Option<u64>,
+}
+
+impl FromStr for SpecOptions {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SpecOptions> {
+        let mut options = SpecOptions::default();
+        for option in s.split(',').map(|o| o.trim()).filter(|o| !o.is_empty()) {
+            let (name, value) = match option.find('=') {
+                Some(i) => (&option[..i], Some(&option[i + 1..])),
+                None => (option, None),
+            };
+            let non_empty = |value: Option<&str>| match value {
+                Some(v) if !v.is_empty() => Ok(v.to_owned()),
+                _ => Err(format!("spec option {} needs a value", name)),
+            };
+            match name {
+                "naming" => {
+                    options.naming = match value {
+                        Some("prefix") => Some(Naming::Prefix),
+                        Some("int") | Some("integer") => Some(Naming::Integer),
+                        Some("sortable") => Some(Naming::Sortable),
+                        _ => {
+                            return Err("spec option naming should be prefix, int or \
+                                        sortable"
+                                               .into())
+                        }
+                    };
+                }
+                "unknown_chunk" => options.unknown_chunk = Some(non_empty(value)?),
+                "new_zip_chunk" => options.new_zip_chunk = Some(non_empty(value)?),
+                "new_zip_fallback" => {
+                    if value.is_some() {
+                        return Err("spec option new_zip_fallback doesn't take a value"
+                                       .into());
+                    }
+                    options.new_zip_fallback = true;
+                }
+                "respect_states" => {
+                    if value.is_some() {
+                        return Err("spec option respect_states doesn't take a value"
+                                       .into());
+                    }
+                    options.respect_states = true;
+                }
+                "min_pop" => {
+                    let min_pop = non_empty(value)?;
+                    options.min_pop =
+                        Some(min_pop
+                                 .parse()
+                                 .chain_err(|| {
+                                                format!("spec option min_pop should be a \
+                                                         number, not {:?}",
+                                                        min_pop)
+                                            })?);
+                }
+                _ => return Err(format!("unknown spec option {:?}", name).into()),
+            }
+        }
+        if options.new_zip_chunk.is_some() && options.new_zip_fallback {
+            return Err("spec options new_zip_chunk and new_zip_fallback can't be \
+                        used together"
+                               .into());
+        }
+        Ok(options)
+    }
+}
+
+impl SpecOptions {
+    /// Were no options given?
+    pub fn is_empty(&self) -> bool {
+        *self == SpecOptions::default()
+    }
+
+    /// The classes needed by `respect_states`, if it was given, for building
+    /// chunks from `prefix_population`.
+    pub fn state_classes(&self, prefix_population: &PrefixPopulation) -> Option<StateClasses> {
+        if self.respect_states {
+            Some(StateClasses::new(prefix_population))
+        } else {
+            None
+        }
+    }
+
+    /// Apply these options to `classifier`, except for `respect_states` and
+    /// `min_pop`, which need to be used when building it.
+    pub fn apply(&self, mut classifier: Classifier) -> Result<Classifier> {
+        if let Some(ref naming) = self.naming {
+            classifier = classifier.with_naming(naming)?;
+        }
+        if let Some(ref chunk_id) = self.unknown_chunk {
+            classifier.add_unknown_chunk(chunk_id.clone())?;
+        }
+        if let Some(ref chunk_id) = self.new_zip_chunk {
+            classifier = classifier.with_new_zip_chunk(chunk_id)?;
+        }
+        if self.new_zip_fallback {
+            classifier = classifier.with_new_zip_fallback()?;
+        }
+        Ok(classifier)
+    }
+}
+
+/// Write these options the way they're parsed, like
+/// `naming=int,new_zip_fallback`. `Naming::Hashed` and `Naming::Template`
+/// can't be written in a spec, so they're left out.
+impl fmt::Display for SpecOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut options = vec![];
+        match self.naming {
+            Some(Naming::Prefix) => options.push("naming=prefix".to_owned()),
+            Some(Naming::Integer) => options.push("naming=int".to_owned()),
+            Some(Naming::Sortable) => options.push("naming=sortable".to_owned()),
+            Some(Naming::Hashed(_)) | Some(Naming::Template(_)) | None => {}
+        }
+        if let Some(ref chunk_id) = self.unknown_chunk {
+            options.push(format!("unknown_chunk={}", chunk_id));
+        }
+        if let Some(ref chunk_id) = self.new_zip_chunk {
+            options.push(format!("new_zip_chunk={}", chunk_id));
+        }
+        if self.new_zip_fallback {
+            options.push("new_zip_fallback".to_owned());
+        }
+        if self.respect_states {
+            options.push("respect_states".to_owned());
+        }
+        if let Some(min_pop) = self.min_pop {
+            options.push(format!("min_pop={}", min_pop));
+        }
+        write!(f, "{}", options.join(","))
+    }
+}
+
+/// Compute the HMAC-SHA256 of `message` using `key`, as described in RFC
+/// 2104.
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block_key = [0; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block_key[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block_key.iter().map(|k| k ^ byte).collect::<Vec<_>>();
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Compute the population of each chunk. If one of our prefixes is inside
+/// another, like `"902"` and `"9021"`, we don't count the population of the
+/// longer prefix towards the shorter one, because zip codes always use the
+/// longest matching prefix.
+fn population_for_chunks(chunk_id_for_prefix: &HashMap<String, String>,
+                         prefix_population: &PrefixPopulation)
+                         -> HashMap<String, u64> {
+    let mut population_for_prefix = chunk_id_for_prefix
+        .keys()
+        .map(|p| (&p[..], prefix_population.lookup(p) as i64))
+        .collect::<HashMap<&str, i64>>();
+    for prefix in chunk_id_for_prefix.keys() {
+        let parent = (0..prefix.len())
+            .rev()
+            .map(|i| &prefix[..i])
+            .find(|p| chunk_id_for_prefix.contains_key(*p));
+        if let Some(parent) = parent {
+            let pop = prefix_population.lookup(prefix) as i64;
+            *population_for_prefix
+                 .get_mut(parent)
+                 .expect("parent should have a population") -= pop;
+        }
+    }
+    let mut population_for_chunk = HashMap::new();
+    for (prefix, pop) in population_for_prefix {
+        *population_for_chunk
+             .entry(chunk_id_for_prefix[prefix].clone())
+             .or_insert(0) += pop as u64;
+    }
+    population_for_chunk
+}
+
+/// A description of a chunk, as returned by `Classifier::chunk_summaries`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkSummary {
+    /// The chunk ID.
+    pub id: String,
+    /// The dense integer index of the chunk. See `Classifier::chunk_index`.
+    pub index: usize,
+    /// The population of the chunk.
+    pub population: u64,
+    /// The zip code prefixes in the chunk, in sorted order.
+    pub prefixes: Vec<String>,
+}
+
+/// The result of `Classifier::check_population`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PopulationCheck {
+    /// The total population of the data used to build our chunks.
+    pub source_population: u64,
+    /// The total population of our chunks.
+    pub chunk_population: u64,
+    /// The chunks whose population isn't the total population of their zip
+    /// codes, in chunk index order.
+    pub mismatched_chunks: Vec<ChunkPopulationMismatch>,
+}
+
+impl PopulationCheck {
+    /// Is all of our population accounted for, in the right chunks?
+    pub fn is_conserved(&self) -> bool {
+        self.source_population == self.chunk_population &&
+        self.mismatched_chunks.is_empty()
+    }
+}
+
+/// A chunk whose population doesn't match its zip codes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkPopulationMismatch {
+    /// The chunk ID.
+    pub chunk_id: String,
+    /// The population reported for the chunk.
+    pub population: u64,
+    /// The total population of the zip codes in the chunk.
+    pub zip_population: u64,
+}
+
+/// A manual change made to a set of chunks using a `ChunkPlan`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "adjustment", rename_all = "snake_case")]
+pub enum Adjustment {
+    /// The zip codes in chunk `from` were moved into chunk `into`.
+    Merge { into: String, from: String },
+    /// The zip codes in `chunk` starting with `prefix` were moved into a new
+    /// chunk named `new_chunk`.
+    Split {
+        chunk: String,
+        prefix: String,
+        new_chunk: String,
+    },
+}
+
+/// A set of chunks which can be adjusted by hand before building a new
+/// classifier, for territory planners who want to merge or split a few
+/// chunks. Create one using `Classifier::plan`.
+///
+/// ```
+/// # #[cfg(feature = "data-zip2010")] {
+/// use geochunk::zip2010::Classifier;
+///
+/// let mut plan = Classifier::new(250_000).plan();
+/// plan.merge("902_0", "902_1").unwrap();
+/// plan.split("902_0", "90210", "beverly_hills").unwrap();
+/// let classifier = plan.build();
+/// assert_eq!(classifier.chunk_for("90210"), Some("beverly_hills"));
+/// assert_eq!(classifier.metadata().adjustments.len(), 2);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ChunkPlan {
+    /// The classifier we started with.
+    classifier: Classifier,
+    /// Map from zip code prefixes to chunk IDs.
+    chunk_id_for_prefix: HashMap<String, String>,
+    /// Our chunk IDs, in order.
+    chunk_ids: Vec<String>,
+    /// The changes we've made.
+    adjustments: Vec<Adjustment>,
+}
+
+impl Classifier {
+    /// Start planning manual changes to our chunks.
+    pub fn plan(&self) -> ChunkPlan {
+        let data = &self.data;
+        ChunkPlan {
+            classifier: self.clone(),
+            chunk_id_for_prefix: data.chunk_id_for_prefix.clone(),
+            chunk_ids: data.chunk_ids
+                .iter()
+                .filter(|&id| !data.is_extra_chunk(id))
+                .cloned()
+                .collect(),
+            adjustments: data.adjustments.clone(),
+        }
+    }
+}
+
+impl ChunkPlan {
+    /// Make sure `chunk_id` is one of our chunks.
+    fn check_chunk(&self, chunk_id: &str) -> Result<()> {
+        if self.chunk_ids.iter().any(|id| id == chunk_id) {
+            Ok(())
+        } else {
+            Err(format!("no chunk {:?} to adjust", chunk_id).into())
+        }
+    }
+
+    /// Move all the zip codes in chunk `from` into chunk `into`, and remove
+    /// `from`.
+    pub fn merge(&mut self, into: &str, from: &str) -> Result<()> {
+        self.check_chunk(into)?;
+        self.check_chunk(from)?;
+        if into == from {
+            return Err(format!("cannot merge chunk {:?} with itself", into).into());
+        }
+        for chunk_id in self.chunk_id_for_prefix.values_mut() {
+            if chunk_id == from {
+                *chunk_id = into.to_owned();
+            }
+        }
+        self.chunk_ids.retain(|id| id != from);
+        self.adjustments
+            .push(Adjustment::Merge {
+                      into: into.to_owned(),
+                      from: from.to_owned(),
+                  });
+        Ok(())
+    }
+
+    /// Move all the zip codes in `chunk` which start with `prefix` into a
+    /// new chunk named `new_chunk`. `prefix` may be longer than the prefixes
+    /// used to build `chunk`, so a chunk can be split anywhere.
+    pub fn split(&mut self, chunk: &str, prefix: &str, new_chunk: &str) -> Result<()> {
+        self.check_chunk(chunk)?;
+        let valid_prefix =
+            prefix.len() <= ZIP_CODE_LENGTH && prefix.bytes().all(|b| b.is_ascii_digit());
+        if !valid_prefix {
+            return Err(format!("invalid zip code prefix {:?}", prefix).into());
+        }
+        if self.chunk_ids.iter().any(|id| id == new_chunk) ||
+            self.classifier.data.is_extra_chunk(new_chunk)
+        {
+            return Err(format!("chunk {:?} already exists", new_chunk).into());
+        }
+
+        // Find any of our prefixes which start with `prefix`, and check
+        // whether `prefix` is currently covered by a shorter prefix in
+        // `chunk`.
+        let to_move = self.chunk_id_for_prefix
+            .iter()
+            .filter(|&(p, id)| id == chunk && p.starts_with(prefix))
+            .map(|(p, _)| p.clone())
+            .collect::<Vec<_>>();
+        let covered = (0..prefix.len())
+            .rev()
+            .map(|i| &prefix[..i])
+            .find(|p| self.chunk_id_for_prefix.contains_key(*p))
+            .is_some_and(|p| self.chunk_id_for_prefix[p] == chunk);
+        if to_move.is_empty() && !covered {
+            return Err(format!("chunk {:?} contains no zip codes starting with {:?}",
+                               chunk,
+                               prefix)
+                               .into());
+        }
+        let prefix_count = self.chunk_id_for_prefix
+            .values()
+            .filter(|&id| id == chunk)
+            .count();
+        if !covered && to_move.len() == prefix_count {
+            return Err(format!("splitting chunk {:?} on {:?} would leave it empty",
+                               chunk,
+                               prefix)
+                               .into());
+        }
+
+        // Move those prefixes, and add `prefix` to our table if necessary so
+        // that it takes precedence over the shorter prefix.
+        for p in to_move {
+            self.chunk_id_for_prefix.insert(p, new_chunk.to_owned());
+        }
+        if covered {
+            self.chunk_id_for_prefix
+                .insert(prefix.to_owned(), new_chunk.to_owned());
+        }
+        self.chunk_ids.push(new_chunk.to_owned());
+        self.adjustments
+            .push(Adjustment::Split {
+                      chunk: chunk.to_owned(),
+                      prefix: prefix.to_owned(),
+                      new_chunk: new_chunk.to_owned(),
+                  });
+        Ok(())
+    }
+
+    /// Build a classifier using our adjusted chunks. Existing chunks keep
+    /// their relative order, and new chunks are added at the end.
+    pub fn build(self) -> Classifier {
+        let old = &self.classifier.data;
+        let mut population_for_chunk =
+            population_for_chunks(&self.chunk_id_for_prefix, &old.prefix_population);
+        let mut chunk_ids = self.chunk_ids;
+        for extra_chunk in old.chunk_ids.iter().filter(|id| old.is_extra_chunk(id)) {
+            population_for_chunk.insert(extra_chunk.clone(), 0);
+            chunk_ids.push(extra_chunk.clone());
+        }
+        let index_for_chunk = chunk_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.clone(), i))
+            .collect();
+        Classifier {
+            data: Arc::new(ClassifierData {
+                               weight_name: old.weight_name.clone(),
+                               census_data: old.census_data.clone(),
+                               target_population: old.target_population,
+                               min_population: old.min_population,
+                               chunk_id_for_prefix: self.chunk_id_for_prefix,
+                               population_for_chunk,
+                               chunk_ids,
+                               index_for_chunk,
+                               prefix_population: old.prefix_population.clone(),
+                               land_area: old.land_area.clone(),
+                               zcta_points: old.zcta_points.clone(),
+                               unknown_chunk: old.unknown_chunk.clone(),
+                               new_zip_chunk: old.new_zip_chunk.clone(),
+                               new_zip_fallback: old.new_zip_fallback,
+                               zip_patch: old.zip_patch.clone(),
+                               patched_zips: old.patched_zips.clone(),
+                               adjustments: self.adjustments,
+                               zip_table: OnceLock::new(),
+                               fallback_table: OnceLock::new(),
+                               centroid_table: OnceLock::new(),
+                           }),
+        }
+    }
+}
+
+// Directly include our zip code population data in our application binary
+// for ease of distribution and packaging. This is generated from
+// `zip2010.csv` by our build script, so that we don't need to parse any CSV
+// at runtime.
+#[cfg(feature = "data-zip2010")]
+include!(concat!(env!("OUT_DIR"), "/census2010.rs"));
+
+// The location of each zip code in our census data, for finding chunk
+// centroids. This comes from the `zipcodes` project's zip code database
+// (https://github.com/seanpianka/zipcodes, MIT license), and is generated
+// from `zip_centroids.csv` by our build script.
+#[cfg(feature = "data-zip2010")]
+include!(concat!(env!("OUT_DIR"), "/zip_centroids.rs"));
+
+/// Iterate over the zip codes and populations in our built-in census data.
+#[cfg(feature = "data-zip2010")]
+fn census2010_rows() -> impl Iterator<Item = (String, u64)> {
+    CENSUS2010_ROWS
+        .iter()
+        .map(|&(zip, pop)| (zip.to_owned(), pop))
+}
+
+/// A way of dividing zip code prefixes into classes, such as
+/// `DensityClasses`, so that we can build chunks which never mix prefixes
+/// from different classes.
+pub trait PrefixClasses {
+    /// A short name describing these classes, which we add to the name of
+    /// our weights for use in column names, or `None` if the name of our
+    /// weights already describes them.
+    fn name(&self) -> Option<String>;
+
+    /// The class of a zip code prefix, or `None` if it may be grouped with
+    /// prefixes of any class, such as a prefix with no population.
+    fn class_for_prefix(&self, prefix: &str) -> Option<usize>;
+}
+
+/// The population (or other weight) associated with each zip code prefix.
+#[derive(Clone, Debug)]
+pub struct PrefixPopulation {
+    maps: PrefixPopulationMaps,
+}
+
+impl PrefixPopulation {
+    /// Load the 2010 census population data built into our executable.
+    #[cfg(feature = "data-zip2010")]
+    pub fn census2010() -> PrefixPopulation {
+        PrefixPopulation::from_weights(census2010_rows())
+    }
+
+    /// Build prefix populations from a list of 5-digit zip codes and weights.
+    pub fn from_weights<I>(weights: I) -> PrefixPopulation
+    where
+        I: IntoIterator<Item = (String, u64)>,
+    {
+        let mut prefix_population = PrefixPopulation { maps: PrefixPopulationMaps::default() };
+        for (zip, pop) in weights {
+            prefix_population.add_weight(&zip, pop);
+        }
+        prefix_population
+    }
+
+    /// Add `pop` to the population of a 5-digit zip code, and each of its
+    /// prefixes.
+    fn add_weight(&mut self, zip: &str, pop: u64) {
+        assert_eq!(zip.len(), ZIP_CODE_LENGTH, "invalid zip code {:?}", zip);
+
+        // For each prefix of this zip code, increment the population of
+        // that prefix.
+        for prefix_len in 0..self.maps.len() {
+            // This is a very long way of writing `(... ||= 0) += pop`.
+            match self.maps[prefix_len].entry(zip[0..prefix_len].to_owned()) {
+                Entry::Vacant(vacant) => {
+                    vacant.insert(pop);
+                }
+                Entry::Occupied(mut occupied) => {
+                    *occupied.get_mut() += pop;
+                }
+            }
+        }
     }
 
     /// Look up the population of a zip code prefix.  Calling this function
     /// with invalid data will panic, since this is intended to be called using
     /// purely compile-time data.
-    fn lookup(&self, prefix: &str) -> u64 {
+    pub fn lookup(&self, prefix: &str) -> u64 {
         if prefix.len() > ZIP_CODE_LENGTH {
             panic!("Invalid zip code prefix: {}", prefix);
         }
@@ -219,29 +2443,77 @@ impl PrefixPopulation {
             .unwrap_or_default()
     }
 
+    /// Does `prefix` contain populated zip codes from more than one class?
+    /// `found` holds the class of the first populated zip code we've seen.
+    fn mixes_classes(&self,
+                     prefix: &str,
+                     classes: &dyn PrefixClasses,
+                     found: &mut Option<usize>)
+                     -> bool {
+        if self.lookup(prefix) == 0 {
+            return false;
+        }
+        if prefix.len() == ZIP_CODE_LENGTH {
+            return match (classes.class_for_prefix(prefix), *found) {
+                (Some(class), Some(other)) => class != other,
+                (Some(class), None) => {
+                    *found = Some(class);
+                    false
+                }
+                (None, _) => false,
+            };
+        }
+        (0..10).any(|digit| {
+                        let child_prefix = format!("{}{}", prefix, digit);
+                        self.mixes_classes(&child_prefix, classes, found)
+                    })
+    }
+
     // Build zip code chunks based on population data.
     fn build_chunks_recursive(&self,
                               target_population: u64,
+                              min_population: u64,
                               prefix: &str,
-                              chunk_id_for_prefix: &mut HashMap<String, String>) {
+                              classes: Option<&dyn PrefixClasses>,
+                              chunk_id_for_prefix: &mut HashMap<String, String>,
+                              mut log: Option<&mut Vec<ConstructionStep>>) {
         let prefix_pop = self.lookup(prefix);
-        if prefix_pop <= target_population || prefix.len() == ZIP_CODE_LENGTH {
-            // We're small enough to fill a chunk on our own, or we can't be
-            // split any further.
+        let mixed = |p: &str| classes.is_some_and(|c| self.mixes_classes(p, c, &mut None));
+        if (prefix_pop <= target_population && !mixed(prefix)) ||
+           prefix.len() == ZIP_CODE_LENGTH {
+            // We're small enough to fill a chunk on our own, without mixing
+            // classes, or we can't be split any further.
             trace!("Mapping {} (pop {}) to {}", prefix, prefix_pop, prefix);
+            if let Some(log) = log {
+                log.push(ConstructionStep::Chunk {
+                             prefix: prefix.to_owned(),
+                             population: prefix_pop,
+                         });
+            }
             chunk_id_for_prefix.insert(prefix.to_owned(), prefix.to_owned());
         } else {
+            if let Some(ref mut log) = log {
+                log.push(ConstructionStep::Split {
+                             prefix: prefix.to_owned(),
+                             population: prefix_pop,
+                         });
+            }
+
             // Check each possible "child" of this prefix, recursing for any
-            // that are greater than or equal to our target size.  Collect
-            // the smaller children in `leftovers`.
+            // that are greater than or equal to our target size, or which
+            // contain zip codes of more than one class.  Collect the smaller
+            // children in `leftovers`.
             let mut leftovers = vec![];
             for digit in 0..10 {
                 let child_prefix = format!("{}{}", prefix, digit);
                 let child_pop = self.lookup(&child_prefix);
-                if child_pop >= target_population {
+                if child_pop >= target_population || mixed(&child_prefix) {
                     self.build_chunks_recursive(target_population,
+                                                min_population,
                                                 &child_prefix,
-                                                chunk_id_for_prefix);
+                                                classes,
+                                                chunk_id_for_prefix,
+                                                log.as_deref_mut());
                 } else {
                     leftovers.push(child_prefix);
                 }
@@ -250,23 +2522,544 @@ impl PrefixPopulation {
             // Group our leftovers into chunks with names like `{prefix}_{i}`.
             // It's important to include the zero-length chunks here, so that
             // post-2010 zip codes can be placed in some chunk.
-            let mut chunk_idx: u64 = 0;
-            let mut chunk_pop: u64 = 0;
+            //
+            // If we need to keep classes apart, we group leftovers by class
+            // first. This is a stable sort, so the order of leftovers within
+            // each class is unchanged. Leftovers without a class sort first,
+            // and may join a chunk of any class.
+            let class_for = |p: &str| classes.and_then(|c| c.class_for_prefix(p));
+            leftovers.sort_by_key(|p| class_for(p));
+            let mut groups: Vec<LeftoverGroup> = vec![];
             for child_prefix in leftovers {
                 let child_pop = self.lookup(&child_prefix);
+                let child_class = class_for(&child_prefix);
                 assert!(child_pop < target_population);
-                if chunk_pop + child_pop > target_population {
-                    chunk_idx += 1;
-                    chunk_pop = 0;
+                let fits = groups.last().is_some_and(|group| {
+                    group.population + child_pop <= target_population &&
+                    group.accepts(child_class)
+                });
+                if !fits {
+                    groups.push(LeftoverGroup::default());
                 }
-                chunk_pop += child_pop;
+                let group = groups.last_mut().expect("should have a group");
+                group.population += child_pop;
+                group.class = group.class.or(child_class);
+                group.prefixes.push(child_prefix);
+            }
+            merge_small_groups(&mut groups, min_population);
+
+            for (chunk_idx, group) in groups.into_iter().enumerate() {
                 let chunk_id = format!("{}_{}", prefix, chunk_idx);
-                trace!("Mapping {} (pop {}) to {}",
-                       child_prefix,
-                       child_pop,
-                       chunk_id);
-                chunk_id_for_prefix.insert(child_prefix, chunk_id);
+                for child_prefix in group.prefixes {
+                    let child_pop = self.lookup(&child_prefix);
+                    trace!("Mapping {} (pop {}) to {}",
+                           child_prefix,
+                           child_pop,
+                           chunk_id);
+                    if let Some(ref mut log) = log {
+                        log.push(ConstructionStep::Pack {
+                                     prefix: child_prefix.clone(),
+                                     population: child_pop,
+                                     chunk: chunk_id.clone(),
+                                 });
+                    }
+                    chunk_id_for_prefix.insert(child_prefix, chunk_id.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Leftover prefixes which we're packing into a single chunk.
+#[derive(Debug, Default)]
+struct LeftoverGroup {
+    /// The total population of `prefixes`.
+    population: u64,
+    /// The class of our prefixes, if any of them has one.
+    class: Option<usize>,
+    /// Our prefixes, in order.
+    prefixes: Vec<String>,
+}
+
+impl LeftoverGroup {
+    /// Can we hold prefixes of class `class` without mixing classes?
+    fn accepts(&self, class: Option<usize>) -> bool {
+        self.class.is_none() || class.is_none() || self.class == class
+    }
+}
+
+/// Merge each group with less than `min_population` into the smaller of its
+/// neighbors which it can join without mixing classes, until no more groups
+/// can be merged.
+fn merge_small_groups(groups: &mut Vec<LeftoverGroup>, min_population: u64) {
+    loop {
+        let merge = (0..groups.len()).find_map(|i| {
+            if groups[i].population >= min_population {
+                return None;
+            }
+            let neighbors = [i.checked_sub(1), Some(i + 1)];
+            neighbors
+                .iter()
+                .filter_map(|&n| n)
+                .filter(|&n| n < groups.len() && groups[n].accepts(groups[i].class))
+                .min_by_key(|&n| groups[n].population)
+                .map(|n| (i.min(n), i.max(n)))
+        });
+        let (first, second) = match merge {
+            Some(pair) => pair,
+            None => return,
+        };
+        let second = groups.remove(second);
+        let first = &mut groups[first];
+        first.population += second.population;
+        first.class = first.class.or(second.class);
+        first.prefixes.extend(second.prefixes);
+    }
+}
+
+#[test]
+fn computes_hmac_sha256() {
+    let _ = env_logger::init();
+    // Test case 2 from RFC 4231.
+    let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+    assert_eq!(mac.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+               "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn builds_classifiers_with_options() {
+    let _ = env_logger::init();
+    let default = ClassifierBuilder::new().target_population(250000).build().unwrap();
+    assert_eq!(default.chunk_for("90210"), Some("902_0"));
+
+    let integer = ClassifierBuilder::new()
+        .target_population(250000)
+        .naming(Naming::Integer)
+        .unknown_policy(UnknownPolicy::Chunk("unknown".to_owned()))
+        .build()
+        .unwrap();
+    let idx = default.chunk_index("902_0").unwrap();
+    assert_eq!(integer.chunk_for("90210"), Some(&idx.to_string()[..]));
+    assert_eq!(integer.chunk_index(&idx.to_string()), Some(idx));
+    assert_eq!(integer.chunk_population(&idx.to_string()),
+               default.chunk_population("902_0"));
+    assert_eq!(integer.chunk_for("N/A"), Some("unknown"));
+    assert_eq!(integer.chunk_index("unknown"), Some(default.chunk_ids().len()));
+    assert_eq!(integer.chunk_population("unknown"), Some(0));
+
+    let hashed = ClassifierBuilder::new()
+        .target_population(250000)
+        .naming(Naming::Hashed(b"secret".to_vec()))
+        .build()
+        .unwrap();
+    let chunk_id = hashed.chunk_for("90210").unwrap();
+    assert_eq!(chunk_id.len(), 16);
+    assert_eq!(hashed.chunk_index(chunk_id), Some(idx));
+    assert_eq!(hashed.chunk_population(chunk_id), default.chunk_population("902_0"));
+    let other_salt = ClassifierBuilder::new()
+        .target_population(250000)
+        .naming(Naming::Hashed(b"other".to_vec()))
+        .build()
+        .unwrap();
+    assert_ne!(other_salt.chunk_for("90210"), Some(chunk_id));
+
+    let sortable = ClassifierBuilder::new()
+        .target_population(250000)
+        .naming(Naming::Sortable)
+        .build()
+        .unwrap();
+    let width = (default.chunk_ids().len() - 1).to_string().len();
+    assert_eq!(sortable.chunk_for("90210"), Some(&format!("{:0w$}", idx, w = width)[..]));
+    assert!(sortable.chunk_ids().iter().all(|id| id.len() == width));
+    assert!(sortable.chunk_ids().windows(2).all(|w| w[0] < w[1]));
+
+    let template = "{state}-{prefix}-{index:04}".parse().unwrap();
+    let templated = ClassifierBuilder::new()
+        .target_population(250000)
+        .naming(Naming::Template(template))
+        .build()
+        .unwrap();
+    assert_eq!(templated.chunk_for("90210"), Some(&format!("CA-902-{:04}", idx)[..]));
+    assert_eq!(templated.chunk_index(&format!("CA-902-{:04}", idx)), Some(idx));
+    let duplicates = ClassifierBuilder::new()
+        .target_population(250000)
+        .naming(Naming::Template("{state}".parse().unwrap()))
+        .build();
+    assert!(duplicates.is_err());
+
+    let new_zips = ClassifierBuilder::new()
+        .target_population(250000)
+        .new_zip_chunk("NEW")
+        .build()
+        .unwrap();
+    assert!(new_zips.is_new_zip("00501"));
+    assert!(!new_zips.is_new_zip("90210"));
+    assert!(!new_zips.is_new_zip("N/A"));
+    assert_eq!(new_zips.chunk_for("00501"), Some("NEW"));
+    assert_eq!(new_zips.chunk_for("90210"), Some("902_0"));
+    assert_eq!(new_zips.chunk_population("NEW"), Some(0));
+    assert_eq!(new_zips.chunk_index("NEW"), Some(default.chunk_ids().len()));
+    assert!(new_zips.with_new_zip_chunk("NEWER").is_err());
+
+    let patch = ZipPatch::load(&mut "zip,population,parent\n00501,1200,\n07799,50,90210\n"
+                                         .as_bytes())
+            .unwrap();
+    let patched = default.clone().with_zip_patch(&patch).unwrap();
+    assert_eq!(patched.chunk_for("07799"), Some("902_0"));
+    assert_eq!(patched.chunk_for("07712"), default.chunk_for("07712"));
+    assert_eq!(patched.chunk_for("00501"), default.chunk_for("00501"));
+    assert_eq!(patched.chunk_population("902_0"),
+               default.chunk_population("902_0").map(|p| p + 50));
+    assert!(!patched.is_new_zip("00501"));
+    assert_eq!(patched.metadata().zip_patch, Some(patch.info()));
+    assert!(patched.with_zip_patch(&patch).is_err());
+    let existing = ZipPatch::load(&mut "zip,population,parent\n90210,1,\n".as_bytes()).unwrap();
+    assert!(default.clone().with_zip_patch(&existing).is_err());
+
+    assert!(ClassifierBuilder::new().build().is_err());
+    assert!(ClassifierBuilder::new()
+                .target_population(250000)
+                .unknown_policy(UnknownPolicy::Chunk("902_0".to_owned()))
+                .build()
+                .is_err());
+}
+
+#[test]
+fn never_mixes_classes_in_one_chunk() {
+    let _ = env_logger::init();
+
+    /// Put zip codes ending in an even digit in one class, and those ending
+    /// in an odd digit in another.
+    struct Parity;
+
+    impl PrefixClasses for Parity {
+        fn name(&self) -> Option<String> {
+            None
+        }
+
+        fn class_for_prefix(&self, prefix: &str) -> Option<usize> {
+            if prefix.len() == ZIP_CODE_LENGTH {
+                prefix[4..].parse::<usize>().ok().map(|d| d % 2)
+            } else {
+                None
+            }
+        }
+    }
+
+    // Everything would fit in one chunk, if it weren't for the classes.
+    let weights = vec![("10001".to_owned(), 1), ("10002".to_owned(), 1), ("10003".to_owned(), 1)];
+    let population = PrefixPopulation::from_weights(weights);
+    let classifier = Classifier::from_prefix_population("test", &population, 100, Some(&Parity));
+    assert_ne!(classifier.chunk_for("10001"), classifier.chunk_for("10002"));
+    assert_eq!(classifier.chunk_for("10001"), classifier.chunk_for("10003"));
+    let unclassed = Classifier::from_prefix_population("test", &population, 100, None);
+    assert_eq!(unclassed.chunk_for("10001"), unclassed.chunk_for("10002"));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn classifiers_are_cheap_to_clone_and_thread_safe() {
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Classifier>();
+
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let clone = classifier.clone();
+    assert!(Arc::ptr_eq(&classifier.data, &clone.data));
+    let chunk = thread::spawn(move || clone.chunk_for("90210").map(|c| c.to_owned()))
+        .join()
+        .unwrap();
+    assert_eq!(chunk.as_ref().map(|c| &c[..]), classifier.chunk_for("90210"));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn memoizes_shared_classifiers() {
+    let _ = env_logger::init();
+    let first = classifier(250000);
+    assert_eq!(first.chunk_for("90210"), Some("902_0"));
+    assert!(::std::ptr::eq(first, classifier(250000)));
+    assert!(!::std::ptr::eq(first, classifier(500000)));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn caches_recently_used_classifiers() {
+    let _ = env_logger::init();
+    let first = cached_classifier(250000);
+    assert_eq!(first.chunk_for("90210"), Some("902_0"));
+    assert!(Arc::ptr_eq(&first.data, &cached_classifier(250000).data));
+    assert!(!Arc::ptr_eq(&first.data, &cached_classifier(500000).data));
+
+    // Once enough other target populations have been used, we build a new
+    // classifier, but ones we've handed out keep working.
+    for i in 0..CACHED_CLASSIFIERS as u64 {
+        cached_classifier(1_000_000 + i);
+    }
+    assert!(!Arc::ptr_eq(&first.data, &cached_classifier(250000).data));
+    assert_eq!(first.chunk_for("90210"), Some("902_0"));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn logs_chunk_construction() {
+    let _ = env_logger::init();
+    let population = PrefixPopulation::census2010();
+    let (classifier, log) =
+        Classifier::from_prefix_population_with_log("zip2010", &population, 250000, None);
+    assert_eq!(log.metadata, classifier.metadata());
+    assert_eq!(log.steps[0],
+               ConstructionStep::Split {
+                   prefix: "".to_owned(),
+                   population: population.lookup(""),
+               });
+    let mut mapped = 0;
+    for step in &log.steps {
+        match *step {
+            ConstructionStep::Chunk { ref prefix, .. } => {
+                assert_eq!(classifier.data.chunk_id_for_prefix[prefix], *prefix);
+                mapped += 1;
             }
+            ConstructionStep::Pack { ref prefix, ref chunk, .. } => {
+                assert_eq!(classifier.data.chunk_id_for_prefix[prefix], *chunk);
+                mapped += 1;
+            }
+            ConstructionStep::Split { .. } => {}
         }
     }
+    assert_eq!(mapped, classifier.data.chunk_id_for_prefix.len());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn merges_and_splits_chunks() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    let pop = |c: &Classifier, id| c.chunk_population(id).unwrap();
+
+    let mut plan = classifier.plan();
+    plan.merge("902_0", "902_1").unwrap();
+    plan.split("902_0", "90210", "beverly_hills").unwrap();
+    assert!(plan.merge("902_0", "902_0").is_err());
+    assert!(plan.merge("902_0", "902_1").is_err());
+    assert!(plan.split("077_0", "902", "new").is_err());
+    assert!(plan.split("beverly_hills", "90210", "new").is_err());
+    assert!(plan.split("077_0", "077", "beverly_hills").is_err());
+    let adjusted = plan.build();
+
+    assert_eq!(adjusted.chunk_for("90210"), Some("beverly_hills"));
+    assert_eq!(adjusted.chunk_for("90211"), Some("902_0"));
+    assert_eq!(adjusted.chunk_population("902_1"), None);
+    assert_eq!(pop(&adjusted, "beverly_hills"),
+               classifier.prefix_population().lookup("90210"));
+    assert_eq!(pop(&adjusted, "902_0") + pop(&adjusted, "beverly_hills"),
+               pop(&classifier, "902_0") + pop(&classifier, "902_1"));
+    assert_eq!(adjusted.chunk_ids().len(), classifier.chunk_ids().len());
+    assert_eq!(adjusted.chunk_index("beverly_hills"),
+               Some(adjusted.chunk_ids().len() - 1));
+    assert_eq!(adjusted.metadata().adjustments,
+               vec![Adjustment::Merge {
+                        into: "902_0".to_owned(),
+                        from: "902_1".to_owned(),
+                    },
+                    Adjustment::Split {
+                        chunk: "902_0".to_owned(),
+                        prefix: "90210".to_owned(),
+                        new_chunk: "beverly_hills".to_owned(),
+                    }]);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn checks_population_conservation() {
+    let _ = env_logger::init();
+    let classifier = Classifier::new(250000);
+    assert_eq!(classifier.total_population(), 308739931);
+    let check = classifier.check_population();
+    assert!(check.is_conserved(), "{:?}", check);
+    assert_eq!(check.chunk_population, 308739931);
+
+    let mut plan = classifier.plan();
+    plan.split("902_0", "90210", "beverly_hills").unwrap();
+    assert!(plan.build().check_population().is_conserved());
+    let patch = "zip,population,parent\n07799,50,90210\n";
+    let patch = ZipPatch::load(&mut patch.as_bytes()).unwrap();
+    let patched = classifier.with_zip_patch(&patch).unwrap();
+    assert_eq!(patched.total_population(), 308739981);
+    assert!(patched.check_population().is_conserved());
+
+    let noise = LaplaceNoise::new(1000.0, Some(1)).unwrap();
+    let check = Classifier::new(250000)
+        .with_population_noise(&noise)
+        .check_population();
+    assert!(!check.is_conserved());
+    assert_eq!(check.source_population, 308739931);
+    let mismatch = &check.mismatched_chunks[0];
+    assert_ne!(mismatch.population, mismatch.zip_population);
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn falls_back_to_most_populous_chunk_for_new_zips() {
+    let _ = env_logger::init();
+    let default = Classifier::new(250000);
+    assert_eq!(default.chunk_for("01780"), Some("017_1"));
+    let fallback = default.clone().with_new_zip_fallback().unwrap();
+    assert_eq!(fallback.prefix_and_chunk_for("01780"), Some(("0178", "017_0")));
+    assert!(fallback.is_fallback_zip("01780"));
+    assert!(!default.is_fallback_zip("01780"));
+    assert_eq!(fallback.chunk_for("01760"), Some("017_1"));
+    assert!(!fallback.is_fallback_zip("01760"));
+    assert_eq!(fallback.chunk_indices(vec![Some("01780")]),
+               vec![fallback.chunk_index("017_0").map(|i| i as u32)]);
+    assert!(fallback.check_population().is_conserved());
+
+    // New zip codes whose first three digits have no population at all
+    // keep the chunk of their prefix.
+    assert!(default.is_new_zip("00501"));
+    assert_eq!(fallback.chunk_for("00501"), default.chunk_for("00501"));
+    assert!(!fallback.is_fallback_zip("00501"));
+
+    // The fallback follows renamed and adjusted chunks.
+    let renamed = fallback.clone().with_naming(&Naming::Integer).unwrap();
+    assert_eq!(renamed.chunk_for("01780"),
+               fallback.chunk_index("017_0").map(|i| i.to_string()).as_deref());
+    let mut plan = fallback.plan();
+    plan.split("017_1", "0175", "017_big").unwrap();
+    plan.merge("017_big", "017_0").unwrap();
+    assert_eq!(plan.build().chunk_for("01780"), Some("017_big"));
+
+    assert!(fallback.clone().with_new_zip_fallback().is_err());
+    assert!(fallback.with_new_zip_chunk("NEW").is_err());
+    assert!(ClassifierBuilder::new()
+                .target_population(250000)
+                .new_zip_chunk("NEW")
+                .new_zip_fallback()
+                .build()
+                .is_err());
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn describes_assignment_methods() {
+    let _ = env_logger::init();
+    let classifier = ClassifierBuilder::new()
+        .target_population(250000)
+        .unknown_policy(UnknownPolicy::Chunk("unknown".to_owned()))
+        .build()
+        .unwrap();
+    let method = |zip| classifier.assignment_method(zip).map(|m| m.name());
+    assert_eq!(method("02120"), Some("exact-5-digit"));
+    assert_eq!(method("90210-1234"), Some("prefix-fallback-4"));
+    assert_eq!(method("59001"), Some("prefix-fallback-3"));
+    assert_eq!(method("bad"), Some("unknown-policy"));
+    assert_eq!(Classifier::new(250000).assignment_method("bad"), None);
+
+    let fallback = Classifier::new(250000).with_new_zip_fallback().unwrap();
+    assert_eq!(fallback.assignment_method("01780"),
+               Some(AssignmentMethod::NewZipFallback));
+    let new_chunk = Classifier::new(250000).with_new_zip_chunk("NEW").unwrap();
+    assert_eq!(new_chunk.assignment_method("01780"),
+               Some(AssignmentMethod::NewZipChunk));
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn parses_spec_options() {
+    let _ = env_logger::init();
+    let options = "naming=int, unknown_chunk=bad,new_zip_fallback"
+        .parse::<SpecOptions>()
+        .unwrap();
+    assert_eq!(options,
+               SpecOptions {
+                   naming: Some(Naming::Integer),
+                   unknown_chunk: Some("bad".to_owned()),
+                   new_zip_chunk: None,
+                   new_zip_fallback: true,
+                   respect_states: false,
+                   min_pop: None,
+               });
+    assert_eq!(options.to_string(), "naming=int,unknown_chunk=bad,new_zip_fallback");
+    assert!("".parse::<SpecOptions>().unwrap().is_empty());
+    let sortable = "naming=sortable".parse::<SpecOptions>().unwrap();
+    assert_eq!(sortable.naming, Some(Naming::Sortable));
+    assert_eq!(sortable.to_string(), "naming=sortable");
+    for invalid in &["naming=hashed", "unknown_chunk", "new_zip_chunk=",
+                     "new_zip_fallback=yes", "new_zip_chunk=NEW,new_zip_fallback",
+                     "respect_states=yes", "min_pop", "min_pop=lots"] {
+        assert!(invalid.parse::<SpecOptions>().is_err(), "{:?}", invalid);
+    }
+
+    let built = ClassifierBuilder::new()
+        .target_population(250000)
+        .options(&options)
+        .build()
+        .unwrap();
+    let applied = options.apply(Classifier::new(250000)).unwrap();
+    for zip in &["90210", "01780", "bad"] {
+        assert_eq!(built.chunk_for(zip), applied.chunk_for(zip));
+    }
+    assert_eq!(applied.chunk_for("bad"), Some("bad"));
+    assert_eq!(applied.assignment_method("01780"),
+               Some(AssignmentMethod::NewZipFallback));
+    let idx = applied.chunk_index(applied.chunk_for("90210").unwrap()).unwrap();
+    assert_eq!(applied.chunk_for("90210"), Some(&idx.to_string()[..]));
+
+    let options = "naming=int,respect_states,min_pop=50000"
+        .parse::<SpecOptions>()
+        .unwrap();
+    assert!(options.respect_states);
+    assert_eq!(options.min_pop, Some(50000));
+    assert_eq!(options.to_string(), "naming=int,respect_states,min_pop=50000");
+}
+
+#[test]
+#[cfg(feature = "data-zip2010")]
+fn builds_chunks_within_states() {
+    let _ = env_logger::init();
+    let classifier = ClassifierBuilder::new()
+        .target_population(250000)
+        .respect_states()
+        .build()
+        .unwrap();
+    assert_eq!(classifier.geochunk_column_name(), "geochunk_zip2010_states_250000");
+    let mut state_for_chunk = HashMap::new();
+    for &(zip, pop) in CENSUS2010_ROWS {
+        if pop == 0 {
+            continue;
+        }
+        let chunk_id = classifier.chunk_for(zip).unwrap();
+        let state = state_for_zip(zip);
+        assert_eq!(*state_for_chunk.entry(chunk_id).or_insert(state),
+                   state,
+                   "{} in {}",
+                   zip,
+                   chunk_id);
+    }
+    assert!(ClassifierBuilder::new()
+                .target_population(250000)
+                .respect_states()
+                .density(DensityClasses::new(&[][..], vec![1000.0]))
+                .build()
+                .is_err());
+}
+
+#[test]
+fn merges_small_leftover_chunks() {
+    let _ = env_logger::init();
+    let population =
+        PrefixPopulation::from_weights((0..10).map(|i| (format!("1000{}", i), 30)));
+    let plain = Classifier::from_prefix_population("test", &population, 100, None);
+    assert_eq!(plain.chunk_for("10009"), Some("1000_3"));
+    assert_eq!(plain.chunk_population("1000_3"), Some(30));
+    let (merged, log) =
+        Classifier::from_prefix_population_with_minimum("test", &population, 100, 50, None);
+    assert_eq!(merged.chunk_population("1000_3"), None);
+    assert_eq!(merged.chunk_for("10009"), Some("1000_2"));
+    assert_eq!(merged.chunk_population("1000_2"), Some(120));
+    assert_eq!(log.metadata.min_population, Some(50));
+    assert_eq!(plain.metadata().min_population, None);
 }
@@ -0,0 +1,165 @@
+//! Patches describing zip codes created since the census, so that they can
+//! be given sensible chunks between census releases.
+//!
+//! A patch is a CSV file listing each new zip code, with either an
+//! estimated population, the existing zip code it was split from, or both:
+//!
+//! ```csv
+//! zip,population,parent
+//! 90299,1200,
+//! 07799,,07712
+//! ```
+//!
+//! A zip code with a `parent` is put in the same chunk as its parent. Any
+//! other zip code stays in the chunk its prefix was assigned to. Either way,
+//! its estimated population is added to that chunk. Patches never move any
+//! other zip code to a different chunk.
+
+use csv;
+#[cfg(test)]
+use env_logger;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::prelude::*;
+
+use errors::*;
+
+/// A zip code added by a `ZipPatch`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct PatchEntry {
+    /// The new 5-digit zip code.
+    pub zip: String,
+    /// The estimated population of this zip code, if known.
+    pub population: Option<u64>,
+    /// An existing zip code whose chunk this zip code should join, such as
+    /// the zip code it was split from.
+    pub parent: Option<String>,
+}
+
+/// A list of zip codes to add to our census data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZipPatch {
+    /// The zip codes we add.
+    entries: Vec<PatchEntry>,
+    /// The SHA-256 checksum of the patch file, in hex.
+    sha256: String,
+}
+
+/// A summary of a `ZipPatch`, which we include in chunk metadata.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatchInfo {
+    /// The number of zip codes in the patch.
+    pub zips: u64,
+    /// The number of zip codes with an estimated population.
+    pub estimated_zips: u64,
+    /// The number of zip codes with a parent.
+    pub parented_zips: u64,
+    /// The total estimated population of all the zip codes in the patch.
+    pub estimated_population: u64,
+    /// The SHA-256 checksum of the patch file, in hex.
+    pub sha256: String,
+}
+
+impl ZipPatch {
+    /// Read a patch from a CSV file.
+    pub fn load(input: &mut dyn Read) -> Result<ZipPatch> {
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+        let sha256 = Sha256::digest(&data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        let mut rdr = csv::Reader::from_reader(&data[..]);
+        let mut entries = vec![];
+        let mut seen = HashSet::new();
+        for row in rdr.deserialize() {
+            let entry: PatchEntry = row?;
+            check_zip(&entry.zip)?;
+            if !seen.insert(entry.zip.clone()) {
+                return Err(format!("zip code {} is patched more than once", entry.zip).into());
+            }
+            match entry.parent {
+                Some(ref parent) => check_zip(parent)?,
+                None if entry.population.is_none() => {
+                    return Err(format!("zip code {} needs a population or a parent",
+                                       entry.zip)
+                                       .into());
+                }
+                None => {}
+            }
+            entries.push(entry);
+        }
+        for entry in &entries {
+            if let Some(ref parent) = entry.parent {
+                if seen.contains(parent) {
+                    return Err(format!("the parent of zip code {} is also patched",
+                                       entry.zip)
+                                       .into());
+                }
+            }
+        }
+        Ok(ZipPatch { entries, sha256 })
+    }
+
+    /// The zip codes we add.
+    pub fn entries(&self) -> &[PatchEntry] {
+        &self.entries
+    }
+
+    /// Summarize this patch.
+    pub fn info(&self) -> PatchInfo {
+        PatchInfo {
+            zips: self.entries.len() as u64,
+            estimated_zips: self.entries
+                .iter()
+                .filter(|e| e.population.is_some())
+                .count() as u64,
+            parented_zips: self.entries
+                .iter()
+                .filter(|e| e.parent.is_some())
+                .count() as u64,
+            estimated_population: self.entries
+                .iter()
+                .filter_map(|e| e.population)
+                .sum(),
+            sha256: self.sha256.clone(),
+        }
+    }
+}
+
+/// Make sure `zip` is a 5-digit zip code.
+fn check_zip(zip: &str) -> Result<()> {
+    if zip.len() == 5 && zip.bytes().all(|b| b.is_ascii_digit()) {
+        Ok(())
+    } else {
+        Err(format!("invalid zip code {:?} in patch", zip).into())
+    }
+}
+
+#[test]
+fn loads_zip_patches() {
+    let _ = env_logger::init();
+    let patch = ZipPatch::load(&mut "zip,population,parent\n90299,1200,\n07799,,07712\n"
+                                         .as_bytes())
+            .unwrap();
+    assert_eq!(patch.entries()[1],
+               PatchEntry {
+                   zip: "07799".to_owned(),
+                   population: None,
+                   parent: Some("07712".to_owned()),
+               });
+    let info = patch.info();
+    assert_eq!((info.zips, info.estimated_zips, info.parented_zips), (2, 1, 1));
+    assert_eq!(info.estimated_population, 1200);
+    assert_eq!(info.sha256.len(), 64);
+
+    let invalid = ["zip,population,parent\n9029,1200,\n",
+                   "zip,population,parent\n90299,,\n",
+                   "zip,population,parent\n90299,1,\n90299,2,\n",
+                   "zip,population,parent\n90299,1,\n07799,,90299\n",
+                   "zip,population,parent\n90299,lots,\n"];
+    for input in &invalid {
+        assert!(ZipPatch::load(&mut input.as_bytes()).is_err(), "{:?}", input);
+    }
+}
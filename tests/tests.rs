@@ -1,3 +1,7 @@
+// Our oldest tests pass argument slices by reference, which newer versions
+// of clippy flag.
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 extern crate cli_test_dir;
 
 use cli_test_dir::*;
@@ -9,6 +13,20 @@ fn help_flag() {
     assert!(output.stdout_str().contains("geochunk csv"));
 }
 
+#[test]
+fn command_help_flag() {
+    let testdir = TestDir::new("geochunk", "command_help_flag");
+    let output = testdir.cmd().args(["csv", "--help"]).expect_success();
+    assert!(output.stdout_str().contains("--add-prefix"));
+    let output = testdir
+        .cmd()
+        .env("GEOCHUNK_FORMAT", "json")
+        .args(["plan", "--help"])
+        .expect_success();
+    assert!(output.stdout_str().contains("geochunk plan"));
+    assert!(!output.stdout_str().contains("--add-prefix"));
+}
+
 #[test]
 fn version_flag() {
     let testdir = TestDir::new("geochunk", "version_flag");
@@ -62,3 +80,2104 @@ J. Doe,90210,902_0
 H. Smith,,
 ");
 }
+
+#[test]
+fn export_soi_returns_uses_soi_file() {
+    let testdir = TestDir::new("geochunk", "export_soi_returns_uses_soi_file");
+    testdir.create_file("soi.csv",
+                        "\
+STATEFIPS,STATE,zipcode,agi_stub,N1,A00100
+36,NY,10001,1,300,9000
+36,NY,10002,1,300,6000
+11,DC,20001,1,100,4000
+");
+    let output = testdir
+        .cmd()
+        .args(["export", "--soi-file=soi.csv", "soi_returns", "250"])
+        .expect_success();
+    assert!(output
+                .stdout_str()
+                .contains("zip,geochunk_soi_returns_250"));
+    assert!(output.stdout_str().contains("10001,10001"));
+    assert!(output.stdout_str().contains("20001,_0"));
+}
+
+#[test]
+fn export_soi_requires_soi_file() {
+    let testdir = TestDir::new("geochunk", "export_soi_requires_soi_file");
+    let output = testdir
+        .cmd()
+        .args(["export", "soi_agi", "250000"])
+        .expect_failure();
+    assert!(output.stderr_str().contains("--soi-file"));
+}
+
+#[test]
+fn export_area_uses_gazetteer() {
+    let testdir = TestDir::new("geochunk", "export_area_uses_gazetteer");
+    testdir.create_file("gaz.txt",
+                        "\
+GEOID\tALAND\tAWATER
+59001\t2589988110\t0
+59002\t2589988110\t0
+83001\t258998811\t0
+");
+    let output = testdir
+        .cmd()
+        .args(["export", "--gazetteer=gaz.txt", "area", "50000"])
+        .expect_success();
+    assert!(output.stdout_str().contains("zip,geochunk_area_50000"));
+    assert!(output.stdout_str().contains("59001,59001"));
+    assert!(output.stdout_str().contains("83001,_0"));
+}
+
+#[test]
+fn export_zip2010_with_density_breaks() {
+    let testdir = TestDir::new("geochunk", "export_zip2010_with_density_breaks");
+    testdir.create_file("gaz.txt", "GEOID\tALAND\n10001\t1640604\n");
+    let output = testdir
+        .cmd()
+        .args(["export",
+               "--gazetteer=gaz.txt",
+               "--density-breaks=1000",
+               "zip2010",
+               "250000"])
+        .expect_success();
+    assert!(output
+                .stdout_str()
+                .contains("zip,geochunk_zip2010_density_1000_250000"));
+}
+
+#[test]
+fn crosswalk_between_zip2010_chunk_sizes() {
+    let testdir = TestDir::new("geochunk", "crosswalk_between_zip2010_chunk_sizes");
+    let output = testdir
+        .cmd()
+        .args(["crosswalk", "zip2010-250000", "zip2010-500000"])
+        .expect_success();
+    assert!(output
+                .stdout_str()
+                .starts_with("geochunk_zip2010_250000,geochunk_zip2010_500000,population,fraction\n"));
+    assert!(output.stdout_str().contains("\n010_0,01_0,210857,1\n"));
+}
+
+#[test]
+fn crosswalk_rejects_malformed_specs() {
+    let testdir = TestDir::new("geochunk", "crosswalk_rejects_malformed_specs");
+    let output = testdir
+        .cmd()
+        .args(["crosswalk", "zip2010", "zip2010-500000"])
+        .expect_failure();
+    assert!(output.stderr_str().contains("zip2010-250000"));
+}
+
+#[test]
+fn export_writes_versioned_metadata() {
+    let testdir = TestDir::new("geochunk", "export_writes_versioned_metadata");
+    testdir
+        .cmd()
+        .args(["export", "--metadata=meta.json", "zip2010", "250000"])
+        .expect_success();
+    testdir.expect_contains("meta.json", "\"version\": \"v1\"");
+    testdir.expect_contains("meta.json", "\"column\": \"geochunk_zip2010_250000\"");
+    testdir.expect_contains("meta.json", "\"total_population\": 308739931");
+}
+
+#[test]
+fn export_writes_construction_log() {
+    let testdir = TestDir::new("geochunk", "export_writes_construction_log");
+    testdir
+        .cmd()
+        .args(["export", "--construction-log=log.json", "zip2010", "250000"])
+        .expect_success();
+    testdir.expect_contains("log.json", "\"column\": \"geochunk_zip2010_250000\"");
+    testdir.expect_contains("log.json", "\"step\": \"split\"");
+    testdir.expect_contains("log.json", "\"chunk\": \"902_0\"");
+}
+
+#[test]
+fn expands_chunks_into_zip_codes() {
+    let testdir = TestDir::new("geochunk", "expands_chunks_into_zip_codes");
+    testdir.create_file("chunks.txt", "902_0\n\n");
+    let output = testdir
+        .cmd()
+        .args(["expand", "--chunks=chunks.txt", "zip2010-250000"])
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.starts_with("zip,geochunk_zip2010_250000\n"));
+    assert!(stdout.contains("\n90210,902_0\n"));
+    assert!(stdout.lines().skip(1).all(|line| line.ends_with(",902_0")));
+
+    let output = testdir
+        .cmd()
+        .args(["expand", "zip2010-250000"])
+        .output_with_stdin("902_0\nbogus\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("no chunk \"bogus\""));
+}
+
+#[test]
+fn verify_reports_rows_with_stale_chunks() {
+    let testdir = TestDir::new("geochunk", "verify_reports_rows_with_stale_chunks");
+    testdir.create_file("chunked.csv",
+                        "zip,geochunk_zip2010_250000\n90210,902_0\n07720,077_0\n");
+    let output = testdir
+        .cmd()
+        .args(["verify", "zip2010-250000", "zip", "chunked.csv"])
+        .expect_failure();
+    assert_eq!(output.stdout_str(),
+               "line,zip,expected,actual\n3,07720,077_0,077_1\n");
+    assert!(output.stderr_str().contains("1 rows"));
+
+    testdir.create_file("chunked.csv", "zip,chunk\n90210,902_0\n");
+    testdir
+        .cmd()
+        .args(["verify", "--output-column=chunk", "zip2010-250000", "zip", "chunked.csv"])
+        .expect_success();
+}
+
+#[test]
+fn counts_rows_in_each_chunk() {
+    let testdir = TestDir::new("geochunk", "counts_rows_in_each_chunk");
+    testdir.create_file("input.csv", "name,zip\nA,90210\nB,90211\nC,07712\nD,\n");
+    let output = testdir
+        .cmd()
+        .args(["count", "zip2010-250000", "zip", "input.csv"])
+        .output()
+        .expect("could not run geochunk");
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = output.stdout_str();
+    assert!(stdout.starts_with("geochunk_zip2010_250000,rows\n"));
+    assert!(stdout.contains("\n902_0,2\n"));
+    assert!(stdout.contains("\n077_0,1\n"));
+    assert!(stdout.contains("\n010_0,0\n"));
+    assert!(output.stderr_str().contains("Warning: 1 rows had no valid zip code"));
+
+    let output = testdir
+        .cmd()
+        .args(["count", "--json", "zip2010-250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert!(output.stdout_str().contains("\"902_0\": 1,"));
+}
+
+#[test]
+#[cfg(unix)]
+fn reads_input_files_which_are_pipes() {
+    let testdir = TestDir::new("geochunk", "reads_input_files_which_are_pipes");
+    // `/dev/stdin` is a pipe here, which reports a length of zero, so we
+    // must read it instead of mapping it.
+    let output = testdir
+        .cmd()
+        .args(["count", "zip2010-250000", "zip", "/dev/stdin"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert!(output.stdout_str().contains("\n902_0,1\n"));
+}
+
+#[test]
+fn skew_shows_most_and_least_represented_chunks() {
+    let testdir = TestDir::new("geochunk", "skew_shows_most_and_least_represented_chunks");
+    testdir.create_file("input.csv", "zip\n90210\n90211\n07712\n");
+    let output = testdir
+        .cmd()
+        .args(["skew", "--top=1", "zip2010-250000", "zip", "input.csv"])
+        .expect_success();
+    let stdout = output.stdout_str();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "skew,chunk,rows,population,ratio");
+    assert!(lines[1].starts_with("high,902_0,2,"));
+    assert!(lines[2].starts_with("low,"));
+    assert!(lines[2].ends_with(",0.000"));
+}
+
+#[test]
+fn copartitions_two_files_by_chunk() {
+    let testdir = TestDir::new("geochunk", "copartitions_two_files_by_chunk");
+    testdir.create_file("customers.csv", "name,zip\nJ. Doe,90210\nA. Jones,07712\n");
+    testdir.create_file("stores.csv", "store,postal_code\n1,90211\n2,90210\n");
+    let output = testdir
+        .cmd()
+        .args(["copartition",
+               "zip2010-250000",
+               "out",
+               "zip",
+               "customers.csv",
+               "postal_code",
+               "stores.csv"])
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.starts_with("chunk,customers.csv,stores.csv\n"));
+    assert!(stdout.contains("\n902_0,1,2\n"));
+    assert!(stdout.contains("\n077_0,1,0\n"));
+    testdir.expect_contains("out/902_0/customers.csv", "J. Doe,90210");
+    testdir.expect_contains("out/902_0/stores.csv", "1,90211\n2,90210");
+    testdir.expect_contains("out/077_0/stores.csv", "store,postal_code");
+    testdir.expect_contains("out/manifest.json", "\"path\": \"902_0/customers.csv\"");
+    testdir.expect_contains("out/manifest.json", "\"rows\": 2");
+}
+
+#[test]
+fn sorts_csv_output_by_chunk() {
+    let testdir = TestDir::new("geochunk", "sorts_csv_output_by_chunk");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--sort-by-chunk", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n07712\n90211\n07712\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000\n\
+                07712,077_0\n07712,077_0\n90210,902_0\n90211,902_0\n");
+}
+
+#[test]
+fn dedupes_rows_within_chunks() {
+    let testdir = TestDir::new("geochunk", "dedupes_rows_within_chunks");
+    let input = "email,zip\na@example.com,90210\na@example.com,90211\na@example.com,07712\n";
+    let output = testdir
+        .cmd()
+        .args(["dedupe", "--key=email", "--within-chunk", "zip2010-250000", "zip"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "email,zip,geochunk_zip2010_250000\n\
+                a@example.com,90210,902_0\na@example.com,07712,077_0\n");
+}
+
+#[test]
+fn splits_csv_output_into_shards() {
+    let testdir = TestDir::new("geochunk", "splits_csv_output_into_shards");
+    testdir
+        .cmd()
+        .args(["csv",
+               "--output=out.csv",
+               "--max-rows-per-file=2",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("zip\n90210\n07712\n90211\n")
+        .expect_success();
+    testdir.expect_contains("out-00000.csv",
+                            "zip,geochunk_zip2010_250000\n90210,902_0\n07712,077_0\n");
+    testdir.expect_contains("out-00001.csv",
+                            "zip,geochunk_zip2010_250000\n90211,902_0\n");
+
+    let output = testdir
+        .cmd()
+        .args(["csv", "--max-bytes-per-file=100", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("need --output"));
+}
+
+#[test]
+fn copartition_skips_completed_jobs() {
+    let testdir = TestDir::new("geochunk", "copartition_skips_completed_jobs");
+    testdir.create_file("customers.csv", "name,zip\nJ. Doe,90210\n");
+    testdir.create_file("stores.csv", "store,zip\n1,90211\n");
+    let args = ["copartition",
+                "--skip-if-done",
+                "zip2010-250000",
+                "out",
+                "zip",
+                "customers.csv",
+                "zip",
+                "stores.csv"];
+    let output = testdir.cmd().args(args).expect_success();
+    assert!(output.stdout_str().contains("902_0,1,1"));
+    let output = testdir.cmd().args(args).expect_success();
+    assert!(output.stderr_str().contains("already up to date"));
+    assert_eq!(output.stdout_str(), "");
+
+    testdir.create_file("stores.csv", "store,zip\n1,90211\n2,90210\n");
+    let output = testdir.cmd().args(args).expect_success();
+    assert!(output.stdout_str().contains("902_0,1,2"));
+
+    // Options which don't change our output don't make us run again.
+    let mut with_uring = args.to_vec();
+    with_uring.insert(1, "--io-uring");
+    let output = testdir.cmd().args(&with_uring).expect_success();
+    assert!(output.stderr_str().contains("already up to date"));
+
+    // Changing a data file named by an option also changes our output.
+    testdir.create_file("salt.txt", "first secret\n");
+    let mut salted = args.to_vec();
+    salted.insert(1, "--chunk-id-salt-file=salt.txt");
+    testdir.cmd().args(&salted).expect_success();
+    let output = testdir.cmd().args(&salted).expect_success();
+    assert!(output.stderr_str().contains("already up to date"));
+    testdir.create_file("salt.txt", "second secret\n");
+    let output = testdir.cmd().args(&salted).expect_success();
+    assert!(!output.stderr_str().contains("already up to date"));
+    assert!(output.stdout_str().contains(",1,2"));
+}
+
+#[test]
+fn adds_territory_column() {
+    let testdir = TestDir::new("geochunk", "adds_territory_column");
+    testdir.create_file("territories.toml",
+                        "West = [\"902_0\"]\nShore = [\"077_0\", \"078_0\"]\n");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--add-territory=territories.toml", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n07712\n02108\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_territory\n\
+                90210,902_0,West\n07712,077_0,Shore\n02108,021_0,\n");
+
+    testdir.create_file("bad.toml", "West = [\"902_0\"]\nEast = [\"902_0\"]\n");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--add-territory=bad.toml", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("is in both"));
+}
+
+#[test]
+fn makes_balanced_territories() {
+    let testdir = TestDir::new("geochunk", "makes_balanced_territories");
+    let output = testdir
+        .cmd()
+        .args(["territories", "--groups=40", "--contiguous", "zip2010-250000"])
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.starts_with("geochunk_zip2010_250000,territory\n"));
+    assert!(stdout.contains(",territory_01\n"));
+    assert!(stdout.contains(",territory_40\n"));
+    assert!(!stdout.contains(",territory_41\n"));
+
+    let output = testdir
+        .cmd()
+        .args(["territories", "--groups=3", "--toml", "zip2010-250000"])
+        .expect_success();
+    testdir.create_file("territories.toml", output.stdout_str());
+    let output = testdir
+        .cmd()
+        .args(["csv", "--add-territory=territories.toml", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert!(output.stdout_str().contains("90210,902_0,territory_"));
+}
+
+#[test]
+fn assigns_chunks_to_treatment_arms() {
+    let testdir = TestDir::new("geochunk", "assigns_chunks_to_treatment_arms");
+    let args = ["assign-treatment",
+                "--arms=control,testA,testB",
+                "--seed=42",
+                "zip2010-250000",
+                "zip"];
+    let input = "zip\n90210\n90211\n07712\n";
+    let output = testdir.cmd().args(args).output_with_stdin(input).expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.starts_with("zip,geochunk_zip2010_250000,geochunk_zip2010_250000_arm\n"));
+    let arms = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.rsplit(',').next().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert!(arms.iter().all(|arm| ["control", "testA", "testB"].contains(&&arm[..])));
+    // Zip codes in the same chunk are always in the same arm.
+    assert_eq!(arms[0], arms[1]);
+
+    let again = testdir.cmd().args(args).output_with_stdin(input).expect_success();
+    assert_eq!(again.stdout_str(), stdout);
+
+    testdir
+        .cmd()
+        .args(["assign-treatment", "--arms=control", "zip2010-250000", "zip"])
+        .output_with_stdin(input)
+        .expect_failure();
+}
+
+#[test]
+fn assigns_matched_pairs_to_treatment_arms() {
+    let testdir = TestDir::new("geochunk", "assigns_matched_pairs_to_treatment_arms");
+    let output = testdir
+        .cmd()
+        .args(["assign-treatment",
+               "--arms=control,test",
+               "--matched-pairs",
+               "--pairs-file=pairs.csv",
+               "zip2010-250000",
+               "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert!(output.stdout_str().contains("90210,902_0,"));
+    testdir.expect_contains("pairs.csv", "geochunk_zip2010_250000,pair,arm,population,density\n");
+    testdir.expect_contains("pairs.csv", ",1,control,");
+    testdir.expect_contains("pairs.csv", ",1,test,");
+}
+
+#[test]
+fn hashes_chunk_ids_with_salt() {
+    let testdir = TestDir::new("geochunk", "hashes_chunk_ids_with_salt");
+    testdir.create_file("salt.txt", "not very secret\n");
+    let run = || {
+        testdir
+            .cmd()
+            .args(["csv", "--chunk-id-salt-file=salt.txt", "zip2010", "250000", "zip"])
+            .output_with_stdin("zip\n90210\n90211\n")
+            .expect_success()
+            .stdout_str()
+            .to_owned()
+    };
+    let stdout = run();
+    assert!(!stdout.contains("902_0"));
+    let chunks = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').nth(1).unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(chunks[0].len(), 16);
+    assert_eq!(chunks[0], chunks[1]);
+    assert_eq!(run(), stdout);
+
+    testdir.create_file("empty.txt", "");
+    testdir
+        .cmd()
+        .args(["csv", "--chunk-id-salt-file=empty.txt", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn adds_noise_to_chunk_populations() {
+    let testdir = TestDir::new("geochunk", "adds_noise_to_chunk_populations");
+    let run = |extra_args: &[&str]| {
+        let mut args = vec!["csv", "--add-chunk-population"];
+        args.extend_from_slice(extra_args);
+        args.extend_from_slice(&["zip2010", "250000", "zip"]);
+        testdir
+            .cmd()
+            .args(&args)
+            .output_with_stdin("zip\n90210\n")
+            .expect_success()
+            .stdout_str()
+            .to_owned()
+    };
+    let exact = run(&[]);
+    let noisy = run(&["--population-noise=1000", "--noise-seed=42"]);
+    assert!(noisy.contains("90210,902_0,"));
+    assert_ne!(noisy, exact);
+    assert_eq!(run(&["--population-noise=1000", "--noise-seed=42"]), noisy);
+
+    testdir
+        .cmd()
+        .args(["csv", "--population-noise=-1", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn flags_and_separates_new_zips() {
+    let testdir = TestDir::new("geochunk", "flags_and_separates_new_zips");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--flag-new-zips", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n00501\n")
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.starts_with("zip,geochunk_zip2010_250000,geochunk_zip2010_250000_new_zip\n"));
+    assert!(stdout.contains("90210,902_0,false\n"));
+    assert!(stdout.contains("00501,") && stdout.ends_with(",true\n"));
+    assert!(output.stderr_str().contains("1 of 2 rows"));
+
+    let output = testdir
+        .cmd()
+        .args(["csv", "--new-zip-chunk=NEW", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n00501\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000\n90210,902_0\n00501,NEW\n");
+}
+
+#[test]
+fn falls_back_to_most_populous_chunk_for_new_zips() {
+    let testdir = TestDir::new("geochunk",
+                               "falls_back_to_most_populous_chunk_for_new_zips");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--new-zip-fallback", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n01760\n01780\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_fallback\n\
+                01760,017_1,false\n\
+                01780,017_0,true\n");
+    assert!(output.stderr_str().contains("1 of 2 rows"));
+
+    testdir
+        .cmd()
+        .args(["csv",
+               "--new-zip-fallback",
+               "--new-zip-chunk=NEW",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("zip\n01780\n")
+        .expect_failure();
+}
+
+#[test]
+fn adds_assignment_method_column() {
+    let testdir = TestDir::new("geochunk", "adds_assignment_method_column");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--add-assignment-method", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n02120\n90210\n59001\nbad\n")
+        .expect("could not run geochunk");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_method\n\
+                02120,0212_0,exact-5-digit\n\
+                90210,902_0,prefix-fallback-4\n\
+                59001,59_0,prefix-fallback-3\n\
+                bad,,\n");
+}
+
+#[test]
+fn applies_zip_patches() {
+    let testdir = TestDir::new("geochunk", "applies_zip_patches");
+    testdir.create_file("patch.csv", "zip,population,parent\n00501,1200,\n07799,,90210\n");
+    let output = testdir
+        .cmd()
+        .args(["patch-info", "patch.csv"])
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.contains("zips: 2\n"));
+    assert!(stdout.contains("estimated_population: 1200\n"));
+
+    let output = testdir
+        .cmd()
+        .args(["csv", "--zip-patch=patch.csv", "--flag-new-zips", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n07799\n00501\n")
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.contains("07799,902_0,false\n"));
+    assert!(stdout.contains("00501,") && stdout.ends_with(",false\n"));
+
+    testdir.create_file("bad.csv", "zip,population,parent\n00501,,\n");
+    testdir
+        .cmd()
+        .args(["patch-info", "bad.csv"])
+        .expect_failure();
+}
+
+#[test]
+fn chunks_with_census2000_file() {
+    let testdir = TestDir::new("geochunk", "chunks_with_census2000_file");
+    testdir.create_file("zip2000.csv",
+                        "Zip Code ZCTA,2000 Census Population\n90210,21733\n07712,35000\n");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--census2000-file=zip2000.csv", "zip2000", "30000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert!(output.stdout_str().starts_with("zip,geochunk_zip2000_30000\n90210,"));
+
+    testdir
+        .cmd()
+        .args(["crosswalk",
+               "--census2000-file=zip2000.csv",
+               "zip2000-30000",
+               "zip2010-250000"])
+        .expect_success();
+    testdir
+        .cmd()
+        .args(["csv", "zip2000", "30000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn chunks_with_estimates_file() {
+    let testdir = TestDir::new("geochunk", "chunks_with_estimates_file");
+    testdir.create_file("estimates.csv",
+                        "zip,POPESTIMATE2014,POPESTIMATE2015\n\
+                         90210,21000,21500\n07712,34000,34500\n");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--estimates-file=estimates.csv", "zip-est2015", "30000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert!(output
+                .stdout_str()
+                .starts_with("zip,geochunk_zip-est2015_30000\n90210,"));
+
+    let output = testdir
+        .cmd()
+        .args(["csv", "--estimates-file=estimates.csv", "zip-est2016", "30000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("2016"));
+}
+
+#[test]
+fn chooses_vintages_from_a_date_column() {
+    let testdir = TestDir::new("geochunk", "chooses_vintages_from_a_date_column");
+    testdir.create_file("zip2000.csv",
+                        "Zip Code ZCTA,2000 Census Population\n90210,300000\n90211,300000\n");
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--census2000-file=zip2000.csv",
+               "--vintage-from-column=event_date",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("event_date,zip\n2004-03-01,90210\n2015-03-01,90210\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "event_date,zip,geochunk_vintage_250000,geochunk_vintage_250000_vintage\n\
+                2004-03-01,90210,90210,zip2000\n2015-03-01,90210,902_0,zip2010\n");
+
+    testdir
+        .cmd()
+        .args(["csv", "--vintage-from-column=event_date", "zip2000", "250000", "zip"])
+        .output_with_stdin("event_date,zip\n2004-03-01,90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn plan_lists_chunks_with_populations_and_prefixes() {
+    let testdir = TestDir::new("geochunk", "plan_lists_chunks_with_populations_and_prefixes");
+    let output = testdir
+        .cmd()
+        .args(["plan", "zip2010-250000"])
+        .expect_success();
+    let plan = output.stdout_str();
+    assert!(plan.starts_with("geochunk_zip2010_250000,population,prefixes\n"));
+    assert!(plan.contains("\n010_0,210857,0100 0101 "));
+}
+
+#[test]
+fn plan_and_export_support_structured_formats() {
+    let testdir = TestDir::new("geochunk", "plan_and_export_support_structured_formats");
+    let output = testdir
+        .cmd()
+        .args(["plan", "--format=json", "zip2010-250000"])
+        .expect_success();
+    let plan = output.stdout_str();
+    assert!(plan.contains("\"column\": \"geochunk_zip2010_250000\""));
+    assert!(plan.contains("\"id\": \"010_0\""));
+
+    let output = testdir
+        .cmd()
+        .args(["plan", "--format=yaml", "zip2010-250000"])
+        .expect_success();
+    assert!(output
+                .stdout_str()
+                .starts_with("chunks:\n- id: \"010_0\"\n  index: 0\n  population: 210857\n"));
+
+    let output = testdir
+        .cmd()
+        .args(["export", "--format=json", "zip2010", "250000"])
+        .expect_success();
+    assert!(output.stdout_str().contains("\"90210\": \"902_0\""));
+
+    testdir
+        .cmd()
+        .args(["plan", "--format=xml", "zip2010-250000"])
+        .expect_failure();
+}
+
+#[test]
+fn explore_answers_commands_from_stdin() {
+    let testdir = TestDir::new("geochunk", "explore_answers_commands_from_stdin");
+    let output = testdir
+        .cmd()
+        .args(["explore", "zip2010-250000"])
+        .output_with_stdin("zip 90210\ncompare 500000\nchunk nonexistent\nquit\n")
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.contains("90210 is in chunk 902_0, found using prefix 9021\n"));
+    assert!(stdout.contains("geochunk_zip2010_250000: "));
+    assert!(stdout.contains("error: no chunk \"nonexistent\"\n"));
+}
+
+#[test]
+fn counts_rows_with_multiple_threads() {
+    let testdir = TestDir::new("geochunk", "counts_rows_with_multiple_threads");
+    let mut input = "\u{feff}name,zip\n".to_owned();
+    for i in 0..500 {
+        let zip = if i % 5 == 0 { "07712" } else { "90210" };
+        // Quoted newlines must not be mistaken for the ends of records.
+        input.push_str(&format!("\"Person\n{}\",{}\n", i, zip));
+    }
+    testdir.create_file("input.csv", &input);
+    let count = |threads: &str| {
+        let output = testdir
+            .cmd()
+            .args(["count", threads, "zip2010-250000", "zip", "input.csv"])
+            .expect_success();
+        output.stdout_str().to_owned()
+    };
+    let serial = count("--threads=1");
+    assert!(serial.contains("\n077_0,100\n"));
+    assert_eq!(count("--threads=4"), serial);
+}
+
+#[test]
+fn copartitions_files_using_io_uring() {
+    let testdir = TestDir::new("geochunk", "copartitions_files_using_io_uring");
+    testdir.create_file("customers.csv", "name,zip\nJ. Doe,90210\nA. Jones,07712\n");
+    testdir.create_file("stores.csv", "store,postal_code\n1,90211\n2,90210\n");
+    let output = testdir
+        .cmd()
+        .args(["copartition",
+               "--io-uring",
+               "--max-rows-per-file=1",
+               "zip2010-250000",
+               "out",
+               "zip",
+               "customers.csv",
+               "postal_code",
+               "stores.csv"])
+        .output()
+        .expect("could not run geochunk");
+    // Some systems don't allow io_uring, but they should tell us why.
+    if !output.status.success() {
+        assert!(output.stderr_str().contains("could not set up io_uring"));
+        return;
+    }
+    assert!(output.stdout_str().contains("\n902_0,1,2\n"));
+    testdir.expect_contains("out/902_0/customers-00000.csv", "name,zip\nJ. Doe,90210\n");
+    testdir.expect_contains("out/902_0/stores-00000.csv", "store,postal_code\n1,90211\n");
+    testdir.expect_contains("out/902_0/stores-00001.csv", "store,postal_code\n2,90210\n");
+    testdir.expect_contains("out/manifest.json", "\"path\": \"902_0/stores-00001.csv\"");
+}
+
+#[test]
+fn counts_rows_in_several_files_at_once() {
+    let testdir = TestDir::new("geochunk", "counts_rows_in_several_files_at_once");
+    testdir.create_file("a.csv", "zip\n90210\n07712\n");
+    testdir.create_file("b.csv", "zip\n90210\n");
+    testdir.create_file("c.csv", "zip\n90211\n");
+    let output = testdir
+        .cmd()
+        .args(["count", "--jobs=2", "zip2010-250000", "zip"])
+        .args(["a.csv", "b.csv", "c.csv"])
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.contains("\n902_0,3\n"));
+    assert!(stdout.contains("\n077_0,1\n"));
+    let stderr = output.stderr_str();
+    assert!(stderr.contains("] a.csv: 2 rows\n"));
+    assert!(stderr.contains("[3/3] "));
+
+    let output = testdir
+        .cmd()
+        .args(["count", "--jobs=2", "zip2010-250000", "zip", "a.csv", "missing.csv"])
+        .expect_failure();
+    assert!(output.stderr_str().contains("could not open missing.csv"));
+}
+
+#[test]
+fn data_validate_reports_problems() {
+    let testdir = TestDir::new("geochunk", "data_validate_reports_problems");
+    testdir.create_file("good.csv", "zip,pop\n90210,21733\n1001,17438\n");
+    let output = testdir
+        .cmd()
+        .args(["data-validate", "good.csv"])
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "good.csv: 2 zip codes, with a total population of 39171\n");
+
+    testdir.create_file("bad.csv", "zip,pop\n90210,21733\n902101,5\n90210,6\n,5000\n");
+    let output = testdir
+        .cmd()
+        .args(["data-validate", "bad.csv"])
+        .expect_failure();
+    assert_eq!(output.stdout_str(),
+               "line,problem\n\
+                3,\"\"\"902101\"\" is not a 5-digit zip code\"\n\
+                4,zip code 90210 already appeared on line 2\n\
+                5,zip code is blank\n");
+    assert!(output.stderr_str().contains("3 rows of bad.csv have problems"));
+}
+
+#[test]
+fn self_check_passes_and_catches_noisy_populations() {
+    let testdir = TestDir::new("geochunk",
+                               "self_check_passes_and_catches_noisy_populations");
+    let output = testdir
+        .cmd()
+        .args(["self-check", "zip2010-250000"])
+        .expect_success();
+    assert!(output.stdout_str().starts_with("geochunk_zip2010_250000: classified "));
+    assert!(output.stdout_str().ends_with(" inputs without any problems\n"));
+
+    let output = testdir
+        .cmd()
+        .args(["self-check",
+               "--population-noise=1000",
+               "--noise-seed=1",
+               "zip2010-250000"])
+        .expect_failure();
+    assert!(output.stdout_str().contains(" has a population of "));
+    assert!(output.stderr_str().contains("problems with geochunk_zip2010_250000"));
+}
+
+#[test]
+fn reads_formatted_weights() {
+    let testdir = TestDir::new("geochunk", "reads_formatted_weights");
+    testdir.create_file("pop.csv", "zip,pop\n90210,\"21,733.5\"\n1001,\"'17 438'\"\n");
+    let output = testdir
+        .cmd()
+        .args(["data-validate", "pop.csv"])
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "pop.csv: 2 zip codes, with a total population of 39172\n");
+    let output = testdir
+        .cmd()
+        .args(["data-validate", "--weight-rounding=down", "pop.csv"])
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "pop.csv: 2 zip codes, with a total population of 39171\n");
+    testdir
+        .cmd()
+        .args(["data-validate", "--weight-rounding=sideways", "pop.csv"])
+        .expect_failure();
+}
+
+#[test]
+fn accepts_options_in_specs() {
+    let testdir = TestDir::new("geochunk", "accepts_options_in_specs");
+    let output = testdir
+        .cmd()
+        .args(["plan", "zip2010-250000[naming=int,new_zip_chunk=NEW]"])
+        .expect_success();
+    let stdout = output.stdout_str();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert!(lines[1].starts_with("0,"));
+    assert_eq!(lines[lines.len() - 1], "NEW,0,");
+
+    let output = testdir
+        .cmd()
+        .env("GEOCHUNK_SPEC", "zip2010-250000[naming=int,respect_states,min_pop=50000]")
+        .env("GEOCHUNK_INPUT_COLUMNS", "zip")
+        .arg("csv")
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert!(output.stdout_str().starts_with("zip,geochunk_zip2010_states_250000\n"));
+
+    let output = testdir
+        .cmd()
+        .args(["plan", "zip2010-250000[min_pop=lots]"])
+        .expect_failure();
+    assert!(output.stderr_str().contains("spec option min_pop should be a number"));
+}
+
+#[test]
+#[cfg(unix)]
+fn daemon_answers_requests_over_unix_socket() {
+    use std::io::prelude::*;
+    use std::os::unix::net::UnixStream;
+    use std::process::Stdio;
+    use std::{thread, time};
+
+    let testdir = TestDir::new("geochunk", "daemon_answers_requests_over_unix_socket");
+    let mut daemon = testdir
+        .cmd()
+        .args(["daemon", "--socket=geochunk.sock", "zip2010-250000"])
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("could not start daemon");
+    let socket = testdir.path("geochunk.sock");
+    let mut stream = None;
+    for _ in 0..600 {
+        if let Ok(s) = UnixStream::connect(&socket) {
+            stream = Some(s);
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(50));
+    }
+    let mut stream = stream.expect("daemon never started listening");
+    stream.write_all(b"90210\nN/A\n02120\n").unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut answers = String::new();
+    stream.read_to_string(&mut answers).unwrap();
+
+    // A second daemon refuses to take over the socket.
+    let output = testdir
+        .cmd()
+        .args(["daemon", "--socket=geochunk.sock", "zip2010-250000"])
+        .expect_failure();
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(answers, "902_0\n\n0212_0\n");
+    assert!(output.stderr_str().contains("already listening"));
+}
+
+#[test]
+#[cfg(not(feature = "flight"))]
+fn serve_flight_requires_flight_feature() {
+    let testdir = TestDir::new("geochunk", "serve_flight_requires_flight_feature");
+    let output = testdir
+        .cmd()
+        .args(["serve", "--flight", "--listen=127.0.0.1:0", "zip2010-250000", "zip"])
+        .output()
+        .expect_failure();
+    assert!(output.stderr_str().contains("requires the flight feature"));
+}
+
+#[test]
+fn exports_snowflake_deployments() {
+    let testdir = TestDir::new("geochunk", "exports_snowflake_deployments");
+    let output = testdir
+        .cmd()
+        .args(["export", "--snowflake=sql", "zip2010", "250000"])
+        .expect_success();
+    assert!(output.stdout_str().contains("    ('90210', '902_0'),\n"));
+    assert!(output
+                .stdout_str()
+                .contains("FUNCTION geochunk_zip2010_250000(zip VARCHAR)\n"));
+
+    let output = testdir
+        .cmd()
+        .args(["export", "--snowflake=lambda", "zip2010", "250000"])
+        .expect_success();
+    assert!(output.stdout_str().contains("GEOCHUNK_SPEC: \"zip2010-250000\""));
+
+    // geochunk-lambda only knows our spec, so it can't use a salt file.
+    testdir.create_file("salt.txt", "not very secret\n");
+    let output = testdir
+        .cmd()
+        .args(["export",
+               "--snowflake=lambda",
+               "--chunk-id-salt-file=salt.txt",
+               "zip2010",
+               "250000"])
+        .expect_failure();
+    assert!(output.stderr_str().contains("--snowflake=sql"));
+}
+
+#[test]
+fn exports_lookml_dimensions() {
+    let testdir = TestDir::new("geochunk", "exports_lookml_dimensions");
+    let output = testdir
+        .cmd()
+        .args(["export-lookml",
+               "--output-column=geochunk",
+               "--zip-sql=${TABLE}.postcode",
+               "zip2010-250000"])
+        .expect_success();
+    let lookml = output.stdout_str();
+    assert!(lookml.contains("dimension: geochunk {\n"));
+    assert!(lookml.contains("WHEN LEFT(${TABLE}.postcode, 2) IN ('00', '09') THEN"));
+}
+
+#[test]
+fn writes_protobuf_records() {
+    let testdir = TestDir::new("geochunk", "writes_protobuf_records");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--format=proto", "zip2010", "250000", "zip"])
+        .output_with_stdin("name,zip\nA,90210\n")
+        .expect_success();
+    // The last message is a `Record` holding our row, plus its geochunk.
+    let record = b"\x13\x2a\x11\x0a\x01A\x0a\x0590210\x0a\x05902_0";
+    assert!(output.stdout.ends_with(record));
+    // The first is our `Metadata`, which needs a two-byte length.
+    assert_eq!(output.stdout[2], 0x0a);
+
+    testdir
+        .cmd()
+        .args(["csv", "--format=json", "zip2010", "250000", "zip"])
+        .output_with_stdin("name,zip\nA,90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn writes_orc_files() {
+    use std::fs;
+
+    let testdir = TestDir::new("geochunk", "writes_orc_files");
+    testdir.create_file("customers.csv", "name,zip\nJ. Doe,90210\n");
+    testdir.create_file("stores.csv", "store,zip\n1,90211\n");
+    let args = ["copartition",
+                "--format=orc",
+                "zip2010-250000",
+                "out",
+                "zip",
+                "customers.csv",
+                "zip",
+                "stores.csv"];
+    if !cfg!(feature = "orc") {
+        let output = testdir.cmd().args(args).expect_failure();
+        assert!(output.stderr_str().contains("requires the orc feature"));
+        return;
+    }
+    let output = testdir.cmd().args(args).expect_success();
+    assert!(output.stdout_str().contains("\n902_0,1,1\n"));
+    let orc = fs::read(testdir.path("out/902_0/customers.orc")).unwrap();
+    assert!(orc.starts_with(b"ORC"));
+    testdir.expect_contains("out/manifest.json", "\"path\": \"902_0/stores.orc\"");
+
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--format=orc",
+               "--orc-compression=zstd",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("name,zip\nA,90210\n")
+        .expect_success();
+    assert!(output.stdout.starts_with(b"ORC"));
+    testdir
+        .cmd()
+        .args(["csv",
+               "--format=orc",
+               "--output=out.orc",
+               "--max-rows-per-file=1",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("name,zip\nA,90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn writes_parquet_files() {
+    use std::fs;
+
+    let testdir = TestDir::new("geochunk", "writes_parquet_files");
+    testdir.create_file("customers.csv", "name,zip\nJ. Doe,90211\nA. Lee,90210\n");
+    testdir.create_file("stores.csv", "store,zip\n1,90211\n");
+    let args = ["copartition",
+                "--format=parquet",
+                "--sort-by-zip",
+                "--output-column=chunk",
+                "zip2010-250000",
+                "out",
+                "zip",
+                "customers.csv",
+                "zip",
+                "stores.csv"];
+    if !cfg!(feature = "parquet") {
+        let output = testdir.cmd().args(args).expect_failure();
+        assert!(output.stderr_str().contains("requires the parquet feature"));
+        return;
+    }
+    let output = testdir.cmd().args(args).expect_success();
+    assert!(output.stdout_str().contains("\n902_0,2,1\n"));
+    let parquet = fs::read(testdir.path("out/902_0/customers.parquet")).unwrap();
+    assert!(parquet.starts_with(b"PAR1"));
+    testdir.expect_contains("out/manifest.json", "\"path\": \"902_0/stores.parquet\"");
+
+    testdir
+        .cmd()
+        .args(["copartition",
+               "--sort-by-zip",
+               "zip2010-250000",
+               "out2",
+               "zip",
+               "customers.csv",
+               "zip",
+               "stores.csv"])
+        .expect_failure();
+}
+
+#[test]
+fn adds_chunk_columns_to_tables() {
+    use std::fs;
+
+    let testdir = TestDir::new("geochunk", "adds_chunk_columns_to_tables");
+    let args = ["table",
+                "zip2010-250000",
+                "zip",
+                "out/902_0/customers.parquet",
+                "chunked.parquet"];
+    if !cfg!(feature = "tables") {
+        let output = testdir.cmd().args(args).expect_failure();
+        assert!(output.stderr_str().contains("requires the tables feature"));
+        return;
+    }
+    testdir.create_file("customers.csv", "name,zip\nJ. Doe,90211\nA. Lee,90210\n");
+    testdir.create_file("stores.csv", "store,zip\n1,90211\n");
+    testdir
+        .cmd()
+        .args(["copartition",
+               "--format=parquet",
+               "--output-column=chunk",
+               "zip2010-250000",
+               "out",
+               "zip",
+               "customers.csv",
+               "zip",
+               "stores.csv"])
+        .expect_success();
+    testdir.cmd().args(args).expect_success();
+    let parquet = fs::read(testdir.path("chunked.parquet")).unwrap();
+    assert!(parquet.starts_with(b"PAR1"));
+
+    testdir
+        .cmd()
+        .args(["table",
+               "zip2010-250000",
+               "zip",
+               "chunked.parquet",
+               "chunked.csv"])
+        .expect_failure();
+}
+
+/// Build an uncompressed tar archive containing `files`.
+fn tar_archive(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut tar = vec![];
+    for &(name, contents) in files {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+        header[136..147].copy_from_slice(b"00000000000");
+        header[156] = b'0';
+        header[148..156].copy_from_slice(b"        ");
+        let checksum = header.iter().map(|&b| b as u32).sum::<u32>();
+        header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(contents.as_bytes());
+        let padding = (512 - contents.len() % 512) % 512;
+        tar.extend(vec![0; padding]);
+    }
+    tar.extend(vec![0; 1024]);
+    tar
+}
+
+#[test]
+fn processes_csv_files_in_archives() {
+    use std::fs;
+
+    let testdir = TestDir::new("geochunk", "processes_csv_files_in_archives");
+    let tar = tar_archive(&[("a.csv", "zip\n90210\n"),
+                            ("notes.md", "hi\n"),
+                            ("more/b.csv", "zip\n07712\n")]);
+    fs::write(testdir.path("in.tar"), tar).unwrap();
+    testdir
+        .cmd()
+        .args(["csv",
+               "--archive=in.tar",
+               "--output=out.zip",
+               "zip2010",
+               "250000",
+               "zip"])
+        .expect_success();
+    testdir
+        .cmd()
+        .args(["csv",
+               "--archive=out.zip",
+               "--output=out",
+               "--output-column=again",
+               "zip2010",
+               "250000",
+               "zip"])
+        .expect_success();
+    testdir.expect_file_contents("out/a.csv",
+                                 "zip,geochunk_zip2010_250000,again\n90210,902_0,902_0\n");
+    testdir.expect_contains("out/more/b.csv", "07712,077_");
+    assert!(!testdir.path("out/notes.md").exists());
+
+    testdir
+        .cmd()
+        .args(["csv",
+               "--archive=in.tar",
+               "zip2010",
+               "250000",
+               "zip"])
+        .expect_failure();
+}
+
+#[test]
+fn names_chunks_with_id_templates() {
+    let testdir = TestDir::new("geochunk", "names_chunks_with_id_templates");
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--id-template={state}-{prefix}-{index:04}",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("zip\n90210\n02138\n")
+        .expect_success();
+    let stdout = output.stdout_str();
+    assert!(stdout.contains("90210,CA-902-"));
+    assert!(stdout.contains("02138,MA-0213-"));
+
+    testdir
+        .cmd()
+        .args(["csv", "--id-template={state}", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn adds_density_column() {
+    let testdir = TestDir::new("geochunk", "adds_density_column");
+    testdir.create_file("gaz.txt", "GEOID\tALAND\n90210\t25899881\n");
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--gazetteer=gaz.txt",
+               "--add-density",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("zip\n90210\n90211\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_density\n\
+                90210,902_0,2174.1\n90211,902_0,\n");
+
+    testdir
+        .cmd()
+        .args(["csv", "--add-density", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn classifies_urbanicity() {
+    let testdir = TestDir::new("geochunk", "classifies_urbanicity");
+    // 90210 has 2,174 people per square mile, and 10001 has 54,133.
+    testdir.create_file("gaz.txt", "GEOID\tALAND\n90210\t25899881\n10001\t1009625\n");
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--gazetteer=gaz.txt",
+               "--add-urbanicity",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("zip\n90210\n10001\n90211\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_urbanicity\n\
+                90210,902_0,suburban\n10001,100_0,urban\n90211,902_0,\n");
+
+    let output = testdir
+        .cmd()
+        .args(["plan", "--gazetteer=gaz.txt", "--density-breaks=urbanicity", "zip2010-250000"])
+        .expect_success();
+    assert!(output.stdout_str().contains("density_500_3000"));
+
+    testdir
+        .cmd()
+        .args(["csv", "--add-urbanicity", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn adds_timezone_column() {
+    let testdir = TestDir::new("geochunk", "adds_timezone_column");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--add-timezone", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n02138\n")
+        .expect_success();
+    assert!(output
+                .stdout_str()
+                .starts_with("zip,geochunk_zip2010_250000,geochunk_zip2010_250000_timezone\n"));
+    assert!(output.stdout_str().contains(",America/Los_Angeles\n"));
+    assert!(output.stdout_str().contains(",America/New_York\n"));
+}
+
+#[test]
+fn chunks_within_dmas() {
+    let testdir = TestDir::new("geochunk", "chunks_within_dmas");
+    testdir.create_file("dmas.csv",
+                        "\
+zip,dma,population
+90210,803,100
+90211,803,100
+90212,825,100
+90213,825,100
+");
+    let output = testdir
+        .cmd()
+        .args(["plan", "--dma-file=dmas.csv", "dma-250"])
+        .expect_success();
+    assert!(output
+                .stdout_str()
+                .contains("9021_0,200,90210 90211 90214 90215 90216 90217 90218 90219\n\
+                           9021_1,200,90212 90213\n"));
+
+    let output = testdir
+        .cmd()
+        .args(["csv", "--dma-file=dmas.csv", "--add-dma", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90212\n07712\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_dma\n\
+                90212,902_0,825\n07712,077_0,\n");
+
+    testdir
+        .cmd()
+        .args(["csv", "--add-dma", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90212\n")
+        .expect_failure();
+}
+
+#[test]
+fn adds_district_columns() {
+    let testdir = TestDir::new("geochunk", "adds_district_columns");
+    testdir.create_file("cds.csv",
+                        "\
+ZIP,CD,RES_RATIO
+90210,0630,0.25
+90210,0633,0.75
+02138,2505,1
+");
+    testdir.create_file("slds.csv", "zip,sldu\n90210,026\n");
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--add-cd",
+               "--cd-file=cds.csv",
+               "--add-sld",
+               "--sld-file=slds.csv",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin("zip\n90210\n02138\n07712\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000,geochunk_zip2010_250000_cd,\
+                geochunk_zip2010_250000_sld\n\
+                90210,902_0,0633,026\n02138,0213_0,2505,\n07712,077_0,,\n");
+
+    let output = testdir
+        .cmd()
+        .args(["csv", "--add-cd", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+    assert!(output.stderr_str().contains("--cd-file"));
+}
+
+#[test]
+fn finds_chunks_for_coordinates() {
+    let testdir = TestDir::new("geochunk", "finds_chunks_for_coordinates");
+    testdir.create_file("gaz.txt",
+                        "\
+GEOID\tALAND\tAWATER\tINTPTLAT\tINTPTLONG
+10001\t1640604\t0\t40.750633\t-73.997177
+90210\t25899881\t0\t34.100517\t-118.41463
+");
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--gazetteer=gaz.txt",
+               "--coordinates",
+               "--nearest-within=25",
+               "zip2010",
+               "250000",
+               "lat",
+               "lng"])
+        .output_with_stdin("lat,lng\n34.1,-118.41\n40.6,-73.7\n45.0,-100.0\n")
+        .expect("could not run geochunk");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout_str(),
+               "lat,lng,geochunk_zip2010_250000,geochunk_zip2010_250000_nearest\n\
+                34.1,-118.41,902_0,false\n40.6,-73.7,100_0,true\n45.0,-100.0,,\n");
+    assert!(output
+                .stderr_str()
+                .contains("1 of 3 rows had points outside every ZCTA"));
+
+    // With boundaries, a point far from the internal point of a large ZCTA
+    // is still inside it.
+    testdir.create_file("zctas.geojson",
+                        r#"{"type": "FeatureCollection", "features": [
+    {"type": "Feature", "properties": {"ZCTA5CE20": "90210"},
+     "geometry": {"type": "Polygon",
+                  "coordinates": [[[-118.5, 34], [-118.5, 34.4], [-118.3, 34.4],
+                                   [-118.3, 34], [-118.5, 34]]]}}
+]}"#);
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--gazetteer=gaz.txt",
+               "--coordinates",
+               "--zcta-boundaries=zctas.geojson",
+               "--nearest-within=25",
+               "zip2010",
+               "250000",
+               "lat",
+               "lng"])
+        .output_with_stdin("lat,lng\n34.3,-118.41\n40.75,-73.99\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "lat,lng,geochunk_zip2010_250000,geochunk_zip2010_250000_nearest\n\
+                34.3,-118.41,902_0,false\n40.75,-73.99,100_0,true\n");
+
+    testdir
+        .cmd()
+        .args(["csv", "--coordinates", "zip2010", "250000", "lat", "lng"])
+        .output_with_stdin("lat,lng\n34.1,-118.41\n")
+        .expect_failure();
+    testdir
+        .cmd()
+        .args(["csv", "--zcta-boundaries=zctas.geojson", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn builds_and_uses_custom_geographies() {
+    let testdir = TestDir::new("geochunk", "builds_and_uses_custom_geographies");
+    testdir.create_file("zones.geojson",
+                        r#"{"type": "FeatureCollection", "features": [
+    {"type": "Feature", "properties": {"zone": "west", "pop": 200},
+     "geometry": {"type": "Polygon",
+                  "coordinates": [[[-100, 40], [-100, 41], [-99, 41], [-99, 40], [-100, 40]]]}},
+    {"type": "Feature", "properties": {"zone": "east", "pop": 200},
+     "geometry": {"type": "Polygon",
+                  "coordinates": [[[-99, 40], [-99, 41], [-98, 41], [-98, 40], [-99, 40]]]}}
+]}"#);
+    let output = testdir
+        .cmd()
+        .args(["build-geography",
+               "--weight-field=pop",
+               "--id-field=zone",
+               "zones.geojson",
+               "250",
+               "zones.json"])
+        .expect_success();
+    assert!(output
+                .stderr_str()
+                .contains("built 2 chunks from 2 regions"));
+
+    let output = testdir
+        .cmd()
+        .args(["locate", "zones.json", "lat", "lng"])
+        .output_with_stdin("id,lat,lng\n1,40.5,-99.5\n2,40.5,-98.5\n3,45,-99\n4,,\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "id,lat,lng,geochunk_zones_250,geochunk_zones_250_region\n\
+                1,40.5,-99.5,0,west\n2,40.5,-98.5,1,east\n3,45,-99,,\n4,,,,\n");
+    assert!(output
+                .stderr_str()
+                .contains("1 of 4 rows had points outside every region"));
+
+    testdir.create_file("zones.gpkg", "");
+    let output = testdir
+        .cmd()
+        .args(["build-geography", "--weight-field=pop", "zones.gpkg", "250", "out.json"])
+        .expect_failure();
+    assert!(output.stderr_str().contains("not a GeoPackage file"));
+}
+
+#[test]
+fn locates_s2_chunks() {
+    let testdir = TestDir::new("geochunk", "locates_s2_chunks");
+    testdir.create_file("gaz.txt",
+                        "\
+GEOID\tALAND\tAWATER\tINTPTLAT\tINTPTLONG
+10001\t1640604\t0\t40.750633\t-73.997177
+90210\t25899881\t0\t34.100517\t-118.41463
+");
+    let output = testdir
+        .cmd()
+        .args(["locate", "--gazetteer=gaz.txt", "--s2=30000", "lat", "lng"])
+        .output_with_stdin("lat,lng\n34.1,-118.41\n40.75,-74.0\nx,1\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "lat,lng,geochunk_s2_30000,geochunk_s2_30000_cell\n\
+                34.1,-118.41,84,84\n40.75,-74.0,8c,8c\nx,1,,\n");
+    assert!(output
+                .stderr_str()
+                .contains("1 of 3 rows had points outside every cell"));
+
+    testdir
+        .cmd()
+        .args(["locate", "--s2=30000", "lat", "lng"])
+        .output_with_stdin("lat,lng\n34.1,-118.41\n")
+        .expect_failure();
+}
+
+#[test]
+fn chunks_mexican_postal_codes() {
+    let testdir = TestDir::new("geochunk", "chunks_mexican_postal_codes");
+    testdir.create_file("cp.csv",
+                        "\
+d_codigo,NOM_LOC,POBTOT
+6600,Ciudad de Mexico,150
+06700,Ciudad de Mexico,150
+44100,Guadalajara,100
+44100,Guadalajara,*
+");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--mx-cp-file=cp.csv", "mx_cp", "200", "cp"])
+        .output_with_stdin("cp\n06600\n06700\n44100\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "cp,geochunk_mx_cp_200\n06600,06_0\n06700,06_1\n44100,_0\n");
+
+    let output = testdir
+        .cmd()
+        .args(["plan", "mx_cp-200"])
+        .expect_failure();
+    assert!(output.stderr_str().contains("--mx-cp-file"));
+
+    // States and time zones are looked up by US zip code, so they'd be wrong.
+    for option in &["--add-timezone", "--id-template={state}-{index}"] {
+        let output = testdir
+            .cmd()
+            .args(["csv", "--mx-cp-file=cp.csv", option, "mx_cp", "200", "cp"])
+            .output_with_stdin("cp\n06700\n")
+            .expect_failure();
+        assert!(output.stderr_str().contains("only works with US zip codes"));
+    }
+    testdir
+        .cmd()
+        .args(["csv", "--mx-cp-file=cp.csv", "--id-template=mx-{index}", "mx_cp", "200", "cp"])
+        .output_with_stdin("cp\n06700\n")
+        .expect_success();
+}
+
+#[test]
+fn verify_golden_accepts_current_export() {
+    let testdir = TestDir::new("geochunk", "verify_golden_accepts_current_export");
+    let output = testdir
+        .cmd()
+        .args(["export", "zip2010", "250000"])
+        .expect_success();
+    testdir.create_file("golden.csv", output.stdout_str());
+    testdir
+        .cmd()
+        .args(["verify-golden", "golden.csv"])
+        .expect_success();
+}
+
+#[test]
+fn verify_golden_fails_on_changed_chunks() {
+    let testdir = TestDir::new("geochunk", "verify_golden_fails_on_changed_chunks");
+    testdir.create_file("golden.csv",
+                        "zip,geochunk_zip2010_250000\n01000,010_0\n07720,077_0\n");
+    let output = testdir
+        .cmd()
+        .args(["verify-golden", "golden.csv"])
+        .expect_failure();
+    assert_eq!(output.stdout_str(), "zip,expected,actual\n07720,077_0,077_1\n");
+    assert!(output.stderr_str().contains("1 zip codes"));
+}
+
+#[test]
+fn data_info_describes_census_data() {
+    let testdir = TestDir::new("geochunk", "data_info_describes_census_data");
+    let output = testdir.cmd().arg("data-info").expect_success();
+    assert!(output.stdout_str().contains("vintage: 2010\n"));
+    assert!(output.stdout_str().contains("rows: 33092\n"));
+    assert!(output.stdout_str().contains("sha256: "));
+
+    // Structured exports describe the same data, but CSV exports stay plain
+    // CSV.
+    let output = testdir
+        .cmd()
+        .args(["export", "zip2010", "250000"])
+        .expect_success();
+    assert!(output.stdout_str().starts_with("zip,geochunk_zip2010_250000\n"));
+    let output = testdir
+        .cmd()
+        .args(["export", "--snowflake=sql", "zip2010", "250000"])
+        .expect_success();
+    assert!(output.stdout_str().contains("\n-- Weight data vintage: 2010\n"));
+    assert!(output
+                .stdout_str()
+                .contains("\n-- Weight data total: 308739931\n"));
+
+    // Chunks weighted by other data describe the file they came from.
+    testdir.create_file("p.csv", "zip,pop\n90210,100\n");
+    testdir
+        .cmd()
+        .args(["export",
+               "--census2000-file=p.csv",
+               "--metadata=meta.json",
+               "zip2000",
+               "1000"])
+        .expect_success();
+    testdir.expect_contains("meta.json", "\"source\": \"p.csv\"");
+    testdir.expect_contains("meta.json",
+                            "\"sha256\": \"7c69729aa7fad36206d6f6b9f2804eee911b88dae8a942a77577c1f5122b8145\"");
+    let metadata = std::fs::read_to_string(testdir.path("meta.json")).unwrap();
+    assert!(!metadata.contains("Census Bureau"));
+}
+
+#[test]
+fn csv_replace_overwrites_zip_column() {
+    let testdir = TestDir::new("geochunk", "csv_replace_overwrites_zip_column");
+    let input = "\
+name,postcode
+J. Doe,90210
+";
+    let output = testdir
+        .cmd()
+        .args(["csv", "--replace", "zip2010", "250000", "postcode"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "\
+name,geochunk_zip2010_250000
+J. Doe,902_0
+");
+}
+
+#[test]
+fn anonymize_replaces_zips_and_suppresses_small_chunks() {
+    let testdir = TestDir::new("geochunk", "anonymize_replaces_zips_and_suppresses_small_chunks");
+    testdir.create_file("in.csv",
+                        "\
+name,city,zip
+A,Beverly Hills,90210
+B,Beverly Hills,90211
+C,Asbury Park,07712
+");
+    let output = testdir
+        .cmd()
+        .args(["anonymize",
+               "--min-rows=2",
+               "--drop-column=city",
+               "zip2010-250000",
+               "zip",
+               "in.csv"])
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "\
+name,geochunk_zip2010_250000
+A,902_0
+B,902_0
+C,
+");
+}
+
+#[test]
+fn csv_zip2010_handles_multiple_columns() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_handles_multiple_columns");
+    let input = "\
+home_zip,work_zip
+90210,
+";
+    let output = testdir
+        .cmd()
+        .args(["csv", "zip2010", "250000", "home_zip", "work_zip"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "\
+home_zip,work_zip,home_zip_geochunk_zip2010_250000,work_zip_geochunk_zip2010_250000
+90210,,902_0,
+");
+}
+
+#[test]
+fn csv_zip2010_names_and_positions_output_column() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_names_and_positions_output_column");
+    let input = "\
+name,postcode,state
+J. Doe,90210,CA
+";
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--output-column=geochunk_250k",
+               "--insert-after=postcode",
+               "zip2010",
+               "250000",
+               "postcode"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "\
+name,postcode,geochunk_250k,state
+J. Doe,90210,902_0,CA
+");
+}
+
+#[test]
+fn csv_zip2010_adds_prefix_and_population_columns() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_adds_prefix_and_population_columns");
+    let input = "\
+name,postcode
+J. Doe,01000
+";
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--add-prefix",
+               "--add-chunk-population",
+               "zip2010",
+               "250000",
+               "postcode"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "\
+name,postcode,geochunk_zip2010_250000,geochunk_zip2010_250000_prefix,geochunk_zip2010_250000_population
+J. Doe,01000,010_0,0100,210857
+");
+}
+
+#[test]
+fn csv_zip2010_chunk_as_int_writes_index_file() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_chunk_as_int_writes_index_file");
+    let input = "\
+name,postcode
+J. Doe,01000
+";
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--chunk-as-int",
+               "--chunk-index-file=index.csv",
+               "zip2010",
+               "250000",
+               "postcode"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "\
+name,postcode,geochunk_zip2010_250000
+J. Doe,01000,0
+");
+    testdir.expect_contains("index.csv", "index,geochunk_zip2010_250000\n0,010_0\n");
+}
+
+#[test]
+fn csv_zip2010_without_header() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_without_header");
+    let input = "\
+J. Doe,90210
+H. Smith,
+";
+    let output = testdir
+        .cmd()
+        .args(["csv", "--no-header", "zip2010", "250000", "2"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "\
+J. Doe,90210,902_0
+H. Smith,,
+");
+}
+
+#[test]
+fn csv_zip2010_with_dialect_options() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_with_dialect_options");
+    let input = "\
+# Exported from our CRM
+name|postcode
+'Doe| J.'|90210|
+";
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--delimiter=|",
+               "--quote='",
+               "--comment-char=#",
+               "--quote-style=always",
+               "--on-ragged=pad",
+               "zip2010",
+               "250000",
+               "postcode"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "\
+'name'|'postcode'|'geochunk_zip2010_250000'
+'Doe| J.'|'90210'|'902_0'
+");
+}
+
+#[test]
+fn csv_zip2010_detects_delimiter_and_compression() {
+    let testdir = TestDir::new("geochunk",
+                               "csv_zip2010_detects_delimiter_and_compression");
+    // "name\tzip\nA\t90210\n", compressed with gzip.
+    let input: &[u8] = &[0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+                         0x03, 0xcb, 0x4b, 0xcc, 0x4d, 0xe5, 0xac, 0xca, 0x2c,
+                         0xe0, 0x72, 0xe4, 0xb4, 0x34, 0x30, 0x32, 0x34, 0xe0,
+                         0x02, 0x00, 0x7d, 0x59, 0xbc, 0x69, 0x11, 0x00, 0x00,
+                         0x00];
+    let output = testdir
+        .cmd()
+        .args(["csv", "zip2010", "250000", "zip"])
+        .output_with_stdin(input)
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "name\tzip\tgeochunk_zip2010_250000\nA\t90210\t902_0\n");
+
+    // An explicit delimiter overrides our guess.
+    let output = testdir
+        .cmd()
+        .args(["csv", "--delimiter=,", "zip2010", "250000", "zip\tcode"])
+        .output_with_stdin("zip\tcode\n90210\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip\tcode,geochunk_zip2010_250000\n90210,902_0\n");
+}
+
+#[test]
+fn csv_zip2010_transcodes_input() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_transcodes_input");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--encoding=windows-1252", "zip2010", "250000", "zip"])
+        .output_with_stdin(&b"city,zip\nSant\xe9e,92071\n"[..])
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "city,zip,geochunk_zip2010_250000\nSantée,92071,920_3\n");
+
+    let output = testdir
+        .cmd()
+        .args(["csv", "zip2010", "250000", "zip"])
+        .output_with_stdin("\u{feff}zip\n92071\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "zip,geochunk_zip2010_250000\n92071,920_3\n");
+}
+
+#[test]
+fn csv_zip2010_strict_reports_invalid_zips() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_strict_reports_invalid_zips");
+    let input = "\
+name,zip
+J. Doe,90210
+A. Jones,N/A
+";
+    let output = testdir
+        .cmd()
+        .args(["csv", "zip2010", "250000", "zip"])
+        .output_with_stdin(input)
+        .expect("could not run geochunk");
+    assert_eq!(output.status.code(), Some(3));
+    let output = testdir
+        .cmd()
+        .args(["csv", "--strict", "zip2010", "250000", "zip"])
+        .output_with_stdin(input)
+        .expect_failure();
+    assert!(output.stderr_str().contains("standard input"));
+    assert!(output
+                .stderr_str()
+                .contains("Invalid zip code \"N/A\" in column 'zip' at line 3"));
+}
+
+#[test]
+fn csv_zip2010_handles_ragged_rows() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_handles_ragged_rows");
+    let input = "\
+name,zip
+J. Doe,90210
+H. Smith
+";
+    let output = testdir
+        .cmd()
+        .args(["csv", "zip2010", "250000", "zip"])
+        .output_with_stdin(input)
+        .expect_failure();
+    assert!(output.stderr_str().contains("line: 3"));
+    let output = testdir
+        .cmd()
+        .args(["csv",
+               "--on-ragged=skip",
+               "--rejects-file=rejects.csv",
+               "zip2010",
+               "250000",
+               "zip"])
+        .output_with_stdin(input)
+        .expect("could not run geochunk");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stderr_str().contains("skipped 1 rows"));
+    assert_eq!(output.stdout_str(),
+               "name,zip,geochunk_zip2010_250000\nJ. Doe,90210,902_0\n");
+    testdir.expect_contains("rejects.csv", "name,zip\nH. Smith\n");
+}
+
+#[test]
+fn csv_zip2010_in_place() {
+    use std::fs;
+
+    let testdir = TestDir::new("geochunk", "csv_zip2010_in_place");
+    testdir.create_file("in.csv", "zip\n90210\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(testdir.path("in.csv"), fs::Permissions::from_mode(0o640))
+            .unwrap();
+    }
+    let output = testdir
+        .cmd()
+        .args(["csv", "--in-place=in.csv", "zip2010", "250000", "zip"])
+        .expect_success();
+    assert_eq!(output.stdout_str(), "");
+    testdir.expect_contains("in.csv", "zip,geochunk_zip2010_250000\n90210,902_0\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(testdir.path("in.csv")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    // A failed run leaves the original file alone.
+    testdir
+        .cmd()
+        .args(["csv", "--in-place=in.csv", "zip2010", "250000", "missing"])
+        .expect_failure();
+    testdir.expect_contains("in.csv", "zip,geochunk_zip2010_250000\n90210,902_0\n");
+
+    // We won't replace a compressed file with uncompressed output.
+    let gzipped = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03";
+    fs::write(testdir.path("in.csv.gz"), &gzipped[..]).unwrap();
+    testdir
+        .cmd()
+        .args(["csv", "--in-place=in.csv.gz", "zip2010", "250000", "zip"])
+        .expect_failure();
+    assert_eq!(fs::read(testdir.path("in.csv.gz")).unwrap(), &gzipped[..]);
+}
+
+#[test]
+fn csv_zip2010_with_config_file() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_with_config_file");
+    testdir.create_file("prod.toml",
+                        "\
+spec = \"zip2010-250000\"
+input-columns = [\"postcode\"]
+delimiter = \";\"
+output-column = \"chunk\"
+");
+    testdir.create_file("in.csv", "name;postcode\nJ. Doe;90210\n");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--config", "prod.toml", "in.csv"])
+        .expect_success();
+    assert_eq!(output.stdout_str(), "name;postcode;chunk\nJ. Doe;90210;902_0\n");
+
+    // Options on the command line override the config file, and we pick up
+    // geochunk.toml automatically.
+    testdir.create_file("geochunk.toml", "output-column = \"chunk\"\n");
+    let output = testdir
+        .cmd()
+        .args(["csv", "--output-column=id", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "zip,id\n90210,902_0\n");
+
+    testdir.create_file("bad.toml", "no-such-option = true\n");
+    testdir
+        .cmd()
+        .args(["csv", "--config=bad.toml", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_failure();
+}
+
+#[test]
+fn csv_zip2010_with_environment_variables() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_with_environment_variables");
+    testdir.create_file("geochunk.toml", "output-column = \"chunk\"\n");
+    let output = testdir
+        .cmd()
+        .env("GEOCHUNK_SPEC", "zip2010-250000[naming=int]")
+        .env("GEOCHUNK_INPUT_COLUMNS", "postcode")
+        .env("GEOCHUNK_DELIMITER", ";")
+        .env("GEOCHUNK_OUTPUT_COLUMN", "id")
+        .env("GEOCHUNK_ADD_PREFIX", "true")
+        .arg("csv")
+        .output_with_stdin("name;postcode\nJ. Doe;90210\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(),
+               "name;postcode;id;id_prefix\nJ. Doe;90210;1508;9021\n");
+
+    // The command line overrides the environment.
+    let output = testdir
+        .cmd()
+        .env("GEOCHUNK_OUTPUT_COLUMN", "id")
+        .env("GEOCHUNK_ADD_PREFIX", "false")
+        .args(["csv", "--output-column=chunk", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert_eq!(output.stdout_str(), "zip,chunk\n90210,902_0\n");
+
+    // Variables which don't match an option, like typos or the Lambda
+    // handler's settings, get a warning, but don't stop us.
+    let output = testdir
+        .cmd()
+        .env("GEOCHUNK_OUTPUT_COLUMNS", "id")
+        .args(["csv", "zip2010", "250000", "zip"])
+        .output_with_stdin("zip\n90210\n")
+        .expect_success();
+    assert!(output.stdout_str().ends_with("\n90210,902_0\n"));
+    assert!(output.stderr_str().contains("GEOCHUNK_OUTPUT_COLUMNS"));
+    testdir
+        .cmd()
+        .env("GEOCHUNK_TARGET_BUCKET", "chunked")
+        .arg("--version")
+        .expect_success();
+}
+
+#[test]
+fn specs_lists_chunk_types() {
+    let testdir = TestDir::new("geochunk", "specs_lists_chunk_types");
+    let output = testdir.cmd().arg("specs").expect_success();
+    assert!(output.stdout_str().contains("\"pattern\": \"zip2010-<population>\""));
+    assert!(output.stdout_str().contains("\"--soi-file\""));
+}
+
+#[test]
+fn completions_for_supported_shells() {
+    let testdir = TestDir::new("geochunk", "completions_for_supported_shells");
+    let output = testdir
+        .cmd()
+        .args(["completions", "bash"])
+        .expect_success();
+    assert!(output.stdout_str().contains("verify-golden"));
+    assert!(output.stdout_str().contains("--delimiter="));
+    let output = testdir
+        .cmd()
+        .args(["completions", "fish"])
+        .expect_success();
+    assert!(output.stdout_str().contains("-l on-ragged -r"));
+    testdir
+        .cmd()
+        .args(["completions", "tcsh"])
+        .expect_failure();
+}
+
+#[test]
+fn csv_zip2010_exit_codes() {
+    let testdir = TestDir::new("geochunk", "csv_zip2010_exit_codes");
+    let input = "\
+zip
+90210
+N/A
+07712
+";
+    let run = |pct: &str| {
+        testdir
+            .cmd()
+            .args(["csv",
+                   &format!("--fail-on-invalid-pct={}", pct),
+                   "zip2010",
+                   "250000",
+                   "zip"])
+            .output_with_stdin(input)
+            .expect("could not run geochunk")
+    };
+    let output = run("50");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout_str().contains("07712,077_0"));
+    assert!(output.stderr_str().contains("1 of 3 rows"));
+    let output = run("10");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stderr_str().contains("--fail-on-invalid-pct"));
+
+    let output = testdir
+        .cmd()
+        .args(["csv", "--in-place=missing.csv", "zip2010", "250000", "zip"])
+        .output()
+        .expect("could not run geochunk");
+    assert_eq!(output.status.code(), Some(2));
+}